@@ -0,0 +1,157 @@
+//! `kubectl echo` plugin: status, expected Deployment diff, and pause and resume of Echo resources.
+use echo_operator::crd::echo::Echo;
+use echo_operator::diff;
+use echo_operator::echo::pause::PAUSED_ANNOTATION;
+use echo_operator::echo::reconcile::Defaults;
+
+use anyhow::Context;
+use chrono::Utc;
+use clap::{crate_authors, crate_version, Parser, Subcommand};
+use k8s_openapi::api::apps::v1::Deployment;
+use kube::api::{Api, ListParams, Patch, PatchParams};
+use kube::{Client, ResourceExt};
+use serde_json::json;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "kubectl-echo",
+    about = "Inspect and control Echo resources",
+    version = crate_version!(),
+    author = crate_authors!("\n"),
+)]
+struct Args {
+    /// Namespace of the echoes. The one of the kubeconfig context by default
+    #[arg(short, long, global = true)]
+    namespace: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show the status of an echo, or of every echo in the namespace
+    Status { name: Option<String> },
+    /// Show the changes the operator would apply to the Deployment of the echo
+    Diff { name: String },
+    /// Stop reconciling the echo, leaving its Deployment as it is
+    Pause { name: String },
+    /// Reconcile the echo again
+    Resume { name: String },
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let client = Client::try_default().await.context("loading kubeconfig")?;
+    let namespace = args
+        .namespace
+        .unwrap_or_else(|| client.default_namespace().to_string());
+    let echo_api = Api::<Echo>::namespaced(client.clone(), &namespace);
+
+    match args.command {
+        Command::Status { name } => status(&echo_api, name.as_deref()).await,
+        Command::Diff { name } => {
+            let deployment_api = Api::<Deployment>::namespaced(client, &namespace);
+            deployment_diff(&echo_api, &deployment_api, &name).await
+        }
+        Command::Pause { name } => set_paused(&echo_api, &name, true).await,
+        Command::Resume { name } => set_paused(&echo_api, &name, false).await,
+    }
+}
+
+async fn status(echo_api: &Api<Echo>, name: Option<&str>) -> anyhow::Result<()> {
+    let echoes = match name {
+        Some(name) => vec![echo_api
+            .get(name)
+            .await
+            .with_context(|| format!("getting echo {name}"))?],
+        None => {
+            echo_api
+                .list(&ListParams::default())
+                .await
+                .context("listing echoes")?
+                .items
+        }
+    };
+    println!(
+        "{:<32} {:<6} {:<9} {:<7} LAST ERROR",
+        "NAME", "READY", "REPLICAS", "PAUSED"
+    );
+    for echo in echoes {
+        let status = echo.status.clone().unwrap_or_default();
+        let replicas = format!(
+            "{}/{}",
+            status.ready_replicas.unwrap_or_default(),
+            echo.spec.replicas
+        );
+        println!(
+            "{:<32} {:<6} {:<9} {:<7} {}",
+            echo.name_any(),
+            status.is_ready(),
+            replicas,
+            echo.paused(),
+            status
+                .last_error
+                .and_then(|e| e.message)
+                .unwrap_or_default(),
+        );
+    }
+    Ok(())
+}
+
+/// Print the fields of the live Deployment the operator would change
+async fn deployment_diff(
+    echo_api: &Api<Echo>,
+    deployment_api: &Api<Deployment>,
+    name: &str,
+) -> anyhow::Result<()> {
+    let echo = echo_api
+        .get(name)
+        .await
+        .with_context(|| format!("getting echo {name}"))?;
+    let live = deployment_api
+        .get_opt(name)
+        .await
+        .with_context(|| format!("getting deployment {name}"))?;
+    let defaults = Defaults {
+        replicas: Some(echo.scheduled_replicas(Utc::now())?.replicas),
+        // copies of the registry credentials are only known by the operator
+        image_pull_secrets: live
+            .as_ref()
+            .and_then(|d| d.spec.as_ref())
+            .and_then(|s| s.template.spec.as_ref())
+            .and_then(|s| s.image_pull_secrets.clone()),
+        ..Defaults::default()
+    };
+    let expected = echo.generate_deployment(&defaults)?;
+    if live.is_none() {
+        println!("deployment {name} does not exist, it would be created");
+        return Ok(());
+    }
+    let changes = diff::applied_diff(live.as_ref(), &expected)?;
+    if changes.is_empty() {
+        println!("deployment {name} is up to date");
+    }
+    for change in changes {
+        let from = change
+            .from
+            .map_or_else(|| "<unset>".to_string(), |v| v.to_string());
+        println!("{}: {} -> {}", change.path, from, change.to);
+    }
+    Ok(())
+}
+
+async fn set_paused(echo_api: &Api<Echo>, name: &str, paused: bool) -> anyhow::Result<()> {
+    let value = paused.then_some("true");
+    echo_api
+        .patch_metadata(
+            name,
+            &PatchParams::default(),
+            &Patch::Merge(json!({"metadata": {"annotations": {PAUSED_ANNOTATION: value}}})),
+        )
+        .await
+        .with_context(|| format!("patching echo {name}"))?;
+    println!("echo {name} {}", if paused { "paused" } else { "resumed" });
+    Ok(())
+}
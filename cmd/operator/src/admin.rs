@@ -0,0 +1,128 @@
+//! Admin API for developers without direct access to the cluster.
+use echo_operator::controller::State;
+use echo_operator::crd::echo::Echo;
+
+use actix_web::web::{Bytes, Data, Path, Query};
+use actix_web::{get, HttpResponse};
+use chrono::Utc;
+use futures::{AsyncBufReadExt, StreamExt, TryStreamExt};
+use k8s_openapi::api::apps::v1::Deployment;
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::{Api, ListParams, LogParams};
+use kube::{Client, ResourceExt};
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct LogsQuery {
+    /// Keep streaming new log lines
+    #[serde(default)]
+    follow: bool,
+    tail_lines: Option<i64>,
+    since_seconds: Option<i64>,
+}
+
+fn error_response(e: kube::Error) -> HttpResponse {
+    tracing::error!(msg = "failed to get echo resources", %e);
+    match e {
+        kube::Error::Api(ae) if ae.code == 404 => HttpResponse::NotFound().finish(),
+        _ => HttpResponse::BadGateway().finish(),
+    }
+}
+
+/// Logs of the pods of an Echo, streamed as plain text lines prefixed with the pod name
+#[get("/api/v1/echoes/{namespace}/{name}/logs")]
+async fn logs(
+    client: Data<Client>,
+    path: Path<(String, String)>,
+    query: Query<LogsQuery>,
+) -> HttpResponse {
+    let (namespace, name) = path.into_inner();
+    let client = client.get_ref().clone();
+    match Api::<Echo>::namespaced(client.clone(), &namespace)
+        .get_opt(&name)
+        .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return HttpResponse::NotFound().body(format!("echo {namespace}/{name} not found"))
+        }
+        Err(e) => return error_response(e),
+    }
+
+    let pod_api = Api::<Pod>::namespaced(client, &namespace);
+    let selector = format!("app={name},app.kubernetes.io/name=echo");
+    let pods = match pod_api.list(&ListParams::default().labels(&selector)).await {
+        Ok(pods) => pods,
+        Err(e) => return error_response(e),
+    };
+    let log_params = LogParams {
+        follow: query.follow,
+        tail_lines: query.tail_lines,
+        since_seconds: query.since_seconds,
+        ..LogParams::default()
+    };
+
+    let mut streams = Vec::new();
+    for pod in pods {
+        let pod_name = pod.name_any();
+        match pod_api.log_stream(&pod_name, &log_params).await {
+            Ok(lines) => streams.push(
+                lines
+                    .lines()
+                    .map_ok(move |line| Bytes::from(format!("[{pod_name}] {line}\n")))
+                    .boxed(),
+            ),
+            // pods can be gone or not started yet, so their logs are skipped
+            Err(e) => tracing::debug!(msg = "skipping pod logs", pod = pod_name, %e),
+        }
+    }
+    if streams.is_empty() {
+        return HttpResponse::NotFound().body(format!("echo {namespace}/{name} has no pods"));
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .streaming(futures::stream::select_all(streams))
+}
+
+/// Manifests the operator applies for an Echo, as a `v1` List, e.g. for `kubectl diff -f -`
+#[get("/api/v1/echoes/{namespace}/{name}/manifests")]
+async fn manifests(
+    state: Data<State>,
+    client: Data<Client>,
+    path: Path<(String, String)>,
+) -> HttpResponse {
+    let (namespace, name) = path.into_inner();
+    let client = client.get_ref().clone();
+    let echo = match Api::<Echo>::namespaced(client.clone(), &namespace)
+        .get_opt(&name)
+        .await
+    {
+        Ok(Some(echo)) => echo,
+        Ok(None) => {
+            return HttpResponse::NotFound().body(format!("echo {namespace}/{name} not found"))
+        }
+        Err(e) => return error_response(e),
+    };
+    let live = match Api::<Deployment>::namespaced(client, &namespace)
+        .get_opt(&name)
+        .await
+    {
+        Ok(live) => live,
+        Err(e) => return error_response(e),
+    };
+
+    match echo.manifests(live.as_ref(), state.cluster_defaults().get(), Utc::now()) {
+        Ok(items) => HttpResponse::Ok().json(json!({
+            "apiVersion": "v1",
+            "kind": "List",
+            "items": items,
+        })),
+        Err(e) => {
+            tracing::error!(msg = "failed to render echo manifests", %e);
+            HttpResponse::UnprocessableEntity().body(e.to_string())
+        }
+    }
+}
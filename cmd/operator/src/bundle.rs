@@ -0,0 +1,207 @@
+//! `bundle` subcommand writing the OLM bundle of the operator: its ClusterServiceVersion, with the
+//! RBAC of the controllers it runs, the CRDs and the bundle metadata.
+use crate::rbac::{self, SERVICE_ACCOUNT};
+use echo_operator::permissions::{self, Permission, CONTROLLER_IDS};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::{crate_description, crate_version, Args};
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use serde_json::{json, Value};
+use tracing::info;
+
+const PACKAGE: &str = "echo-operator";
+const METRICS_PORT: u32 = 8080;
+
+/// CRDs of the chart, which the CRD types are generated from
+const CRDS: [(&str, &str); 6] = [
+    (
+        "crd-echo.yaml",
+        include_str!("../../../charts/echo-operator/crds/crd-echo.yaml"),
+    ),
+    (
+        "crd-echogateway.yaml",
+        include_str!("../../../charts/echo-operator/crds/crd-echogateway.yaml"),
+    ),
+    (
+        "crd-echoroute.yaml",
+        include_str!("../../../charts/echo-operator/crds/crd-echoroute.yaml"),
+    ),
+    (
+        "crd-echoquota.yaml",
+        include_str!("../../../charts/echo-operator/crds/crd-echoquota.yaml"),
+    ),
+    (
+        "crd-echoreplication.yaml",
+        include_str!("../../../charts/echo-operator/crds/crd-echoreplication.yaml"),
+    ),
+    (
+        "crd-clusterecho.yaml",
+        include_str!("../../../charts/echo-operator/crds/crd-clusterecho.yaml"),
+    ),
+];
+
+#[derive(Args, Debug)]
+pub struct BundleArgs {
+    /// Directory where the `manifests` and `metadata` directories of the bundle are written
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Image of the operator
+    #[arg(long, default_value = concat!("ghcr.io/pando85/echo-operator:", env!("CARGO_PKG_VERSION")))]
+    image: String,
+
+    /// OLM channel of the bundle
+    #[arg(long, default_value = "alpha")]
+    channel: String,
+}
+
+pub fn run(args: BundleArgs, controllers: &[String]) -> anyhow::Result<()> {
+    let crds = CRDS
+        .iter()
+        .map(|(file, yaml)| {
+            let crd: CustomResourceDefinition =
+                serde_yaml::from_str(yaml).with_context(|| format!("parsing {file}"))?;
+            Ok((*file, yaml, crd))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let manifests = args.output.join("manifests");
+    let metadata = args.output.join("metadata");
+    fs::create_dir_all(&manifests).context("creating manifests directory")?;
+    fs::create_dir_all(&metadata).context("creating metadata directory")?;
+
+    let csv = cluster_service_version(
+        &args.image,
+        &crds.iter().map(|(_, _, crd)| crd).collect::<Vec<_>>(),
+        &enabled_permissions(controllers)?,
+    );
+    write(
+        &manifests.join(format!("{PACKAGE}.clusterserviceversion.yaml")),
+        &serde_yaml::to_string(&csv)?,
+    )?;
+    for (file, yaml, _) in &crds {
+        write(&manifests.join(file), yaml)?;
+    }
+    write(
+        &metadata.join("annotations.yaml"),
+        &serde_yaml::to_string(&annotations(&args.channel))?,
+    )?;
+    info!(msg = "bundle written", output = %args.output.display());
+    Ok(())
+}
+
+fn write(path: &Path, content: &str) -> anyhow::Result<()> {
+    fs::write(path, content).with_context(|| format!("writing {}", path.display()))
+}
+
+/// Permissions of the operator running the given controllers, every controller if none is given
+fn enabled_permissions(controllers: &[String]) -> anyhow::Result<Vec<Permission>> {
+    if controllers.is_empty() {
+        rbac::permissions(&CONTROLLER_IDS)
+    } else {
+        rbac::permissions(&controllers.iter().map(String::as_str).collect::<Vec<_>>())
+    }
+}
+
+fn cluster_service_version(
+    image: &str,
+    crds: &[&CustomResourceDefinition],
+    permissions: &[Permission],
+) -> Value {
+    let version = crate_version!();
+    let owned: Vec<Value> = crds
+        .iter()
+        .map(|crd| {
+            let served = crd.spec.versions.iter().find(|v| v.storage);
+            json!({
+                "name": crd.metadata.name,
+                "kind": crd.spec.names.kind,
+                "version": served.map(|v| v.name.as_str()),
+                "displayName": crd.spec.names.kind,
+                "description": served
+                    .and_then(|v| v.schema.as_ref())
+                    .and_then(|s| s.open_api_v3_schema.as_ref())
+                    .and_then(|s| s.description.as_deref()),
+            })
+        })
+        .collect();
+    let labels = json!({"app.kubernetes.io/name": PACKAGE});
+    json!({
+        "apiVersion": "operators.coreos.com/v1alpha1",
+        "kind": "ClusterServiceVersion",
+        "metadata": {
+            "name": format!("{PACKAGE}.v{version}"),
+            "annotations": {
+                "capabilities": "Basic Install",
+                "containerImage": image,
+            },
+        },
+        "spec": {
+            "displayName": "Echo Operator",
+            "description": crate_description!(),
+            "version": version,
+            "maturity": "alpha",
+            "provider": {"name": "pando85"},
+            "installModes": [
+                {"type": "OwnNamespace", "supported": false},
+                {"type": "SingleNamespace", "supported": false},
+                {"type": "MultiNamespace", "supported": false},
+                {"type": "AllNamespaces", "supported": true},
+            ],
+            "customresourcedefinitions": {"owned": owned},
+            "install": {
+                "strategy": "deployment",
+                "spec": {
+                    "clusterPermissions": [{
+                        "serviceAccountName": SERVICE_ACCOUNT,
+                        "rules": permissions::policy_rules(permissions),
+                    }],
+                    "deployments": [{
+                        "name": PACKAGE,
+                        "spec": {
+                            "replicas": 1,
+                            "selector": {"matchLabels": labels},
+                            "template": {
+                                "metadata": {"labels": labels},
+                                "spec": {
+                                    "serviceAccountName": SERVICE_ACCOUNT,
+                                    "containers": [{
+                                        "name": PACKAGE,
+                                        "image": image,
+                                        "ports": [{
+                                            "name": "metrics",
+                                            "containerPort": METRICS_PORT,
+                                            "protocol": "TCP",
+                                        }],
+                                        "readinessProbe": {
+                                            "httpGet": {"path": "/readyz", "port": "metrics"},
+                                        },
+                                        "livenessProbe": {
+                                            "httpGet": {"path": "/health", "port": "metrics"},
+                                        },
+                                    }],
+                                },
+                            },
+                        },
+                    }],
+                },
+            },
+        },
+    })
+}
+
+fn annotations(channel: &str) -> Value {
+    json!({
+        "annotations": {
+            "operators.operatorframework.io.bundle.mediatype.v1": "registry+v1",
+            "operators.operatorframework.io.bundle.manifests.v1": "manifests/",
+            "operators.operatorframework.io.bundle.metadata.v1": "metadata/",
+            "operators.operatorframework.io.bundle.package.v1": PACKAGE,
+            "operators.operatorframework.io.bundle.channels.v1": channel,
+            "operators.operatorframework.io.bundle.channel.default.v1": channel,
+        }
+    })
+}
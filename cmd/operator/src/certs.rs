@@ -0,0 +1,536 @@
+//! Self-signed certificates of the admission webhook, so it can be deployed without cert-manager.
+//!
+//! A CA and a serving certificate signed by it are stored in a Secret of the operator namespace,
+//! shared by every replica, and the CA is patched into the caBundle of the webhook configuration.
+//! The serving certificate is generated again, and served without restarting, before it expires.
+//! It is signed by the same CA, so the certificates the other replicas serve until they reload the
+//! Secret stay trusted. The CA is only replaced when it would expire before the new certificate,
+//! and the previous one is trusted along with it until the next renewal.
+use std::collections::BTreeMap;
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Duration, Utc};
+use k8s_openapi::api::admissionregistration::v1::ValidatingWebhookConfiguration;
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::ByteString;
+use kube::api::{Api, ObjectMeta, Patch, PatchParams, PostParams};
+use kube::{Client, ResourceExt};
+use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+use rustls::crypto::CryptoProvider;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use serde_json::json;
+use tracing::{error, info};
+
+const FIELD_MANAGER: &str = "echoes.example.com";
+/// Expiration of the serving certificate in the Secret, so it is known without parsing it
+const NOT_AFTER_ANNOTATION: &str = "echoes.example.com/not-after";
+/// Expiration of the CA in the Secret
+const ISSUER_NOT_AFTER_ANNOTATION: &str = "echoes.example.com/issuer-not-after";
+/// CAs trusted by the webhook configuration
+const CA_CERT_KEY: &str = "ca.crt";
+const ISSUER_CERT_KEY: &str = "issuer.crt";
+const ISSUER_KEY_KEY: &str = "issuer.key";
+const TLS_CERT_KEY: &str = "tls.crt";
+const TLS_KEY_KEY: &str = "tls.key";
+const VALIDITY_DAYS: i64 = 365;
+const ISSUER_VALIDITY_DAYS: i64 = 10 * VALIDITY_DAYS;
+const RENEW_BEFORE_DAYS: i64 = 30;
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(12 * 60 * 60);
+
+/// Valid from the day before, in case of clock skews, until `not_after`
+fn set_validity(params: &mut CertificateParams, now: DateTime<Utc>, not_after: DateTime<Utc>) {
+    let ymd = |d: DateTime<Utc>| rcgen::date_time_ymd(d.year(), d.month() as u8, d.day() as u8);
+    params.not_before = ymd(now - Duration::days(1));
+    params.not_after = ymd(not_after);
+}
+
+/// PEM encoded CA signing the serving certificates, and its private key
+#[derive(Clone, Debug)]
+struct Issuer {
+    cert: String,
+    key: String,
+    not_after: DateTime<Utc>,
+}
+
+impl Issuer {
+    /// Parameters of the CA. Its subject is the only one signing needs, so the certificate
+    /// generated again from them and the stored key signs as the stored one.
+    fn params() -> anyhow::Result<CertificateParams> {
+        let mut params = CertificateParams::new(Vec::<String>::new())?;
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "echo-operator-webhook-ca");
+        Ok(params)
+    }
+
+    fn generate(now: DateTime<Utc>) -> anyhow::Result<Self> {
+        let not_after = now + Duration::days(ISSUER_VALIDITY_DAYS);
+        let mut params = Self::params()?;
+        set_validity(&mut params, now, not_after);
+        let key = KeyPair::generate()?;
+        let cert = params.self_signed(&key)?;
+        Ok(Self {
+            cert: cert.pem(),
+            key: key.serialize_pem(),
+            not_after,
+        })
+    }
+
+    /// Whether a serving certificate signed now would outlive the CA
+    fn needs_renewal(&self, now: DateTime<Utc>) -> bool {
+        self.not_after - Duration::days(VALIDITY_DAYS) <= now
+    }
+
+    /// PEM encoded serving certificate, expiring at `not_after`, and its private key
+    fn sign(
+        &self,
+        dns_names: Vec<String>,
+        now: DateTime<Utc>,
+        not_after: DateTime<Utc>,
+    ) -> anyhow::Result<(String, String)> {
+        let ca_key = KeyPair::from_pem(&self.key).context("invalid webhook CA private key")?;
+        let ca = Self::params()?.self_signed(&ca_key)?;
+
+        let mut params = CertificateParams::new(dns_names)?;
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "echo-operator-webhook");
+        set_validity(&mut params, now, not_after);
+        let key = KeyPair::generate()?;
+        let cert = params.signed_by(&key, &ca, &ca_key)?;
+        Ok((cert.pem(), key.serialize_pem()))
+    }
+}
+
+/// PEM encoded CAs, serving certificate and its private key
+struct Certificates {
+    /// CAs trusted by the webhook configuration: the issuer and, after it was replaced, the
+    /// previous one
+    ca_bundle: String,
+    /// Missing in the Secrets written before the CA was kept across renewals
+    issuer: Option<Issuer>,
+    cert: String,
+    key: String,
+    not_after: DateTime<Utc>,
+}
+
+impl Certificates {
+    /// Serving certificate signed by the CA of the current certificates, unless there are none or
+    /// their CA needs to be replaced
+    fn renew(
+        current: Option<&Self>,
+        dns_names: Vec<String>,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<Self> {
+        let kept = current
+            .and_then(|c| c.issuer.as_ref())
+            .filter(|i| !i.needs_renewal(now));
+        let (issuer, ca_bundle) = match kept {
+            Some(issuer) => (issuer.clone(), issuer.cert.clone()),
+            None => {
+                let issuer = Issuer::generate(now)?;
+                // the other replicas serve the certificates signed by the previous CA until
+                // they reload the Secret
+                let ca_bundle = match current {
+                    Some(Self {
+                        issuer: Some(previous),
+                        ..
+                    }) => format!("{}{}", issuer.cert, previous.cert),
+                    Some(previous) => format!("{}{}", issuer.cert, previous.ca_bundle),
+                    None => issuer.cert.clone(),
+                };
+                (issuer, ca_bundle)
+            }
+        };
+        let not_after = now + Duration::days(VALIDITY_DAYS);
+        let (cert, key) = issuer.sign(dns_names, now, not_after)?;
+        Ok(Self {
+            ca_bundle,
+            issuer: Some(issuer),
+            cert,
+            key,
+            not_after,
+        })
+    }
+
+    fn from_secret(secret: &Secret) -> Option<Self> {
+        let data = secret.data.as_ref()?;
+        let pem = |key: &str| {
+            data.get(key)
+                .and_then(|v| String::from_utf8(v.0.clone()).ok())
+        };
+        let not_after = |annotation: &str| {
+            secret
+                .annotations()
+                .get(annotation)
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .map(|t| t.with_timezone(&Utc))
+        };
+        let issuer = pem(ISSUER_CERT_KEY)
+            .zip(pem(ISSUER_KEY_KEY))
+            .zip(not_after(ISSUER_NOT_AFTER_ANNOTATION))
+            .map(|((cert, key), not_after)| Issuer {
+                cert,
+                key,
+                not_after,
+            });
+        Some(Self {
+            ca_bundle: pem(CA_CERT_KEY)?,
+            issuer,
+            cert: pem(TLS_CERT_KEY)?,
+            key: pem(TLS_KEY_KEY)?,
+            not_after: not_after(NOT_AFTER_ANNOTATION)?,
+        })
+    }
+
+    fn secret(&self, name: &str, namespace: &str) -> Secret {
+        let data = |pem: &str| ByteString(pem.as_bytes().to_vec());
+        let mut annotations =
+            BTreeMap::from([(NOT_AFTER_ANNOTATION.to_owned(), self.not_after.to_rfc3339())]);
+        let mut secret_data = BTreeMap::from([
+            (CA_CERT_KEY.to_owned(), data(&self.ca_bundle)),
+            (TLS_CERT_KEY.to_owned(), data(&self.cert)),
+            (TLS_KEY_KEY.to_owned(), data(&self.key)),
+        ]);
+        if let Some(issuer) = &self.issuer {
+            annotations.insert(
+                ISSUER_NOT_AFTER_ANNOTATION.to_owned(),
+                issuer.not_after.to_rfc3339(),
+            );
+            secret_data.insert(ISSUER_CERT_KEY.to_owned(), data(&issuer.cert));
+            secret_data.insert(ISSUER_KEY_KEY.to_owned(), data(&issuer.key));
+        }
+        Secret {
+            metadata: ObjectMeta {
+                name: Some(name.to_owned()),
+                namespace: Some(namespace.to_owned()),
+                annotations: Some(annotations),
+                ..ObjectMeta::default()
+            },
+            type_: Some("kubernetes.io/tls".to_owned()),
+            data: Some(secret_data),
+            ..Secret::default()
+        }
+    }
+
+    fn needs_renewal(&self, now: DateTime<Utc>) -> bool {
+        self.not_after - Duration::days(RENEW_BEFORE_DAYS) <= now
+    }
+
+    fn certified_key(&self) -> anyhow::Result<Arc<CertifiedKey>> {
+        let certs = rustls_pemfile::certs(&mut BufReader::new(self.cert.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()
+            .context("reading webhook certificate")?;
+        let key = rustls_pemfile::private_key(&mut BufReader::new(self.key.as_bytes()))
+            .context("reading webhook private key")?
+            .context("webhook private key not found")?;
+        let key = CryptoProvider::get_default()
+            .context("crypto provider not installed")?
+            .key_provider
+            .load_private_key(key)
+            .context("invalid webhook private key")?;
+        Ok(Arc::new(CertifiedKey::new(certs, key)))
+    }
+}
+
+/// Certificate served by the webhook, replaced when it is rotated
+#[derive(Debug)]
+struct CertResolver(RwLock<Arc<CertifiedKey>>);
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        // safe unwrap: the lock is never held across a panic
+        Some(self.0.read().unwrap().clone())
+    }
+}
+
+/// Secret with the certificates of the webhook Service
+struct CertificateSecret {
+    client: Client,
+    namespace: String,
+    secret: String,
+    service: String,
+    configuration: String,
+}
+
+impl CertificateSecret {
+    fn dns_names(&self) -> Vec<String> {
+        let (service, namespace) = (&self.service, &self.namespace);
+        vec![
+            service.clone(),
+            format!("{service}.{namespace}"),
+            format!("{service}.{namespace}.svc"),
+            format!("{service}.{namespace}.svc.cluster.local"),
+        ]
+    }
+
+    /// Certificates of the Secret, renewed if needed. Concurrent renewals of other replicas win
+    /// through the optimistic concurrency of the Secret writes.
+    async fn load_or_generate(&self) -> anyhow::Result<Certificates> {
+        let api = Api::<Secret>::namespaced(self.client.clone(), &self.namespace);
+        loop {
+            let current = api.get_opt(&self.secret).await?;
+            let now = Utc::now();
+            let loaded = match current.as_ref().and_then(Certificates::from_secret) {
+                Some(certificates) if !certificates.needs_renewal(now) => return Ok(certificates),
+                loaded => loaded,
+            };
+            info!(
+                msg = "generating webhook certificates",
+                secret = self.secret
+            );
+            let certificates = Certificates::renew(loaded.as_ref(), self.dns_names(), now)?;
+            let mut secret = certificates.secret(&self.secret, &self.namespace);
+            let result = match current {
+                Some(current) => {
+                    secret.metadata.resource_version = current.resource_version();
+                    api.replace(&self.secret, &PostParams::default(), &secret)
+                        .await
+                }
+                None => api.create(&PostParams::default(), &secret).await,
+            };
+            match result {
+                Ok(_) => return Ok(certificates),
+                // written by another replica meanwhile
+                Err(kube::Error::Api(ae)) if ae.code == 409 => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    async fn patch_ca_bundle(&self, ca: &str) -> anyhow::Result<()> {
+        let api = Api::<ValidatingWebhookConfiguration>::all(self.client.clone());
+        let configuration = api.get(&self.configuration).await?;
+        let webhooks: Vec<_> = configuration
+            .webhooks
+            .iter()
+            .flatten()
+            .map(|w| json!({"name": w.name, "clientConfig": {"caBundle": ByteString(ca.as_bytes().to_vec())}}))
+            .collect();
+        api.patch(
+            &self.configuration,
+            &PatchParams::apply(FIELD_MANAGER).force(),
+            &Patch::Apply(json!({
+                "apiVersion": "admissionregistration.k8s.io/v1",
+                "kind": "ValidatingWebhookConfiguration",
+                "webhooks": webhooks,
+            })),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// Certificates served by the webhook
+pub struct WebhookCertificates {
+    secret: CertificateSecret,
+    resolver: Arc<CertResolver>,
+}
+
+impl WebhookCertificates {
+    /// Load the certificates, or generate them if missing or about to expire, returning the TLS
+    /// config serving them
+    pub async fn init(
+        client: Client,
+        secret: String,
+        service: String,
+        configuration: String,
+    ) -> anyhow::Result<(Self, rustls::ServerConfig)> {
+        let secret = CertificateSecret {
+            namespace: client.default_namespace().to_owned(),
+            client,
+            secret,
+            service,
+            configuration,
+        };
+        let certificates = secret.load_or_generate().await?;
+        secret.patch_ca_bundle(&certificates.ca_bundle).await?;
+        let resolver = Arc::new(CertResolver(RwLock::new(certificates.certified_key()?)));
+        let config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone());
+        Ok((Self { secret, resolver }, config))
+    }
+
+    /// Trust the CAs of the Secret and serve its certificate
+    async fn refresh(&self) -> anyhow::Result<()> {
+        let certificates = self.secret.load_or_generate().await?;
+        // a new CA is trusted before the certificate it signed is served
+        self.secret.patch_ca_bundle(&certificates.ca_bundle).await?;
+        // safe unwrap: the lock is never held across a panic
+        *self.resolver.0.write().unwrap() = certificates.certified_key()?;
+        Ok(())
+    }
+
+    /// Rotate the certificates before they expire, until the operator shuts down
+    pub async fn rotate(self) {
+        let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+        // the first tick completes immediately, and the certificates were just loaded
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.refresh().await {
+                error!(msg = "failed to rotate webhook certificates", %e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Certificates, RENEW_BEFORE_DAYS, VALIDITY_DAYS};
+
+    use std::io::BufReader;
+    use std::sync::Arc;
+
+    use chrono::{DateTime, Duration, Utc};
+    use rustls::client::danger::ServerCertVerifier;
+    use rustls::client::WebPkiServerVerifier;
+    use rustls::pki_types::{ServerName, UnixTime};
+    use rustls::RootCertStore;
+
+    const DNS_NAME: &str = "echo-operator-webhook.default.svc";
+
+    fn generate(now: DateTime<Utc>) -> Certificates {
+        Certificates::renew(None, vec![DNS_NAME.to_owned()], now).unwrap()
+    }
+
+    /// Whether a client trusting the CA bundle accepts the serving certificate at the time
+    fn trusts(ca_bundle: &str, cert: &str, now: DateTime<Utc>) -> bool {
+        let mut roots = RootCertStore::empty();
+        for ca in rustls_pemfile::certs(&mut BufReader::new(ca_bundle.as_bytes())) {
+            roots.add(ca.unwrap()).unwrap();
+        }
+        let verifier = WebPkiServerVerifier::builder_with_provider(
+            Arc::new(roots),
+            Arc::new(rustls::crypto::ring::default_provider()),
+        )
+        .build()
+        .unwrap();
+        let cert = rustls_pemfile::certs(&mut BufReader::new(cert.as_bytes()))
+            .next()
+            .unwrap()
+            .unwrap();
+        verifier
+            .verify_server_cert(
+                &cert,
+                &[],
+                &ServerName::try_from(DNS_NAME).unwrap(),
+                &[],
+                UnixTime::since_unix_epoch(std::time::Duration::from_secs(now.timestamp() as u64)),
+            )
+            .is_ok()
+    }
+
+    #[test]
+    fn test_generate() {
+        let now = Utc::now();
+        let certificates = generate(now);
+
+        assert_eq!(certificates.not_after, now + Duration::days(VALIDITY_DAYS));
+        let issuer = certificates.issuer.as_ref().unwrap();
+        assert_eq!(certificates.ca_bundle, issuer.cert);
+        assert!(trusts(&certificates.ca_bundle, &certificates.cert, now));
+    }
+
+    #[test]
+    fn test_secret_round_trip() {
+        let certificates = generate(Utc::now());
+        let secret = certificates.secret("webhook-certs", "default");
+
+        assert_eq!(secret.metadata.name.as_deref(), Some("webhook-certs"));
+        assert_eq!(secret.type_.as_deref(), Some("kubernetes.io/tls"));
+        let loaded = Certificates::from_secret(&secret).unwrap();
+        assert_eq!(loaded.ca_bundle, certificates.ca_bundle);
+        assert_eq!(loaded.cert, certificates.cert);
+        assert_eq!(loaded.key, certificates.key);
+        // the annotations only keep whole seconds
+        assert_eq!(
+            loaded.not_after.timestamp(),
+            certificates.not_after.timestamp()
+        );
+        let (issuer, loaded_issuer) = (certificates.issuer.unwrap(), loaded.issuer.unwrap());
+        assert_eq!(loaded_issuer.cert, issuer.cert);
+        assert_eq!(loaded_issuer.key, issuer.key);
+
+        let mut incomplete = secret.clone();
+        incomplete.data.as_mut().unwrap().remove("tls.key");
+        assert!(Certificates::from_secret(&incomplete).is_none());
+        // written before the CA was kept
+        let mut without_issuer = secret;
+        without_issuer.data.as_mut().unwrap().remove("issuer.key");
+        assert!(Certificates::from_secret(&without_issuer)
+            .unwrap()
+            .issuer
+            .is_none());
+    }
+
+    #[test]
+    fn test_needs_renewal() {
+        let now = Utc::now();
+        let certificates = generate(now);
+        let renewal = certificates.not_after - Duration::days(RENEW_BEFORE_DAYS);
+
+        assert!(!certificates.needs_renewal(now));
+        assert!(!certificates.needs_renewal(renewal - Duration::seconds(1)));
+        assert!(certificates.needs_renewal(renewal));
+        assert!(certificates.needs_renewal(certificates.not_after));
+    }
+
+    #[test]
+    fn test_renew_keeps_issuer() {
+        let now = Utc::now();
+        let current = generate(now);
+        let renewal = current.not_after - Duration::days(RENEW_BEFORE_DAYS);
+
+        let renewed =
+            Certificates::renew(Some(&current), vec![DNS_NAME.to_owned()], renewal).unwrap();
+
+        assert_eq!(renewed.ca_bundle, current.ca_bundle);
+        assert_ne!(renewed.cert, current.cert);
+        // both certificates are trusted until every replica serves the renewed one
+        assert!(trusts(&current.ca_bundle, &renewed.cert, renewal));
+        assert!(trusts(&renewed.ca_bundle, &current.cert, renewal));
+    }
+
+    #[test]
+    fn test_renew_replaces_expiring_issuer() {
+        let now = Utc::now();
+        let mut current = generate(now);
+        let issuer = current.issuer.as_mut().unwrap();
+        assert!(!issuer.needs_renewal(now));
+        // expiring before a renewed serving certificate
+        issuer.not_after = now + Duration::days(VALIDITY_DAYS - 1);
+        assert!(issuer.needs_renewal(now));
+
+        let renewed = Certificates::renew(Some(&current), vec![DNS_NAME.to_owned()], now).unwrap();
+
+        let previous = current.issuer.as_ref().unwrap();
+        let issuer = renewed.issuer.as_ref().unwrap();
+        assert_ne!(issuer.cert, previous.cert);
+        assert_eq!(
+            renewed.ca_bundle,
+            format!("{}{}", issuer.cert, previous.cert)
+        );
+        assert!(trusts(&renewed.ca_bundle, &renewed.cert, now));
+        assert!(trusts(&renewed.ca_bundle, &current.cert, now));
+        assert!(!trusts(&current.ca_bundle, &renewed.cert, now));
+    }
+
+    #[test]
+    fn test_renew_without_issuer() {
+        let now = Utc::now();
+        let mut current = generate(now);
+        current.issuer = None;
+
+        let renewed = Certificates::renew(Some(&current), vec![DNS_NAME.to_owned()], now).unwrap();
+
+        assert!(renewed.ca_bundle.ends_with(&current.ca_bundle));
+        assert!(trusts(&renewed.ca_bundle, &renewed.cert, now));
+        assert!(trusts(&renewed.ca_bundle, &current.cert, now));
+    }
+}
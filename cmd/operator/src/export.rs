@@ -0,0 +1,171 @@
+//! `export` subcommand dumping Echo resources as apply-ready YAML.
+use echo_operator::crd::echo::Echo;
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::Args;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use k8s_openapi::api::apps::v1::Deployment;
+use k8s_openapi::api::core::v1::Service;
+use kube::api::{Api, ListParams};
+use kube::{Client, ResourceExt};
+use serde::Serialize;
+use serde_json::Value;
+use tracing::info;
+
+/// Metadata fields set by the API server, which are not valid in another cluster
+const SERVER_METADATA: [&str; 7] = [
+    "uid",
+    "resourceVersion",
+    "generation",
+    "creationTimestamp",
+    "managedFields",
+    "ownerReferences",
+    "selfLink",
+];
+
+#[derive(Args, Debug)]
+pub struct ExportArgs {
+    /// Directory, or tarball when ending in `.tar.gz` or `.tgz`, where resources are written
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Export echoes only from the given namespace. All namespaces by default
+    #[arg(short, long)]
+    namespace: Option<String>,
+
+    /// Export the Deployments and Services owned by every Echo too
+    #[arg(long)]
+    include_children: bool,
+
+    /// Remove the status of the exported resources
+    #[arg(long)]
+    strip_status: bool,
+}
+
+/// Exported resource, with its path relative to the output
+struct Manifest {
+    path: PathBuf,
+    yaml: String,
+}
+
+pub async fn run(client: Client, args: ExportArgs) -> anyhow::Result<()> {
+    let echo_api = match &args.namespace {
+        Some(namespace) => Api::<Echo>::namespaced(client.clone(), namespace),
+        None => Api::<Echo>::all(client.clone()),
+    };
+    let echoes = echo_api
+        .list(&ListParams::default())
+        .await
+        .context("listing echoes")?;
+
+    let mut manifests = Vec::new();
+    for echo in &echoes {
+        // safe unwrap: Echo is namespaced scoped
+        let namespace = echo.namespace().unwrap();
+        manifests.push(manifest(&namespace, "echo", echo, args.strip_status)?);
+        if args.include_children {
+            let deployment_api = Api::<Deployment>::namespaced(client.clone(), &namespace);
+            if let Some(deployment) = deployment_api.get_opt(&echo.name_any()).await? {
+                manifests.push(manifest(
+                    &namespace,
+                    "deployment",
+                    &deployment,
+                    args.strip_status,
+                )?);
+            }
+            if echo.spec.dns_name.is_some() {
+                let service_api = Api::<Service>::namespaced(client.clone(), &namespace);
+                if let Some(service) = service_api.get_opt(&echo.name_any()).await? {
+                    manifests.push(manifest(
+                        &namespace,
+                        "service",
+                        &service,
+                        args.strip_status,
+                    )?);
+                }
+            }
+        }
+    }
+
+    if is_tarball(&args.output) {
+        write_tarball(&args.output, &manifests)?;
+    } else {
+        write_directory(&args.output, &manifests)?;
+    }
+    info!(
+        msg = "exported echoes",
+        echoes = echoes.items.len(),
+        files = manifests.len(),
+        output = %args.output.display()
+    );
+    Ok(())
+}
+
+fn manifest<K: Serialize + ResourceExt>(
+    namespace: &str,
+    kind: &str,
+    resource: &K,
+    strip_status: bool,
+) -> anyhow::Result<Manifest> {
+    let mut value = serde_json::to_value(resource)?;
+    sanitize(&mut value, strip_status);
+    Ok(Manifest {
+        path: Path::new(namespace).join(format!("{kind}-{}.yaml", resource.name_any())),
+        yaml: serde_yaml::to_string(&value)?,
+    })
+}
+
+/// Remove the server populated fields so the resource can be applied in any cluster
+fn sanitize(value: &mut Value, strip_status: bool) {
+    if let Some(metadata) = value.get_mut("metadata").and_then(Value::as_object_mut) {
+        SERVER_METADATA.iter().for_each(|field| {
+            metadata.remove(*field);
+        });
+        if let Some(annotations) = metadata
+            .get_mut("annotations")
+            .and_then(Value::as_object_mut)
+        {
+            annotations.remove("kubectl.kubernetes.io/last-applied-configuration");
+        }
+    }
+    if strip_status {
+        if let Some(object) = value.as_object_mut() {
+            object.remove("status");
+        }
+    }
+}
+
+fn is_tarball(output: &Path) -> bool {
+    let name = output.to_string_lossy();
+    name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+fn write_directory(output: &Path, manifests: &[Manifest]) -> anyhow::Result<()> {
+    for manifest in manifests {
+        let path = output.join(&manifest.path);
+        // safe unwrap: manifest paths are always inside a namespace directory
+        fs::create_dir_all(path.parent().unwrap())
+            .with_context(|| format!("creating {}", path.display()))?;
+        fs::write(&path, &manifest.yaml).with_context(|| format!("writing {}", path.display()))?;
+    }
+    Ok(())
+}
+
+fn write_tarball(output: &Path, manifests: &[Manifest]) -> anyhow::Result<()> {
+    let file = File::create(output).with_context(|| format!("creating {}", output.display()))?;
+    let mut tarball = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    for manifest in manifests {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest.yaml.len() as u64);
+        header.set_mode(0o644);
+        tarball
+            .append_data(&mut header, &manifest.path, manifest.yaml.as_bytes())
+            .with_context(|| format!("adding {}", manifest.path.display()))?;
+    }
+    tarball.into_inner()?.finish()?;
+    Ok(())
+}
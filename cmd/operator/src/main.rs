@@ -1,15 +1,53 @@
 use actix_web::{
     get, middleware, web::Data, App, HttpRequest, HttpResponse, HttpServer, Responder,
 };
-use echo_operator::controller::State;
-use echo_operator::echo;
+use echo_operator::audit::{AuditSink, Auditor};
+use echo_operator::clusterdefaults;
+use echo_operator::clusterecho;
+use echo_operator::controller::{ControllerRegistry, State, METRICS_PREFIX};
+use echo_operator::dashboard::{self, DashboardKind};
+use echo_operator::echo::{self, priority::SchedulingPolicy};
+use echo_operator::echogateway;
+use echo_operator::echoquota;
+use echo_operator::echoreplication;
+use echo_operator::echoroute;
+use echo_operator::echostatus;
+use echo_operator::gc::{self, GcPolicy};
+use echo_operator::heartbeat::{self, HeartbeatConfig};
+use echo_operator::maintenance::{self, MaintenanceWindow};
+use echo_operator::namespace_filter::NamespaceFilter;
+use echo_operator::notify::{self, Notifier, Sink, SinkFormat};
+use echo_operator::prober::{self, ProbeConfig};
+use echo_operator::rbac_allowlist::{AllowedPermission, RbacAllowlist};
+use echo_operator::remotewrite::{self, RemoteWriteAuth, RemoteWriteConfig};
+use echo_operator::requeue::ReconcileConfig;
+use echo_operator::sentry::{Dsn, ErrorReporter};
+use echo_operator::startup::StartupRampConfig;
+use echo_operator::statsd::{self, StatsdConfig};
 use echo_operator::telemetry;
 use echo_operator_k8s_util::client::new_client_with_metrics;
+use echo_operator_k8s_util::hedge::{self, HedgeConfig};
+use echo_operator_k8s_util::tls;
 
-use clap::{crate_authors, crate_description, crate_version, Parser};
-use kube::Config;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{
+    crate_authors, crate_description, crate_version, CommandFactory, FromArgMatches, Parser,
+    Subcommand,
+};
+use kube::{Client, Config};
 use prometheus_client::registry::Registry;
 
+mod admin;
+mod bundle;
+mod certs;
+mod export;
+mod manifests;
+mod migrate;
+mod rbac;
+mod webhook;
+
 #[get("/metrics")]
 async fn metrics(c: Data<State>, _req: HttpRequest) -> impl Responder {
     match c.metrics() {
@@ -28,6 +66,16 @@ async fn health(_: HttpRequest) -> impl Responder {
     HttpResponse::Ok().json("healthy")
 }
 
+#[get("/readyz")]
+async fn readyz(c: Data<State>, client: Data<Client>, _req: HttpRequest) -> impl Responder {
+    let readiness = c.readiness(client.get_ref().clone()).await;
+    if readiness.ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(
     name="echo-operator",
@@ -62,39 +110,613 @@ struct Args {
     /// of traces are sampled.
     #[arg(short, long, default_value_t = 0.1, env)]
     sample_ratio: f64,
+
+    /// Latency percentile for hedging read-only requests to the Kubernetes API.
+    ///
+    /// When set, GET and LIST requests slower than the given percentile (between 0 and 1) of
+    /// the latest observed latencies are sent a second time and the first response is used.
+    /// If not provided, request hedging is disabled.
+    #[arg(long, env, value_parser = hedge::parse_latency_percentile)]
+    hedge_latency_percentile: Option<f64>,
+
+    /// Listen on given port for admission webhook requests
+    #[arg(long, default_value_t = 8443, env)]
+    webhook_port: u32,
+
+    /// PEM certificate file used to serve the admission webhook.
+    ///
+    /// The admission webhook is enabled when both certificate and private key files are provided.
+    #[arg(long, env, requires = "webhook_tls_key_file")]
+    webhook_tls_cert_file: Option<PathBuf>,
+
+    /// PEM private key file used to serve the admission webhook.
+    #[arg(long, env, requires = "webhook_tls_cert_file")]
+    webhook_tls_key_file: Option<PathBuf>,
+
+    /// Secret, in the operator namespace, where self-signed webhook certificates are generated
+    /// and rotated, instead of using certificate files.
+    ///
+    /// The admission webhook is enabled when it is provided, together with the webhook Service and
+    /// configuration.
+    #[arg(
+        long,
+        env,
+        conflicts_with = "webhook_tls_cert_file",
+        requires_all = ["webhook_service", "webhook_configuration"]
+    )]
+    webhook_cert_secret: Option<String>,
+
+    /// Service of the admission webhook, in the operator namespace, named in the certificates.
+    #[arg(long, env)]
+    webhook_service: Option<String>,
+
+    /// ValidatingWebhookConfiguration whose caBundle is set to the generated CA.
+    #[arg(long, env)]
+    webhook_configuration: Option<String>,
+
+    /// Webhook URLs receiving a JSON notification when an Echo becomes Ready, NotReady or
+    /// Degraded.
+    #[arg(long, env, value_delimiter = ',')]
+    notification_webhook_url: Vec<String>,
+
+    /// Slack incoming webhook URLs receiving the Echo status transition notifications.
+    #[arg(long, env, value_delimiter = ',')]
+    notification_slack_webhook_url: Vec<String>,
+
+    /// URLs receiving the Echo status transitions, creations and deletions as CloudEvents in
+    /// HTTP binary mode.
+    #[arg(long, env, value_delimiter = ',')]
+    notification_cloudevents_url: Vec<String>,
+
+    /// Template of the notification messages.
+    ///
+    /// `{namespace}`, `{name}`, `{health}` and `{message}` are replaced with the Echo values.
+    #[arg(long, default_value = notify::DEFAULT_TEMPLATE, env)]
+    notification_template: String,
+
+    /// File where the mutating actions of the reconcilers are appended as JSON lines.
+    #[arg(long, env, conflicts_with = "audit_url")]
+    audit_file: Option<PathBuf>,
+
+    /// HTTP endpoint receiving the mutating actions of the reconcilers as JSON objects.
+    #[arg(long, env)]
+    audit_url: Option<String>,
+
+    /// Sentry DSN, or the one of a tracker compatible with its protocol, receiving the failed
+    /// reconciliations and the panics of the operator.
+    ///
+    /// If not provided, errors are only logged.
+    #[arg(long, env)]
+    sentry_dsn: Option<Dsn>,
+
+    /// Environment of the errors reported to Sentry, e.g. the cluster name.
+    #[arg(long, env)]
+    sentry_environment: Option<String>,
+
+    /// What to do with the managed Deployments and Services whose owner reference was lost.
+    #[arg(long, value_enum, default_value_t = GcPolicy::Report, env)]
+    gc_policy: GcPolicy,
+
+    /// Seconds between garbage collections of the managed resources.
+    #[arg(long, default_value_t = 600, env)]
+    gc_interval_seconds: u64,
+
+    /// Provision a Grafana dashboard with the operator metrics in every namespace with Echoes.
+    ///
+    /// If not provided, dashboards are not provisioned.
+    #[arg(long, value_enum, env)]
+    grafana_dashboards: Option<DashboardKind>,
+
+    /// Seconds between provisionings of the Grafana dashboards.
+    #[arg(long, default_value_t = 300, env)]
+    grafana_dashboards_interval_seconds: u64,
+
+    /// Seconds between probes of the Service of every Echo, reported in its EndpointHealthy
+    /// condition.
+    ///
+    /// If not provided, the Echoes are not probed.
+    #[arg(long, env)]
+    probe_interval_seconds: Option<u64>,
+
+    /// HTTP path requested by the Echo probes.
+    #[arg(long, default_value = "/", env)]
+    probe_path: String,
+
+    /// Seconds after which an Echo probe fails.
+    #[arg(long, default_value_t = 5, env)]
+    probe_timeout_seconds: u64,
+
+    /// Host of a StatsD server, e.g. the Datadog agent, receiving the operator metrics with the
+    /// DogStatsD protocol.
+    ///
+    /// If not provided, the metrics are only served in `/metrics`.
+    #[arg(long, env)]
+    statsd_host: Option<String>,
+
+    /// UDP port of the StatsD server.
+    #[arg(long, default_value_t = 8125, env)]
+    statsd_port: u16,
+
+    /// Seconds between the metrics sent to the StatsD server.
+    #[arg(long, default_value_t = 10, env)]
+    statsd_interval_seconds: u64,
+
+    /// Comma separated tags added to the metrics sent to the StatsD server, besides their labels,
+    /// e.g. `env:prod,cluster:eu-1`.
+    #[arg(long, env, value_delimiter = ',')]
+    statsd_tags: Vec<String>,
+
+    /// Prometheus remote-write endpoint receiving the operator metrics, for environments where
+    /// `/metrics` is not scraped.
+    ///
+    /// If not provided, the metrics are only served in `/metrics`.
+    #[arg(long, env)]
+    remote_write_url: Option<String>,
+
+    /// Seconds between the metrics pushes to the remote-write endpoint.
+    #[arg(long, default_value_t = 30, env)]
+    remote_write_interval_seconds: u64,
+
+    /// User of the basic authentication of the remote-write endpoint.
+    #[arg(long, env, requires = "remote_write_password")]
+    remote_write_username: Option<String>,
+
+    /// Password of the basic authentication of the remote-write endpoint.
+    #[arg(long, env, requires = "remote_write_username")]
+    remote_write_password: Option<String>,
+
+    /// File with the bearer token of the remote-write endpoint, read on every push.
+    #[arg(long, env, conflicts_with = "remote_write_username")]
+    remote_write_bearer_token_file: Option<PathBuf>,
+
+    /// Comma separated labels added to the series pushed to the remote-write endpoint, e.g.
+    /// `cluster=eu-1,env=prod`.
+    #[arg(long, env, value_delimiter = ',', value_parser = remotewrite::parse_label)]
+    remote_write_labels: Vec<(String, String)>,
+
+    /// Seconds between heartbeats, emitted while the Echo controller makes progress.
+    #[arg(long, default_value_t = 60, env)]
+    heartbeat_interval_seconds: u64,
+
+    /// URL receiving a POST on every heartbeat, e.g. a dead man's switch.
+    #[arg(long, env)]
+    heartbeat_url: Option<String>,
+
+    /// Serve the logs of the echo pods in `/api/v1/echoes/{namespace}/{name}/logs`.
+    ///
+    /// The endpoint is not authenticated, so only enable it when the port is not exposed to
+    /// untrusted clients.
+    #[arg(long, env)]
+    logs_api: bool,
+
+    /// Serve the manifests the operator applies for every Echo in
+    /// `/api/v1/echoes/{namespace}/{name}/manifests`, e.g. to diff them before a change.
+    ///
+    /// The endpoint is not authenticated, so only enable it when the port is not exposed to
+    /// untrusted clients.
+    #[arg(long, env)]
+    manifests_api: bool,
+
+    /// Controllers to run, e.g. `echo,echoquota`. Every controller runs if not provided.
+    #[arg(long, env, value_delimiter = ',')]
+    controllers: Vec<String>,
+
+    /// Order in which the Echoes listed at startup are reconciled. `priority` reconciles new and
+    /// changed Echoes first and resyncs the rest in batches.
+    #[arg(long, value_enum, default_value_t = SchedulingPolicy::Fifo, env)]
+    scheduling_policy: SchedulingPolicy,
+
+    /// Milliseconds a status patch of an Echo waits for newer ones, which replace it, before
+    /// being sent. `0` sends every status patch right away.
+    #[arg(long, default_value_t = 0, env)]
+    status_batch_window_ms: u64,
+
+    /// Patch the Echo statuses from a background writer instead of the reconciliations, queueing
+    /// the latest patch of up to this many Echoes. Patches of other Echoes are dropped while the
+    /// queue is full.
+    #[arg(long, env)]
+    status_queue_capacity: Option<usize>,
+
+    /// Do not reconcile the Echoes when only their labels or annotations change. They are applied
+    /// in the next periodic reconciliation.
+    #[arg(long, env)]
+    ignore_metadata_changes: bool,
+
+    /// Receive the initial lists of the Echo and Deployment watchers as watch events, lowering the
+    /// memory spikes of relisting on reconnections. Only used when the apiserver supports
+    /// streaming lists, Kubernetes 1.32 or later.
+    #[arg(long, env)]
+    enable_streaming_lists: bool,
+
+    /// Objects per page of the initial lists of the Echo and Deployment watchers, bounding the
+    /// memory used to list large clusters. Ignored with streaming lists.
+    #[arg(long, default_value_t = 500, value_parser = clap::value_parser!(u32).range(1..), env)]
+    watch_page_size: u32,
+
+    /// Watch the Deployments of each namespace from its first Echo until its last one is deleted,
+    /// instead of every managed Deployment of the cluster, bounding the memory of their cache to
+    /// the namespaces using the operator.
+    #[arg(long, env)]
+    lazy_deployment_reflectors: bool,
+
+    /// Cluster-wide maintenance window, e.g. `2024-01-01T22:00:00Z/2024-01-02T02:00:00Z`.
+    ///
+    /// Echo changes are not applied, and the garbage collection is skipped, during the window.
+    #[arg(long, env)]
+    maintenance_window: Option<MaintenanceWindow>,
+
+    /// ConfigMap, in the operator namespace, whose `window` key sets a cluster-wide maintenance
+    /// window which can be changed without restarting the operator.
+    #[arg(long, env)]
+    maintenance_config_map: Option<String>,
+
+    /// ConfigMap, in the operator namespace, with the cluster-wide defaults and overrides of the
+    /// Echo pods: its `defaults` and `overrides` keys hold a JSON object with the `image`,
+    /// `resources` and `tolerations` to use, and can be changed without restarting the operator.
+    #[arg(long, env)]
+    defaults_config_map: Option<String>,
+
+    /// Namespaces whose Echoes are not managed, e.g. `kube-system,kube-public`. A trailing `*`
+    /// matches every namespace with that prefix.
+    #[arg(long, env, value_delimiter = ',')]
+    namespace_denylist: Vec<String>,
+
+    /// Only manage the Echoes of these namespaces. Every namespace not denied is managed if not
+    /// provided.
+    #[arg(long, env, value_delimiter = ',')]
+    namespace_allowlist: Vec<String>,
+
+    /// Permissions the Echoes may grant to their pods with `spec.rbac.rules`, as
+    /// `<verb>:<resource>[.<group>]`, e.g. `get:pods,list:pods,get:pods/log`. `*` matches every
+    /// verb, resource or group. Echoes with other rules are denied and their rules never applied.
+    #[arg(long, env, value_delimiter = ',')]
+    workload_rbac_allowlist: Vec<AllowedPermission>,
+
+    /// dockerconfigjson Secret, in the operator namespace, copied to the namespace of every Echo
+    /// and used as image pull secret of its pods.
+    #[arg(long, env)]
+    registry_credentials_secret: Option<String>,
+
+    /// Milliseconds above which an Echo reconciliation logs a warning with the time spent in each
+    /// phase and increments the `reconcile_slow` counter.
+    #[arg(long, env)]
+    slow_reconcile_threshold_ms: Option<u64>,
+
+    /// Seconds after which every resource is reconciled again, correcting drift without changes.
+    #[arg(long, env, default_value_t = 300)]
+    requeue_interval_seconds: u64,
+
+    /// Seconds between full resyncs, reconciling every resource of every controller at once, e.g.
+    /// where compliance requires a periodic full reconciliation. Disabled by default.
+    #[arg(long, env)]
+    resync_interval_seconds: Option<u64>,
+
+    /// Seconds before retrying a failed reconciliation, doubled on every consecutive failure of
+    /// the same resource.
+    #[arg(long, env, default_value_t = 300)]
+    error_backoff_base_seconds: u64,
+
+    /// Longest wait, in seconds, before retrying a failed reconciliation.
+    #[arg(long, env, default_value_t = 300)]
+    error_backoff_max_seconds: u64,
+
+    /// Fraction, between 0 and 1, of the requeues added to every resource, so resources failing
+    /// at the same time are not retried at the same time.
+    #[arg(long, env, default_value_t = 0.0)]
+    requeue_jitter: f64,
+
+    /// Seconds after the start during which the Deployment applies are limited, so a restart
+    /// reconciling every Echo does not trip the apiserver priority and fairness. Applies which
+    /// would not change the Deployment are skipped meanwhile. `0` disables it.
+    #[arg(long, env, default_value_t = 0)]
+    startup_ramp_seconds: u64,
+
+    /// Deployment applies per second during the startup ramp.
+    #[arg(long, env, default_value_t = 5.0)]
+    startup_ramp_applies_per_second: f64,
+
+    /// Deployment applies allowed at once during the startup ramp.
+    #[arg(long, env, default_value_t = 10)]
+    startup_ramp_burst: u32,
+
+    /// Use the FIPS-validated crypto provider for the Kubernetes client and the webhook TLS.
+    ///
+    /// Requires a binary built with the `fips` feature.
+    #[arg(long, env)]
+    fips: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Export Echo resources as apply-ready YAML, e.g. for backups or cluster migrations
+    Export(export::ExportArgs),
+    /// Write the OLM bundle of the operator, with the RBAC of the enabled controllers
+    Bundle(bundle::BundleArgs),
+    /// Render the manifests installing the operator with the given flags, and the Helm values
+    /// schema of the flags
+    Manifests(manifests::ManifestsArgs),
+    /// Rewrite the stored objects of the CRDs in their storage version and record it as the only
+    /// stored one, so the previous versions can be removed from the CRDs after an upgrade
+    MigrateStorage(migrate::MigrateStorageArgs),
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let args: Args = Args::parse();
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches)?;
 
     telemetry::init(
         &args.log_filter,
-        args.log_format,
+        args.log_format.clone(),
         args.tracing_url.as_deref(),
         args.sample_ratio,
     )
     .await?;
 
-    let mut registry = Registry::with_prefix("echo-operator");
+    tls::install_crypto_provider(args.fips)?;
+    if tls::fips_enabled() {
+        tracing::info!(msg = "using FIPS-validated crypto provider");
+    }
+
+    let manifests_args = match args.command.take() {
+        Some(Command::Export(export_args)) => {
+            return export::run(Client::try_default().await?, export_args).await;
+        }
+        Some(Command::Bundle(bundle_args)) => return bundle::run(bundle_args, &args.controllers),
+        Some(Command::MigrateStorage(migrate_args)) => {
+            return migrate::run(Client::try_default().await?, migrate_args).await;
+        }
+        Some(Command::Manifests(manifests_args)) => Some(manifests_args),
+        None => None,
+    };
+
+    let gc_policy = args.gc_policy;
+    let gc_interval = Duration::from_secs(args.gc_interval_seconds);
+    let heartbeat_config = HeartbeatConfig {
+        interval: Duration::from_secs(args.heartbeat_interval_seconds),
+        url: args.heartbeat_url.clone(),
+    };
+    let mut controllers = ControllerRegistry::default()
+        .register(echo::controller::CONTROLLER_ID, echo::controller::run)
+        .register(
+            echostatus::controller::CONTROLLER_ID,
+            echostatus::controller::run,
+        )
+        .register(
+            echogateway::controller::CONTROLLER_ID,
+            echogateway::controller::run,
+        )
+        .register(
+            echoroute::controller::CONTROLLER_ID,
+            echoroute::controller::run,
+        )
+        .register(
+            echoquota::controller::CONTROLLER_ID,
+            echoquota::controller::run,
+        )
+        .register(
+            echoreplication::controller::CONTROLLER_ID,
+            echoreplication::controller::run,
+        )
+        .register(
+            clusterecho::controller::CONTROLLER_ID,
+            clusterecho::controller::run,
+        )
+        .register(gc::CONTROLLER_ID, move |state, client| {
+            gc::run(state, client, gc_policy, gc_interval)
+        })
+        .register(heartbeat::CONTROLLER_ID, move |state, client| {
+            heartbeat::run(state, client, heartbeat_config.clone())
+        });
+    if let Some(kind) = args.grafana_dashboards {
+        let interval = Duration::from_secs(args.grafana_dashboards_interval_seconds);
+        controllers = controllers.register(dashboard::CONTROLLER_ID, move |state, client| {
+            dashboard::run(state, client, kind, interval)
+        });
+    }
+    if let Some(interval) = args.probe_interval_seconds {
+        let config = ProbeConfig {
+            path: args.probe_path.clone(),
+            interval: Duration::from_secs(interval),
+            timeout: Duration::from_secs(args.probe_timeout_seconds),
+        };
+        controllers = controllers.register(prober::CONTROLLER_ID, move |state, client| {
+            prober::run(state, client, config.clone())
+        });
+    }
+    if let Some(host) = args.statsd_host.as_deref() {
+        let config = StatsdConfig {
+            address: format!("{host}:{}", args.statsd_port),
+            interval: Duration::from_secs(args.statsd_interval_seconds),
+            tags: args.statsd_tags.clone(),
+        };
+        controllers = controllers.register(statsd::CONTROLLER_ID, move |state, client| {
+            statsd::run(state, client, config.clone())
+        });
+    }
+    if let Some(url) = args.remote_write_url.clone() {
+        let auth = match (
+            &args.remote_write_username,
+            &args.remote_write_password,
+            &args.remote_write_bearer_token_file,
+        ) {
+            (Some(username), Some(password), _) => RemoteWriteAuth::Basic {
+                username: username.clone(),
+                password: password.clone(),
+            },
+            (_, _, Some(path)) => RemoteWriteAuth::BearerTokenFile(path.clone()),
+            _ => RemoteWriteAuth::None,
+        };
+        let config = RemoteWriteConfig {
+            url,
+            interval: Duration::from_secs(args.remote_write_interval_seconds),
+            auth,
+            labels: args.remote_write_labels.clone(),
+        };
+        controllers = controllers.register(remotewrite::CONTROLLER_ID, move |state, client| {
+            remotewrite::run(state, client, config.clone())
+        });
+    }
+    if let Some(config_map) = args.maintenance_config_map.clone() {
+        controllers = controllers.register(maintenance::CONTROLLER_ID, move |state, client| {
+            maintenance::run(state, client, config_map.clone())
+        });
+    }
+    if let Some(config_map) = args.defaults_config_map.clone() {
+        controllers = controllers.register(clusterdefaults::CONTROLLER_ID, move |state, client| {
+            clusterdefaults::run(state, client, config_map.clone())
+        });
+    }
+    let controllers = controllers.enable(&args.controllers)?;
+    if let Some(manifests_args) = manifests_args {
+        return manifests::run(manifests_args, &args, &matches, &controllers.ids());
+    }
+
+    let mut registry = Registry::with_prefix(METRICS_PREFIX);
     let config = Config::infer().await?;
-    let client = new_client_with_metrics(config, &mut registry).await?;
-    let controllers = [echo::controller::CONTROLLER_ID];
-    let state = State::new(registry, &controllers);
+    let hedge_config = args.hedge_latency_percentile.map(|p| HedgeConfig {
+        latency_percentile: p,
+        ..HedgeConfig::default()
+    });
+    let client = new_client_with_metrics(config, &mut registry, hedge_config).await?;
+    let sinks = args
+        .notification_webhook_url
+        .iter()
+        .map(|url| (url, SinkFormat::Generic))
+        .chain(
+            args.notification_slack_webhook_url
+                .iter()
+                .map(|url| (url, SinkFormat::Slack)),
+        )
+        .chain(
+            args.notification_cloudevents_url
+                .iter()
+                .map(|url| (url, SinkFormat::CloudEvents)),
+        )
+        .map(|(url, format)| Sink {
+            url: url.clone(),
+            format,
+        })
+        .collect();
+    let audit_sink = match (&args.audit_file, &args.audit_url) {
+        (Some(path), _) => AuditSink::File(path.clone()),
+        (None, Some(url)) => AuditSink::Http(url.clone()),
+        (None, None) => AuditSink::None,
+    };
+    let error_reporter =
+        ErrorReporter::new(args.sentry_dsn.clone(), args.sentry_environment.clone());
+    error_reporter.install_panic_hook();
+    let state = State::new(registry, &controllers.ids())
+        .with_notifier(Notifier::new(sinks, args.notification_template.clone()))
+        .with_auditor(Auditor::new(audit_sink))
+        .with_error_reporter(error_reporter)
+        .with_scheduling_policy(args.scheduling_policy)
+        .with_status_batch_window(Duration::from_millis(args.status_batch_window_ms))
+        .with_status_queue_capacity(args.status_queue_capacity)
+        .with_ignore_metadata_changes(args.ignore_metadata_changes)
+        .with_streaming_lists(args.enable_streaming_lists)
+        .with_watch_page_size(args.watch_page_size)
+        .with_lazy_deployment_reflectors(args.lazy_deployment_reflectors)
+        .with_maintenance_window(args.maintenance_window)
+        .with_registry_credentials_secret(args.registry_credentials_secret.clone())
+        .with_namespace_filter(NamespaceFilter::new(
+            args.namespace_allowlist.clone(),
+            args.namespace_denylist.clone(),
+        ))
+        .with_rbac_allowlist(RbacAllowlist::new(args.workload_rbac_allowlist.clone()))
+        .with_slow_reconcile_threshold(args.slow_reconcile_threshold_ms.map(Duration::from_millis))
+        .with_reconcile_config(ReconcileConfig {
+            success_interval: Duration::from_secs(args.requeue_interval_seconds),
+            error_backoff_base: Duration::from_secs(args.error_backoff_base_seconds),
+            error_backoff_max: Duration::from_secs(args.error_backoff_max_seconds),
+            jitter: args.requeue_jitter,
+            resync_interval: args.resync_interval_seconds.map(Duration::from_secs),
+        })
+        .with_startup_ramp(StartupRampConfig {
+            duration: Duration::from_secs(args.startup_ramp_seconds),
+            rate: args.startup_ramp_applies_per_second,
+            burst: args.startup_ramp_burst,
+        });
 
-    let controller = echo::controller::run(state.clone(), client);
+    let controllers = controllers.run(state.clone(), client.clone());
+
+    let mut webhook_certificates = None;
+    let webhook_tls_config = match (
+        &args.webhook_tls_cert_file,
+        &args.webhook_tls_key_file,
+        &args.webhook_cert_secret,
+    ) {
+        (Some(cert_file), Some(key_file), _) => Some(webhook::tls_config(cert_file, key_file)?),
+        (_, _, Some(secret)) => {
+            let (certificates, config) = certs::WebhookCertificates::init(
+                client.clone(),
+                secret.clone(),
+                // safe unwraps: required by the webhook_cert_secret argument
+                args.webhook_service.clone().unwrap(),
+                args.webhook_configuration.clone().unwrap(),
+            )
+            .await?;
+            webhook_certificates = Some(certificates);
+            Some(config)
+        }
+        _ => None,
+    };
+    let webhook_server = webhook_tls_config
+        .map(|config| {
+            webhook::server(
+                client.clone(),
+                RbacAllowlist::new(args.workload_rbac_allowlist.clone()),
+                args.webhook_port,
+                config,
+            )
+        })
+        .transpose()?;
+    let webhook = async {
+        let Some(server) = webhook_server else {
+            return Ok(());
+        };
+        match webhook_certificates {
+            // the rotation never ends, so the webhook runs until the server is shut down
+            Some(certificates) => tokio::select! {
+                result = server => result,
+                _ = certificates.rotate() => Ok(()),
+            },
+            None => server.await,
+        }
+    };
 
+    let logs_api = args.logs_api;
+    let manifests_api = args.manifests_api;
     let server = HttpServer::new(move || {
         App::new()
             .app_data(Data::new(state.clone()))
-            .wrap(middleware::Logger::default().exclude("/health"))
+            .app_data(Data::new(client.clone()))
+            .wrap(
+                middleware::Logger::default()
+                    .exclude("/health")
+                    .exclude("/readyz"),
+            )
             .service(health)
+            .service(readyz)
             .service(metrics)
+            .configure(|cfg| {
+                if logs_api {
+                    cfg.service(admin::logs);
+                }
+                if manifests_api {
+                    cfg.service(admin::manifests);
+                }
+            })
     })
     .bind(format!("0.0.0.0:{}", args.port))?
     .shutdown_timeout(5);
 
-    // Both runtimes implements graceful shutdown, so poll until both are done
-    tokio::join!(controller, server.run()).1?;
+    // All runtimes implements graceful shutdown, so poll until all are done
+    let (_, server_result, webhook_result) = tokio::join!(controllers, server.run(), webhook);
+    server_result?;
+    webhook_result?;
     Ok(())
 }
@@ -0,0 +1,357 @@
+//! `manifests` subcommand rendering the manifests installing the operator with the flags it is
+//! given and the RBAC of the controllers they enable, and the Helm values schema of those flags.
+use crate::rbac::{self, SERVICE_ACCOUNT};
+use crate::Args;
+use echo_operator::controller::ControllerId;
+use echo_operator::permissions;
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, CommandFactory};
+use k8s_openapi::api::apps::v1::{Deployment, DeploymentSpec};
+use k8s_openapi::api::core::v1::{
+    Capabilities, Container, ContainerPort, EnvVar, HTTPGetAction, PodSecurityContext, PodSpec,
+    PodTemplateSpec, Probe, SeccompProfile, SecurityContext, Service, ServiceAccount, ServicePort,
+    ServiceSpec,
+};
+use k8s_openapi::api::rbac::v1::{ClusterRole, ClusterRoleBinding, RoleRef, Subject};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use kube::api::ObjectMeta;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use tracing::info;
+
+const NAME: &str = "echo-operator";
+const WEBHOOK_SERVICE_PORT: i32 = 443;
+
+/// Values schema of the chart, where the `config` values are generated from the flags
+const CHART_VALUES_SCHEMA: &str = include_str!("../../../charts/echo-operator/values.schema.json");
+
+#[derive(clap::Args, Debug)]
+pub struct ManifestsArgs {
+    /// Namespace where the operator is installed
+    #[arg(long, default_value = NAME)]
+    namespace: String,
+
+    /// Image of the operator
+    #[arg(long, default_value = concat!("ghcr.io/pando85/echo-operator:", env!("CARGO_PKG_VERSION")))]
+    image: String,
+
+    /// File where the manifests are written. They are printed if not provided
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// File where the Helm values schema of the chart is written, with a `config` value for every
+    /// operator flag, e.g. `charts/echo-operator/values.schema.json`
+    #[arg(long)]
+    values_schema: Option<PathBuf>,
+}
+
+/// Render the manifests of the operator running with `operator` flags and `controllers`
+pub fn run(
+    args: ManifestsArgs,
+    operator: &Args,
+    matches: &ArgMatches,
+    controllers: &[ControllerId],
+) -> anyhow::Result<()> {
+    let command = Args::command();
+    let labels = BTreeMap::from([("app.kubernetes.io/name".to_string(), NAME.to_string())]);
+    let metadata = ObjectMeta {
+        name: Some(NAME.to_string()),
+        namespace: Some(args.namespace.clone()),
+        labels: Some(labels),
+        ..ObjectMeta::default()
+    };
+
+    let mut manifests = vec![
+        to_yaml(&ServiceAccount {
+            metadata: ObjectMeta {
+                name: Some(SERVICE_ACCOUNT.to_string()),
+                ..metadata.clone()
+            },
+            ..ServiceAccount::default()
+        })?,
+        to_yaml(&ClusterRole {
+            metadata: ObjectMeta {
+                namespace: None,
+                ..metadata.clone()
+            },
+            rules: Some(permissions::policy_rules(&rbac::permissions(controllers)?)),
+            ..ClusterRole::default()
+        })?,
+        to_yaml(&ClusterRoleBinding {
+            metadata: ObjectMeta {
+                namespace: None,
+                ..metadata.clone()
+            },
+            role_ref: RoleRef {
+                api_group: "rbac.authorization.k8s.io".to_string(),
+                kind: "ClusterRole".to_string(),
+                name: NAME.to_string(),
+            },
+            subjects: Some(vec![Subject {
+                kind: "ServiceAccount".to_string(),
+                name: SERVICE_ACCOUNT.to_string(),
+                namespace: Some(args.namespace.clone()),
+                ..Subject::default()
+            }]),
+        })?,
+        to_yaml(&deployment(
+            &args,
+            operator,
+            metadata.clone(),
+            env(&command, matches),
+        ))?,
+        to_yaml(&service(metadata.clone(), "metrics", operator.port as i32))?,
+    ];
+    if let Some(webhook_service) = &operator.webhook_service {
+        manifests.push(to_yaml(&service(
+            ObjectMeta {
+                name: Some(webhook_service.clone()),
+                ..metadata
+            },
+            "webhook",
+            WEBHOOK_SERVICE_PORT,
+        ))?);
+    }
+
+    let manifests = manifests.join("---\n");
+    match &args.output {
+        Some(path) => {
+            fs::write(path, manifests).with_context(|| format!("writing {}", path.display()))?;
+            info!(msg = "manifests written", output = %path.display());
+        }
+        None => print!("{manifests}"),
+    }
+    if let Some(path) = &args.values_schema {
+        let schema = serde_json::to_string_pretty(&values_schema(&command)?)? + "\n";
+        fs::write(path, schema).with_context(|| format!("writing {}", path.display()))?;
+        info!(msg = "values schema written", output = %path.display());
+    }
+    Ok(())
+}
+
+fn to_yaml<T: Serialize>(manifest: &T) -> anyhow::Result<String> {
+    Ok(serde_yaml::to_string(manifest)?)
+}
+
+/// Environment variables of the flags set on the command line or in the environment, so the
+/// Deployment runs the operator as it was called
+fn env(command: &clap::Command, matches: &ArgMatches) -> Vec<EnvVar> {
+    command
+        .get_arguments()
+        .filter(|arg| {
+            matches!(
+                matches.value_source(arg.get_id().as_str()),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        })
+        .filter_map(|arg| {
+            let name = arg.get_env()?.to_str()?.to_string();
+            let values: Vec<_> = matches
+                .get_raw(arg.get_id().as_str())?
+                .map(OsStr::to_string_lossy)
+                .collect();
+            Some(EnvVar {
+                name,
+                value: Some(values.join(",")),
+                ..EnvVar::default()
+            })
+        })
+        .collect()
+}
+
+fn deployment(
+    args: &ManifestsArgs,
+    operator: &Args,
+    metadata: ObjectMeta,
+    env: Vec<EnvVar>,
+) -> Deployment {
+    let mut ports = vec![ContainerPort {
+        name: Some("metrics".to_string()),
+        container_port: operator.port as i32,
+        protocol: Some("TCP".to_string()),
+        ..ContainerPort::default()
+    }];
+    if operator.webhook_tls_cert_file.is_some() || operator.webhook_cert_secret.is_some() {
+        ports.push(ContainerPort {
+            name: Some("webhook".to_string()),
+            container_port: operator.webhook_port as i32,
+            protocol: Some("TCP".to_string()),
+            ..ContainerPort::default()
+        });
+    }
+    let probe = |path: &str| Probe {
+        http_get: Some(HTTPGetAction {
+            path: Some(path.to_string()),
+            port: IntOrString::String("metrics".to_string()),
+            ..HTTPGetAction::default()
+        }),
+        ..Probe::default()
+    };
+    Deployment {
+        metadata: metadata.clone(),
+        spec: Some(DeploymentSpec {
+            replicas: Some(1),
+            selector: LabelSelector {
+                match_labels: metadata.labels.clone(),
+                ..LabelSelector::default()
+            },
+            template: PodTemplateSpec {
+                metadata: Some(ObjectMeta {
+                    labels: metadata.labels,
+                    ..ObjectMeta::default()
+                }),
+                spec: Some(PodSpec {
+                    service_account_name: Some(SERVICE_ACCOUNT.to_string()),
+                    security_context: Some(PodSecurityContext {
+                        run_as_non_root: Some(true),
+                        run_as_user: Some(65534),
+                        run_as_group: Some(65534),
+                        fs_group: Some(65534),
+                        seccomp_profile: Some(SeccompProfile {
+                            type_: "RuntimeDefault".to_string(),
+                            ..SeccompProfile::default()
+                        }),
+                        ..PodSecurityContext::default()
+                    }),
+                    containers: vec![Container {
+                        name: NAME.to_string(),
+                        image: Some(args.image.clone()),
+                        env: Some(env),
+                        ports: Some(ports),
+                        readiness_probe: Some(probe("/readyz")),
+                        liveness_probe: Some(probe("/health")),
+                        security_context: Some(SecurityContext {
+                            allow_privilege_escalation: Some(false),
+                            read_only_root_filesystem: Some(true),
+                            capabilities: Some(Capabilities {
+                                drop: Some(vec!["ALL".to_string()]),
+                                ..Capabilities::default()
+                            }),
+                            ..SecurityContext::default()
+                        }),
+                        ..Container::default()
+                    }],
+                    ..PodSpec::default()
+                }),
+            },
+            ..DeploymentSpec::default()
+        }),
+        ..Deployment::default()
+    }
+}
+
+fn service(metadata: ObjectMeta, port_name: &str, port: i32) -> Service {
+    Service {
+        spec: Some(ServiceSpec {
+            selector: metadata.labels.clone(),
+            ports: Some(vec![ServicePort {
+                name: Some(port_name.to_string()),
+                port,
+                target_port: Some(IntOrString::String(port_name.to_string())),
+                ..ServicePort::default()
+            }]),
+            ..ServiceSpec::default()
+        }),
+        metadata,
+        ..Service::default()
+    }
+}
+
+/// Values schema of the chart with a `config` value for every flag, named after its environment
+/// variable in camel case
+fn values_schema(command: &clap::Command) -> anyhow::Result<Value> {
+    let mut schema: Value =
+        serde_json::from_str(CHART_VALUES_SCHEMA).context("parsing chart values schema")?;
+    let properties: Map<String, Value> = command
+        .get_arguments()
+        .filter_map(|arg| {
+            let env = arg.get_env()?.to_str()?;
+            Some((camel_case(env), flag_schema(command, arg)))
+        })
+        .collect();
+    schema["properties"]["config"] = json!({
+        "type": "object",
+        "additionalProperties": false,
+        "description": "Operator flags, set as environment variables of its container.",
+        "properties": properties,
+    });
+    Ok(schema)
+}
+
+fn flag_schema(command: &clap::Command, arg: &Arg) -> Value {
+    let mut schema = if matches!(arg.get_action(), ArgAction::SetTrue) {
+        json!({"type": "boolean"})
+    } else {
+        let possible_values: Vec<_> = arg
+            .get_possible_values()
+            .iter()
+            .map(|v| v.get_name().to_string())
+            .collect();
+        let value_type = value_type(command, arg);
+        let mut value = json!({"type": value_type});
+        if !possible_values.is_empty() {
+            value["enum"] = json!(possible_values);
+        }
+        if let Some(default) = arg.get_default_values().first() {
+            let default = default.to_string_lossy();
+            value["default"] = match value_type {
+                "integer" => default.parse::<i64>().map_or(Value::Null, Value::from),
+                "number" => default.parse::<f64>().map_or(Value::Null, Value::from),
+                _ => Value::from(default.into_owned()),
+            };
+        }
+        match arg.get_value_delimiter() {
+            Some(_) => json!({"type": "array", "items": value}),
+            None => value,
+        }
+    };
+    if let Some(help) = arg.get_help() {
+        schema["description"] = json!(help.to_string());
+    }
+    schema
+}
+
+/// JSON type of the flag values, found from the values the command accepts because value parsers
+/// do not expose the type they return
+fn value_type(command: &clap::Command, arg: &Arg) -> &'static str {
+    let accepts = |value: &str| {
+        let flag = format!("--{}={value}", arg.get_long().unwrap_or_default());
+        let matches = command
+            .clone()
+            .try_get_matches_from([command.get_name(), &flag]);
+        !matches!(
+            matches.map_err(|e| e.kind()),
+            Err(ErrorKind::ValueValidation | ErrorKind::InvalidValue)
+        )
+    };
+    if accepts("x") || !accepts("0") {
+        "string"
+    } else if accepts("0.5") {
+        "number"
+    } else {
+        "integer"
+    }
+}
+
+/// `SOME_FLAG` as `someFlag`
+fn camel_case(env: &str) -> String {
+    env.split('_')
+        .enumerate()
+        .map(|(i, word)| {
+            let word = word.to_lowercase();
+            match word.get(..1) {
+                Some(first) if i > 0 => first.to_uppercase() + &word[1..],
+                _ => word,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,236 @@
+//! `migrate-storage` subcommand rewriting the stored objects of the operator CRDs in their current
+//! storage version, so the previous versions can be removed from the CRDs after an upgrade.
+use anyhow::{bail, Context};
+use clap::Args;
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use kube::api::{Api, ApiResource, DynamicObject, ListParams, Patch, PatchParams, PostParams};
+use kube::{Client, ResourceExt};
+use serde_json::json;
+use tracing::info;
+
+/// Objects listed per request
+const PAGE_SIZE: u32 = 500;
+
+/// Every CRD of the operator
+const CRDS: &str = "echoes.example.com,echogateways.example.com,echoroutes.example.com,\
+                    echoreplications.example.com,clusterechoes.example.com,echoquotas.example.com";
+
+#[derive(Args, Debug)]
+pub struct MigrateStorageArgs {
+    /// CRDs whose objects are migrated, every CRD of the operator by default
+    #[arg(long, value_delimiter = ',', default_value = CRDS)]
+    crds: Vec<String>,
+
+    /// Rewrite the objects even when the CRD only lists its storage version as stored
+    #[arg(long)]
+    force: bool,
+}
+
+pub async fn run(client: Client, args: MigrateStorageArgs) -> anyhow::Result<()> {
+    let crd_api = Api::<CustomResourceDefinition>::all(client.clone());
+    for name in &args.crds {
+        let crd = crd_api
+            .get(name)
+            .await
+            .with_context(|| format!("getting CRD {name}"))?;
+        migrate(&client, &crd_api, &crd, args.force).await?;
+    }
+    Ok(())
+}
+
+/// Version the API server writes the objects of the CRD in
+fn storage_version(crd: &CustomResourceDefinition) -> anyhow::Result<&str> {
+    match crd.spec.versions.iter().find(|v| v.storage) {
+        Some(version) => Ok(&version.name),
+        None => bail!("CRD {} has no storage version", crd.name_any()),
+    }
+}
+
+/// Versions the objects of the CRD may be stored in, as recorded in its status
+fn stored_versions(crd: &CustomResourceDefinition) -> Vec<String> {
+    crd.status
+        .as_ref()
+        .and_then(|s| s.stored_versions.clone())
+        .unwrap_or_default()
+}
+
+/// Whether objects may be stored in another version than the storage one
+fn needs_migration(stored_versions: &[String], storage_version: &str) -> bool {
+    stored_versions != [storage_version]
+}
+
+/// Rewrite every object of the CRD in its storage version, then record it as the only stored one
+async fn migrate(
+    client: &Client,
+    crd_api: &Api<CustomResourceDefinition>,
+    crd: &CustomResourceDefinition,
+    force: bool,
+) -> anyhow::Result<()> {
+    let name = crd.name_any();
+    let storage_version = storage_version(crd)?;
+    let stored_versions = stored_versions(crd);
+    if !force && !needs_migration(&stored_versions, storage_version) {
+        info!(
+            msg = "CRD objects already stored in the storage version",
+            crd = name,
+            version = storage_version
+        );
+        return Ok(());
+    }
+
+    let resource = ApiResource {
+        group: crd.spec.group.clone(),
+        version: storage_version.to_owned(),
+        api_version: format!("{}/{}", crd.spec.group, storage_version),
+        kind: crd.spec.names.kind.clone(),
+        plural: crd.spec.names.plural.clone(),
+    };
+    let api = Api::<DynamicObject>::all_with(client.clone(), &resource);
+    let mut params = ListParams::default().limit(PAGE_SIZE);
+    let mut migrated = 0;
+    loop {
+        let page = api
+            .list(&params)
+            .await
+            .with_context(|| format!("listing {}", resource.plural))?;
+        for object in &page.items {
+            if rewrite(client, &resource, object).await? {
+                migrated += 1;
+            }
+        }
+        match page.metadata.continue_.filter(|c| !c.is_empty()) {
+            Some(token) => params = params.continue_token(&token),
+            None => break,
+        }
+    }
+
+    // the objects stored in the previous versions are gone, so only the storage one is kept
+    let patch = json!({"status": {"storedVersions": [storage_version]}});
+    crd_api
+        .patch_status(&name, &PatchParams::default(), &Patch::Merge(&patch))
+        .await
+        .with_context(|| format!("updating the stored versions of CRD {name}"))?;
+    info!(
+        msg = "migrated CRD objects to the storage version",
+        crd = name,
+        version = storage_version,
+        previous_versions = ?stored_versions,
+        migrated
+    );
+    Ok(())
+}
+
+/// Replace the object with itself, which the API server writes in the storage version. Returns
+/// whether it was rewritten, as it can be deleted meanwhile.
+async fn rewrite(
+    client: &Client,
+    resource: &ApiResource,
+    object: &DynamicObject,
+) -> anyhow::Result<bool> {
+    let name = object.name_any();
+    let api = match object.namespace() {
+        Some(namespace) => {
+            Api::<DynamicObject>::namespaced_with(client.clone(), &namespace, resource)
+        }
+        None => Api::<DynamicObject>::all_with(client.clone(), resource),
+    };
+    match api.replace(&name, &PostParams::default(), object).await {
+        Ok(_) => Ok(true),
+        Err(kube::Error::Api(ae)) if ae.code == 404 => Ok(false),
+        // the object was written meanwhile, so it is already stored in the storage version
+        Err(kube::Error::Api(ae)) if ae.code == 409 => Ok(true),
+        Err(e) => Err(e).with_context(|| format!("rewriting {} {name}", resource.kind)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{needs_migration, storage_version, stored_versions, MigrateStorageArgs, CRDS};
+
+    use std::collections::BTreeSet;
+
+    use clap::{Args, Command, FromArgMatches};
+    use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::{
+        CustomResourceDefinition, CustomResourceDefinitionStatus, CustomResourceDefinitionVersion,
+    };
+
+    fn crd(
+        versions: &[(&str, bool)],
+        stored_versions: Option<&[&str]>,
+    ) -> CustomResourceDefinition {
+        let mut crd = CustomResourceDefinition::default();
+        crd.metadata.name = Some("echoes.example.com".to_string());
+        crd.spec.versions = versions
+            .iter()
+            .map(|(name, storage)| CustomResourceDefinitionVersion {
+                name: name.to_string(),
+                storage: *storage,
+                served: true,
+                ..CustomResourceDefinitionVersion::default()
+            })
+            .collect();
+        crd.status = stored_versions.map(|versions| CustomResourceDefinitionStatus {
+            stored_versions: Some(versions.iter().map(|v| v.to_string()).collect()),
+            ..CustomResourceDefinitionStatus::default()
+        });
+        crd
+    }
+
+    fn args(args: &[&str]) -> MigrateStorageArgs {
+        let command = MigrateStorageArgs::augment_args(Command::new("migrate-storage"));
+        let matches = command
+            .try_get_matches_from(["migrate-storage"].iter().chain(args))
+            .unwrap();
+        MigrateStorageArgs::from_arg_matches(&matches).unwrap()
+    }
+
+    #[test]
+    fn test_storage_version() {
+        let with_previous = crd(&[("v1alpha1", false), ("v1", true)], None);
+        assert_eq!(storage_version(&with_previous).unwrap(), "v1");
+
+        assert!(storage_version(&crd(&[("v1", false)], None)).is_err());
+    }
+
+    #[test]
+    fn test_stored_versions() {
+        let upgraded = crd(&[("v1", true)], Some(&["v1alpha1", "v1"]));
+        assert_eq!(stored_versions(&upgraded), vec!["v1alpha1", "v1"]);
+        // not reported yet by the API server
+        assert!(stored_versions(&crd(&[("v1", true)], None)).is_empty());
+    }
+
+    #[test]
+    fn test_needs_migration() {
+        let versions = |v: &[&str]| v.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+
+        assert!(!needs_migration(&versions(&["v1"]), "v1"));
+        assert!(needs_migration(&versions(&["v1alpha1", "v1"]), "v1"));
+        assert!(needs_migration(&versions(&["v1alpha1"]), "v1"));
+        assert!(needs_migration(&versions(&[]), "v1"));
+    }
+
+    #[test]
+    fn test_default_crds() {
+        let chart_crds: BTreeSet<String> = std::fs::read_dir(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../charts/echo-operator/crds"
+        ))
+        .unwrap()
+        .map(|entry| {
+            let crd: CustomResourceDefinition =
+                serde_yaml::from_reader(std::fs::File::open(entry.unwrap().path()).unwrap())
+                    .unwrap();
+            crd.metadata.name.unwrap()
+        })
+        .collect();
+
+        let crds: BTreeSet<String> = args(&[]).crds.into_iter().collect();
+        assert_eq!(crds, chart_crds);
+        assert_eq!(CRDS.split(',').count(), chart_crds.len());
+        assert_eq!(
+            args(&["--crds", "echoes.example.com"]).crds,
+            vec!["echoes.example.com"]
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! RBAC of the operator: the permissions of its own API calls and of the controllers it runs.
+use echo_operator::permissions::{self, Permission};
+
+use anyhow::Context;
+
+pub const SERVICE_ACCOUNT: &str = "echo-operator";
+
+/// API calls of the operator outside the controllers: Events, namespace selectors, the admission
+/// webhook, its certificates and the logs and manifests APIs
+const OPERATOR_PERMISSIONS: &[Permission] = &[
+    Permission::new("events.k8s.io", &["events"], &["create"]),
+    Permission::new("", &["namespaces"], &["list", "watch"]),
+    Permission::new("example.com", &["echoes"], &["get", "list"]),
+    Permission::new("example.com", &["echoquotas"], &["list"]),
+    Permission::new("", &["secrets"], &["create", "get", "patch", "update"]),
+    Permission::new(
+        "admissionregistration.k8s.io",
+        &["validatingwebhookconfigurations"],
+        &["get", "patch"],
+    ),
+    Permission::new("", &["pods"], &["list"]),
+    Permission::new("", &["pods/log"], &["get"]),
+    Permission::new("apps", &["deployments"], &["get"]),
+];
+
+/// Permissions of the operator running the given controllers
+pub fn permissions(controllers: &[&str]) -> anyhow::Result<Vec<Permission>> {
+    let mut enabled = OPERATOR_PERMISSIONS.to_vec();
+    for id in controllers {
+        let controller = permissions::controller_permissions(id)
+            .with_context(|| format!("unknown controller {id}"))?;
+        enabled.extend_from_slice(controller);
+    }
+    Ok(enabled)
+}
+
+#[cfg(test)]
+mod test {
+    use super::permissions;
+
+    use echo_operator::permissions::{policy_rules, CONTROLLER_IDS};
+
+    use std::collections::BTreeSet;
+
+    use k8s_openapi::api::rbac::v1::PolicyRule;
+
+    const CHART_CLUSTER_ROLE: &str =
+        include_str!("../../../charts/echo-operator/templates/clusterrole.yaml");
+
+    /// Verbs granted by the rules on every resource, as `(api group, resource, verb)`
+    fn grants(rules: &[PolicyRule]) -> BTreeSet<(String, String, String)> {
+        let mut grants = BTreeSet::new();
+        for rule in rules {
+            for api_group in rule.api_groups.iter().flatten() {
+                for resource in rule.resources.iter().flatten() {
+                    for verb in &rule.verbs {
+                        grants.insert((api_group.clone(), resource.clone(), verb.clone()));
+                    }
+                }
+            }
+        }
+        grants
+    }
+
+    /// Rules of the chart ClusterRole, without its template directives
+    fn chart_rules() -> Vec<PolicyRule> {
+        let (_, rules) = CHART_CLUSTER_ROLE
+            .split_once("rules:\n")
+            .expect("chart ClusterRole has rules");
+        let rules: Vec<&str> = rules
+            .lines()
+            .filter(|l| !l.trim_start().starts_with("{{"))
+            .collect();
+        serde_yaml::from_str(&rules.join("\n")).expect("chart ClusterRole rules are valid")
+    }
+
+    #[test]
+    fn test_chart_cluster_role_matches_permissions() {
+        let generated = policy_rules(&permissions(&CONTROLLER_IDS).unwrap());
+
+        assert_eq!(grants(&chart_rules()), grants(&generated));
+    }
+}
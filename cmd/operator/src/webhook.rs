@@ -0,0 +1,72 @@
+//! HTTPS server answering the Kubernetes admission reviews.
+use echo_operator::crd::echo::Echo;
+use echo_operator::echoquota;
+use echo_operator::rbac_allowlist::RbacAllowlist;
+use echo_operator_k8s_util::tls;
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use actix_web::dev::Server;
+use actix_web::{middleware, post, web::Data, web::Json, App, HttpResponse, HttpServer, Responder};
+use anyhow::Context;
+use kube::core::admission::AdmissionReview;
+use kube::Client;
+
+#[post("/validate-echo")]
+async fn validate_echo(
+    client: Data<Client>,
+    rbac_allowlist: Data<RbacAllowlist>,
+    review: Json<AdmissionReview<Echo>>,
+) -> impl Responder {
+    let response = echoquota::admission::validate_echo(
+        client.get_ref().clone(),
+        rbac_allowlist.get_ref(),
+        review.into_inner(),
+    )
+    .await;
+    HttpResponse::Ok().json(response)
+}
+
+/// TLS config serving the certificate and private key files
+pub fn tls_config(cert_file: &Path, key_file: &Path) -> anyhow::Result<rustls::ServerConfig> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(
+        File::open(cert_file).context("opening webhook certificate")?,
+    ))
+    .collect::<Result<Vec<_>, _>>()
+    .context("reading webhook certificate")?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(
+        File::open(key_file).context("opening webhook private key")?,
+    ))
+    .context("reading webhook private key")?
+    .context("webhook private key not found")?;
+
+    rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("invalid webhook certificate")
+}
+
+/// Admission webhook server, which Kubernetes requires to be served over TLS
+pub fn server(
+    client: Client,
+    rbac_allowlist: RbacAllowlist,
+    port: u32,
+    config: rustls::ServerConfig,
+) -> anyhow::Result<Server> {
+    anyhow::ensure!(
+        config.fips() || !tls::fips_enabled(),
+        "webhook TLS config is not FIPS compliant"
+    );
+    Ok(HttpServer::new(move || {
+        App::new()
+            .app_data(Data::new(client.clone()))
+            .app_data(Data::new(rbac_allowlist.clone()))
+            .wrap(middleware::Logger::default())
+            .service(validate_echo)
+    })
+    .bind_rustls_0_23(format!("0.0.0.0:{port}"), config)?
+    .shutdown_timeout(5)
+    .run())
+}
@@ -1,3 +1,4 @@
+use crate::hedge::{HedgeConfig, HedgeLayer};
 use crate::metrics::MetricsLayer;
 
 use hyper_util::rt::TokioExecutor;
@@ -6,11 +7,17 @@ use kube::{client::ConfigExt, Client, Config};
 use prometheus_client::registry::Registry;
 use tower::ServiceBuilder;
 
-pub async fn new_client_with_metrics(config: Config, registry: &mut Registry) -> Result<Client> {
+pub async fn new_client_with_metrics(
+    config: Config,
+    registry: &mut Registry,
+    hedge_config: Option<HedgeConfig>,
+) -> Result<Client> {
     let metrics_layer = MetricsLayer::new(registry);
+    let hedge_layer = hedge_config.map(|c| HedgeLayer::new(c, registry));
     let https = config.rustls_https_connector()?;
     let service = ServiceBuilder::new()
         .layer(metrics_layer)
+        .option_layer(hedge_layer)
         .layer(config.base_uri_layer())
         .option_layer(config.auth_layer()?)
         .service(hyper_util::client::legacy::Client::builder(TokioExecutor::new()).build(https));
@@ -0,0 +1,310 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::future::{poll_fn, FutureExt};
+use http::{Method, Request};
+use prometheus_client::encoding::{EncodeLabelSet, EncodeLabelValue};
+use prometheus_client::metrics::{counter::Counter, family::Family};
+use prometheus_client::registry::Registry;
+use tokio::time::{Duration, Instant};
+use tower::{Layer, Service};
+
+/// Configuration for hedged read-only requests.
+///
+/// When a GET/LIST request takes longer than the `latency_percentile` of the latest observed
+/// latencies, a second identical request is sent and the first response wins.
+#[derive(Clone, Debug)]
+pub struct HedgeConfig {
+    /// Percentile of observed latencies (between 0 and 1) after which a hedge request is sent.
+    pub latency_percentile: f64,
+    /// Minimum number of observed latencies required before hedging is enabled.
+    pub min_data_points: usize,
+    /// Maximum number of latencies kept to compute the percentile.
+    pub window_size: usize,
+}
+
+impl Default for HedgeConfig {
+    fn default() -> Self {
+        Self {
+            latency_percentile: 0.95,
+            min_data_points: 20,
+            window_size: 1000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum Winner {
+    Primary,
+    Hedge,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+pub struct WinnerLabel {
+    pub winner: Winner,
+}
+
+/// Rolling window of latencies used to compute the hedge threshold
+#[derive(Debug)]
+struct LatencyWindow {
+    latencies: VecDeque<Duration>,
+    window_size: usize,
+}
+
+impl LatencyWindow {
+    fn new(window_size: usize) -> Self {
+        Self {
+            latencies: VecDeque::with_capacity(window_size),
+            window_size,
+        }
+    }
+
+    fn record(&mut self, latency: Duration) {
+        if self.latencies.len() >= self.window_size {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(latency);
+    }
+
+    fn percentile(&self, percentile: f64, min_data_points: usize) -> Option<Duration> {
+        if self.latencies.is_empty() || self.latencies.len() < min_data_points {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = self.latencies.iter().copied().collect();
+        sorted.sort_unstable();
+        let index = ((sorted.len() - 1) as f64 * percentile).round() as usize;
+        sorted.get(index).copied()
+    }
+}
+
+pub struct HedgeLayer {
+    config: HedgeConfig,
+    latencies: Arc<Mutex<LatencyWindow>>,
+    hedged_requests: Family<WinnerLabel, Counter>,
+}
+
+impl HedgeLayer {
+    pub fn new(config: HedgeConfig, registry: &mut Registry) -> Self {
+        let hedged_requests = Family::<WinnerLabel, Counter>::default();
+        registry.register(
+            "kubernetes_client_http_hedged_requests",
+            "Total number of Kubernetes's client hedged requests by the request that answered first",
+            hedged_requests.clone(),
+        );
+
+        Self {
+            latencies: Arc::new(Mutex::new(LatencyWindow::new(config.window_size))),
+            config,
+            hedged_requests,
+        }
+    }
+}
+
+impl<S> Layer<S> for HedgeLayer {
+    type Service = HedgeService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HedgeService {
+            inner,
+            config: self.config.clone(),
+            latencies: self.latencies.clone(),
+            hedged_requests: self.hedged_requests.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HedgeService<S> {
+    inner: S,
+    config: HedgeConfig,
+    latencies: Arc<Mutex<LatencyWindow>>,
+    hedged_requests: Family<WinnerLabel, Counter>,
+}
+
+/// Query parameters of long-lived streaming reads, e.g. watches or followed pod logs
+const STREAMING_QUERIES: [&str; 2] = ["watch=", "follow=true"];
+
+/// Subresources upgrading the GET to a long-lived stream
+const STREAMING_SUBRESOURCES: [&str; 3] = ["/exec", "/attach", "/portforward"];
+
+/// Only plain GET requests are hedged: streaming reads are long-lived and must never be
+/// duplicated, as the duplicate stream would be left open.
+fn is_hedgeable<B>(req: &Request<B>) -> bool {
+    let uri = req.uri();
+    req.method() == Method::GET
+        && !uri.query().is_some_and(|q| {
+            q.split('&')
+                .any(|p| STREAMING_QUERIES.iter().any(|s| p.starts_with(s)))
+        })
+        && !STREAMING_SUBRESOURCES
+            .iter()
+            .any(|s| uri.path().ends_with(s))
+}
+
+/// Latency percentile of the hedge configuration, between 0 and 1
+pub fn parse_latency_percentile(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(percentile) if (0.0..=1.0).contains(&percentile) => Ok(percentile),
+        Ok(_) => Err(format!("{s}: expected a percentile between 0 and 1")),
+        Err(e) => Err(format!("{s}: {e}")),
+    }
+}
+
+/// Read-only requests don't carry a body, so cloning them only requires the request parts.
+fn clone_request<B: From<Vec<u8>>>(req: &Request<B>) -> Request<B> {
+    let mut cloned = Request::new(B::from(Vec::new()));
+    *cloned.method_mut() = req.method().clone();
+    *cloned.uri_mut() = req.uri().clone();
+    *cloned.version_mut() = req.version();
+    *cloned.headers_mut() = req.headers().clone();
+    cloned
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for HedgeService<S>
+where
+    S: Service<Request<ReqBody>, Response = http::Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+    ReqBody: From<Vec<u8>> + Send + 'static,
+    ResBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        if !is_hedgeable(&req) {
+            return self.inner.call(req).boxed();
+        }
+
+        let hedge_after = self
+            .latencies
+            .lock()
+            .expect("latency window lock poisoned")
+            .percentile(self.config.latency_percentile, self.config.min_data_points);
+        let hedge_req = clone_request(&req);
+        let start_time = Instant::now();
+
+        let primary = self.inner.call(req);
+        // the hedge service clone has to be driven to readiness before it is called
+        let mut hedge_inner = self.inner.clone();
+
+        let latencies = self.latencies.clone();
+        let hedged_requests = self.hedged_requests.clone();
+        async move {
+            let mut primary = primary.boxed();
+            let result = match hedge_after {
+                None => primary.await,
+                Some(delay) => {
+                    tokio::select! {
+                        result = &mut primary => result,
+                        _ = tokio::time::sleep(delay) => {
+                            let mut hedge = async move {
+                                poll_fn(|cx| hedge_inner.poll_ready(cx)).await?;
+                                hedge_inner.call(hedge_req).await
+                            }
+                            .boxed();
+                            let (result, winner) = tokio::select! {
+                                result = &mut primary => (result, Winner::Primary),
+                                result = &mut hedge => (result, Winner::Hedge),
+                            };
+                            hedged_requests
+                                .get_or_create(&WinnerLabel { winner })
+                                .inc();
+                            result
+                        }
+                    }
+                }
+            };
+            if result.is_ok() {
+                latencies
+                    .lock()
+                    .expect("latency window lock poisoned")
+                    .record(start_time.elapsed());
+            }
+            result
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile_requires_min_data_points() {
+        let mut window = LatencyWindow::new(10);
+        window.record(Duration::from_millis(10));
+
+        assert_eq!(window.percentile(0.95, 2), None);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let mut window = LatencyWindow::new(100);
+        (1..=100).for_each(|i| window.record(Duration::from_millis(i)));
+
+        assert_eq!(window.percentile(0.5, 1), Some(Duration::from_millis(51)));
+        assert_eq!(window.percentile(0.95, 1), Some(Duration::from_millis(95)));
+        assert_eq!(window.percentile(1.0, 1), Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_window_drops_oldest_latencies() {
+        let mut window = LatencyWindow::new(2);
+        window.record(Duration::from_secs(10));
+        window.record(Duration::from_millis(1));
+        window.record(Duration::from_millis(2));
+
+        assert_eq!(window.percentile(1.0, 1), Some(Duration::from_millis(2)));
+    }
+
+    #[test]
+    fn test_is_hedgeable() {
+        let get = Request::get("/api/v1/namespaces/default/pods")
+            .body(())
+            .unwrap();
+        let watch = Request::get("/api/v1/namespaces/default/pods?&watch=true&timeoutSeconds=290")
+            .body(())
+            .unwrap();
+        let patch = Request::patch("/api/v1/namespaces/default/pods/test")
+            .body(())
+            .unwrap();
+
+        let logs = Request::get("/api/v1/namespaces/default/pods/test/log?&follow=true")
+            .body(())
+            .unwrap();
+        let exec = Request::get("/api/v1/namespaces/default/pods/test/exec?&command=sh")
+            .body(())
+            .unwrap();
+        let tail = Request::get("/api/v1/namespaces/default/pods/test/log?&tailLines=10")
+            .body(())
+            .unwrap();
+
+        assert!(is_hedgeable(&get));
+        assert!(!is_hedgeable(&watch));
+        assert!(!is_hedgeable(&patch));
+        assert!(!is_hedgeable(&logs));
+        assert!(!is_hedgeable(&exec));
+        assert!(is_hedgeable(&tail));
+    }
+
+    #[test]
+    fn test_parse_latency_percentile() {
+        assert_eq!(parse_latency_percentile("0.95"), Ok(0.95));
+        assert_eq!(parse_latency_percentile("0"), Ok(0.0));
+        assert_eq!(parse_latency_percentile("1"), Ok(1.0));
+        assert!(parse_latency_percentile("1.5").is_err());
+        assert!(parse_latency_percentile("-0.1").is_err());
+        assert!(parse_latency_percentile("NaN").is_err());
+        assert!(parse_latency_percentile("p95").is_err());
+    }
+}
@@ -1,3 +1,5 @@
 pub mod client;
+pub mod hedge;
 pub mod metrics;
+pub mod tls;
 mod url;
@@ -1,16 +1,18 @@
 use crate::url::template_path;
 
+use std::error::Error;
 use std::future::Future;
+use std::io;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
 use futures::future::FutureExt;
-use http::Request;
-use prometheus_client::encoding::EncodeLabelSet;
+use http::{Request, StatusCode};
+use prometheus_client::encoding::{EncodeLabelSet, EncodeLabelValue};
 use prometheus_client::metrics::{counter::Counter, family::Family, histogram::Histogram};
 use prometheus_client::registry::Registry;
 use tokio::time::Instant;
-use tower::{Layer, Service};
+use tower::{BoxError, Layer, Service};
 
 #[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug, Default)]
 pub struct EndpointLabel {
@@ -22,9 +24,75 @@ pub struct StatusCodeLabel {
     pub status_code: String,
 }
 
+/// Category of a failed request, either an error response or no response at all
+#[derive(Clone, Copy, Hash, PartialEq, Eq, EncodeLabelValue, Debug)]
+pub enum FailureCategory {
+    /// The request, or the connection, timed out
+    Timeout,
+    /// The connection could not be established or was closed
+    Connection,
+    /// 4xx response
+    ClientError,
+    /// 5xx response
+    ServerError,
+    /// The response could not be parsed
+    Decode,
+    /// Any other error
+    Other,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+pub struct FailureCategoryLabel {
+    pub category: FailureCategory,
+}
+
+impl FailureCategory {
+    /// Category of an error response
+    pub fn from_status(status: StatusCode) -> Option<Self> {
+        if status.is_client_error() {
+            Some(Self::ClientError)
+        } else if status.is_server_error() {
+            Some(Self::ServerError)
+        } else {
+            None
+        }
+    }
+
+    /// Category of an error without response, from the first known error of its source chain
+    pub fn from_error(error: &(dyn Error + 'static)) -> Self {
+        let mut source = Some(error);
+        while let Some(e) = source {
+            if e.is::<tokio::time::error::Elapsed>() {
+                return Self::Timeout;
+            }
+            if let Some(e) = e.downcast_ref::<hyper_util::client::legacy::Error>() {
+                if e.is_connect() {
+                    return Self::Connection;
+                }
+            }
+            if let Some(e) = e.downcast_ref::<io::Error>() {
+                match e.kind() {
+                    io::ErrorKind::TimedOut => return Self::Timeout,
+                    io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof => return Self::Connection,
+                    io::ErrorKind::InvalidData => return Self::Decode,
+                    _ => {}
+                }
+            }
+            source = e.source();
+        }
+        Self::Other
+    }
+}
+
 pub struct MetricsLayer {
     request_histogram: Family<EndpointLabel, Histogram>,
     requests_total: Family<StatusCodeLabel, Counter>,
+    request_failures: Family<FailureCategoryLabel, Counter>,
 }
 
 impl MetricsLayer {
@@ -49,9 +117,17 @@ impl MetricsLayer {
             requests_total.clone(),
         );
 
+        let request_failures = Family::<FailureCategoryLabel, Counter>::default();
+        registry.register(
+            "kubernetes_client_http_request_failures",
+            "Total number of Kubernetes's client requests failed, with or without response, by category",
+            request_failures.clone(),
+        );
+
         Self {
             request_histogram,
             requests_total,
+            request_failures,
         }
     }
 }
@@ -64,6 +140,7 @@ impl<S> Layer<S> for MetricsLayer {
             inner,
             request_histogram: self.request_histogram.clone(),
             requests_total: self.requests_total.clone(),
+            request_failures: self.request_failures.clone(),
         }
     }
 }
@@ -73,19 +150,21 @@ pub struct MetricsService<S> {
     inner: S,
     request_histogram: Family<EndpointLabel, Histogram>,
     requests_total: Family<StatusCodeLabel, Counter>,
+    request_failures: Family<FailureCategoryLabel, Counter>,
 }
 
 impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for MetricsService<S>
 where
     S: Service<Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Error: Into<BoxError>,
     S::Future: Send + 'static,
 {
     type Response = S::Response;
-    type Error = S::Error;
+    type Error = BoxError;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.inner.poll_ready(cx)
+        self.inner.poll_ready(cx).map_err(Into::into)
     }
 
     fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
@@ -99,14 +178,24 @@ where
         let fut = self.inner.call(req);
         let request_histogram = self.request_histogram.clone();
         let requests_total = self.requests_total.clone();
+        let request_failures = self.request_failures.clone();
         async move {
-            let result = fut.await;
+            let result = fut.await.map_err(Into::into);
             let duration = start_time.elapsed().as_secs_f64();
             request_histogram.get_or_create(&labels).observe(duration);
-            if let Ok(ref response) = result {
-                let status_code = response.status().as_u16().to_string();
-                requests_total
-                    .get_or_create(&StatusCodeLabel { status_code })
+            let failure = match &result {
+                Ok(response) => {
+                    let status_code = response.status().as_u16().to_string();
+                    requests_total
+                        .get_or_create(&StatusCodeLabel { status_code })
+                        .inc();
+                    FailureCategory::from_status(response.status())
+                }
+                Err(e) => Some(FailureCategory::from_error(e.as_ref())),
+            };
+            if let Some(category) = failure {
+                request_failures
+                    .get_or_create(&FailureCategoryLabel { category })
                     .inc();
             }
             result
@@ -114,3 +203,66 @@ where
         .boxed()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::FailureCategory;
+
+    use std::io;
+
+    use http::StatusCode;
+
+    #[derive(Debug)]
+    struct Wrapper(io::Error);
+
+    impl std::fmt::Display for Wrapper {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "request failed")
+        }
+    }
+
+    impl std::error::Error for Wrapper {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[test]
+    fn test_from_status() {
+        assert_eq!(FailureCategory::from_status(StatusCode::OK), None);
+        assert_eq!(
+            FailureCategory::from_status(StatusCode::CONFLICT),
+            Some(FailureCategory::ClientError)
+        );
+        assert_eq!(
+            FailureCategory::from_status(StatusCode::SERVICE_UNAVAILABLE),
+            Some(FailureCategory::ServerError)
+        );
+    }
+
+    #[test]
+    fn test_from_error() {
+        let category = |kind| FailureCategory::from_error(&Wrapper(io::Error::from(kind)));
+        assert_eq!(category(io::ErrorKind::TimedOut), FailureCategory::Timeout);
+        assert_eq!(
+            category(io::ErrorKind::ConnectionRefused),
+            FailureCategory::Connection
+        );
+        assert_eq!(
+            category(io::ErrorKind::InvalidData),
+            FailureCategory::Decode
+        );
+        assert_eq!(category(io::ErrorKind::Other), FailureCategory::Other);
+    }
+
+    #[tokio::test]
+    async fn test_from_elapsed() {
+        let elapsed = tokio::time::timeout(std::time::Duration::ZERO, std::future::pending::<()>())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            FailureCategory::from_error(&elapsed),
+            FailureCategory::Timeout
+        );
+    }
+}
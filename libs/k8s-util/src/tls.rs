@@ -0,0 +1,71 @@
+//! Process-wide rustls crypto provider, used by the Kubernetes client and the TLS servers of the
+//! operator.
+use std::fmt;
+
+use rustls::crypto::CryptoProvider;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CryptoProviderError {
+    /// FIPS mode was requested in a binary built without the `fips` feature
+    FipsUnsupported,
+    /// The installed provider does not run in FIPS mode
+    NotFips,
+    /// A provider was already installed for the process
+    AlreadyInstalled,
+}
+
+impl fmt::Display for CryptoProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FipsUnsupported => write!(f, "FIPS mode requires building with the fips feature"),
+            Self::NotFips => write!(f, "crypto provider is not running in FIPS mode"),
+            Self::AlreadyInstalled => write!(f, "crypto provider already installed"),
+        }
+    }
+}
+
+impl std::error::Error for CryptoProviderError {}
+
+#[cfg(feature = "fips")]
+fn fips_provider() -> Result<CryptoProvider, CryptoProviderError> {
+    Ok(rustls::crypto::default_fips_provider())
+}
+
+#[cfg(not(feature = "fips"))]
+fn fips_provider() -> Result<CryptoProvider, CryptoProviderError> {
+    Err(CryptoProviderError::FipsUnsupported)
+}
+
+/// Install the crypto provider of every rustls client and server config built afterwards. With
+/// `fips`, the FIPS-validated aws-lc-rs provider is installed, which requires the `fips` feature.
+pub fn install_crypto_provider(fips: bool) -> Result<(), CryptoProviderError> {
+    let provider = if fips {
+        fips_provider()?
+    } else {
+        rustls::crypto::ring::default_provider()
+    };
+    if fips && !provider.fips() {
+        return Err(CryptoProviderError::NotFips);
+    }
+    provider
+        .install_default()
+        .map_err(|_| CryptoProviderError::AlreadyInstalled)
+}
+
+/// Whether the installed crypto provider runs in FIPS mode
+pub fn fips_enabled() -> bool {
+    CryptoProvider::get_default().is_some_and(|p| p.fips())
+}
+
+#[cfg(all(test, not(feature = "fips")))]
+mod test {
+    use super::{install_crypto_provider, CryptoProviderError};
+
+    #[test]
+    fn test_fips_requires_feature() {
+        assert_eq!(
+            install_crypto_provider(true),
+            Err(CryptoProviderError::FipsUnsupported)
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! Benchmarks of the echo reconcile path: the whole reconciliation against the in-process fake
+//! apiserver, and the Deployment and status builders, with a minimal Echo and one of the size of
+//! the ones found in production, with many labels, annotations and tolerations.
+//!
+//! `cargo bench -p echo-operator --features test-utils`
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use echo_operator::controller::State;
+use echo_operator::crd::echo::Echo;
+use echo_operator::echo::controller::CONTROLLER_ID;
+use echo_operator::echo::reconcile::{reconcile_echo, Defaults};
+use echo_operator::stores::Stores;
+use echo_operator::test_utils::fake_apiserver::FakeApiServer;
+use echo_operator::test_utils::test_time;
+use k8s_openapi::api::apps::v1::{Deployment, DeploymentStatus};
+use kube::runtime::reflector::store::Writer;
+use kube::runtime::watcher;
+use kube::ResourceExt;
+use prometheus_client::registry::Registry;
+use serde_json::{json, Map, Value};
+use tokio::runtime::Runtime;
+
+/// Labels and annotations of the Echoes, the tolerations being a quarter of them
+const SIZES: [usize; 2] = [0, 40];
+
+fn entries(size: usize, prefix: &str, value_len: usize) -> Map<String, Value> {
+    (0..size)
+        .map(|i| {
+            (
+                format!("example.com/{prefix}-{i}"),
+                Value::String(format!("{i:0>value_len$}")),
+            )
+        })
+        .collect()
+}
+
+fn echo(size: usize) -> Echo {
+    let tolerations: Vec<Value> = (0..size / 4)
+        .map(|i| json!({"key": format!("example.com/taint-{i}"), "operator": "Exists"}))
+        .collect();
+    serde_json::from_value(json!({
+        "apiVersion": "example.com/v1",
+        "kind": "Echo",
+        "metadata": {
+            "name": "bench",
+            "namespace": "default",
+            "generation": 1,
+            "labels": entries(size, "label", 16),
+            "annotations": entries(size, "annotation", 128),
+        },
+        "spec": {
+            "replicas": 3,
+            "image": "inanimate/echo-server:0.1.0",
+            "resources": {
+                "requests": {"cpu": "100m", "memory": "64Mi"},
+                "limits": {"cpu": "500m", "memory": "128Mi"},
+            },
+            "tolerations": tolerations,
+        },
+    }))
+    .expect("valid echo")
+}
+
+fn deployment_status() -> DeploymentStatus {
+    DeploymentStatus {
+        replicas: Some(3),
+        ready_replicas: Some(2),
+        available_replicas: Some(2),
+        updated_replicas: Some(3),
+        observed_generation: Some(1),
+        ..DeploymentStatus::default()
+    }
+}
+
+fn bench_generate_deployment(c: &mut Criterion) {
+    let mut group = c.benchmark_group("generate_deployment");
+    for size in SIZES {
+        let echo = echo(size);
+        let defaults = Defaults::default();
+        group.bench_with_input(BenchmarkId::from_parameter(size), &echo, |b, echo| {
+            b.iter(|| echo.generate_deployment(&defaults).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_generate_status(c: &mut Criterion) {
+    let mut group = c.benchmark_group("generate_status");
+    let status = deployment_status();
+    for size in SIZES {
+        let echo = echo(size);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &echo, |b, echo| {
+            b.iter(|| echo.generate_status(&status, Some(1), test_time()))
+        });
+    }
+    group.finish();
+}
+
+fn bench_reconcile_echo(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("tokio runtime");
+    // the clients spawn their request buffer in the runtime
+    let _guard = runtime.enter();
+    let mut group = c.benchmark_group("reconcile_echo");
+    for size in SIZES {
+        let echo = Arc::new(echo(size));
+        let fake = FakeApiServer::default();
+        fake.create(echo.as_ref());
+        let state = State::new(Registry::default(), &[CONTROLLER_ID]);
+
+        // the Deployment is not cached yet, as on the first reconciliation
+        let stores = Stores::default().with(Writer::<Deployment>::default().as_reader());
+        let ctx = state.to_context(fake.client(), CONTROLLER_ID, stores);
+        group.bench_with_input(BenchmarkId::new("uncached", size), &echo, |b, echo| {
+            b.to_async(&runtime).iter(|| async {
+                // a failed reconciliation returns early, timing less than the benchmarked path
+                reconcile_echo(echo.clone(), ctx.clone())
+                    .await
+                    .expect("reconciled")
+            })
+        });
+
+        // the Deployment is cached as applied, as on the periodic reconciliations
+        let mut writer = Writer::<Deployment>::default();
+        let deployment = fake.get::<Deployment>(Some("default"), &echo.name_any());
+        writer.apply_watcher_event(&watcher::Event::Apply(deployment.expect("applied")));
+        let stores = Stores::default().with(writer.as_reader());
+        let ctx = state.to_context(fake.client(), CONTROLLER_ID, stores);
+        group.bench_with_input(BenchmarkId::new("cached", size), &echo, |b, echo| {
+            b.to_async(&runtime).iter(|| async {
+                reconcile_echo(echo.clone(), ctx.clone())
+                    .await
+                    .expect("reconciled")
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_generate_deployment,
+    bench_generate_status,
+    bench_reconcile_echo
+);
+criterion_main!(benches);
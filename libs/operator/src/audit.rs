@@ -0,0 +1,277 @@
+//! Append-only audit trail of the mutating actions taken by the reconcilers.
+use crate::telemetry;
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::apps::v1::Deployment;
+use kube::Resource;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tracing::error;
+
+/// Destination of the audit events, one JSON object each
+#[derive(Clone, Debug, Default)]
+pub enum AuditSink {
+    /// Auditing disabled
+    #[default]
+    None,
+    /// JSON lines appended to a file
+    File(PathBuf),
+    /// JSON object posted to an HTTP endpoint
+    Http(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AuditAction {
+    Apply,
+    Recreate,
+    StatusPatch,
+    Delete,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEvent {
+    pub timestamp: DateTime<Utc>,
+    pub controller: String,
+    pub action: AuditAction,
+    pub kind: String,
+    pub namespace: String,
+    pub name: String,
+    /// Human readable summary of the changes
+    pub summary: String,
+    pub trace_id: String,
+}
+
+impl AuditEvent {
+    pub fn new(
+        timestamp: DateTime<Utc>,
+        controller: &str,
+        action: AuditAction,
+        kind: &str,
+        namespace: &str,
+        name: &str,
+        summary: String,
+    ) -> Self {
+        Self {
+            timestamp,
+            controller: controller.to_string(),
+            action,
+            kind: kind.to_string(),
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+            summary,
+            trace_id: telemetry::get_trace_id().to_string(),
+        }
+    }
+}
+
+/// Writes audit events to the configured sink
+#[derive(Default)]
+pub struct Auditor {
+    sink: AuditSink,
+    http: reqwest::Client,
+}
+
+impl Auditor {
+    pub fn new(sink: AuditSink) -> Self {
+        Self {
+            sink,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Record an event. Failures are logged, as auditing never blocks a reconciliation
+    pub async fn record(&self, event: AuditEvent) {
+        let result = match &self.sink {
+            AuditSink::None => return,
+            AuditSink::File(path) => append_line(path, &event).await,
+            AuditSink::Http(url) => self
+                .http
+                .post(url)
+                .json(&event)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+        };
+        if let Err(e) = result {
+            error!(msg = "failed to record audit event", action = ?event.action, %e);
+        }
+    }
+}
+
+async fn append_line(path: &Path, event: &AuditEvent) -> Result<(), String> {
+    let mut line = serde_json::to_string(event).map_err(|e| e.to_string())?;
+    line.push('\n');
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .map_err(|e| e.to_string())?;
+    file.write_all(line.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    // tokio writes in the background, the write can be lost if the file is dropped unflushed
+    file.flush().await.map_err(|e| e.to_string())
+}
+
+/// Summary of a Deployment apply without changes
+pub const DIFF_UNCHANGED: &str = "unchanged";
+
+/// Summary of the changes between the current and the applied Deployment
+pub fn deployment_diff_summary(current: Option<&Deployment>, applied: &Deployment) -> String {
+    let Some(current) = current else {
+        return "created".to_string();
+    };
+    let replicas = |d: &Deployment| d.spec.as_ref().and_then(|s| s.replicas);
+    let images = |d: &Deployment| -> Vec<Option<String>> {
+        d.spec
+            .iter()
+            .flat_map(|s| s.template.spec.iter())
+            .flat_map(|s| s.containers.iter())
+            .map(|c| c.image.clone())
+            .collect()
+    };
+
+    let mut changes = Vec::new();
+    if replicas(current) != replicas(applied) {
+        changes.push(format!(
+            "replicas {} -> {}",
+            replicas(current).unwrap_or_default(),
+            replicas(applied).unwrap_or_default()
+        ));
+    }
+    if images(current) != images(applied) {
+        changes.push(format!(
+            "image {} -> {}",
+            images(current)
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join(","),
+            images(applied)
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join(",")
+        ));
+    }
+    if current.metadata.labels != applied.metadata.labels {
+        changes.push("labels changed".to_string());
+    }
+    if changes.is_empty() {
+        DIFF_UNCHANGED.to_string()
+    } else {
+        changes.join(", ")
+    }
+}
+
+/// Whether an apply created the object or changed its resource version, as server-side applies
+/// without changes leave it untouched and are not audited
+pub fn apply_changed<K: Resource>(current: Option<&K>, applied: &K) -> bool {
+    current.map_or(true, |c| {
+        c.meta().resource_version != applied.meta().resource_version
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        apply_changed, deployment_diff_summary, AuditAction, AuditEvent, AuditSink, Auditor,
+    };
+
+    use crate::test_utils::test_time;
+
+    use k8s_openapi::api::apps::v1::{Deployment, DeploymentSpec};
+    use serde_json::json;
+
+    fn deployment(replicas: i32) -> Deployment {
+        Deployment {
+            spec: Some(DeploymentSpec {
+                replicas: Some(replicas),
+                ..DeploymentSpec::default()
+            }),
+            ..Deployment::default()
+        }
+    }
+
+    fn event() -> AuditEvent {
+        AuditEvent {
+            timestamp: test_time(),
+            controller: "echo".to_string(),
+            action: AuditAction::StatusPatch,
+            kind: "Echo".to_string(),
+            namespace: "default".to_string(),
+            name: "test".to_string(),
+            summary: "Ready".to_string(),
+            trace_id: "00000000000000000000000000000000".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_audit_event_serialization() {
+        assert_eq!(
+            serde_json::to_value(event()).unwrap(),
+            json!({
+                "timestamp": "2024-01-01T00:00:00Z",
+                "controller": "echo",
+                "action": "statusPatch",
+                "kind": "Echo",
+                "namespace": "default",
+                "name": "test",
+                "summary": "Ready",
+                "traceId": "00000000000000000000000000000000",
+            })
+        );
+    }
+
+    #[test]
+    fn test_deployment_diff_summary() {
+        assert_eq!(deployment_diff_summary(None, &deployment(1)), "created");
+        assert_eq!(
+            deployment_diff_summary(Some(&deployment(1)), &deployment(1)),
+            "unchanged"
+        );
+        assert_eq!(
+            deployment_diff_summary(Some(&deployment(1)), &deployment(3)),
+            "replicas 1 -> 3"
+        );
+    }
+
+    #[test]
+    fn test_apply_changed() {
+        let with_version = |version: &str| {
+            let mut deployment = deployment(1);
+            deployment.metadata.resource_version = Some(version.to_string());
+            deployment
+        };
+
+        assert!(apply_changed(None, &with_version("1")));
+        assert!(!apply_changed(Some(&with_version("1")), &with_version("1")));
+        assert!(apply_changed(Some(&with_version("1")), &with_version("2")));
+    }
+
+    #[tokio::test]
+    async fn test_file_sink_appends_events() {
+        let path = std::env::temp_dir().join(format!("echo-audit-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let auditor = Auditor::new(AuditSink::File(path.clone()));
+
+        auditor.record(event()).await;
+        auditor.record(event()).await;
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(lines[0]).unwrap()["action"],
+            "statusPatch"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+
+/// Source of the current time for controllers.
+///
+/// Reconcilers read the time from the `Context` clock instead of calling `Utc::now()` directly,
+/// so tests can control time deterministically.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// Wall-clock time
+#[derive(Clone, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock which only moves when it is told to
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl MockClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Set the current time
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().expect("mock clock lock poisoned") = now;
+    }
+
+    /// Move the current time forward
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().expect("mock clock lock poisoned") += duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().expect("mock clock lock poisoned")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Clock, MockClock};
+
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn test_mock_clock_advance() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let clock = MockClock::new(start);
+
+        clock.advance(Duration::seconds(30));
+
+        assert_eq!(clock.now(), start + Duration::seconds(30));
+    }
+
+    #[test]
+    fn test_mock_clock_is_shared_between_clones() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let clock = MockClock::new(start);
+        let clone = clock.clone();
+
+        let later = Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap();
+        clock.set(later);
+
+        assert_eq!(clone.now(), later);
+    }
+}
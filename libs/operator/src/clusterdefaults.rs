@@ -0,0 +1,362 @@
+//! Cluster-wide defaults and overrides of the Echo pods, from a ConfigMap watched at runtime, so
+//! platform teams can roll out e.g. a patched echo image or node tolerations without editing every
+//! Echo.
+//!
+//! The `defaults` key applies to the echoes not setting the field in their spec, while the
+//! `overrides` key applies to every echo. Both hold a JSON object with the optional `image`,
+//! `resources`, `tolerations` and `securityProfile` fields. The `profiles` key holds named sets of
+//! the same fields, e.g. `small` or `hardened`, applied to the echoes selecting them in
+//! `spec.profile` for the fields set neither by their spec nor by the defaults.
+use crate::controller::{ControllerId, State};
+use crate::error::{Error, Result};
+use crate::permissions::Permission;
+
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::{ConfigMap, ResourceRequirements, Toleration};
+use kube::api::Api;
+use kube::client::Client;
+use kube::runtime::{watcher, WatchStreamExt};
+use kube::ResourceExt;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info};
+
+pub const CONTROLLER_ID: ControllerId = "clusterdefaults";
+
+pub const PERMISSIONS: &[Permission] = &[Permission::new("", &["configmaps"], &["list", "watch"])];
+
+/// Key of the ConfigMap with the values of the echoes not setting them
+pub const DEFAULTS_KEY: &str = "defaults";
+
+/// Key of the ConfigMap with the values of every echo, whatever their spec
+pub const OVERRIDES_KEY: &str = "overrides";
+
+/// Key of the ConfigMap with the named profiles the echoes select
+pub const PROFILES_KEY: &str = "profiles";
+
+/// Image of the echo server when neither the spec nor the cluster defaults set one
+pub const DEFAULT_IMAGE: &str = "inanimate/echo-server:latest";
+
+/// Security context presets of the echo pods, as `spec.securityProfile`
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum SecurityProfile {
+    Restricted,
+    Baseline,
+    Custom,
+}
+
+/// Values of the echo pods
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct EchoPodValues {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resources: Option<ResourceRequirements>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tolerations: Option<Vec<Toleration>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub security_profile: Option<SecurityProfile>,
+}
+
+impl EchoPodValues {
+    /// Values of `self`, falling back to the ones of `other` when unset
+    pub fn or(self, other: &EchoPodValues) -> EchoPodValues {
+        EchoPodValues {
+            image: self.image.or_else(|| other.image.clone()),
+            resources: self.resources.or_else(|| other.resources.clone()),
+            tolerations: self.tolerations.or_else(|| other.tolerations.clone()),
+            security_profile: self.security_profile.or(other.security_profile),
+        }
+    }
+}
+
+/// Defaults, overrides and profiles of the cluster defaults ConfigMap
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClusterDefaults {
+    pub defaults: EchoPodValues,
+    pub overrides: EchoPodValues,
+    pub profiles: BTreeMap<String, EchoPodValues>,
+}
+
+impl ClusterDefaults {
+    /// Values of an echo pod with the given profile and spec values: the overrides, then the
+    /// spec, then the defaults and then the profile. Fails when the profile does not exist.
+    pub fn resolve(&self, profile: Option<&str>, spec: EchoPodValues) -> Result<EchoPodValues> {
+        let profile = match profile {
+            Some(name) => self
+                .profiles
+                .get(name)
+                .cloned()
+                .ok_or_else(|| Error::InvalidSpec(format!("unknown profile {name:?}")))?,
+            None => EchoPodValues::default(),
+        };
+        let mut values = self
+            .overrides
+            .clone()
+            .or(&spec.or(&self.defaults.clone().or(&profile)));
+        values
+            .image
+            .get_or_insert_with(|| DEFAULT_IMAGE.to_string());
+        Ok(values)
+    }
+}
+
+/// Cluster defaults of the ConfigMap, when it is watched
+#[derive(Debug, Default)]
+pub struct ClusterDefaultsStore {
+    values: RwLock<ClusterDefaults>,
+}
+
+impl ClusterDefaultsStore {
+    /// Current defaults and overrides
+    pub fn get(&self) -> ClusterDefaults {
+        // safe unwrap: the lock is never held across a panic
+        self.values.read().unwrap().clone()
+    }
+
+    fn set(&self, values: ClusterDefaults) {
+        // safe unwrap: the lock is never held across a panic
+        let mut current = self.values.write().unwrap();
+        if *current != values {
+            info!(
+                msg = "cluster defaults changed",
+                defaults = ?values.defaults,
+                overrides = ?values.overrides,
+                profiles = ?values.profiles.keys().collect::<Vec<_>>()
+            );
+            *current = values;
+        }
+    }
+}
+
+/// Defaults, overrides and profiles of the ConfigMap. Invalid keys are ignored, as they can not be
+/// reported in any status.
+fn config_map_defaults(config_map: &ConfigMap) -> ClusterDefaults {
+    fn value<T: DeserializeOwned + Default>(config_map: &ConfigMap, key: &str) -> T {
+        config_map
+            .data
+            .as_ref()
+            .and_then(|data| data.get(key))
+            .and_then(|value| {
+                serde_json::from_str(value)
+                    .inspect_err(
+                        |e| error!(msg = "ignoring cluster defaults ConfigMap key", key, %e),
+                    )
+                    .ok()
+            })
+            .unwrap_or_default()
+    }
+    ClusterDefaults {
+        defaults: value(config_map, DEFAULTS_KEY),
+        overrides: value(config_map, OVERRIDES_KEY),
+        profiles: value(config_map, PROFILES_KEY),
+    }
+}
+
+/// Keep the cluster defaults of the ConfigMap, in the operator namespace, up to date
+pub async fn run(state: State, client: Client, config_map: String) {
+    let metrics = state.controller_metrics(CONTROLLER_ID);
+    let cluster_defaults = state.cluster_defaults();
+    let config = watcher::Config::default().fields(&format!("metadata.name={config_map}"));
+    let mut events = watcher(Api::<ConfigMap>::default_namespaced(client), config)
+        .default_backoff()
+        .boxed();
+    // safe unwrap: signal handlers can always be registered in the tokio runtime
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    info!(msg = "watching cluster defaults ConfigMap", %config_map);
+    // whether the ConfigMap was found by the list in progress
+    let mut listed = false;
+    loop {
+        let event = tokio::select! {
+            event = events.next() => event,
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        };
+        match event {
+            Some(Ok(watcher::Event::Init)) => listed = false,
+            Some(Ok(watcher::Event::InitApply(cm))) => {
+                listed = true;
+                cluster_defaults.set(config_map_defaults(&cm));
+            }
+            Some(Ok(watcher::Event::InitDone)) => {
+                if !listed {
+                    cluster_defaults.set(ClusterDefaults::default());
+                }
+                metrics.ready_set(1);
+            }
+            Some(Ok(watcher::Event::Apply(cm))) => cluster_defaults.set(config_map_defaults(&cm)),
+            Some(Ok(watcher::Event::Delete(cm))) => {
+                info!(
+                    msg = "cluster defaults ConfigMap deleted",
+                    name = cm.name_any()
+                );
+                cluster_defaults.set(ClusterDefaults::default());
+            }
+            Some(Err(e)) => {
+                error!(msg = "cluster defaults ConfigMap watch failed", %e);
+                metrics.watch_operations_failed_inc();
+            }
+            None => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        config_map_defaults, ClusterDefaults, EchoPodValues, SecurityProfile, DEFAULTS_KEY,
+        DEFAULT_IMAGE, OVERRIDES_KEY, PROFILES_KEY,
+    };
+    use crate::error::Error;
+
+    use std::collections::BTreeMap;
+
+    use k8s_openapi::api::core::v1::{ConfigMap, Toleration};
+
+    fn image(image: &str) -> EchoPodValues {
+        EchoPodValues {
+            image: Some(image.to_string()),
+            ..EchoPodValues::default()
+        }
+    }
+
+    #[test]
+    fn test_config_map_defaults() {
+        let config_map = ConfigMap {
+            data: Some(BTreeMap::from([
+                (
+                    DEFAULTS_KEY.to_string(),
+                    r#"{"image": "echo:1", "tolerations": [{"key": "spot", "operator": "Exists"}]}"#
+                        .to_string(),
+                ),
+                (OVERRIDES_KEY.to_string(), r#"{"replicas": 1}"#.to_string()),
+                (
+                    PROFILES_KEY.to_string(),
+                    r#"{"hardened": {"securityProfile": "Restricted"}}"#.to_string(),
+                ),
+            ])),
+            ..ConfigMap::default()
+        };
+        let defaults = config_map_defaults(&config_map);
+        assert_eq!(defaults.defaults.image.as_deref(), Some("echo:1"));
+        assert_eq!(
+            defaults.defaults.tolerations,
+            Some(vec![Toleration {
+                key: Some("spot".to_string()),
+                operator: Some("Exists".to_string()),
+                ..Toleration::default()
+            }])
+        );
+        // unknown fields invalidate the key
+        assert_eq!(defaults.overrides, EchoPodValues::default());
+        assert_eq!(
+            defaults.profiles["hardened"].security_profile,
+            Some(SecurityProfile::Restricted)
+        );
+        assert_eq!(
+            config_map_defaults(&ConfigMap::default()),
+            ClusterDefaults::default()
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let cluster_defaults = ClusterDefaults::default();
+        assert_eq!(
+            cluster_defaults
+                .resolve(None, EchoPodValues::default())
+                .unwrap(),
+            image(DEFAULT_IMAGE)
+        );
+        assert_eq!(
+            cluster_defaults.resolve(None, image("spec")).unwrap(),
+            image("spec")
+        );
+
+        let cluster_defaults = ClusterDefaults {
+            defaults: image("default"),
+            ..ClusterDefaults::default()
+        };
+        assert_eq!(
+            cluster_defaults
+                .resolve(None, EchoPodValues::default())
+                .unwrap(),
+            image("default")
+        );
+        assert_eq!(
+            cluster_defaults.resolve(None, image("spec")).unwrap(),
+            image("spec")
+        );
+
+        let cluster_defaults = ClusterDefaults {
+            defaults: image("default"),
+            overrides: image("override"),
+            ..ClusterDefaults::default()
+        };
+        assert_eq!(
+            cluster_defaults.resolve(None, image("spec")).unwrap(),
+            image("override")
+        );
+    }
+
+    #[test]
+    fn test_resolve_profile() {
+        let small = EchoPodValues {
+            security_profile: Some(SecurityProfile::Baseline),
+            ..image("profile")
+        };
+        let cluster_defaults = ClusterDefaults {
+            profiles: BTreeMap::from([("small".to_string(), small.clone())]),
+            ..ClusterDefaults::default()
+        };
+        assert_eq!(
+            cluster_defaults
+                .resolve(Some("small"), EchoPodValues::default())
+                .unwrap(),
+            small
+        );
+        assert!(matches!(
+            cluster_defaults.resolve(Some("large"), EchoPodValues::default()),
+            Err(Error::InvalidSpec(_))
+        ));
+
+        // profile, then cluster defaults, then spec
+        let cluster_defaults = ClusterDefaults {
+            defaults: image("default"),
+            ..cluster_defaults
+        };
+        let values = cluster_defaults
+            .resolve(Some("small"), EchoPodValues::default())
+            .unwrap();
+        assert_eq!(values.image.as_deref(), Some("default"));
+        assert_eq!(values.security_profile, Some(SecurityProfile::Baseline));
+        let spec = EchoPodValues {
+            security_profile: Some(SecurityProfile::Restricted),
+            ..image("spec")
+        };
+        assert_eq!(
+            cluster_defaults
+                .resolve(Some("small"), spec.clone())
+                .unwrap(),
+            spec
+        );
+
+        let cluster_defaults = ClusterDefaults {
+            overrides: image("override"),
+            ..cluster_defaults
+        };
+        assert_eq!(
+            cluster_defaults
+                .resolve(Some("small"), spec)
+                .unwrap()
+                .image
+                .as_deref(),
+            Some("override")
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use crate::clusterecho::reconcile::reconcile_cluster_echo;
+use crate::controller::{Context, ControllerId, State};
+use crate::crd::clusterecho::ClusterEcho;
+use crate::crd::echo::Echo;
+use crate::error::Error;
+use crate::permissions::Permission;
+use crate::stores::Stores;
+
+use std::sync::Arc;
+
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::Namespace;
+use kube::api::{Api, ListParams, ResourceExt};
+use kube::client::Client;
+use kube::runtime::controller::{self, Action, Controller};
+use kube::runtime::reflector::ObjectRef;
+use kube::runtime::watcher;
+use tokio::time::Duration;
+use tracing::{error, info};
+
+pub const CONTROLLER_ID: ControllerId = "clusterecho";
+
+pub const PERMISSIONS: &[Permission] = &[
+    Permission::new(
+        "example.com",
+        &["clusterechoes"],
+        &["get", "list", "watch", "patch", "update"],
+    ),
+    Permission::new("example.com", &["clusterechoes/status"], &["patch"]),
+    Permission::new("example.com", &["clusterechoes/finalizers"], &["update"]),
+    Permission::new(
+        "example.com",
+        &["echoes"],
+        &["create", "delete", "list", "patch", "watch"],
+    ),
+    Permission::new("", &["namespaces"], &["list", "watch"]),
+];
+
+fn error_policy<K: ResourceExt>(obj: Arc<K>, error: &Error, ctx: Arc<Context>) -> Action {
+    error!(msg = "failed reconciliation", name = %obj.name_any(), %error);
+    ctx.metrics.reconcile_failure_set(error);
+    ctx.error_reporter
+        .capture_failure(CONTROLLER_ID, &*obj, error);
+    Action::requeue(ctx.error_requeue(&*obj))
+}
+
+/// Initialize cluster echoes controller and shared state (given the crd is installed)
+pub async fn run(state: State, client: Client) {
+    let cluster_echo = Api::<ClusterEcho>::all(client.clone());
+    if let Err(e) = cluster_echo.list(&ListParams::default().limit(1)).await {
+        error!("CRD is not queryable; {e:?}. Is the CRD installed?");
+        std::process::exit(1);
+    }
+
+    let ctx = state.to_context(client.clone(), CONTROLLER_ID, Stores::default());
+
+    info!(msg = "starting cluster echo controller");
+    let controller = Controller::new(cluster_echo, watcher::Config::default().any_semantic())
+        // debounce to filter out reconcile calls that happen quick succession (only taking the latest)
+        .with_config(controller::Config::default().debounce(Duration::from_millis(500)));
+    let cluster_echoes = controller.store();
+    let cluster_echo_controller = controller
+        .owns(Api::<Echo>::all(client.clone()), watcher::Config::default())
+        // new or relabeled namespaces can be selected by any cluster echo
+        .watches(
+            Api::<Namespace>::all(client),
+            watcher::Config::default(),
+            move |_| {
+                cluster_echoes
+                    .state()
+                    .iter()
+                    .map(|c| ObjectRef::from_obj(c.as_ref()))
+                    .collect::<Vec<_>>()
+            },
+        )
+        .reconcile_all_on(ctx.reconcile_config.resyncs())
+        .shutdown_on_signal()
+        .run(reconcile_cluster_echo, error_policy, ctx.clone())
+        .filter_map(|x| async move { std::result::Result::ok(x) })
+        .for_each(|_| futures::future::ready(()));
+
+    ctx.metrics.ready_set(1);
+    cluster_echo_controller.await;
+}
@@ -0,0 +1,343 @@
+use crate::controller::Context;
+use crate::crd::clusterecho::{ClusterEcho, ClusterEchoStatus};
+use crate::crd::echo::{Echo, EchoSpec};
+use crate::error::{Error, Result};
+use crate::telemetry;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use k8s_openapi::api::core::v1::Namespace;
+use kube::api::{Api, DeleteParams, ListParams, ObjectMeta, Patch, PatchParams, Resource};
+use kube::runtime::controller::Action;
+use kube::ResourceExt;
+use serde_json::json;
+use tracing::{debug, field, info, instrument, trace, warn, Span};
+
+const FIELD_MANAGER: &str = "clusterechoes.example.com";
+/// Name of the ClusterEcho which created an Echo
+pub const CLUSTER_ECHO_LABEL: &str = "clusterechoes.example.com/name";
+
+#[instrument(skip(ctx, cluster_echo))]
+pub async fn reconcile_cluster_echo(
+    cluster_echo: Arc<ClusterEcho>,
+    ctx: Arc<Context>,
+) -> Result<Action> {
+    let trace_id = telemetry::get_trace_id();
+    Span::current().record("trace_id", field::display(&trace_id));
+    let _timer = ctx.metrics.reconcile_count_and_measure(&trace_id);
+    info!(msg = "reconciling ClusterEcho");
+
+    if let Some(requeue) = ctx.maintenance_requeue(&*cluster_echo) {
+        return Ok(Action::requeue(requeue));
+    }
+
+    let spec = cluster_echo.echo_spec()?;
+    let mut namespaces = cluster_echo.target_namespaces(&ctx).await?;
+    let mut conflicts = BTreeSet::new();
+    for namespace in &namespaces {
+        if !cluster_echo.apply_echo(&spec, namespace, &ctx).await? {
+            conflicts.insert(namespace.clone());
+        }
+    }
+    cluster_echo.delete_echoes(&namespaces, &ctx).await?;
+    namespaces.retain(|n| !conflicts.contains(n));
+
+    cluster_echo
+        .update_status(namespaces, conflicts, ctx.clone())
+        .await?;
+    ctx.reconciled(&*cluster_echo);
+    Ok(Action::requeue(ctx.requeue_interval(&*cluster_echo)))
+}
+
+impl ClusterEcho {
+    /// Echo spec of the template
+    fn echo_spec(&self) -> Result<EchoSpec> {
+        serde_json::to_value(&self.spec.template)
+            .and_then(serde_json::from_value)
+            .map_err(Error::SerializationError)
+    }
+
+    /// Namespaces matching the selector, or every namespace without selector
+    async fn target_namespaces(&self, ctx: &Context) -> Result<BTreeSet<String>> {
+        let labels: Vec<String> = self
+            .spec
+            .namespace_selector
+            .iter()
+            .flatten()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect();
+        let namespaces = Api::<Namespace>::all(ctx.client.clone())
+            .list(&ListParams::default().labels(&labels.join(",")))
+            .await
+            .map_err(Error::KubeError)?;
+        Ok(namespaces
+            .iter()
+            // namespaces being deleted reject new objects
+            .filter(|n| n.meta().deletion_timestamp.is_none())
+            .map(|n| n.name_any())
+            .collect())
+    }
+
+    /// Echo created by the ClusterEcho in a namespace
+    fn echo(&self, spec: &EchoSpec, namespace: &str) -> Echo {
+        Echo {
+            metadata: ObjectMeta {
+                name: Some(self.name_any()),
+                namespace: Some(namespace.to_owned()),
+                labels: Some(BTreeMap::from([(
+                    CLUSTER_ECHO_LABEL.to_owned(),
+                    self.name_any(),
+                )])),
+                // cluster scoped owners can own namespaced objects, so echoes are garbage collected
+                owner_references: self.controller_owner_ref(&()).map(|oref| vec![oref]),
+                ..ObjectMeta::default()
+            },
+            spec: spec.clone(),
+            status: None,
+        }
+    }
+
+    /// Whether the Echo is managed by the ClusterEcho
+    fn is_managed(&self, echo: &Echo) -> bool {
+        echo.labels().get(CLUSTER_ECHO_LABEL) == Some(&self.name_any())
+    }
+
+    /// Apply the Echo in a namespace, unless an Echo which is not managed by the ClusterEcho
+    /// already has its name there, as the apply would take it over and delete it with the
+    /// ClusterEcho. Returns whether the Echo was applied.
+    async fn apply_echo(&self, spec: &EchoSpec, namespace: &str, ctx: &Context) -> Result<bool> {
+        let echo_api = Api::<Echo>::namespaced(ctx.client.clone(), namespace);
+        let existing = echo_api
+            .get_opt(&self.name_any())
+            .await
+            .map_err(Error::KubeError)?;
+        if existing.is_some_and(|e| !self.is_managed(&e)) {
+            warn!(
+                msg = "skipping Echo conflicting with an existing Echo",
+                namespace,
+                name = self.name_any()
+            );
+            return Ok(false);
+        }
+        debug!(msg = "applying Echo", namespace);
+        echo_api
+            .patch(
+                &self.name_any(),
+                &PatchParams::apply(FIELD_MANAGER).force(),
+                &Patch::Apply(&self.echo(spec, namespace)),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        Ok(true)
+    }
+
+    /// Delete the echoes of the namespaces no longer selected
+    async fn delete_echoes(&self, keep: &BTreeSet<String>, ctx: &Context) -> Result<()> {
+        let echoes = Api::<Echo>::all(ctx.client.clone())
+            .list(
+                &ListParams::default().labels(&format!("{CLUSTER_ECHO_LABEL}={}", self.name_any())),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        for echo in echoes {
+            // safe unwrap: Echo is namespaced scoped
+            let namespace = echo.namespace().unwrap();
+            if keep.contains(&namespace) {
+                continue;
+            }
+            info!(msg = "deleting Echo", %namespace, name = echo.name_any());
+            match Api::<Echo>::namespaced(ctx.client.clone(), &namespace)
+                .delete(&echo.name_any(), &DeleteParams::default())
+                .await
+            {
+                Ok(_) => {}
+                Err(kube::Error::Api(ae)) if ae.code == 404 => {}
+                Err(e) => return Err(Error::KubeError(e)),
+            }
+        }
+        Ok(())
+    }
+
+    async fn update_status(
+        &self,
+        namespaces: BTreeSet<String>,
+        conflicts: BTreeSet<String>,
+        ctx: Arc<Context>,
+    ) -> Result<()> {
+        let new_status = ClusterEchoStatus {
+            namespaces: Some(namespaces.into_iter().collect()),
+            conflicts: Some(conflicts.into_iter().collect::<Vec<_>>()).filter(|c| !c.is_empty()),
+            observed_generation: self.metadata.generation,
+        };
+        let new_status_patch = Patch::Apply(json!({
+            "apiVersion": "example.com/v1",
+            "kind": "ClusterEcho",
+            "status": new_status
+        }));
+        debug!(msg = "updating ClusterEcho status");
+        trace!(msg = format!("new status {:?}", new_status_patch));
+        let patch = PatchParams::apply(FIELD_MANAGER).force();
+        let cluster_echo_api = Api::<ClusterEcho>::all(ctx.client.clone());
+        let _o = cluster_echo_api
+            .patch_status(&self.name_any(), &patch, &new_status_patch)
+            .await
+            .map_err(Error::KubeError)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{reconcile_cluster_echo, CLUSTER_ECHO_LABEL};
+
+    use crate::controller::Context;
+    use crate::crd::clusterecho::{ClusterEcho, ClusterEchoSpec};
+    use crate::crd::echo::Echo;
+    use crate::error::Error;
+    use crate::maintenance::{Maintenance, MaintenanceWindow};
+    use crate::test_utils::fake_apiserver::FakeApiServer;
+    use crate::test_utils::{get_test_context, test_time};
+
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use chrono::Duration;
+    use k8s_openapi::api::core::v1::Namespace;
+    use kube::api::ObjectMeta;
+    use kube::runtime::controller::Action;
+    use kube::{Resource, ResourceExt};
+    use serde_json::json;
+
+    fn cluster_echo(template: serde_json::Value) -> ClusterEcho {
+        let mut cluster_echo = ClusterEcho::new(
+            "debug",
+            ClusterEchoSpec {
+                namespace_selector: None,
+                template: serde_json::from_value(template).unwrap(),
+            },
+        );
+        cluster_echo.meta_mut().uid = Some("uid".to_string());
+        cluster_echo
+    }
+
+    #[test]
+    fn test_echo() {
+        let cluster_echo = cluster_echo(json!({"replicas": 2, "dnsName": "debug.example.com"}));
+
+        let echo = cluster_echo.echo(&cluster_echo.echo_spec().unwrap(), "team-a");
+
+        assert_eq!(echo.name_any(), "debug");
+        assert_eq!(echo.namespace().as_deref(), Some("team-a"));
+        assert_eq!(echo.spec.replicas, 2);
+        assert_eq!(echo.spec.dns_name.as_deref(), Some("debug.example.com"));
+        assert_eq!(
+            echo.labels(),
+            &BTreeMap::from([(CLUSTER_ECHO_LABEL.to_string(), "debug".to_string())])
+        );
+        let owner = &echo.owner_references()[0];
+        assert_eq!(owner.kind, "ClusterEcho");
+        assert_eq!(owner.controller, Some(true));
+    }
+
+    #[test]
+    fn test_echo_spec_invalid_template() {
+        assert!(matches!(
+            cluster_echo(json!({"replicas": "two"})).echo_spec(),
+            Err(Error::SerializationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_is_managed() {
+        let cluster_echo = cluster_echo(json!({"replicas": 1}));
+        let echo = cluster_echo.echo(&cluster_echo.echo_spec().unwrap(), "team-a");
+        assert!(cluster_echo.is_managed(&echo));
+        // an Echo of the user with the same name
+        assert!(!cluster_echo.is_managed(&Echo::test(None)));
+
+        let mut other = cluster_echo.clone();
+        other.meta_mut().name = Some("other".to_string());
+        assert!(!other.is_managed(&echo));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_skips_unmanaged_echo() {
+        let fake = FakeApiServer::default();
+        for namespace in ["team-a", "team-b"] {
+            fake.create(&Namespace {
+                metadata: ObjectMeta {
+                    name: Some(namespace.to_string()),
+                    ..ObjectMeta::default()
+                },
+                ..Namespace::default()
+            });
+        }
+        let mut user_echo = Echo::test(None).change_replicas(3);
+        user_echo.meta_mut().name = Some("debug".to_string());
+        user_echo.meta_mut().namespace = Some("team-a".to_string());
+        fake.create(&user_echo);
+        let cluster_echo = cluster_echo(json!({"replicas": 2}));
+        fake.create(&cluster_echo);
+        let (testctx, _fakeserver) = get_test_context();
+        let ctx = Arc::new(Context {
+            client: fake.client(),
+            ..(*testctx).clone()
+        });
+
+        reconcile_cluster_echo(Arc::new(cluster_echo), ctx)
+            .await
+            .unwrap();
+
+        let existing = fake.get::<Echo>(Some("team-a"), "debug").unwrap();
+        assert_eq!(existing.spec.replicas, 3);
+        assert!(existing.labels().get(CLUSTER_ECHO_LABEL).is_none());
+        assert!(existing.owner_references().is_empty());
+        let created = fake.get::<Echo>(Some("team-b"), "debug").unwrap();
+        assert_eq!(created.spec.replicas, 2);
+        let status = fake
+            .get::<ClusterEcho>(None, "debug")
+            .unwrap()
+            .status
+            .unwrap();
+        assert_eq!(status.namespaces, Some(vec!["team-b".to_string()]));
+        assert_eq!(status.conflicts, Some(vec!["team-a".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_suppressed_during_maintenance() {
+        let fake = FakeApiServer::default();
+        fake.create(&Namespace {
+            metadata: ObjectMeta {
+                name: Some("team-a".to_string()),
+                ..ObjectMeta::default()
+            },
+            ..Namespace::default()
+        });
+        let cluster_echo = cluster_echo(json!({"replicas": 2}));
+        fake.create(&cluster_echo);
+        let window = MaintenanceWindow {
+            start: test_time() - Duration::hours(1),
+            end: test_time() + Duration::minutes(1),
+        };
+        let (testctx, _fakeserver) = get_test_context();
+        let ctx = Arc::new(Context {
+            client: fake.client(),
+            maintenance: Arc::new(Maintenance::new(Some(window))),
+            ..(*testctx).clone()
+        });
+
+        let action = reconcile_cluster_echo(Arc::new(cluster_echo), ctx)
+            .await
+            .unwrap();
+
+        // requeued when the window ends
+        assert_eq!(action, Action::requeue(std::time::Duration::from_secs(60)));
+        assert!(fake.get::<Echo>(Some("team-a"), "debug").is_none());
+        assert!(fake
+            .get::<ClusterEcho>(None, "debug")
+            .unwrap()
+            .status
+            .is_none());
+    }
+}
@@ -1,21 +1,160 @@
+use crate::audit::Auditor;
+use crate::clock::{Clock, SystemClock};
+use crate::clusterdefaults::ClusterDefaultsStore;
+use crate::echo::priority::SchedulingPolicy;
 use crate::error::{Error, Result};
+use crate::heartbeat::Heartbeat;
+use crate::hook::{Hooks, ReconcileHook};
+use crate::maintenance::{Maintenance, MaintenanceWindow};
 use crate::metrics::{ControllerMetrics, Metrics};
+use crate::namespace_filter::NamespaceFilter;
+use crate::notify::Notifier;
+use crate::rbac_allowlist::RbacAllowlist;
+use crate::requeue::{Failures, ReconcileConfig};
+use crate::sentry::ErrorReporter;
+use crate::startup::{StartupRamp, StartupRampConfig};
+use crate::status::StatusBatcher;
+use crate::stores::Stores;
+use crate::watchlist;
 
-use std::collections::HashMap;
-use std::hash::Hash;
+use std::collections::BTreeMap;
+use std::future::Future;
 use std::sync::Arc;
+use std::time::Duration;
 
+use futures::future::{self, BoxFuture};
+use futures::FutureExt;
 use kube::client::Client;
-use kube::runtime::reflector::{Lookup, Store};
+use kube::runtime::reflector::store::WriterDropped;
+use kube::ResourceExt;
 use prometheus_client::registry::Registry;
+use tracing::debug;
+
+pub use crate::metrics::METRICS_PREFIX;
 
 pub type ControllerId = &'static str;
 
+/// Controller run by the operator until shutdown
+pub trait ControllerRunner: Send + Sync {
+    /// Identifier of the controller in the metrics and the `--controllers` flag
+    fn id(&self) -> ControllerId;
+
+    fn run(&self, state: State, client: Client) -> BoxFuture<'static, ()>;
+}
+
+/// Runner of a controller `run` function
+struct FnRunner<F> {
+    id: ControllerId,
+    run: F,
+}
+
+impl<F, Fut> ControllerRunner for FnRunner<F>
+where
+    F: Fn(State, Client) -> Fut + Send + Sync,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    fn id(&self) -> ControllerId {
+        self.id
+    }
+
+    fn run(&self, state: State, client: Client) -> BoxFuture<'static, ()> {
+        (self.run)(state, client).boxed()
+    }
+}
+
+/// Controllers composing the operator
+#[derive(Default)]
+pub struct ControllerRegistry {
+    runners: Vec<Box<dyn ControllerRunner>>,
+}
+
+impl ControllerRegistry {
+    /// Register a controller from its `run` function
+    pub fn register<F, Fut>(self, id: ControllerId, run: F) -> Self
+    where
+        F: Fn(State, Client) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.register_runner(FnRunner { id, run })
+    }
+
+    pub fn register_runner(mut self, runner: impl ControllerRunner + 'static) -> Self {
+        self.runners.push(Box::new(runner));
+        self
+    }
+
+    /// Identifiers of the registered controllers, in registration order
+    pub fn ids(&self) -> Vec<ControllerId> {
+        self.runners.iter().map(|r| r.id()).collect()
+    }
+
+    /// Keep only the given controllers, or every controller if none is given
+    pub fn enable(mut self, ids: &[String]) -> Result<Self> {
+        if let Some(unknown) = ids.iter().find(|id| !self.ids().contains(&id.as_str())) {
+            return Err(Error::UnknownController(unknown.clone()));
+        }
+        if !ids.is_empty() {
+            self.runners.retain(|r| ids.iter().any(|id| id == r.id()));
+        }
+        Ok(self)
+    }
+
+    /// Run every controller until all of them are shut down
+    pub async fn run(self, state: State, client: Client) {
+        future::join_all(
+            self.runners
+                .iter()
+                .map(|r| r.run(state.clone(), client.clone())),
+        )
+        .await;
+    }
+}
+
 /// State shared between the controller and the web server
 #[derive(Clone)]
 pub struct State {
     /// Metrics
     metrics: Arc<Metrics>,
+    /// Status transition notifications
+    notifier: Arc<Notifier>,
+    /// Audit trail of the mutating actions
+    auditor: Arc<Auditor>,
+    /// Error tracker receiving the failed reconciliations
+    error_reporter: Arc<ErrorReporter>,
+    /// Reconcile hooks
+    hooks: Arc<Hooks>,
+    /// Order of the echoes reconciled after each initial list
+    scheduling_policy: SchedulingPolicy,
+    /// Window coalescing the status patches of each echo
+    status_batch_window: Duration,
+    /// Echoes the status writer queue holds, unset to patch the status from the reconciliations
+    status_queue_capacity: Option<usize>,
+    /// Do not reconcile the echoes when only their labels or annotations change
+    ignore_metadata_changes: bool,
+    /// Use streaming lists in the Echo and Deployment watchers, when the cluster supports them
+    streaming_lists: bool,
+    /// Objects per page of the paginated initial lists of the Echo and Deployment watchers
+    watch_page_size: u32,
+    /// Watch the Deployments of the namespaces with echoes only, instead of cluster-wide
+    lazy_deployment_reflectors: bool,
+    /// Cluster-wide maintenance windows
+    maintenance: Arc<Maintenance>,
+    /// Cluster-wide defaults and overrides of the echo pods
+    cluster_defaults: Arc<ClusterDefaultsStore>,
+    /// Secret, in the operator namespace, with the registry credentials of the echo images
+    registry_credentials_secret: Option<String>,
+    /// Namespaces where the echoes are managed
+    namespace_filter: Arc<NamespaceFilter>,
+    /// Permissions the echoes may grant to their pods
+    rbac_allowlist: Arc<RbacAllowlist>,
+    /// Duration above which an echo reconciliation is reported as slow
+    slow_reconcile_threshold: Option<Duration>,
+    /// When the reconciliations are requeued
+    reconcile_config: ReconcileConfig,
+    /// Limit of the Deployment applies after the start
+    startup_ramp: Arc<StartupRamp>,
+    /// Progress of the echo controller
+    heartbeat: Arc<Heartbeat>,
 }
 
 /// State wrapper around the controller outputs for the web server
@@ -23,11 +162,180 @@ impl State {
     pub fn new(registry: Registry, controller_names: &[&'static str]) -> Self {
         Self {
             metrics: Arc::new(Metrics::new(registry, controller_names)),
+            notifier: Arc::default(),
+            auditor: Arc::default(),
+            error_reporter: Arc::default(),
+            hooks: Arc::default(),
+            scheduling_policy: SchedulingPolicy::default(),
+            status_batch_window: Duration::ZERO,
+            status_queue_capacity: None,
+            ignore_metadata_changes: false,
+            streaming_lists: false,
+            watch_page_size: watchlist::DEFAULT_PAGE_SIZE,
+            lazy_deployment_reflectors: false,
+            maintenance: Arc::default(),
+            cluster_defaults: Arc::default(),
+            registry_credentials_secret: None,
+            namespace_filter: Arc::default(),
+            rbac_allowlist: Arc::default(),
+            slow_reconcile_threshold: None,
+            reconcile_config: ReconcileConfig::default(),
+            startup_ramp: Arc::default(),
+            heartbeat: Arc::default(),
         }
     }
 
+    /// Send status transition notifications with the given notifier
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = Arc::new(notifier);
+        self
+    }
+
+    /// Record the mutating actions of the controllers with the given auditor
+    pub fn with_auditor(mut self, auditor: Auditor) -> Self {
+        self.auditor = Arc::new(auditor);
+        self
+    }
+
+    /// Report the failed reconciliations of the controllers with the given error reporter
+    pub fn with_error_reporter(mut self, error_reporter: ErrorReporter) -> Self {
+        self.error_reporter = Arc::new(error_reporter);
+        self
+    }
+
+    /// Register a hook called by the echo reconciler, after the already registered ones
+    pub fn with_hook(mut self, hook: impl ReconcileHook + 'static) -> Self {
+        Arc::make_mut(&mut self.hooks).push(Arc::new(hook));
+        self
+    }
+
+    /// Schedule the echoes of the initial list with the given policy
+    pub fn with_scheduling_policy(mut self, policy: SchedulingPolicy) -> Self {
+        self.scheduling_policy = policy;
+        self
+    }
+
+    /// Coalesce the status patches of each echo sent within the window, zero to disable it
+    pub fn with_status_batch_window(mut self, window: Duration) -> Self {
+        self.status_batch_window = window;
+        self
+    }
+
+    /// Patch the echo statuses from a writer task with a queue of the given capacity, instead of
+    /// from the reconciliations
+    pub fn with_status_queue_capacity(mut self, capacity: Option<usize>) -> Self {
+        self.status_queue_capacity = capacity;
+        self
+    }
+
+    /// Do not reconcile the echoes when only their labels or annotations change
+    pub fn with_ignore_metadata_changes(mut self, ignore: bool) -> Self {
+        self.ignore_metadata_changes = ignore;
+        self
+    }
+
+    /// Use streaming lists in the Echo and Deployment watchers, when the cluster supports them
+    pub fn with_streaming_lists(mut self, streaming_lists: bool) -> Self {
+        self.streaming_lists = streaming_lists;
+        self
+    }
+
+    /// List the objects of the Echo and Deployment watchers in pages of the given size, when they
+    /// do not use streaming lists
+    pub fn with_watch_page_size(mut self, page_size: u32) -> Self {
+        self.watch_page_size = page_size;
+        self
+    }
+
+    /// Watch the Deployments of each namespace from its first echo until its last one is deleted,
+    /// instead of cluster-wide
+    pub fn with_lazy_deployment_reflectors(mut self, lazy: bool) -> Self {
+        self.lazy_deployment_reflectors = lazy;
+        self
+    }
+
+    /// Suppress the mutating actions during the given cluster-wide maintenance window
+    pub fn with_maintenance_window(mut self, window: Option<MaintenanceWindow>) -> Self {
+        self.maintenance = Arc::new(Maintenance::new(window));
+        self
+    }
+
+    /// Copy the registry credentials of the given Secret, in the operator namespace, to the
+    /// namespace of every echo
+    pub fn with_registry_credentials_secret(mut self, secret: Option<String>) -> Self {
+        self.registry_credentials_secret = secret;
+        self
+    }
+
+    /// Only manage the echoes of the namespaces allowed by the filter
+    pub fn with_namespace_filter(mut self, filter: NamespaceFilter) -> Self {
+        self.namespace_filter = Arc::new(filter);
+        self
+    }
+
+    /// Only apply the RBAC rules of the echoes granting permissions of the allowlist
+    pub fn with_rbac_allowlist(mut self, allowlist: RbacAllowlist) -> Self {
+        self.rbac_allowlist = Arc::new(allowlist);
+        self
+    }
+
+    /// Warn about the echo reconciliations taking longer than the threshold
+    pub fn with_slow_reconcile_threshold(mut self, threshold: Option<Duration>) -> Self {
+        self.slow_reconcile_threshold = threshold;
+        self
+    }
+
+    /// Requeue the reconciliations of every controller with the given policy
+    pub fn with_reconcile_config(mut self, config: ReconcileConfig) -> Self {
+        self.reconcile_config = config;
+        self
+    }
+
+    /// Limit the Deployment applies after the start with the given ramp
+    pub fn with_startup_ramp(mut self, config: StartupRampConfig) -> Self {
+        self.startup_ramp = Arc::new(StartupRamp::new(config));
+        self
+    }
+
+    pub(crate) fn scheduling_policy(&self) -> SchedulingPolicy {
+        self.scheduling_policy
+    }
+
+    pub(crate) fn ignore_metadata_changes(&self) -> bool {
+        self.ignore_metadata_changes
+    }
+
+    pub(crate) fn streaming_lists(&self) -> bool {
+        self.streaming_lists
+    }
+
+    pub(crate) fn watch_page_size(&self) -> u32 {
+        self.watch_page_size
+    }
+
+    pub(crate) fn lazy_deployment_reflectors(&self) -> bool {
+        self.lazy_deployment_reflectors
+    }
+
+    pub(crate) fn maintenance(&self) -> Arc<Maintenance> {
+        self.maintenance.clone()
+    }
+
+    /// Cluster-wide defaults and overrides of the echo pods, e.g. to render their manifests
+    pub fn cluster_defaults(&self) -> Arc<ClusterDefaultsStore> {
+        self.cluster_defaults.clone()
+    }
+
+    pub(crate) fn heartbeat(&self) -> Arc<Heartbeat> {
+        self.heartbeat.clone()
+    }
+
     /// Metrics getter
     pub fn metrics(&self) -> Result<String> {
+        self.metrics
+            .controllers
+            .values()
+            .for_each(|m| m.success_ratios_refresh());
         let mut buffer = String::new();
         let registry = &*self.metrics.registry;
         prometheus_client::encoding::text::encode(&mut buffer, registry)
@@ -35,16 +343,31 @@ impl State {
         Ok(buffer)
     }
 
+    /// Whether each enabled controller reports itself ready in its `ready` gauge
+    pub(crate) fn controllers_ready(&self) -> BTreeMap<ControllerId, bool> {
+        self.metrics
+            .controllers
+            .iter()
+            .map(|(&id, m)| (id, m.ready()))
+            .collect()
+    }
+
+    /// Metrics of a controller not using a Context
+    pub(crate) fn controller_metrics(&self, controller_id: ControllerId) -> Arc<ControllerMetrics> {
+        self.metrics
+            .controllers
+            .get(controller_id)
+            .expect("all CONTROLLER_IDs have to be registered")
+            .clone()
+    }
+
     /// Create a Controller Context that can update State
-    pub fn to_context<K: 'static + Lookup>(
+    pub fn to_context(
         &self,
         client: Client,
         controller_id: ControllerId,
-        store: HashMap<String, Box<Store<K>>>,
-    ) -> Arc<Context<K>>
-    where
-        K::DynamicType: Hash + Eq,
-    {
+        stores: Stores,
+    ) -> Arc<Context> {
         Arc::new(Context {
             client,
             metrics: self
@@ -53,21 +376,161 @@ impl State {
                 .get(controller_id)
                 .expect("all CONTROLLER_IDs have to be registered")
                 .clone(),
-            stores: Arc::new(store),
+            stores: Arc::new(stores),
+            clock: Arc::new(SystemClock),
+            notifier: self.notifier.clone(),
+            auditor: self.auditor.clone(),
+            error_reporter: self.error_reporter.clone(),
+            hooks: self.hooks.clone(),
+            status_batcher: Arc::new(
+                StatusBatcher::new(self.status_batch_window).with_queue(self.status_queue_capacity),
+            ),
+            maintenance: self.maintenance.clone(),
+            cluster_defaults: self.cluster_defaults.clone(),
+            registry_credentials_secret: self.registry_credentials_secret.clone(),
+            namespace_filter: self.namespace_filter.clone(),
+            rbac_allowlist: self.rbac_allowlist.clone(),
+            slow_reconcile_threshold: self.slow_reconcile_threshold,
+            reconcile_config: self.reconcile_config.clone(),
+            startup_ramp: self.startup_ramp.clone(),
+            failures: Arc::default(),
         })
     }
 }
 
 // Context for our reconciler
 #[derive(Clone)]
-pub struct Context<K: 'static + Lookup>
-where
-    K::DynamicType: Hash + Eq,
-{
+pub struct Context {
     /// Kubernetes client
     pub client: Client,
     /// Prometheus metrics
     pub metrics: Arc<ControllerMetrics>,
-    /// Shared store
-    pub stores: Arc<HashMap<String, Box<Store<K>>>>,
+    /// Shared stores, by object type
+    pub stores: Arc<Stores>,
+    /// Time source
+    pub clock: Arc<dyn Clock>,
+    /// Status transition notifications
+    pub notifier: Arc<Notifier>,
+    /// Audit trail of the mutating actions
+    pub auditor: Arc<Auditor>,
+    /// Error tracker receiving the failed reconciliations
+    pub error_reporter: Arc<ErrorReporter>,
+    /// Reconcile hooks
+    pub hooks: Arc<Hooks>,
+    /// Pending status patches
+    pub status_batcher: Arc<StatusBatcher>,
+    /// Cluster-wide maintenance windows
+    pub maintenance: Arc<Maintenance>,
+    /// Cluster-wide defaults and overrides of the echo pods
+    pub cluster_defaults: Arc<ClusterDefaultsStore>,
+    /// Secret, in the operator namespace, with the registry credentials of the echo images
+    pub registry_credentials_secret: Option<String>,
+    /// Namespaces where the echoes are managed
+    pub namespace_filter: Arc<NamespaceFilter>,
+    /// Permissions the echoes may grant to their pods
+    pub rbac_allowlist: Arc<RbacAllowlist>,
+    /// Duration above which an echo reconciliation is reported as slow
+    pub slow_reconcile_threshold: Option<Duration>,
+    /// When the reconciliations are requeued
+    pub reconcile_config: ReconcileConfig,
+    /// Limit of the Deployment applies after the start
+    pub startup_ramp: Arc<StartupRamp>,
+    /// Consecutive failed reconciliations, backing off their requeues
+    pub failures: Arc<Failures>,
+}
+
+impl Context {
+    /// Wait until every store completed its initial list, so objects not listed yet are not
+    /// reported as missing
+    pub async fn wait_for_stores(&self) -> std::result::Result<(), WriterDropped> {
+        self.stores.wait_until_ready().await
+    }
+
+    /// Requeue of a successful reconciliation of the object
+    pub fn requeue_interval<K: ResourceExt>(&self, obj: &K) -> Duration {
+        self.reconcile_config.success_requeue(obj)
+    }
+
+    /// Forget the failures of the object, so its next failure is retried after the base backoff
+    pub fn reconciled<K: ResourceExt>(&self, obj: &K) {
+        self.failures.reset(obj);
+    }
+
+    /// Requeue of an object whose changes are suppressed by the cluster-wide maintenance window in
+    /// progress, if any
+    pub fn maintenance_requeue<K: ResourceExt>(&self, obj: &K) -> Option<Duration> {
+        let now = self.clock.now();
+        let window = self.maintenance.active(now)?;
+        debug!(msg = "suppressing changes during maintenance window", %window);
+        Some(window.requeue_after(now, self.requeue_interval(obj)))
+    }
+
+    /// Requeue of a failed reconciliation of the object, backing off on consecutive failures
+    pub fn error_requeue<K: ResourceExt>(&self, obj: &K) -> Duration {
+        self.reconcile_config
+            .error_requeue(obj, self.failures.record(obj))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ControllerRegistry, State};
+
+    use crate::error::Error;
+    use crate::stores::Stores;
+
+    use http::{Request, Response};
+    use k8s_openapi::api::apps::v1::Deployment;
+    use kube::client::Body;
+    use kube::runtime::reflector::store::Writer;
+    use kube::runtime::watcher;
+    use kube::Client;
+    use prometheus_client::registry::Registry;
+
+    fn registry() -> ControllerRegistry {
+        ControllerRegistry::default()
+            .register("echo", |_, _| async {})
+            .register("gc", |_, _| async {})
+    }
+
+    #[test]
+    fn test_enable_all_by_default() {
+        assert_eq!(registry().enable(&[]).unwrap().ids(), vec!["echo", "gc"]);
+    }
+
+    #[test]
+    fn test_enable_some() {
+        let registry = registry().enable(&["gc".to_string()]).unwrap();
+        assert_eq!(registry.ids(), vec!["gc"]);
+    }
+
+    #[test]
+    fn test_enable_unknown() {
+        assert!(matches!(
+            registry().enable(&["echoes".to_string()]),
+            Err(Error::UnknownController(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_stores() {
+        let (mock_service, _handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+        let mut writer = Writer::<Deployment>::default();
+        let stores = Stores::default().with(writer.as_reader());
+        let ctx = State::new(Registry::default(), &["echo"]).to_context(
+            Client::new(mock_service, "default"),
+            "echo",
+            stores,
+        );
+
+        let wait = tokio::spawn({
+            let ctx = ctx.clone();
+            async move { ctx.wait_for_stores().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!wait.is_finished());
+
+        writer.apply_watcher_event(&watcher::Event::InitDone);
+        assert!(wait.await.unwrap().is_ok());
+    }
 }
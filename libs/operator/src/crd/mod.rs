@@ -1,2 +1,12 @@
 #[rustfmt::skip]
+pub mod clusterecho;
+#[rustfmt::skip]
 pub mod echo;
+#[rustfmt::skip]
+pub mod echogateway;
+#[rustfmt::skip]
+pub mod echoroute;
+#[rustfmt::skip]
+pub mod echoquota;
+#[rustfmt::skip]
+pub mod echoreplication;
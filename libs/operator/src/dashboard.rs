@@ -0,0 +1,283 @@
+//! Grafana dashboards summarizing the operator metrics for the Echoes of every namespace.
+//!
+//! Panels are generated from the [`METRIC_DEFINITIONS`] registered by the controllers, so new
+//! metrics show up in the dashboards without further changes.
+use crate::controller::{ControllerId, State};
+use crate::crd::echo::Echo;
+use crate::error::{Error, Result};
+use crate::metrics::{ControllerMetrics, MetricKind, METRICS_PREFIX, METRIC_DEFINITIONS};
+use crate::permissions::Permission;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::api::{
+    Api, ApiResource, DeleteParams, DynamicObject, GroupVersionKind, ListParams, ObjectMeta, Patch,
+    PatchParams,
+};
+use kube::client::Client;
+use kube::ResourceExt;
+use opentelemetry::trace::TraceId;
+use serde_json::{json, Value};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::{self, Duration};
+use tracing::{debug, error, info};
+
+pub const CONTROLLER_ID: ControllerId = "dashboard";
+
+pub const PERMISSIONS: &[Permission] = &[
+    Permission::new("example.com", &["echoes"], &["list"]),
+    Permission::new("", &["configmaps"], &["create", "delete", "list", "patch"]),
+    Permission::new(
+        "grafana.integreatly.org",
+        &["grafanadashboards"],
+        &["create", "delete", "list", "patch"],
+    ),
+];
+
+const DASHBOARD_NAME: &str = "echo-operator";
+const FIELD_MANAGER: &str = "dashboards.example.com";
+const SELECTOR: &str = "app.kubernetes.io/name=echo-operator-dashboard";
+const PANEL_WIDTH: i64 = 12;
+const PANEL_HEIGHT: i64 = 8;
+
+/// How dashboards are provisioned in Grafana
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DashboardKind {
+    /// ConfigMap labeled `grafana_dashboard: "1"`, loaded by the Grafana sidecar
+    ConfigMap,
+    /// grafana-operator `GrafanaDashboard`, for Grafana instances labeled `dashboards: grafana`
+    GrafanaOperator,
+}
+
+/// grafana-operator GrafanaDashboard, which is not part of the core Kubernetes API
+fn grafana_dashboard_resource() -> ApiResource {
+    ApiResource::from_gvk(&GroupVersionKind::gvk(
+        "grafana.integreatly.org",
+        "v1beta1",
+        "GrafanaDashboard",
+    ))
+}
+
+/// PromQL query of a metric, filtered by namespace when the metric is labeled per resource.
+///
+/// Metrics are selected by `__name__` because the prefix is not a valid PromQL identifier.
+fn query(name: &str, kind: MetricKind, per_resource: bool, namespace: &str) -> String {
+    let (namespace_selector, by) = if per_resource {
+        (format!(r#",namespace="{namespace}""#), "name")
+    } else {
+        (String::new(), "controller")
+    };
+    let selector = |suffix: &str| {
+        format!(r#"{{__name__="{METRICS_PREFIX}_{name}{suffix}"{namespace_selector}}}"#)
+    };
+    match kind {
+        MetricKind::Counter => format!("sum by ({by}) (rate({}[5m]))", selector("_total")),
+        MetricKind::Gauge => format!("sum by ({by}) ({})", selector("")),
+        MetricKind::Histogram => format!(
+            "histogram_quantile(0.99, sum by (le, {by}) (rate({}[5m])))",
+            selector("_seconds_bucket")
+        ),
+    }
+}
+
+/// Grafana dashboard model for the Echoes of a namespace
+pub fn dashboard(namespace: &str) -> Value {
+    // per resource metrics first, as they are the ones specific to the namespace
+    let mut definitions = METRIC_DEFINITIONS.to_vec();
+    definitions.sort_by_key(|d| !d.per_resource);
+
+    let panels: Vec<Value> = definitions
+        .iter()
+        .enumerate()
+        .map(|(i, definition)| {
+            let i = i as i64;
+            json!({
+                "id": i + 1,
+                "type": "timeseries",
+                "title": definition.name,
+                "description": definition.help,
+                "datasource": {"type": "prometheus", "uid": "${datasource}"},
+                "gridPos": {
+                    "x": (i % 2) * PANEL_WIDTH,
+                    "y": (i / 2) * PANEL_HEIGHT,
+                    "w": PANEL_WIDTH,
+                    "h": PANEL_HEIGHT,
+                },
+                "targets": [{
+                    "refId": "A",
+                    "expr": query(definition.name, definition.kind, definition.per_resource, namespace),
+                    "legendFormat": if definition.per_resource { "{{name}}" } else { "{{controller}}" },
+                }],
+            })
+        })
+        .collect();
+
+    json!({
+        "title": format!("Echoes / {namespace}"),
+        "uid": format!("echo-operator-{namespace}"),
+        "tags": ["echo-operator"],
+        "schemaVersion": 39,
+        "time": {"from": "now-6h", "to": "now"},
+        "templating": {"list": [{
+            "name": "datasource",
+            "type": "datasource",
+            "query": "prometheus",
+        }]},
+        "panels": panels,
+    })
+}
+
+fn metadata(namespace: &str, extra_labels: &[(&str, &str)]) -> ObjectMeta {
+    ObjectMeta {
+        name: Some(DASHBOARD_NAME.to_owned()),
+        namespace: Some(namespace.to_owned()),
+        labels: Some(
+            [
+                ("app.kubernetes.io/name", "echo-operator-dashboard"),
+                ("app.kubernetes.io/managed-by", "echo-operator"),
+            ]
+            .iter()
+            .chain(extra_labels)
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        ),
+        ..ObjectMeta::default()
+    }
+}
+
+/// Dashboard resource of a namespace for the given provisioning kind
+pub fn dashboard_resource(kind: DashboardKind, namespace: &str) -> Value {
+    let dashboard = dashboard(namespace).to_string();
+    match kind {
+        DashboardKind::ConfigMap => json!(ConfigMap {
+            metadata: metadata(namespace, &[("grafana_dashboard", "1")]),
+            data: Some(BTreeMap::from([(
+                format!("{DASHBOARD_NAME}-{namespace}.json"),
+                dashboard
+            )])),
+            ..ConfigMap::default()
+        }),
+        DashboardKind::GrafanaOperator => json!({
+            "apiVersion": "grafana.integreatly.org/v1beta1",
+            "kind": "GrafanaDashboard",
+            "metadata": metadata(namespace, &[]),
+            "spec": {
+                "instanceSelector": {"matchLabels": {"dashboards": "grafana"}},
+                "allowCrossNamespaceImport": true,
+                "json": dashboard,
+            },
+        }),
+    }
+}
+
+/// Provision the dashboards every interval
+pub async fn run(state: State, client: Client, kind: DashboardKind, interval: Duration) {
+    let metrics = state.controller_metrics(CONTROLLER_ID);
+    let mut ticker = time::interval(interval);
+    // safe unwrap: signal handlers can always be registered in the tokio runtime
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    metrics.ready_set(1);
+    info!(msg = "starting dashboard provisioning", ?kind);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {},
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        }
+        if let Err(e) = provision(client.clone(), kind, &metrics).await {
+            error!(msg = "failed to provision dashboards", %e);
+            metrics.reconcile_failure_set(&e);
+        }
+    }
+}
+
+async fn provision(client: Client, kind: DashboardKind, metrics: &ControllerMetrics) -> Result<()> {
+    let _timer = metrics.reconcile_count_and_measure(&TraceId::INVALID);
+    let namespaces: BTreeSet<String> = Api::<Echo>::all(client.clone())
+        .list(&ListParams::default())
+        .await
+        .map_err(Error::KubeError)?
+        .iter()
+        .filter_map(|echo| echo.namespace())
+        .collect();
+
+    let api_resource = match kind {
+        DashboardKind::ConfigMap => ApiResource::erase::<ConfigMap>(&()),
+        DashboardKind::GrafanaOperator => grafana_dashboard_resource(),
+    };
+
+    for namespace in &namespaces {
+        debug!(msg = "applying dashboard", %namespace);
+        Api::<DynamicObject>::namespaced_with(client.clone(), namespace, &api_resource)
+            .patch(
+                DASHBOARD_NAME,
+                &PatchParams::apply(FIELD_MANAGER).force(),
+                &Patch::Apply(&dashboard_resource(kind, namespace)),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+    }
+
+    // namespaces without echoes don't need a dashboard anymore
+    let stale = Api::<DynamicObject>::all_with(client.clone(), &api_resource)
+        .list(&ListParams::default().labels(SELECTOR))
+        .await
+        .map_err(Error::KubeError)?
+        .into_iter()
+        .filter(|d| d.namespace().is_some_and(|n| !namespaces.contains(&n)));
+    for dashboard in stale {
+        // safe unwrap: dashboards are namespaced
+        let namespace = dashboard.namespace().unwrap();
+        info!(msg = "deleting dashboard", %namespace);
+        Api::<DynamicObject>::namespaced_with(client.clone(), &namespace, &api_resource)
+            .delete(&dashboard.name_any(), &DeleteParams::default())
+            .await
+            .map_err(Error::KubeError)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dashboard, dashboard_resource, query, DashboardKind};
+
+    use crate::metrics::{MetricKind, METRIC_DEFINITIONS};
+
+    #[test]
+    fn test_query() {
+        assert_eq!(
+            query("spec_replicas", MetricKind::Gauge, true, "team-a"),
+            r#"sum by (name) ({__name__="echo-operator_spec_replicas",namespace="team-a"})"#
+        );
+        assert_eq!(
+            query("reconcile_failures", MetricKind::Counter, false, "team-a"),
+            r#"sum by (controller) (rate({__name__="echo-operator_reconcile_failures_total"}[5m]))"#
+        );
+        assert_eq!(
+            query("reconcile_duration", MetricKind::Histogram, false, "team-a"),
+            r#"histogram_quantile(0.99, sum by (le, controller) (rate({__name__="echo-operator_reconcile_duration_seconds_bucket"}[5m])))"#
+        );
+    }
+
+    #[test]
+    fn test_dashboard_has_a_panel_per_metric() {
+        let dashboard = dashboard("team-a");
+
+        let panels = dashboard["panels"].as_array().unwrap();
+        assert_eq!(panels.len(), METRIC_DEFINITIONS.len());
+        assert_eq!(panels[0]["title"], "spec_replicas");
+        assert_eq!(dashboard["uid"], "echo-operator-team-a");
+    }
+
+    #[test]
+    fn test_dashboard_resource() {
+        let config_map = dashboard_resource(DashboardKind::ConfigMap, "team-a");
+        assert_eq!(config_map["metadata"]["labels"]["grafana_dashboard"], "1");
+        assert!(config_map["data"]["echo-operator-team-a.json"].is_string());
+
+        let grafana_dashboard = dashboard_resource(DashboardKind::GrafanaOperator, "team-a");
+        assert_eq!(grafana_dashboard["kind"], "GrafanaDashboard");
+        assert!(grafana_dashboard["spec"]["json"].is_string());
+    }
+}
@@ -0,0 +1,136 @@
+//! Field level diff between a cached object and the one about to be applied.
+use crate::error::{Error, Result};
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// Field set in the applied object with a different value than the current one
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FieldChange {
+    /// Path of the field, e.g. `spec.template.spec.containers[0].image`
+    pub path: String,
+    /// Current value, if the field is set
+    pub from: Option<Value>,
+    pub to: Value,
+}
+
+/// Changes of the fields set in the applied object. Fields only set in the current object, like
+/// defaults or fields of other managers, are not changed by a server-side apply, so they are
+/// ignored.
+pub fn applied_diff<T: Serialize>(current: Option<&T>, applied: &T) -> Result<Vec<FieldChange>> {
+    let current = current
+        .map(serde_json::to_value)
+        .transpose()
+        .map_err(Error::SerializationError)?;
+    let applied = serde_json::to_value(applied).map_err(Error::SerializationError)?;
+    let mut changes = Vec::new();
+    diff_value(String::new(), current.as_ref(), &applied, &mut changes);
+    Ok(changes)
+}
+
+fn diff_value(
+    path: String,
+    current: Option<&Value>,
+    applied: &Value,
+    changes: &mut Vec<FieldChange>,
+) {
+    match applied {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                let path = if path.is_empty() {
+                    key.to_owned()
+                } else {
+                    format!("{path}.{key}")
+                };
+                diff_value(path, current.and_then(|c| c.get(key)), value, changes);
+            }
+        }
+        // items are only compared one by one while they are not added or removed
+        Value::Array(items)
+            if current
+                .and_then(Value::as_array)
+                .is_some_and(|c| c.len() == items.len()) =>
+        {
+            for (i, item) in items.iter().enumerate() {
+                diff_value(
+                    format!("{path}[{i}]"),
+                    current.and_then(|c| c.get(i)),
+                    item,
+                    changes,
+                );
+            }
+        }
+        _ if current != Some(applied) => changes.push(FieldChange {
+            path,
+            from: current.cloned(),
+            to: applied.clone(),
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{applied_diff, FieldChange};
+
+    use serde_json::json;
+
+    #[test]
+    fn test_applied_diff_unchanged() {
+        let current = json!({"spec": {"replicas": 1}, "status": {"readyReplicas": 1}});
+        let applied = json!({"spec": {"replicas": 1}});
+        assert!(applied_diff(Some(&current), &applied).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_applied_diff_resized_array() {
+        let current = json!({"containers": [{"name": "echo"}]});
+        let applied = json!({"containers": [{"name": "echo"}, {"name": "sidecar"}]});
+        assert_eq!(
+            applied_diff(Some(&current), &applied).unwrap(),
+            vec![FieldChange {
+                path: "containers".to_string(),
+                from: Some(json!([{"name": "echo"}])),
+                to: json!([{"name": "echo"}, {"name": "sidecar"}]),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_applied_diff_changed_fields() {
+        let current = json!({
+            "spec": {
+                "replicas": 1,
+                "template": {"spec": {"containers": [{"name": "echo", "image": "echo:1"}]}},
+            },
+        });
+        let applied = json!({
+            "metadata": {"labels": {"team": "platform"}},
+            "spec": {
+                "replicas": 3,
+                "template": {"spec": {"containers": [{"name": "echo", "image": "echo:2"}]}},
+            },
+        });
+
+        assert_eq!(
+            applied_diff(Some(&current), &applied).unwrap(),
+            vec![
+                FieldChange {
+                    path: "metadata.labels.team".to_string(),
+                    from: None,
+                    to: json!("platform"),
+                },
+                FieldChange {
+                    path: "spec.replicas".to_string(),
+                    from: Some(json!(1)),
+                    to: json!(3),
+                },
+                FieldChange {
+                    path: "spec.template.spec.containers[0].image".to_string(),
+                    from: Some(json!("echo:1")),
+                    to: json!("echo:2"),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! `spec.imagePerArch`: image of the echo server for each node architecture, so mixed amd64 and
+//! arm64 clusters do not schedule an image on nodes which can not run it.
+//!
+//! A Deployment runs a single image, so the validation requires the same multi-arch image for
+//! every architecture, and the pods are pinned with a node affinity to the listed ones.
+use crate::crd::echo::Echo;
+
+use k8s_openapi::api::core::v1::{
+    Affinity, NodeAffinity, NodeSelector, NodeSelectorRequirement, NodeSelectorTerm,
+};
+
+/// Well-known node label with the architecture of the node, as reported by the kubelet
+pub const ARCH_LABEL: &str = "kubernetes.io/arch";
+
+impl Echo {
+    /// Image of the echo pods and the architectures able to run it, if the Echo sets images per
+    /// architecture
+    pub(crate) fn arch_image(&self) -> Option<(String, Vec<String>)> {
+        let images = self.spec.image_per_arch.as_ref()?;
+        // the images were validated to be the same
+        let image = images.values().next()?;
+        Some((image.clone(), images.keys().cloned().collect()))
+    }
+
+    /// Node affinity scheduling the echo pods on the architectures of their image
+    pub(crate) fn arch_affinity(&self) -> Option<Affinity> {
+        let (_, archs) = self.arch_image()?;
+        Some(Affinity {
+            node_affinity: Some(NodeAffinity {
+                required_during_scheduling_ignored_during_execution: Some(NodeSelector {
+                    node_selector_terms: vec![NodeSelectorTerm {
+                        match_expressions: Some(vec![NodeSelectorRequirement {
+                            key: ARCH_LABEL.to_owned(),
+                            operator: "In".to_owned(),
+                            values: Some(archs),
+                        }]),
+                        ..NodeSelectorTerm::default()
+                    }],
+                }),
+                ..NodeAffinity::default()
+            }),
+            ..Affinity::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ARCH_LABEL;
+
+    use crate::crd::echo::Echo;
+
+    use std::collections::BTreeMap;
+
+    fn echo_with_images(images: &[(&str, &str)]) -> Echo {
+        let mut echo = Echo::test(None);
+        echo.spec.image_per_arch = Some(
+            images
+                .iter()
+                .map(|(arch, image)| (arch.to_string(), image.to_string()))
+                .collect::<BTreeMap<_, _>>(),
+        );
+        echo
+    }
+
+    #[test]
+    fn test_arch_image() {
+        assert_eq!(Echo::test(None).arch_image(), None);
+        assert_eq!(Echo::test(None).arch_affinity(), None);
+
+        let multi_arch = echo_with_images(&[("arm64", "echo:1"), ("amd64", "echo:1")]);
+        assert_eq!(
+            multi_arch.arch_image(),
+            Some((
+                "echo:1".to_string(),
+                vec!["amd64".to_string(), "arm64".to_string()]
+            ))
+        );
+
+        let single_arch = echo_with_images(&[("arm64", "echo:1-arm64")]);
+        assert_eq!(
+            single_arch.arch_image(),
+            Some(("echo:1-arm64".to_string(), vec!["arm64".to_string()]))
+        );
+    }
+
+    #[test]
+    fn test_arch_affinity() {
+        let echo = echo_with_images(&[("arm64", "echo:1-arm64")]);
+        let requirement = echo
+            .arch_affinity()
+            .and_then(|a| a.node_affinity)
+            .and_then(|n| n.required_during_scheduling_ignored_during_execution)
+            .and_then(|s| s.node_selector_terms.into_iter().next())
+            .and_then(|t| t.match_expressions)
+            .and_then(|e| e.into_iter().next())
+            .unwrap();
+        assert_eq!(requirement.key, ARCH_LABEL);
+        assert_eq!(requirement.operator, "In");
+        assert_eq!(requirement.values, Some(vec!["arm64".to_string()]));
+    }
+}
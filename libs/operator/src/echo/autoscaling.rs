@@ -0,0 +1,308 @@
+//! `spec.autoscaling`: HorizontalPodAutoscaler scaling the Deployment of the echo.
+//!
+//! While the Echo autoscales, the Deployment is applied without replicas so the HPA owns them, and
+//! `status.autoscaled` records the HPA so it is deleted once the section is removed from the spec.
+//! The apply owns the replicas again from then on, setting back `spec.replicas`.
+//!
+//! Dropping the replicas from the apply would release the only owner of the field, which the
+//! apiserver then defaults to 1. So before the first autoscaled apply, the live replicas are handed
+//! over to another field manager, as documented for server-side apply with an HPA, and the
+//! Deployment keeps its size until the HPA scales it.
+use crate::audit::{self, AuditAction};
+use crate::controller::Context;
+use crate::crd::echo::Echo;
+use crate::error::{Error, Result};
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::apps::v1::Deployment;
+use k8s_openapi::api::autoscaling::v2::{
+    CrossVersionObjectReference, HorizontalPodAutoscaler, HorizontalPodAutoscalerSpec, MetricSpec,
+    MetricTarget, ResourceMetricSource,
+};
+use kube::api::{Api, DeleteParams, ObjectMeta, Patch, PatchParams};
+use kube::ResourceExt;
+use serde_json::{json, Value};
+use tracing::{debug, info};
+
+/// Field manager keeping the replicas of the Deployment while the HPA takes them over
+const HANDOVER_FIELD_MANAGER: &str = "echoes.example.com-handover-to-hpa";
+
+/// CPU utilization targeted when the spec does not set any target, as the HPA itself does
+const DEFAULT_CPU_UTILIZATION: i32 = 80;
+
+/// Metric of the average utilization of the resource, a percentage of the pod requests
+fn utilization(resource: &str, average_utilization: i32) -> MetricSpec {
+    MetricSpec {
+        type_: "Resource".to_string(),
+        resource: Some(ResourceMetricSource {
+            name: resource.to_string(),
+            target: MetricTarget {
+                type_: "Utilization".to_string(),
+                average_utilization: Some(average_utilization),
+                ..MetricTarget::default()
+            },
+        }),
+        ..MetricSpec::default()
+    }
+}
+
+impl Echo {
+    /// Whether the HPA of the Echo owns the replicas of its Deployment
+    pub(crate) fn autoscaled(&self) -> bool {
+        self.spec.autoscaling.is_some()
+    }
+
+    fn autoscaling_recorded(&self) -> bool {
+        self.status
+            .as_ref()
+            .and_then(|s| s.autoscaled)
+            .unwrap_or(false)
+    }
+
+    /// Apply of the live replicas of the Deployment by the handover field manager, when the Echo
+    /// starts autoscaling an existing Deployment
+    pub(crate) fn replicas_handover(&self, current: Option<&Deployment>) -> Option<Value> {
+        if !self.autoscaled() || self.autoscaling_recorded() {
+            return None;
+        }
+        let replicas = current?.spec.as_ref()?.replicas?;
+        Some(json!({
+            "apiVersion": "apps/v1",
+            "kind": "Deployment",
+            "metadata": {"name": self.name_any(), "namespace": self.get_namespace()},
+            "spec": {"replicas": replicas},
+        }))
+    }
+
+    /// Apply the HPA while the spec sets it, or delete the one recorded in the status. Runs before
+    /// the Deployment apply, so the replicas are handed over before it drops them.
+    pub(crate) async fn reconcile_autoscaling(&self, ctx: &Context) -> Result<()> {
+        let hpa_api =
+            Api::<HorizontalPodAutoscaler>::namespaced(ctx.client.clone(), &self.get_namespace());
+        let recorded = self.autoscaling_recorded();
+        let Some(mut hpa) = self.horizontal_pod_autoscaler() else {
+            if recorded {
+                info!(msg = "deleting echo HorizontalPodAutoscaler");
+                match hpa_api
+                    .delete(&self.name_any(), &DeleteParams::default())
+                    .await
+                {
+                    Ok(_) => {
+                        self.audit(
+                            ctx,
+                            AuditAction::Delete,
+                            "HorizontalPodAutoscaler",
+                            "disabled".into(),
+                        )
+                        .await
+                    }
+                    Err(kube::Error::Api(ae)) if ae.code == 404 => {}
+                    Err(e) => return Err(Error::KubeError(e)),
+                }
+                self.patch_autoscaled(ctx, false).await?;
+            }
+            return Ok(());
+        };
+
+        if let Some(handover) = self.replicas_handover(self.current_deployment(ctx).as_deref()) {
+            info!(msg = "handing over the Deployment replicas to the HPA");
+            Api::<Deployment>::namespaced(ctx.client.clone(), &self.get_namespace())
+                .patch(
+                    &self.name_any(),
+                    &PatchParams::apply(HANDOVER_FIELD_MANAGER),
+                    &Patch::Apply(&handover),
+                )
+                .await
+                .map_err(Error::KubeError)?;
+        }
+        hpa.annotations_mut()
+            .extend(self.provenance_annotations(ctx)?);
+        let current = hpa_api
+            .get_opt(&self.name_any())
+            .await
+            .map_err(Error::KubeError)?;
+        let applied = hpa_api
+            .patch(
+                &self.name_any(),
+                &PatchParams::apply("echoes.example.com").force(),
+                &Patch::Apply(&hpa),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        if audit::apply_changed(current.as_ref(), &applied) {
+            let spec = hpa.spec.as_ref();
+            self.audit(
+                ctx,
+                AuditAction::Apply,
+                "HorizontalPodAutoscaler",
+                format!(
+                    "{}-{} replicas",
+                    spec.and_then(|s| s.min_replicas).unwrap_or(1),
+                    spec.map(|s| s.max_replicas).unwrap_or_default()
+                ),
+            )
+            .await;
+        }
+        if !recorded {
+            self.patch_autoscaled(ctx, true).await?;
+        }
+        Ok(())
+    }
+
+    async fn patch_autoscaled(&self, ctx: &Context, autoscaled: bool) -> Result<()> {
+        debug!(msg = "patching Echo autoscaled", autoscaled);
+        // a null removes the field instead of reporting false
+        let autoscaled = autoscaled.then_some(true);
+        Api::<Echo>::namespaced(ctx.client.clone(), &self.get_namespace())
+            .patch_status(
+                &self.name_any(),
+                &PatchParams::default(),
+                &Patch::Merge(json!({"status": {"autoscaled": autoscaled}})),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        Ok(())
+    }
+
+    /// HPA scaling the Deployment of the Echo on the utilization targets of the spec, CPU by
+    /// default, if the spec sets one
+    pub(crate) fn horizontal_pod_autoscaler(&self) -> Option<HorizontalPodAutoscaler> {
+        let autoscaling = self.spec.autoscaling.as_ref()?;
+        let name = self.name_any();
+        let mut metrics: Vec<MetricSpec> = [
+            ("cpu", autoscaling.target_cpu_utilization_percentage),
+            ("memory", autoscaling.target_memory_utilization_percentage),
+        ]
+        .into_iter()
+        .filter_map(|(resource, target)| target.map(|t| utilization(resource, t)))
+        .collect();
+        if metrics.is_empty() {
+            metrics.push(utilization("cpu", DEFAULT_CPU_UTILIZATION));
+        }
+        Some(HorizontalPodAutoscaler {
+            metadata: ObjectMeta {
+                name: Some(name.clone()),
+                namespace: Some(self.get_namespace()),
+                labels: Some(BTreeMap::from([
+                    ("app".to_string(), name.clone()),
+                    ("app.kubernetes.io/name".to_string(), "echo".to_string()),
+                    (
+                        "app.kubernetes.io/managed-by".to_string(),
+                        "echo-operator".to_string(),
+                    ),
+                ])),
+                owner_references: self.child_owner_references(),
+                ..ObjectMeta::default()
+            },
+            spec: Some(HorizontalPodAutoscalerSpec {
+                scale_target_ref: CrossVersionObjectReference {
+                    api_version: Some("apps/v1".to_string()),
+                    kind: "Deployment".to_string(),
+                    name,
+                },
+                min_replicas: autoscaling.min_replicas,
+                max_replicas: autoscaling.max_replicas,
+                metrics: Some(metrics),
+                ..HorizontalPodAutoscalerSpec::default()
+            }),
+            ..HorizontalPodAutoscaler::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crd::echo::{Echo, EchoAutoscaling, EchoStatus};
+
+    use k8s_openapi::api::apps::v1::{Deployment, DeploymentSpec};
+
+    fn echo_with_autoscaling(autoscaling: EchoAutoscaling) -> Echo {
+        let mut echo = Echo::test(None);
+        echo.spec.autoscaling = Some(autoscaling);
+        echo
+    }
+
+    #[test]
+    fn test_horizontal_pod_autoscaler_defaults() {
+        assert!(Echo::test(None).horizontal_pod_autoscaler().is_none());
+
+        let hpa = echo_with_autoscaling(EchoAutoscaling {
+            max_replicas: 5,
+            ..EchoAutoscaling::default()
+        })
+        .horizontal_pod_autoscaler()
+        .unwrap();
+        let spec = hpa.spec.unwrap();
+        assert_eq!(spec.scale_target_ref.kind, "Deployment");
+        assert_eq!(spec.scale_target_ref.name, "test");
+        assert_eq!(spec.min_replicas, None);
+        assert_eq!(spec.max_replicas, 5);
+        let metrics = spec.metrics.unwrap();
+        assert_eq!(metrics.len(), 1);
+        let resource = metrics[0].resource.as_ref().unwrap();
+        assert_eq!(resource.name, "cpu");
+        assert_eq!(resource.target.average_utilization, Some(80));
+    }
+
+    #[test]
+    fn test_horizontal_pod_autoscaler() {
+        let hpa = echo_with_autoscaling(EchoAutoscaling {
+            min_replicas: Some(2),
+            max_replicas: 10,
+            target_cpu_utilization_percentage: Some(60),
+            target_memory_utilization_percentage: Some(75),
+        })
+        .horizontal_pod_autoscaler()
+        .unwrap();
+        let spec = hpa.spec.unwrap();
+        assert_eq!(spec.min_replicas, Some(2));
+        assert_eq!(spec.max_replicas, 10);
+        let targets: Vec<_> = spec
+            .metrics
+            .unwrap()
+            .into_iter()
+            .map(|m| {
+                let resource = m.resource.unwrap();
+                (resource.name, resource.target.average_utilization)
+            })
+            .collect();
+        assert_eq!(
+            targets,
+            vec![
+                ("cpu".to_string(), Some(60)),
+                ("memory".to_string(), Some(75))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_replicas_handover() {
+        let live = Deployment {
+            spec: Some(DeploymentSpec {
+                replicas: Some(3),
+                ..DeploymentSpec::default()
+            }),
+            ..Deployment::default()
+        };
+        assert_eq!(Echo::test(None).replicas_handover(Some(&live)), None);
+
+        // the Echo starts autoscaling its running Deployment
+        let mut echo = echo_with_autoscaling(EchoAutoscaling {
+            max_replicas: 5,
+            ..EchoAutoscaling::default()
+        });
+        let handover = echo.replicas_handover(Some(&live)).unwrap();
+        assert_eq!(handover["kind"], "Deployment");
+        assert_eq!(handover["metadata"]["name"], "test");
+        assert_eq!(handover["spec"], serde_json::json!({"replicas": 3}));
+        assert_eq!(echo.replicas_handover(None), None);
+
+        // the HPA already owns the replicas
+        echo.status = Some(EchoStatus {
+            autoscaled: Some(true),
+            ..EchoStatus::default()
+        });
+        assert_eq!(echo.replicas_handover(Some(&live)), None);
+    }
+}
@@ -0,0 +1,140 @@
+//! Types of the Echo status conditions, also used by the EchoGateways and EchoRoutes, and helpers
+//! to read and update them without matching condition strings.
+use crate::crd::echo::EchoStatus;
+
+use std::fmt;
+
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Condition;
+
+/// Type of an Echo status condition
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ConditionType {
+    /// Every replica of the Deployment is updated and ready
+    Ready,
+    /// The Deployment is rolling out
+    Progressing,
+    /// Another field manager owns fields of the Deployment
+    Conflicted,
+    /// The DNS name of the echo is published
+    DnsReady,
+    /// Result of the last probe of the echo endpoint
+    EndpointHealthy,
+    /// An Ingress named after the echo, which it does not own, is kept instead of applied
+    IngressConflict,
+    /// Changes wait for the end of a maintenance window
+    MaintenanceSuppressed,
+    /// The namespace of the echo is not managed by the operator
+    NamespaceDenied,
+    /// The Deployment must be recreated to apply the spec
+    RecreateRequired,
+    /// The echo is registered in the registry of `spec.registration`
+    Registered,
+    /// The rollout of the spec missed its deadline and the last Ready spec was applied instead
+    RolledBack,
+    /// A Service named after the echo, which it does not own, is kept instead of applied
+    ServiceConflict,
+}
+
+impl ConditionType {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            ConditionType::Ready => "Ready",
+            ConditionType::Progressing => "Progressing",
+            ConditionType::Conflicted => "Conflicted",
+            ConditionType::DnsReady => "DNSReady",
+            ConditionType::EndpointHealthy => "EndpointHealthy",
+            ConditionType::IngressConflict => "IngressConflict",
+            ConditionType::MaintenanceSuppressed => "MaintenanceSuppressed",
+            ConditionType::NamespaceDenied => "NamespaceDenied",
+            ConditionType::RecreateRequired => "RecreateRequired",
+            ConditionType::Registered => "Registered",
+            ConditionType::RolledBack => "RolledBack",
+            ConditionType::ServiceConflict => "ServiceConflict",
+        }
+    }
+}
+
+impl fmt::Display for ConditionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl EchoStatus {
+    /// Condition of the given type, if any
+    pub fn condition(&self, type_: ConditionType) -> Option<&Condition> {
+        self.conditions
+            .iter()
+            .flatten()
+            .find(|c| c.type_ == type_.as_str())
+    }
+
+    /// Whether the Echo is Ready
+    pub fn is_ready(&self) -> bool {
+        self.condition(ConditionType::Ready)
+            .is_some_and(|c| c.status == "True")
+    }
+
+    /// Replace the condition of the same type, keeping the rest of the conditions
+    pub fn set_condition(&mut self, condition: Condition) {
+        let conditions = self.conditions.get_or_insert_with(Vec::new);
+        conditions.retain(|c| c.type_ != condition.type_);
+        conditions.push(condition);
+    }
+
+    /// Remove the condition of the given type, if any
+    pub fn remove_condition(&mut self, type_: ConditionType) {
+        if let Some(conditions) = self.conditions.as_mut() {
+            conditions.retain(|c| c.type_ != type_.as_str());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ConditionType;
+
+    use crate::crd::echo::EchoStatus;
+
+    use chrono::{TimeZone, Utc};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+
+    fn condition(type_: ConditionType, status: &str) -> Condition {
+        Condition {
+            type_: type_.to_string(),
+            status: status.to_string(),
+            reason: "".to_string(),
+            message: "".to_string(),
+            last_transition_time: Time(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()),
+            observed_generation: None,
+        }
+    }
+
+    #[test]
+    fn test_is_ready() {
+        let mut status = EchoStatus::default();
+        assert!(!status.is_ready());
+
+        status.set_condition(condition(ConditionType::Ready, "True"));
+        assert!(status.is_ready());
+
+        status.set_condition(condition(ConditionType::Ready, "False"));
+        assert!(!status.is_ready());
+        assert_eq!(status.conditions.as_ref().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_set_and_remove_condition() {
+        let mut status = EchoStatus::default();
+        status.set_condition(condition(ConditionType::DnsReady, "True"));
+        status.set_condition(condition(ConditionType::Conflicted, "True"));
+        assert_eq!(
+            status.condition(ConditionType::DnsReady).unwrap().type_,
+            "DNSReady"
+        );
+
+        status.remove_condition(ConditionType::DnsReady);
+        assert!(status.condition(ConditionType::DnsReady).is_none());
+        assert!(status.condition(ConditionType::Conflicted).is_some());
+    }
+}
@@ -0,0 +1,299 @@
+//! `spec.conflictPolicy`: how the Deployment apply resolves the conflicts with other field
+//! managers, and the `status.fieldConflicts` listing them.
+use crate::controller::Context;
+use crate::crd::echo::{Echo, EchoConflictPolicy, EchoStatusFieldConflicts};
+use crate::echo::condition::ConditionType;
+use crate::echo::timings::{self, Phase};
+use crate::error::{Error, ErrorContext, Operation, Result, ResultExt};
+
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::apps::v1::Deployment;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+use kube::api::{Api, Patch, PatchParams};
+use kube::ResourceExt;
+use serde_json::json;
+use tokio::time::Duration;
+use tracing::{debug, info};
+
+const FIELD_MANAGER: &str = "echoes.example.com";
+/// Applies of the Deployment, including the first one, with the `Retry` policy
+const CONFLICT_RETRY_ATTEMPTS: u32 = 3;
+const CONFLICT_RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+/// Field managers and the fields they own named in a conflict error message, sorted by manager,
+/// e.g. `Apply failed with 1 conflict: conflict with "kubectl" using apps/v1: .spec.replicas`, or
+/// `conflicts with "kubectl" using apps/v1:` followed by a `- <field>` line per field
+fn field_conflicts(message: &str) -> Vec<EchoStatusFieldConflicts> {
+    let mut conflicts: Vec<EchoStatusFieldConflicts> = Vec::new();
+    for group in message.split(" with \"").skip(1) {
+        let Some((manager, rest)) = group.split_once('"') else {
+            continue;
+        };
+        // the fields follow the `using <group version>:` of the manager
+        let fields = rest.split_once(':').map_or("", |(_, fields)| fields);
+        let fields = fields
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|field| field.starts_with('.'))
+            .map(str::to_owned);
+        match conflicts.iter_mut().find(|c| c.manager == manager) {
+            Some(conflict) => conflict.fields.extend(fields),
+            None => conflicts.push(EchoStatusFieldConflicts {
+                manager: manager.to_owned(),
+                fields: fields.collect(),
+            }),
+        }
+    }
+    for conflict in conflicts.iter_mut() {
+        conflict.fields.sort();
+        conflict.fields.dedup();
+    }
+    conflicts.sort_by(|a, b| a.manager.cmp(&b.manager));
+    conflicts
+}
+
+/// Field managers named in a conflict error message
+fn conflict_managers(message: &str) -> Vec<String> {
+    field_conflicts(message)
+        .into_iter()
+        .map(|c| c.manager)
+        .collect()
+}
+
+/// Field conflicts as comparable pairs, as the generated status types do not implement `PartialEq`
+fn conflict_pairs(conflicts: &[EchoStatusFieldConflicts]) -> Vec<(&str, &[String])> {
+    conflicts
+        .iter()
+        .map(|c| (c.manager.as_str(), c.fields.as_slice()))
+        .collect()
+}
+
+impl Echo {
+    fn conflict_policy(&self) -> EchoConflictPolicy {
+        self.spec
+            .conflict_policy
+            .clone()
+            .unwrap_or(EchoConflictPolicy::Force)
+    }
+
+    /// Whether a conflict is reported in the status instead of failing the reconciliation
+    pub(crate) fn report_conflicts(&self) -> bool {
+        matches!(self.conflict_policy(), EchoConflictPolicy::Report)
+    }
+
+    fn apply_params(&self) -> PatchParams {
+        match self.conflict_policy() {
+            EchoConflictPolicy::Force => PatchParams::apply(FIELD_MANAGER).force(),
+            EchoConflictPolicy::Retry | EchoConflictPolicy::Report => {
+                PatchParams::apply(FIELD_MANAGER)
+            }
+        }
+    }
+
+    /// Apply the Deployment, retrying the conflicts with force if the policy allows it, and return
+    /// it with the fields owned by other field managers that were taken over.
+    ///
+    /// The forced applies of the `Force` policy do not report conflicts, so with `detect_conflicts`
+    /// they are found with a dry-run apply without force first.
+    pub(crate) async fn apply_deployment(
+        &self,
+        api: &Api<Deployment>,
+        deployment: &Deployment,
+        detect_conflicts: bool,
+    ) -> kube::Result<(Deployment, Vec<EchoStatusFieldConflicts>)> {
+        let mut conflicts = Vec::new();
+        if detect_conflicts && matches!(self.conflict_policy(), EchoConflictPolicy::Force) {
+            let params = PatchParams::apply(FIELD_MANAGER).dry_run();
+            match api
+                .patch(&self.name_any(), &params, &Patch::Apply(deployment))
+                .await
+            {
+                Err(kube::Error::Api(ae)) if ae.code == 409 => {
+                    conflicts = field_conflicts(&ae.message);
+                    info!(
+                        msg = "forcing Deployment fields owned by other field managers",
+                        managers = ?conflict_managers(&ae.message)
+                    );
+                }
+                Err(e) => return Err(e),
+                Ok(_) => {}
+            }
+        }
+        let mut params = self.apply_params();
+        let mut attempt = 1;
+        loop {
+            match api
+                .patch(&self.name_any(), &params, &Patch::Apply(deployment))
+                .await
+            {
+                Err(kube::Error::Api(ae))
+                    if ae.code == 409
+                        && matches!(self.conflict_policy(), EchoConflictPolicy::Retry)
+                        && attempt < CONFLICT_RETRY_ATTEMPTS =>
+                {
+                    info!(
+                        msg = "retrying conflicting Deployment apply with force",
+                        attempt,
+                        managers = ?conflict_managers(&ae.message)
+                    );
+                    conflicts = field_conflicts(&ae.message);
+                    tokio::time::sleep(CONFLICT_RETRY_BACKOFF * attempt).await;
+                    params = PatchParams::apply(FIELD_MANAGER).force();
+                    attempt += 1;
+                }
+                result => return result.map(|deployment| (deployment, conflicts)),
+            }
+        }
+    }
+
+    /// Record the fields owned by other field managers in `status.fieldConflicts`, removing it
+    /// when there are none
+    pub(crate) async fn record_field_conflicts(
+        &self,
+        ctx: &Context,
+        conflicts: &[EchoStatusFieldConflicts],
+    ) -> Result<()> {
+        let current = self
+            .status
+            .as_ref()
+            .and_then(|s| s.field_conflicts.as_deref())
+            .unwrap_or_default();
+        // avoid patching the status, and so triggering a new reconciliation, when unchanged
+        if conflict_pairs(current) == conflict_pairs(conflicts) {
+            return Ok(());
+        }
+        let field_conflicts = (!conflicts.is_empty()).then_some(conflicts);
+        debug!(
+            msg = "patching Echo field conflicts",
+            managers = conflicts.len()
+        );
+        let echo_api = Api::<Echo>::namespaced(ctx.client.clone(), &self.get_namespace());
+        let patch = Patch::Merge(json!({"status": {"fieldConflicts": field_conflicts}}));
+        timings::measure(
+            Phase::Status,
+            echo_api.patch_status(&self.name_any(), &PatchParams::default(), &patch),
+        )
+        .await
+        .map_err(Error::KubeError)
+        .with_context(|| ErrorContext::of(Operation::StatusPatch, self))?;
+        Ok(())
+    }
+
+    /// Conflicted condition naming the field managers owning the applied fields
+    fn conflicted_condition(&self, message: &str, now: DateTime<Utc>) -> Condition {
+        let managers = conflict_managers(message);
+        Condition {
+            type_: ConditionType::Conflicted.to_string(),
+            status: "True".to_owned(),
+            reason: "FieldManagerConflict".to_owned(),
+            message: if managers.is_empty() {
+                message.to_owned()
+            } else {
+                format!("fields owned by {}: {message}", managers.join(", "))
+            },
+            last_transition_time: Time(now),
+            observed_generation: self.metadata.generation,
+        }
+    }
+
+    /// Report the conflict of the Deployment apply
+    pub(crate) async fn report_conflict(&self, ctx: &Context, message: &str) -> Result<()> {
+        self.record_field_conflicts(ctx, &field_conflicts(message))
+            .await?;
+        let condition = self.conflicted_condition(message, ctx.clock.now());
+        // avoid patching the status, and so triggering a new reconciliation, while it is reported
+        if self
+            .condition(ConditionType::Conflicted)
+            .is_some_and(|c| c.message == condition.message)
+        {
+            return Ok(());
+        }
+        self.patch_condition(ctx, ConditionType::Conflicted, Some(condition))
+            .await
+    }
+
+    /// Remove the Conflicted condition once the Deployment is applied
+    pub(crate) async fn clear_conflict(&self, ctx: &Context) -> Result<()> {
+        if self.condition(ConditionType::Conflicted).is_none() {
+            return Ok(());
+        }
+        self.patch_condition(ctx, ConditionType::Conflicted, None)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{conflict_managers, field_conflicts};
+
+    use crate::crd::echo::{Echo, EchoConflictPolicy};
+    use crate::echo::condition::ConditionType;
+    use crate::test_utils::test_time;
+
+    const MESSAGE: &str =
+        "Apply failed with 2 conflicts: conflict with \"kubectl\" using apps/v1: \
+        .spec.replicas, conflict with \"hpa\" using apps/v1: .spec.replicas";
+
+    fn echo_with_policy(policy: Option<EchoConflictPolicy>) -> Echo {
+        let mut echo = Echo::test(None);
+        echo.spec.conflict_policy = policy;
+        echo
+    }
+
+    #[test]
+    fn test_conflict_managers() {
+        assert_eq!(conflict_managers(MESSAGE), vec!["hpa", "kubectl"]);
+        assert!(conflict_managers("Operation cannot be fulfilled").is_empty());
+    }
+
+    #[test]
+    fn test_field_conflicts() {
+        let conflicts = field_conflicts(MESSAGE);
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(conflicts[0].manager, "hpa");
+        assert_eq!(conflicts[0].fields, vec![".spec.replicas"]);
+        assert_eq!(conflicts[1].manager, "kubectl");
+
+        let message = "Apply failed with 3 conflicts: conflicts with \"kubectl-edit\" using apps/v1:\n\
+            - .spec.replicas\n\
+            - .spec.template.spec.containers[name=\"echo\"].image\n\
+            conflict with \"kubectl\" with subresource \"scale\" using autoscaling/v1: .spec.replicas";
+        let conflicts = field_conflicts(message);
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(conflicts[0].manager, "kubectl");
+        assert_eq!(conflicts[0].fields, vec![".spec.replicas"]);
+        assert_eq!(conflicts[1].manager, "kubectl-edit");
+        assert_eq!(
+            conflicts[1].fields,
+            vec![
+                ".spec.replicas",
+                ".spec.template.spec.containers[name=\"echo\"].image"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_params() {
+        assert!(echo_with_policy(None).apply_params().force);
+        assert!(
+            !echo_with_policy(Some(EchoConflictPolicy::Retry))
+                .apply_params()
+                .force
+        );
+        assert!(
+            !echo_with_policy(Some(EchoConflictPolicy::Report))
+                .apply_params()
+                .force
+        );
+    }
+
+    #[test]
+    fn test_conflicted_condition() {
+        let condition = echo_with_policy(Some(EchoConflictPolicy::Report))
+            .conflicted_condition(MESSAGE, test_time());
+        assert_eq!(condition.type_, ConditionType::Conflicted.as_str());
+        assert_eq!(condition.reason, "FieldManagerConflict");
+        assert!(condition
+            .message
+            .starts_with("fields owned by hpa, kubectl: "));
+    }
+}
@@ -1,10 +1,18 @@
 use crate::controller::{Context, ControllerId, State};
 use crate::crd::echo::Echo;
+use crate::echo::predicates::{
+    deployment_spec_changes, echo_changes, filter_unchanged, filter_unchanged_objects,
+};
+use crate::echo::priority::{prioritize, SchedulingPolicy};
 use crate::echo::reconcile::reconcile_echo;
 use crate::error::Error;
+use crate::lazy_reflector;
 use crate::metrics;
+use crate::notify::Lifecycle;
+use crate::permissions::Permission;
+use crate::stores::{self, Stores};
+use crate::watchlist;
 
-use std::collections::HashMap;
 use std::sync::Arc;
 
 use futures::StreamExt;
@@ -19,18 +27,73 @@ use tracing::{debug, error, info};
 
 pub const CONTROLLER_ID: ControllerId = "echo";
 
+/// API calls of the echo reconciliation and the resources it creates
+pub const PERMISSIONS: &[Permission] = &[
+    Permission::new(
+        "example.com",
+        &["echoes"],
+        &["get", "list", "watch", "patch", "update"],
+    ),
+    Permission::new(
+        "example.com",
+        &["echoes/status"],
+        &["get", "patch", "update"],
+    ),
+    Permission::new("example.com", &["echoes/finalizers"], &["update"]),
+    Permission::new("example.com", &["echoquotas"], &["list"]),
+    Permission::new(
+        "apps",
+        &["deployments"],
+        &["create", "delete", "list", "patch", "update", "watch"],
+    ),
+    Permission::new(
+        "",
+        &["services"],
+        &["create", "delete", "get", "patch", "update"],
+    ),
+    Permission::new(
+        "networking.k8s.io",
+        &["ingresses"],
+        &["create", "delete", "get", "patch", "update"],
+    ),
+    Permission::new(
+        "autoscaling",
+        &["horizontalpodautoscalers"],
+        &["create", "delete", "get", "patch", "update"],
+    ),
+    Permission::new(
+        "",
+        &["secrets"],
+        &["create", "delete", "get", "list", "patch", "update"],
+    ),
+    Permission::new(
+        "",
+        &["serviceaccounts"],
+        &["create", "delete", "get", "patch", "update"],
+    ),
+    Permission::new(
+        "rbac.authorization.k8s.io",
+        &["roles", "rolebindings"],
+        &["create", "delete", "get", "patch", "update"],
+    ),
+    Permission::new(
+        "monitoring.coreos.com",
+        &["prometheusrules"],
+        &["create", "delete", "get", "patch", "update"],
+    ),
+    Permission::new("events.k8s.io", &["events"], &["create"]),
+];
+
 const SUBSCRIBE_BUFFER_SIZE: usize = 256;
 const RELOAD_BUFFER_SIZE: usize = 16;
 
-fn error_policy<K: ResourceExt>(
-    obj: Arc<K>,
-    error: &Error,
-    ctx: Arc<Context<Deployment>>,
-) -> Action {
+fn error_policy<K: ResourceExt>(obj: Arc<K>, error: &Error, ctx: Arc<Context>) -> Action {
     // safe unwrap: deployment is a namespace scoped resource
     error!(msg = "failed reconciliation", namespace = %obj.namespace().unwrap(), name = %obj.name_any(), %error);
     ctx.metrics.reconcile_failure_set(error);
-    Action::requeue(Duration::from_secs(5 * 60))
+    ctx.error_reporter
+        .capture_failure(CONTROLLER_ID, &*obj, error);
+    Action::requeue(ctx.error_requeue(&*obj))
 }
 
 /// Initialize echoes controller and shared state (given the crd is installed)
@@ -49,18 +112,67 @@ pub async fn run(state: State, client: Client) {
 
     let (reload_tx, reload_rx) = futures::channel::mpsc::channel(RELOAD_BUFFER_SIZE);
 
-    let deployment = Api::<Deployment>::all(client.clone());
-
-    let stores = HashMap::from([("deployment".to_string(), Box::new(deployment_store))]);
+    let stores = Stores::default().with(deployment_store.clone());
 
     let ctx = state.to_context(client, CONTROLLER_ID, stores);
+    let (echo_reader, echo_writer) = reflector::store();
+    let heartbeat = state.heartbeat();
+    heartbeat.watch(echo_reader.clone());
+    let streaming_lists = watchlist::enabled(&state, &ctx.client).await;
+    let notifier = ctx.notifier.clone();
+    let lifecycle_metrics = ctx.metrics.clone();
+    let echo_config = watchlist::config(
+        watcher::Config::default().any_semantic(),
+        streaming_lists,
+        state.watch_page_size(),
+    );
+    let (mut namespace_tracker, namespaces) =
+        lazy_reflector::namespaces(state.lazy_deployment_reflectors());
+    let ready_namespaces = namespaces.ready();
+    let echo_events = watcher(echo, echo_config)
+        .default_backoff()
+        .reflect(echo_writer)
+        .inspect(move |event| {
+            if let Ok(event) = event {
+                namespace_tracker.observe(event);
+            }
+        })
+        .inspect(move |event| {
+            if let Ok(watcher::Event::Delete(echo)) = event {
+                notifier.send_lifecycle(
+                    &echo.get_namespace(),
+                    &echo.name_any(),
+                    Lifecycle::Deleted,
+                    lifecycle_metrics.clone(),
+                );
+            }
+        });
+    let echoes = match state.scheduling_policy() {
+        SchedulingPolicy::Fifo => echo_events.applied_objects().boxed(),
+        SchedulingPolicy::Priority => prioritize(echo_events.boxed()).boxed(),
+    };
+    // status updates, e.g. the ones of this controller, do not trigger a reconciliation
+    let echo_controller = Controller::for_stream(
+        filter_unchanged_objects(
+            echoes,
+            echo_changes(state.ignore_metadata_changes()),
+            echo_reader.clone(),
+            ctx.metrics.clone(),
+        ),
+        echo_reader,
+    );
     // TODO: remove for each trigger on delete logic when
     // (dispatch delete events issue)[https://github.com/kube-rs/kube/issues/1590] is solved
-    let deployment_watch = watcher(
-        deployment.clone(),
-        watcher::Config::default().labels("app.kubernetes.io/managed-by=echo-operator"),
+    let deployment_watch = lazy_reflector::watcher(
+        ctx.client.clone(),
+        watchlist::config(
+            watcher::Config::default().labels("app.kubernetes.io/managed-by=echo-operator"),
+            streaming_lists,
+            state.watch_page_size(),
+        ),
+        namespaces,
     )
-    .default_backoff()
+    .modify(stores::prune)
     .reflect_shared(writer)
     .for_each(|res| {
         let mut reload_tx_clone = reload_tx.clone();
@@ -107,21 +219,199 @@ pub async fn run(state: State, client: Client) {
         }
     });
 
-    info!(msg = "starting echo controller");
-    // TODO: watcher::Config::default().streaming_lists() when stabilized in K8s
-    let echo_controller = Controller::new(echo, watcher::Config::default().any_semantic())
+    info!(
+        msg = "starting echo controller",
+        scheduling_policy = ?state.scheduling_policy(),
+        streaming_lists
+    );
+    let echo_controller = echo_controller
         // debounce to filter out reconcile calls that happen quick succession (only taking the latest)
         .with_config(controller::Config::default().debounce(Duration::from_millis(500)))
-        .owns_shared_stream(subscriber)
+        .owns_shared_stream(filter_unchanged(
+            subscriber,
+            deployment_spec_changes,
+            deployment_store,
+            ctx.metrics.clone(),
+        ))
         .reconcile_all_on(reload_rx.map(|_| ()))
+        .reconcile_all_on(ctx.reconcile_config.resyncs())
         .shutdown_on_signal()
-        .run(reconcile_echo, error_policy, ctx.clone())
-        .filter_map(|x| async move { std::result::Result::ok(x) })
-        .for_each(|_| futures::future::ready(()));
+        .run(
+            move |echo, ctx| {
+                let ready_namespaces = ready_namespaces.clone();
+                async move {
+                    // writer is only dropped on shutdown, when nothing else can be waited
+                    let _ignore_errors = ctx.wait_for_stores().await;
+                    // the Deployments of a namespace are listed once its first echo appears
+                    ready_namespaces.wait(&echo.get_namespace()).await;
+                    reconcile_echo(echo, ctx).await
+                }
+            },
+            error_policy,
+            ctx.clone(),
+        )
+        .for_each(|_| {
+            // failed reconciliations are progress too, they are requeued by the error policy
+            heartbeat.progress();
+            futures::future::ready(())
+        });
 
-    ctx.metrics.ready_set(1);
+    let ready = async {
+        if ctx.wait_for_stores().await.is_ok() {
+            ctx.metrics.ready_set(1);
+        }
+    };
     tokio::select! {
-        _ = echo_controller => {},
+        _ = futures::future::join(ready, echo_controller) => {},
         _ = deployment_watch => {}
     }
+    ctx.status_batcher.flush(&ctx.client, &ctx.metrics).await;
+}
+
+#[cfg(test)]
+mod test {
+    use super::{error_policy, run, CONTROLLER_ID};
+
+    use crate::controller::State;
+    use crate::crd::echo::Echo;
+    use crate::echostatus;
+    use crate::error::Error;
+    use crate::metrics::ErrorLabels;
+    use crate::test_utils::fake_apiserver::FakeApiServer;
+    use crate::test_utils::get_test_context;
+
+    use std::sync::Arc;
+
+    use futures::FutureExt;
+    use k8s_openapi::api::apps::v1::Deployment;
+    use kube::runtime::controller::Action;
+    use prometheus_client::registry::Registry;
+    use serde_json::json;
+    use tokio::time::Duration;
+
+    const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn spawn_controller(fake: &FakeApiServer) -> tokio::task::JoinHandle<()> {
+        let state = State::new(
+            Registry::default(),
+            &[CONTROLLER_ID, echostatus::controller::CONTROLLER_ID],
+        );
+        tokio::spawn(
+            futures::future::join(
+                run(state.clone(), fake.client()),
+                echostatus::controller::run(state, fake.client()),
+            )
+            .map(|_| ()),
+        )
+    }
+
+    fn set_deployment_ready(fake: &FakeApiServer, replicas: i32) {
+        fake.set_status::<Deployment>(
+            Some("default"),
+            "test",
+            json!({
+                "replicas": replicas,
+                "updatedReplicas": replicas,
+                "readyReplicas": replicas,
+            }),
+        );
+    }
+
+    async fn wait_for_ready(fake: &FakeApiServer) -> Echo {
+        fake.wait_for::<Echo, _>(
+            Some("default"),
+            "test",
+            |e| e.status.as_ref().is_some_and(|s| s.is_ready()),
+            WAIT_TIMEOUT,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn error_policy_requeues_and_counts_failure() {
+        let (testctx, _fakeserver) = get_test_context();
+        let error = Error::MissingObject("deployment");
+
+        let action = error_policy(Arc::new(Echo::test(None)), &error, testctx.clone());
+
+        assert_eq!(action, Action::requeue(Duration::from_secs(5 * 60)));
+        let failures = testctx
+            .metrics
+            .reconcile
+            .failures
+            .get_or_create(&ErrorLabels {
+                controller: String::new(),
+                error: error.metric_label(),
+            })
+            .get();
+        assert_eq!(failures, 1);
+    }
+
+    #[tokio::test]
+    async fn controller_creates_deployment_and_updates_status() {
+        let fake = FakeApiServer::default();
+        fake.create(&Echo::test(None));
+        let controller = spawn_controller(&fake);
+
+        let deployment = fake
+            .wait_for::<Deployment, _>(Some("default"), "test", |_| true, WAIT_TIMEOUT)
+            .await;
+        assert_eq!(deployment.spec.unwrap().replicas, Some(1));
+
+        set_deployment_ready(&fake, 1);
+        let echo = wait_for_ready(&fake).await;
+        assert_eq!(echo.status.unwrap().ready_replicas, Some(1));
+
+        controller.abort();
+    }
+
+    #[tokio::test]
+    async fn controller_recreates_deleted_deployment() {
+        let fake = FakeApiServer::default();
+        fake.create(&Echo::test(None));
+        let controller = spawn_controller(&fake);
+
+        let deployment = fake
+            .wait_for::<Deployment, _>(Some("default"), "test", |_| true, WAIT_TIMEOUT)
+            .await;
+        fake.delete::<Deployment>(Some("default"), "test");
+
+        let recreated = fake
+            .wait_for::<Deployment, _>(
+                Some("default"),
+                "test",
+                |d| d.metadata.uid != deployment.metadata.uid,
+                WAIT_TIMEOUT,
+            )
+            .await;
+        assert_eq!(recreated.spec.unwrap().replicas, Some(1));
+
+        controller.abort();
+    }
+
+    #[tokio::test]
+    async fn controller_keeps_reconciling_after_bookmarks() {
+        let fake = FakeApiServer::default();
+        fake.create(&Echo::test(None));
+        let controller = spawn_controller(&fake);
+
+        fake.wait_for::<Deployment, _>(Some("default"), "test", |_| true, WAIT_TIMEOUT)
+            .await;
+        fake.bookmark::<Echo>();
+        fake.bookmark::<Deployment>();
+
+        fake.create(&Echo::test(None).change_replicas(3));
+        let deployment = fake
+            .wait_for::<Deployment, _>(
+                Some("default"),
+                "test",
+                |d| d.spec.as_ref().and_then(|s| s.replicas) == Some(3),
+                WAIT_TIMEOUT,
+            )
+            .await;
+        assert_eq!(deployment.spec.unwrap().replicas, Some(3));
+        assert!(fake.watchers() >= 2);
+
+        controller.abort();
+    }
 }
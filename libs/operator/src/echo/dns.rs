@@ -0,0 +1,247 @@
+//! external-dns integration: the Service of the Echo, a LoadBalancer by default, annotated with
+//! the Echo DNS name.
+use crate::audit::AuditAction;
+use crate::controller::Context;
+use crate::crd::echo::Echo;
+use crate::echo::condition::ConditionType;
+use crate::error::{Error, Result};
+use crate::hook;
+
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::Service;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+use kube::api::{Api, DeleteParams, Patch, PatchParams, PropagationPolicy, Resource};
+use kube::client::Client;
+use kube::ResourceExt;
+use serde_json::json;
+use tracing::{debug, info};
+
+/// Finalizer keeping the Echo until its DNS Service, and so its records, are deleted
+pub(crate) const DNS_FINALIZER: &str = "echoes.example.com/dns";
+pub(crate) const HOSTNAME_ANNOTATION: &str = "external-dns.alpha.kubernetes.io/hostname";
+
+impl Echo {
+    fn has_dns_finalizer(&self) -> bool {
+        self.finalizers().iter().any(|f| f == DNS_FINALIZER)
+    }
+
+    /// Whether the Echo is being deleted and its DNS records must be cleaned up first
+    pub(crate) fn dns_cleanup_pending(&self) -> bool {
+        self.meta().deletion_timestamp.is_some() && self.has_dns_finalizer()
+    }
+
+    /// Add the DNS finalizer before the Service is annotated with the `dnsName`, or clean up the
+    /// DNS records when the Echo has no `dnsName` anymore
+    pub(crate) async fn reconcile_dns_finalizer(&self, ctx: &Context) -> Result<()> {
+        if self.spec.dns_name.is_none() {
+            if self.has_dns_finalizer() {
+                self.cleanup_dns(ctx).await?;
+            }
+            return Ok(());
+        }
+        if self.has_dns_finalizer() {
+            return Ok(());
+        }
+        let finalizers: Vec<String> = self
+            .finalizers()
+            .iter()
+            .cloned()
+            .chain([DNS_FINALIZER.to_owned()])
+            .collect();
+        self.patch_finalizers(ctx.client.clone(), finalizers).await
+    }
+
+    /// DNSReady condition of the applied Service, while the Echo has a `dnsName`
+    pub(crate) fn dns_ready(
+        &self,
+        service: Option<&Service>,
+        now: DateTime<Utc>,
+    ) -> Option<Condition> {
+        self.spec.dns_name.as_ref()?;
+        Some(self.dns_condition(service?, now))
+    }
+
+    /// Patch the DNSReady condition when it changed
+    pub(crate) async fn report_dns_condition(
+        &self,
+        ctx: &Context,
+        condition: Option<Condition>,
+    ) -> Result<()> {
+        let unchanged = match (self.condition(ConditionType::DnsReady), condition.as_ref()) {
+            (None, None) => true,
+            (Some(current), Some(new)) => {
+                current.status == new.status
+                    && current.reason == new.reason
+                    && current.observed_generation == new.observed_generation
+            }
+            _ => false,
+        };
+        if unchanged {
+            return Ok(());
+        }
+        self.patch_condition(ctx, ConditionType::DnsReady, condition)
+            .await
+    }
+
+    /// Delete the DNS Service, so external-dns removes its records, and release the finalizer. A
+    /// Service the Echo keeps with `service` or `ingress` only loses its annotation in the next
+    /// apply.
+    pub(crate) async fn cleanup_dns(&self, ctx: &Context) -> Result<()> {
+        info!(msg = "cleaning up DNS records");
+        let client = ctx.client.clone();
+        let keep_service = self.meta().deletion_timestamp.is_none() && self.service_enabled();
+        if !keep_service {
+            let service_api = Api::<Service>::namespaced(client.clone(), &self.get_namespace());
+            let delete_params = DeleteParams {
+                propagation_policy: Some(PropagationPolicy::Foreground),
+                ..DeleteParams::default()
+            };
+            hook::pre_delete(&ctx.hooks, self, "Service", &self.name_any()).await?;
+            match service_api.delete(&self.name_any(), &delete_params).await {
+                Ok(_) => {
+                    self.audit(
+                        ctx,
+                        AuditAction::Delete,
+                        "Service",
+                        "dnsName removed".into(),
+                    )
+                    .await
+                }
+                Err(kube::Error::Api(ae)) if ae.code == 404 => {}
+                Err(e) => return Err(Error::KubeError(e)),
+            }
+        }
+
+        let finalizers: Vec<String> = self
+            .finalizers()
+            .iter()
+            .filter(|f| *f != DNS_FINALIZER)
+            .cloned()
+            .collect();
+        self.patch_finalizers(client, finalizers).await
+    }
+
+    pub(crate) async fn patch_finalizers(
+        &self,
+        client: Client,
+        finalizers: Vec<String>,
+    ) -> Result<()> {
+        debug!(msg = "patching Echo finalizers", ?finalizers);
+        let echo_api = Api::<Echo>::namespaced(client, &self.get_namespace());
+        echo_api
+            .patch(
+                &self.name_any(),
+                &PatchParams::default(),
+                &Patch::Merge(json!({"metadata": {"finalizers": finalizers}})),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        Ok(())
+    }
+
+    /// DNSReady is True once the Service has a load balancer address external-dns can publish
+    fn dns_condition(&self, service: &Service, now: DateTime<Utc>) -> Condition {
+        let ready = service
+            .status
+            .as_ref()
+            .and_then(|s| s.load_balancer.as_ref())
+            .and_then(|lb| lb.ingress.as_ref())
+            .is_some_and(|ingress| !ingress.is_empty());
+        let (status, reason, message) = if ready {
+            (
+                "True",
+                "LoadBalancerReady",
+                "load balancer address published",
+            )
+        } else {
+            (
+                "False",
+                "LoadBalancerPending",
+                "waiting for a load balancer address",
+            )
+        };
+        // keep the transition time while the status does not change
+        let last_transition_time = self
+            .condition(ConditionType::DnsReady)
+            .filter(|c| c.status == status)
+            .map_or(Time(now), |c| c.last_transition_time.clone());
+
+        Condition {
+            type_: ConditionType::DnsReady.to_string(),
+            status: status.to_owned(),
+            reason: reason.to_owned(),
+            message: message.to_owned(),
+            last_transition_time,
+            observed_generation: self.metadata.generation,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crd::echo::{Echo, EchoStatus};
+    use crate::echo::condition::ConditionType;
+    use crate::test_utils::test_time;
+
+    use chrono::Duration;
+    use k8s_openapi::api::core::v1::{
+        LoadBalancerIngress, LoadBalancerStatus, Service, ServiceStatus,
+    };
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+
+    fn service_with_address() -> Service {
+        Service {
+            status: Some(ServiceStatus {
+                load_balancer: Some(LoadBalancerStatus {
+                    ingress: Some(vec![LoadBalancerIngress {
+                        ip: Some("10.0.0.1".to_string()),
+                        ..LoadBalancerIngress::default()
+                    }]),
+                }),
+                ..ServiceStatus::default()
+            }),
+            ..Service::default()
+        }
+    }
+
+    #[test]
+    fn test_dns_ready() {
+        let mut echo = Echo::test(None);
+        let service = service_with_address();
+        assert!(echo.dns_ready(Some(&service), test_time()).is_none());
+
+        echo.spec.dns_name = Some("echo.example.com".to_string());
+        assert!(echo.dns_ready(None, test_time()).is_none());
+        assert_eq!(
+            echo.dns_ready(Some(&service), test_time())
+                .map(|c| c.status)
+                .as_deref(),
+            Some("True")
+        );
+    }
+
+    #[test]
+    fn test_dns_condition_pending() {
+        let condition = Echo::test(None).dns_condition(&Service::default(), test_time());
+
+        assert_eq!(condition.type_, ConditionType::DnsReady.as_str());
+        assert_eq!(condition.status, "False");
+        assert_eq!(condition.reason, "LoadBalancerPending");
+    }
+
+    #[test]
+    fn test_dns_condition_ready_keeps_transition_time() {
+        let echo = Echo::test(None);
+        let ready = echo.dns_condition(&service_with_address(), test_time());
+        let echo = Echo::test(Some(EchoStatus {
+            conditions: Some(vec![ready.clone()]),
+            ..EchoStatus::default()
+        }));
+
+        let condition =
+            echo.dns_condition(&service_with_address(), test_time() + Duration::minutes(1));
+
+        assert_eq!(condition.status, "True");
+        assert_eq!(condition.last_transition_time, Time(test_time()));
+    }
+}
@@ -0,0 +1,105 @@
+//! EndpointHealthy condition of the echoes, reflecting the probes of their Service.
+use crate::controller::Context;
+use crate::crd::echo::Echo;
+use crate::echo::condition::ConditionType;
+use crate::error::Result;
+
+use chrono::{DateTime, Utc};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+
+/// Result of probing the Service of an echo
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum ProbeResult {
+    /// Successful response with the given status code
+    Healthy(u16),
+    /// Unsuccessful response status code, or request error
+    Unhealthy(String),
+}
+
+impl Echo {
+    fn endpoint_condition(&self, result: &ProbeResult, now: DateTime<Utc>) -> Condition {
+        let (status, reason, message) = match result {
+            ProbeResult::Healthy(code) => ("True", "ProbeSucceeded", format!("responded {code}")),
+            ProbeResult::Unhealthy(message) => ("False", "ProbeFailed", message.clone()),
+        };
+        // keep the transition time while the status does not change
+        let last_transition_time = self
+            .condition(ConditionType::EndpointHealthy)
+            .filter(|c| c.status == status)
+            .map_or(Time(now), |c| c.last_transition_time.clone());
+        Condition {
+            type_: ConditionType::EndpointHealthy.to_string(),
+            status: status.to_owned(),
+            reason: reason.to_owned(),
+            message,
+            last_transition_time,
+            observed_generation: self.metadata.generation,
+        }
+    }
+
+    /// Patch the EndpointHealthy condition when the probe result changed
+    pub(crate) async fn report_endpoint_health(
+        &self,
+        ctx: &Context,
+        result: &ProbeResult,
+    ) -> Result<()> {
+        let condition = self.endpoint_condition(result, ctx.clock.now());
+        if self
+            .condition(ConditionType::EndpointHealthy)
+            .is_some_and(|c| {
+                c.status == condition.status
+                    && c.message == condition.message
+                    && c.observed_generation == condition.observed_generation
+            })
+        {
+            return Ok(());
+        }
+        self.patch_condition(ctx, ConditionType::EndpointHealthy, Some(condition))
+            .await
+    }
+
+    /// Remove the EndpointHealthy condition of an echo without a Service to probe
+    pub(crate) async fn clear_endpoint_health(&self, ctx: &Context) -> Result<()> {
+        if self.condition(ConditionType::EndpointHealthy).is_none() {
+            return Ok(());
+        }
+        self.patch_condition(ctx, ConditionType::EndpointHealthy, None)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ProbeResult;
+
+    use crate::crd::echo::{Echo, EchoStatus};
+    use crate::echo::condition::ConditionType;
+    use crate::test_utils::test_time;
+
+    use chrono::Duration;
+
+    #[test]
+    fn test_endpoint_condition() {
+        let mut echo = Echo::test(None);
+        let healthy = echo.endpoint_condition(&ProbeResult::Healthy(200), test_time());
+        assert_eq!(healthy.type_, ConditionType::EndpointHealthy.as_str());
+        assert_eq!(healthy.status, "True");
+        assert_eq!(healthy.message, "responded 200");
+
+        echo.status = Some(EchoStatus {
+            conditions: Some(vec![healthy.clone()]),
+            ..EchoStatus::default()
+        });
+        let later = test_time() + Duration::minutes(1);
+        assert_eq!(
+            echo.endpoint_condition(&ProbeResult::Healthy(204), later)
+                .last_transition_time,
+            healthy.last_transition_time
+        );
+        let unhealthy =
+            echo.endpoint_condition(&ProbeResult::Unhealthy("responded 503".into()), later);
+        assert_eq!(unhealthy.status, "False");
+        assert_eq!(unhealthy.reason, "ProbeFailed");
+        assert_eq!(unhealthy.last_transition_time.0, later);
+    }
+}
@@ -0,0 +1,109 @@
+//! Failed reconciliations reported to the users of the echo, with the trace id, in a warning Event
+//! and the `lastError` of the status, so `kubectl describe` leads to the distributed trace.
+use crate::controller::Context;
+use crate::crd::echo::Echo;
+use crate::echo::timings::{self, Phase};
+use crate::error::{Error, ErrorContext, Operation, Result, ResultExt};
+
+use kube::api::{Api, Patch, PatchParams};
+use kube::runtime::events::{Event, EventType, Recorder, Reporter};
+use kube::{Resource, ResourceExt};
+use opentelemetry::trace::TraceId;
+use serde_json::json;
+use tracing::debug;
+
+const REASON: &str = "ReconcileFailed";
+
+/// Message of the failure, with the trace id when the reconciliation was traced
+fn failure_message(error: &Error, trace_id: &TraceId) -> String {
+    if *trace_id == TraceId::INVALID {
+        error.to_string()
+    } else {
+        format!("{error} (trace id {trace_id})")
+    }
+}
+
+impl Echo {
+    /// Record the failure in the `lastError` status and a warning Event
+    pub(crate) async fn report_failure(
+        &self,
+        ctx: &Context,
+        error: &Error,
+        trace_id: &TraceId,
+    ) -> Result<()> {
+        let message = failure_message(error, trace_id);
+        let last_error = json!({
+            "message": message,
+            "traceId": (*trace_id != TraceId::INVALID).then(|| trace_id.to_string()),
+            "time": ctx.clock.now(),
+        });
+        debug!(msg = "patching Echo last error", %message);
+        Api::<Echo>::namespaced(ctx.client.clone(), &self.get_namespace())
+            .patch_status(
+                &self.name_any(),
+                &PatchParams::default(),
+                &Patch::Merge(json!({"status": {"lastError": last_error}})),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        let reporter = Reporter {
+            controller: "echo-operator".to_owned(),
+            instance: None,
+        };
+        Recorder::new(ctx.client.clone(), reporter, self.object_ref(&()))
+            .publish(Event {
+                type_: EventType::Warning,
+                reason: REASON.to_owned(),
+                note: Some(message),
+                action: "Reconcile".to_owned(),
+                secondary: None,
+            })
+            .await
+            .map_err(Error::KubeError)
+    }
+
+    /// Remove the `lastError` of the status after a successful reconciliation
+    pub(crate) async fn clear_last_error(&self, ctx: &Context) -> Result<()> {
+        if self
+            .status
+            .as_ref()
+            .and_then(|s| s.last_error.as_ref())
+            .is_none()
+        {
+            return Ok(());
+        }
+        let echo_api = Api::<Echo>::namespaced(ctx.client.clone(), &self.get_namespace());
+        let patch = Patch::Merge(json!({"status": {"lastError": null}}));
+        timings::measure(
+            Phase::Status,
+            echo_api.patch_status(&self.name_any(), &PatchParams::default(), &patch),
+        )
+        .await
+        .map_err(Error::KubeError)
+        .with_context(|| ErrorContext::of(Operation::StatusPatch, self))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::failure_message;
+
+    use crate::error::Error;
+
+    use opentelemetry::trace::TraceId;
+
+    #[test]
+    fn test_failure_message() {
+        let error = Error::InvalidTraceId;
+        assert_eq!(
+            failure_message(&error, &TraceId::INVALID),
+            error.to_string()
+        );
+        let trace_id = TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap();
+        assert_eq!(
+            failure_message(&error, &trace_id),
+            format!("{error} (trace id 4bf92f3577b34da6a3ce929d0e0e4736)")
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Compatibility of the Echo status with GitOps health checks: the health ArgoCD assesses with the
+//! Lua health check documented in the README, evaluated on the serialized Echo as ArgoCD does.
+use crate::crd::echo::Echo;
+use crate::echo::condition::ConditionType;
+use crate::test_utils::test_time;
+
+use chrono::Duration;
+use k8s_openapi::api::apps::v1::DeploymentStatus;
+use serde_json::Value;
+
+/// ArgoCD health status of the Echo, as the README health check assesses it
+fn argocd_health(echo: &Echo) -> &'static str {
+    let obj = serde_json::to_value(echo).unwrap();
+    let generation = &obj["metadata"]["generation"];
+    let status = &obj["status"];
+    if status.is_null() || &status["observedGeneration"] != generation {
+        return "Progressing";
+    }
+    let ready = status["conditions"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|c| c["type"] == "Ready" && &c["observedGeneration"] == generation);
+    match ready.map(|c| &c["status"]) {
+        Some(Value::String(s)) if s == "True" => "Healthy",
+        _ => "Progressing",
+    }
+}
+
+fn deployment_status(replicas: i32, updated: i32, ready: i32, observed: i64) -> DeploymentStatus {
+    DeploymentStatus {
+        available_replicas: Some(ready),
+        ready_replicas: Some(ready),
+        replicas: Some(replicas),
+        updated_replicas: Some(updated),
+        observed_generation: Some(observed),
+        ..DeploymentStatus::default()
+    }
+}
+
+/// Echo at `generation`, with the status generated from the Deployment status
+fn reconciled(
+    mut echo: Echo,
+    generation: i64,
+    deployment_status: &DeploymentStatus,
+    deployment_generation: i64,
+) -> Echo {
+    echo.metadata.generation = Some(generation);
+    let status = echo.generate_status(deployment_status, Some(deployment_generation), test_time());
+    echo.with_status(status)
+}
+
+#[test]
+fn test_new_echo_is_progressing() {
+    let mut echo = Echo::test(None);
+    echo.metadata.generation = Some(1);
+    assert_eq!(argocd_health(&echo), "Progressing");
+}
+
+#[test]
+fn test_rollout_is_progressing_until_ready() {
+    let echo = reconciled(Echo::test(None), 1, &deployment_status(1, 0, 0, 1), 1);
+    assert_eq!(argocd_health(&echo), "Progressing");
+
+    let echo = reconciled(echo, 1, &deployment_status(1, 1, 1, 1), 1);
+    assert_eq!(argocd_health(&echo), "Healthy");
+}
+
+#[test]
+fn test_spec_change_is_progressing_until_observed() {
+    let echo = reconciled(Echo::test(None), 1, &deployment_status(1, 1, 1, 1), 1);
+    assert_eq!(argocd_health(&echo), "Healthy");
+
+    // the spec changed, and the status is the one of the previous generation
+    let mut changed = echo.clone();
+    changed.metadata.generation = Some(2);
+    assert_eq!(argocd_health(&changed), "Progressing");
+
+    // the Deployment was patched, but its controller did not observe it yet
+    let echo = reconciled(echo, 2, &deployment_status(1, 1, 1, 1), 2);
+    assert_eq!(argocd_health(&echo), "Progressing");
+
+    let echo = reconciled(echo, 2, &deployment_status(1, 1, 1, 2), 2);
+    assert_eq!(argocd_health(&echo), "Healthy");
+}
+
+#[test]
+fn test_scale_is_progressing_until_replicas_ready() {
+    let echo = reconciled(Echo::test(None), 1, &deployment_status(1, 1, 1, 1), 1);
+    let mut echo = echo.change_replicas(3);
+    echo.metadata.generation = Some(2);
+
+    let echo = reconciled(echo, 2, &deployment_status(3, 3, 1, 2), 2);
+    assert_eq!(argocd_health(&echo), "Progressing");
+
+    let echo = reconciled(echo, 2, &deployment_status(3, 3, 3, 2), 2);
+    assert_eq!(argocd_health(&echo), "Healthy");
+    // the scale subresource reads the replicas of the status
+    assert_eq!(echo.status.unwrap().replicas, Some(echo.spec.replicas));
+}
+
+#[test]
+fn test_ready_transition_time_is_kept() {
+    let mut echo = reconciled(Echo::test(None), 1, &deployment_status(1, 1, 1, 1), 1);
+    echo.metadata.generation = Some(2);
+    let status = echo.generate_status(
+        &deployment_status(1, 1, 1, 2),
+        Some(2),
+        test_time() + Duration::minutes(1),
+    );
+    let ready = status.condition(ConditionType::Ready);
+    assert_eq!(ready.unwrap().last_transition_time.0, test_time());
+    assert_eq!(ready.unwrap().observed_generation, Some(2));
+}
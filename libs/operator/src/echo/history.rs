@@ -0,0 +1,176 @@
+//! `status.history`: in-cluster change log of the specs applied to the Deployment of each echo,
+//! and the outcome of their rollouts.
+use crate::crd::echo::{Echo, EchoStatusHistory, EchoStatusHistoryOutcome};
+use crate::echo::condition::ConditionType;
+use crate::echo::provenance::{RECONCILED_AT_ANNOTATION, SPEC_HASH_ANNOTATION};
+
+use k8s_openapi::api::apps::v1::{Deployment, DeploymentStatus};
+use kube::ResourceExt;
+
+/// Entries kept in the history, the oldest being dropped first
+pub(crate) const HISTORY_LIMIT: usize = 10;
+
+/// Outcome of the rollout of the Deployment
+fn outcome(status: &DeploymentStatus) -> EchoStatusHistoryOutcome {
+    let deadline_exceeded = status.conditions.iter().flatten().any(|c| {
+        c.type_ == "Progressing" && c.reason.as_deref() == Some("ProgressDeadlineExceeded")
+    });
+    if Echo::determine_status_type(status) == ConditionType::Ready {
+        EchoStatusHistoryOutcome::Ready
+    } else if deadline_exceeded {
+        EchoStatusHistoryOutcome::Failed
+    } else {
+        EchoStatusHistoryOutcome::Progressing
+    }
+}
+
+impl Echo {
+    /// History with the spec of the Deployment, updating the outcome of its entry until the
+    /// rollout finishes
+    pub(crate) fn applied_history(
+        &self,
+        deployment: &Deployment,
+    ) -> Option<Vec<EchoStatusHistory>> {
+        let mut history = self
+            .status
+            .as_ref()
+            .and_then(|s| s.history.clone())
+            .unwrap_or_default();
+        let annotations = deployment.annotations();
+        let (Some(spec_hash), Some(applied_at), Some(status)) = (
+            annotations.get(SPEC_HASH_ANNOTATION),
+            annotations.get(RECONCILED_AT_ANNOTATION),
+            deployment.status.as_ref(),
+        ) else {
+            return Some(history).filter(|h| !h.is_empty());
+        };
+        let outcome = outcome(status);
+
+        match history.last_mut() {
+            Some(last) if last.spec_hash == *spec_hash && last.applied_at == *applied_at => {
+                // the outcome of a finished rollout is kept
+                if matches!(last.outcome, EchoStatusHistoryOutcome::Progressing) {
+                    last.outcome = outcome;
+                }
+            }
+            _ => history.push(EchoStatusHistory {
+                spec_hash: spec_hash.clone(),
+                applied_at: applied_at.clone(),
+                outcome,
+            }),
+        }
+        let excess = history.len().saturating_sub(HISTORY_LIMIT);
+        history.drain(..excess);
+        Some(history)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HISTORY_LIMIT;
+
+    use crate::crd::echo::{Echo, EchoStatus, EchoStatusHistory, EchoStatusHistoryOutcome};
+    use crate::echo::provenance::{RECONCILED_AT_ANNOTATION, SPEC_HASH_ANNOTATION};
+
+    use std::collections::BTreeMap;
+
+    use k8s_openapi::api::apps::v1::{Deployment, DeploymentCondition, DeploymentStatus};
+    use kube::api::ObjectMeta;
+
+    fn deployment(spec_hash: &str, ready_replicas: i32) -> Deployment {
+        Deployment {
+            metadata: ObjectMeta {
+                annotations: Some(BTreeMap::from([
+                    (SPEC_HASH_ANNOTATION.to_string(), spec_hash.to_string()),
+                    (
+                        RECONCILED_AT_ANNOTATION.to_string(),
+                        "2024-01-01T00:00:00Z".to_string(),
+                    ),
+                ])),
+                ..ObjectMeta::default()
+            },
+            status: Some(DeploymentStatus {
+                replicas: Some(1),
+                updated_replicas: Some(1),
+                ready_replicas: Some(ready_replicas),
+                ..DeploymentStatus::default()
+            }),
+            ..Deployment::default()
+        }
+    }
+
+    fn echo_with_history(history: Vec<EchoStatusHistory>) -> Echo {
+        Echo::test(Some(EchoStatus {
+            history: Some(history),
+            ..EchoStatus::default()
+        }))
+    }
+
+    #[test]
+    fn test_applied_history() {
+        assert!(Echo::test(None)
+            .applied_history(&Deployment::default())
+            .is_none());
+
+        let history = Echo::test(None)
+            .applied_history(&deployment("a", 0))
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].spec_hash, "a");
+        assert_eq!(history[0].applied_at, "2024-01-01T00:00:00Z");
+        assert!(matches!(
+            history[0].outcome,
+            EchoStatusHistoryOutcome::Progressing
+        ));
+
+        let history = echo_with_history(history)
+            .applied_history(&deployment("a", 1))
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        assert!(matches!(
+            history[0].outcome,
+            EchoStatusHistoryOutcome::Ready
+        ));
+
+        // a finished rollout keeps its outcome
+        let history = echo_with_history(history)
+            .applied_history(&deployment("a", 0))
+            .unwrap();
+        assert!(matches!(
+            history[0].outcome,
+            EchoStatusHistoryOutcome::Ready
+        ));
+
+        let mut failed = deployment("b", 0);
+        failed.status.as_mut().unwrap().conditions = Some(vec![DeploymentCondition {
+            type_: "Progressing".to_string(),
+            status: "False".to_string(),
+            reason: Some("ProgressDeadlineExceeded".to_string()),
+            ..DeploymentCondition::default()
+        }]);
+        let history = echo_with_history(history).applied_history(&failed).unwrap();
+        assert_eq!(history.len(), 2);
+        assert!(matches!(
+            history[1].outcome,
+            EchoStatusHistoryOutcome::Failed
+        ));
+    }
+
+    #[test]
+    fn test_applied_history_limit() {
+        let history = (0..HISTORY_LIMIT)
+            .map(|i| EchoStatusHistory {
+                spec_hash: i.to_string(),
+                applied_at: "2023-01-01T00:00:00Z".to_string(),
+                outcome: EchoStatusHistoryOutcome::Ready,
+            })
+            .collect();
+
+        let history = echo_with_history(history)
+            .applied_history(&deployment("new", 1))
+            .unwrap();
+        assert_eq!(history.len(), HISTORY_LIMIT);
+        assert_eq!(history[0].spec_hash, "1");
+        assert_eq!(history[HISTORY_LIMIT - 1].spec_hash, "new");
+    }
+}
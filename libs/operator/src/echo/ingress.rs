@@ -0,0 +1,296 @@
+//! `spec.ingress`: Ingress routing a host to the Service of the echo, see
+//! [`crate::echo::service`].
+//!
+//! The Ingress is named after the Echo and its host reported in `status.ingressHost`, so it is
+//! deleted once the section is removed from the spec. An Ingress with its name that the Echo does
+//! not own, e.g. of an EchoRoute, is reported instead of taken over, see [`crate::echo::ownership`].
+use crate::audit::{self, AuditAction};
+use crate::controller::Context;
+use crate::crd::echo::Echo;
+use crate::echo::condition::ConditionType;
+use crate::error::{Error, Result};
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::networking::v1::{
+    HTTPIngressPath, HTTPIngressRuleValue, Ingress, IngressBackend, IngressRule,
+    IngressServiceBackend, IngressSpec, IngressTLS, ServiceBackendPort,
+};
+use kube::api::{Api, DeleteParams, ObjectMeta, Patch, PatchParams};
+use kube::ResourceExt;
+use serde_json::json;
+use tracing::{debug, info};
+
+/// Path of the Ingress when the spec does not set one
+const INGRESS_PATH: &str = "/";
+
+impl Echo {
+    /// Apply the Ingress while the spec sets it, or delete the one reported in the status
+    pub(crate) async fn reconcile_ingress(&self, ctx: &Context) -> Result<()> {
+        let ingress_api = Api::<Ingress>::namespaced(ctx.client.clone(), &self.get_namespace());
+        let ingress_host = self.status.as_ref().and_then(|s| s.ingress_host.as_deref());
+        let Some(mut ingress) = self.ingress() else {
+            self.clear_not_owned(ctx, ConditionType::IngressConflict)
+                .await?;
+            if ingress_host.is_some() {
+                info!(msg = "deleting echo Ingress");
+                match ingress_api
+                    .delete(&self.name_any(), &DeleteParams::default())
+                    .await
+                {
+                    Ok(_) => {
+                        self.audit(ctx, AuditAction::Delete, "Ingress", "disabled".into())
+                            .await
+                    }
+                    Err(kube::Error::Api(ae)) if ae.code == 404 => {}
+                    Err(e) => return Err(Error::KubeError(e)),
+                }
+                self.patch_ingress_host(ctx, None).await?;
+            }
+            return Ok(());
+        };
+
+        ingress
+            .annotations_mut()
+            .extend(self.provenance_annotations(ctx)?);
+        let current = ingress_api
+            .get_opt(&self.name_any())
+            .await
+            .map_err(Error::KubeError)?;
+        // the forced apply would take over the Ingress of a user or an EchoRoute
+        if current.as_ref().is_some_and(|i| !self.owns(i)) {
+            self.report_not_owned(ctx, ConditionType::IngressConflict, "Ingress")
+                .await?;
+            // nor deleted once the section is removed
+            if ingress_host.is_some() {
+                self.patch_ingress_host(ctx, None).await?;
+            }
+            return Ok(());
+        }
+        let applied = ingress_api
+            .patch(
+                &self.name_any(),
+                &PatchParams::apply("echoes.example.com").force(),
+                &Patch::Apply(&ingress),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        let host = self.spec.ingress.as_ref().map(|i| i.host.as_str());
+        if audit::apply_changed(current.as_ref(), &applied) {
+            self.audit(
+                ctx,
+                AuditAction::Apply,
+                "Ingress",
+                host.unwrap_or_default().to_string(),
+            )
+            .await;
+        }
+        if host != ingress_host {
+            self.patch_ingress_host(ctx, host).await?;
+        }
+        self.clear_not_owned(ctx, ConditionType::IngressConflict)
+            .await
+    }
+
+    async fn patch_ingress_host(&self, ctx: &Context, host: Option<&str>) -> Result<()> {
+        debug!(msg = "patching Echo ingress host", ?host);
+        Api::<Echo>::namespaced(ctx.client.clone(), &self.get_namespace())
+            .patch_status(
+                &self.name_any(),
+                &PatchParams::default(),
+                &Patch::Merge(json!({"status": {"ingressHost": host}})),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        Ok(())
+    }
+
+    /// Ingress routing the host of the spec to the Service of the echo, if the spec sets one
+    pub(crate) fn ingress(&self) -> Option<Ingress> {
+        let spec = self.spec.ingress.as_ref()?;
+        let name = self.name_any();
+        Some(Ingress {
+            metadata: ObjectMeta {
+                name: Some(name.clone()),
+                namespace: Some(self.get_namespace()),
+                labels: Some(BTreeMap::from([
+                    ("app".to_string(), name.clone()),
+                    ("app.kubernetes.io/name".to_string(), "echo".to_string()),
+                    (
+                        "app.kubernetes.io/managed-by".to_string(),
+                        "echo-operator".to_string(),
+                    ),
+                ])),
+                annotations: spec.annotations.clone().filter(|a| !a.is_empty()),
+                owner_references: self.child_owner_references(),
+                ..ObjectMeta::default()
+            },
+            spec: Some(IngressSpec {
+                ingress_class_name: spec.ingress_class_name.clone(),
+                rules: Some(vec![IngressRule {
+                    host: Some(spec.host.clone()),
+                    http: Some(HTTPIngressRuleValue {
+                        paths: vec![HTTPIngressPath {
+                            path: Some(
+                                spec.path
+                                    .clone()
+                                    .unwrap_or_else(|| INGRESS_PATH.to_string()),
+                            ),
+                            path_type: "Prefix".to_string(),
+                            backend: IngressBackend {
+                                service: Some(IngressServiceBackend {
+                                    name,
+                                    port: Some(ServiceBackendPort {
+                                        number: Some(self.service_port()),
+                                        ..ServiceBackendPort::default()
+                                    }),
+                                }),
+                                ..IngressBackend::default()
+                            },
+                        }],
+                    }),
+                }]),
+                tls: spec.tls_secret_name.as_ref().map(|secret| {
+                    vec![IngressTLS {
+                        hosts: Some(vec![spec.host.clone()]),
+                        secret_name: Some(secret.clone()),
+                    }]
+                }),
+                ..IngressSpec::default()
+            }),
+            ..Ingress::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::controller::Context;
+    use crate::crd::echo::{Echo, EchoIngress};
+    use crate::echo::condition::ConditionType;
+    use crate::test_utils::fake_apiserver::FakeApiServer;
+    use crate::test_utils::get_test_context;
+
+    use std::collections::BTreeMap;
+
+    use k8s_openapi::api::networking::v1::Ingress;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+    use kube::api::ObjectMeta;
+
+    fn echo_with_ingress(ingress: EchoIngress) -> Echo {
+        let mut echo = Echo::test(None);
+        echo.spec.ingress = Some(ingress);
+        echo
+    }
+
+    #[test]
+    fn test_ingress_defaults() {
+        assert!(Echo::test(None).ingress().is_none());
+
+        let ingress = echo_with_ingress(EchoIngress {
+            host: "echo.example.com".to_string(),
+            ..EchoIngress::default()
+        })
+        .ingress()
+        .unwrap();
+        assert_eq!(ingress.metadata.annotations, None);
+        let spec = ingress.spec.unwrap();
+        assert_eq!(spec.ingress_class_name, None);
+        assert_eq!(spec.tls, None);
+        let rule = &spec.rules.unwrap()[0];
+        assert_eq!(rule.host.as_deref(), Some("echo.example.com"));
+        let path = &rule.http.as_ref().unwrap().paths[0];
+        assert_eq!(path.path.as_deref(), Some("/"));
+        assert_eq!(path.path_type, "Prefix");
+        let backend = path.backend.service.as_ref().unwrap();
+        assert_eq!(backend.name, "test");
+        assert_eq!(backend.port.as_ref().unwrap().number, Some(80));
+    }
+
+    #[test]
+    fn test_ingress() {
+        let ingress = echo_with_ingress(EchoIngress {
+            host: "echo.example.com".to_string(),
+            path: Some("/echo".to_string()),
+            tls_secret_name: Some("echo-tls".to_string()),
+            ingress_class_name: Some("nginx".to_string()),
+            annotations: Some(BTreeMap::from([(
+                "nginx.ingress.kubernetes.io/ssl-redirect".to_string(),
+                "true".to_string(),
+            )])),
+        })
+        .ingress()
+        .unwrap();
+        assert_eq!(
+            ingress
+                .metadata
+                .annotations
+                .unwrap()
+                .get("nginx.ingress.kubernetes.io/ssl-redirect")
+                .map(String::as_str),
+            Some("true")
+        );
+        let spec = ingress.spec.unwrap();
+        assert_eq!(spec.ingress_class_name.as_deref(), Some("nginx"));
+        let tls = &spec.tls.unwrap()[0];
+        assert_eq!(tls.secret_name.as_deref(), Some("echo-tls"));
+        assert_eq!(tls.hosts, Some(vec!["echo.example.com".to_string()]));
+        let rule = &spec.rules.unwrap()[0];
+        assert_eq!(
+            rule.http.as_ref().unwrap().paths[0].path.as_deref(),
+            Some("/echo")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_ingress_not_owned() {
+        let fake = FakeApiServer::default();
+        fake.create(&echo_with_ingress(EchoIngress {
+            host: "echo.example.com".to_string(),
+            ..EchoIngress::default()
+        }));
+        // e.g. the Ingress of an EchoRoute with the name of the echo
+        let route = OwnerReference {
+            api_version: "example.com/v1".to_string(),
+            kind: "EchoRoute".to_string(),
+            name: "test".to_string(),
+            uid: "route-uid".to_string(),
+            controller: Some(true),
+            ..OwnerReference::default()
+        };
+        fake.create(&Ingress {
+            metadata: ObjectMeta {
+                name: Some("test".to_string()),
+                namespace: Some("default".to_string()),
+                owner_references: Some(vec![route.clone()]),
+                ..ObjectMeta::default()
+            },
+            ..Ingress::default()
+        });
+        let (testctx, _fakeserver) = get_test_context();
+        let ctx = Context {
+            client: fake.client(),
+            ..(*testctx).clone()
+        };
+        let stored = || fake.get::<Echo>(Some("default"), "test").unwrap();
+
+        stored().reconcile_ingress(&ctx).await.unwrap();
+        let ingress = fake.get::<Ingress>(Some("default"), "test").unwrap();
+        assert_eq!(ingress.metadata.owner_references, Some(vec![route]));
+        assert!(ingress.spec.is_none());
+        let echo = stored();
+        assert!(echo.condition(ConditionType::IngressConflict).is_some());
+        assert_eq!(echo.status.as_ref().unwrap().ingress_host, None);
+
+        // applied once the Ingress of the route is deleted
+        fake.delete::<Ingress>(Some("default"), "test");
+        echo.reconcile_ingress(&ctx).await.unwrap();
+        let echo = stored();
+        assert!(echo.owns(&fake.get::<Ingress>(Some("default"), "test").unwrap()));
+        assert!(echo.condition(ConditionType::IngressConflict).is_none());
+        assert_eq!(
+            echo.status.unwrap().ingress_host.as_deref(),
+            Some("echo.example.com")
+        );
+    }
+}
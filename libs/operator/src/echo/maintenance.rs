@@ -0,0 +1,135 @@
+//! Maintenance windows of the echoes: the cluster-wide ones and the one of the
+//! `maintenance-window` annotation.
+use crate::controller::Context;
+use crate::crd::echo::Echo;
+use crate::echo::condition::ConditionType;
+use crate::error::Result;
+use crate::maintenance::{MaintenanceWindow, MAINTENANCE_WINDOW_ANNOTATION};
+
+use chrono::{DateTime, Utc};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+use kube::ResourceExt;
+
+impl Echo {
+    /// Maintenance window in progress, the annotated one first
+    pub(crate) fn maintenance_window(
+        &self,
+        ctx: &Context,
+        now: DateTime<Utc>,
+    ) -> Result<Option<MaintenanceWindow>> {
+        let annotated = self
+            .annotations()
+            .get(MAINTENANCE_WINDOW_ANNOTATION)
+            .map(|w| w.parse::<MaintenanceWindow>())
+            .transpose()?;
+        Ok(annotated
+            .filter(|w| w.contains(now))
+            .or_else(|| ctx.maintenance.active(now)))
+    }
+
+    fn maintenance_condition(&self, window: &MaintenanceWindow, now: DateTime<Utc>) -> Condition {
+        Condition {
+            type_: ConditionType::MaintenanceSuppressed.to_string(),
+            status: "True".to_owned(),
+            reason: "MaintenanceWindow".to_owned(),
+            message: format!("changes are not applied until the maintenance window {window} ends"),
+            last_transition_time: Time(now),
+            observed_generation: self.metadata.generation,
+        }
+    }
+
+    /// Report the reconciliation suppressed by the maintenance window
+    pub(crate) async fn report_maintenance(
+        &self,
+        ctx: &Context,
+        window: &MaintenanceWindow,
+    ) -> Result<()> {
+        let condition = self.maintenance_condition(window, ctx.clock.now());
+        // avoid patching the status, and so triggering a new reconciliation, while it is reported
+        if self
+            .condition(ConditionType::MaintenanceSuppressed)
+            .is_some_and(|c| c.message == condition.message)
+        {
+            return Ok(());
+        }
+        self.patch_condition(ctx, ConditionType::MaintenanceSuppressed, Some(condition))
+            .await
+    }
+
+    /// Remove the MaintenanceSuppressed condition once the window is over
+    pub(crate) async fn clear_maintenance(&self, ctx: &Context) -> Result<()> {
+        if self
+            .condition(ConditionType::MaintenanceSuppressed)
+            .is_none()
+        {
+            return Ok(());
+        }
+        self.patch_condition(ctx, ConditionType::MaintenanceSuppressed, None)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crd::echo::Echo;
+    use crate::echo::condition::ConditionType;
+    use crate::error::Error;
+    use crate::maintenance::{Maintenance, MaintenanceWindow, MAINTENANCE_WINDOW_ANNOTATION};
+    use crate::test_utils::{get_test_context, test_time};
+
+    use std::sync::Arc;
+
+    use chrono::Duration;
+    use kube::ResourceExt;
+
+    const WINDOW: &str = "2024-01-01T00:00:00Z/2024-01-01T02:00:00Z";
+
+    fn annotated_echo(window: &str) -> Echo {
+        let mut echo = Echo::test(None);
+        echo.annotations_mut().insert(
+            MAINTENANCE_WINDOW_ANNOTATION.to_string(),
+            window.to_string(),
+        );
+        echo
+    }
+
+    #[tokio::test]
+    async fn test_annotated_maintenance_window() {
+        let (ctx, _) = get_test_context();
+        let echo = annotated_echo(WINDOW);
+        let window: MaintenanceWindow = WINDOW.parse().unwrap();
+
+        assert_eq!(
+            echo.maintenance_window(&ctx, test_time()).unwrap(),
+            Some(window)
+        );
+        assert_eq!(echo.maintenance_window(&ctx, window.end).unwrap(), None);
+        assert!(matches!(
+            annotated_echo("tonight").maintenance_window(&ctx, test_time()),
+            Err(Error::InvalidMaintenanceWindow(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cluster_maintenance_window() {
+        let (ctx, _) = get_test_context();
+        let window = MaintenanceWindow {
+            start: test_time() - Duration::hours(1),
+            end: test_time() + Duration::hours(1),
+        };
+        let mut ctx = Arc::into_inner(ctx).unwrap();
+        ctx.maintenance = Arc::new(Maintenance::new(Some(window)));
+
+        let echo = Echo::test(None);
+        assert_eq!(
+            echo.maintenance_window(&ctx, test_time()).unwrap(),
+            Some(window)
+        );
+        let condition = echo.maintenance_condition(&window, test_time());
+        assert_eq!(
+            condition.type_,
+            ConditionType::MaintenanceSuppressed.as_str()
+        );
+        assert!(condition.message.contains(&window.to_string()));
+    }
+}
@@ -0,0 +1,169 @@
+//! Manifests of the resources the operator applies for an Echo, rendered without applying them,
+//! e.g. to diff them with the live resources before a change or to debug a reconciliation.
+use crate::clusterdefaults::ClusterDefaults;
+use crate::crd::echo::Echo;
+use crate::echo::reconcile::Defaults;
+use crate::error::{Error, Result};
+
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::apps::v1::Deployment;
+use kube::ResourceExt;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+fn to_value(resource: &impl Serialize) -> Result<Value> {
+    serde_json::to_value(resource).map_err(Error::SerializationError)
+}
+
+impl Echo {
+    /// Manifests the operator applies for the Echo at `now`, with the given cluster defaults: its
+    /// Deployment and, when enabled, its RBAC, Service, Ingress, HorizontalPodAutoscaler and
+    /// PrometheusRule.
+    ///
+    /// The live Deployment keeps the copies of the registry credentials, which are not rendered,
+    /// and the provenance of the last reconciliation. Changes of the reconcile hooks are not
+    /// included.
+    pub fn manifests(
+        &self,
+        live: Option<&Deployment>,
+        cluster: ClusterDefaults,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Value>> {
+        let annotations = self.provenance_annotations_with(live.map(|d| d.annotations()), now)?;
+        let defaults = Defaults {
+            replicas: Some(self.scheduled_replicas(now)?.replicas),
+            image_pull_secrets: live
+                .and_then(|d| d.spec.as_ref())
+                .and_then(|s| s.template.spec.as_ref())
+                .and_then(|s| s.image_pull_secrets.clone()),
+            annotations: annotations.clone(),
+            cluster,
+        };
+        let mut manifests = vec![to_value(&self.generate_deployment(&defaults)?)?];
+
+        if self.spec.create_service_account == Some(true) {
+            manifests.push(to_value(&self.service_account())?);
+            let rules = self.rbac_rules();
+            if !rules.is_empty() {
+                manifests.push(to_value(&self.role(rules))?);
+                manifests.push(to_value(&self.role_binding())?);
+            }
+        }
+        if self.service_enabled() {
+            manifests.push(to_value(&self.service())?);
+        }
+        if let Some(ingress) = self.ingress() {
+            manifests.push(to_value(&ingress)?);
+        }
+        if let Some(hpa) = self.horizontal_pod_autoscaler() {
+            manifests.push(to_value(&hpa)?);
+        }
+        let alerting = self
+            .spec
+            .monitoring
+            .as_ref()
+            .and_then(|m| m.alerting.as_ref());
+        if alerting.is_some_and(|a| a.enabled != Some(false)) {
+            manifests.push(self.prometheus_rule());
+        }
+
+        // the other children are applied with the provenance annotations of the Deployment
+        for manifest in manifests.iter_mut().skip(1) {
+            let metadata = &mut manifest["metadata"];
+            let mut child_annotations: serde_json::Map<String, Value> = metadata["annotations"]
+                .as_object()
+                .cloned()
+                .unwrap_or_default();
+            child_annotations.extend(annotations.iter().map(|(k, v)| (k.clone(), json!(v))));
+            metadata["annotations"] = Value::Object(child_annotations);
+        }
+        Ok(manifests)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::clusterdefaults::ClusterDefaults;
+    use crate::crd::echo::{Echo, EchoRbac, EchoRbacRules};
+    use crate::echo::provenance::RECONCILED_AT_ANNOTATION;
+    use crate::echo::reconcile::Defaults;
+    use crate::test_utils::test_time;
+
+    use chrono::Duration;
+    use k8s_openapi::api::core::v1::LocalObjectReference;
+    use kube::ResourceExt;
+
+    fn kinds(echo: &Echo) -> Vec<String> {
+        echo.manifests(None, ClusterDefaults::default(), test_time())
+            .unwrap()
+            .iter()
+            .map(|m| m["kind"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_manifests_children() {
+        let mut echo = Echo::test(None);
+        assert_eq!(kinds(&echo), vec!["Deployment"]);
+
+        echo.spec.create_service_account = Some(true);
+        echo.spec.dns_name = Some("echo.example.com".to_string());
+        assert_eq!(
+            kinds(&echo),
+            vec!["Deployment", "ServiceAccount", "Service"]
+        );
+
+        echo.spec.rbac = Some(EchoRbac {
+            rules: Some(vec![EchoRbacRules {
+                api_groups: Some(vec!["".to_string()]),
+                resources: Some(vec!["pods".to_string()]),
+                resource_names: None,
+                verbs: vec!["get".to_string()],
+            }]),
+        });
+        assert_eq!(
+            kinds(&echo),
+            vec![
+                "Deployment",
+                "ServiceAccount",
+                "Role",
+                "RoleBinding",
+                "Service"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_manifests_keep_live_values() {
+        let echo = Echo::test(None);
+        let live = echo
+            .generate_deployment(&Defaults {
+                image_pull_secrets: Some(vec![LocalObjectReference {
+                    name: "test-registry".to_string(),
+                }]),
+                annotations: echo.provenance_annotations_with(None, test_time()).unwrap(),
+                ..Defaults::default()
+            })
+            .unwrap();
+
+        let manifests = echo
+            .manifests(
+                Some(&live),
+                ClusterDefaults::default(),
+                test_time() + Duration::hours(1),
+            )
+            .unwrap();
+        let deployment = &manifests[0];
+        assert_eq!(
+            deployment["spec"]["template"]["spec"]["imagePullSecrets"][0]["name"],
+            "test-registry"
+        );
+        let reconciled_at = &deployment["metadata"]["annotations"][RECONCILED_AT_ANNOTATION];
+        assert_eq!(
+            reconciled_at.as_str(),
+            live.annotations()
+                .get(RECONCILED_AT_ANNOTATION)
+                .map(String::as_str)
+        );
+    }
+}
@@ -0,0 +1,291 @@
+//! Baseline alerting for every Echo with a Prometheus Operator PrometheusRule.
+use crate::audit::AuditAction;
+use crate::controller::Context;
+use crate::crd::echo::Echo;
+use crate::error::{Error, Result};
+use crate::owned;
+
+use std::collections::BTreeMap;
+
+use kube::api::{
+    Api, ApiResource, DynamicObject, GroupVersionKind, ObjectMeta, Patch, PatchParams,
+};
+use kube::ResourceExt;
+use serde_json::{json, Value};
+use tracing::{debug, info};
+
+const DEFAULT_PENDING_FOR: &str = "5m";
+const DEFAULT_SEVERITY: &str = "warning";
+
+/// PrometheusRule, which is not part of the core Kubernetes API
+fn prometheus_rule_resource() -> ApiResource {
+    ApiResource::from_gvk(&GroupVersionKind::gvk(
+        "monitoring.coreos.com",
+        "v1",
+        "PrometheusRule",
+    ))
+}
+
+impl Echo {
+    fn alerting_recorded(&self) -> bool {
+        self.status
+            .as_ref()
+            .and_then(|s| s.alerting)
+            .unwrap_or(false)
+    }
+
+    /// Apply the PrometheusRule when alerting is enabled, or delete the one recorded in the status
+    /// once alerting is disabled or removed from the spec
+    pub(crate) async fn reconcile_alerting(&self, ctx: &Context) -> Result<()> {
+        let rule_api = Api::<DynamicObject>::namespaced_with(
+            ctx.client.clone(),
+            &self.get_namespace(),
+            &prometheus_rule_resource(),
+        );
+        let recorded = self.alerting_recorded();
+        let enabled = self
+            .spec
+            .monitoring
+            .as_ref()
+            .and_then(|m| m.alerting.as_ref())
+            .is_some_and(|a| a.enabled != Some(false));
+        if !enabled {
+            if recorded {
+                info!(msg = "deleting echo PrometheusRule");
+                // a rule created with the same name by a user is kept
+                if owned::delete_controlled(&rule_api, &self.name_any(), self).await? {
+                    self.audit(
+                        ctx,
+                        AuditAction::Delete,
+                        "PrometheusRule",
+                        "alerting disabled".into(),
+                    )
+                    .await;
+                }
+                self.patch_alerting(ctx, false).await?;
+            }
+            return Ok(());
+        }
+
+        let mut rule = self.prometheus_rule();
+        rule["metadata"]["annotations"] = json!(self.provenance_annotations(ctx)?);
+        rule_api
+            .patch(
+                &self.name_any(),
+                &PatchParams::apply("echoes.example.com").force(),
+                &Patch::Apply(&rule),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        if !recorded {
+            self.patch_alerting(ctx, true).await?;
+        }
+        Ok(())
+    }
+
+    async fn patch_alerting(&self, ctx: &Context, alerting: bool) -> Result<()> {
+        debug!(msg = "patching Echo alerting", alerting);
+        // a null removes the field instead of reporting false
+        let alerting = alerting.then_some(true);
+        Api::<Echo>::namespaced(ctx.client.clone(), &self.get_namespace())
+            .patch_status(
+                &self.name_any(),
+                &PatchParams::default(),
+                &Patch::Merge(json!({"status": {"alerting": alerting}})),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        Ok(())
+    }
+
+    /// PrometheusRule with the standard alerts of the Echo, based on kube-state-metrics
+    pub(crate) fn prometheus_rule(&self) -> Value {
+        let name = self.name_any();
+        let namespace = self.get_namespace();
+        let alerting = self
+            .spec
+            .monitoring
+            .as_ref()
+            .and_then(|m| m.alerting.as_ref());
+        let pending_for = alerting
+            .and_then(|a| a.pending_for.clone())
+            .unwrap_or_else(|| DEFAULT_PENDING_FOR.to_owned());
+        let labels: BTreeMap<String, String> = alerting
+            .and_then(|a| a.labels.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .chain([(
+                "severity".to_owned(),
+                alerting
+                    .and_then(|a| a.severity.clone())
+                    .unwrap_or_else(|| DEFAULT_SEVERITY.to_owned()),
+            )])
+            .collect();
+
+        let selector = format!(r#"namespace="{namespace}",deployment="{name}""#);
+        let alert = |alert: &str, expr: String, summary: String| {
+            json!({
+                "alert": alert,
+                "expr": expr,
+                "for": pending_for,
+                "labels": labels,
+                "annotations": {"summary": summary},
+            })
+        };
+
+        json!({
+            "apiVersion": "monitoring.coreos.com/v1",
+            "kind": "PrometheusRule",
+            "metadata": ObjectMeta {
+                name: Some(name.clone()),
+                namespace: Some(namespace.clone()),
+                labels: Some(BTreeMap::from([
+                    ("app".to_owned(), name.clone()),
+                    ("app.kubernetes.io/name".to_owned(), "echo".to_owned()),
+                    (
+                        "app.kubernetes.io/managed-by".to_owned(),
+                        "echo-operator".to_owned(),
+                    ),
+                ])),
+                owner_references: self.child_owner_references(),
+                ..ObjectMeta::default()
+            },
+            "spec": {
+                "groups": [{
+                    "name": format!("echo-{namespace}-{name}"),
+                    "rules": [
+                        alert(
+                            "EchoNotReady",
+                            format!(
+                                "kube_deployment_status_replicas_available{{{selector}}} == 0 \
+                                 and kube_deployment_spec_replicas{{{selector}}} > 0"
+                            ),
+                            format!("Echo {namespace}/{name} has no available replicas"),
+                        ),
+                        alert(
+                            "EchoReplicasMismatch",
+                            format!(
+                                "kube_deployment_spec_replicas{{{selector}}} \
+                                 != kube_deployment_status_replicas_available{{{selector}}}"
+                            ),
+                            format!("Echo {namespace}/{name} has not the expected replicas"),
+                        ),
+                    ],
+                }],
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::prometheus_rule_resource;
+
+    use crate::controller::Context;
+    use crate::crd::echo::{Echo, EchoMonitoring, EchoMonitoringAlerting};
+    use crate::test_utils::fake_apiserver::FakeApiServer;
+    use crate::test_utils::get_test_context;
+
+    use std::collections::BTreeMap;
+
+    use kube::api::{Api, DynamicObject, PostParams};
+    use serde_json::json;
+
+    fn echo_with_alerting(alerting: EchoMonitoringAlerting) -> Echo {
+        let mut echo = Echo::test(None);
+        echo.spec.monitoring = Some(EchoMonitoring {
+            alerting: Some(alerting),
+        });
+        echo
+    }
+
+    #[test]
+    fn test_prometheus_rule_defaults() {
+        let rule = echo_with_alerting(EchoMonitoringAlerting::default()).prometheus_rule();
+
+        assert_eq!(rule["kind"], "PrometheusRule");
+        let rules = rule["spec"]["groups"][0]["rules"].as_array().unwrap();
+        assert_eq!(rules[0]["alert"], "EchoNotReady");
+        assert_eq!(rules[1]["alert"], "EchoReplicasMismatch");
+        assert_eq!(rules[0]["for"], "5m");
+        assert_eq!(rules[0]["labels"]["severity"], "warning");
+        assert_eq!(
+            rules[0]["expr"],
+            "kube_deployment_status_replicas_available{namespace=\"default\",deployment=\"test\"} == 0 \
+             and kube_deployment_spec_replicas{namespace=\"default\",deployment=\"test\"} > 0"
+        );
+    }
+
+    #[test]
+    fn test_prometheus_rule_custom() {
+        let rule = echo_with_alerting(EchoMonitoringAlerting {
+            enabled: Some(true),
+            pending_for: Some("15m".to_string()),
+            severity: Some("critical".to_string()),
+            labels: Some(BTreeMap::from([(
+                "team".to_string(),
+                "platform".to_string(),
+            )])),
+        })
+        .prometheus_rule();
+
+        let labels = &rule["spec"]["groups"][0]["rules"][1]["labels"];
+        assert_eq!(rule["spec"]["groups"][0]["rules"][1]["for"], "15m");
+        assert_eq!(labels["severity"], "critical");
+        assert_eq!(labels["team"], "platform");
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_alerting() {
+        let fake = FakeApiServer::default();
+        fake.create(&echo_with_alerting(EchoMonitoringAlerting::default()));
+        let (testctx, _fakeserver) = get_test_context();
+        let ctx = Context {
+            client: fake.client(),
+            ..(*testctx).clone()
+        };
+        let rule_api = Api::<DynamicObject>::namespaced_with(
+            fake.client(),
+            "default",
+            &prometheus_rule_resource(),
+        );
+        let stored = || fake.get::<Echo>(Some("default"), "test").unwrap();
+
+        stored().reconcile_alerting(&ctx).await.unwrap();
+        assert!(rule_api.get_opt("test").await.unwrap().is_some());
+        assert!(stored().alerting_recorded());
+
+        // removing the section deletes the rule
+        let mut echo = stored();
+        echo.spec.monitoring = None;
+        echo.reconcile_alerting(&ctx).await.unwrap();
+        assert!(rule_api.get_opt("test").await.unwrap().is_none());
+        assert!(!stored().alerting_recorded());
+
+        // a rule created by a user is kept
+        let user_rule: DynamicObject = serde_json::from_value(json!({
+            "apiVersion": "monitoring.coreos.com/v1",
+            "kind": "PrometheusRule",
+            "metadata": {"name": "test", "namespace": "default"},
+        }))
+        .unwrap();
+        rule_api
+            .create(&PostParams::default(), &user_rule)
+            .await
+            .unwrap();
+        // not recorded, so not deleted
+        echo.reconcile_alerting(&ctx).await.unwrap();
+        assert!(rule_api.get_opt("test").await.unwrap().is_some());
+        fake.set_status::<Echo>(Some("default"), "test", json!({"alerting": true}));
+        let mut echo = stored();
+        echo.spec.monitoring = Some(EchoMonitoring {
+            alerting: Some(EchoMonitoringAlerting {
+                enabled: Some(false),
+                ..EchoMonitoringAlerting::default()
+            }),
+        });
+        echo.reconcile_alerting(&ctx).await.unwrap();
+        assert!(rule_api.get_opt("test").await.unwrap().is_some());
+        assert!(!stored().alerting_recorded());
+    }
+}
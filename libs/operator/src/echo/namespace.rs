@@ -0,0 +1,102 @@
+//! Echoes of the namespaces the operator must not manage, which are reported instead of
+//! reconciled.
+use crate::controller::Context;
+use crate::crd::echo::Echo;
+use crate::echo::condition::ConditionType;
+use crate::error::{Error, Result};
+
+use chrono::{DateTime, Utc};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+use kube::runtime::events::{Event, EventType, Recorder, Reporter};
+use kube::Resource;
+
+const REASON: &str = "NamespaceDenied";
+
+impl Echo {
+    /// Whether the operator manages the namespace of the echo
+    pub(crate) fn namespace_allowed(&self, ctx: &Context) -> bool {
+        ctx.namespace_filter.allows(&self.get_namespace())
+    }
+
+    fn namespace_denied_message(&self) -> String {
+        format!(
+            "namespace {} is not managed by the operator",
+            self.get_namespace()
+        )
+    }
+
+    fn namespace_denied_condition(&self, now: DateTime<Utc>) -> Condition {
+        Condition {
+            type_: ConditionType::NamespaceDenied.to_string(),
+            status: "True".to_owned(),
+            reason: REASON.to_owned(),
+            message: self.namespace_denied_message(),
+            last_transition_time: Time(now),
+            observed_generation: self.metadata.generation,
+        }
+    }
+
+    /// Report the echo as not managed, with a condition and a warning Event, once
+    pub(crate) async fn report_namespace_denied(&self, ctx: &Context) -> Result<()> {
+        if self.condition(ConditionType::NamespaceDenied).is_some() {
+            return Ok(());
+        }
+        self.patch_condition(
+            ctx,
+            ConditionType::NamespaceDenied,
+            Some(self.namespace_denied_condition(ctx.clock.now())),
+        )
+        .await?;
+        let reporter = Reporter {
+            controller: "echo-operator".to_owned(),
+            instance: None,
+        };
+        Recorder::new(ctx.client.clone(), reporter, self.object_ref(&()))
+            .publish(Event {
+                type_: EventType::Warning,
+                reason: REASON.to_owned(),
+                note: Some(self.namespace_denied_message()),
+                action: "Reconcile".to_owned(),
+                secondary: None,
+            })
+            .await
+            .map_err(Error::KubeError)
+    }
+
+    /// Remove the NamespaceDenied condition once the namespace is managed again
+    pub(crate) async fn clear_namespace_denied(&self, ctx: &Context) -> Result<()> {
+        if self.condition(ConditionType::NamespaceDenied).is_none() {
+            return Ok(());
+        }
+        self.patch_condition(ctx, ConditionType::NamespaceDenied, None)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crd::echo::Echo;
+    use crate::echo::condition::ConditionType;
+    use crate::namespace_filter::NamespaceFilter;
+    use crate::test_utils::{get_test_context, test_time};
+
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_namespace_allowed() {
+        let (ctx, _) = get_test_context();
+        let echo = Echo::test(None);
+        assert!(echo.namespace_allowed(&ctx));
+
+        let mut ctx = Arc::into_inner(ctx).unwrap();
+        ctx.namespace_filter = Arc::new(NamespaceFilter::new(vec![], vec!["default".to_string()]));
+        assert!(!echo.namespace_allowed(&ctx));
+
+        let condition = echo.namespace_denied_condition(test_time());
+        assert_eq!(condition.type_, ConditionType::NamespaceDenied.as_str());
+        assert_eq!(
+            condition.message,
+            "namespace default is not managed by the operator"
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! `spec.ownership`: owner references of the resources of the echo.
+//!
+//! Restoring an Echo from a backup creates it again with another uid, so its restored Deployment
+//! still references the previous Echo, which Kubernetes garbage collects as it does not exist
+//! anymore, and the controller reference of the new one is rejected as a second controller.
+//! `adoptOrphans` replaces the references to the previous Echo before the apply.
+//!
+//! A Service or Ingress named after the Echo that it does not own, e.g. created by a user or for an
+//! EchoGateway, is reported with a condition instead of taken over by the forced apply.
+use crate::audit::AuditAction;
+use crate::controller::Context;
+use crate::crd::echo::Echo;
+use crate::echo::condition::ConditionType;
+use crate::error::{Error, Result};
+
+use k8s_openapi::api::apps::v1::Deployment;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, OwnerReference, Time};
+use kube::api::{Api, Patch, PatchParams, Resource};
+use kube::runtime::events::{Event, EventType, Recorder, Reporter};
+use kube::ResourceExt;
+use serde_json::json;
+use tracing::{info, warn};
+
+const NOT_OWNED_REASON: &str = "NotOwned";
+
+impl Echo {
+    /// Reference to the Echo from its resources, a controller one blocking its deletion by
+    /// default
+    pub(crate) fn owner_reference(&self) -> Option<OwnerReference> {
+        let ownership = self.spec.ownership.as_ref();
+        let mut owner_reference = self.controller_owner_ref(&())?;
+        owner_reference.controller = Some(ownership.and_then(|o| o.controller).unwrap_or(true));
+        owner_reference.block_owner_deletion = Some(
+            ownership
+                .and_then(|o| o.block_owner_deletion)
+                .unwrap_or(true),
+        );
+        Some(owner_reference)
+    }
+
+    /// Owner references of the resources of the Echo
+    pub(crate) fn child_owner_references(&self) -> Option<Vec<OwnerReference>> {
+        self.owner_reference().map(|oref| vec![oref])
+    }
+
+    /// Whether the resource references the Echo as owner, with a controller reference or not
+    /// depending on `spec.ownership`, so it was created for the Echo
+    pub(crate) fn owns<K: Resource>(&self, resource: &K) -> bool {
+        resource
+            .meta()
+            .owner_references
+            .iter()
+            .flatten()
+            .any(|r| Some(&r.uid) == self.meta().uid.as_ref())
+    }
+
+    fn not_owned_message(&self, kind: &str) -> String {
+        format!(
+            "{kind} {} exists and is not owned by the echo",
+            self.name_any()
+        )
+    }
+
+    /// Report the object named after the Echo that it does not own, with a condition and a
+    /// warning Event, once
+    pub(crate) async fn report_not_owned(
+        &self,
+        ctx: &Context,
+        type_: ConditionType,
+        kind: &str,
+    ) -> Result<()> {
+        warn!(
+            msg = "skipping apply of an object not owned by the echo",
+            kind
+        );
+        if self.condition(type_).is_some() {
+            return Ok(());
+        }
+        let condition = Condition {
+            type_: type_.to_string(),
+            status: "True".to_owned(),
+            reason: NOT_OWNED_REASON.to_owned(),
+            message: self.not_owned_message(kind),
+            last_transition_time: Time(ctx.clock.now()),
+            observed_generation: self.metadata.generation,
+        };
+        self.patch_condition(ctx, type_, Some(condition)).await?;
+        let reporter = Reporter {
+            controller: "echo-operator".to_owned(),
+            instance: None,
+        };
+        Recorder::new(ctx.client.clone(), reporter, self.object_ref(&()))
+            .publish(Event {
+                type_: EventType::Warning,
+                reason: NOT_OWNED_REASON.to_owned(),
+                note: Some(self.not_owned_message(kind)),
+                action: "Reconcile".to_owned(),
+                secondary: None,
+            })
+            .await
+            .map_err(Error::KubeError)
+    }
+
+    /// Remove the condition reporting an object not owned by the Echo, once it is applied or not
+    /// needed anymore
+    pub(crate) async fn clear_not_owned(&self, ctx: &Context, type_: ConditionType) -> Result<()> {
+        if self.condition(type_).is_none() {
+            return Ok(());
+        }
+        self.patch_condition(ctx, type_, None).await
+    }
+
+    fn adopt_orphans(&self) -> bool {
+        self.spec
+            .ownership
+            .as_ref()
+            .and_then(|o| o.adopt_orphans)
+            .unwrap_or(false)
+    }
+
+    /// Whether the reference is to a previous Echo with the same name, e.g. before a restore
+    fn is_previous_owner(&self, owner_reference: &OwnerReference) -> bool {
+        owner_reference.kind == Echo::kind(&())
+            && owner_reference.api_version == Echo::api_version(&())
+            && owner_reference.name == self.name_any()
+            && Some(&owner_reference.uid) != self.meta().uid.as_ref()
+    }
+
+    /// Owner references of the Deployment without the ones to previous Echoes, if it has any
+    fn adopted_owner_references(&self, deployment: &Deployment) -> Option<Vec<OwnerReference>> {
+        let owner_references = deployment.owner_references();
+        if !owner_references.iter().any(|r| self.is_previous_owner(r)) {
+            return None;
+        }
+        Some(
+            owner_references
+                .iter()
+                .filter(|r| !self.is_previous_owner(r))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Remove the references to previous Echoes from the current Deployment, when the Echo adopts
+    /// its orphans, so the apply sets its own
+    pub(crate) async fn adopt_deployment(
+        &self,
+        ctx: &Context,
+        current: Option<&Deployment>,
+    ) -> Result<()> {
+        if !self.adopt_orphans() {
+            return Ok(());
+        }
+        let Some(owner_references) = current.and_then(|d| self.adopted_owner_references(d)) else {
+            return Ok(());
+        };
+        info!(msg = "adopting Deployment of a previous Echo");
+        let patch = json!({"metadata": {"ownerReferences": owner_references}});
+        Api::<Deployment>::namespaced(ctx.client.clone(), &self.get_namespace())
+            .patch(
+                &self.name_any(),
+                &PatchParams::default(),
+                &Patch::Merge(&patch),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        self.audit(ctx, AuditAction::Apply, "Deployment", "adopted".to_owned())
+            .await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crd::echo::{Echo, EchoOwnership};
+
+    use k8s_openapi::api::apps::v1::Deployment;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+    use kube::api::ObjectMeta;
+    use kube::Resource;
+
+    fn echo() -> Echo {
+        let mut echo = Echo::test(None);
+        echo.meta_mut().uid = Some("f2a1c5de-0000-4000-8000-000000000001".to_string());
+        echo
+    }
+
+    fn echo_with_ownership(ownership: EchoOwnership) -> Echo {
+        let mut echo = echo();
+        echo.spec.ownership = Some(ownership);
+        echo
+    }
+
+    fn deployment(owner_references: Vec<OwnerReference>) -> Deployment {
+        Deployment {
+            metadata: ObjectMeta {
+                owner_references: Some(owner_references),
+                ..ObjectMeta::default()
+            },
+            ..Deployment::default()
+        }
+    }
+
+    #[test]
+    fn test_owner_reference() {
+        assert!(Echo::test(None).owner_reference().is_none());
+        let owner_reference = echo().owner_reference().unwrap();
+        assert_eq!(owner_reference.controller, Some(true));
+        assert_eq!(owner_reference.block_owner_deletion, Some(true));
+
+        let owner_reference = echo_with_ownership(EchoOwnership {
+            block_owner_deletion: Some(false),
+            controller: Some(false),
+            adopt_orphans: None,
+        })
+        .owner_reference()
+        .unwrap();
+        assert_eq!(owner_reference.kind, "Echo");
+        assert_eq!(owner_reference.controller, Some(false));
+        assert_eq!(owner_reference.block_owner_deletion, Some(false));
+    }
+
+    #[test]
+    fn test_adopted_owner_references() {
+        let echo = echo();
+        let owner_reference = echo.owner_reference().unwrap();
+        assert!(echo
+            .adopted_owner_references(&deployment(vec![owner_reference.clone()]))
+            .is_none());
+
+        let previous = OwnerReference {
+            uid: "previous".to_string(),
+            ..owner_reference.clone()
+        };
+        let other_echo = OwnerReference {
+            name: "other".to_string(),
+            ..previous.clone()
+        };
+        let adopted = echo
+            .adopted_owner_references(&deployment(vec![previous, other_echo.clone()]))
+            .unwrap();
+        assert_eq!(adopted, vec![other_echo]);
+    }
+
+    #[test]
+    fn test_owns() {
+        let echo = echo();
+        let owner_reference = echo.owner_reference().unwrap();
+        assert!(echo.owns(&deployment(vec![owner_reference.clone()])));
+        // non controller references set by the ownership policy
+        assert!(echo.owns(&deployment(vec![OwnerReference {
+            controller: Some(false),
+            ..owner_reference.clone()
+        }])));
+
+        // a resource of a user, or of a previous Echo, with the same name
+        assert!(!echo.owns(&deployment(vec![])));
+        assert!(!echo.owns(&deployment(vec![OwnerReference {
+            uid: "previous".to_string(),
+            ..owner_reference
+        }])));
+    }
+}
@@ -0,0 +1,39 @@
+//! Paused echoes, whose Deployment is left as it is until they are resumed, e.g. to debug it by
+//! hand. `kubectl echo pause` and `kubectl echo resume` set and remove the annotation.
+use crate::crd::echo::Echo;
+
+use kube::ResourceExt;
+
+pub const PAUSED_ANNOTATION: &str = "echoes.example.com/paused";
+
+impl Echo {
+    /// Whether the reconciliation of the Echo is paused
+    pub fn paused(&self) -> bool {
+        self.annotations()
+            .get(PAUSED_ANNOTATION)
+            .is_some_and(|v| v == "true")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PAUSED_ANNOTATION;
+
+    use crate::crd::echo::Echo;
+
+    use kube::ResourceExt;
+
+    #[test]
+    fn test_paused() {
+        let mut echo = Echo::test(None);
+        assert!(!echo.paused());
+
+        echo.annotations_mut()
+            .insert(PAUSED_ANNOTATION.to_string(), "false".to_string());
+        assert!(!echo.paused());
+
+        echo.annotations_mut()
+            .insert(PAUSED_ANNOTATION.to_string(), "true".to_string());
+        assert!(echo.paused());
+    }
+}
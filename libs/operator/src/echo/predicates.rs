@@ -0,0 +1,313 @@
+//! Filters of the events triggering the echo reconciliation, so the changes which can not modify
+//! its result, like the status updates of the Deployment by the kubelet, do not trigger it.
+//!
+//! Each event is deduplicated by a hash of the fields the reconciler reads, including the uid so a
+//! recreated object always triggers it, and the dropped events are counted in the
+//! `triggers_deduplicated` metric. The watched streams do not carry the deletions, so the hashes
+//! of the objects missing from their store are pruned once the hashes doubled.
+use crate::crd::echo::Echo;
+use crate::metrics::ControllerMetrics;
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use futures::{future, Stream, StreamExt};
+use k8s_openapi::api::apps::v1::Deployment;
+use kube::runtime::reflector::{ObjectRef, Store};
+use kube::{Resource, ResourceExt};
+
+fn hash<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Spec, finalizers and deletion of the echo and, unless ignored, its labels and annotations
+pub(crate) fn echo_changes(ignore_metadata: bool) -> impl Fn(&Echo) -> Option<u64> {
+    move |echo| {
+        let metadata = (!ignore_metadata).then(|| (echo.labels(), echo.annotations()));
+        Some(hash(&(
+            echo.uid(),
+            echo.meta().generation,
+            echo.finalizers(),
+            echo.meta().deletion_timestamp.is_some(),
+            metadata,
+        )))
+    }
+}
+
+/// Spec of the Deployment
+pub(crate) fn deployment_spec_changes(deployment: &Deployment) -> Option<u64> {
+    deployment
+        .meta()
+        .generation
+        .map(|g| hash(&(deployment.uid(), g)))
+}
+
+/// Spec of the Deployment and the status fields reported in the echo status
+pub(crate) fn deployment_status_changes(deployment: &Deployment) -> Option<u64> {
+    let status = deployment.status.as_ref().map(|s| {
+        (
+            s.replicas,
+            s.ready_replicas,
+            s.available_replicas,
+            s.updated_replicas,
+        )
+    });
+    Some(hash(&(
+        deployment.uid(),
+        deployment.meta().generation,
+        status,
+    )))
+}
+
+/// Hashes kept before the ones of the deleted objects are pruned
+const MIN_PRUNE_LEN: usize = 1024;
+
+/// Hashes of the objects last seen, counting the unchanged ones in the controller metrics
+struct Seen<K: Resource<DynamicType = ()> + Clone + 'static, P> {
+    predicate: P,
+    hashes: HashMap<ObjectRef<K>, u64>,
+    /// Objects not deleted yet
+    store: Store<K>,
+    /// Number of hashes at which the deleted objects are pruned
+    prune_at: usize,
+    metrics: Arc<ControllerMetrics>,
+}
+
+impl<K, P> Seen<K, P>
+where
+    K: Resource<DynamicType = ()> + Clone + 'static,
+    P: Fn(&K) -> Option<u64>,
+{
+    fn new(predicate: P, store: Store<K>, metrics: Arc<ControllerMetrics>) -> Self {
+        Self {
+            predicate,
+            hashes: HashMap::new(),
+            store,
+            prune_at: MIN_PRUNE_LEN,
+            metrics,
+        }
+    }
+
+    /// Whether the predicate of the object changed since it was last seen
+    fn changed(&mut self, obj: &K) -> bool {
+        self.prune();
+        let changed = match (self.predicate)(obj) {
+            Some(hash) => self.hashes.insert(ObjectRef::from_obj(obj), hash) != Some(hash),
+            None => true,
+        };
+        if !changed {
+            self.metrics.triggers_deduplicated_inc(&K::kind(&()));
+        }
+        changed
+    }
+
+    /// Forget the objects deleted from the store, once the hashes doubled since they were last
+    /// pruned so it costs a constant time per object
+    fn prune(&mut self) {
+        if self.hashes.len() < self.prune_at {
+            return;
+        }
+        self.hashes
+            .retain(|obj_ref, _| self.store.get(obj_ref).is_some());
+        self.prune_at = (2 * self.hashes.len()).max(MIN_PRUNE_LEN);
+    }
+}
+
+/// Skip the objects of a shared stream whose predicate did not change since they were last seen
+pub(crate) fn filter_unchanged<K, S, P>(
+    stream: S,
+    predicate: P,
+    store: Store<K>,
+    metrics: Arc<ControllerMetrics>,
+) -> impl Stream<Item = Arc<K>>
+where
+    K: Resource<DynamicType = ()> + Clone + 'static,
+    S: Stream<Item = Arc<K>>,
+    P: Fn(&K) -> Option<u64>,
+{
+    let mut seen = Seen::new(predicate, store, metrics);
+    stream.filter(move |obj| future::ready(seen.changed(obj.as_ref())))
+}
+
+/// Skip the objects of a watch stream whose predicate did not change since they were last seen,
+/// keeping the errors
+pub(crate) fn filter_unchanged_objects<K, E, S, P>(
+    stream: S,
+    predicate: P,
+    store: Store<K>,
+    metrics: Arc<ControllerMetrics>,
+) -> impl Stream<Item = Result<K, E>>
+where
+    K: Resource<DynamicType = ()> + Clone + 'static,
+    S: Stream<Item = Result<K, E>>,
+    P: Fn(&K) -> Option<u64>,
+{
+    let mut seen = Seen::new(predicate, store, metrics);
+    stream.filter(move |res| {
+        future::ready(match res {
+            Ok(obj) => seen.changed(obj),
+            Err(_) => true,
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        deployment_spec_changes, deployment_status_changes, echo_changes, filter_unchanged,
+        filter_unchanged_objects, Seen, MIN_PRUNE_LEN,
+    };
+
+    use crate::crd::echo::{Echo, EchoStatus};
+    use crate::metrics::{ControllerMetrics, TriggeredByLabels};
+
+    use std::sync::Arc;
+
+    use futures::StreamExt;
+    use k8s_openapi::api::apps::v1::{Deployment, DeploymentStatus};
+    use kube::api::ObjectMeta;
+    use kube::runtime::reflector::{self, ObjectRef};
+    use kube::runtime::watcher;
+    use kube::ResourceExt;
+
+    #[test]
+    fn test_echo_changes() {
+        let echo = Echo::test(None);
+        let mut labeled = echo.clone().with_status(EchoStatus::default());
+        labeled
+            .labels_mut()
+            .insert("team".to_string(), "platform".to_string());
+        let mut changed = echo.clone();
+        changed.metadata.generation = Some(2);
+
+        let changes = echo_changes(false);
+        assert_eq!(
+            changes(&echo),
+            changes(&echo.clone().with_status(EchoStatus::default()))
+        );
+        assert_ne!(changes(&echo), changes(&labeled));
+        assert_ne!(changes(&echo), changes(&changed));
+
+        let changes = echo_changes(true);
+        assert_eq!(changes(&echo), changes(&labeled));
+        assert_ne!(changes(&echo), changes(&changed));
+    }
+
+    fn deployment(ready_replicas: i32, observed_generation: i64) -> Arc<Deployment> {
+        Arc::new(Deployment {
+            metadata: ObjectMeta {
+                name: Some("test".to_string()),
+                namespace: Some("default".to_string()),
+                generation: Some(1),
+                ..ObjectMeta::default()
+            },
+            status: Some(DeploymentStatus {
+                replicas: Some(1),
+                ready_replicas: Some(ready_replicas),
+                observed_generation: Some(observed_generation),
+                ..DeploymentStatus::default()
+            }),
+            ..Deployment::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_filter_unchanged_deployments() {
+        let deployments = futures::stream::iter([
+            deployment(0, 1),
+            // status update which is not reported in the echo status
+            deployment(0, 2),
+            deployment(1, 2),
+        ]);
+
+        let metrics = Arc::new(ControllerMetrics::new("test"));
+        let filtered: Vec<Arc<Deployment>> = filter_unchanged(
+            deployments,
+            deployment_status_changes,
+            reflector::store().0,
+            metrics.clone(),
+        )
+        .collect()
+        .await;
+
+        let ready: Vec<Option<i32>> = filtered
+            .iter()
+            .map(|d| d.status.as_ref().unwrap().ready_replicas)
+            .collect();
+        assert_eq!(ready, vec![Some(0), Some(1)]);
+        assert_eq!(deduplicated(&metrics, "Deployment"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_filter_unchanged_recreated_objects() {
+        let mut recreated = (*deployment(0, 1)).clone();
+        recreated.metadata.uid = Some("recreated".to_string());
+        let deployments = futures::stream::iter([
+            Ok::<_, ()>((*deployment(0, 1)).clone()),
+            Ok((*deployment(1, 1)).clone()),
+            Err(()),
+            Ok(recreated),
+        ]);
+
+        let metrics = Arc::new(ControllerMetrics::new("test"));
+        let filtered: Vec<Result<Deployment, ()>> = filter_unchanged_objects(
+            deployments,
+            deployment_spec_changes,
+            reflector::store().0,
+            metrics.clone(),
+        )
+        .collect()
+        .await;
+
+        let uids: Vec<Option<String>> = filtered
+            .iter()
+            .map(|res| res.as_ref().ok().and_then(|d| d.uid()))
+            .collect();
+        assert_eq!(uids, vec![None, None, Some("recreated".to_string())]);
+        assert_eq!(deduplicated(&metrics, "Deployment"), 1);
+    }
+
+    #[test]
+    fn test_prune_deleted_objects() {
+        let (store, mut writer) = reflector::store();
+        let named = |name: &str| {
+            let mut deployment = (*deployment(0, 1)).clone();
+            deployment.metadata.name = Some(name.to_string());
+            deployment
+        };
+        let kept = named("kept");
+        writer.apply_watcher_event(&watcher::Event::Apply(kept.clone()));
+        let mut seen = Seen::new(
+            deployment_spec_changes,
+            store,
+            Arc::new(ControllerMetrics::new("test")),
+        );
+
+        assert!(seen.changed(&kept));
+        for i in 1..MIN_PRUNE_LEN {
+            assert!(seen.changed(&named(&format!("deleted-{i}"))));
+        }
+        assert_eq!(seen.hashes.len(), MIN_PRUNE_LEN);
+        // the deleted objects are forgotten on the next event
+        assert!(!seen.changed(&kept));
+        assert_eq!(
+            seen.hashes.keys().collect::<Vec<_>>(),
+            vec![&ObjectRef::from_obj(&kept)]
+        );
+        assert_eq!(seen.prune_at, MIN_PRUNE_LEN);
+    }
+
+    fn deduplicated(metrics: &ControllerMetrics, triggered_by: &str) -> u64 {
+        metrics
+            .triggers_deduplicated
+            .get_or_create(&TriggeredByLabels {
+                controller: "test".to_string(),
+                triggered_by: triggered_by.to_string(),
+            })
+            .get()
+    }
+}
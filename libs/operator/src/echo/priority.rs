@@ -0,0 +1,222 @@
+//! Scheduling of the echoes listed when the controller starts, so new and changed echoes are not
+//! queued behind the resync of every echo already reconciled.
+use crate::crd::echo::Echo;
+
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
+use kube::runtime::watcher;
+use tokio::time::{self, Duration, Interval, MissedTickBehavior};
+
+/// Echoes of the initial list released per interval with the `Priority` policy
+pub(crate) const RESYNC_BATCH_SIZE: usize = 50;
+pub(crate) const RESYNC_BATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Order in which the echoes of the initial list are reconciled
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SchedulingPolicy {
+    /// Every echo as soon as it is listed
+    #[default]
+    Fifo,
+    /// Never reconciled and changed echoes first, then the rest in batches
+    Priority,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum ReconcilePriority {
+    /// Never reconciled: it has no status yet
+    New,
+    /// Changed after its status was last written
+    Changed,
+    /// Already reconciled, only a periodic resync
+    Resync,
+}
+
+impl Echo {
+    /// Last write to the status subresource (`true`) or to the rest of the object (`false`)
+    fn last_write(&self, status: bool) -> Option<DateTime<Utc>> {
+        self.metadata
+            .managed_fields
+            .iter()
+            .flatten()
+            .filter(|m| (m.subresource.as_deref() == Some("status")) == status)
+            .filter_map(|m| m.time.as_ref().map(|t| t.0))
+            .max()
+    }
+
+    pub(crate) fn reconcile_priority(&self) -> ReconcilePriority {
+        if self.status.is_none() {
+            return ReconcilePriority::New;
+        }
+        match (self.last_write(false), self.last_write(true)) {
+            (Some(changed), Some(reconciled)) if changed > reconciled => ReconcilePriority::Changed,
+            _ => ReconcilePriority::Resync,
+        }
+    }
+}
+
+struct Prioritizer<S> {
+    events: S,
+    /// Echoes of the list in progress
+    listed: Vec<Echo>,
+    /// Echoes to trigger right away
+    ready: VecDeque<Echo>,
+    /// Already reconciled echoes of the last list, waiting for their batch
+    resyncs: VecDeque<Echo>,
+    ticker: Interval,
+}
+
+impl<S> Prioritizer<S> {
+    fn schedule_listed(&mut self) {
+        let mut listed = std::mem::take(&mut self.listed);
+        // stable sort: the list order is kept between echoes of the same priority
+        listed.sort_by_key(|e| e.reconcile_priority());
+        let resyncs_start = listed
+            .iter()
+            .position(|e| e.reconcile_priority() == ReconcilePriority::Resync)
+            .unwrap_or(listed.len());
+        self.resyncs = listed.split_off(resyncs_start).into();
+        self.ready.extend(listed);
+        self.ticker.reset();
+    }
+
+    fn release_resyncs(&mut self) {
+        let batch = self.resyncs.len().min(RESYNC_BATCH_SIZE);
+        self.ready.extend(self.resyncs.drain(..batch));
+    }
+}
+
+/// Trigger stream of the echoes applied, releasing the already reconciled ones of every initial
+/// list in batches after the rest. Echoes applied after the list are triggered right away.
+pub(crate) fn prioritize<S>(events: S) -> impl Stream<Item = Result<Echo, watcher::Error>>
+where
+    S: Stream<Item = Result<watcher::Event<Echo>, watcher::Error>> + Send + Unpin,
+{
+    let mut ticker = time::interval(RESYNC_BATCH_INTERVAL);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let prioritizer = Prioritizer {
+        events: events.fuse(),
+        listed: Vec::new(),
+        ready: VecDeque::new(),
+        resyncs: VecDeque::new(),
+        ticker,
+    };
+    futures::stream::unfold(prioritizer, |mut p| async move {
+        loop {
+            if let Some(echo) = p.ready.pop_front() {
+                return Some((Ok(echo), p));
+            }
+            tokio::select! {
+                event = p.events.next() => match event {
+                    Some(Ok(watcher::Event::Init)) => p.listed.clear(),
+                    Some(Ok(watcher::Event::InitApply(echo))) => p.listed.push(echo),
+                    Some(Ok(watcher::Event::InitDone)) => p.schedule_listed(),
+                    Some(Ok(watcher::Event::Apply(echo))) => p.ready.push_back(echo),
+                    Some(Ok(watcher::Event::Delete(_))) => {}
+                    Some(Err(e)) => return Some((Err(e), p)),
+                    // nothing else can change, so the pending resyncs are not delayed anymore
+                    None if !p.resyncs.is_empty() => p.ready.extend(p.resyncs.drain(..)),
+                    None => return None,
+                },
+                _ = p.ticker.tick(), if !p.resyncs.is_empty() => p.release_resyncs(),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{prioritize, ReconcilePriority, RESYNC_BATCH_SIZE};
+
+    use crate::crd::echo::{Echo, EchoStatus};
+    use crate::test_utils::test_time;
+
+    use chrono::Duration;
+    use futures::StreamExt;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ManagedFieldsEntry, Time};
+    use kube::runtime::watcher;
+    use kube::ResourceExt;
+
+    fn managed_fields(subresource: Option<&str>, seconds: i64) -> ManagedFieldsEntry {
+        ManagedFieldsEntry {
+            subresource: subresource.map(str::to_owned),
+            time: Some(Time(test_time() + Duration::seconds(seconds))),
+            ..ManagedFieldsEntry::default()
+        }
+    }
+
+    fn echo(name: &str, spec_write: i64, status_write: Option<i64>) -> Echo {
+        let mut echo = Echo::test(None);
+        echo.metadata.name = Some(name.to_string());
+        echo.metadata.managed_fields = Some(
+            [managed_fields(None, spec_write)]
+                .into_iter()
+                .chain(status_write.map(|s| managed_fields(Some("status"), s)))
+                .collect(),
+        );
+        echo.status = status_write.map(|_| EchoStatus::default());
+        echo
+    }
+
+    #[test]
+    fn test_reconcile_priority() {
+        assert_eq!(
+            echo("a", 0, None).reconcile_priority(),
+            ReconcilePriority::New
+        );
+        assert_eq!(
+            echo("a", 10, Some(0)).reconcile_priority(),
+            ReconcilePriority::Changed
+        );
+        assert_eq!(
+            echo("a", 0, Some(10)).reconcile_priority(),
+            ReconcilePriority::Resync
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_prioritize_initial_list() {
+        let resyncs = (0..=RESYNC_BATCH_SIZE).map(|i| echo(&format!("resync-{i}"), 0, Some(10)));
+        let events = [watcher::Event::Init]
+            .into_iter()
+            .chain(resyncs.map(watcher::Event::InitApply))
+            .chain([
+                watcher::Event::InitApply(echo("changed", 10, Some(0))),
+                watcher::Event::InitApply(echo("new", 0, None)),
+                watcher::Event::InitDone,
+            ])
+            .map(Ok);
+
+        let names: Vec<String> = prioritize(futures::stream::iter(events))
+            .map(|e| e.unwrap().name_any())
+            .collect()
+            .await;
+
+        assert_eq!(names.len(), RESYNC_BATCH_SIZE + 3);
+        assert_eq!(names[..3], ["new", "changed", "resync-0"]);
+        assert_eq!(
+            names.last().unwrap(),
+            &format!("resync-{RESYNC_BATCH_SIZE}")
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_prioritize_applied_before_resyncs() {
+        let events = [
+            watcher::Event::Init,
+            watcher::Event::InitApply(echo("resync", 0, Some(10))),
+            watcher::Event::InitDone,
+            watcher::Event::Apply(echo("applied", 0, Some(10))),
+        ]
+        .into_iter()
+        .map(Ok);
+        let mut stream = Box::pin(prioritize(
+            futures::stream::iter(events).chain(futures::stream::pending()),
+        ));
+
+        // the resync batch waits for the first tick after the list
+        assert_eq!(stream.next().await.unwrap().unwrap().name_any(), "applied");
+        assert_eq!(stream.next().await.unwrap().unwrap().name_any(), "resync");
+    }
+}
@@ -0,0 +1,134 @@
+//! Provenance annotations of the resources generated for an echo, tracing them back to the
+//! operator build and the Echo spec that produced them.
+use crate::controller::Context;
+use crate::crd::echo::Echo;
+use crate::echo::timings::{self, Phase};
+use crate::error::{Error, Result};
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use k8s_openapi::api::apps::v1::Deployment;
+use kube::runtime::reflector::ObjectRef;
+use kube::ResourceExt;
+
+pub(crate) const OPERATOR_VERSION_ANNOTATION: &str = "echoes.example.com/operator-version";
+pub(crate) const RECONCILED_AT_ANNOTATION: &str = "echoes.example.com/reconciled-at";
+pub(crate) const SOURCE_UID_ANNOTATION: &str = "echoes.example.com/source-uid";
+pub(crate) const SPEC_HASH_ANNOTATION: &str = "echoes.example.com/spec-hash";
+
+const OPERATOR_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// 64-bit FNV-1a, stable across builds unlike the std hasher
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl Echo {
+    pub(crate) fn spec_hash(&self) -> Result<String> {
+        let spec = serde_json::to_vec(&self.spec).map_err(Error::SerializationError)?;
+        Ok(format!("{:016x}", fnv1a(&spec)))
+    }
+
+    /// Provenance annotations given the ones of the current Deployment. The reconcile timestamp
+    /// is only moved when the operator version or the spec change, so unchanged resources are not
+    /// rewritten on every reconciliation.
+    pub(crate) fn provenance_annotations_with(
+        &self,
+        current: Option<&BTreeMap<String, String>>,
+        now: DateTime<Utc>,
+    ) -> Result<BTreeMap<String, String>> {
+        let mut annotations = BTreeMap::from([
+            (
+                OPERATOR_VERSION_ANNOTATION.to_owned(),
+                OPERATOR_VERSION.to_owned(),
+            ),
+            (
+                SOURCE_UID_ANNOTATION.to_owned(),
+                self.uid().unwrap_or_default(),
+            ),
+            (SPEC_HASH_ANNOTATION.to_owned(), self.spec_hash()?),
+        ]);
+        let unchanged = current.is_some_and(|current| {
+            annotations
+                .iter()
+                .all(|(k, v)| current.get(k).is_some_and(|c| c == v))
+        });
+        let reconciled_at = current
+            .filter(|_| unchanged)
+            .and_then(|c| c.get(RECONCILED_AT_ANNOTATION).cloned())
+            .unwrap_or_else(|| now.to_rfc3339_opts(SecondsFormat::Secs, true));
+        annotations.insert(RECONCILED_AT_ANNOTATION.to_owned(), reconciled_at);
+        Ok(annotations)
+    }
+
+    /// Provenance annotations of the resources generated in this reconciliation
+    pub(crate) fn provenance_annotations(&self, ctx: &Context) -> Result<BTreeMap<String, String>> {
+        let deployment_ref =
+            ObjectRef::<Deployment>::new_with(&self.name_any(), ()).within(&self.get_namespace());
+        let current = timings::measure_sync(Phase::Store, || {
+            ctx.stores
+                .get::<Deployment>()
+                // safe unwrap: deployment store should exists
+                .unwrap()
+                .get(&deployment_ref)
+        });
+        self.provenance_annotations_with(current.as_ref().map(|d| d.annotations()), ctx.clock.now())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        fnv1a, OPERATOR_VERSION_ANNOTATION, RECONCILED_AT_ANNOTATION, SOURCE_UID_ANNOTATION,
+        SPEC_HASH_ANNOTATION,
+    };
+
+    use crate::crd::echo::Echo;
+    use crate::test_utils::test_time;
+
+    use chrono::Duration;
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn test_provenance_annotations() {
+        let mut echo = Echo::test(None);
+        echo.metadata.uid = Some("3f1c".to_string());
+
+        let annotations = echo.provenance_annotations_with(None, test_time()).unwrap();
+        assert_eq!(annotations[SOURCE_UID_ANNOTATION], "3f1c");
+        assert_eq!(
+            annotations[OPERATOR_VERSION_ANNOTATION],
+            env!("CARGO_PKG_VERSION")
+        );
+        assert_eq!(
+            annotations[RECONCILED_AT_ANNOTATION],
+            "2024-01-01T00:00:00Z"
+        );
+
+        let later = test_time() + Duration::hours(1);
+        // same input: the timestamp of the reconciliation which produced it is kept
+        let unchanged = echo
+            .provenance_annotations_with(Some(&annotations), later)
+            .unwrap();
+        assert_eq!(unchanged, annotations);
+
+        let changed = echo
+            .clone()
+            .change_replicas(3)
+            .provenance_annotations_with(Some(&annotations), later)
+            .unwrap();
+        assert_ne!(
+            changed[SPEC_HASH_ANNOTATION],
+            annotations[SPEC_HASH_ANNOTATION]
+        );
+        assert_eq!(changed[RECONCILED_AT_ANNOTATION], "2024-01-01T01:00:00Z");
+    }
+}
@@ -0,0 +1,240 @@
+//! ServiceAccount of the echo pods and, optionally, a Role bound to it with `spec.rbac.rules`.
+//!
+//! The API server lets the operator grant every permission of its ClusterRole, so the rules are
+//! only applied when the operator [`RbacAllowlist`](crate::rbac_allowlist::RbacAllowlist) allows
+//! them.
+use crate::audit::{self, AuditAction};
+use crate::controller::Context;
+use crate::crd::echo::Echo;
+use crate::error::{Error, Result};
+use crate::hook;
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use k8s_openapi::api::core::v1::ServiceAccount;
+use k8s_openapi::api::rbac::v1::{PolicyRule, Role, RoleBinding, RoleRef, Subject};
+use k8s_openapi::NamespaceResourceScope;
+use kube::api::{Api, DeleteParams, ObjectMeta, Patch, PatchParams, Resource};
+use kube::ResourceExt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+impl Echo {
+    /// Name of the ServiceAccount of the echo pods, if the Echo creates one
+    pub(crate) fn service_account_name(&self) -> Option<String> {
+        (self.spec.create_service_account == Some(true)).then(|| self.name_any())
+    }
+
+    pub(crate) fn rbac_rules(&self) -> Vec<PolicyRule> {
+        self.spec
+            .rbac
+            .iter()
+            .flat_map(|rbac| rbac.rules.iter().flatten())
+            .map(|rule| PolicyRule {
+                api_groups: rule.api_groups.clone(),
+                resources: rule.resources.clone(),
+                resource_names: rule.resource_names.clone(),
+                verbs: rule.verbs.clone(),
+                ..PolicyRule::default()
+            })
+            .collect()
+    }
+
+    /// Apply the ServiceAccount and the Role and RoleBinding of its rules, or delete them when
+    /// `createServiceAccount` is false
+    pub(crate) async fn reconcile_rbac(&self, ctx: &Context) -> Result<()> {
+        match self.spec.create_service_account {
+            None => return Ok(()),
+            Some(false) => {
+                self.delete_rbac::<RoleBinding>(ctx).await?;
+                self.delete_rbac::<Role>(ctx).await?;
+                return self.delete_rbac::<ServiceAccount>(ctx).await;
+            }
+            Some(true) => {}
+        }
+
+        let rules = self.rbac_rules();
+        ctx.rbac_allowlist.check(&rules)?;
+        self.apply_rbac(ctx, &self.service_account()).await?;
+        if rules.is_empty() {
+            self.delete_rbac::<RoleBinding>(ctx).await?;
+            return self.delete_rbac::<Role>(ctx).await;
+        }
+        self.apply_rbac(ctx, &self.role(rules)).await?;
+        self.apply_rbac(ctx, &self.role_binding()).await
+    }
+
+    async fn apply_rbac<K>(&self, ctx: &Context, resource: &K) -> Result<()>
+    where
+        K: Resource<DynamicType = (), Scope = NamespaceResourceScope>
+            + Clone
+            + Debug
+            + DeserializeOwned
+            + Serialize,
+    {
+        let mut resource = resource.clone();
+        resource
+            .annotations_mut()
+            .extend(self.provenance_annotations(ctx)?);
+        let api = Api::<K>::namespaced(ctx.client.clone(), &self.get_namespace());
+        let current = api
+            .get_opt(&self.name_any())
+            .await
+            .map_err(Error::KubeError)?;
+        let applied = api
+            .patch(
+                &self.name_any(),
+                &PatchParams::apply("echoes.example.com").force(),
+                &Patch::Apply(&resource),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        if audit::apply_changed(current.as_ref(), &applied) {
+            self.audit(ctx, AuditAction::Apply, &K::kind(&()), "rbac".into())
+                .await;
+        }
+        Ok(())
+    }
+
+    async fn delete_rbac<K>(&self, ctx: &Context) -> Result<()>
+    where
+        K: Resource<DynamicType = (), Scope = NamespaceResourceScope>
+            + Clone
+            + Debug
+            + DeserializeOwned,
+    {
+        let kind = K::kind(&());
+        let api = Api::<K>::namespaced(ctx.client.clone(), &self.get_namespace());
+        let existing = api
+            .get_opt(&self.name_any())
+            .await
+            .map_err(Error::KubeError)?;
+        // resources with the name of the Echo which it does not own belong to someone else
+        if !existing.is_some_and(|r| self.owns(&r)) {
+            return Ok(());
+        }
+        hook::pre_delete(&ctx.hooks, self, &kind, &self.name_any()).await?;
+        match api.delete(&self.name_any(), &DeleteParams::default()).await {
+            Ok(_) => {
+                self.audit(ctx, AuditAction::Delete, &kind, "rbac disabled".into())
+                    .await;
+                Ok(())
+            }
+            Err(kube::Error::Api(ae)) if ae.code == 404 => Ok(()),
+            Err(e) => Err(Error::KubeError(e)),
+        }
+    }
+
+    fn rbac_metadata(&self) -> ObjectMeta {
+        let name = self.name_any();
+        ObjectMeta {
+            name: Some(name.clone()),
+            namespace: Some(self.get_namespace()),
+            labels: Some(BTreeMap::from([
+                ("app".to_owned(), name),
+                ("app.kubernetes.io/name".to_owned(), "echo".to_owned()),
+                (
+                    "app.kubernetes.io/managed-by".to_owned(),
+                    "echo-operator".to_owned(),
+                ),
+            ])),
+            owner_references: self.child_owner_references(),
+            ..ObjectMeta::default()
+        }
+    }
+
+    pub(crate) fn service_account(&self) -> ServiceAccount {
+        ServiceAccount {
+            metadata: self.rbac_metadata(),
+            ..ServiceAccount::default()
+        }
+    }
+
+    pub(crate) fn role(&self, rules: Vec<PolicyRule>) -> Role {
+        Role {
+            metadata: self.rbac_metadata(),
+            rules: Some(rules),
+        }
+    }
+
+    pub(crate) fn role_binding(&self) -> RoleBinding {
+        RoleBinding {
+            metadata: self.rbac_metadata(),
+            role_ref: RoleRef {
+                api_group: "rbac.authorization.k8s.io".to_owned(),
+                kind: "Role".to_owned(),
+                name: self.name_any(),
+            },
+            subjects: Some(vec![Subject {
+                kind: "ServiceAccount".to_owned(),
+                name: self.name_any(),
+                namespace: Some(self.get_namespace()),
+                ..Subject::default()
+            }]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crd::echo::{Echo, EchoRbac, EchoRbacRules};
+    use crate::error::Error;
+    use crate::test_utils::get_test_context;
+
+    fn echo_with_rules() -> Echo {
+        let mut echo = Echo::test(None);
+        echo.spec.create_service_account = Some(true);
+        echo.spec.rbac = Some(EchoRbac {
+            rules: Some(vec![EchoRbacRules {
+                api_groups: Some(vec!["".to_string()]),
+                resources: Some(vec!["pods".to_string()]),
+                resource_names: None,
+                verbs: vec!["get".to_string(), "list".to_string()],
+            }]),
+        });
+        echo
+    }
+
+    #[test]
+    fn test_service_account_name() {
+        let mut echo = Echo::test(None);
+        assert_eq!(echo.service_account_name(), None);
+
+        echo.spec.create_service_account = Some(true);
+        assert_eq!(echo.service_account_name(), Some("test".to_string()));
+    }
+
+    #[test]
+    fn test_role() {
+        let echo = echo_with_rules();
+
+        let role = echo.role(echo.rbac_rules());
+
+        let rules = role.rules.unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].resources, Some(vec!["pods".to_string()]));
+        assert_eq!(rules[0].verbs, vec!["get".to_string(), "list".to_string()]);
+    }
+
+    #[test]
+    fn test_role_binding() {
+        let binding = echo_with_rules().role_binding();
+
+        assert_eq!(binding.role_ref.kind, "Role");
+        assert_eq!(binding.role_ref.name, "test");
+        let subjects = binding.subjects.unwrap();
+        assert_eq!(subjects[0].kind, "ServiceAccount");
+        assert_eq!(subjects[0].name, "test");
+        assert_eq!(subjects[0].namespace.as_deref(), Some("default"));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_rbac_denies_rules_not_allowed() {
+        let (testctx, _fakeserver) = get_test_context();
+
+        let result = echo_with_rules().reconcile_rbac(&testctx).await;
+
+        assert!(matches!(result, Err(Error::ForbiddenRbacRules(_))));
+    }
+}
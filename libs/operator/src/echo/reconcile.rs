@@ -1,53 +1,563 @@
+use crate::audit::{deployment_diff_summary, AuditAction, AuditEvent, DIFF_UNCHANGED};
+use crate::clusterdefaults::{ClusterDefaults, EchoPodValues, SecurityProfile, DEFAULT_IMAGE};
 use crate::controller::Context;
-use crate::crd::echo::{Echo, EchoStatus};
-use crate::error::{Error, Result};
+use crate::crd::echo::{Echo, EchoImagePullPolicy, EchoStatus};
+use crate::crd::echoquota::EchoQuota;
+use crate::diff;
+use crate::echo::condition::ConditionType;
+use crate::echo::controller::CONTROLLER_ID;
+use crate::echo::schedule::ScheduledReplicas;
+use crate::echo::timings::{self, Phase, PhaseTimings};
+use crate::error::{Error, ErrorContext, Operation, Result, ResultExt};
+use crate::hook;
+use crate::notify::{Health, Lifecycle, Notification};
 use crate::telemetry;
 
 use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::time::Instant;
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use k8s_openapi::api::apps::v1::{Deployment, DeploymentSpec, DeploymentStatus};
-use k8s_openapi::api::core::v1::{Container, ContainerPort, PodSpec, PodTemplateSpec};
+use k8s_openapi::api::core::v1::{
+    Container, ContainerPort, LocalObjectReference, PodSpec, PodTemplateSpec,
+};
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, LabelSelector, Time};
-use kube::api::{Api, ObjectMeta, Patch, PatchParams, Resource};
+use kube::api::{Api, ListParams, ObjectMeta, Patch, PatchParams};
 use kube::client::Client;
 use kube::runtime::controller::Action;
 use kube::runtime::reflector::ObjectRef;
 use kube::ResourceExt;
 use serde_json::json;
 use tokio::time::Duration;
-use tracing::{debug, field, info, instrument, trace, Span};
+use tracing::{debug, field, info, instrument, trace, warn, Span};
 
-static STATUS_READY: &str = "Ready";
-static STATUS_PROGRESSING: &str = "Progressing";
+/// Port where the echo server listens
+pub(crate) const ECHO_PORT: i32 = 8080;
 
-#[instrument(skip(ctx, echo))]
-pub async fn reconcile_echo(echo: Arc<Echo>, ctx: Arc<Context<Deployment>>) -> Result<Action> {
+/// Values of the Deployment of an Echo that do not come from its spec, set by the operator in
+/// every reconciliation
+#[derive(Clone, Debug, Default)]
+pub struct Defaults {
+    /// Replicas of the active schedule, the Echo replicas if unset
+    pub replicas: Option<i32>,
+    /// Pull secrets of the echo pods, e.g. the copies of the registry credentials
+    pub image_pull_secrets: Option<Vec<LocalObjectReference>>,
+    /// Annotations of the Deployment, e.g. the provenance ones
+    pub annotations: BTreeMap<String, String>,
+    /// Cluster-wide defaults and overrides of the echo pods
+    pub cluster: ClusterDefaults,
+}
+
+/// What a reconciliation did to the Deployment, reported in its summary log line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ReconcileOutcome {
+    /// The Deployment changed and was applied
+    Applied,
+    /// The Deployment was applied without changes
+    Unchanged,
+    /// The Deployment was deleted and created again
+    Recreated,
+    /// The Deployment was not applied, for the given reason
+    Skipped(&'static str),
+}
+
+impl ReconcileOutcome {
+    fn action(&self) -> &'static str {
+        match self {
+            ReconcileOutcome::Applied => "applied",
+            ReconcileOutcome::Unchanged => "unchanged",
+            ReconcileOutcome::Recreated => "recreated",
+            ReconcileOutcome::Skipped(_) => "skipped",
+        }
+    }
+
+    fn reason(&self) -> Option<&'static str> {
+        match self {
+            ReconcileOutcome::Skipped(reason) => Some(reason),
+            _ => None,
+        }
+    }
+}
+
+#[instrument(skip(ctx, echo), fields(trace_id, uid, generation, resource_version))]
+pub async fn reconcile_echo(echo: Arc<Echo>, ctx: Arc<Context>) -> Result<Action> {
     let trace_id = telemetry::get_trace_id();
     Span::current().record("trace_id", field::display(&trace_id));
+    echo.record_identity(&Span::current());
     let _timer = ctx.metrics.reconcile_count_and_measure(&trace_id);
-    info!(msg = "reconciling Echo");
-
-    let _ignore_errors = echo.update_status(ctx.clone()).await.map_err(|e| {
-        debug!(msg = "failed to reconcile status", %e);
-        ctx.metrics.status_update_errors_inc();
-    });
-    echo.patch(ctx).await?;
-    Ok(Action::requeue(Duration::from_secs(5 * 60)))
+    let start = Instant::now();
+    debug!(msg = "reconciling Echo");
+
+    let (result, phases) = timings::timed(reconcile(&echo, ctx.clone())).await;
+    let elapsed = start.elapsed();
+    if ctx
+        .slow_reconcile_threshold
+        .is_some_and(|threshold| elapsed > threshold)
+    {
+        warn_slow_reconcile(&echo, &ctx, elapsed, &phases);
+    }
+    // single line per reconciliation, with the same fields whatever the outcome
+    let duration_ms = elapsed.as_millis() as u64;
+    match &result {
+        Ok((outcome, requeue)) => {
+            ctx.reconciled(&*echo);
+            info!(
+                msg = "reconciled Echo",
+                namespace = echo.get_namespace(),
+                name = echo.name_any(),
+                action = outcome.action(),
+                reason = outcome.reason(),
+                duration_ms,
+                requeue_secs = requeue.map(|r| r.as_secs()),
+            );
+        }
+        Err(e) => {
+            info!(
+                msg = "reconciled Echo",
+                namespace = echo.get_namespace(),
+                name = echo.name_any(),
+                action = "failed",
+                reason = e.metric_label(),
+                duration_ms,
+            );
+            // the reconciliation error is returned anyway
+            if let Err(report_error) = echo.report_failure(&ctx, e, &trace_id).await {
+                warn!(msg = "failed to report Echo reconciliation failure", %report_error);
+            }
+        }
+    }
+    result.map(|(_, requeue)| requeue.map_or_else(Action::await_change, Action::requeue))
+}
+
+/// Warn about a reconciliation slower than the threshold, with the time spent in each phase
+fn warn_slow_reconcile(echo: &Echo, ctx: &Context, elapsed: Duration, phases: &PhaseTimings) {
+    warn!(
+        msg = "slow Echo reconciliation",
+        namespace = echo.get_namespace(),
+        name = echo.name_any(),
+        duration_ms = elapsed.as_millis() as u64,
+        apply_ms = phases.apply.as_millis() as u64,
+        status_ms = phases.status.as_millis() as u64,
+        store_ms = phases.store.as_millis() as u64,
+        other_ms = phases.other(elapsed).as_millis() as u64,
+    );
+    ctx.metrics.reconcile_slow_inc();
+}
+
+/// Reconcile the Echo, returning the outcome and when to requeue it, if it has to
+async fn reconcile(echo: &Echo, ctx: Arc<Context>) -> Result<(ReconcileOutcome, Option<Duration>)> {
+    if !echo.namespace_allowed(&ctx) {
+        debug!(msg = "ignoring Echo of a namespace not managed by the operator");
+        echo.report_namespace_denied(&ctx).await?;
+        return Ok((ReconcileOutcome::Skipped("namespace-denied"), None));
+    }
+    echo.clear_namespace_denied(&ctx).await?;
+
+    // the annotation can be removed while metadata changes are ignored, so it is checked again
+    if echo.paused() {
+        debug!(msg = "ignoring paused Echo");
+        return Ok((
+            ReconcileOutcome::Skipped("paused"),
+            Some(ctx.requeue_interval(echo)),
+        ));
+    }
+
+    let now = ctx.clock.now();
+    if let Some(window) = echo.maintenance_window(&ctx, now)? {
+        debug!(msg = "suppressing Echo changes during maintenance window", %window);
+        echo.report_maintenance(&ctx, &window).await?;
+        return Ok((
+            ReconcileOutcome::Skipped("maintenance-window"),
+            Some(window.requeue_after(now, ctx.requeue_interval(echo))),
+        ));
+    }
+    echo.clear_maintenance(&ctx).await?;
+
+    // before the DNS cleanup, as both patch the finalizers
+    if echo.deregistration_pending() {
+        echo.deregister(&ctx).await?;
+        return Ok((ReconcileOutcome::Skipped("deregistering"), None));
+    }
+    if echo.dns_cleanup_pending() {
+        echo.cleanup_dns(&ctx).await?;
+        return Ok((ReconcileOutcome::Skipped("deleting"), None));
+    }
+
+    let scheduled = echo.scheduled_replicas(now)?;
+    echo.reconcile_dns_finalizer(&ctx).await?;
+    let service = echo.reconcile_service(&ctx).await?;
+    echo.reconcile_ingress(&ctx).await?;
+    // the rest of the status is aggregated from the Deployment by the echostatus controller
+    echo.report_dns_condition(&ctx, echo.dns_ready(service.as_ref(), now))
+        .await?;
+    echo.reconcile_registration_finalizer(&ctx).await?;
+    echo.check_quotas(ctx.clone(), scheduled.replicas).await?;
+    echo.reconcile_rbac(&ctx).await?;
+    let image_pull_secrets = echo.reconcile_registry_credentials(&ctx).await?;
+    let rolled_back = echo.reconcile_rollout(&ctx).await?;
+    // before the apply, which drops the replicas once handed over to the HPA, or owns them again
+    // once the HPA is removed
+    echo.reconcile_autoscaling(&ctx).await?;
+    let outcome = rolled_back
+        .as_ref()
+        .unwrap_or(echo)
+        .patch(ctx.clone(), scheduled.replicas, image_pull_secrets)
+        .await?;
+    echo.reconcile_alerting(&ctx).await?;
+    echo.clear_last_error(&ctx).await?;
+    let requeue = requeue_after(&scheduled, now, ctx.requeue_interval(echo));
+    Ok((
+        outcome,
+        Some(echo.rollout_requeue_after(&ctx, now, requeue)),
+    ))
+}
+
+/// Requeue after the interval, or when the next schedule fires if it is sooner
+pub(crate) fn requeue_after(
+    scheduled: &ScheduledReplicas,
+    now: DateTime<Utc>,
+    requeue: Duration,
+) -> Duration {
+    scheduled
+        .next_change
+        .and_then(|next| (next - now).to_std().ok())
+        .map_or(requeue, |until_next| until_next.min(requeue))
 }
 
 impl Echo {
     #[inline]
-    fn get_namespace(&self) -> String {
+    pub(crate) fn get_namespace(&self) -> String {
         // safe unwrap: Echo is namespaced scoped
         self.namespace().unwrap()
     }
 
-    async fn patch(&self, ctx: Arc<Context<Deployment>>) -> Result<Deployment, Error> {
+    #[instrument(
+        skip(self, ctx, image_pull_secrets),
+        fields(
+            namespace = %self.get_namespace(),
+            name = %self.name_any(),
+            deployment_diff,
+            outcome
+        ),
+        err
+    )]
+    async fn patch(
+        &self,
+        ctx: Arc<Context>,
+        replicas: i32,
+        image_pull_secrets: Option<Vec<LocalObjectReference>>,
+    ) -> Result<ReconcileOutcome, Error> {
         let namespace = self.get_namespace();
         let deployment_api = Api::<Deployment>::namespaced(ctx.client.clone(), &namespace);
-        let owner_references = self.controller_owner_ref(&()).map(|oref| vec![oref]);
+
+        ctx.metrics
+            .spec_replicas_set(&namespace, &self.name_any(), self.spec.replicas);
+        let defaults = Defaults {
+            replicas: Some(replicas),
+            image_pull_secrets,
+            annotations: self.provenance_annotations(&ctx)?,
+            cluster: ctx.cluster_defaults.get(),
+        };
+        let mut deployment = self.generate_deployment(&defaults)?;
+        hook::pre_apply(&ctx.hooks, self, &mut deployment).await?;
+        let deployment_ref =
+            ObjectRef::<Deployment>::new_with(&self.name_any(), ()).within(&namespace);
+        let current = timings::measure_sync(Phase::Store, || {
+            ctx.stores
+                .get::<Deployment>()
+                // safe unwrap: deployment store should exists
+                .unwrap()
+                .get(&deployment_ref)
+        });
+        let summary = deployment_diff_summary(current.as_deref(), &deployment);
+        Span::current().record("deployment_diff", summary.as_str());
+        let changed = summary != DIFF_UNCHANGED;
+        // the summary only covers the audited fields, the diff every applied one
+        let changes = diff::applied_diff(current.as_deref(), &deployment)?;
+        if !changes.is_empty() {
+            debug!(
+                msg = "applying Deployment changes",
+                changes = %serde_json::to_string(&changes).map_err(Error::SerializationError)?
+            );
+        }
+
+        if ctx.startup_ramp.active() {
+            // a restart reconciles every Echo, most of them without changes
+            if current.is_some() && changes.is_empty() {
+                debug!(msg = "skipping unchanged Deployment apply during startup ramp");
+                return Ok(ReconcileOutcome::Skipped("startup-unchanged"));
+            }
+            timings::measure(Phase::Apply, ctx.startup_ramp.acquire()).await;
+        }
+
+        timings::measure(
+            Phase::Apply,
+            self.adopt_deployment(&ctx, current.as_deref()),
+        )
+        .await?;
+        let result = timings::measure(
+            Phase::Apply,
+            // a new Deployment has no fields owned by other managers
+            self.apply_deployment(&deployment_api, &deployment, current.is_some() && changed),
+        )
+        .await;
+        let outcome = match result {
+            Ok((deployment, conflicts)) => {
+                hook::post_apply(&ctx.hooks, self, &deployment).await?;
+                if current.is_none() {
+                    ctx.notifier.send_lifecycle(
+                        &namespace,
+                        &self.name_any(),
+                        Lifecycle::Created,
+                        ctx.metrics.clone(),
+                    );
+                }
+                if changed {
+                    self.audit(&ctx, AuditAction::Apply, "Deployment", summary)
+                        .await;
+                }
+                self.clear_recreate_required(&ctx).await?;
+                self.clear_conflict(&ctx).await?;
+                self.record_field_conflicts(&ctx, &conflicts).await?;
+                if changed {
+                    Ok(ReconcileOutcome::Applied)
+                } else {
+                    Ok(ReconcileOutcome::Unchanged)
+                }
+            }
+            Err(e) => {
+                match e {
+                    kube::Error::Api(ae) if ae.code == 409 && self.report_conflicts() => {
+                        info!(
+                            msg = "Deployment fields are owned by other field managers",
+                            reason = ae.reason
+                        );
+                        self.report_conflict(&ctx, &ae.message).await?;
+                        Ok(ReconcileOutcome::Skipped("conflicted"))
+                    }
+                    kube::Error::Api(ae) if ae.code == 422 && !self.recreate_allowed() => {
+                        info!(msg = "Deployment must be recreated but the recreate policy does not allow it", reason=ae.reason);
+                        self.report_recreate_required(&ctx, &ae.message).await?;
+                        Ok(ReconcileOutcome::Skipped("recreate-required"))
+                    }
+                    kube::Error::Api(ae) if ae.code == 422 => {
+                        info!(msg = "recreating Deployment because the update operation wasn't possible", reason=ae.reason);
+                        hook::pre_delete(&ctx.hooks, self, "Deployment", &self.name_any()).await?;
+                        timings::measure(Phase::Apply, self.delete_deployment(ctx.client.clone()))
+                            .await?;
+                        self.audit(&ctx, AuditAction::Delete, "Deployment", ae.reason.clone())
+                            .await;
+                        ctx.metrics.reconcile_deploy_delete_create_inc();
+                        let deployment = timings::measure(
+                            Phase::Apply,
+                            deployment_api.patch(
+                                &self.name_any(),
+                                &PatchParams::apply("echoes.example.com").force(),
+                                &Patch::Apply(&deployment),
+                            ),
+                        )
+                        .await
+                        .map_err(Error::KubeError)
+                        .with_context(|| ErrorContext::of(Operation::Apply, &deployment))?;
+                        hook::post_apply(&ctx.hooks, self, &deployment).await?;
+                        self.audit(&ctx, AuditAction::Recreate, "Deployment", summary)
+                            .await;
+                        self.consume_recreate_approval(&ctx).await?;
+                        self.clear_recreate_required(&ctx).await?;
+                        Ok(ReconcileOutcome::Recreated)
+                    }
+                    _ => Err(Error::KubeError(e)
+                        .context(ErrorContext::of(Operation::Apply, &deployment))),
+                }
+            }
+        }?;
+        Span::current().record("outcome", outcome.action());
+        Ok(outcome)
+    }
+
+    /// Record the identity of this version of the Echo in the `uid`, `generation` and
+    /// `resource_version` fields of the span, so its history can be followed across restarts
+    pub(crate) fn record_identity(&self, span: &Span) {
+        span.record("uid", self.metadata.uid.as_deref());
+        span.record("generation", self.metadata.generation);
+        span.record(
+            "resource_version",
+            self.metadata.resource_version.as_deref(),
+        );
+    }
+
+    /// Condition of the given type in the current status
+    pub(crate) fn condition(&self, type_: ConditionType) -> Option<&Condition> {
+        self.status.as_ref().and_then(|s| s.condition(type_))
+    }
+
+    /// Replace, or remove, the condition of the given type, keeping the rest of the conditions
+    pub(crate) async fn patch_condition(
+        &self,
+        ctx: &Context,
+        type_: ConditionType,
+        condition: Option<Condition>,
+    ) -> Result<()> {
+        let echo_api = Api::<Echo>::namespaced(ctx.client.clone(), &self.get_namespace());
+        // the status can be updated by the echostatus controller meanwhile, so its conditions are
+        // stale
+        let current = timings::measure(Phase::Status, echo_api.get_status(&self.name_any()))
+            .await
+            .map_err(Error::KubeError)
+            .with_context(|| ErrorContext::of(Operation::StatusPatch, self))?;
+        let mut status = current.status.unwrap_or_default();
+        match condition {
+            Some(condition) => status.set_condition(condition),
+            None => status.remove_condition(type_),
+        }
+        let conditions = status.conditions.unwrap_or_default();
+        debug!(msg = "patching Echo condition", %type_);
+        timings::measure(
+            Phase::Status,
+            echo_api.patch_status(
+                &self.name_any(),
+                &PatchParams::default(),
+                &Patch::Merge(json!({"status": {"conditions": conditions}})),
+            ),
+        )
+        .await
+        .map_err(Error::KubeError)
+        .with_context(|| ErrorContext::of(Operation::StatusPatch, self))?;
+        Ok(())
+    }
+
+    /// Record a mutating action on a resource named as the Echo
+    pub(crate) async fn audit(
+        &self,
+        ctx: &Context,
+        action: AuditAction,
+        kind: &str,
+        summary: String,
+    ) {
+        let event = AuditEvent::new(
+            ctx.clock.now(),
+            CONTROLLER_ID,
+            action,
+            kind,
+            &self.get_namespace(),
+            &self.name_any(),
+            summary,
+        );
+        ctx.auditor.record(event).await;
+    }
+
+    /// Deployment manifest of the Echo with the given defaults, as applied by the operator before
+    /// the reconcile hooks, so it can be computed without a client, e.g. for dry runs. Fails when
+    /// the response templates do not render.
+    pub fn generate_deployment(&self, defaults: &Defaults) -> Result<Deployment> {
+        let mut deployment = self.deployment(defaults.replicas.unwrap_or(self.spec.replicas));
+        if let Some(env) = self.response_env()? {
+            deployment
+                .spec
+                .iter_mut()
+                .flat_map(|s| s.template.spec.iter_mut())
+                .flat_map(|s| s.containers.iter_mut())
+                .for_each(|c| c.env = Some(env.clone()));
+        }
+        if let Some(service_account_name) = self.service_account_name() {
+            deployment
+                .spec
+                .iter_mut()
+                .flat_map(|s| s.template.spec.iter_mut())
+                .for_each(|s| s.service_account_name = Some(service_account_name.clone()));
+        }
+        if self.autoscaled() {
+            // the HPA owns the replicas
+            deployment.spec.iter_mut().for_each(|s| s.replicas = None);
+        }
+        if let Some(deadline) = self.progress_deadline() {
+            deployment.spec.iter_mut().for_each(|s| {
+                s.progress_deadline_seconds = i32::try_from(deadline.as_secs()).ok();
+            });
+        }
+        let pod_values = defaults
+            .cluster
+            .resolve(self.spec.profile.as_deref(), self.pod_values()?)?;
+        let affinity = self.affinity()?;
+        let topology_spread_constraints = self.topology_spread_constraints()?;
+        deployment
+            .spec
+            .iter_mut()
+            .flat_map(|s| s.template.spec.iter_mut())
+            .for_each(|s| {
+                s.tolerations = pod_values.tolerations.clone();
+                s.node_selector = self.spec.node_selector.clone();
+                s.affinity = affinity.clone();
+                s.topology_spread_constraints = topology_spread_constraints.clone();
+                s.containers.iter_mut().for_each(|c| {
+                    c.image = pod_values.image.clone();
+                    c.image_pull_policy = self.image_pull_policy();
+                    c.resources = pod_values.resources.clone();
+                });
+            });
+        let (pod_security_context, security_context) =
+            self.security_contexts_with(pod_values.security_profile);
+        let writable_volume = self.writable_volume();
+        deployment
+            .spec
+            .iter_mut()
+            .flat_map(|s| s.template.spec.iter_mut())
+            .for_each(|s| {
+                s.security_context = pod_security_context.clone();
+                s.image_pull_secrets = defaults.image_pull_secrets.clone();
+                if let Some((volume, _)) = writable_volume.as_ref() {
+                    s.volumes = Some(vec![volume.clone()]);
+                }
+                s.containers.iter_mut().for_each(|c| {
+                    c.security_context = Some(security_context.clone());
+                    if let Some((_, mount)) = writable_volume.as_ref() {
+                        c.volume_mounts = Some(vec![mount.clone()]);
+                    }
+                });
+            });
+        if let Some(annotations) = self.apparmor_annotations() {
+            deployment
+                .spec
+                .iter_mut()
+                .flat_map(|s| s.template.metadata.iter_mut())
+                .for_each(|m| m.annotations = Some(annotations.clone()));
+        }
+        if !defaults.annotations.is_empty() {
+            deployment
+                .annotations_mut()
+                .extend(defaults.annotations.clone());
+        }
+        Ok(deployment)
+    }
+
+    /// Image, resources, tolerations and security profile of the echo pods set by the spec, the
+    /// image for the node architectures taking precedence over the plain one
+    fn pod_values(&self) -> Result<EchoPodValues> {
+        let image = self.arch_image().map(|(image, _)| image);
+        serde_json::from_value(json!({
+            "image": image.or_else(|| self.spec.image.clone()),
+            "resources": self.spec.resources,
+            "tolerations": self.spec.tolerations,
+            "securityProfile": self.spec.security_profile.as_ref().map(SecurityProfile::from),
+        }))
+        .map_err(Error::SerializationError)
+    }
+
+    /// Pull policy of the echo image, left to the Kubernetes default when unset so the apply does
+    /// not own the defaulted field
+    fn image_pull_policy(&self) -> Option<String> {
+        let policy = match self.spec.image_pull_policy.as_ref()? {
+            EchoImagePullPolicy::Always => "Always",
+            EchoImagePullPolicy::IfNotPresent => "IfNotPresent",
+            EchoImagePullPolicy::Never => "Never",
+        };
+        Some(policy.to_owned())
+    }
+
+    /// Deployment manifest managed by the Echo
+    fn deployment(&self, replicas: i32) -> Deployment {
+        let owner_references = self.child_owner_references();
 
         let name = self.name_any();
         let labels: BTreeMap<String, String> = self
@@ -64,18 +574,16 @@ impl Echo {
             ])
             .collect();
 
-        ctx.metrics
-            .spec_replicas_set(&namespace, &name, self.spec.replicas);
-        let deployment = Deployment {
+        Deployment {
             metadata: ObjectMeta {
                 name: Some(self.name_any()),
-                namespace: Some(namespace),
+                namespace: self.namespace(),
                 labels: Some(labels.clone()),
                 owner_references,
                 ..ObjectMeta::default()
             },
             spec: Some(DeploymentSpec {
-                replicas: Some(self.spec.replicas),
+                replicas: Some(replicas),
                 selector: LabelSelector {
                     match_expressions: None,
                     match_labels: Some(labels.clone()),
@@ -84,9 +592,9 @@ impl Echo {
                     spec: Some(PodSpec {
                         containers: vec![Container {
                             name: self.name_any(),
-                            image: Some("inanimate/echo-server:latest".to_owned()),
+                            image: Some(DEFAULT_IMAGE.to_owned()),
                             ports: Some(vec![ContainerPort {
-                                container_port: 8080,
+                                container_port: ECHO_PORT,
                                 ..ContainerPort::default()
                             }]),
                             ..Container::default()
@@ -101,174 +609,265 @@ impl Echo {
                 ..DeploymentSpec::default()
             }),
             ..Deployment::default()
-        };
+        }
+    }
 
-        let result = deployment_api
-            .patch(
-                &self.name_any(),
-                &PatchParams::apply("echoes.example.com").force(),
-                &Patch::Apply(&deployment),
-            )
-            .await;
-        match result {
-            Ok(deployment) => Ok(deployment),
-            Err(e) => {
-                match e {
-                    kube::Error::Api(ae) if ae.code == 422 => {
-                        info!(msg = "recreating Deployment because the update operation wasn't possible", reason=ae.reason);
-                        self.delete_deployment(ctx.client.clone()).await?;
-                        ctx.metrics.reconcile_deploy_delete_create_inc();
-                        deployment_api
-                            .patch(
-                                &self.name_any(),
-                                &PatchParams::apply("echoes.example.com").force(),
-                                &Patch::Apply(&deployment),
-                            )
-                            .await
-                            .map_err(Error::KubeError)
-                    }
-                    _ => Err(Error::KubeError(e)),
-                }
-            }
+    /// Re-check the namespace quotas, as the Echo could be admitted before the quota existed or
+    /// with the admission webhook disabled
+    async fn check_quotas(&self, ctx: Arc<Context>, replicas: i32) -> Result<()> {
+        let namespace = self.get_namespace();
+        let deployment_ref =
+            ObjectRef::<Deployment>::new_with(&self.name_any(), ()).within(&namespace);
+        let current_replicas = timings::measure_sync(Phase::Store, || {
+            ctx.stores
+                .get::<Deployment>()
+                // safe unwrap: deployment store should exists
+                .unwrap()
+                .get(&deployment_ref)
+        })
+        .and_then(|d| d.spec.as_ref().and_then(|s| s.replicas));
+        // scale downs are always allowed, so over quota namespaces can recover
+        if current_replicas.is_some_and(|current| replicas <= current) {
+            return Ok(());
         }
+
+        let quotas = Api::<EchoQuota>::all(ctx.client.clone())
+            .list(&ListParams::default())
+            .await
+            .map_err(Error::KubeError)?;
+        // quota status already accounts this Echo spec
+        quotas
+            .iter()
+            .filter(|q| q.applies_to(&namespace))
+            .filter_map(|q| q.status_usage(&namespace).map(|usage| (q, usage)))
+            .try_for_each(|(q, usage)| q.check(&namespace, &usage))?;
+        Ok(())
     }
 
+    #[instrument(skip_all, fields(namespace = %self.get_namespace(), name = %self.name_any()), err)]
     async fn delete_deployment(&self, client: Client) -> Result<(), Error> {
         let deployment_api = Api::<Deployment>::namespaced(client, &self.get_namespace());
         deployment_api
             .delete(&self.name_any(), &Default::default())
             .await
-            .map_err(Error::KubeError)?;
+            .map_err(Error::KubeError)
+            .with_context(|| {
+                ErrorContext::new::<Deployment>(
+                    Operation::Delete,
+                    self.namespace(),
+                    &self.name_any(),
+                )
+            })?;
         Ok(())
     }
 
-    async fn update_status(&self, ctx: Arc<Context<Deployment>>) -> Result<()> {
-        let namespace = &self.get_namespace();
-        let deployment_ref =
-            ObjectRef::<Deployment>::new_with(&self.name_any(), ()).within(namespace);
-        debug!(msg = "getting deployment");
-        let deployment = ctx
-            .stores
-            .get("deployment")
-            // safe unwrap: deployment store should exists
-            .unwrap()
-            .get(&deployment_ref)
-            .ok_or_else(|| Error::MissingObject("deployment"))?;
-        let owner = deployment
-            .metadata
-            .owner_references
-            .as_ref()
-            .and_then(|refs| refs.iter().find(|r| r.controller == Some(true)))
-            .ok_or_else(|| Error::MissingObjectKey("ownerReferences"))?;
-
-        let deployment_status = deployment
-            .status
-            .as_ref()
-            .ok_or_else(|| Error::MissingObjectKey("status"))?;
+    /// Aggregate the status of the Deployment into the Echo status
+    #[instrument(
+        skip_all,
+        fields(namespace = %self.get_namespace(), name = %self.name_any(), conditions),
+        err
+    )]
+    pub(crate) async fn update_status(
+        &self,
+        ctx: &Context,
+        deployment: &Deployment,
+        scheduled: &ScheduledReplicas,
+    ) -> Result<()> {
+        let deployment_status = deployment.status.as_ref().ok_or_else(|| {
+            Error::MissingObjectKey("status")
+                .context(ErrorContext::of(Operation::StatusPatch, self))
+        })?;
 
-        let new_status = self.generate_status(deployment_status, deployment.metadata.generation);
+        let status = self.generate_status(
+            deployment_status,
+            deployment.metadata.generation,
+            ctx.clock.now(),
+        );
+        let new_status = EchoStatus {
+            active_schedule: scheduled.active_schedule.clone(),
+            history: self.applied_history(deployment),
+            ..status
+        };
 
-        let new_status_patch = Patch::Apply(json!({
+        let new_status_patch = json!({
             "apiVersion": "example.com/v1",
             "kind": "Echo",
             "status": new_status
-        }));
+        });
         debug!(msg = "updating Echo status");
         trace!(msg = format!("new status {:?}", new_status_patch));
-        let patch = PatchParams::apply("echoes.example.com").force();
-        let echo_api = Api::<Echo>::namespaced(ctx.client.clone(), namespace);
-        let _o = echo_api
-            .patch_status(&owner.name, &patch, &new_status_patch)
+        ctx.status_batcher
+            .patch(
+                &ctx.client,
+                &ctx.metrics,
+                &self.get_namespace(),
+                &self.name_any(),
+                new_status_patch,
+            )
             .await
-            .map_err(Error::KubeError)?;
+            .with_context(|| ErrorContext::of(Operation::StatusPatch, self))?;
+        let conditions: Vec<&str> = new_status
+            .conditions
+            .iter()
+            .flatten()
+            .map(|c| c.type_.as_str())
+            .collect();
+        let conditions = conditions.join(",");
+        Span::current().record("conditions", conditions.as_str());
+        self.audit(ctx, AuditAction::StatusPatch, "Echo", conditions)
+            .await;
+        self.notify_health(ctx, deployment_status);
         Ok(())
     }
 
-    /// Generate the EchoStatus based on the deployment status
-    fn generate_status(
+    /// Notify when the Echo becomes Ready, NotReady or Degraded
+    fn notify_health(&self, ctx: &Context, deployment_status: &DeploymentStatus) {
+        let previous = self
+            .status
+            .as_ref()
+            .filter(|s| s.conditions.is_some())
+            .map(|s| {
+                if s.is_ready() {
+                    Health::Ready
+                } else {
+                    Health::NotReady
+                }
+            });
+        let notification = Notification {
+            namespace: self.get_namespace(),
+            name: self.name_any(),
+            health: Echo::determine_health(deployment_status),
+            message: format!(
+                "{}/{} replicas ready",
+                deployment_status.ready_replicas.unwrap_or_default(),
+                deployment_status.replicas.unwrap_or_default()
+            ),
+        };
+        if let Some(notification) = ctx.notifier.observe(notification, previous) {
+            info!(msg = "echo health changed", health = %notification.health);
+            ctx.notifier.send(notification, ctx.metrics.clone());
+        }
+    }
+
+    /// Degraded when the deployment stopped progressing or failed to create replicas
+    fn determine_health(deployment_status: &DeploymentStatus) -> Health {
+        let degraded = deployment_status.conditions.iter().flatten().any(|c| {
+            (c.type_ == "Progressing" && c.status == "False")
+                || (c.type_ == "ReplicaFailure" && c.status == "True")
+        });
+        if degraded {
+            Health::Degraded
+        } else if Echo::determine_status_type(deployment_status) == ConditionType::Ready {
+            Health::Ready
+        } else {
+            Health::NotReady
+        }
+    }
+
+    /// Generate the EchoStatus based on the deployment status, observing the Echo generation so
+    /// GitOps health checks wait for the status of the applied spec
+    pub fn generate_status(
         &self,
         deployment_status: &DeploymentStatus,
         deployment_metadata_generation: Option<i64>,
+        now: DateTime<Utc>,
     ) -> EchoStatus {
-        let status_type = Echo::determine_status_type(deployment_status);
-
-        // Create a new condition with the current status
-        let new_condition = Condition {
-            type_: status_type.to_string(),
-            status: "True".to_string(),
-            reason: "".to_string(),
-            message: "".to_string(),
-            last_transition_time: Time(Utc::now()),
-            observed_generation: deployment_metadata_generation,
+        // the Deployment status describes a previous spec until its controller observes it
+        let deployment_observed = !matches!(
+            deployment_status.observed_generation.zip(deployment_metadata_generation),
+            Some((observed, generation)) if observed < generation
+        );
+        let status_type = if deployment_observed {
+            Echo::determine_status_type(deployment_status)
+        } else {
+            ConditionType::Progressing
         };
 
-        let conditions = self.update_conditions(&new_condition, status_type);
-
         EchoStatus {
             available_replicas: deployment_status.available_replicas,
-            observed_generation: deployment_metadata_generation,
+            observed_generation: self.metadata.generation,
             ready_replicas: deployment_status.ready_replicas,
             replicas: deployment_status.replicas,
             updated_replicas: deployment_status.updated_replicas,
-            conditions: Some(conditions),
+            conditions: Some(self.update_conditions(status_type, now)),
+            ..EchoStatus::default()
         }
     }
 
     /// Determine the status type based on the deployment status
-    fn determine_status_type(deployment_status: &DeploymentStatus) -> &str {
+    pub(crate) fn determine_status_type(deployment_status: &DeploymentStatus) -> ConditionType {
         if deployment_status.replicas == deployment_status.updated_replicas
             && deployment_status.replicas == deployment_status.ready_replicas
         {
-            STATUS_READY
+            ConditionType::Ready
         } else {
-            STATUS_PROGRESSING
+            ConditionType::Progressing
         }
     }
 
-    /// Update conditions based on the current status and previous conditions in the Echo
-    fn update_conditions(&self, new_condition: &Condition, status_type: &str) -> Vec<Condition> {
-        match self.status.as_ref().and_then(|s| s.conditions.as_ref()) {
-            // Remove the 'Ready' condition if we are 'Progressing'
-            Some(previous_conditions) if status_type == STATUS_PROGRESSING => previous_conditions
-                .iter()
-                .filter(|c| c.type_ != STATUS_READY)
-                .cloned()
-                .chain(std::iter::once(new_condition.clone()))
-                .collect(),
-
-            // Add the new condition if it's not already present
-            Some(previous_conditions)
-                if !previous_conditions.iter().any(|c| c.type_ == *status_type) =>
-            {
-                previous_conditions
-                    .iter()
-                    .cloned()
-                    .chain(std::iter::once(new_condition.clone()))
-                    .collect()
+    /// Update the Ready and Progressing conditions from the status type, keeping the rest of the
+    /// previous conditions and the transition time of the conditions whose status is unchanged.
+    ///
+    /// Ready is `False` while progressing instead of missing, as GitOps health checks consider
+    /// resources without a Ready condition healthy.
+    fn update_conditions(&self, status_type: ConditionType, now: DateTime<Utc>) -> Vec<Condition> {
+        let mut status = self.status.clone().unwrap_or_default();
+        let condition = |status: &EchoStatus, type_: ConditionType, value: bool, reason: &str| {
+            let value = if value { "True" } else { "False" };
+            let last_transition_time = status
+                .condition(type_)
+                .filter(|c| c.status == value)
+                .map_or(Time(now), |c| c.last_transition_time.clone());
+            Condition {
+                type_: type_.to_string(),
+                status: value.to_string(),
+                reason: reason.to_string(),
+                message: "".to_string(),
+                last_transition_time,
+                observed_generation: self.metadata.generation,
             }
+        };
 
-            // Otherwise, keep the existing conditions unchanged
-            Some(previous_conditions) => previous_conditions.clone(),
-
-            // No previous conditions; start fresh with the new condition
-            None => vec![new_condition.clone()],
+        if status_type == ConditionType::Progressing {
+            let ready = condition(&status, ConditionType::Ready, false, "Progressing");
+            let progressing = condition(&status, ConditionType::Progressing, true, "");
+            status.set_condition(ready);
+            status.set_condition(progressing);
+        } else {
+            let ready = condition(&status, ConditionType::Ready, true, "");
+            status.set_condition(ready);
+            if status.condition(ConditionType::Progressing).is_some() {
+                let progressing = condition(&status, ConditionType::Progressing, false, "Ready");
+                status.set_condition(progressing);
+            }
         }
+        status.conditions.unwrap_or_default()
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{reconcile_echo, Echo, STATUS_PROGRESSING, STATUS_READY};
+    use super::{reconcile_echo, Defaults, Echo, ReconcileOutcome};
 
-    use crate::crd::echo::EchoStatus;
-    use crate::echo::test::get_test_context;
-    use crate::echo::test::{timeout_after_1s, Scenario};
+    use crate::clusterdefaults::{ClusterDefaults, EchoPodValues, SecurityProfile};
+    use crate::crd::echo::{EchoAutoscaling, EchoImagePullPolicy, EchoRollout, EchoStatus};
+    use crate::echo::condition::ConditionType;
+    use crate::error::Error;
+    use crate::metrics::ControllerLabels;
+    use crate::notify::Health;
+    use crate::test_utils::{get_test_context, test_time};
+    use crate::test_utils::{timeout_after_1s, Fault, Scenario};
 
+    use std::collections::BTreeMap;
     use std::sync::Arc;
 
-    use chrono::Utc;
-    use k8s_openapi::api::apps::v1::DeploymentStatus;
+    use chrono::Duration;
+    use k8s_openapi::api::apps::v1::{DeploymentCondition, DeploymentStatus};
+    use k8s_openapi::api::core::v1::{LocalObjectReference, Toleration};
     use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+    use kube::runtime::controller::Action;
+    use kube::{Resource, ResourceExt};
+    use serde_json::json;
 
     #[tokio::test]
     async fn echo_create() {
@@ -304,6 +903,425 @@ mod test {
         timeout_after_1s(mocksrv).await;
     }
 
+    #[tokio::test]
+    async fn echo_reconcile_requeues_after_5_minutes() {
+        let (testctx, fakeserver) = get_test_context();
+        let echo = Echo::test(None);
+        let mocksrv = fakeserver.run(Scenario::EchoPatch(echo.clone()));
+        let action = reconcile_echo(Arc::new(echo), testctx)
+            .await
+            .expect("reconciler");
+        timeout_after_1s(mocksrv).await;
+        assert_eq!(
+            action,
+            Action::requeue(std::time::Duration::from_secs(5 * 60))
+        );
+    }
+
+    #[tokio::test]
+    async fn echo_patch_conflict_fails() {
+        let (testctx, fakeserver) = get_test_context();
+        let echo = Echo::test(None);
+        let mocksrv = fakeserver.run(Scenario::EchoPatchFault(
+            echo.clone(),
+            Fault::Status(409, "Conflict"),
+        ));
+        let result = reconcile_echo(Arc::new(echo), testctx).await;
+        timeout_after_1s(mocksrv).await;
+        assert!(
+            matches!(result.as_ref().map_err(Error::root), Err(Error::KubeError(kube::Error::Api(ae))) if ae.code == 409),
+            "conflict error"
+        );
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("apply Deployment default/test: "));
+    }
+
+    #[tokio::test]
+    async fn echo_patch_throttled_fails() {
+        let (testctx, fakeserver) = get_test_context();
+        let echo = Echo::test(None);
+        let mocksrv = fakeserver.run(Scenario::EchoPatchFault(
+            echo.clone(),
+            Fault::Status(429, "TooManyRequests"),
+        ));
+        let result = reconcile_echo(Arc::new(echo), testctx).await;
+        timeout_after_1s(mocksrv).await;
+        assert!(
+            matches!(result.as_ref().map_err(Error::root), Err(Error::KubeError(kube::Error::Api(ae))) if ae.code == 429),
+            "throttling error"
+        );
+    }
+
+    #[tokio::test]
+    async fn echo_patch_malformed_response_fails() {
+        let (testctx, fakeserver) = get_test_context();
+        let echo = Echo::test(None);
+        let mocksrv = fakeserver.run(Scenario::EchoPatchFault(echo.clone(), Fault::MalformedBody));
+        let result = reconcile_echo(Arc::new(echo), testctx).await;
+        timeout_after_1s(mocksrv).await;
+        assert!(
+            matches!(
+                result.as_ref().map_err(Error::root),
+                Err(Error::KubeError(kube::Error::SerdeError(_)))
+            ),
+            "deserialization error"
+        );
+    }
+
+    #[tokio::test]
+    async fn echo_patch_without_response_fails() {
+        let (testctx, fakeserver) = get_test_context();
+        let echo = Echo::test(None);
+        let mocksrv = fakeserver.run(Scenario::EchoPatchFault(echo.clone(), Fault::NoResponse));
+        let result = reconcile_echo(Arc::new(echo), testctx).await;
+        timeout_after_1s(mocksrv).await;
+        assert!(
+            matches!(
+                result.as_ref().map_err(Error::root),
+                Err(Error::KubeError(kube::Error::Service(_)))
+            ),
+            "service error"
+        );
+    }
+
+    #[tokio::test]
+    async fn echo_patch_delayed_response_succeeds() {
+        let (testctx, fakeserver) = get_test_context();
+        let echo = Echo::test(None);
+        let mocksrv = fakeserver.run(Scenario::EchoPatchDelayed(
+            echo.clone(),
+            std::time::Duration::from_millis(200),
+        ));
+        reconcile_echo(Arc::new(echo), testctx)
+            .await
+            .expect("reconciler");
+        timeout_after_1s(mocksrv).await;
+    }
+
+    #[tokio::test]
+    async fn echo_patch_unprocessable_recreates_deployment() {
+        let (testctx, fakeserver) = get_test_context();
+        let echo = Echo::test(None);
+        let mocksrv = fakeserver.run(Scenario::EchoRecreate(echo.clone()));
+        reconcile_echo(Arc::new(echo), testctx.clone())
+            .await
+            .expect("reconciler");
+        timeout_after_1s(mocksrv).await;
+        let recreations = testctx
+            .metrics
+            .reconcile
+            .deploy_delete_create
+            .get_or_create(&ControllerLabels {
+                controller: String::new(),
+            })
+            .get();
+        assert_eq!(recreations, 1);
+    }
+
+    #[test]
+    fn test_generate_status_new_condition_transition_time() {
+        let deployment_status = DeploymentStatus {
+            available_replicas: Some(1),
+            ready_replicas: Some(1),
+            replicas: Some(1),
+            updated_replicas: Some(1),
+            ..Default::default()
+        };
+        let echo = Echo::test(None);
+
+        let result = echo.generate_status(&deployment_status, Some(1), test_time());
+
+        let conditions = result.conditions.unwrap();
+        assert_eq!(conditions[0].last_transition_time, Time(test_time()));
+    }
+
+    #[test]
+    fn test_generate_status_keeps_transition_time_when_unchanged() {
+        let deployment_status = DeploymentStatus {
+            available_replicas: Some(1),
+            ready_replicas: Some(1),
+            replicas: Some(1),
+            updated_replicas: Some(1),
+            ..Default::default()
+        };
+        let previous_transition_time = Time(test_time() - Duration::hours(1));
+        let echo_status = EchoStatus {
+            conditions: Some(vec![Condition {
+                type_: ConditionType::Ready.to_string(),
+                status: "True".to_string(),
+                reason: "".to_string(),
+                message: "".to_string(),
+                last_transition_time: previous_transition_time.clone(),
+                observed_generation: Some(1),
+            }]),
+            ..Default::default()
+        };
+        let echo = Echo::test(Some(echo_status));
+
+        let result = echo.generate_status(&deployment_status, Some(1), test_time());
+
+        let conditions = result.conditions.unwrap();
+        assert_eq!(conditions.len(), 1);
+        assert_eq!(conditions[0].last_transition_time, previous_transition_time);
+    }
+
+    #[test]
+    fn test_determine_health() {
+        let ready = DeploymentStatus {
+            ready_replicas: Some(1),
+            replicas: Some(1),
+            updated_replicas: Some(1),
+            ..Default::default()
+        };
+        let not_ready = DeploymentStatus {
+            ready_replicas: Some(0),
+            ..ready.clone()
+        };
+        let degraded = DeploymentStatus {
+            conditions: Some(vec![DeploymentCondition {
+                type_: "Progressing".to_string(),
+                status: "False".to_string(),
+                reason: Some("ProgressDeadlineExceeded".to_string()),
+                ..Default::default()
+            }]),
+            ..not_ready.clone()
+        };
+
+        assert_eq!(Echo::determine_health(&ready), Health::Ready);
+        assert_eq!(Echo::determine_health(&not_ready), Health::NotReady);
+        assert_eq!(Echo::determine_health(&degraded), Health::Degraded);
+    }
+
+    #[test]
+    fn test_generate_deployment_snapshots() {
+        let mut labeled = Echo::test(None);
+        labeled
+            .labels_mut()
+            .insert("team".to_string(), "platform".to_string());
+        let mut owned = Echo::test(None);
+        owned.meta_mut().uid = Some("f2a1c5de-0000-4000-8000-000000000001".to_string());
+        let mut autoscaled = Echo::test(None);
+        autoscaled.spec.autoscaling = Some(EchoAutoscaling {
+            min_replicas: Some(2),
+            max_replicas: 5,
+            ..EchoAutoscaling::default()
+        });
+        let mut pinned_image = Echo::test(None);
+        pinned_image.spec.image = Some("registry.example.com/echo-server:1.2".to_string());
+        pinned_image.spec.image_pull_policy = Some(EchoImagePullPolicy::IfNotPresent);
+
+        for (name, echo) in [
+            ("deployment_default", Echo::test(None)),
+            ("deployment_replicas", Echo::test(None).change_replicas(3)),
+            ("deployment_labels", labeled),
+            ("deployment_owner_reference", owned),
+            ("deployment_autoscaled", autoscaled),
+            ("deployment_pinned_image", pinned_image),
+        ] {
+            let deployment = echo.generate_deployment(&Defaults::default()).unwrap();
+            insta::assert_json_snapshot!(name, deployment);
+        }
+    }
+
+    #[test]
+    fn test_generate_deployment() {
+        let echo = Echo::test(None);
+        let defaults = Defaults {
+            replicas: Some(5),
+            image_pull_secrets: Some(vec![LocalObjectReference {
+                name: "test-registry".to_string(),
+            }]),
+            annotations: BTreeMap::from([("team".to_string(), "platform".to_string())]),
+            ..Defaults::default()
+        };
+        let deployment = echo.generate_deployment(&defaults).unwrap();
+        assert_eq!(deployment.spec.as_ref().unwrap().replicas, Some(5));
+
+        let mut autoscaled = echo.clone();
+        autoscaled.spec.autoscaling = Some(EchoAutoscaling {
+            max_replicas: 5,
+            ..EchoAutoscaling::default()
+        });
+        let deployment = autoscaled.generate_deployment(&defaults).unwrap();
+        assert_eq!(deployment.spec.as_ref().unwrap().replicas, None);
+        assert_eq!(
+            deployment.annotations().get("team").map(String::as_str),
+            Some("platform")
+        );
+        let pod_spec = deployment.spec.unwrap().template.spec.unwrap();
+        assert_eq!(pod_spec.image_pull_secrets, defaults.image_pull_secrets);
+    }
+
+    #[test]
+    fn test_generate_deployment_scheduling() {
+        let mut echo = Echo::test(None);
+        echo.spec.node_selector = Some(BTreeMap::from([("pool".to_string(), "echo".to_string())]));
+        echo.spec.topology_spread_constraints = Some(
+            serde_json::from_value(json!([{
+                "maxSkew": 1,
+                "topologyKey": "kubernetes.io/hostname",
+                "whenUnsatisfiable": "DoNotSchedule",
+            }]))
+            .unwrap(),
+        );
+        let pod_spec = echo
+            .generate_deployment(&Defaults::default())
+            .unwrap()
+            .spec
+            .unwrap()
+            .template
+            .spec
+            .unwrap();
+        assert_eq!(pod_spec.node_selector, echo.spec.node_selector);
+        assert_eq!(
+            pod_spec.topology_spread_constraints,
+            echo.topology_spread_constraints().unwrap()
+        );
+        assert_eq!(pod_spec.affinity, None);
+
+        echo.spec.affinity = Some(serde_json::from_value(json!({"podAffinity": []})).unwrap());
+        assert!(echo.generate_deployment(&Defaults::default()).is_err());
+    }
+
+    #[test]
+    fn test_generate_deployment_cluster_defaults() {
+        let mut echo = Echo::test(None);
+        echo.spec.image = Some("echo:spec".to_string());
+        let toleration = Toleration {
+            key: Some("spot".to_string()),
+            operator: Some("Exists".to_string()),
+            ..Toleration::default()
+        };
+        let defaults = Defaults {
+            cluster: ClusterDefaults {
+                defaults: EchoPodValues {
+                    image: Some("echo:default".to_string()),
+                    tolerations: Some(vec![toleration.clone()]),
+                    ..EchoPodValues::default()
+                },
+                ..ClusterDefaults::default()
+            },
+            ..Defaults::default()
+        };
+        let pod_spec = echo
+            .generate_deployment(&defaults)
+            .unwrap()
+            .spec
+            .unwrap()
+            .template
+            .spec
+            .unwrap();
+        assert_eq!(pod_spec.containers[0].image.as_deref(), Some("echo:spec"));
+        assert_eq!(pod_spec.tolerations, Some(vec![toleration]));
+    }
+
+    #[test]
+    fn test_generate_deployment_profile() {
+        let mut echo = Echo::test(None);
+        echo.spec.profile = Some("hardened".to_string());
+        assert!(matches!(
+            echo.generate_deployment(&Defaults::default()),
+            Err(Error::InvalidSpec(_))
+        ));
+
+        let defaults = Defaults {
+            cluster: ClusterDefaults {
+                profiles: BTreeMap::from([(
+                    "hardened".to_string(),
+                    EchoPodValues {
+                        image: Some("echo:hardened".to_string()),
+                        security_profile: Some(SecurityProfile::Restricted),
+                        ..EchoPodValues::default()
+                    },
+                )]),
+                ..ClusterDefaults::default()
+            },
+            ..Defaults::default()
+        };
+        let pod_spec = echo
+            .generate_deployment(&defaults)
+            .unwrap()
+            .spec
+            .unwrap()
+            .template
+            .spec
+            .unwrap();
+        assert_eq!(
+            pod_spec.containers[0].image.as_deref(),
+            Some("echo:hardened")
+        );
+        assert_eq!(
+            pod_spec.security_context.and_then(|s| s.run_as_non_root),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn test_generate_deployment_image_pull_policy() {
+        let mut echo = Echo::test(None);
+        let container = |echo: &Echo| {
+            echo.generate_deployment(&Defaults::default())
+                .unwrap()
+                .spec
+                .unwrap()
+                .template
+                .spec
+                .unwrap()
+                .containers
+                .remove(0)
+        };
+        assert!(container(&echo).image_pull_policy.is_none());
+
+        echo.spec.image = Some("registry.example.com/echo-server:1.2".to_string());
+        echo.spec.image_pull_policy = Some(EchoImagePullPolicy::IfNotPresent);
+        let container = container(&echo);
+        assert_eq!(
+            container.image.as_deref(),
+            Some("registry.example.com/echo-server:1.2")
+        );
+        assert_eq!(container.image_pull_policy.as_deref(), Some("IfNotPresent"));
+    }
+
+    #[test]
+    fn test_generate_deployment_image_per_arch() {
+        let mut echo = Echo::test(None);
+        echo.spec.image = Some("echo:spec".to_string());
+        echo.spec.image_per_arch = Some(BTreeMap::from([
+            ("amd64".to_string(), "echo:multi-arch".to_string()),
+            ("arm64".to_string(), "echo:multi-arch".to_string()),
+        ]));
+        let pod_spec = echo
+            .generate_deployment(&Defaults::default())
+            .unwrap()
+            .spec
+            .unwrap()
+            .template
+            .spec
+            .unwrap();
+        assert_eq!(
+            pod_spec.containers[0].image.as_deref(),
+            Some("echo:multi-arch")
+        );
+        assert_eq!(pod_spec.affinity, echo.arch_affinity());
+        assert!(pod_spec.affinity.is_some());
+    }
+
+    #[test]
+    fn test_generate_deployment_progress_deadline() {
+        let mut echo = Echo::test(None);
+        echo.spec.rollout = Some(EchoRollout {
+            progress_deadline: Some("10m".to_string()),
+            auto_rollback: None,
+        });
+        let deployment = echo.generate_deployment(&Defaults::default()).unwrap();
+        assert_eq!(
+            deployment.spec.unwrap().progress_deadline_seconds,
+            Some(600)
+        );
+    }
+
     #[test]
     fn test_generate_status_ready() {
         let deployment_status = DeploymentStatus {
@@ -315,9 +1333,14 @@ mod test {
         };
 
         let deployment_metadata_generation = Some(1);
-        let echo = Echo::test(None);
+        let mut echo = Echo::test(None);
+        echo.metadata.generation = Some(1);
 
-        let result = echo.generate_status(&deployment_status, deployment_metadata_generation);
+        let result = echo.generate_status(
+            &deployment_status,
+            deployment_metadata_generation,
+            test_time(),
+        );
 
         assert_eq!(result.available_replicas, Some(3));
         assert_eq!(result.ready_replicas, Some(3));
@@ -327,7 +1350,7 @@ mod test {
 
         let conditions = result.conditions.unwrap();
         assert_eq!(conditions.len(), 1);
-        assert_eq!(conditions[0].type_, STATUS_READY);
+        assert_eq!(conditions[0].type_, ConditionType::Ready.as_str());
     }
 
     #[test]
@@ -341,9 +1364,14 @@ mod test {
         };
 
         let deployment_metadata_generation = Some(2);
-        let echo = Echo::test(None);
+        let mut echo = Echo::test(None);
+        echo.metadata.generation = Some(2);
 
-        let result = echo.generate_status(&deployment_status, deployment_metadata_generation);
+        let result = echo.generate_status(
+            &deployment_status,
+            deployment_metadata_generation,
+            test_time(),
+        );
 
         assert_eq!(result.available_replicas, Some(2));
         assert_eq!(result.ready_replicas, Some(2));
@@ -351,9 +1379,12 @@ mod test {
         assert_eq!(result.updated_replicas, Some(2));
         assert_eq!(result.observed_generation, Some(2));
 
-        let conditions = result.conditions.unwrap();
-        assert_eq!(conditions.len(), 1);
-        assert_eq!(conditions[0].type_, STATUS_PROGRESSING);
+        assert_eq!(result.conditions.as_ref().unwrap().len(), 2);
+        let ready = result.condition(ConditionType::Ready).unwrap();
+        assert_eq!(ready.status, "False");
+        assert_eq!(ready.observed_generation, Some(2));
+        let progressing = result.condition(ConditionType::Progressing).unwrap();
+        assert_eq!(progressing.status, "True");
     }
 
     #[test]
@@ -370,11 +1401,11 @@ mod test {
 
         // Previous condition with a different type (Progressing)
         let previous_conditions = vec![Condition {
-            type_: STATUS_PROGRESSING.to_string(),
+            type_: ConditionType::Progressing.to_string(),
             status: "True".to_string(),
             reason: "".to_string(),
             message: "".to_string(),
-            last_transition_time: Time(Utc::now()),
+            last_transition_time: Time(test_time() - Duration::hours(1)),
             observed_generation: Some(1),
         }];
 
@@ -385,12 +1416,20 @@ mod test {
 
         let echo = Echo::test(Some(echo_status));
 
-        let result = echo.generate_status(&deployment_status, deployment_metadata_generation);
+        let result = echo.generate_status(
+            &deployment_status,
+            deployment_metadata_generation,
+            test_time(),
+        );
 
         let conditions = result.conditions.unwrap();
         assert_eq!(conditions.len(), 2);
-        assert!(conditions.iter().any(|c| c.type_ == STATUS_READY));
-        assert!(conditions.iter().any(|c| c.type_ == STATUS_PROGRESSING));
+        assert!(conditions
+            .iter()
+            .any(|c| c.type_ == ConditionType::Ready.as_str()));
+        assert!(conditions
+            .iter()
+            .any(|c| c.type_ == ConditionType::Progressing.as_str() && c.status == "False"));
     }
 
     #[test]
@@ -407,11 +1446,11 @@ mod test {
 
         // Previous condition with type Ready
         let previous_conditions = vec![Condition {
-            type_: STATUS_READY.to_string(),
+            type_: ConditionType::Ready.to_string(),
             status: "True".to_string(),
             reason: "".to_string(),
             message: "".to_string(),
-            last_transition_time: Time(Utc::now()),
+            last_transition_time: Time(test_time() - Duration::hours(1)),
             observed_generation: Some(2),
         }];
 
@@ -422,11 +1461,18 @@ mod test {
 
         let echo = Echo::test(Some(echo_status));
 
-        let result = echo.generate_status(&deployment_status, deployment_metadata_generation);
+        let result = echo.generate_status(
+            &deployment_status,
+            deployment_metadata_generation,
+            test_time(),
+        );
 
-        let conditions = result.conditions.unwrap();
-        assert_eq!(conditions.len(), 1);
-        assert!(conditions.iter().all(|c| c.type_ == STATUS_PROGRESSING));
+        assert_eq!(result.conditions.as_ref().unwrap().len(), 2);
+        assert!(!result.is_ready());
+        assert_eq!(
+            result.condition(ConditionType::Progressing).unwrap().status,
+            "True"
+        );
     }
 
     #[test]
@@ -442,10 +1488,114 @@ mod test {
         let deployment_metadata_generation = Some(5);
         let echo = Echo::test(None);
 
-        let result = echo.generate_status(&deployment_status, deployment_metadata_generation);
+        let result = echo.generate_status(
+            &deployment_status,
+            deployment_metadata_generation,
+            test_time(),
+        );
 
-        let conditions = result.conditions.unwrap();
-        assert_eq!(conditions.len(), 1);
-        assert_eq!(conditions[0].type_, STATUS_PROGRESSING);
+        assert_eq!(result.conditions.as_ref().unwrap().len(), 2);
+        assert!(!result.is_ready());
+        assert!(result.condition(ConditionType::Progressing).is_some());
+    }
+
+    #[test]
+    fn test_generate_status_deployment_not_observed() {
+        let deployment_status = DeploymentStatus {
+            available_replicas: Some(3),
+            ready_replicas: Some(3),
+            replicas: Some(3),
+            updated_replicas: Some(3),
+            observed_generation: Some(1),
+            ..Default::default()
+        };
+        let echo = Echo::test(None);
+
+        let result = echo.generate_status(&deployment_status, Some(2), test_time());
+        assert!(!result.is_ready());
+
+        let result = echo.generate_status(&deployment_status, Some(1), test_time());
+        assert!(result.is_ready());
+    }
+
+    mod proptests {
+        use super::super::Echo;
+
+        use crate::echo::condition::ConditionType;
+        use crate::test_utils::test_time;
+
+        use std::collections::HashSet;
+
+        use chrono::Duration;
+        use k8s_openapi::api::apps::v1::DeploymentStatus;
+        use proptest::prelude::*;
+
+        fn deployment_status() -> impl Strategy<Value = DeploymentStatus> {
+            (0..4i32, 0..4i32, 0..4i32).prop_map(|(replicas, updated, ready)| DeploymentStatus {
+                available_replicas: Some(ready),
+                ready_replicas: Some(ready),
+                replicas: Some(replicas),
+                updated_replicas: Some(updated),
+                ..Default::default()
+            })
+        }
+
+        /// Sequence of deployment statuses with their (non decreasing) deployment generation
+        fn transitions() -> impl Strategy<Value = Vec<(DeploymentStatus, i64)>> {
+            prop::collection::vec((deployment_status(), 0..2i64), 1..20).prop_map(|steps| {
+                steps
+                    .into_iter()
+                    .scan(1, |generation, (status, increment)| {
+                        *generation += increment;
+                        Some((status, *generation))
+                    })
+                    .collect()
+            })
+        }
+
+        proptest! {
+            #[test]
+            fn test_generate_status_invariants(steps in transitions()) {
+                let mut echo = Echo::test(None);
+                let mut previous_generation = None;
+
+                for (i, (deployment_status, generation)) in steps.into_iter().enumerate() {
+                    let now = test_time() + Duration::seconds(i as i64);
+                    echo.metadata.generation = Some(generation);
+                    let status = echo.generate_status(&deployment_status, Some(generation), now);
+                    let conditions = status.conditions.clone().unwrap_or_default();
+
+                    let ready = conditions.iter().filter(|c| c.type_ == ConditionType::Ready.as_str()).count();
+                    prop_assert!(ready <= 1, "more than one Ready condition: {conditions:?}");
+
+                    let types: HashSet<_> = conditions.iter().map(|c| c.type_.as_str()).collect();
+                    prop_assert_eq!(
+                        types.len(),
+                        conditions.len(),
+                        "duplicated condition types: {:?}",
+                        conditions
+                    );
+
+                    prop_assert!(status.observed_generation >= previous_generation);
+                    previous_generation = status.observed_generation;
+
+                    let is_ready = deployment_status.replicas == deployment_status.updated_replicas
+                        && deployment_status.replicas == deployment_status.ready_replicas;
+                    prop_assert_eq!(ready, 1, "missing Ready condition: {:?}", conditions);
+                    prop_assert_eq!(status.is_ready(), is_ready);
+
+                    echo = echo.with_status(status);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reconcile_outcome_summary() {
+        assert_eq!(ReconcileOutcome::Applied.action(), "applied");
+        assert_eq!(ReconcileOutcome::Applied.reason(), None);
+        let skipped = ReconcileOutcome::Skipped("conflicted");
+        assert_eq!(skipped.action(), "skipped");
+        assert_eq!(skipped.reason(), Some("conflicted"));
     }
 }
@@ -0,0 +1,145 @@
+//! `spec.recreatePolicy`: whether a Deployment rejected as unprocessable can be deleted and
+//! created again, which briefly takes the echo down.
+use crate::controller::Context;
+use crate::crd::echo::{Echo, EchoRecreatePolicy};
+use crate::echo::condition::ConditionType;
+use crate::error::{Error, Result};
+
+use chrono::{DateTime, Utc};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+use kube::api::{Api, Patch, PatchParams};
+use kube::ResourceExt;
+use serde_json::json;
+use tracing::debug;
+
+/// Annotation approving the recreation of the Deployment with the `Manual` policy
+pub(crate) const RECREATE_APPROVED_ANNOTATION: &str = "echoes.example.com/approve-recreate";
+
+impl Echo {
+    fn recreate_approved(&self) -> bool {
+        self.annotations()
+            .get(RECREATE_APPROVED_ANNOTATION)
+            .is_some_and(|v| v == "true")
+    }
+
+    /// Whether the Deployment can be recreated now
+    pub(crate) fn recreate_allowed(&self) -> bool {
+        match self.spec.recreate_policy {
+            None | Some(EchoRecreatePolicy::Auto) => true,
+            Some(EchoRecreatePolicy::Manual) => self.recreate_approved(),
+            Some(EchoRecreatePolicy::Never) => false,
+        }
+    }
+
+    /// RecreateRequired condition reporting the conflict that the policy does not let resolve
+    fn recreate_condition(&self, conflict: &str, now: DateTime<Utc>) -> Condition {
+        let (reason, message) = match self.spec.recreate_policy {
+            Some(EchoRecreatePolicy::Manual) => (
+                "ApprovalRequired",
+                format!(
+                    "{conflict}; annotate the echo with {RECREATE_APPROVED_ANNOTATION}=true to \
+                     recreate the Deployment"
+                ),
+            ),
+            _ => ("RecreateDisabled", conflict.to_owned()),
+        };
+        Condition {
+            type_: ConditionType::RecreateRequired.to_string(),
+            status: "True".to_owned(),
+            reason: reason.to_owned(),
+            message,
+            last_transition_time: Time(now),
+            observed_generation: self.metadata.generation,
+        }
+    }
+
+    /// Report a Deployment conflict that must not be resolved by recreating it
+    pub(crate) async fn report_recreate_required(
+        &self,
+        ctx: &Context,
+        conflict: &str,
+    ) -> Result<()> {
+        let condition = self.recreate_condition(conflict, ctx.clock.now());
+        // avoid patching the status, and so triggering a new reconciliation, while it is reported
+        if self
+            .condition(ConditionType::RecreateRequired)
+            .is_some_and(|c| c.reason == condition.reason && c.message == condition.message)
+        {
+            return Ok(());
+        }
+        self.patch_condition(ctx, ConditionType::RecreateRequired, Some(condition))
+            .await
+    }
+
+    /// Remove the RecreateRequired condition once the Deployment is applied
+    pub(crate) async fn clear_recreate_required(&self, ctx: &Context) -> Result<()> {
+        if self.condition(ConditionType::RecreateRequired).is_none() {
+            return Ok(());
+        }
+        self.patch_condition(ctx, ConditionType::RecreateRequired, None)
+            .await
+    }
+
+    /// Remove the approval once used, so next recreations must be approved again
+    pub(crate) async fn consume_recreate_approval(&self, ctx: &Context) -> Result<()> {
+        if !self.recreate_approved() {
+            return Ok(());
+        }
+        debug!(msg = "removing recreate approval");
+        Api::<Echo>::namespaced(ctx.client.clone(), &self.get_namespace())
+            .patch(
+                &self.name_any(),
+                &PatchParams::default(),
+                &Patch::Merge(json!({
+                    "metadata": {"annotations": {RECREATE_APPROVED_ANNOTATION: null}}
+                })),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RECREATE_APPROVED_ANNOTATION;
+
+    use crate::crd::echo::{Echo, EchoRecreatePolicy};
+    use crate::echo::condition::ConditionType;
+    use crate::test_utils::test_time;
+
+    use kube::ResourceExt;
+
+    fn echo_with_policy(policy: Option<EchoRecreatePolicy>) -> Echo {
+        let mut echo = Echo::test(None);
+        echo.spec.recreate_policy = policy;
+        echo
+    }
+
+    #[test]
+    fn test_recreate_allowed() {
+        assert!(echo_with_policy(None).recreate_allowed());
+        assert!(echo_with_policy(Some(EchoRecreatePolicy::Auto)).recreate_allowed());
+        assert!(!echo_with_policy(Some(EchoRecreatePolicy::Never)).recreate_allowed());
+
+        let mut echo = echo_with_policy(Some(EchoRecreatePolicy::Manual));
+        assert!(!echo.recreate_allowed());
+        echo.annotations_mut()
+            .insert(RECREATE_APPROVED_ANNOTATION.to_string(), "true".to_string());
+        assert!(echo.recreate_allowed());
+    }
+
+    #[test]
+    fn test_recreate_condition() {
+        let condition = echo_with_policy(Some(EchoRecreatePolicy::Manual))
+            .recreate_condition("field is immutable", test_time());
+        assert_eq!(condition.type_, ConditionType::RecreateRequired.as_str());
+        assert_eq!(condition.reason, "ApprovalRequired");
+        assert!(condition.message.contains(RECREATE_APPROVED_ANNOTATION));
+
+        let condition = echo_with_policy(Some(EchoRecreatePolicy::Never))
+            .recreate_condition("field is immutable", test_time());
+        assert_eq!(condition.reason, "RecreateDisabled");
+        assert_eq!(condition.message, "field is immutable");
+    }
+}
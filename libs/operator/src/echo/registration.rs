@@ -0,0 +1,357 @@
+//! `spec.registration`: enrollment of the echo in an external endpoint registry, e.g. a synthetic
+//! monitoring system probing it.
+//!
+//! The echostatus reconciler registers the echo once its Deployment is Ready, and the echo
+//! reconciler deregisters it, behind a finalizer, when it is deleted or the registration removed.
+//! Failed requests fail the reconciliation, so they are retried with its backoff.
+use crate::audit::AuditAction;
+use crate::controller::Context;
+use crate::crd::echo::Echo;
+use crate::echo::condition::ConditionType;
+use crate::echo::dns::DNS_FINALIZER;
+use crate::echo::service::SERVICE_PORT;
+use crate::error::{Error, Result};
+
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::apps::v1::Deployment;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+use kube::api::{Api, Patch, PatchParams, Resource};
+use kube::ResourceExt;
+use serde_json::{json, Value};
+use tracing::{debug, info};
+
+/// Finalizer keeping the Echo until it is deregistered
+pub(crate) const REGISTRATION_FINALIZER: &str = "echoes.example.com/registration";
+/// Time after which a registry request fails
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+static HTTP: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        // safe unwrap: the client only sets a timeout
+        .unwrap()
+});
+
+impl Echo {
+    fn registration_url(&self) -> Option<&str> {
+        self.spec.registration.as_ref().map(|r| r.url.as_str())
+    }
+
+    fn registered_url(&self) -> Option<&str> {
+        self.status.as_ref()?.registered_url.as_deref()
+    }
+
+    fn has_registration_finalizer(&self) -> bool {
+        self.finalizers()
+            .iter()
+            .any(|f| f == REGISTRATION_FINALIZER)
+    }
+
+    /// Whether the echo must be deregistered, as it is being deleted or its registration was
+    /// removed
+    pub(crate) fn deregistration_pending(&self) -> bool {
+        self.has_registration_finalizer()
+            && (self.meta().deletion_timestamp.is_some() || self.registration_url().is_none())
+    }
+
+    /// Address registered, the `dnsName` of the echo or else the in-cluster address of its Service
+    fn registration_address(&self) -> String {
+        match self.spec.dns_name.as_deref() {
+            Some(name) => {
+                let port = Some(self.service_port())
+                    .filter(|p| *p != SERVICE_PORT)
+                    .map(|p| format!(":{p}"))
+                    .unwrap_or_default();
+                format!("http://{name}{port}/")
+            }
+            None => format!(
+                "http://{}.{}.svc:{}/",
+                self.name_any(),
+                self.get_namespace(),
+                self.service_port()
+            ),
+        }
+    }
+
+    /// Body of the registry requests
+    fn registration(&self) -> Value {
+        json!({
+            "namespace": self.get_namespace(),
+            "name": self.name_any(),
+            "address": self.registration_address(),
+        })
+    }
+
+    /// Add the finalizer of the registration, once the DNS one is set or removed following the
+    /// `dnsName`, so the finalizers patched by the DNS reconciliation in the same pass are not
+    /// overwritten
+    pub(crate) async fn reconcile_registration_finalizer(&self, ctx: &Context) -> Result<()> {
+        let dns_finalizer = self.finalizers().iter().any(|f| f == DNS_FINALIZER);
+        if self.registration_url().is_none()
+            || self.has_registration_finalizer()
+            || dns_finalizer != self.spec.dns_name.is_some()
+        {
+            return Ok(());
+        }
+        let finalizers: Vec<String> = self
+            .finalizers()
+            .iter()
+            .cloned()
+            .chain([REGISTRATION_FINALIZER.to_owned()])
+            .collect();
+        self.patch_finalizers(ctx.client.clone(), finalizers).await
+    }
+
+    /// Register the echo once its Deployment is Ready, replacing a registration in another
+    /// registry
+    pub(crate) async fn reconcile_registration(
+        &self,
+        ctx: &Context,
+        deployment: &Deployment,
+    ) -> Result<()> {
+        let Some(url) = self.registration_url() else {
+            return Ok(());
+        };
+        let ready = deployment
+            .status
+            .as_ref()
+            .is_some_and(|s| Echo::determine_status_type(s) == ConditionType::Ready);
+        let registered = self.registered_url() == Some(url)
+            && self
+                .condition(ConditionType::Registered)
+                .is_some_and(|c| c.status == "True");
+        let now = ctx.clock.now();
+        // the registry is not changed during a maintenance window, nor while an invalid one fails
+        // the echo reconciliations
+        let maintenance = !matches!(self.maintenance_window(ctx, now), Ok(None));
+        // the echo reconciler adds the finalizer first, so the registration is never leaked
+        if !ready
+            || registered
+            || maintenance
+            || self.deregistration_pending()
+            || !self.has_registration_finalizer()
+        {
+            return Ok(());
+        }
+
+        if let Some(previous) = self.registered_url().filter(|u| *u != url) {
+            self.send_registration(reqwest::Method::DELETE, previous)
+                .await?;
+        }
+        if let Err(e) = self.send_registration(reqwest::Method::POST, url).await {
+            let condition = self.registration_condition(Some(&e), now);
+            self.patch_registration(ctx, Some(condition), None).await?;
+            return Err(e);
+        }
+        info!(msg = "registered echo", url);
+        let condition = self.registration_condition(None, now);
+        self.patch_registration(ctx, Some(condition), Some(Some(url)))
+            .await?;
+        self.audit(ctx, AuditAction::Apply, "Registration", url.to_owned())
+            .await;
+        Ok(())
+    }
+
+    /// Deregister the echo, clear its Registered condition and release the finalizer
+    pub(crate) async fn deregister(&self, ctx: &Context) -> Result<()> {
+        if let Some(url) = self.registered_url() {
+            info!(msg = "deregistering echo", url);
+            self.send_registration(reqwest::Method::DELETE, url).await?;
+            self.audit(ctx, AuditAction::Delete, "Registration", url.to_owned())
+                .await;
+        }
+        if self.meta().deletion_timestamp.is_none() {
+            self.patch_registration(ctx, None, Some(None)).await?;
+        }
+        let finalizers: Vec<String> = self
+            .finalizers()
+            .iter()
+            .filter(|f| *f != REGISTRATION_FINALIZER)
+            .cloned()
+            .collect();
+        self.patch_finalizers(ctx.client.clone(), finalizers).await
+    }
+
+    /// Send the registration to the registry, a missing one being deregistered already
+    async fn send_registration(&self, method: reqwest::Method, url: &str) -> Result<()> {
+        debug!(msg = "sending echo registration", %method, url);
+        let deregister = method == reqwest::Method::DELETE;
+        let response = HTTP
+            .request(method, url)
+            .json(&self.registration())
+            .send()
+            .await
+            .map_err(|e| Error::RegistrationError(e.without_url().to_string()))?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            reqwest::StatusCode::NOT_FOUND if deregister => Ok(()),
+            status => Err(Error::RegistrationError(format!(
+                "registry responded {}",
+                status.as_u16()
+            ))),
+        }
+    }
+
+    fn registration_condition(&self, error: Option<&Error>, now: DateTime<Utc>) -> Condition {
+        let (status, reason, message) = match error {
+            None => (
+                "True",
+                "Registered",
+                "registered in the registry".to_owned(),
+            ),
+            Some(e) => ("False", "RegistrationFailed", e.to_string()),
+        };
+        // keep the transition time while the status does not change
+        let last_transition_time = self
+            .condition(ConditionType::Registered)
+            .filter(|c| c.status == status)
+            .map_or(Time(now), |c| c.last_transition_time.clone());
+        Condition {
+            type_: ConditionType::Registered.to_string(),
+            status: status.to_owned(),
+            reason: reason.to_owned(),
+            message,
+            last_transition_time,
+            observed_generation: self.metadata.generation,
+        }
+    }
+
+    /// Patch the Registered condition and, when it changed, the registry the echo is registered
+    /// in
+    async fn patch_registration(
+        &self,
+        ctx: &Context,
+        condition: Option<Condition>,
+        registered_url: Option<Option<&str>>,
+    ) -> Result<()> {
+        let echo_api = Api::<Echo>::namespaced(ctx.client.clone(), &self.get_namespace());
+        // the status can be updated by the echostatus controller meanwhile, so its conditions are
+        // stale
+        let current = echo_api
+            .get_status(&self.name_any())
+            .await
+            .map_err(Error::KubeError)?;
+        let mut status = current.status.unwrap_or_default();
+        match condition {
+            Some(condition) => status.set_condition(condition),
+            None => status.remove_condition(ConditionType::Registered),
+        }
+        let mut patch = json!({"status": {"conditions": status.conditions.unwrap_or_default()}});
+        if let Some(registered_url) = registered_url {
+            patch["status"]["registeredUrl"] = json!(registered_url);
+        }
+        debug!(msg = "patching Echo registration", ?registered_url);
+        echo_api
+            .patch_status(
+                &self.name_any(),
+                &PatchParams::default(),
+                &Patch::Merge(patch),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::REGISTRATION_FINALIZER;
+
+    use crate::crd::echo::{Echo, EchoRegistration, EchoService, EchoStatus};
+    use crate::echo::condition::ConditionType;
+    use crate::error::Error;
+    use crate::test_utils::test_time;
+
+    use chrono::Duration;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+    use kube::Resource;
+
+    fn echo_with_registration() -> Echo {
+        let mut echo = Echo::test(None);
+        echo.spec.dns_name = Some("echo.example.com".to_string());
+        echo.spec.registration = Some(EchoRegistration {
+            url: "https://registry.example.com/endpoints".to_string(),
+        });
+        echo
+    }
+
+    #[test]
+    fn test_registration() {
+        let registration = echo_with_registration().registration();
+
+        assert_eq!(registration["namespace"], "default");
+        assert_eq!(registration["name"], "test");
+        assert_eq!(registration["address"], "http://echo.example.com/");
+
+        let mut echo = echo_with_registration();
+        echo.spec.service = Some(EchoService {
+            port: Some(8080),
+            ..EchoService::default()
+        });
+        assert_eq!(
+            echo.registration()["address"],
+            "http://echo.example.com:8080/"
+        );
+
+        // the Service of the echo without dnsName
+        echo.spec.dns_name = None;
+        assert_eq!(
+            echo.registration()["address"],
+            "http://test.default.svc:8080/"
+        );
+        echo.spec.service = Some(EchoService::default());
+        assert_eq!(
+            echo.registration()["address"],
+            "http://test.default.svc:80/"
+        );
+    }
+
+    #[test]
+    fn test_deregistration_pending() {
+        let mut echo = echo_with_registration();
+        assert!(!echo.deregistration_pending());
+
+        echo.meta_mut().finalizers = Some(vec![REGISTRATION_FINALIZER.to_string()]);
+        assert!(!echo.deregistration_pending());
+
+        echo.spec.registration = None;
+        assert!(echo.deregistration_pending());
+
+        let mut echo = echo_with_registration();
+        echo.meta_mut().finalizers = Some(vec![REGISTRATION_FINALIZER.to_string()]);
+        echo.meta_mut().deletion_timestamp = Some(Time(test_time()));
+        assert!(echo.deregistration_pending());
+    }
+
+    #[test]
+    fn test_registration_condition() {
+        let echo = echo_with_registration();
+        let registered = echo.registration_condition(None, test_time());
+        assert_eq!(registered.type_, ConditionType::Registered.as_str());
+        assert_eq!(registered.status, "True");
+
+        let echo = Echo {
+            status: Some(EchoStatus {
+                conditions: Some(vec![registered.clone()]),
+                ..EchoStatus::default()
+            }),
+            ..echo
+        };
+        let later = test_time() + Duration::minutes(1);
+        assert_eq!(
+            echo.registration_condition(None, later)
+                .last_transition_time,
+            registered.last_transition_time
+        );
+        let error = Error::RegistrationError("registry responded 503".to_string());
+        let failed = echo.registration_condition(Some(&error), later);
+        assert_eq!(failed.status, "False");
+        assert_eq!(failed.reason, "RegistrationFailed");
+        assert_eq!(failed.message, "RegistrationError: registry responded 503");
+        assert_eq!(failed.last_transition_time.0, later);
+    }
+}
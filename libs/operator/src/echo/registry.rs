@@ -0,0 +1,295 @@
+//! Registry credentials of the echo images: the dockerconfigjson Secret configured in the operator
+//! namespace is copied into a Secret owned by each echo and referenced as image pull secret.
+//!
+//! Copies are immutable and named after a hash of their content, so a changed source Secret is
+//! rotated into a new copy rolling the Deployment over, and rolled back Deployments find the copy
+//! they were using among the retained ones.
+use crate::audit::AuditAction;
+use crate::controller::Context;
+use crate::crd::echo::Echo;
+use crate::echo::provenance::fnv1a;
+use crate::error::{Error, Result};
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::{LocalObjectReference, Secret};
+use k8s_openapi::ByteString;
+use kube::api::{Api, DeleteParams, ListParams, ObjectMeta, Patch, PatchParams, Resource};
+use kube::ResourceExt;
+use tracing::{debug, info};
+
+const DOCKER_CONFIG_JSON_TYPE: &str = "kubernetes.io/dockerconfigjson";
+const DOCKER_CONFIG_JSON_KEY: &str = ".dockerconfigjson";
+/// Annotation of the copies with the source Secret, in `namespace/name` format
+const SOURCE_ANNOTATION: &str = "echoes.example.com/registry-credentials-source";
+/// Copies kept besides the current one, for rollbacks of the Deployment
+const RETAINED_PULL_SECRETS: usize = 3;
+
+/// dockerconfigjson of the source Secret
+fn docker_config_json(source: &Secret) -> Result<ByteString> {
+    if source.type_.as_deref() != Some(DOCKER_CONFIG_JSON_TYPE) {
+        return Err(Error::InvalidRegistryCredentials(format!(
+            "{} is not of type {DOCKER_CONFIG_JSON_TYPE}",
+            source.name_any()
+        )));
+    }
+    source
+        .data
+        .as_ref()
+        .and_then(|d| d.get(DOCKER_CONFIG_JSON_KEY))
+        .cloned()
+        .ok_or_else(|| {
+            Error::InvalidRegistryCredentials(format!(
+                "{} has no {DOCKER_CONFIG_JSON_KEY} key",
+                source.name_any()
+            ))
+        })
+}
+
+/// Copies to delete, oldest first, keeping the current one and the most recent others
+fn stale_pull_secrets(mut copies: Vec<Secret>, current: &str) -> Vec<String> {
+    copies.retain(|s| s.name_any() != current);
+    copies.sort_by_key(|c| Reverse(c.creation_timestamp()));
+    copies
+        .into_iter()
+        .skip(RETAINED_PULL_SECRETS)
+        .rev()
+        .map(|s| s.name_any())
+        .collect()
+}
+
+impl Echo {
+    fn pull_secret_name(&self, config: &ByteString) -> String {
+        format!(
+            "{}-registry-{:010x}",
+            self.name_any(),
+            fnv1a(&config.0) & 0xff_ffff_ffff
+        )
+    }
+
+    fn pull_secret(&self, source: &Secret, config: ByteString) -> Secret {
+        let name = self.name_any();
+        Secret {
+            metadata: ObjectMeta {
+                name: Some(self.pull_secret_name(&config)),
+                namespace: Some(self.get_namespace()),
+                labels: Some(BTreeMap::from([
+                    ("app".to_owned(), name),
+                    ("app.kubernetes.io/name".to_owned(), "echo".to_owned()),
+                    (
+                        "app.kubernetes.io/managed-by".to_owned(),
+                        "echo-operator".to_owned(),
+                    ),
+                ])),
+                annotations: Some(BTreeMap::from([(
+                    SOURCE_ANNOTATION.to_owned(),
+                    format!(
+                        "{}/{}",
+                        source.namespace().unwrap_or_default(),
+                        source.name_any()
+                    ),
+                )])),
+                owner_references: self.child_owner_references(),
+                ..ObjectMeta::default()
+            },
+            immutable: Some(true),
+            type_: Some(DOCKER_CONFIG_JSON_TYPE.to_owned()),
+            data: Some(BTreeMap::from([(
+                DOCKER_CONFIG_JSON_KEY.to_owned(),
+                config,
+            )])),
+            ..Secret::default()
+        }
+    }
+
+    /// Copy the configured registry credentials into a pull secret of the echo, returning the
+    /// image pull secrets of its pods if they are configured
+    pub(crate) async fn reconcile_registry_credentials(
+        &self,
+        ctx: &Context,
+    ) -> Result<Option<Vec<LocalObjectReference>>> {
+        let Some(source_name) = ctx.registry_credentials_secret.as_deref() else {
+            return Ok(None);
+        };
+        let source = Api::<Secret>::default_namespaced(ctx.client.clone())
+            .get(source_name)
+            .await
+            .map_err(Error::KubeError)?;
+        let mut pull_secret = self.pull_secret(&source, docker_config_json(&source)?);
+        pull_secret
+            .annotations_mut()
+            .extend(self.provenance_annotations(ctx)?);
+        let name = pull_secret.name_any();
+        debug!(msg = "applying registry credentials", secret = name);
+        let api = Api::<Secret>::namespaced(ctx.client.clone(), &self.get_namespace());
+        // only metadata changes once created, as the content determines the name
+        api.patch(
+            &name,
+            &PatchParams::apply("echoes.example.com").force(),
+            &Patch::Apply(&pull_secret),
+        )
+        .await
+        .map_err(Error::KubeError)?;
+        self.audit(
+            ctx,
+            AuditAction::Apply,
+            &Secret::kind(&()),
+            "registry credentials".into(),
+        )
+        .await;
+        self.prune_pull_secrets(ctx, &api, &name).await?;
+        Ok(Some(vec![LocalObjectReference { name }]))
+    }
+
+    /// Delete the copies rotated out beyond the retained ones
+    async fn prune_pull_secrets(
+        &self,
+        ctx: &Context,
+        api: &Api<Secret>,
+        current: &str,
+    ) -> Result<()> {
+        let selector = format!(
+            "app={},app.kubernetes.io/managed-by=echo-operator",
+            self.name_any()
+        );
+        let copies = api
+            .list(&ListParams::default().labels(&selector))
+            .await
+            .map_err(Error::KubeError)?
+            .items
+            .into_iter()
+            .filter(|s| s.annotations().contains_key(SOURCE_ANNOTATION))
+            .collect();
+        for name in stale_pull_secrets(copies, current) {
+            info!(msg = "deleting rotated registry credentials", secret = name);
+            match api.delete(&name, &DeleteParams::default()).await {
+                Ok(_) => {}
+                Err(kube::Error::Api(ae)) if ae.code == 404 => {}
+                Err(e) => return Err(Error::KubeError(e)),
+            }
+            self.audit(
+                ctx,
+                AuditAction::Delete,
+                &Secret::kind(&()),
+                "rotated registry credentials".into(),
+            )
+            .await;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        docker_config_json, stale_pull_secrets, DOCKER_CONFIG_JSON_KEY, DOCKER_CONFIG_JSON_TYPE,
+        RETAINED_PULL_SECRETS,
+    };
+
+    use crate::crd::echo::Echo;
+    use crate::error::Error;
+
+    use std::collections::BTreeMap;
+
+    use chrono::{TimeZone, Utc};
+    use k8s_openapi::api::core::v1::Secret;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+    use k8s_openapi::ByteString;
+    use kube::api::ObjectMeta;
+
+    fn source(type_: &str) -> Secret {
+        Secret {
+            metadata: ObjectMeta {
+                name: Some("registry".to_string()),
+                namespace: Some("echo-operator".to_string()),
+                ..ObjectMeta::default()
+            },
+            type_: Some(type_.to_string()),
+            data: Some(BTreeMap::from([(
+                DOCKER_CONFIG_JSON_KEY.to_string(),
+                ByteString(b"{\"auths\": {}}".to_vec()),
+            )])),
+            ..Secret::default()
+        }
+    }
+
+    #[test]
+    fn test_docker_config_json() {
+        assert!(docker_config_json(&source(DOCKER_CONFIG_JSON_TYPE)).is_ok());
+        assert!(matches!(
+            docker_config_json(&source("Opaque")),
+            Err(Error::InvalidRegistryCredentials(_))
+        ));
+    }
+
+    #[test]
+    fn test_pull_secret() {
+        let echo = Echo::test(None);
+        let source = source(DOCKER_CONFIG_JSON_TYPE);
+
+        let secret = echo.pull_secret(&source, docker_config_json(&source).unwrap());
+
+        assert!(secret
+            .metadata
+            .name
+            .as_deref()
+            .unwrap()
+            .starts_with("test-registry-"));
+        assert_eq!(secret.metadata.namespace.as_deref(), Some("default"));
+        assert_eq!(secret.immutable, Some(true));
+        assert_eq!(secret.type_.as_deref(), Some(DOCKER_CONFIG_JSON_TYPE));
+        assert_eq!(
+            secret
+                .metadata
+                .annotations
+                .unwrap()
+                .values()
+                .next()
+                .unwrap(),
+            "echo-operator/registry"
+        );
+    }
+
+    #[test]
+    fn test_pull_secret_name_changes_with_content() {
+        let echo = Echo::test(None);
+        let name = echo.pull_secret_name(&ByteString(b"{\"auths\": {}}".to_vec()));
+
+        assert_eq!(name.len(), "test-registry-".len() + 10);
+        assert_eq!(
+            echo.pull_secret_name(&ByteString(b"{\"auths\": {}}".to_vec())),
+            name
+        );
+        assert_ne!(
+            echo.pull_secret_name(&ByteString(b"{\"auths\": {\"ghcr.io\": {}}}".to_vec())),
+            name
+        );
+    }
+
+    #[test]
+    fn test_stale_pull_secrets() {
+        let copy = |name: &str, day: u32| Secret {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                creation_timestamp: Some(Time(
+                    Utc.with_ymd_and_hms(2024, 1, day, 0, 0, 0).unwrap(),
+                )),
+                ..ObjectMeta::default()
+            },
+            ..Secret::default()
+        };
+        let copies = (1..=RETAINED_PULL_SECRETS as u32 + 3)
+            .map(|day| copy(&format!("test-registry-{day}"), day))
+            .collect::<Vec<_>>();
+
+        assert!(
+            stale_pull_secrets(copies[..=RETAINED_PULL_SECRETS].to_vec(), "test-registry-4")
+                .is_empty()
+        );
+        assert_eq!(
+            stale_pull_secrets(copies, "test-registry-6"),
+            vec!["test-registry-1", "test-registry-2"]
+        );
+    }
+}
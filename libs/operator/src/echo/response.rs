@@ -0,0 +1,223 @@
+//! Synthetic responses of the echo server rendered from the `spec.response` templates.
+use crate::crd::echo::Echo;
+use crate::error::{Error, Result};
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::EnvVar;
+use kube::ResourceExt;
+
+/// Only operator environment variables with this prefix are available to templates
+const ENV_PREFIX: &str = "ECHO_";
+
+/// Render a template replacing every `{{ variable }}` with the value given by `lookup`
+fn render_with(template: &str, mut lookup: impl FnMut(&str) -> Result<String>) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after_start = &rest[start + 2..];
+        let end = after_start
+            .find("}}")
+            .ok_or_else(|| Error::TemplateError(format!("unclosed variable in {template:?}")))?;
+        rendered.push_str(&lookup(after_start[..end].trim())?);
+        rest = &after_start[end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// Render a template replacing every `{{ variable }}` with its value
+fn render(template: &str, echo: &Echo, env: &dyn Fn(&str) -> Option<String>) -> Result<String> {
+    render_with(template, |variable| lookup(variable, echo, env))
+}
+
+/// Check the template is well-formed and only uses known variables, whatever their values
+pub(crate) fn check(template: &str) -> Result<()> {
+    render_with(template, |variable| {
+        let known = match variable.split_once('.') {
+            None => matches!(variable, "name" | "namespace"),
+            Some(("labels" | "annotations", key)) => !key.is_empty(),
+            Some(("env", name)) => name.starts_with(ENV_PREFIX),
+            _ => false,
+        };
+        known
+            .then(String::new)
+            .ok_or_else(|| Error::TemplateError(format!("unknown variable {variable:?}")))
+    })
+    .map(|_| ())
+}
+
+fn lookup(variable: &str, echo: &Echo, env: &dyn Fn(&str) -> Option<String>) -> Result<String> {
+    let value = match variable.split_once('.') {
+        None if variable == "name" => Some(echo.name_any()),
+        None if variable == "namespace" => echo.namespace(),
+        Some(("labels", key)) => echo.labels().get(key).cloned(),
+        Some(("annotations", key)) => echo.annotations().get(key).cloned(),
+        Some(("env", name)) if name.starts_with(ENV_PREFIX) => env(name),
+        _ => None,
+    };
+    value.ok_or_else(|| Error::TemplateError(format!("unknown variable {variable:?}")))
+}
+
+impl Echo {
+    /// Environment configuring the echo server with the rendered response, if any
+    pub(crate) fn response_env(&self) -> Result<Option<Vec<EnvVar>>> {
+        self.response_env_with(&|name| std::env::var(name).ok())
+    }
+
+    fn response_env_with(
+        &self,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<Option<Vec<EnvVar>>> {
+        let Some(response) = self.spec.response.as_ref() else {
+            return Ok(None);
+        };
+        let env_var = |name: &str, value: String| EnvVar {
+            name: name.to_owned(),
+            value: Some(value),
+            ..EnvVar::default()
+        };
+
+        let mut vars = Vec::new();
+        if let Some(body) = response.body.as_deref() {
+            vars.push(env_var("RESPONSE_BODY", render(body, self, env)?));
+        }
+        if let Some(status_code) = response.status_code {
+            vars.push(env_var("RESPONSE_STATUS_CODE", status_code.to_string()));
+        }
+        if let Some(headers) = response.headers.as_ref() {
+            let headers = headers
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), render(v, self, env)?)))
+                .collect::<Result<BTreeMap<String, String>>>()?;
+            vars.push(env_var(
+                "RESPONSE_HEADERS",
+                serde_json::to_string(&headers).map_err(Error::SerializationError)?,
+            ));
+        }
+        if let Some(delay) = response.delay_milliseconds {
+            vars.push(env_var("RESPONSE_DELAY_MS", delay.to_string()));
+        }
+        if let Some(include_headers) = response.include_headers.as_ref() {
+            vars.push(env_var(
+                "RESPONSE_INCLUDE_HEADERS",
+                include_headers.join(","),
+            ));
+        }
+        Ok(Some(vars))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, render};
+
+    use crate::crd::echo::{Echo, EchoResponse};
+    use crate::error::Error;
+
+    use std::collections::BTreeMap;
+
+    use kube::ResourceExt;
+
+    fn env(name: &str) -> Option<String> {
+        match name {
+            "ECHO_CLUSTER" => Some("production".to_string()),
+            "SECRET" => Some("hidden".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_render_variables() {
+        let mut echo = Echo::test(None);
+        echo.labels_mut()
+            .insert("team".to_string(), "platform".to_string());
+
+        assert_eq!(
+            render(
+                "{{name}} in {{ namespace }} by {{ labels.team }} at {{ env.ECHO_CLUSTER }}",
+                &echo,
+                &env
+            )
+            .unwrap(),
+            "test in default by platform at production"
+        );
+    }
+
+    #[test]
+    fn test_render_errors() {
+        let echo = Echo::test(None);
+
+        assert!(matches!(
+            render("{{ env.SECRET }}", &echo, &env),
+            Err(Error::TemplateError(_))
+        ));
+        assert!(matches!(
+            render("{{ labels.missing }}", &echo, &env),
+            Err(Error::TemplateError(_))
+        ));
+        assert!(matches!(
+            render("{{ name", &echo, &env),
+            Err(Error::TemplateError(_))
+        ));
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(check("{{ name }} by {{ labels.missing }} at {{ env.ECHO_CLUSTER }}").is_ok());
+        assert!(matches!(
+            check("{{ env.SECRET }}"),
+            Err(Error::TemplateError(_))
+        ));
+        assert!(matches!(
+            check("{{ labels. }}"),
+            Err(Error::TemplateError(_))
+        ));
+        assert!(matches!(check("{{ name"), Err(Error::TemplateError(_))));
+    }
+
+    #[test]
+    fn test_response_env() {
+        let mut echo = Echo::test(None);
+        assert_eq!(echo.response_env_with(&env).unwrap(), None);
+
+        echo.spec.response = Some(EchoResponse {
+            body: Some("hello from {{ name }}".to_string()),
+            headers: Some(BTreeMap::from([(
+                "X-Cluster".to_string(),
+                "{{ env.ECHO_CLUSTER }}".to_string(),
+            )])),
+            status_code: Some(418),
+            delay_milliseconds: Some(250),
+            include_headers: Some(vec!["X-Request-Id".to_string(), "Traceparent".to_string()]),
+        });
+
+        let vars: Vec<(String, Option<String>)> = echo
+            .response_env_with(&env)
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.name, v.value))
+            .collect();
+        assert_eq!(
+            vars,
+            vec![
+                (
+                    "RESPONSE_BODY".to_string(),
+                    Some("hello from test".to_string())
+                ),
+                ("RESPONSE_STATUS_CODE".to_string(), Some("418".to_string())),
+                (
+                    "RESPONSE_HEADERS".to_string(),
+                    Some(r#"{"X-Cluster":"production"}"#.to_string())
+                ),
+                ("RESPONSE_DELAY_MS".to_string(), Some("250".to_string())),
+                (
+                    "RESPONSE_INCLUDE_HEADERS".to_string(),
+                    Some("X-Request-Id,Traceparent".to_string())
+                ),
+            ]
+        );
+    }
+}
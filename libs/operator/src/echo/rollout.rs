@@ -0,0 +1,363 @@
+//! `spec.rollout`: deadline of the rollouts of new specs and, optionally, automatic rollback of
+//! the ones missing it.
+//!
+//! The echostatus reconciler records the last spec whose Deployment became Ready in an annotation
+//! of the Echo. When the Deployment of a newer spec is still not Ready once the deadline passed
+//! since it was applied, the echo reconciler applies the recorded spec instead and sets the
+//! RolledBack condition, until the Echo spec changes again.
+use crate::controller::Context;
+use crate::crd::echo::{Echo, EchoSpec};
+use crate::echo::condition::ConditionType;
+use crate::echo::provenance::{RECONCILED_AT_ANNOTATION, SPEC_HASH_ANNOTATION};
+use crate::error::{Error, Result};
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::apps::v1::Deployment;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+use kube::api::{Api, Patch, PatchParams};
+use kube::runtime::reflector::ObjectRef;
+use kube::ResourceExt;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{debug, info};
+
+/// Annotation of the Echo with the last spec which became Ready
+pub(crate) const LAST_GOOD_SPEC_ANNOTATION: &str = "echoes.example.com/last-good-spec";
+
+/// Spec whose Deployment became Ready, and its hash in the provenance annotations
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LastGoodSpec {
+    spec_hash: String,
+    spec: EchoSpec,
+}
+
+/// Duration of a sequence of integers with `h`, `m` or `s` units, e.g. `1h30m`
+pub(crate) fn parse_duration(duration: &str) -> Option<Duration> {
+    let mut seconds: u64 = 0;
+    let mut value: Option<u64> = None;
+    for c in duration.chars() {
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => {
+                let digit = u64::from(c.to_digit(10)?);
+                value = Some(value.unwrap_or(0).checked_mul(10)?.checked_add(digit)?);
+                continue;
+            }
+        };
+        seconds = seconds.checked_add(value.take()?.checked_mul(unit)?)?;
+    }
+    (value.is_none() && !duration.is_empty()).then_some(Duration::from_secs(seconds))
+}
+
+impl Echo {
+    /// Time the Deployment of a new spec has to become Ready
+    pub(crate) fn progress_deadline(&self) -> Option<Duration> {
+        let deadline = self.spec.rollout.as_ref()?.progress_deadline.as_deref()?;
+        parse_duration(deadline)
+    }
+
+    fn auto_rollback(&self) -> bool {
+        self.progress_deadline().is_some()
+            && self
+                .spec
+                .rollout
+                .as_ref()
+                .is_some_and(|r| r.auto_rollback == Some(true))
+    }
+
+    fn last_good_spec(&self) -> Option<LastGoodSpec> {
+        let last_good = self.annotations().get(LAST_GOOD_SPEC_ANNOTATION)?;
+        serde_json::from_str(last_good).ok()
+    }
+
+    /// Whether the current generation was rolled back
+    fn rolled_back(&self) -> bool {
+        self.condition(ConditionType::RolledBack)
+            .is_some_and(|c| c.observed_generation == self.metadata.generation)
+    }
+
+    /// Reason of the rollout of the current spec missing its deadline, if it did
+    fn rollout_failure(
+        &self,
+        deployment: &Deployment,
+        now: DateTime<Utc>,
+    ) -> Result<Option<String>> {
+        let (Some(deadline), Some(status)) = (self.progress_deadline(), deployment.status.as_ref())
+        else {
+            return Ok(None);
+        };
+        let annotations = deployment.annotations();
+        let applied = annotations.get(SPEC_HASH_ANNOTATION) == Some(&self.spec_hash()?);
+        let expired = annotations
+            .get(RECONCILED_AT_ANNOTATION)
+            .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+            .and_then(|at| (now - at.with_timezone(&Utc)).to_std().ok())
+            .is_some_and(|elapsed| elapsed >= deadline);
+        if !applied || !expired || Echo::determine_status_type(status) == ConditionType::Ready {
+            return Ok(None);
+        }
+        // the Deployment controller reports why its own deadline, the same one, was exceeded
+        let reason = status
+            .conditions
+            .iter()
+            .flatten()
+            .find(|c| c.type_ == "Progressing" && c.status == "False")
+            .and_then(|c| c.message.clone())
+            .unwrap_or_else(|| format!("Deployment not Ready after {deadline:?}"));
+        Ok(Some(reason))
+    }
+
+    fn rolled_back_condition(&self, reason: String, now: DateTime<Utc>) -> Condition {
+        Condition {
+            type_: ConditionType::RolledBack.to_string(),
+            status: "True".to_owned(),
+            reason: "ProgressDeadlineExceeded".to_owned(),
+            message: reason,
+            last_transition_time: Time(now),
+            observed_generation: self.metadata.generation,
+        }
+    }
+
+    /// Deployment of the Echo in the store
+    pub(crate) fn current_deployment(&self, ctx: &Context) -> Option<Arc<Deployment>> {
+        let deployment_ref =
+            ObjectRef::<Deployment>::new_with(&self.name_any(), ()).within(&self.get_namespace());
+        ctx.stores
+            .get::<Deployment>()
+            // safe unwrap: deployment store should exists
+            .unwrap()
+            .get(&deployment_ref)
+    }
+
+    /// Echo with the last spec which became Ready when the rollout of the current one missed its
+    /// deadline, reporting it in the RolledBack condition
+    pub(crate) async fn reconcile_rollout(&self, ctx: &Context) -> Result<Option<Echo>> {
+        let spec_hash = self.spec_hash()?;
+        let last_good = self
+            .last_good_spec()
+            .filter(|g| g.spec_hash != spec_hash && self.auto_rollback());
+        let Some(last_good) = last_good else {
+            self.clear_rolled_back(ctx).await?;
+            return Ok(None);
+        };
+        if !self.rolled_back() {
+            // a new generation is rolled out again
+            self.clear_rolled_back(ctx).await?;
+            let now = ctx.clock.now();
+            let failure = self
+                .current_deployment(ctx)
+                .map(|d| self.rollout_failure(&d, now))
+                .transpose()?
+                .flatten();
+            let Some(reason) = failure else {
+                return Ok(None);
+            };
+            info!(msg = "rolling back Echo to its last Ready spec", reason);
+            let condition = self.rolled_back_condition(reason, now);
+            self.patch_condition(ctx, ConditionType::RolledBack, Some(condition))
+                .await?;
+        }
+        Ok(Some(Echo {
+            spec: last_good.spec,
+            ..self.clone()
+        }))
+    }
+
+    /// Requeue when the rollout of the current spec reaches its deadline, if it is sooner
+    pub(crate) fn rollout_requeue_after(
+        &self,
+        ctx: &Context,
+        now: DateTime<Utc>,
+        requeue: Duration,
+    ) -> Duration {
+        let Some(deadline) = self.progress_deadline().filter(|_| self.auto_rollback()) else {
+            return requeue;
+        };
+        self.current_deployment(ctx)
+            .filter(|_| !self.rolled_back())
+            .and_then(|d| {
+                d.annotations()
+                    .get(RECONCILED_AT_ANNOTATION)
+                    .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+            })
+            .map(|at| (now - at.with_timezone(&Utc)).to_std().unwrap_or_default())
+            .and_then(|elapsed| deadline.checked_sub(elapsed))
+            .map_or(requeue, |until_deadline| until_deadline.min(requeue))
+    }
+
+    /// Remove the RolledBack condition of a previous generation
+    async fn clear_rolled_back(&self, ctx: &Context) -> Result<()> {
+        if self.condition(ConditionType::RolledBack).is_none() {
+            return Ok(());
+        }
+        self.patch_condition(ctx, ConditionType::RolledBack, None)
+            .await
+    }
+
+    /// Record the current spec as the last good one once its Deployment is Ready
+    pub(crate) async fn record_last_good_spec(
+        &self,
+        ctx: &Context,
+        deployment: &Deployment,
+    ) -> Result<()> {
+        if !self.auto_rollback() {
+            return Ok(());
+        }
+        let spec_hash = self.spec_hash()?;
+        let ready = deployment
+            .status
+            .as_ref()
+            .is_some_and(|s| Echo::determine_status_type(s) == ConditionType::Ready);
+        let applied = deployment.annotations().get(SPEC_HASH_ANNOTATION) == Some(&spec_hash);
+        let recorded = self
+            .last_good_spec()
+            .is_some_and(|g| g.spec_hash == spec_hash);
+        if !ready || !applied || recorded {
+            return Ok(());
+        }
+        let last_good = LastGoodSpec {
+            spec_hash,
+            spec: self.spec.clone(),
+        };
+        let last_good = serde_json::to_string(&last_good).map_err(Error::SerializationError)?;
+        debug!(msg = "recording last good Echo spec");
+        Api::<Echo>::namespaced(ctx.client.clone(), &self.get_namespace())
+            .patch(
+                &self.name_any(),
+                &PatchParams::default(),
+                &Patch::Merge(json!({
+                    "metadata": {"annotations": {LAST_GOOD_SPEC_ANNOTATION: last_good}}
+                })),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_duration, LastGoodSpec, LAST_GOOD_SPEC_ANNOTATION};
+
+    use crate::crd::echo::{Echo, EchoRollout};
+    use crate::echo::provenance::{RECONCILED_AT_ANNOTATION, SPEC_HASH_ANNOTATION};
+    use crate::test_utils::test_time;
+
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    use chrono::SecondsFormat;
+    use k8s_openapi::api::apps::v1::{Deployment, DeploymentCondition, DeploymentStatus};
+    use kube::api::ObjectMeta;
+    use kube::ResourceExt;
+
+    fn echo_with_rollback() -> Echo {
+        let mut echo = Echo::test(None);
+        echo.spec.rollout = Some(EchoRollout {
+            progress_deadline: Some("10m".to_string()),
+            auto_rollback: Some(true),
+        });
+        echo
+    }
+
+    fn deployment(echo: &Echo, ready_replicas: i32) -> Deployment {
+        Deployment {
+            metadata: ObjectMeta {
+                annotations: Some(BTreeMap::from([
+                    (SPEC_HASH_ANNOTATION.to_string(), echo.spec_hash().unwrap()),
+                    (
+                        RECONCILED_AT_ANNOTATION.to_string(),
+                        test_time().to_rfc3339_opts(SecondsFormat::Secs, true),
+                    ),
+                ])),
+                ..ObjectMeta::default()
+            },
+            status: Some(DeploymentStatus {
+                replicas: Some(1),
+                updated_replicas: Some(1),
+                ready_replicas: Some(ready_replicas),
+                ..DeploymentStatus::default()
+            }),
+            ..Deployment::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1h30m"), Some(Duration::from_secs(5400)));
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("10"), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("10d"), None);
+    }
+
+    #[test]
+    fn test_rollout_failure() {
+        let echo = echo_with_rollback();
+        let failing = deployment(&echo, 0);
+        let before = test_time() + chrono::Duration::minutes(9);
+        let after = test_time() + chrono::Duration::minutes(10);
+
+        assert_eq!(echo.rollout_failure(&failing, before).unwrap(), None);
+        assert_eq!(
+            echo.rollout_failure(&failing, after).unwrap().as_deref(),
+            Some("Deployment not Ready after 600s")
+        );
+        assert_eq!(
+            echo.rollout_failure(&deployment(&echo, 1), after).unwrap(),
+            None
+        );
+        // the Deployment of another spec
+        assert_eq!(
+            echo_with_rollback()
+                .change_replicas(2)
+                .rollout_failure(&failing, after)
+                .unwrap(),
+            None
+        );
+
+        let mut progress_deadline_exceeded = failing;
+        progress_deadline_exceeded
+            .status
+            .as_mut()
+            .unwrap()
+            .conditions = Some(vec![DeploymentCondition {
+            type_: "Progressing".to_string(),
+            status: "False".to_string(),
+            reason: Some("ProgressDeadlineExceeded".to_string()),
+            message: Some("ReplicaSet \"test-1\" has timed out progressing.".to_string()),
+            ..DeploymentCondition::default()
+        }]);
+        assert_eq!(
+            echo.rollout_failure(&progress_deadline_exceeded, after)
+                .unwrap()
+                .as_deref(),
+            Some("ReplicaSet \"test-1\" has timed out progressing.")
+        );
+    }
+
+    #[test]
+    fn test_last_good_spec() {
+        let mut echo = echo_with_rollback();
+        assert!(echo.last_good_spec().is_none());
+
+        let last_good = LastGoodSpec {
+            spec_hash: echo.spec_hash().unwrap(),
+            spec: echo.spec.clone(),
+        };
+        echo.annotations_mut().insert(
+            LAST_GOOD_SPEC_ANNOTATION.to_string(),
+            serde_json::to_string(&last_good).unwrap(),
+        );
+        let recorded = echo.last_good_spec().unwrap();
+        assert_eq!(recorded.spec_hash, last_good.spec_hash);
+        assert_eq!(recorded.spec.replicas, 1);
+    }
+}
@@ -0,0 +1,149 @@
+//! Scheduled scaling of Echo replicas from cron expressions.
+use crate::crd::echo::Echo;
+use crate::error::{Error, Result};
+
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Utc};
+use cron::Schedule;
+
+/// Replicas given by the Echo schedules at a point in time
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScheduledReplicas {
+    pub replicas: i32,
+    /// Cron expression of the schedule setting the replicas
+    pub active_schedule: Option<String>,
+    /// Next time a schedule fires
+    pub next_change: Option<DateTime<Utc>>,
+}
+
+/// Parse a cron expression, accepting the classic crontab format without seconds
+pub(crate) fn parse(expression: &str) -> Result<Schedule> {
+    let expression = match expression.split_whitespace().count() {
+        5 => format!("0 {expression}"),
+        _ => expression.to_string(),
+    };
+    Schedule::from_str(&expression)
+        .map_err(|e| Error::InvalidSchedule(format!("{expression}: {e}")))
+}
+
+impl Echo {
+    /// Replicas of the latest fired schedule, or spec replicas when there are no schedules
+    pub fn scheduled_replicas(&self, now: DateTime<Utc>) -> Result<ScheduledReplicas> {
+        let mut scheduled = ScheduledReplicas {
+            replicas: self.spec.replicas,
+            active_schedule: None,
+            next_change: None,
+        };
+        let mut last_fired: Option<DateTime<Utc>> = None;
+
+        for entry in self.spec.schedules.iter().flatten() {
+            let schedule = parse(&entry.schedule)?;
+            // schedules firing exactly now are already active
+            let previous = schedule.after(&(now + Duration::seconds(1))).next_back();
+            if let Some(previous) = previous.filter(|p| last_fired.map_or(true, |l| *p > l)) {
+                last_fired = Some(previous);
+                scheduled.replicas = entry.replicas;
+                scheduled.active_schedule = Some(entry.schedule.clone());
+            }
+            if let Some(next) = schedule.after(&now).next() {
+                scheduled.next_change = Some(scheduled.next_change.map_or(next, |n| n.min(next)));
+            }
+        }
+        Ok(scheduled)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ScheduledReplicas;
+
+    use crate::crd::echo::{Echo, EchoSchedules};
+    use crate::error::Error;
+
+    use chrono::{DateTime, TimeZone, Utc};
+
+    fn echo_with_schedules() -> Echo {
+        let mut echo = Echo::test(None).change_replicas(2);
+        echo.spec.schedules = Some(vec![
+            EchoSchedules {
+                schedule: "0 8 * * Mon-Fri".to_string(),
+                replicas: 5,
+            },
+            EchoSchedules {
+                schedule: "0 20 * * *".to_string(),
+                replicas: 0,
+            },
+        ]);
+        echo
+    }
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        // 2024-01-01 is a Monday
+        Utc.with_ymd_and_hms(2024, 1, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_scheduled_replicas_without_schedules() {
+        let echo = Echo::test(None).change_replicas(2);
+
+        assert_eq!(
+            echo.scheduled_replicas(at(1, 12, 0)).unwrap(),
+            ScheduledReplicas {
+                replicas: 2,
+                active_schedule: None,
+                next_change: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_scheduled_replicas_business_hours() {
+        let scheduled = echo_with_schedules()
+            .scheduled_replicas(at(2, 12, 0))
+            .unwrap();
+
+        assert_eq!(scheduled.replicas, 5);
+        assert_eq!(
+            scheduled.active_schedule.as_deref(),
+            Some("0 8 * * Mon-Fri")
+        );
+        assert_eq!(scheduled.next_change, Some(at(2, 20, 0)));
+    }
+
+    #[test]
+    fn test_scheduled_replicas_weekend() {
+        // Saturday, the Friday night schedule is the latest fired
+        let scheduled = echo_with_schedules()
+            .scheduled_replicas(at(6, 12, 0))
+            .unwrap();
+
+        assert_eq!(scheduled.replicas, 0);
+        assert_eq!(scheduled.active_schedule.as_deref(), Some("0 20 * * *"));
+        assert_eq!(scheduled.next_change, Some(at(6, 20, 0)));
+    }
+
+    #[test]
+    fn test_scheduled_replicas_fired_now() {
+        let scheduled = echo_with_schedules()
+            .scheduled_replicas(at(2, 8, 0))
+            .unwrap();
+
+        assert_eq!(scheduled.replicas, 5);
+        assert_eq!(scheduled.next_change, Some(at(2, 20, 0)));
+    }
+
+    #[test]
+    fn test_scheduled_replicas_invalid_schedule() {
+        let mut echo = Echo::test(None);
+        echo.spec.schedules = Some(vec![EchoSchedules {
+            schedule: "every monday".to_string(),
+            replicas: 1,
+        }]);
+
+        assert!(matches!(
+            echo.scheduled_replicas(at(1, 12, 0)),
+            Err(Error::InvalidSchedule(_))
+        ));
+    }
+}
@@ -0,0 +1,189 @@
+//! `spec.nodeSelector`, `spec.affinity` and `spec.topologySpreadConstraints`: scheduling of the
+//! echo pods, copied into their template, e.g. to pin them to a node pool.
+//!
+//! The affinity and spread constraints are kept verbatim in the spec, as in a Kubernetes pod, and
+//! only parsed when validated or applied. The node affinity of `imagePerArch`, see
+//! [`crate::echo::arch`], is required on top of the one of the spec.
+use crate::crd::echo::Echo;
+use crate::error::{Error, Result};
+
+use k8s_openapi::api::core::v1::{Affinity, NodeAffinity, TopologySpreadConstraint};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Kubernetes type of a field the spec keeps verbatim
+fn parse<T: DeserializeOwned>(value: &impl Serialize) -> Result<T> {
+    serde_json::to_value(value)
+        .and_then(serde_json::from_value)
+        .map_err(Error::SerializationError)
+}
+
+impl Echo {
+    /// Affinity of the echo pods, the one of the spec also requiring the architectures of
+    /// `imagePerArch`
+    pub(crate) fn affinity(&self) -> Result<Option<Affinity>> {
+        let affinity: Option<Affinity> = self.spec.affinity.as_ref().map(parse).transpose()?;
+        let Some(arch_affinity) = self.arch_affinity() else {
+            return Ok(affinity);
+        };
+        let Some(mut affinity) = affinity else {
+            return Ok(Some(arch_affinity));
+        };
+        let arch_selector = arch_affinity
+            .node_affinity
+            .and_then(|n| n.required_during_scheduling_ignored_during_execution);
+        let node_affinity = affinity
+            .node_affinity
+            .get_or_insert_with(NodeAffinity::default);
+        match node_affinity
+            .required_during_scheduling_ignored_during_execution
+            .as_mut()
+        {
+            // the terms are ORed, so every one of them requires the architectures
+            Some(selector) => {
+                let arch_requirements = arch_selector
+                    .into_iter()
+                    .flat_map(|s| s.node_selector_terms)
+                    .flat_map(|t| t.match_expressions.unwrap_or_default())
+                    .collect::<Vec<_>>();
+                selector.node_selector_terms.iter_mut().for_each(|term| {
+                    term.match_expressions
+                        .get_or_insert_with(Vec::new)
+                        .extend(arch_requirements.clone())
+                });
+            }
+            None => {
+                node_affinity.required_during_scheduling_ignored_during_execution = arch_selector
+            }
+        }
+        Ok(Some(affinity))
+    }
+
+    /// Topology spread constraints of the echo pods
+    pub(crate) fn topology_spread_constraints(
+        &self,
+    ) -> Result<Option<Vec<TopologySpreadConstraint>>> {
+        self.spec
+            .topology_spread_constraints
+            .as_ref()
+            .map(parse)
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crd::echo::Echo;
+    use crate::echo::arch::ARCH_LABEL;
+    use crate::error::Error;
+
+    use std::collections::BTreeMap;
+
+    use serde_json::json;
+
+    fn echo_with_affinity(affinity: serde_json::Value) -> Echo {
+        let mut echo = Echo::test(None);
+        echo.spec.affinity = Some(serde_json::from_value(affinity).unwrap());
+        echo
+    }
+
+    fn node_affinity() -> serde_json::Value {
+        json!({
+            "nodeAffinity": {
+                "requiredDuringSchedulingIgnoredDuringExecution": {
+                    "nodeSelectorTerms": [
+                        {"matchExpressions": [
+                            {"key": "pool", "operator": "In", "values": ["echo"]}
+                        ]},
+                        {"matchExpressions": [
+                            {"key": "pool", "operator": "In", "values": ["shared"]}
+                        ]},
+                    ]
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn test_affinity() {
+        assert_eq!(Echo::test(None).affinity().unwrap(), None);
+
+        let affinity = echo_with_affinity(json!({
+            "podAntiAffinity": {
+                "preferredDuringSchedulingIgnoredDuringExecution": [{
+                    "weight": 100,
+                    "podAffinityTerm": {
+                        "topologyKey": "kubernetes.io/hostname",
+                        "labelSelector": {"matchLabels": {"app": "test"}},
+                    },
+                }]
+            }
+        }))
+        .affinity()
+        .unwrap()
+        .unwrap();
+        assert!(affinity.node_affinity.is_none());
+        let preferred = affinity
+            .pod_anti_affinity
+            .and_then(|a| a.preferred_during_scheduling_ignored_during_execution)
+            .unwrap();
+        assert_eq!(preferred[0].weight, 100);
+
+        let mut echo = echo_with_affinity(json!({"nodeAffinity": "invalid"}));
+        assert!(matches!(echo.affinity(), Err(Error::SerializationError(_))));
+        echo.spec.affinity = None;
+        echo.spec.image_per_arch = Some(BTreeMap::from([(
+            "arm64".to_string(),
+            "echo:1-arm64".to_string(),
+        )]));
+        assert_eq!(echo.affinity().unwrap(), echo.arch_affinity());
+    }
+
+    #[test]
+    fn test_affinity_with_arch() {
+        let mut echo = echo_with_affinity(node_affinity());
+        echo.spec.image_per_arch = Some(BTreeMap::from([(
+            "arm64".to_string(),
+            "echo:1-arm64".to_string(),
+        )]));
+        let terms = echo
+            .affinity()
+            .unwrap()
+            .and_then(|a| a.node_affinity)
+            .and_then(|n| n.required_during_scheduling_ignored_during_execution)
+            .unwrap()
+            .node_selector_terms;
+        assert_eq!(terms.len(), 2);
+        for term in terms {
+            let keys: Vec<String> = term
+                .match_expressions
+                .unwrap()
+                .into_iter()
+                .map(|e| e.key)
+                .collect();
+            assert_eq!(keys, vec!["pool".to_string(), ARCH_LABEL.to_string()]);
+        }
+    }
+
+    #[test]
+    fn test_topology_spread_constraints() {
+        assert_eq!(
+            Echo::test(None).topology_spread_constraints().unwrap(),
+            None
+        );
+
+        let mut echo = Echo::test(None);
+        echo.spec.topology_spread_constraints = Some(
+            serde_json::from_value(json!([{
+                "maxSkew": 1,
+                "topologyKey": "topology.kubernetes.io/zone",
+                "whenUnsatisfiable": "ScheduleAnyway",
+                "labelSelector": {"matchLabels": {"app": "test"}},
+            }]))
+            .unwrap(),
+        );
+        let constraints = echo.topology_spread_constraints().unwrap().unwrap();
+        assert_eq!(constraints[0].max_skew, 1);
+        assert_eq!(constraints[0].topology_key, "topology.kubernetes.io/zone");
+    }
+}
@@ -0,0 +1,319 @@
+//! `spec.securityProfile`: security context presets of the echo pods, so they are admitted in
+//! namespaces enforcing the Pod Security Standards, the explicit seccomp and AppArmor profiles and
+//! the read-only root filesystem of the echo container.
+use crate::clusterdefaults::SecurityProfile;
+use crate::crd::echo::{
+    Echo, EchoAppArmorProfileType, EchoSeccompProfileType, EchoSecurityProfile,
+};
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::{
+    Capabilities, EmptyDirVolumeSource, PodSecurityContext, SeccompProfile, SecurityContext,
+    Volume, VolumeMount,
+};
+use kube::ResourceExt;
+
+/// Prefix of the pod annotations setting the AppArmor profile of each container
+const APPARMOR_ANNOTATION_PREFIX: &str = "container.apparmor.security.beta.kubernetes.io/";
+
+/// Writable directory of the echo container when its root filesystem is read-only
+const TMP_VOLUME: &str = "tmp";
+const TMP_PATH: &str = "/tmp";
+
+/// User `nobody`, so images built to run as root still run as non-root
+const NON_ROOT_USER: i64 = 65534;
+
+fn runtime_default_seccomp() -> Option<SeccompProfile> {
+    Some(SeccompProfile {
+        type_: "RuntimeDefault".to_owned(),
+        ..SeccompProfile::default()
+    })
+}
+
+/// Pod and container security contexts complying with the "restricted" Pod Security Standard
+fn restricted() -> (PodSecurityContext, SecurityContext) {
+    let pod = PodSecurityContext {
+        run_as_non_root: Some(true),
+        run_as_user: Some(NON_ROOT_USER),
+        run_as_group: Some(NON_ROOT_USER),
+        seccomp_profile: runtime_default_seccomp(),
+        ..PodSecurityContext::default()
+    };
+    let container = SecurityContext {
+        allow_privilege_escalation: Some(false),
+        privileged: Some(false),
+        run_as_non_root: Some(true),
+        capabilities: Some(Capabilities {
+            drop: Some(vec!["ALL".to_owned()]),
+            ..Capabilities::default()
+        }),
+        ..SecurityContext::default()
+    };
+    (pod, container)
+}
+
+/// Pod and container security contexts complying with the "baseline" Pod Security Standard
+fn baseline() -> (PodSecurityContext, SecurityContext) {
+    let pod = PodSecurityContext {
+        seccomp_profile: runtime_default_seccomp(),
+        ..PodSecurityContext::default()
+    };
+    let container = SecurityContext {
+        allow_privilege_escalation: Some(false),
+        privileged: Some(false),
+        ..SecurityContext::default()
+    };
+    (pod, container)
+}
+
+impl From<&EchoSecurityProfile> for SecurityProfile {
+    fn from(profile: &EchoSecurityProfile) -> Self {
+        match profile {
+            EchoSecurityProfile::Restricted => SecurityProfile::Restricted,
+            EchoSecurityProfile::Baseline => SecurityProfile::Baseline,
+            EchoSecurityProfile::Custom => SecurityProfile::Custom,
+        }
+    }
+}
+
+impl Echo {
+    /// Whether the root filesystem of the echo container is read-only, the default
+    fn read_only_root_filesystem(&self) -> bool {
+        let custom = self
+            .spec
+            .security_context
+            .as_ref()
+            .filter(|_| {
+                matches!(
+                    self.spec.security_profile,
+                    Some(EchoSecurityProfile::Custom)
+                )
+            })
+            .and_then(|c| c.read_only_root_filesystem);
+        custom
+            .or(self.spec.read_only_root_filesystem)
+            .unwrap_or(true)
+    }
+
+    /// Security contexts of the echo pods with the security profile resolved from the spec and
+    /// the cluster defaults
+    pub(crate) fn security_contexts_with(
+        &self,
+        profile: Option<SecurityProfile>,
+    ) -> (Option<PodSecurityContext>, SecurityContext) {
+        let seccomp_profile = self.spec.seccomp_profile.as_ref().map(|p| SeccompProfile {
+            type_: match p.r#type {
+                EchoSeccompProfileType::RuntimeDefault => "RuntimeDefault",
+                EchoSeccompProfileType::Localhost => "Localhost",
+                EchoSeccompProfileType::Unconfined => "Unconfined",
+            }
+            .to_owned(),
+            localhost_profile: p.localhost_profile.clone(),
+        });
+        let (mut pod, mut container) = match self.profile_security_contexts(profile) {
+            Some((pod, container)) => (Some(pod), container),
+            None => (None, SecurityContext::default()),
+        };
+        if seccomp_profile.is_some() {
+            pod.get_or_insert_with(PodSecurityContext::default)
+                .seccomp_profile = seccomp_profile;
+        }
+        container.read_only_root_filesystem = Some(self.read_only_root_filesystem());
+        (pod, container)
+    }
+
+    /// Writable volume of the echo container, if its root filesystem is read-only
+    pub(crate) fn writable_volume(&self) -> Option<(Volume, VolumeMount)> {
+        self.read_only_root_filesystem().then(|| {
+            (
+                Volume {
+                    name: TMP_VOLUME.to_owned(),
+                    empty_dir: Some(EmptyDirVolumeSource::default()),
+                    ..Volume::default()
+                },
+                VolumeMount {
+                    name: TMP_VOLUME.to_owned(),
+                    mount_path: TMP_PATH.to_owned(),
+                    ..VolumeMount::default()
+                },
+            )
+        })
+    }
+
+    /// Pod annotations with the AppArmor profile of the echo container, if the Echo sets one
+    pub(crate) fn apparmor_annotations(&self) -> Option<BTreeMap<String, String>> {
+        let profile = self.spec.app_armor_profile.as_ref()?;
+        let value = match profile.r#type {
+            EchoAppArmorProfileType::RuntimeDefault => "runtime/default".to_owned(),
+            EchoAppArmorProfileType::Localhost => format!(
+                "localhost/{}",
+                profile.localhost_profile.as_deref().unwrap_or_default()
+            ),
+            EchoAppArmorProfileType::Unconfined => "unconfined".to_owned(),
+        };
+        // the echo container is named as the Echo
+        Some(BTreeMap::from([(
+            format!("{APPARMOR_ANNOTATION_PREFIX}{}", self.name_any()),
+            value,
+        )]))
+    }
+
+    fn profile_security_contexts(
+        &self,
+        profile: Option<SecurityProfile>,
+    ) -> Option<(PodSecurityContext, SecurityContext)> {
+        match profile? {
+            SecurityProfile::Restricted => Some(restricted()),
+            SecurityProfile::Baseline => Some(baseline()),
+            SecurityProfile::Custom => {
+                let (mut pod, mut container) = restricted();
+                let Some(custom) = self.spec.security_context.as_ref() else {
+                    return Some((pod, container));
+                };
+                pod.run_as_user = custom.run_as_user.or(pod.run_as_user);
+                pod.run_as_group = custom.run_as_group.or(pod.run_as_group);
+                pod.fs_group = custom.fs_group.or(pod.fs_group);
+                if let Some(add) = custom.add_capabilities.clone() {
+                    container
+                        .capabilities
+                        .get_or_insert_with(Capabilities::default)
+                        .add = Some(add);
+                }
+                Some((pod, container))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::clusterdefaults::SecurityProfile;
+    use crate::crd::echo::{
+        Echo, EchoAppArmorProfile, EchoAppArmorProfileType, EchoSeccompProfile,
+        EchoSeccompProfileType, EchoSecurityContext, EchoSecurityProfile,
+    };
+
+    use std::collections::BTreeMap;
+
+    use k8s_openapi::api::core::v1::{PodSecurityContext, SecurityContext};
+
+    /// Security contexts of the echo pods with the security profile of the spec only
+    fn security_contexts(echo: &Echo) -> (Option<PodSecurityContext>, SecurityContext) {
+        let profile = echo
+            .spec
+            .security_profile
+            .as_ref()
+            .map(SecurityProfile::from);
+        echo.security_contexts_with(profile)
+    }
+
+    fn echo_with_profile(profile: Option<EchoSecurityProfile>) -> Echo {
+        let mut echo = Echo::test(None);
+        echo.spec.security_profile = profile;
+        echo
+    }
+
+    #[test]
+    fn test_no_security_profile() {
+        let (pod, container) = security_contexts(&echo_with_profile(None));
+        assert!(pod.is_none());
+        assert_eq!(
+            container,
+            SecurityContext {
+                read_only_root_filesystem: Some(true),
+                ..SecurityContext::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_read_only_root_filesystem_opt_out() {
+        let mut echo = echo_with_profile(Some(EchoSecurityProfile::Restricted));
+        assert!(echo.writable_volume().is_some());
+
+        echo.spec.read_only_root_filesystem = Some(false);
+        let (_, container) = security_contexts(&echo);
+        assert_eq!(container.read_only_root_filesystem, Some(false));
+        assert!(echo.writable_volume().is_none());
+    }
+
+    #[test]
+    fn test_restricted_security_profile() {
+        let (pod, container) =
+            security_contexts(&echo_with_profile(Some(EchoSecurityProfile::Restricted)));
+        let pod = pod.unwrap();
+        assert_eq!(pod.run_as_non_root, Some(true));
+        assert_eq!(
+            pod.seccomp_profile.map(|s| s.type_).as_deref(),
+            Some("RuntimeDefault")
+        );
+        assert_eq!(container.allow_privilege_escalation, Some(false));
+        assert_eq!(
+            container.capabilities.and_then(|c| c.drop),
+            Some(vec!["ALL".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_custom_security_profile() {
+        let mut echo = echo_with_profile(Some(EchoSecurityProfile::Custom));
+        echo.spec.security_context = Some(EchoSecurityContext {
+            run_as_user: Some(1000),
+            read_only_root_filesystem: Some(false),
+            add_capabilities: Some(vec!["NET_BIND_SERVICE".to_string()]),
+            ..EchoSecurityContext::default()
+        });
+
+        let (pod, container) = security_contexts(&echo);
+        let pod = pod.unwrap();
+        assert_eq!(pod.run_as_user, Some(1000));
+        // restricted defaults not overridden are kept
+        assert_eq!(pod.run_as_non_root, Some(true));
+        assert_eq!(container.read_only_root_filesystem, Some(false));
+        let capabilities = container.capabilities.unwrap();
+        assert_eq!(capabilities.drop, Some(vec!["ALL".to_string()]));
+        assert_eq!(capabilities.add, Some(vec!["NET_BIND_SERVICE".to_string()]));
+    }
+
+    #[test]
+    fn test_seccomp_profile() {
+        let mut echo = echo_with_profile(None);
+        echo.spec.seccomp_profile = Some(EchoSeccompProfile {
+            r#type: EchoSeccompProfileType::Localhost,
+            localhost_profile: Some("profiles/echo.json".to_string()),
+        });
+
+        let (pod, container) = security_contexts(&echo);
+        assert!(container.allow_privilege_escalation.is_none());
+        let seccomp_profile = pod.unwrap().seccomp_profile.unwrap();
+        assert_eq!(seccomp_profile.type_, "Localhost");
+        assert_eq!(
+            seccomp_profile.localhost_profile.as_deref(),
+            Some("profiles/echo.json")
+        );
+
+        // explicit profile takes precedence over the one of the security profile
+        echo.spec.security_profile = Some(EchoSecurityProfile::Restricted);
+        let (pod, _) = security_contexts(&echo);
+        assert_eq!(pod.unwrap().seccomp_profile.unwrap().type_, "Localhost");
+    }
+
+    #[test]
+    fn test_apparmor_annotations() {
+        let mut echo = echo_with_profile(None);
+        assert!(echo.apparmor_annotations().is_none());
+
+        echo.spec.app_armor_profile = Some(EchoAppArmorProfile {
+            r#type: EchoAppArmorProfileType::Localhost,
+            localhost_profile: Some("echo".to_string()),
+        });
+        assert_eq!(
+            echo.apparmor_annotations(),
+            Some(BTreeMap::from([(
+                "container.apparmor.security.beta.kubernetes.io/test".to_string(),
+                "localhost/echo".to_string()
+            )]))
+        );
+    }
+}
@@ -0,0 +1,367 @@
+//! `spec.service`: Service exposing the echo pods, created while the Echo sets `service`,
+//! `dnsName` or `ingress`, and its cluster IP reported in `status.clusterIP`.
+//!
+//! The Service is named after the Echo. With `dnsName` it is a LoadBalancer annotated for
+//! external-dns by default, see [`crate::echo::dns`], and a ClusterIP Service otherwise.
+use crate::audit::{self, AuditAction};
+use crate::controller::Context;
+use crate::crd::echo::{Echo, EchoServiceIpFamilyPolicy};
+use crate::echo::condition::ConditionType;
+use crate::echo::dns::HOSTNAME_ANNOTATION;
+use crate::echo::reconcile::ECHO_PORT;
+use crate::error::{Error, Result};
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::{Service, ServicePort, ServiceSpec};
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use kube::api::{Api, DeleteParams, ObjectMeta, Patch, PatchParams};
+use kube::ResourceExt;
+use serde_json::json;
+use tracing::{debug, info};
+
+/// Port of the Service when the spec does not set one
+pub(crate) const SERVICE_PORT: i32 = 80;
+
+impl Echo {
+    /// Whether the Echo is exposed by a Service, also the backend of its Ingress
+    pub(crate) fn service_enabled(&self) -> bool {
+        self.spec.service.is_some() || self.spec.dns_name.is_some() || self.spec.ingress.is_some()
+    }
+
+    /// Port of the Service
+    pub(crate) fn service_port(&self) -> i32 {
+        self.spec
+            .service
+            .as_ref()
+            .and_then(|s| s.port)
+            .unwrap_or(SERVICE_PORT)
+    }
+
+    /// Type of the Service, a LoadBalancer external-dns can publish by default with `dnsName`
+    fn service_type(&self) -> String {
+        self.spec
+            .service
+            .as_ref()
+            .and_then(|s| serde_json::to_value(&s.r#type).ok())
+            .and_then(|t| t.as_str().map(str::to_owned))
+            .unwrap_or_else(|| {
+                if self.spec.dns_name.is_some() {
+                    "LoadBalancer".to_owned()
+                } else {
+                    "ClusterIP".to_owned()
+                }
+            })
+    }
+
+    /// Apply the Service while it is enabled, or delete the one reported in the status, and
+    /// report its cluster IP
+    pub(crate) async fn reconcile_service(&self, ctx: &Context) -> Result<Option<Service>> {
+        let service_api = Api::<Service>::namespaced(ctx.client.clone(), &self.get_namespace());
+        let cluster_ip = self.status.as_ref().and_then(|s| s.cluster_ip.as_deref());
+        if !self.service_enabled() {
+            self.clear_not_owned(ctx, ConditionType::ServiceConflict)
+                .await?;
+            if cluster_ip.is_some() {
+                info!(msg = "deleting echo Service");
+                match service_api
+                    .delete(&self.name_any(), &DeleteParams::default())
+                    .await
+                {
+                    Ok(_) => {
+                        self.audit(ctx, AuditAction::Delete, "Service", "disabled".into())
+                            .await
+                    }
+                    Err(kube::Error::Api(ae)) if ae.code == 404 => {}
+                    Err(e) => return Err(Error::KubeError(e)),
+                }
+                self.patch_cluster_ip(ctx, None).await?;
+            }
+            return Ok(None);
+        }
+
+        let mut service = self.service();
+        service
+            .annotations_mut()
+            .extend(self.provenance_annotations(ctx)?);
+        let current = service_api
+            .get_opt(&self.name_any())
+            .await
+            .map_err(Error::KubeError)?;
+        // the forced apply would take over the Service of a user or an EchoGateway
+        if current.as_ref().is_some_and(|s| !self.owns(s)) {
+            self.report_not_owned(ctx, ConditionType::ServiceConflict, "Service")
+                .await?;
+            // nor deleted once the Service is disabled
+            if cluster_ip.is_some() {
+                self.patch_cluster_ip(ctx, None).await?;
+            }
+            return Ok(None);
+        }
+        let service = service_api
+            .patch(
+                &self.name_any(),
+                &PatchParams::apply("echoes.example.com").force(),
+                &Patch::Apply(&service),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        if audit::apply_changed(current.as_ref(), &service) {
+            self.audit(ctx, AuditAction::Apply, "Service", self.service_type())
+                .await;
+        }
+        let applied_cluster_ip = service.spec.as_ref().and_then(|s| s.cluster_ip.as_deref());
+        if applied_cluster_ip != cluster_ip {
+            self.patch_cluster_ip(ctx, applied_cluster_ip).await?;
+        }
+        self.clear_not_owned(ctx, ConditionType::ServiceConflict)
+            .await?;
+        Ok(Some(service))
+    }
+
+    async fn patch_cluster_ip(&self, ctx: &Context, cluster_ip: Option<&str>) -> Result<()> {
+        debug!(msg = "patching Echo cluster IP", ?cluster_ip);
+        Api::<Echo>::namespaced(ctx.client.clone(), &self.get_namespace())
+            .patch_status(
+                &self.name_any(),
+                &PatchParams::default(),
+                &Patch::Merge(json!({"status": {"clusterIP": cluster_ip}})),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        Ok(())
+    }
+
+    /// Service exposing the echo pods, annotated for external-dns with `dnsName`
+    pub(crate) fn service(&self) -> Service {
+        let name = self.name_any();
+        let service = self.spec.service.as_ref();
+        let mut annotations = service
+            .and_then(|s| s.annotations.clone())
+            .unwrap_or_default();
+        if let Some(dns_name) = self.spec.dns_name.as_ref() {
+            annotations.insert(HOSTNAME_ANNOTATION.to_owned(), dns_name.clone());
+        }
+        Service {
+            metadata: ObjectMeta {
+                name: Some(name.clone()),
+                namespace: Some(self.get_namespace()),
+                labels: Some(BTreeMap::from([
+                    ("app".to_owned(), name.clone()),
+                    ("app.kubernetes.io/name".to_owned(), "echo".to_owned()),
+                    (
+                        "app.kubernetes.io/managed-by".to_owned(),
+                        "echo-operator".to_owned(),
+                    ),
+                ])),
+                annotations: Some(annotations).filter(|a| !a.is_empty()),
+                owner_references: self.child_owner_references(),
+                ..ObjectMeta::default()
+            },
+            spec: Some(ServiceSpec {
+                type_: Some(self.service_type()),
+                selector: Some(BTreeMap::from([
+                    ("app".to_owned(), name),
+                    ("app.kubernetes.io/name".to_owned(), "echo".to_owned()),
+                ])),
+                ports: Some(vec![ServicePort {
+                    name: Some("http".to_owned()),
+                    port: self.service_port(),
+                    target_port: Some(IntOrString::Int(ECHO_PORT)),
+                    protocol: Some("TCP".to_owned()),
+                    ..ServicePort::default()
+                }]),
+                ip_families: service.and_then(|s| s.ip_families.clone()),
+                ip_family_policy: self.ip_family_policy().map(str::to_owned),
+                ..ServiceSpec::default()
+            }),
+            ..Service::default()
+        }
+    }
+
+    /// Dual-stack policy of the Service set by the spec
+    fn ip_family_policy(&self) -> Option<&'static str> {
+        let policy = self.spec.service.as_ref()?.ip_family_policy.as_ref()?;
+        Some(match policy {
+            EchoServiceIpFamilyPolicy::SingleStack => "SingleStack",
+            EchoServiceIpFamilyPolicy::PreferDualStack => "PreferDualStack",
+            EchoServiceIpFamilyPolicy::RequireDualStack => "RequireDualStack",
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::audit::{AuditSink, Auditor};
+    use crate::controller::Context;
+    use crate::crd::echo::{Echo, EchoIngress, EchoService, EchoServiceIpFamilyPolicy};
+    use crate::echo::condition::ConditionType;
+    use crate::echo::dns::HOSTNAME_ANNOTATION;
+    use crate::test_utils::fake_apiserver::FakeApiServer;
+    use crate::test_utils::get_test_context;
+
+    use std::sync::Arc;
+
+    use k8s_openapi::api::core::v1::Service;
+    use kube::api::ObjectMeta;
+    use serde_json::json;
+
+    fn echo_with_service(service: serde_json::Value) -> Echo {
+        let mut echo = Echo::test(None);
+        echo.spec.service = Some(serde_json::from_value(service).unwrap());
+        echo
+    }
+
+    #[test]
+    fn test_service_enabled() {
+        let mut echo = Echo::test(None);
+        assert!(!echo.service_enabled());
+
+        echo.spec.dns_name = Some("echo.example.com".to_string());
+        assert!(echo.service_enabled());
+        assert!(echo_with_service(json!({})).service_enabled());
+
+        let mut echo = Echo::test(None);
+        echo.spec.ingress = Some(EchoIngress {
+            host: "echo.example.com".to_string(),
+            ..EchoIngress::default()
+        });
+        assert!(echo.service_enabled());
+    }
+
+    #[test]
+    fn test_dns_service() {
+        let mut echo = Echo::test(None);
+        echo.spec.dns_name = Some("echo.example.com".to_string());
+        let service = echo.service();
+
+        assert_eq!(
+            service
+                .metadata
+                .annotations
+                .unwrap()
+                .get(HOSTNAME_ANNOTATION),
+            Some(&"echo.example.com".to_string())
+        );
+        let spec = service.spec.unwrap();
+        assert_eq!(spec.type_.as_deref(), Some("LoadBalancer"));
+        assert_eq!(
+            spec.selector.unwrap().get("app"),
+            Some(&Echo::test(None).metadata.name.unwrap())
+        );
+        assert_eq!(spec.ports.unwrap()[0].port, 80);
+    }
+
+    #[test]
+    fn test_service() {
+        let echo = echo_with_service(json!({
+            "type": "NodePort",
+            "port": 8080,
+            "annotations": {"example.com/team": "platform"},
+        }));
+        let service = echo.service();
+
+        let annotations = service.metadata.annotations.unwrap();
+        assert_eq!(
+            annotations.get("example.com/team").map(String::as_str),
+            Some("platform")
+        );
+        assert!(!annotations.contains_key(HOSTNAME_ANNOTATION));
+        let spec = service.spec.unwrap();
+        assert_eq!(spec.type_.as_deref(), Some("NodePort"));
+        assert_eq!(spec.ports.unwrap()[0].port, 8080);
+
+        let spec = echo_with_service(json!({})).service().spec.unwrap();
+        assert_eq!(spec.type_.as_deref(), Some("ClusterIP"));
+    }
+
+    #[test]
+    fn test_service_dual_stack() {
+        let mut echo = Echo::test(None);
+        echo.spec.dns_name = Some("echo.example.com".to_string());
+        let spec = echo.service().spec.unwrap();
+        assert_eq!(spec.ip_families, None);
+        assert_eq!(spec.ip_family_policy, None);
+
+        echo.spec.service = Some(EchoService {
+            ip_families: Some(vec!["IPv6".to_string(), "IPv4".to_string()]),
+            ip_family_policy: Some(EchoServiceIpFamilyPolicy::RequireDualStack),
+            ..EchoService::default()
+        });
+        let spec = echo.service().spec.unwrap();
+        assert_eq!(
+            spec.ip_families,
+            Some(vec!["IPv6".to_string(), "IPv4".to_string()])
+        );
+        assert_eq!(spec.ip_family_policy.as_deref(), Some("RequireDualStack"));
+        // the type is kept when only the IP families are set
+        assert_eq!(spec.type_.as_deref(), Some("LoadBalancer"));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_service_audits_changes() {
+        let path = std::env::temp_dir().join(format!("echo-service-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let fake = FakeApiServer::default();
+        fake.create(&echo_with_service(json!({})));
+        let mut echo = fake.get::<Echo>(Some("default"), "test").unwrap();
+        let (testctx, _fakeserver) = get_test_context();
+        let ctx = Context {
+            client: fake.client(),
+            auditor: Arc::new(Auditor::new(AuditSink::File(path.clone()))),
+            ..(*testctx).clone()
+        };
+
+        echo.reconcile_service(&ctx).await.unwrap();
+        echo.reconcile_service(&ctx).await.unwrap();
+        echo.spec.service = Some(EchoService {
+            port: Some(8080),
+            ..EchoService::default()
+        });
+        echo.reconcile_service(&ctx).await.unwrap();
+
+        assert!(fake.get::<Service>(Some("default"), "test").is_some());
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // the apply without changes is not audited
+        assert_eq!(content.lines().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_service_not_owned() {
+        let fake = FakeApiServer::default();
+        fake.create(&echo_with_service(json!({})));
+        fake.create(&Service {
+            metadata: ObjectMeta {
+                name: Some("test".to_string()),
+                namespace: Some("default".to_string()),
+                ..ObjectMeta::default()
+            },
+            ..Service::default()
+        });
+        let (testctx, _fakeserver) = get_test_context();
+        let ctx = Context {
+            client: fake.client(),
+            ..(*testctx).clone()
+        };
+        let stored = || fake.get::<Echo>(Some("default"), "test").unwrap();
+
+        assert!(stored().reconcile_service(&ctx).await.unwrap().is_none());
+        let service = fake.get::<Service>(Some("default"), "test").unwrap();
+        assert!(service.metadata.owner_references.is_none());
+        assert!(service.spec.is_none());
+        let echo = stored();
+        let condition = echo.condition(ConditionType::ServiceConflict).unwrap();
+        assert_eq!(
+            condition.message,
+            "Service test exists and is not owned by the echo"
+        );
+
+        // applied once the Service of the user is deleted
+        fake.delete::<Service>(Some("default"), "test");
+        assert!(echo.reconcile_service(&ctx).await.unwrap().is_some());
+        let echo = stored();
+        assert!(echo.owns(&fake.get::<Service>(Some("default"), "test").unwrap()));
+        assert!(echo.condition(ConditionType::ServiceConflict).is_none());
+    }
+}
@@ -0,0 +1,616 @@
+//! Construction and validation of Echo specs, so tooling creating Echoes from Rust checks them
+//! client-side with the same rules as the admission webhook.
+use crate::crd::echo::{
+    EchoAppArmorProfile, EchoAppArmorProfileType, EchoAutoscaling, EchoConflictPolicy,
+    EchoImagePullPolicy, EchoMonitoring, EchoOwnership, EchoRbac, EchoRbacRules,
+    EchoRecreatePolicy, EchoRegistration, EchoResponse, EchoRollout, EchoSchedules,
+    EchoSeccompProfile, EchoSeccompProfileType, EchoSecurityContext, EchoSecurityProfile,
+    EchoService, EchoServiceIpFamilyPolicy, EchoSpec,
+};
+use crate::echo::{response, rollout, schedule};
+use crate::error::{Error, Result};
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use k8s_openapi::api::core::v1::{Affinity, TopologySpreadConstraint};
+
+/// Valid HTTP status codes of the synthetic responses
+const STATUS_CODES: std::ops::RangeInclusive<i32> = 100..=599;
+
+/// Valid artificial delays of the synthetic responses, in milliseconds
+const DELAYS: std::ops::RangeInclusive<i32> = 0..=60_000;
+
+/// IP families of a Kubernetes Service
+const IP_FAMILIES: [&str; 2] = ["IPv4", "IPv6"];
+
+/// Ports of a Kubernetes Service
+const PORTS: std::ops::RangeInclusive<i32> = 1..=65_535;
+
+/// Longest progress deadline of the rollouts
+const MAX_PROGRESS_DEADLINE: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// Whether the name is a valid HTTP header name, an RFC 9110 token
+fn is_header_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+}
+
+impl EchoSpec {
+    /// Builder of a spec with the given replicas
+    pub fn builder(replicas: i32) -> EchoSpecBuilder {
+        EchoSpecBuilder(EchoSpec {
+            replicas,
+            ..EchoSpec::default()
+        })
+    }
+
+    /// Check the spec against the rules of the admission webhook
+    pub fn validate(&self) -> Result<()> {
+        if self.replicas < 0 {
+            return Err(Error::InvalidSpec(format!(
+                "replicas must not be negative, got {}",
+                self.replicas
+            )));
+        }
+        for entry in self.schedules.iter().flatten() {
+            schedule::parse(&entry.schedule)?;
+            if entry.replicas < 0 {
+                return Err(Error::InvalidSpec(format!(
+                    "replicas of schedule {:?} must not be negative, got {}",
+                    entry.schedule, entry.replicas
+                )));
+            }
+        }
+        if let Some(r) = self.response.as_ref() {
+            r.body.as_deref().map(response::check).transpose()?;
+            r.headers
+                .iter()
+                .flatten()
+                .try_for_each(|(_, v)| response::check(v))?;
+            if let Some(code) = r.status_code.filter(|c| !STATUS_CODES.contains(c)) {
+                return Err(Error::InvalidSpec(format!(
+                    "response statusCode must be between 100 and 599, got {code}"
+                )));
+            }
+            if let Some(delay) = r.delay_milliseconds.filter(|d| !DELAYS.contains(d)) {
+                return Err(Error::InvalidSpec(format!(
+                    "response delayMilliseconds must be between 0 and 60000, got {delay}"
+                )));
+            }
+            // joined with commas in the echo server environment
+            if let Some(header) = r
+                .include_headers
+                .iter()
+                .flatten()
+                .find(|h| !is_header_name(h))
+            {
+                return Err(Error::InvalidSpec(format!(
+                    "response includeHeaders must be HTTP header names, got {header:?}"
+                )));
+            }
+        }
+        if let Some(images) = self.image_per_arch.as_ref() {
+            if images.is_empty() {
+                return Err(Error::InvalidSpec(
+                    "imagePerArch must have at least one architecture".to_string(),
+                ));
+            }
+            if let Some((arch, image)) = images
+                .iter()
+                .find(|(arch, image)| arch.is_empty() || image.is_empty())
+            {
+                return Err(Error::InvalidSpec(format!(
+                    "imagePerArch architectures and images must not be empty, got {arch:?}: {image:?}"
+                )));
+            }
+            // a Deployment runs a single image
+            let distinct: BTreeSet<&String> = images.values().collect();
+            if distinct.len() > 1 {
+                return Err(Error::InvalidSpec(format!(
+                    "imagePerArch must set the same multi-arch image for every architecture, got {distinct:?}"
+                )));
+            }
+        }
+        if let Some(service) = self.service.as_ref() {
+            let families = service.ip_families.as_deref().unwrap_or_default();
+            if let Some(family) = families.iter().find(|f| !IP_FAMILIES.contains(&f.as_str())) {
+                return Err(Error::InvalidSpec(format!(
+                    "service ipFamilies must be IPv4 or IPv6, got {family:?}"
+                )));
+            }
+            if families.len() == 2 && families[0] == families[1] {
+                return Err(Error::InvalidSpec(
+                    "service ipFamilies must not be repeated".to_string(),
+                ));
+            }
+            let single_stack = matches!(
+                service.ip_family_policy,
+                Some(EchoServiceIpFamilyPolicy::SingleStack)
+            );
+            if single_stack && families.len() > 1 {
+                return Err(Error::InvalidSpec(
+                    "service ipFamilyPolicy SingleStack allows a single ipFamilies entry"
+                        .to_string(),
+                ));
+            }
+            if let Some(port) = service.port.filter(|p| !PORTS.contains(p)) {
+                return Err(Error::InvalidSpec(format!(
+                    "service port must be between 1 and 65535, got {port}"
+                )));
+            }
+        }
+        if let Some(registration) = self.registration.as_ref() {
+            if self.dns_name.is_none() && self.service.is_none() && self.ingress.is_none() {
+                return Err(Error::InvalidSpec(
+                    "registration requires a dnsName or a Service, the address registered"
+                        .to_string(),
+                ));
+            }
+            if !["http://", "https://"]
+                .iter()
+                .any(|scheme| registration.url.starts_with(scheme))
+            {
+                return Err(Error::InvalidSpec(format!(
+                    "registration url must be an HTTP URL, got {:?}",
+                    registration.url
+                )));
+            }
+        }
+        if let Some(autoscaling) = self.autoscaling.as_ref() {
+            if let Some(min_replicas) = autoscaling
+                .min_replicas
+                .filter(|min| *min > autoscaling.max_replicas)
+            {
+                return Err(Error::InvalidSpec(format!(
+                    "autoscaling minReplicas {min_replicas} exceeds maxReplicas {}",
+                    autoscaling.max_replicas
+                )));
+            }
+            if self.schedules.as_ref().is_some_and(|s| !s.is_empty()) {
+                return Err(Error::InvalidSpec(
+                    "schedules can not scale an autoscaled echo".to_string(),
+                ));
+            }
+        }
+        if let Some(rollout) = self.rollout.as_ref() {
+            let deadline = rollout.progress_deadline.as_deref();
+            if let Some(deadline) = deadline.filter(|d| {
+                rollout::parse_duration(d)
+                    .map_or(true, |d| d.is_zero() || d > MAX_PROGRESS_DEADLINE)
+            }) {
+                return Err(Error::InvalidSpec(format!(
+                    "rollout progressDeadline must be a duration between 1s and 24h, e.g. 10m, got \
+                     {deadline:?}"
+                )));
+            }
+            if rollout.auto_rollback == Some(true) && deadline.is_none() {
+                return Err(Error::InvalidSpec(
+                    "rollout autoRollback requires a progressDeadline".to_string(),
+                ));
+            }
+        }
+        let seccomp_without_profile = self.seccomp_profile.as_ref().is_some_and(|p| {
+            matches!(p.r#type, EchoSeccompProfileType::Localhost) && p.localhost_profile.is_none()
+        });
+        let apparmor_without_profile = self.app_armor_profile.as_ref().is_some_and(|p| {
+            matches!(p.r#type, EchoAppArmorProfileType::Localhost) && p.localhost_profile.is_none()
+        });
+        if seccomp_without_profile || apparmor_without_profile {
+            return Err(Error::InvalidSpec(
+                "Localhost profiles require a localhostProfile".to_string(),
+            ));
+        }
+        if let Some(affinity) = self.affinity.as_ref() {
+            serde_json::to_value(affinity)
+                .and_then(serde_json::from_value::<Affinity>)
+                .map_err(|e| Error::InvalidSpec(format!("invalid affinity: {e}")))?;
+        }
+        if let Some(constraints) = self.topology_spread_constraints.as_ref() {
+            serde_json::to_value(constraints)
+                .and_then(serde_json::from_value::<Vec<TopologySpreadConstraint>>)
+                .map_err(|e| {
+                    Error::InvalidSpec(format!("invalid topologySpreadConstraints: {e}"))
+                })?;
+        }
+        Ok(())
+    }
+}
+
+/// Fluent builder of Echo specs, validated when built
+#[derive(Clone, Debug)]
+pub struct EchoSpecBuilder(EchoSpec);
+
+impl EchoSpecBuilder {
+    /// Scale to the replicas when the cron expression fires
+    pub fn schedule(mut self, schedule: &str, replicas: i32) -> Self {
+        self.0
+            .schedules
+            .get_or_insert_with(Vec::new)
+            .push(EchoSchedules {
+                schedule: schedule.to_string(),
+                replicas,
+            });
+        self
+    }
+
+    pub fn image(mut self, image: &str) -> Self {
+        self.0.image = Some(image.to_string());
+        self
+    }
+
+    pub fn image_pull_policy(mut self, policy: EchoImagePullPolicy) -> Self {
+        self.0.image_pull_policy = Some(policy);
+        self
+    }
+
+    /// Schedule the echo pods on the nodes with the label
+    pub fn node_selector(mut self, key: &str, value: &str) -> Self {
+        self.0
+            .node_selector
+            .get_or_insert_with(BTreeMap::new)
+            .insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn dns_name(mut self, dns_name: &str) -> Self {
+        self.0.dns_name = Some(dns_name.to_string());
+        self
+    }
+
+    pub fn create_service_account(mut self, create: bool) -> Self {
+        self.0.create_service_account = Some(create);
+        self
+    }
+
+    /// Add a rule to the Role of the echo
+    pub fn rbac_rule(mut self, rule: EchoRbacRules) -> Self {
+        self.0
+            .rbac
+            .get_or_insert_with(EchoRbac::default)
+            .rules
+            .get_or_insert_with(Vec::new)
+            .push(rule);
+        self
+    }
+
+    pub fn monitoring(mut self, monitoring: EchoMonitoring) -> Self {
+        self.0.monitoring = Some(monitoring);
+        self
+    }
+
+    /// Image of the echo server for the node architecture
+    pub fn arch_image(mut self, arch: &str, image: &str) -> Self {
+        self.0
+            .image_per_arch
+            .get_or_insert_with(BTreeMap::new)
+            .insert(arch.to_string(), image.to_string());
+        self
+    }
+
+    pub fn response(mut self, response: EchoResponse) -> Self {
+        self.0.response = Some(response);
+        self
+    }
+
+    /// Scale the echo with a HorizontalPodAutoscaler instead of the replicas
+    pub fn autoscaling(mut self, autoscaling: EchoAutoscaling) -> Self {
+        self.0.autoscaling = Some(autoscaling);
+        self
+    }
+
+    pub fn service(mut self, service: EchoService) -> Self {
+        self.0.service = Some(service);
+        self
+    }
+
+    /// Register the echo in the registry once it is Ready
+    pub fn registration(mut self, url: &str) -> Self {
+        self.0.registration = Some(EchoRegistration {
+            url: url.to_string(),
+        });
+        self
+    }
+
+    pub fn conflict_policy(mut self, policy: EchoConflictPolicy) -> Self {
+        self.0.conflict_policy = Some(policy);
+        self
+    }
+
+    pub fn recreate_policy(mut self, policy: EchoRecreatePolicy) -> Self {
+        self.0.recreate_policy = Some(policy);
+        self
+    }
+
+    pub fn ownership(mut self, ownership: EchoOwnership) -> Self {
+        self.0.ownership = Some(ownership);
+        self
+    }
+
+    pub fn rollout(mut self, rollout: EchoRollout) -> Self {
+        self.0.rollout = Some(rollout);
+        self
+    }
+
+    pub fn read_only_root_filesystem(mut self, read_only: bool) -> Self {
+        self.0.read_only_root_filesystem = Some(read_only);
+        self
+    }
+
+    pub fn profile(mut self, profile: &str) -> Self {
+        self.0.profile = Some(profile.to_owned());
+        self
+    }
+
+    pub fn security_profile(mut self, profile: EchoSecurityProfile) -> Self {
+        self.0.security_profile = Some(profile);
+        self
+    }
+
+    /// Security context overrides, only applied with the Custom security profile
+    pub fn security_context(mut self, context: EchoSecurityContext) -> Self {
+        self.0.security_context = Some(context);
+        self
+    }
+
+    pub fn seccomp_profile(mut self, profile: EchoSeccompProfile) -> Self {
+        self.0.seccomp_profile = Some(profile);
+        self
+    }
+
+    pub fn app_armor_profile(mut self, profile: EchoAppArmorProfile) -> Self {
+        self.0.app_armor_profile = Some(profile);
+        self
+    }
+
+    /// Validated spec
+    pub fn build(self) -> Result<EchoSpec> {
+        self.0.validate()?;
+        Ok(self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crd::echo::{
+        EchoAutoscaling, EchoRbacRules, EchoResponse, EchoRollout, EchoSeccompProfile,
+        EchoSeccompProfileType, EchoService, EchoServiceIpFamilyPolicy, EchoSpec,
+    };
+    use crate::error::Error;
+
+    use serde_json::json;
+
+    #[test]
+    fn test_build() {
+        let spec = EchoSpec::builder(2)
+            .schedule("0 20 * * 1-5", 0)
+            .dns_name("echo.example.com")
+            .rbac_rule(EchoRbacRules {
+                api_groups: Some(vec!["".to_string()]),
+                resources: Some(vec!["pods".to_string()]),
+                resource_names: None,
+                verbs: vec!["get".to_string()],
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(spec.replicas, 2);
+        assert_eq!(spec.schedules.unwrap()[0].schedule, "0 20 * * 1-5");
+        assert_eq!(spec.dns_name.as_deref(), Some("echo.example.com"));
+        assert_eq!(spec.rbac.unwrap().rules.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(matches!(
+            EchoSpec::builder(-1).build(),
+            Err(Error::InvalidSpec(_))
+        ));
+        assert!(matches!(
+            EchoSpec::builder(1).schedule("not a cron", 0).build(),
+            Err(Error::InvalidSchedule(_))
+        ));
+        assert!(matches!(
+            EchoSpec::builder(1).schedule("0 20 * * *", -1).build(),
+            Err(Error::InvalidSpec(_))
+        ));
+        assert!(matches!(
+            EchoSpec::builder(1)
+                .response(EchoResponse {
+                    body: Some("{{ env.SECRET }}".to_string()),
+                    ..EchoResponse::default()
+                })
+                .build(),
+            Err(Error::TemplateError(_))
+        ));
+        assert!(matches!(
+            EchoSpec::builder(1)
+                .response(EchoResponse {
+                    status_code: Some(600),
+                    ..EchoResponse::default()
+                })
+                .build(),
+            Err(Error::InvalidSpec(_))
+        ));
+        assert!(matches!(
+            EchoSpec::builder(1)
+                .response(EchoResponse {
+                    delay_milliseconds: Some(-1),
+                    ..EchoResponse::default()
+                })
+                .build(),
+            Err(Error::InvalidSpec(_))
+        ));
+        assert!(matches!(
+            EchoSpec::builder(1)
+                .response(EchoResponse {
+                    include_headers: Some(vec!["X-Request-Id, Traceparent".to_string()]),
+                    ..EchoResponse::default()
+                })
+                .build(),
+            Err(Error::InvalidSpec(_))
+        ));
+        assert!(EchoSpec::builder(1)
+            .response(EchoResponse {
+                delay_milliseconds: Some(500),
+                include_headers: Some(vec!["X-Request-Id".to_string()]),
+                ..EchoResponse::default()
+            })
+            .build()
+            .is_ok());
+        assert!(matches!(
+            EchoSpec::builder(1).arch_image("arm64", "").build(),
+            Err(Error::InvalidSpec(_))
+        ));
+        assert!(EchoSpec::builder(1)
+            .arch_image("amd64", "echo:1")
+            .arch_image("arm64", "echo:1")
+            .build()
+            .is_ok());
+        assert!(matches!(
+            EchoSpec::builder(1)
+                .arch_image("amd64", "echo:1-amd64")
+                .arch_image("arm64", "echo:1-arm64")
+                .build(),
+            Err(Error::InvalidSpec(_))
+        ));
+        assert!(matches!(
+            EchoSpec::builder(1)
+                .service(EchoService {
+                    ip_families: Some(vec!["IPv6".to_string(), "IPv4".to_string()]),
+                    ip_family_policy: Some(EchoServiceIpFamilyPolicy::SingleStack),
+                    ..EchoService::default()
+                })
+                .build(),
+            Err(Error::InvalidSpec(_))
+        ));
+        assert!(matches!(
+            EchoSpec::builder(1)
+                .service(EchoService {
+                    ip_families: Some(vec!["IPv6".to_string(), "IPv6".to_string()]),
+                    ip_family_policy: None,
+                    ..EchoService::default()
+                })
+                .build(),
+            Err(Error::InvalidSpec(_))
+        ));
+        assert!(EchoSpec::builder(1)
+            .service(EchoService {
+                ip_families: Some(vec!["IPv6".to_string()]),
+                ip_family_policy: Some(EchoServiceIpFamilyPolicy::PreferDualStack),
+                ..EchoService::default()
+            })
+            .build()
+            .is_ok());
+        assert!(matches!(
+            EchoSpec::builder(1)
+                .service(EchoService {
+                    port: Some(0),
+                    ..EchoService::default()
+                })
+                .build(),
+            Err(Error::InvalidSpec(_))
+        ));
+        assert!(matches!(
+            EchoSpec::builder(1)
+                .registration("https://registry.example.com")
+                .build(),
+            Err(Error::InvalidSpec(_))
+        ));
+        assert!(matches!(
+            EchoSpec::builder(1)
+                .dns_name("echo.example.com")
+                .registration("registry.example.com")
+                .build(),
+            Err(Error::InvalidSpec(_))
+        ));
+        assert!(EchoSpec::builder(1)
+            .dns_name("echo.example.com")
+            .registration("https://registry.example.com")
+            .build()
+            .is_ok());
+        assert!(EchoSpec::builder(1)
+            .service(EchoService::default())
+            .registration("https://registry.example.com")
+            .build()
+            .is_ok());
+        assert!(matches!(
+            EchoSpec::builder(1)
+                .rollout(EchoRollout {
+                    progress_deadline: Some("10 minutes".to_string()),
+                    auto_rollback: None,
+                })
+                .build(),
+            Err(Error::InvalidSpec(_))
+        ));
+        assert!(matches!(
+            EchoSpec::builder(1)
+                .rollout(EchoRollout {
+                    progress_deadline: None,
+                    auto_rollback: Some(true),
+                })
+                .build(),
+            Err(Error::InvalidSpec(_))
+        ));
+        assert!(EchoSpec::builder(1)
+            .rollout(EchoRollout {
+                progress_deadline: Some("10m".to_string()),
+                auto_rollback: Some(true),
+            })
+            .build()
+            .is_ok());
+        let mut spec = EchoSpec::builder(1)
+            .node_selector("pool", "echo")
+            .build()
+            .unwrap();
+        spec.affinity = Some(
+            serde_json::from_value(json!({
+                "podAntiAffinity": {"requiredDuringSchedulingIgnoredDuringExecution": []}
+            }))
+            .unwrap(),
+        );
+        assert!(spec.validate().is_ok());
+        spec.affinity = Some(serde_json::from_value(json!({"podAntiAffinity": []})).unwrap());
+        assert!(matches!(spec.validate(), Err(Error::InvalidSpec(_))));
+        spec.affinity = None;
+        spec.topology_spread_constraints =
+            Some(serde_json::from_value(json!([{"maxSkew": "one"}])).unwrap());
+        assert!(matches!(spec.validate(), Err(Error::InvalidSpec(_))));
+
+        let autoscaling = EchoAutoscaling {
+            min_replicas: Some(2),
+            max_replicas: 5,
+            ..EchoAutoscaling::default()
+        };
+        assert!(EchoSpec::builder(1)
+            .autoscaling(autoscaling.clone())
+            .build()
+            .is_ok());
+        assert!(matches!(
+            EchoSpec::builder(1)
+                .autoscaling(EchoAutoscaling {
+                    min_replicas: Some(6),
+                    ..autoscaling.clone()
+                })
+                .build(),
+            Err(Error::InvalidSpec(_))
+        ));
+        assert!(matches!(
+            EchoSpec::builder(1)
+                .autoscaling(autoscaling)
+                .schedule("0 8 * * 1-5", 3)
+                .build(),
+            Err(Error::InvalidSpec(_))
+        ));
+        assert!(matches!(
+            EchoSpec::builder(1)
+                .seccomp_profile(EchoSeccompProfile {
+                    r#type: EchoSeccompProfileType::Localhost,
+                    localhost_profile: None,
+                })
+                .build(),
+            Err(Error::InvalidSpec(_))
+        ));
+    }
+}
@@ -0,0 +1,117 @@
+//! Time spent by an echo reconciliation in each phase, to explain the slow ones.
+//!
+//! Phases are recorded in a task local, so they do not have to be passed around the reconcile
+//! functions; time measured outside of [`timed`] is dropped.
+use std::cell::Cell;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+tokio::task_local! {
+    static TIMINGS: Cell<PhaseTimings>;
+}
+
+/// Reconciliation phase
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Phase {
+    /// Apply, or recreate, of the Deployment
+    Apply,
+    /// Patches of the Echo status
+    Status,
+    /// Lookups of the reflector stores
+    Store,
+}
+
+/// Time spent in each phase
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct PhaseTimings {
+    pub apply: Duration,
+    pub status: Duration,
+    pub store: Duration,
+}
+
+impl PhaseTimings {
+    fn add(mut self, phase: Phase, elapsed: Duration) -> Self {
+        match phase {
+            Phase::Apply => self.apply += elapsed,
+            Phase::Status => self.status += elapsed,
+            Phase::Store => self.store += elapsed,
+        }
+        self
+    }
+
+    /// Time of the given total not spent in any phase, e.g. other API calls
+    pub fn other(&self, total: Duration) -> Duration {
+        total.saturating_sub(self.apply + self.status + self.store)
+    }
+}
+
+fn record(phase: Phase, elapsed: Duration) {
+    // outside of a timed reconciliation there is nothing to record
+    let _ = TIMINGS.try_with(|t| t.set(t.get().add(phase, elapsed)));
+}
+
+/// Run the future recording its time in the given phase
+pub(crate) async fn measure<F: Future>(phase: Phase, future: F) -> F::Output {
+    let start = Instant::now();
+    let output = future.await;
+    record(phase, start.elapsed());
+    output
+}
+
+/// Run the function recording its time in the given phase
+pub(crate) fn measure_sync<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let output = f();
+    record(phase, start.elapsed());
+    output
+}
+
+/// Run the reconciliation future, returning the time spent in each phase with its output
+pub(crate) async fn timed<F: Future>(future: F) -> (F::Output, PhaseTimings) {
+    TIMINGS
+        .scope(Cell::new(PhaseTimings::default()), async {
+            let output = future.await;
+            (output, TIMINGS.with(Cell::get))
+        })
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::{measure, measure_sync, timed, Phase, PhaseTimings};
+
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_timed() {
+        let (output, timings) = timed(async {
+            measure(Phase::Apply, tokio::time::sleep(Duration::from_millis(10))).await;
+            measure_sync(Phase::Store, || 42)
+        })
+        .await;
+
+        assert_eq!(output, 42);
+        assert!(timings.apply >= Duration::from_millis(10));
+        assert_eq!(timings.status, Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_measure_outside_timed() {
+        assert_eq!(measure(Phase::Status, async { 1 }).await, 1);
+    }
+
+    #[test]
+    fn test_other() {
+        let timings = PhaseTimings {
+            apply: Duration::from_millis(300),
+            status: Duration::from_millis(200),
+            store: Duration::ZERO,
+        };
+
+        assert_eq!(
+            timings.other(Duration::from_secs(1)),
+            Duration::from_millis(500)
+        );
+        assert_eq!(timings.other(Duration::from_millis(100)), Duration::ZERO);
+    }
+}
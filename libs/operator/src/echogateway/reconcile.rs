@@ -0,0 +1,614 @@
+use crate::controller::Context;
+use crate::crd::echogateway::{EchoGateway, EchoGatewayStatus, EchoGatewayStatusBackends};
+use crate::echo::condition::ConditionType;
+use crate::echo::reconcile::ECHO_PORT;
+use crate::error::{Error, Result};
+use crate::owned;
+use crate::telemetry;
+
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::{ObjectReference, Pod, Service, ServicePort, ServiceSpec};
+use k8s_openapi::api::discovery::v1::{Endpoint, EndpointConditions, EndpointPort, EndpointSlice};
+use k8s_openapi::api::networking::v1::{
+    HTTPIngressPath, HTTPIngressRuleValue, Ingress, IngressBackend, IngressRule,
+    IngressServiceBackend, IngressSpec, ServiceBackendPort,
+};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use kube::api::{Api, ObjectMeta, Patch, PatchParams, Resource};
+use kube::runtime::controller::Action;
+use kube::ResourceExt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
+use tracing::{debug, field, info, instrument, trace, Span};
+
+/// Port exposed by the gateway Service when it is not set in the spec
+pub const DEFAULT_PORT: i32 = 80;
+const DEFAULT_WEIGHT: i32 = 1;
+const DEFAULT_PATH: &str = "/";
+const PORT_NAME: &str = "http";
+const FIELD_MANAGER: &str = "echogateways.example.com";
+
+#[instrument(skip(ctx, gateway))]
+pub async fn reconcile_echo_gateway(
+    gateway: Arc<EchoGateway>,
+    ctx: Arc<Context>,
+) -> Result<Action> {
+    let trace_id = telemetry::get_trace_id();
+    Span::current().record("trace_id", field::display(&trace_id));
+    let _timer = ctx.metrics.reconcile_count_and_measure(&trace_id);
+    info!(msg = "reconciling EchoGateway");
+
+    if let Some(requeue) = ctx.maintenance_requeue(&*gateway) {
+        return Ok(Action::requeue(requeue));
+    }
+
+    let endpoints = gateway.select_endpoints(&ctx);
+    gateway.apply(gateway.service(), ctx.clone()).await?;
+    for family in AddressFamily::ALL {
+        gateway
+            .apply(gateway.endpoint_slice(&endpoints, family), ctx.clone())
+            .await?;
+    }
+    match gateway.ingress() {
+        Some(ingress) => {
+            gateway.apply(ingress, ctx.clone()).await?;
+        }
+        None => gateway.delete_ingress(ctx.clone()).await?,
+    }
+
+    let _ignore_errors = gateway
+        .update_status(&endpoints, ctx.clone())
+        .await
+        .map_err(|e| {
+            debug!(msg = "failed to reconcile status", %e);
+            ctx.metrics.status_update_errors_inc();
+        });
+    ctx.reconciled(&*gateway);
+    Ok(Action::requeue(ctx.requeue_interval(&*gateway)))
+}
+
+/// IP family of the addresses of an EndpointSlice, as a slice only holds addresses of one
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AddressFamily {
+    IPv4,
+    IPv6,
+}
+
+impl AddressFamily {
+    const ALL: [AddressFamily; 2] = [AddressFamily::IPv4, AddressFamily::IPv6];
+
+    fn address_type(self) -> &'static str {
+        match self {
+            AddressFamily::IPv4 => "IPv4",
+            AddressFamily::IPv6 => "IPv6",
+        }
+    }
+
+    fn contains(self, ip: &IpAddr) -> bool {
+        match self {
+            AddressFamily::IPv4 => ip.is_ipv4(),
+            AddressFamily::IPv6 => ip.is_ipv6(),
+        }
+    }
+}
+
+/// Address of the pod of the family, from its IPs of every family in dual-stack clusters
+fn pod_address(pod: &Pod, family: AddressFamily) -> Option<String> {
+    let status = pod.status.as_ref()?;
+    status
+        .pod_ips
+        .iter()
+        .flatten()
+        .map(|pod_ip| &pod_ip.ip)
+        .chain(status.pod_ip.as_ref())
+        .find(|ip| ip.parse().is_ok_and(|ip| family.contains(&ip)))
+        .cloned()
+}
+
+/// Pods of a backend receiving the gateway traffic
+struct BackendEndpoints {
+    name: String,
+    weight: i32,
+    pods: Vec<Arc<Pod>>,
+}
+
+/// Number of endpoints of each backend so traffic is split as close as possible to the weights
+///
+/// Every endpoint of a Service receives the same share of traffic, so backends get a number of
+/// endpoints proportional to their weight, limited by the backend with less ready pods per weight
+/// unit. Backends with weight 0 or without ready pods get no endpoints and the rest of backends
+/// get at least one.
+fn allocate_endpoints(backends: &[(i32, usize)]) -> Vec<usize> {
+    let eligible = |(weight, ready): &(i32, usize)| *weight > 0 && *ready > 0;
+    // backend with the lowest ready pods per weight unit limits the rest of backends
+    let limit = backends
+        .iter()
+        .filter(|&b| eligible(b))
+        .min_by(|(wa, ra), (wb, rb)| (*ra * *wb as usize).cmp(&(*rb * *wa as usize)));
+
+    backends
+        .iter()
+        .map(|backend @ (weight, ready)| match limit {
+            Some((limit_weight, limit_ready)) if eligible(backend) => {
+                let endpoints = *weight as usize * limit_ready / *limit_weight as usize;
+                endpoints.clamp(1, *ready)
+            }
+            _ => 0,
+        })
+        .collect()
+}
+
+fn is_pod_ready(pod: &Pod) -> bool {
+    pod.metadata.deletion_timestamp.is_none()
+        && pod
+            .status
+            .as_ref()
+            .and_then(|s| s.conditions.as_ref())
+            .is_some_and(|c| c.iter().any(|c| c.type_ == "Ready" && c.status == "True"))
+        && pod
+            .status
+            .as_ref()
+            .and_then(|s| s.pod_ip.as_ref())
+            .is_some()
+}
+
+fn condition(type_: ConditionType, generation: Option<i64>, now: DateTime<Utc>) -> Condition {
+    Condition {
+        type_: type_.to_string(),
+        status: "True".to_string(),
+        reason: "".to_string(),
+        message: "".to_string(),
+        last_transition_time: Time(now),
+        observed_generation: generation,
+    }
+}
+
+impl EchoGateway {
+    #[inline]
+    fn get_namespace(&self) -> String {
+        // safe unwrap: EchoGateway is namespaced scoped
+        self.namespace().unwrap()
+    }
+
+    fn gateway_labels(&self) -> BTreeMap<String, String> {
+        self.labels()
+            .iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .chain([
+                ("app".to_owned(), self.name_any()),
+                (
+                    "app.kubernetes.io/name".to_owned(),
+                    "echogateway".to_owned(),
+                ),
+                (
+                    "app.kubernetes.io/managed-by".to_owned(),
+                    "echo-operator".to_owned(),
+                ),
+            ])
+            .collect()
+    }
+
+    fn object_meta(&self, labels: BTreeMap<String, String>) -> ObjectMeta {
+        ObjectMeta {
+            name: Some(self.name_any()),
+            namespace: Some(self.get_namespace()),
+            labels: Some(labels),
+            owner_references: self.controller_owner_ref(&()).map(|oref| vec![oref]),
+            ..ObjectMeta::default()
+        }
+    }
+
+    /// Ready pods of every backend, limited to the number of endpoints given by their weights
+    fn select_endpoints(&self, ctx: &Context) -> Vec<BackendEndpoints> {
+        let namespace = self.get_namespace();
+        let pods = ctx
+            .stores
+            .get::<Pod>()
+            // safe unwrap: pod store should exists
+            .unwrap()
+            .state();
+
+        let backends: Vec<BackendEndpoints> = self
+            .spec
+            .backends
+            .iter()
+            .map(|backend| {
+                let mut backend_pods: Vec<Arc<Pod>> = pods
+                    .iter()
+                    .filter(|pod| pod.namespace().as_deref() == Some(namespace.as_str()))
+                    .filter(|pod| {
+                        let labels = pod.labels();
+                        labels.get("app") == Some(&backend.name)
+                            && labels.get("app.kubernetes.io/name").map(String::as_str)
+                                == Some("echo")
+                    })
+                    .filter(|pod| is_pod_ready(pod))
+                    .cloned()
+                    .collect();
+                // sort to keep the same endpoints between reconciliations
+                backend_pods.sort_by_key(|pod| pod.name_any());
+                BackendEndpoints {
+                    name: backend.name.clone(),
+                    weight: backend.weight.unwrap_or(DEFAULT_WEIGHT),
+                    pods: backend_pods,
+                }
+            })
+            .collect();
+
+        let allocation = allocate_endpoints(
+            &backends
+                .iter()
+                .map(|b| (b.weight, b.pods.len()))
+                .collect::<Vec<_>>(),
+        );
+        backends
+            .into_iter()
+            .zip(allocation)
+            .map(|(mut backend, endpoints)| {
+                backend.pods.truncate(endpoints);
+                backend
+            })
+            .collect()
+    }
+
+    /// Service without selector: its endpoints are managed by the gateway EndpointSlice
+    fn service(&self) -> Service {
+        Service {
+            metadata: self.object_meta(self.gateway_labels()),
+            spec: Some(ServiceSpec {
+                ports: Some(vec![ServicePort {
+                    name: Some(PORT_NAME.to_owned()),
+                    port: self.spec.port.unwrap_or(DEFAULT_PORT),
+                    target_port: Some(IntOrString::Int(ECHO_PORT)),
+                    protocol: Some("TCP".to_owned()),
+                    ..ServicePort::default()
+                }]),
+                ..ServiceSpec::default()
+            }),
+            ..Service::default()
+        }
+    }
+
+    /// EndpointSlice of the endpoints of the family. The IPv4 one is named after the gateway and
+    /// the IPv6 one gets the `-ipv6` suffix.
+    fn endpoint_slice(
+        &self,
+        backends: &[BackendEndpoints],
+        family: AddressFamily,
+    ) -> EndpointSlice {
+        let labels = self
+            .gateway_labels()
+            .into_iter()
+            .chain([
+                ("kubernetes.io/service-name".to_owned(), self.name_any()),
+                (
+                    "endpointslice.kubernetes.io/managed-by".to_owned(),
+                    FIELD_MANAGER.to_owned(),
+                ),
+            ])
+            .collect();
+
+        let endpoints = backends
+            .iter()
+            .flat_map(|b| b.pods.iter())
+            .filter_map(|pod| pod_address(pod, family).map(|address| (pod, address)))
+            .map(|(pod, address)| Endpoint {
+                addresses: vec![address],
+                conditions: Some(EndpointConditions {
+                    ready: Some(true),
+                    ..EndpointConditions::default()
+                }),
+                node_name: pod.spec.as_ref().and_then(|s| s.node_name.clone()),
+                target_ref: Some(ObjectReference {
+                    kind: Some("Pod".to_owned()),
+                    name: Some(pod.name_any()),
+                    namespace: pod.namespace(),
+                    uid: pod.uid(),
+                    ..ObjectReference::default()
+                }),
+                ..Endpoint::default()
+            })
+            .collect();
+
+        let name = match family {
+            AddressFamily::IPv4 => self.name_any(),
+            AddressFamily::IPv6 => format!("{}-ipv6", self.name_any()),
+        };
+        EndpointSlice {
+            metadata: ObjectMeta {
+                name: Some(name),
+                ..self.object_meta(labels)
+            },
+            address_type: family.address_type().to_owned(),
+            endpoints,
+            ports: Some(vec![EndpointPort {
+                name: Some(PORT_NAME.to_owned()),
+                port: Some(ECHO_PORT),
+                protocol: Some("TCP".to_owned()),
+                ..EndpointPort::default()
+            }]),
+        }
+    }
+
+    fn ingress(&self) -> Option<Ingress> {
+        let ingress = self.spec.ingress.as_ref()?;
+        Some(Ingress {
+            metadata: self.object_meta(self.gateway_labels()),
+            spec: Some(IngressSpec {
+                ingress_class_name: ingress.class_name.clone(),
+                rules: Some(vec![IngressRule {
+                    host: Some(ingress.host.clone()),
+                    http: Some(HTTPIngressRuleValue {
+                        paths: vec![HTTPIngressPath {
+                            path: Some(
+                                ingress
+                                    .path
+                                    .clone()
+                                    .unwrap_or_else(|| DEFAULT_PATH.to_owned()),
+                            ),
+                            path_type: "Prefix".to_owned(),
+                            backend: IngressBackend {
+                                service: Some(IngressServiceBackend {
+                                    name: self.name_any(),
+                                    port: Some(ServiceBackendPort {
+                                        name: Some(PORT_NAME.to_owned()),
+                                        ..ServiceBackendPort::default()
+                                    }),
+                                }),
+                                ..IngressBackend::default()
+                            },
+                        }],
+                    }),
+                }]),
+                ..IngressSpec::default()
+            }),
+            ..Ingress::default()
+        })
+    }
+
+    async fn apply<K>(&self, object: K, ctx: Arc<Context>) -> Result<K, Error>
+    where
+        K: Resource<Scope = k8s_openapi::NamespaceResourceScope, DynamicType = ()>
+            + Clone
+            + DeserializeOwned
+            + Serialize
+            + std::fmt::Debug,
+    {
+        let api = Api::<K>::namespaced(ctx.client.clone(), &self.get_namespace());
+        api.patch(
+            &object.name_any(),
+            &PatchParams::apply(FIELD_MANAGER).force(),
+            &Patch::Apply(&object),
+        )
+        .await
+        .map_err(Error::KubeError)
+    }
+
+    /// Delete the Ingress of the gateway, left alone when it was not created for the gateway
+    async fn delete_ingress(&self, ctx: Arc<Context>) -> Result<(), Error> {
+        let ingress_api = Api::<Ingress>::namespaced(ctx.client.clone(), &self.get_namespace());
+        owned::delete_controlled(&ingress_api, &self.name_any(), self).await?;
+        Ok(())
+    }
+
+    async fn update_status(&self, backends: &[BackendEndpoints], ctx: Arc<Context>) -> Result<()> {
+        let new_status = self.generate_status(backends, ctx.clock.now());
+        let new_status_patch = Patch::Apply(json!({
+            "apiVersion": "example.com/v1",
+            "kind": "EchoGateway",
+            "status": new_status
+        }));
+        debug!(msg = "updating EchoGateway status");
+        trace!(msg = format!("new status {:?}", new_status_patch));
+        let patch = PatchParams::apply(FIELD_MANAGER).force();
+        let gateway_api = Api::<EchoGateway>::namespaced(ctx.client.clone(), &self.get_namespace());
+        let _o = gateway_api
+            .patch_status(&self.name_any(), &patch, &new_status_patch)
+            .await
+            .map_err(Error::KubeError)?;
+        Ok(())
+    }
+
+    /// Generate the EchoGatewayStatus based on the selected endpoints
+    fn generate_status(
+        &self,
+        backends: &[BackendEndpoints],
+        now: DateTime<Utc>,
+    ) -> EchoGatewayStatus {
+        // ready when every backend expected to receive traffic has endpoints
+        let status_type = if backends.iter().all(|b| b.weight == 0 || !b.pods.is_empty()) {
+            ConditionType::Ready
+        } else {
+            ConditionType::Progressing
+        };
+
+        let generation = self.metadata.generation;
+        let condition = match self.status.as_ref().and_then(|s| s.conditions.as_ref()) {
+            // keep the transition time while the status type doesn't change
+            Some(previous) => match previous.iter().find(|c| c.type_ == status_type.as_str()) {
+                Some(c) => Condition {
+                    observed_generation: generation,
+                    ..c.clone()
+                },
+                None => condition(status_type, generation, now),
+            },
+            None => condition(status_type, generation, now),
+        };
+
+        EchoGatewayStatus {
+            backends: Some(
+                backends
+                    .iter()
+                    .map(|b| EchoGatewayStatusBackends {
+                        name: Some(b.name.clone()),
+                        endpoints: Some(b.pods.len() as i32),
+                    })
+                    .collect(),
+            ),
+            conditions: Some(vec![condition]),
+            observed_generation: generation,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{allocate_endpoints, AddressFamily, BackendEndpoints};
+
+    use crate::crd::echogateway::{EchoGateway, EchoGatewayBackends, EchoGatewaySpec};
+    use crate::echo::condition::ConditionType;
+    use crate::test_utils::test_time;
+
+    use std::sync::Arc;
+
+    use k8s_openapi::api::core::v1::{Pod, PodIP, PodStatus};
+    use kube::api::ObjectMeta;
+    use kube::Resource;
+
+    fn gateway() -> EchoGateway {
+        let mut g = EchoGateway::new(
+            "test",
+            EchoGatewaySpec {
+                backends: vec![EchoGatewayBackends {
+                    name: "test".to_string(),
+                    weight: None,
+                }],
+                ..EchoGatewaySpec::default()
+            },
+        );
+        g.meta_mut().namespace = Some("default".into());
+        g
+    }
+
+    #[test]
+    fn test_allocate_endpoints_equal_weights() {
+        assert_eq!(allocate_endpoints(&[(1, 3), (1, 3)]), vec![3, 3]);
+        assert_eq!(allocate_endpoints(&[(1, 3), (1, 1)]), vec![1, 1]);
+    }
+
+    #[test]
+    fn test_allocate_endpoints_weighted() {
+        assert_eq!(allocate_endpoints(&[(3, 6), (1, 2)]), vec![6, 2]);
+        assert_eq!(allocate_endpoints(&[(3, 3), (1, 3)]), vec![3, 1]);
+        assert_eq!(allocate_endpoints(&[(9, 3), (1, 3)]), vec![3, 1]);
+    }
+
+    #[test]
+    fn test_allocate_endpoints_skips_unavailable_backends() {
+        assert_eq!(allocate_endpoints(&[(0, 3), (1, 2)]), vec![0, 2]);
+        assert_eq!(allocate_endpoints(&[(1, 0), (1, 2)]), vec![0, 2]);
+        assert_eq!(allocate_endpoints(&[(1, 0)]), vec![0]);
+        assert_eq!(allocate_endpoints(&[]), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_service_is_selectorless() {
+        let service = gateway().service();
+
+        let spec = service.spec.unwrap();
+        assert_eq!(spec.selector, None);
+        assert_eq!(spec.ports.unwrap()[0].port, super::DEFAULT_PORT);
+    }
+
+    #[test]
+    fn test_endpoint_slice_is_linked_to_service() {
+        let slice = gateway().endpoint_slice(&[], AddressFamily::IPv4);
+
+        assert_eq!(
+            slice.metadata.labels.unwrap()["kubernetes.io/service-name"],
+            "test"
+        );
+        assert!(slice.endpoints.is_empty());
+    }
+
+    #[test]
+    fn test_endpoint_slice_per_address_family() {
+        let pod = |name: &str, ips: &[&str]| {
+            Arc::new(Pod {
+                metadata: ObjectMeta {
+                    name: Some(name.to_string()),
+                    ..ObjectMeta::default()
+                },
+                status: Some(PodStatus {
+                    pod_ip: Some(ips[0].to_string()),
+                    pod_ips: Some(ips.iter().map(|ip| PodIP { ip: ip.to_string() }).collect()),
+                    ..PodStatus::default()
+                }),
+                ..Pod::default()
+            })
+        };
+        let backends = [BackendEndpoints {
+            name: "test".to_string(),
+            weight: 1,
+            pods: vec![
+                pod("dual-stack", &["10.0.0.1", "fd00::1"]),
+                pod("ipv4", &["10.0.0.2"]),
+                pod("ipv6", &["fd00::3"]),
+            ],
+        }];
+        let addresses = |family| {
+            let slice = gateway().endpoint_slice(&backends, family);
+            let addresses: Vec<String> = slice
+                .endpoints
+                .into_iter()
+                .flat_map(|e| e.addresses)
+                .collect();
+            (slice.metadata.name.unwrap(), slice.address_type, addresses)
+        };
+
+        assert_eq!(
+            addresses(AddressFamily::IPv4),
+            (
+                "test".to_string(),
+                "IPv4".to_string(),
+                vec!["10.0.0.1".to_string(), "10.0.0.2".to_string()]
+            )
+        );
+        assert_eq!(
+            addresses(AddressFamily::IPv6),
+            (
+                "test-ipv6".to_string(),
+                "IPv6".to_string(),
+                vec!["fd00::1".to_string(), "fd00::3".to_string()]
+            )
+        );
+    }
+
+    #[test]
+    fn test_no_ingress_without_spec() {
+        assert!(gateway().ingress().is_none());
+    }
+
+    #[test]
+    fn test_generate_status() {
+        let gateway = gateway();
+        let ready = [BackendEndpoints {
+            name: "test".to_string(),
+            weight: 1,
+            pods: vec![Default::default()],
+        }];
+        let progressing = [BackendEndpoints {
+            name: "test".to_string(),
+            weight: 1,
+            pods: vec![],
+        }];
+
+        let status = gateway.generate_status(&ready, test_time());
+        let conditions = status.conditions.unwrap();
+        assert_eq!(conditions.len(), 1);
+        assert_eq!(conditions[0].type_, ConditionType::Ready.as_str());
+        assert_eq!(status.backends.unwrap()[0].endpoints, Some(1));
+
+        let status = gateway.generate_status(&progressing, test_time());
+        assert_eq!(
+            status.conditions.unwrap()[0].type_,
+            ConditionType::Progressing.as_str()
+        );
+    }
+}
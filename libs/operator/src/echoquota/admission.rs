@@ -0,0 +1,134 @@
+//! Validating admission of Echo resources: their spec, the permissions their RBAC rules grant and
+//! the EchoQuotas of their namespace.
+use crate::crd::echo::Echo;
+use crate::crd::echoquota::EchoQuota;
+use crate::echoquota::reconcile::Usage;
+use crate::error::{Error, Result};
+use crate::rbac_allowlist::RbacAllowlist;
+
+use std::iter;
+
+use kube::api::{Api, ListParams};
+use kube::client::Client;
+use kube::core::admission::{AdmissionRequest, AdmissionResponse, AdmissionReview};
+use kube::core::DynamicObject;
+use kube::ResourceExt;
+use tracing::{debug, error};
+
+/// Answer an Echo admission review, denying invalid specs, RBAC rules beyond the allowlist, and
+/// creations and scale ups exceeding any quota
+pub async fn validate_echo(
+    client: Client,
+    rbac_allowlist: &RbacAllowlist,
+    review: AdmissionReview<Echo>,
+) -> AdmissionReview<DynamicObject> {
+    let request: AdmissionRequest<Echo> = match review.try_into() {
+        Ok(request) => request,
+        Err(e) => {
+            error!(msg = "invalid admission review", %e);
+            return AdmissionResponse::invalid(e.to_string()).into_review();
+        }
+    };
+    let response = AdmissionResponse::from(&request);
+
+    let (Some(echo), Some(namespace)) = (request.object.as_ref(), request.namespace.as_deref())
+    else {
+        return response.into_review();
+    };
+    if let Err(e) = echo.spec.validate() {
+        debug!(msg = "echo denied", %e);
+        return response.deny(e.to_string()).into_review();
+    }
+    if let Err(e) = rbac_allowlist.check(&echo.rbac_rules()) {
+        debug!(msg = "echo denied", %e);
+        return response.deny(e.to_string()).into_review();
+    }
+    // scale downs are always allowed, so over quota namespaces can recover
+    if request
+        .old_object
+        .as_ref()
+        .is_some_and(|old| echo.spec.replicas <= old.spec.replicas)
+    {
+        return response.into_review();
+    }
+
+    match check_quotas(client, namespace, echo).await {
+        Ok(()) => response.into_review(),
+        Err(e) => {
+            debug!(msg = "echo denied", %e);
+            response.deny(e.to_string()).into_review()
+        }
+    }
+}
+
+async fn check_quotas(client: Client, namespace: &str, echo: &Echo) -> Result<()> {
+    let quotas: Vec<EchoQuota> = Api::<EchoQuota>::all(client.clone())
+        .list(&ListParams::default())
+        .await
+        .map_err(Error::KubeError)?
+        .into_iter()
+        .filter(|q| q.applies_to(namespace))
+        .collect();
+    if quotas.is_empty() {
+        return Ok(());
+    }
+
+    let echoes = Api::<Echo>::namespaced(client, namespace)
+        .list(&ListParams::default())
+        .await
+        .map_err(Error::KubeError)?;
+    let usage = projected_usage(&echoes.items, echo);
+    quotas.iter().try_for_each(|q| q.check(namespace, &usage))
+}
+
+/// Namespace usage once the Echo is admitted
+fn projected_usage(echoes: &[Echo], admitted: &Echo) -> Usage {
+    let name = admitted.name_any();
+    echoes
+        .iter()
+        .filter(|e| e.name_any() != name)
+        .chain(iter::once(admitted))
+        .fold(Usage::default(), Usage::add)
+}
+
+#[cfg(test)]
+mod test {
+    use super::projected_usage;
+
+    use crate::crd::echo::Echo;
+    use crate::echoquota::reconcile::Usage;
+
+    use kube::Resource;
+
+    fn echo(name: &str, replicas: i32) -> Echo {
+        let mut e = Echo::test(None).change_replicas(replicas);
+        e.meta_mut().name = Some(name.to_string());
+        e
+    }
+
+    #[test]
+    fn test_projected_usage_on_create() {
+        let echoes = [echo("a", 1), echo("b", 2)];
+
+        assert_eq!(
+            projected_usage(&echoes, &echo("c", 3)),
+            Usage {
+                echoes: 3,
+                replicas: 6
+            }
+        );
+    }
+
+    #[test]
+    fn test_projected_usage_on_update() {
+        let echoes = [echo("a", 1), echo("b", 2)];
+
+        assert_eq!(
+            projected_usage(&echoes, &echo("b", 5)),
+            Usage {
+                echoes: 2,
+                replicas: 6
+            }
+        );
+    }
+}
@@ -0,0 +1,3 @@
+pub mod admission;
+pub mod controller;
+pub mod reconcile;
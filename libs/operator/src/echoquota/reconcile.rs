@@ -0,0 +1,274 @@
+use crate::controller::Context;
+use crate::crd::echo::Echo;
+use crate::crd::echoquota::{EchoQuota, EchoQuotaStatus, EchoQuotaStatusNamespaces};
+use crate::error::{Error, Result};
+use crate::telemetry;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use kube::api::{Api, Patch, PatchParams};
+use kube::runtime::controller::Action;
+use kube::ResourceExt;
+use serde_json::json;
+use tracing::{debug, field, info, instrument, trace, Span};
+
+const FIELD_MANAGER: &str = "echoquotas.example.com";
+
+/// Echo resources and replicas of a namespace
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Usage {
+    pub echoes: i32,
+    pub replicas: i32,
+}
+
+impl Usage {
+    pub(crate) fn add(mut self, echo: &Echo) -> Self {
+        self.echoes += 1;
+        self.replicas += echo.spec.replicas;
+        self
+    }
+}
+
+/// Usage of every namespace with Echo resources
+pub fn usage_by_namespace<'a>(echoes: impl Iterator<Item = &'a Echo>) -> BTreeMap<String, Usage> {
+    echoes.fold(BTreeMap::new(), |mut usage, echo| {
+        // safe unwrap: Echo is namespaced scoped
+        let namespace = echo.namespace().unwrap();
+        let namespace_usage = usage.remove(&namespace).unwrap_or_default().add(echo);
+        usage.insert(namespace, namespace_usage);
+        usage
+    })
+}
+
+#[instrument(skip(ctx, quota))]
+pub async fn reconcile_echo_quota(quota: Arc<EchoQuota>, ctx: Arc<Context>) -> Result<Action> {
+    let trace_id = telemetry::get_trace_id();
+    Span::current().record("trace_id", field::display(&trace_id));
+    let _timer = ctx.metrics.reconcile_count_and_measure(&trace_id);
+    info!(msg = "reconciling EchoQuota");
+
+    let echoes = ctx
+        .stores
+        .get::<Echo>()
+        // safe unwrap: echo store should exists
+        .unwrap()
+        .state();
+    let usage = usage_by_namespace(echoes.iter().map(|e| e.as_ref()));
+    quota
+        .update_status(quota.generate_status(&usage), ctx.clone())
+        .await?;
+    ctx.reconciled(&*quota);
+    Ok(Action::requeue(ctx.requeue_interval(&*quota)))
+}
+
+impl EchoQuota {
+    /// Whether the quota limits the given namespace
+    pub fn applies_to(&self, namespace: &str) -> bool {
+        match self.spec.namespaces.as_deref() {
+            Some(namespaces) if !namespaces.is_empty() => namespaces.iter().any(|n| n == namespace),
+            _ => true,
+        }
+    }
+
+    /// Check the usage of a namespace against the quota limits
+    pub fn check(&self, namespace: &str, usage: &Usage) -> Result<()> {
+        if let Some(max_echoes) = self.spec.max_echoes.filter(|max| usage.echoes > *max) {
+            return Err(Error::QuotaExceeded(format!(
+                "{namespace} would have {} echoes, EchoQuota {} allows {max_echoes}",
+                usage.echoes,
+                self.name_any()
+            )));
+        }
+        if let Some(max_replicas) = self.spec.max_replicas.filter(|max| usage.replicas > *max) {
+            return Err(Error::QuotaExceeded(format!(
+                "{namespace} would have {} replicas, EchoQuota {} allows {max_replicas}",
+                usage.replicas,
+                self.name_any()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Usage of a namespace recorded in the quota status
+    pub fn status_usage(&self, namespace: &str) -> Option<Usage> {
+        self.status
+            .as_ref()
+            .and_then(|s| s.namespaces.as_ref())
+            .and_then(|n| n.iter().find(|n| n.namespace == namespace))
+            .map(|n| Usage {
+                echoes: n.echoes,
+                replicas: n.replicas,
+            })
+    }
+
+    /// Generate the EchoQuotaStatus with the usage of the namespaces where the quota applies
+    fn generate_status(&self, usage: &BTreeMap<String, Usage>) -> EchoQuotaStatus {
+        let mut namespaces: BTreeMap<String, Usage> = usage
+            .iter()
+            .filter(|(namespace, _)| self.applies_to(namespace))
+            .map(|(namespace, usage)| (namespace.clone(), usage.clone()))
+            .collect();
+        // namespaces listed in the spec are always reported, even without echoes
+        for namespace in self.spec.namespaces.iter().flatten() {
+            namespaces.entry(namespace.clone()).or_default();
+        }
+
+        EchoQuotaStatus {
+            namespaces: Some(
+                namespaces
+                    .into_iter()
+                    .map(|(namespace, usage)| EchoQuotaStatusNamespaces {
+                        namespace,
+                        echoes: usage.echoes,
+                        replicas: usage.replicas,
+                    })
+                    .collect(),
+            ),
+            observed_generation: self.metadata.generation,
+        }
+    }
+
+    async fn update_status(&self, new_status: EchoQuotaStatus, ctx: Arc<Context>) -> Result<()> {
+        let new_status_patch = Patch::Apply(json!({
+            "apiVersion": "example.com/v1",
+            "kind": "EchoQuota",
+            "status": new_status
+        }));
+        debug!(msg = "updating EchoQuota status");
+        trace!(msg = format!("new status {:?}", new_status_patch));
+        let patch = PatchParams::apply(FIELD_MANAGER).force();
+        let quota_api = Api::<EchoQuota>::all(ctx.client.clone());
+        let _o = quota_api
+            .patch_status(&self.name_any(), &patch, &new_status_patch)
+            .await
+            .map_err(Error::KubeError)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{usage_by_namespace, Usage};
+
+    use crate::crd::echo::Echo;
+    use crate::crd::echoquota::{EchoQuota, EchoQuotaSpec};
+    use crate::error::Error;
+
+    use std::collections::BTreeMap;
+
+    use kube::Resource;
+
+    fn quota(namespaces: Option<Vec<String>>) -> EchoQuota {
+        EchoQuota::new(
+            "test",
+            EchoQuotaSpec {
+                namespaces,
+                max_echoes: Some(2),
+                max_replicas: Some(5),
+            },
+        )
+    }
+
+    #[test]
+    fn test_usage_by_namespace() {
+        let mut other = Echo::test(None).change_replicas(4);
+        other.meta_mut().namespace = Some("other".into());
+        let echoes = [Echo::test(None), Echo::test(None).change_replicas(2), other];
+
+        let usage = usage_by_namespace(echoes.iter());
+
+        assert_eq!(
+            usage,
+            BTreeMap::from([
+                (
+                    "default".to_string(),
+                    Usage {
+                        echoes: 2,
+                        replicas: 3
+                    }
+                ),
+                (
+                    "other".to_string(),
+                    Usage {
+                        echoes: 1,
+                        replicas: 4
+                    }
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_applies_to() {
+        assert!(quota(None).applies_to("default"));
+        assert!(quota(Some(vec![])).applies_to("default"));
+        assert!(quota(Some(vec!["default".to_string()])).applies_to("default"));
+        assert!(!quota(Some(vec!["other".to_string()])).applies_to("default"));
+    }
+
+    #[test]
+    fn test_check() {
+        let quota = quota(None);
+
+        assert!(quota
+            .check(
+                "default",
+                &Usage {
+                    echoes: 2,
+                    replicas: 5
+                }
+            )
+            .is_ok());
+        assert!(matches!(
+            quota.check(
+                "default",
+                &Usage {
+                    echoes: 3,
+                    replicas: 3
+                }
+            ),
+            Err(Error::QuotaExceeded(_))
+        ));
+        assert!(matches!(
+            quota.check(
+                "default",
+                &Usage {
+                    echoes: 1,
+                    replicas: 6
+                }
+            ),
+            Err(Error::QuotaExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test_generate_status() {
+        let quota = quota(Some(vec!["default".to_string(), "empty".to_string()]));
+        let usage = BTreeMap::from([
+            (
+                "default".to_string(),
+                Usage {
+                    echoes: 1,
+                    replicas: 3,
+                },
+            ),
+            (
+                "other".to_string(),
+                Usage {
+                    echoes: 1,
+                    replicas: 1,
+                },
+            ),
+        ]);
+
+        let status = quota.generate_status(&usage);
+
+        let namespaces = status.namespaces.unwrap();
+        assert_eq!(namespaces.len(), 2);
+        assert_eq!(namespaces[0].namespace, "default");
+        assert_eq!(namespaces[0].replicas, 3);
+        assert_eq!(namespaces[1].namespace, "empty");
+        assert_eq!(namespaces[1].echoes, 0);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::controller::{Context, ControllerId, State};
+use crate::crd::echo::Echo;
+use crate::crd::echoreplication::EchoReplication;
+use crate::echoreplication::reconcile::{
+    reconcile_echo_replication, REPLICATION_NAMESPACE_LABEL, REPLICATION_NAME_LABEL,
+};
+use crate::error::Error;
+use crate::permissions::Permission;
+use crate::stores::Stores;
+
+use std::sync::Arc;
+
+use futures::StreamExt;
+use kube::api::{Api, ListParams, ResourceExt};
+use kube::client::Client;
+use kube::runtime::controller::{self, Action, Controller};
+use kube::runtime::reflector::{self, ObjectRef, ReflectHandle, Store};
+use kube::runtime::{watcher, WatchStreamExt};
+use tokio::time::Duration;
+use tracing::{debug, error, info};
+
+pub const CONTROLLER_ID: ControllerId = "echoreplication";
+
+pub const PERMISSIONS: &[Permission] = &[
+    Permission::new(
+        "example.com",
+        &["echoreplications"],
+        &["get", "list", "watch", "patch", "update"],
+    ),
+    Permission::new("example.com", &["echoreplications/status"], &["patch"]),
+    Permission::new("example.com", &["echoreplications/finalizers"], &["update"]),
+    Permission::new(
+        "example.com",
+        &["echoes"],
+        &["create", "delete", "get", "list", "patch", "watch"],
+    ),
+    Permission::new("", &["namespaces"], &["list", "watch"]),
+];
+
+const SUBSCRIBE_BUFFER_SIZE: usize = 256;
+
+fn error_policy<K: ResourceExt>(obj: Arc<K>, error: &Error, ctx: Arc<Context>) -> Action {
+    // safe unwrap: echo replication is a namespace scoped resource
+    error!(msg = "failed reconciliation", namespace = %obj.namespace().unwrap(), name = %obj.name_any(), %error);
+    ctx.metrics.reconcile_failure_set(error);
+    ctx.error_reporter
+        .capture_failure(CONTROLLER_ID, &*obj, error);
+    Action::requeue(ctx.error_requeue(&*obj))
+}
+
+/// Replications of the echo, as source, or which created it, as copy
+fn replications_for_echo(
+    replications: &Store<EchoReplication>,
+    echo: &Echo,
+) -> Vec<ObjectRef<EchoReplication>> {
+    let labels = echo.labels();
+    let owner = labels
+        .get(REPLICATION_NAMESPACE_LABEL)
+        .zip(labels.get(REPLICATION_NAME_LABEL))
+        .map(|(namespace, name)| ObjectRef::new(name).within(namespace));
+    replications
+        .state()
+        .iter()
+        .filter(|r| r.namespace() == echo.namespace() && r.spec.source == echo.name_any())
+        .map(|r| ObjectRef::from_obj(r.as_ref()))
+        .chain(owner)
+        .collect()
+}
+
+/// Initialize echo replications controller and shared state (given the crd is installed)
+pub async fn run(state: State, client: Client) {
+    let replication = Api::<EchoReplication>::all(client.clone());
+    if let Err(e) = replication.list(&ListParams::default().limit(1)).await {
+        error!("CRD is not queryable; {e:?}. Is the CRD installed?");
+        std::process::exit(1);
+    }
+
+    let (echo_store, writer) = reflector::store_shared(SUBSCRIBE_BUFFER_SIZE);
+    let subscriber: ReflectHandle<Echo> = writer
+        .subscribe()
+        // safe unwrap: writer is created from a shared store. It should be improved in kube-rs API
+        .expect("subscribers can only be created from shared stores");
+
+    let echo = Api::<Echo>::all(client.clone());
+
+    let stores = Stores::default().with(echo_store);
+
+    let ctx = state.to_context(client, CONTROLLER_ID, stores);
+    let echo_watch = watcher(echo, watcher::Config::default())
+        .default_backoff()
+        .reflect_shared(writer)
+        .for_each(|res| {
+            let ctx = ctx.clone();
+            async move {
+                match res {
+                    Ok(_) => debug!("watched event"),
+                    Err(e) => {
+                        error!(msg = "unexpected error when watching resource", %e);
+                        ctx.metrics.watch_operations_failed_inc();
+                    }
+                }
+            }
+        });
+
+    info!(msg = "starting echo replication controller");
+    let controller = Controller::new(replication, watcher::Config::default().any_semantic())
+        // debounce to filter out reconcile calls that happen quick succession (only taking the latest)
+        .with_config(controller::Config::default().debounce(Duration::from_millis(500)));
+    let replications = controller.store();
+    let replication_controller = controller
+        .watches_shared_stream(subscriber, move |echo| {
+            replications_for_echo(&replications, &echo)
+        })
+        .reconcile_all_on(ctx.reconcile_config.resyncs())
+        .shutdown_on_signal()
+        .run(
+            |replication, ctx| async move {
+                // writer is only dropped on shutdown, when nothing else can be waited
+                let _ignore_errors = ctx.wait_for_stores().await;
+                reconcile_echo_replication(replication, ctx).await
+            },
+            error_policy,
+            ctx.clone(),
+        )
+        .filter_map(|x| async move { std::result::Result::ok(x) })
+        .for_each(|_| futures::future::ready(()));
+
+    let ready = async {
+        if ctx.wait_for_stores().await.is_ok() {
+            ctx.metrics.ready_set(1);
+        }
+    };
+    tokio::select! {
+        _ = futures::future::join(ready, replication_controller) => {},
+        _ = echo_watch => {}
+    }
+}
@@ -0,0 +1,333 @@
+use crate::controller::Context;
+use crate::crd::echo::Echo;
+use crate::crd::echoreplication::{EchoReplication, EchoReplicationStatus};
+use crate::error::{Error, Result};
+use crate::telemetry;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use k8s_openapi::api::core::v1::Namespace;
+use kube::api::{Api, DeleteParams, ListParams, ObjectMeta, Patch, PatchParams, Resource};
+use kube::runtime::controller::Action;
+use kube::runtime::reflector::ObjectRef;
+use kube::ResourceExt;
+use serde_json::json;
+use tracing::{debug, field, info, instrument, trace, warn, Span};
+
+const FIELD_MANAGER: &str = "echoreplications.example.com";
+/// Finalizer keeping the replication until its copies, which can not be owned across namespaces,
+/// are deleted
+pub(crate) const REPLICATION_FINALIZER: &str = "echoreplications.example.com/copies";
+/// Namespace of the replication which created an Echo copy
+pub const REPLICATION_NAMESPACE_LABEL: &str = "echoreplications.example.com/namespace";
+/// Name of the replication which created an Echo copy
+pub const REPLICATION_NAME_LABEL: &str = "echoreplications.example.com/name";
+
+#[instrument(skip(ctx, replication))]
+pub async fn reconcile_echo_replication(
+    replication: Arc<EchoReplication>,
+    ctx: Arc<Context>,
+) -> Result<Action> {
+    let trace_id = telemetry::get_trace_id();
+    Span::current().record("trace_id", field::display(&trace_id));
+    let _timer = ctx.metrics.reconcile_count_and_measure(&trace_id);
+    info!(msg = "reconciling EchoReplication");
+
+    if let Some(requeue) = ctx.maintenance_requeue(&*replication) {
+        return Ok(Action::requeue(requeue));
+    }
+
+    if replication.meta().deletion_timestamp.is_some() {
+        replication.delete_copies(&BTreeSet::new(), &ctx).await?;
+        replication
+            .patch_finalizers(
+                &ctx,
+                replication
+                    .finalizers()
+                    .iter()
+                    .filter(|f| *f != REPLICATION_FINALIZER)
+                    .cloned()
+                    .collect(),
+            )
+            .await?;
+        return Ok(Action::await_change());
+    }
+    if !replication
+        .finalizers()
+        .iter()
+        .any(|f| f == REPLICATION_FINALIZER)
+    {
+        let finalizers = replication
+            .finalizers()
+            .iter()
+            .cloned()
+            .chain([REPLICATION_FINALIZER.to_owned()])
+            .collect();
+        replication.patch_finalizers(&ctx, finalizers).await?;
+    }
+
+    let source_ref = ObjectRef::<Echo>::new_with(&replication.spec.source, ())
+        .within(&replication.get_namespace());
+    let source = ctx
+        .stores
+        .get::<Echo>()
+        // safe unwrap: echo store should exists
+        .unwrap()
+        .get(&source_ref);
+    // copies are kept while the source is missing, so a recreation does not disrupt them
+    let mut conflicts = BTreeSet::new();
+    let namespaces = match source {
+        Some(source) => {
+            let mut namespaces = replication.target_namespaces(&ctx).await?;
+            for namespace in &namespaces {
+                if !replication.apply_copy(&source, namespace, &ctx).await? {
+                    conflicts.insert(namespace.clone());
+                }
+            }
+            replication.delete_copies(&namespaces, &ctx).await?;
+            namespaces.retain(|n| !conflicts.contains(n));
+            namespaces
+        }
+        None => {
+            debug!(msg = "source Echo not found", source = %replication.spec.source);
+            BTreeSet::new()
+        }
+    };
+
+    replication
+        .update_status(namespaces, conflicts, ctx.clone())
+        .await?;
+    ctx.reconciled(&*replication);
+    Ok(Action::requeue(ctx.requeue_interval(&*replication)))
+}
+
+impl EchoReplication {
+    #[inline]
+    fn get_namespace(&self) -> String {
+        // safe unwrap: EchoReplication is namespaced scoped
+        self.namespace().unwrap()
+    }
+
+    /// Label selector of the Echo copies created by the replication
+    fn copies_selector(&self) -> String {
+        format!(
+            "{REPLICATION_NAMESPACE_LABEL}={},{REPLICATION_NAME_LABEL}={}",
+            self.get_namespace(),
+            self.name_any()
+        )
+    }
+
+    /// Listed namespaces and the ones matching the selector, except the source one
+    async fn target_namespaces(&self, ctx: &Context) -> Result<BTreeSet<String>> {
+        let mut namespaces: BTreeSet<String> =
+            self.spec.namespaces.iter().flatten().cloned().collect();
+        if let Some(selector) = self.spec.namespace_selector.as_ref() {
+            let labels: Vec<String> = selector.iter().map(|(k, v)| format!("{k}={v}")).collect();
+            let selected = Api::<Namespace>::all(ctx.client.clone())
+                .list(&ListParams::default().labels(&labels.join(",")))
+                .await
+                .map_err(Error::KubeError)?;
+            namespaces.extend(selected.iter().map(|n| n.name_any()));
+        }
+        namespaces.remove(&self.get_namespace());
+        Ok(namespaces)
+    }
+
+    /// Copy of the source Echo in a namespace
+    fn copy(&self, source: &Echo, namespace: &str) -> Echo {
+        let labels: BTreeMap<String, String> = source
+            .labels()
+            .iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .chain([
+                (REPLICATION_NAMESPACE_LABEL.to_owned(), self.get_namespace()),
+                (REPLICATION_NAME_LABEL.to_owned(), self.name_any()),
+            ])
+            .collect();
+        Echo {
+            metadata: ObjectMeta {
+                name: Some(source.name_any()),
+                namespace: Some(namespace.to_owned()),
+                labels: Some(labels),
+                annotations: Some(source.annotations().clone()).filter(|a| !a.is_empty()),
+                ..ObjectMeta::default()
+            },
+            spec: source.spec.clone(),
+            status: None,
+        }
+    }
+
+    /// Whether the Echo is a copy created by the replication
+    fn is_copy(&self, echo: &Echo) -> bool {
+        let labels = echo.labels();
+        labels.get(REPLICATION_NAMESPACE_LABEL) == Some(&self.get_namespace())
+            && labels.get(REPLICATION_NAME_LABEL) == Some(&self.name_any())
+    }
+
+    /// Apply the copy of the source Echo in a namespace, unless an Echo which is not a copy of the
+    /// replication already has its name there, as the copy would take it over and delete it with
+    /// the replication. Returns whether the copy was applied.
+    async fn apply_copy(&self, source: &Echo, namespace: &str, ctx: &Context) -> Result<bool> {
+        let echo_api = Api::<Echo>::namespaced(ctx.client.clone(), namespace);
+        let existing = echo_api
+            .get_opt(&source.name_any())
+            .await
+            .map_err(Error::KubeError)?;
+        if existing.is_some_and(|e| !self.is_copy(&e)) {
+            warn!(
+                msg = "skipping Echo copy conflicting with an existing Echo",
+                namespace,
+                name = source.name_any()
+            );
+            return Ok(false);
+        }
+        debug!(msg = "applying Echo copy", namespace);
+        echo_api
+            .patch(
+                &source.name_any(),
+                &PatchParams::apply(FIELD_MANAGER).force(),
+                &Patch::Apply(&self.copy(source, namespace)),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        Ok(true)
+    }
+
+    /// Delete the copies outside the given namespaces
+    async fn delete_copies(&self, keep: &BTreeSet<String>, ctx: &Context) -> Result<()> {
+        let copies = Api::<Echo>::all(ctx.client.clone())
+            .list(&ListParams::default().labels(&self.copies_selector()))
+            .await
+            .map_err(Error::KubeError)?;
+        for copy in copies {
+            // safe unwrap: Echo is namespaced scoped
+            let namespace = copy.namespace().unwrap();
+            if keep.contains(&namespace) {
+                continue;
+            }
+            info!(msg = "deleting Echo copy", %namespace, name = copy.name_any());
+            match Api::<Echo>::namespaced(ctx.client.clone(), &namespace)
+                .delete(&copy.name_any(), &DeleteParams::default())
+                .await
+            {
+                Ok(_) => {}
+                Err(kube::Error::Api(ae)) if ae.code == 404 => {}
+                Err(e) => return Err(Error::KubeError(e)),
+            }
+        }
+        Ok(())
+    }
+
+    async fn patch_finalizers(&self, ctx: &Context, finalizers: Vec<String>) -> Result<()> {
+        debug!(msg = "patching EchoReplication finalizers", ?finalizers);
+        Api::<EchoReplication>::namespaced(ctx.client.clone(), &self.get_namespace())
+            .patch(
+                &self.name_any(),
+                &PatchParams::default(),
+                &Patch::Merge(json!({"metadata": {"finalizers": finalizers}})),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        Ok(())
+    }
+
+    async fn update_status(
+        &self,
+        namespaces: BTreeSet<String>,
+        conflicts: BTreeSet<String>,
+        ctx: Arc<Context>,
+    ) -> Result<()> {
+        let new_status = EchoReplicationStatus {
+            namespaces: Some(namespaces.into_iter().collect()),
+            conflicts: Some(conflicts.into_iter().collect::<Vec<_>>()).filter(|c| !c.is_empty()),
+            observed_generation: self.metadata.generation,
+        };
+        let new_status_patch = Patch::Apply(json!({
+            "apiVersion": "example.com/v1",
+            "kind": "EchoReplication",
+            "status": new_status
+        }));
+        debug!(msg = "updating EchoReplication status");
+        trace!(msg = format!("new status {:?}", new_status_patch));
+        let patch = PatchParams::apply(FIELD_MANAGER).force();
+        let replication_api =
+            Api::<EchoReplication>::namespaced(ctx.client.clone(), &self.get_namespace());
+        let _o = replication_api
+            .patch_status(&self.name_any(), &patch, &new_status_patch)
+            .await
+            .map_err(Error::KubeError)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{REPLICATION_NAMESPACE_LABEL, REPLICATION_NAME_LABEL};
+
+    use crate::crd::echo::Echo;
+    use crate::crd::echoreplication::{EchoReplication, EchoReplicationSpec};
+
+    use kube::{Resource, ResourceExt};
+
+    fn replication() -> EchoReplication {
+        let mut replication = EchoReplication::new(
+            "debug",
+            EchoReplicationSpec {
+                source: "test".to_string(),
+                namespaces: Some(vec!["team-a".to_string()]),
+                namespace_selector: None,
+            },
+        );
+        replication.meta_mut().namespace = Some("default".into());
+        replication
+    }
+
+    #[test]
+    fn test_copy() {
+        let mut source = Echo::test(None).change_replicas(3);
+        source
+            .labels_mut()
+            .insert("team".to_string(), "platform".to_string());
+        source.meta_mut().uid = Some("uid".to_string());
+
+        let copy = replication().copy(&source, "team-a");
+
+        assert_eq!(copy.name_any(), "test");
+        assert_eq!(copy.namespace().as_deref(), Some("team-a"));
+        assert_eq!(copy.spec.replicas, 3);
+        assert_eq!(copy.metadata.uid, None);
+        assert!(copy.status.is_none());
+        let labels = copy.labels();
+        assert_eq!(labels.get("team"), Some(&"platform".to_string()));
+        assert_eq!(
+            labels.get(REPLICATION_NAMESPACE_LABEL),
+            Some(&"default".to_string())
+        );
+        assert_eq!(
+            labels.get(REPLICATION_NAME_LABEL),
+            Some(&"debug".to_string())
+        );
+    }
+
+    #[test]
+    fn test_is_copy() {
+        let replication = replication();
+        let copy = replication.copy(&Echo::test(None), "team-a");
+        assert!(replication.is_copy(&copy));
+        // an Echo of the user with the same name
+        assert!(!replication.is_copy(&Echo::test(None)));
+
+        let mut other = replication.clone();
+        other.meta_mut().name = Some("other".to_string());
+        assert!(!other.is_copy(&copy));
+    }
+
+    #[test]
+    fn test_copies_selector() {
+        assert_eq!(
+            replication().copies_selector(),
+            "echoreplications.example.com/namespace=default,echoreplications.example.com/name=debug"
+        );
+    }
+}
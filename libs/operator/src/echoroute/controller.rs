@@ -0,0 +1,144 @@
+use crate::controller::{Context, ControllerId, State};
+use crate::crd::echo::Echo;
+use crate::crd::echoroute::EchoRoute;
+use crate::echoroute::reconcile::reconcile_echo_route;
+use crate::error::Error;
+use crate::permissions::Permission;
+use crate::stores::Stores;
+
+use std::sync::Arc;
+
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::Service;
+use k8s_openapi::api::networking::v1::Ingress;
+use kube::api::{Api, ListParams, ResourceExt};
+use kube::client::Client;
+use kube::runtime::controller::{self, Action, Controller};
+use kube::runtime::reflector::{self, ObjectRef, ReflectHandle, Store};
+use kube::runtime::{watcher, WatchStreamExt};
+use tokio::time::Duration;
+use tracing::{debug, error, info};
+
+pub const CONTROLLER_ID: ControllerId = "echoroute";
+
+pub const PERMISSIONS: &[Permission] = &[
+    Permission::new(
+        "example.com",
+        &["echoroutes"],
+        &["get", "list", "watch", "patch", "update"],
+    ),
+    Permission::new("example.com", &["echoroutes/status"], &["patch"]),
+    Permission::new("example.com", &["echoroutes/finalizers"], &["update"]),
+    Permission::new("example.com", &["echoes"], &["list", "watch"]),
+    Permission::new(
+        "",
+        &["services"],
+        &["create", "delete", "list", "patch", "update", "watch"],
+    ),
+    Permission::new(
+        "networking.k8s.io",
+        &["ingresses"],
+        &[
+            "create", "delete", "get", "list", "patch", "update", "watch",
+        ],
+    ),
+    Permission::new(
+        "gateway.networking.k8s.io",
+        &["httproutes"],
+        &["create", "delete", "get", "patch", "update"],
+    ),
+];
+
+const SUBSCRIBE_BUFFER_SIZE: usize = 256;
+const MANAGED_BY_SELECTOR: &str = "app.kubernetes.io/managed-by=echo-operator";
+
+fn error_policy<K: ResourceExt>(obj: Arc<K>, error: &Error, ctx: Arc<Context>) -> Action {
+    // safe unwrap: echoroute is a namespace scoped resource
+    error!(msg = "failed reconciliation", namespace = %obj.namespace().unwrap(), name = %obj.name_any(), %error);
+    ctx.metrics.reconcile_failure_set(error);
+    ctx.error_reporter
+        .capture_failure(CONTROLLER_ID, &*obj, error);
+    Action::requeue(ctx.error_requeue(&*obj))
+}
+
+/// Routes in the echo namespace with the echo as backend
+fn routes_for_echo(routes: &Store<EchoRoute>, echo: &Echo) -> Vec<ObjectRef<EchoRoute>> {
+    let name = echo.name_any();
+    routes
+        .state()
+        .iter()
+        .filter(|r| r.namespace() == echo.namespace())
+        .filter(|r| r.spec.rules.iter().any(|rule| rule.echo == name))
+        .map(|r| ObjectRef::from_obj(r.as_ref()))
+        .collect()
+}
+
+/// Initialize echo routes controller and shared state (given the crd is installed)
+pub async fn run(state: State, client: Client) {
+    let route = Api::<EchoRoute>::all(client.clone());
+    if let Err(e) = route.list(&ListParams::default().limit(1)).await {
+        error!("CRD is not queryable; {e:?}. Is the CRD installed?");
+        std::process::exit(1);
+    }
+
+    let (echo_store, writer) = reflector::store_shared(SUBSCRIBE_BUFFER_SIZE);
+    let subscriber: ReflectHandle<Echo> = writer
+        .subscribe()
+        // safe unwrap: writer is created from a shared store. It should be improved in kube-rs API
+        .expect("subscribers can only be created from shared stores");
+
+    let echo = Api::<Echo>::all(client.clone());
+
+    let stores = Stores::default().with(echo_store);
+
+    let ctx = state.to_context(client.clone(), CONTROLLER_ID, stores);
+    let echo_watch = watcher(echo, watcher::Config::default())
+        .default_backoff()
+        .reflect_shared(writer)
+        .for_each(|res| {
+            let ctx = ctx.clone();
+            async move {
+                match res {
+                    Ok(_) => debug!("watched event"),
+                    Err(e) => {
+                        error!(msg = "unexpected error when watching resource", %e);
+                        ctx.metrics.watch_operations_failed_inc();
+                    }
+                }
+            }
+        });
+
+    info!(msg = "starting echo route controller");
+    let owned_config = watcher::Config::default().labels(MANAGED_BY_SELECTOR);
+    let controller = Controller::new(route, watcher::Config::default().any_semantic())
+        // debounce to filter out reconcile calls that happen quick succession (only taking the latest)
+        .with_config(controller::Config::default().debounce(Duration::from_millis(500)));
+    let routes = controller.store();
+    let route_controller = controller
+        .owns(Api::<Service>::all(client.clone()), owned_config.clone())
+        .owns(Api::<Ingress>::all(client), owned_config)
+        .watches_shared_stream(subscriber, move |echo| routes_for_echo(&routes, &echo))
+        .reconcile_all_on(ctx.reconcile_config.resyncs())
+        .shutdown_on_signal()
+        .run(
+            |route, ctx| async move {
+                // writer is only dropped on shutdown, when nothing else can be waited
+                let _ignore_errors = ctx.wait_for_stores().await;
+                reconcile_echo_route(route, ctx).await
+            },
+            error_policy,
+            ctx.clone(),
+        )
+        .filter_map(|x| async move { std::result::Result::ok(x) })
+        .for_each(|_| futures::future::ready(()));
+
+    let ready = async {
+        if ctx.wait_for_stores().await.is_ok() {
+            ctx.metrics.ready_set(1);
+        }
+    };
+    tokio::select! {
+        _ = futures::future::join(ready, route_controller) => {},
+        _ = echo_watch => {}
+    }
+}
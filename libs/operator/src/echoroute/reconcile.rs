@@ -0,0 +1,540 @@
+use crate::controller::Context;
+use crate::crd::echo::Echo;
+use crate::crd::echoroute::{EchoRoute, EchoRouteRulesPathType, EchoRouteStatus};
+use crate::echo::condition::ConditionType;
+use crate::echo::reconcile::ECHO_PORT;
+use crate::error::{Error, Result};
+use crate::owned::{self, ignore_not_found};
+use crate::telemetry;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::{Service, ServicePort, ServiceSpec};
+use k8s_openapi::api::networking::v1::{
+    HTTPIngressPath, HTTPIngressRuleValue, Ingress, IngressBackend, IngressRule,
+    IngressServiceBackend, IngressSpec, ServiceBackendPort,
+};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use kube::api::{
+    Api, ApiResource, DeleteParams, DynamicObject, GroupVersionKind, ListParams, ObjectMeta, Patch,
+    PatchParams, Resource,
+};
+use kube::runtime::controller::Action;
+use kube::runtime::reflector::ObjectRef;
+use kube::ResourceExt;
+use serde_json::{json, Value};
+use tracing::{debug, field, info, instrument, trace, Span};
+
+const SERVICE_PORT: i32 = 80;
+const PORT_NAME: &str = "http";
+const FIELD_MANAGER: &str = "echoroutes.example.com";
+
+/// Gateway API HTTPRoute, which is not part of the core Kubernetes API
+fn http_route_resource() -> ApiResource {
+    ApiResource::from_gvk(&GroupVersionKind::gvk(
+        "gateway.networking.k8s.io",
+        "v1",
+        "HTTPRoute",
+    ))
+}
+
+#[instrument(skip(ctx, route))]
+pub async fn reconcile_echo_route(route: Arc<EchoRoute>, ctx: Arc<Context>) -> Result<Action> {
+    let trace_id = telemetry::get_trace_id();
+    Span::current().record("trace_id", field::display(&trace_id));
+    let _timer = ctx.metrics.reconcile_count_and_measure(&trace_id);
+    info!(msg = "reconciling EchoRoute");
+
+    if let Some(requeue) = ctx.maintenance_requeue(&*route) {
+        return Ok(Action::requeue(requeue));
+    }
+
+    for service in route.services() {
+        route.apply_service(service, ctx.clone()).await?;
+    }
+    route.delete_stale_services(ctx.clone()).await?;
+
+    if route.spec.gateway.is_some() {
+        route.apply_http_route(ctx.clone()).await?;
+        route.delete_ingress(ctx.clone()).await?;
+    } else {
+        route.apply_ingress(ctx.clone()).await?;
+        route.delete_http_route(ctx.clone()).await?;
+    }
+
+    let _ignore_errors = route.update_status(ctx.clone()).await.map_err(|e| {
+        debug!(msg = "failed to reconcile status", %e);
+        ctx.metrics.status_update_errors_inc();
+    });
+    ctx.reconciled(&*route);
+    Ok(Action::requeue(ctx.requeue_interval(&*route)))
+}
+
+impl EchoRoute {
+    #[inline]
+    fn get_namespace(&self) -> String {
+        // safe unwrap: EchoRoute is namespaced scoped
+        self.namespace().unwrap()
+    }
+
+    fn route_labels(&self) -> BTreeMap<String, String> {
+        self.labels()
+            .iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .chain([
+                ("app".to_owned(), self.name_any()),
+                ("app.kubernetes.io/name".to_owned(), "echoroute".to_owned()),
+                (
+                    "app.kubernetes.io/managed-by".to_owned(),
+                    "echo-operator".to_owned(),
+                ),
+            ])
+            .collect()
+    }
+
+    fn object_meta(&self, name: String) -> ObjectMeta {
+        ObjectMeta {
+            name: Some(name),
+            namespace: Some(self.get_namespace()),
+            labels: Some(self.route_labels()),
+            owner_references: self.controller_owner_ref(&()).map(|oref| vec![oref]),
+            ..ObjectMeta::default()
+        }
+    }
+
+    /// Echoes receiving traffic from the route, without duplicates
+    fn echoes(&self) -> Vec<&str> {
+        let mut echoes: Vec<&str> = self.spec.rules.iter().map(|r| r.echo.as_str()).collect();
+        echoes.sort_unstable();
+        echoes.dedup();
+        echoes
+    }
+
+    fn service_name(&self, echo: &str) -> String {
+        format!("{}-{echo}", self.name_any())
+    }
+
+    /// Services selecting the pods of every Echo in the route rules
+    fn services(&self) -> Vec<Service> {
+        self.echoes()
+            .into_iter()
+            .map(|echo| Service {
+                metadata: self.object_meta(self.service_name(echo)),
+                spec: Some(ServiceSpec {
+                    selector: Some(BTreeMap::from([
+                        ("app".to_owned(), echo.to_owned()),
+                        ("app.kubernetes.io/name".to_owned(), "echo".to_owned()),
+                    ])),
+                    ports: Some(vec![ServicePort {
+                        name: Some(PORT_NAME.to_owned()),
+                        port: SERVICE_PORT,
+                        target_port: Some(IntOrString::Int(ECHO_PORT)),
+                        protocol: Some("TCP".to_owned()),
+                        ..ServicePort::default()
+                    }]),
+                    ..ServiceSpec::default()
+                }),
+                ..Service::default()
+            })
+            .collect()
+    }
+
+    fn ingress(&self) -> Ingress {
+        let paths: Vec<HTTPIngressPath> = self
+            .spec
+            .rules
+            .iter()
+            .map(|rule| HTTPIngressPath {
+                path: Some(rule.path.clone()),
+                path_type: match rule.path_type {
+                    Some(EchoRouteRulesPathType::Exact) => "Exact",
+                    Some(EchoRouteRulesPathType::Prefix) | None => "Prefix",
+                }
+                .to_owned(),
+                backend: IngressBackend {
+                    service: Some(IngressServiceBackend {
+                        name: self.service_name(&rule.echo),
+                        port: Some(ServiceBackendPort {
+                            name: Some(PORT_NAME.to_owned()),
+                            ..ServiceBackendPort::default()
+                        }),
+                    }),
+                    ..IngressBackend::default()
+                },
+            })
+            .collect();
+
+        let hosts: Vec<Option<String>> = match self.spec.hosts.as_deref() {
+            Some(hosts) if !hosts.is_empty() => hosts.iter().cloned().map(Some).collect(),
+            _ => vec![None],
+        };
+
+        Ingress {
+            metadata: self.object_meta(self.name_any()),
+            spec: Some(IngressSpec {
+                ingress_class_name: self.spec.class_name.clone(),
+                rules: Some(
+                    hosts
+                        .into_iter()
+                        .map(|host| IngressRule {
+                            host,
+                            http: Some(HTTPIngressRuleValue {
+                                paths: paths.clone(),
+                            }),
+                        })
+                        .collect(),
+                ),
+                ..IngressSpec::default()
+            }),
+            ..Ingress::default()
+        }
+    }
+
+    fn http_route(&self) -> Value {
+        let parent_refs: Vec<Value> = self
+            .spec
+            .gateway
+            .iter()
+            .map(|gateway| {
+                let mut parent = json!({ "name": gateway.name });
+                if let Some(namespace) = &gateway.namespace {
+                    parent["namespace"] = json!(namespace);
+                }
+                if let Some(section_name) = &gateway.section_name {
+                    parent["sectionName"] = json!(section_name);
+                }
+                parent
+            })
+            .collect();
+
+        let rules: Vec<Value> = self
+            .spec
+            .rules
+            .iter()
+            .map(|rule| {
+                let path_type = match rule.path_type {
+                    Some(EchoRouteRulesPathType::Exact) => "Exact",
+                    Some(EchoRouteRulesPathType::Prefix) | None => "PathPrefix",
+                };
+                json!({
+                    "matches": [{"path": {"type": path_type, "value": rule.path}}],
+                    "backendRefs": [{"name": self.service_name(&rule.echo), "port": SERVICE_PORT}],
+                })
+            })
+            .collect();
+
+        let mut spec = json!({ "parentRefs": parent_refs, "rules": rules });
+        if let Some(hosts) = self.spec.hosts.as_ref().filter(|h| !h.is_empty()) {
+            spec["hostnames"] = json!(hosts);
+        }
+
+        json!({
+            "apiVersion": "gateway.networking.k8s.io/v1",
+            "kind": "HTTPRoute",
+            "metadata": self.object_meta(self.name_any()),
+            "spec": spec,
+        })
+    }
+
+    async fn apply_service(&self, service: Service, ctx: Arc<Context>) -> Result<Service> {
+        let service_api = Api::<Service>::namespaced(ctx.client.clone(), &self.get_namespace());
+        service_api
+            .patch(
+                &service.name_any(),
+                &PatchParams::apply(FIELD_MANAGER).force(),
+                &Patch::Apply(&service),
+            )
+            .await
+            .map_err(Error::KubeError)
+    }
+
+    /// Delete services of echoes which are not in the route rules anymore, created for the route
+    async fn delete_stale_services(&self, ctx: Arc<Context>) -> Result<()> {
+        let service_api = Api::<Service>::namespaced(ctx.client.clone(), &self.get_namespace());
+        let selector = format!("app={},app.kubernetes.io/name=echoroute", self.name_any());
+        let services = service_api
+            .list(&ListParams::default().labels(&selector))
+            .await
+            .map_err(Error::KubeError)?;
+        let desired: Vec<String> = self
+            .echoes()
+            .into_iter()
+            .map(|echo| self.service_name(echo))
+            .collect();
+        for service in services
+            .into_iter()
+            .filter(|s| !desired.contains(&s.name_any()))
+            .filter(|s| owned::is_controlled_by(s, self))
+        {
+            debug!(msg = "deleting stale service", name = service.name_any());
+            ignore_not_found(
+                service_api
+                    .delete(&service.name_any(), &DeleteParams::default())
+                    .await,
+            )?;
+        }
+        Ok(())
+    }
+
+    async fn apply_ingress(&self, ctx: Arc<Context>) -> Result<Ingress> {
+        let ingress_api = Api::<Ingress>::namespaced(ctx.client.clone(), &self.get_namespace());
+        ingress_api
+            .patch(
+                &self.name_any(),
+                &PatchParams::apply(FIELD_MANAGER).force(),
+                &Patch::Apply(&self.ingress()),
+            )
+            .await
+            .map_err(Error::KubeError)
+    }
+
+    /// Delete the Ingress of the route, left alone when it was not created for the route
+    async fn delete_ingress(&self, ctx: Arc<Context>) -> Result<()> {
+        let ingress_api = Api::<Ingress>::namespaced(ctx.client.clone(), &self.get_namespace());
+        owned::delete_controlled(&ingress_api, &self.name_any(), self).await?;
+        Ok(())
+    }
+
+    async fn apply_http_route(&self, ctx: Arc<Context>) -> Result<DynamicObject> {
+        let http_route_api = Api::<DynamicObject>::namespaced_with(
+            ctx.client.clone(),
+            &self.get_namespace(),
+            &http_route_resource(),
+        );
+        http_route_api
+            .patch(
+                &self.name_any(),
+                &PatchParams::apply(FIELD_MANAGER).force(),
+                &Patch::Apply(&self.http_route()),
+            )
+            .await
+            .map_err(Error::KubeError)
+    }
+
+    /// Delete the HTTPRoute of the route, left alone when it was not created for the route
+    async fn delete_http_route(&self, ctx: Arc<Context>) -> Result<()> {
+        let http_route_api = Api::<DynamicObject>::namespaced_with(
+            ctx.client.clone(),
+            &self.get_namespace(),
+            &http_route_resource(),
+        );
+        // not found is also returned when Gateway API is not installed in the cluster
+        owned::delete_controlled(&http_route_api, &self.name_any(), self).await?;
+        Ok(())
+    }
+
+    /// Echoes referenced by the route rules which don't exist
+    fn missing_echoes(&self, ctx: &Context) -> Vec<String> {
+        let namespace = self.get_namespace();
+        let echo_store = ctx
+            .stores
+            .get::<Echo>()
+            // safe unwrap: echo store should exists
+            .unwrap();
+        self.echoes()
+            .into_iter()
+            .filter(|echo| {
+                echo_store
+                    .get(&ObjectRef::<Echo>::new(echo).within(&namespace))
+                    .is_none()
+            })
+            .map(String::from)
+            .collect()
+    }
+
+    async fn update_status(&self, ctx: Arc<Context>) -> Result<()> {
+        let new_status = self.generate_status(&self.missing_echoes(&ctx), ctx.clock.now());
+        let new_status_patch = Patch::Apply(json!({
+            "apiVersion": "example.com/v1",
+            "kind": "EchoRoute",
+            "status": new_status
+        }));
+        debug!(msg = "updating EchoRoute status");
+        trace!(msg = format!("new status {:?}", new_status_patch));
+        let patch = PatchParams::apply(FIELD_MANAGER).force();
+        let route_api = Api::<EchoRoute>::namespaced(ctx.client.clone(), &self.get_namespace());
+        let _o = route_api
+            .patch_status(&self.name_any(), &patch, &new_status_patch)
+            .await
+            .map_err(Error::KubeError)?;
+        Ok(())
+    }
+
+    /// Generate the EchoRouteStatus, which is ready when every backend Echo exists
+    fn generate_status(&self, missing_echoes: &[String], now: DateTime<Utc>) -> EchoRouteStatus {
+        let (status, reason, message) = if missing_echoes.is_empty() {
+            ("True", "RoutesApplied", String::new())
+        } else {
+            (
+                "False",
+                "EchoNotFound",
+                format!("echoes not found: {}", missing_echoes.join(", ")),
+            )
+        };
+
+        let generation = self.metadata.generation;
+        let previous = self
+            .status
+            .as_ref()
+            .and_then(|s| s.conditions.as_ref())
+            .and_then(|c| c.iter().find(|c| c.type_ == ConditionType::Ready.as_str()));
+        let last_transition_time = match previous {
+            // keep the transition time while the condition status doesn't change
+            Some(c) if c.status == status => c.last_transition_time.clone(),
+            _ => Time(now),
+        };
+
+        EchoRouteStatus {
+            conditions: Some(vec![Condition {
+                type_: ConditionType::Ready.to_string(),
+                status: status.to_string(),
+                reason: reason.to_string(),
+                message,
+                last_transition_time,
+                observed_generation: generation,
+            }]),
+            observed_generation: generation,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crd::echoroute::{
+        EchoRoute, EchoRouteGateway, EchoRouteRules, EchoRouteRulesPathType, EchoRouteSpec,
+    };
+    use crate::test_utils::test_time;
+
+    use chrono::Duration;
+    use kube::Resource;
+
+    fn route() -> EchoRoute {
+        let mut r = EchoRoute::new(
+            "test",
+            EchoRouteSpec {
+                hosts: Some(vec!["echo.example.com".to_string()]),
+                rules: vec![
+                    EchoRouteRules {
+                        path: "/a".to_string(),
+                        path_type: None,
+                        echo: "a".to_string(),
+                    },
+                    EchoRouteRules {
+                        path: "/b".to_string(),
+                        path_type: Some(EchoRouteRulesPathType::Exact),
+                        echo: "b".to_string(),
+                    },
+                    EchoRouteRules {
+                        path: "/b/".to_string(),
+                        path_type: None,
+                        echo: "b".to_string(),
+                    },
+                ],
+                ..EchoRouteSpec::default()
+            },
+        );
+        r.meta_mut().namespace = Some("default".into());
+        r
+    }
+
+    #[test]
+    fn test_services_are_deduplicated() {
+        let services = route().services();
+
+        let names: Vec<_> = services
+            .iter()
+            .map(|s| s.metadata.name.clone().unwrap())
+            .collect();
+        assert_eq!(names, vec!["test-a", "test-b"]);
+        assert_eq!(
+            services[1]
+                .spec
+                .as_ref()
+                .unwrap()
+                .selector
+                .as_ref()
+                .unwrap()["app"],
+            "b"
+        );
+    }
+
+    #[test]
+    fn test_ingress_rules() {
+        let ingress = route().ingress();
+
+        let rules = ingress.spec.unwrap().rules.unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].host.as_deref(), Some("echo.example.com"));
+        let paths = &rules[0].http.as_ref().unwrap().paths;
+        assert_eq!(paths.len(), 3);
+        assert_eq!(paths[0].path_type, "Prefix");
+        assert_eq!(paths[1].path_type, "Exact");
+        assert_eq!(
+            paths[1].backend.service.as_ref().unwrap().name,
+            "test-b".to_string()
+        );
+    }
+
+    #[test]
+    fn test_ingress_without_hosts() {
+        let mut route = route();
+        route.spec.hosts = None;
+
+        let rules = route.ingress().spec.unwrap().rules.unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].host, None);
+    }
+
+    #[test]
+    fn test_http_route() {
+        let mut route = route();
+        route.spec.gateway = Some(EchoRouteGateway {
+            name: "public".to_string(),
+            namespace: Some("gateways".to_string()),
+            section_name: None,
+        });
+
+        let http_route = route.http_route();
+
+        assert_eq!(http_route["kind"], "HTTPRoute");
+        assert_eq!(
+            http_route["spec"]["parentRefs"][0],
+            serde_json::json!({"name": "public", "namespace": "gateways"})
+        );
+        assert_eq!(http_route["spec"]["hostnames"][0], "echo.example.com");
+        assert_eq!(
+            http_route["spec"]["rules"][0]["matches"][0]["path"]["type"],
+            "PathPrefix"
+        );
+        assert_eq!(
+            http_route["spec"]["rules"][1]["matches"][0]["path"]["type"],
+            "Exact"
+        );
+    }
+
+    #[test]
+    fn test_generate_status_missing_echo() {
+        let status = route().generate_status(&["b".to_string()], test_time());
+
+        let condition = &status.conditions.unwrap()[0];
+        assert_eq!(condition.status, "False");
+        assert_eq!(condition.reason, "EchoNotFound");
+    }
+
+    #[test]
+    fn test_generate_status_keeps_transition_time() {
+        let mut route = route();
+        let previous = route.generate_status(&[], test_time() - Duration::hours(1));
+        route.status = Some(previous.clone());
+
+        let status = route.generate_status(&[], test_time());
+
+        assert_eq!(
+            status.conditions.unwrap()[0].last_transition_time,
+            previous.conditions.unwrap()[0].last_transition_time
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use crate::controller::{Context, ControllerId, State};
+use crate::crd::echo::Echo;
+use crate::echo::predicates::{
+    deployment_status_changes, echo_changes, filter_unchanged, filter_unchanged_objects,
+};
+use crate::echostatus::reconcile::reconcile_echo_status;
+use crate::error::Error;
+use crate::lazy_reflector;
+use crate::permissions::Permission;
+use crate::stores::{self, Stores};
+use crate::watchlist;
+
+use std::sync::Arc;
+
+use futures::StreamExt;
+use k8s_openapi::api::apps::v1::Deployment;
+use kube::api::{Api, ListParams, ResourceExt};
+use kube::client::Client;
+use kube::runtime::controller::{self, Action, Controller};
+use kube::runtime::reflector::{self, ObjectRef, ReflectHandle};
+use kube::runtime::{watcher, WatchStreamExt};
+use tokio::time::Duration;
+use tracing::{debug, error, info};
+
+pub const CONTROLLER_ID: ControllerId = "echostatus";
+
+pub const PERMISSIONS: &[Permission] = &[
+    Permission::new("example.com", &["echoes"], &["list", "watch", "patch"]),
+    Permission::new("example.com", &["echoes/status"], &["get", "patch"]),
+    Permission::new("apps", &["deployments"], &["list", "watch"]),
+];
+
+const SUBSCRIBE_BUFFER_SIZE: usize = 256;
+
+fn error_policy<K: ResourceExt>(obj: Arc<K>, error: &Error, ctx: Arc<Context>) -> Action {
+    // safe unwrap: deployment is a namespace scoped resource
+    error!(msg = "failed reconciliation", namespace = %obj.namespace().unwrap(), name = %obj.name_any(), %error);
+    ctx.metrics.reconcile_failure_set(error);
+    ctx.error_reporter
+        .capture_failure(CONTROLLER_ID, &*obj, error);
+    Action::requeue(ctx.error_requeue(&*obj))
+}
+
+/// Initialize echo status controller, aggregating the status of the Deployments into their
+/// echoes apart from the echo controller (given the crd is installed)
+pub async fn run(state: State, client: Client) {
+    let echo = Api::<Echo>::all(client.clone());
+    if let Err(e) = echo.list(&ListParams::default().limit(1)).await {
+        error!("CRD is not queryable; {e:?}. Is the CRD installed?");
+        std::process::exit(1);
+    }
+
+    let (echo_store, writer) = reflector::store_shared(SUBSCRIBE_BUFFER_SIZE);
+    let subscriber: ReflectHandle<Echo> = writer
+        .subscribe()
+        // safe unwrap: writer is created from a shared store. It should be improved in kube-rs API
+        .expect("subscribers can only be created from shared stores");
+
+    let stores = Stores::default().with(echo_store.clone());
+
+    let ctx = state.to_context(client.clone(), CONTROLLER_ID, stores);
+    let streaming_lists = watchlist::enabled(&state, &client).await;
+    let echo_config = watchlist::config(
+        watcher::Config::default(),
+        streaming_lists,
+        state.watch_page_size(),
+    );
+    let (mut namespace_tracker, namespaces) =
+        lazy_reflector::namespaces(state.lazy_deployment_reflectors());
+    let echo_watch = watcher(echo, echo_config)
+        .default_backoff()
+        .modify(stores::prune)
+        .reflect_shared(writer)
+        .inspect(move |event| {
+            if let Ok(event) = event {
+                namespace_tracker.observe(event);
+            }
+        })
+        .for_each(|res| {
+            let ctx = ctx.clone();
+            async move {
+                match res {
+                    Ok(_) => debug!("watched event"),
+                    Err(e) => {
+                        error!(msg = "unexpected error when watching resource", %e);
+                        ctx.metrics.watch_operations_failed_inc();
+                    }
+                }
+            }
+        });
+
+    info!(msg = "starting echo status controller", streaming_lists);
+    let (reader, writer) = reflector::store();
+    let deployment_events = lazy_reflector::watcher(
+        client,
+        watchlist::config(
+            watcher::Config::default().labels("app.kubernetes.io/managed-by=echo-operator"),
+            streaming_lists,
+            state.watch_page_size(),
+        ),
+        namespaces,
+    )
+    .modify(stores::prune)
+    .reflect(writer)
+    .applied_objects();
+    // rollout progress not reported in the echo status does not trigger a reconciliation
+    let deployments = filter_unchanged_objects(
+        deployment_events,
+        deployment_status_changes,
+        reader.clone(),
+        ctx.metrics.clone(),
+    );
+    let echoes = filter_unchanged(
+        subscriber,
+        echo_changes(false),
+        echo_store,
+        ctx.metrics.clone(),
+    );
+    let status_controller = Controller::for_stream(deployments, reader)
+        // debounce to filter out reconcile calls that happen quick succession (only taking the latest)
+        .with_config(controller::Config::default().debounce(Duration::from_millis(500)))
+        // the Deployment is named as its echo
+        .watches_shared_stream(echoes, |echo| {
+            echo.namespace()
+                .map(|namespace| ObjectRef::<Deployment>::new(&echo.name_any()).within(&namespace))
+        })
+        .reconcile_all_on(ctx.reconcile_config.resyncs())
+        .shutdown_on_signal()
+        .run(
+            |deployment, ctx| async move {
+                // writer is only dropped on shutdown, when nothing else can be waited
+                let _ignore_errors = ctx.wait_for_stores().await;
+                reconcile_echo_status(deployment, ctx).await
+            },
+            error_policy,
+            ctx.clone(),
+        )
+        .filter_map(|x| async move { std::result::Result::ok(x) })
+        .for_each(|_| futures::future::ready(()));
+
+    let ready = async {
+        if ctx.wait_for_stores().await.is_ok() {
+            ctx.metrics.ready_set(1);
+        }
+    };
+    tokio::select! {
+        _ = futures::future::join(ready, status_controller) => {},
+        _ = echo_watch => {}
+    }
+    ctx.status_batcher.flush(&ctx.client, &ctx.metrics).await;
+}
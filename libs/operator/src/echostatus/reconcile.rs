@@ -0,0 +1,134 @@
+use crate::controller::Context;
+use crate::crd::echo::Echo;
+use crate::echo::reconcile::requeue_after;
+use crate::error::Result;
+use crate::telemetry;
+
+use std::sync::Arc;
+
+use k8s_openapi::api::apps::v1::Deployment;
+use kube::runtime::controller::Action;
+use kube::runtime::reflector::ObjectRef;
+use kube::ResourceExt;
+use tracing::{debug, field, info, instrument, Span};
+
+#[instrument(
+    skip(ctx, deployment),
+    fields(trace_id, uid, generation, resource_version)
+)]
+pub async fn reconcile_echo_status(
+    deployment: Arc<Deployment>,
+    ctx: Arc<Context>,
+) -> Result<Action> {
+    let trace_id = telemetry::get_trace_id();
+    Span::current().record("trace_id", field::display(&trace_id));
+    let _timer = ctx.metrics.reconcile_count_and_measure(&trace_id);
+    info!(msg = "reconciling Echo status");
+
+    let Some(echo) = owner_echo(&deployment, &ctx) else {
+        debug!(msg = "Echo owning the Deployment not found");
+        return Ok(Action::await_change());
+    };
+    echo.record_identity(&Span::current());
+    let now = ctx.clock.now();
+    let scheduled = echo.scheduled_replicas(now)?;
+    echo.update_status(&ctx, &deployment, &scheduled)
+        .await
+        .inspect_err(|_| ctx.metrics.status_update_errors_inc())?;
+    echo.reconcile_registration(&ctx, &deployment).await?;
+    echo.record_last_good_spec(&ctx, &deployment).await?;
+    ctx.reconciled(&*deployment);
+    // the active schedule can change without any Deployment change
+    Ok(Action::requeue(requeue_after(
+        &scheduled,
+        now,
+        ctx.requeue_interval(&*deployment),
+    )))
+}
+
+/// Echo owning the Deployment, its controller unless `spec.ownership` sets a non-controller
+/// reference
+fn owner_echo(deployment: &Deployment, ctx: &Context) -> Option<Arc<Echo>> {
+    let owners = deployment.owner_references();
+    let owner = owners
+        .iter()
+        .filter(|r| r.kind == "Echo")
+        .max_by_key(|r| r.controller == Some(true))?;
+    let echo_ref = ObjectRef::<Echo>::new(&owner.name).within(&deployment.namespace()?);
+    ctx.stores
+        .get::<Echo>()
+        // safe unwrap: echo store should exists
+        .unwrap()
+        .get(&echo_ref)
+}
+
+#[cfg(test)]
+mod test {
+    use super::owner_echo;
+
+    use crate::controller::{Context, State};
+    use crate::crd::echo::Echo;
+    use crate::stores::Stores;
+
+    use std::sync::Arc;
+
+    use http::{Request, Response};
+    use k8s_openapi::api::apps::v1::Deployment;
+    use kube::api::ObjectMeta;
+    use kube::client::Body;
+    use kube::runtime::reflector::store::Writer;
+    use kube::runtime::watcher;
+    use kube::{Client, Resource};
+    use prometheus_client::registry::Registry;
+
+    fn context(echoes: &[Echo]) -> Arc<Context> {
+        let (mock_service, _handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+        let mut writer = Writer::<Echo>::default();
+        for echo in echoes {
+            writer.apply_watcher_event(&watcher::Event::Apply(echo.clone()));
+        }
+        let stores = Stores::default().with(writer.as_reader());
+        State::new(Registry::default(), &["echostatus"]).to_context(
+            Client::new(mock_service, "default"),
+            "echostatus",
+            stores,
+        )
+    }
+
+    fn deployment_owned_by(echo: &Echo) -> Deployment {
+        Deployment {
+            metadata: ObjectMeta {
+                name: Some("test".to_string()),
+                namespace: Some("default".to_string()),
+                owner_references: echo.controller_owner_ref(&()).map(|oref| vec![oref]),
+                ..ObjectMeta::default()
+            },
+            ..Deployment::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_owner_echo() {
+        let mut echo = Echo::test(None);
+        echo.meta_mut().uid = Some("uid".to_string());
+        let deployment = deployment_owned_by(&echo);
+
+        assert_eq!(
+            owner_echo(&deployment, &context(&[echo.clone()])).map(|e| e.spec.replicas),
+            Some(1)
+        );
+        assert!(owner_echo(&deployment, &context(&[])).is_none());
+        assert!(owner_echo(&Deployment::default(), &context(&[echo.clone()])).is_none());
+
+        let mut deployment = deployment;
+        deployment
+            .metadata
+            .owner_references
+            .iter_mut()
+            .flatten()
+            .for_each(|r| {
+                r.controller = Some(false);
+            });
+        assert!(owner_echo(&deployment, &context(&[echo])).is_some());
+    }
+}
@@ -1,3 +1,6 @@
+use std::fmt;
+
+use kube::{Resource, ResourceExt};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -24,11 +27,203 @@ pub enum Error {
 
     #[error("InvalidTraceId")]
     InvalidTraceId,
+
+    #[error("QuotaExceeded: {0}")]
+    QuotaExceeded(String),
+
+    #[error("InvalidSchedule: {0}")]
+    InvalidSchedule(String),
+
+    #[error("HookError: {0}")]
+    HookError(String),
+
+    #[error("TemplateError: {0}")]
+    TemplateError(String),
+
+    #[error("UnknownController: {0}")]
+    UnknownController(String),
+
+    #[error("InvalidMaintenanceWindow: {0}")]
+    InvalidMaintenanceWindow(String),
+
+    #[error("InvalidRegistryCredentials: {0}")]
+    InvalidRegistryCredentials(String),
+
+    #[error("InvalidSpec: {0}")]
+    InvalidSpec(String),
+
+    #[error("InvalidRbacAllowlist: {0}")]
+    InvalidRbacAllowlist(String),
+
+    #[error("ForbiddenRbacRules: {0}")]
+    ForbiddenRbacRules(String),
+
+    #[error("InvalidDsn: {0}")]
+    InvalidDsn(String),
+
+    #[error("RegistrationError: {0}")]
+    RegistrationError(String),
+
+    #[error("{context}: {source}")]
+    WithContext {
+        context: ErrorContext,
+        #[source]
+        source: Box<Error>,
+    },
 }
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 impl Error {
+    /// Label of the error in the failure metrics. The context adds the operation and the kind,
+    /// but not the object identity, to keep the cardinality bounded.
     pub fn metric_label(&self) -> String {
-        format!("{self:?}").to_lowercase()
+        match self {
+            Error::WithContext { context, source } => format!(
+                "{} {}: {}",
+                context.operation,
+                context.kind.to_lowercase(),
+                source.metric_label()
+            ),
+            _ => format!("{self:?}").to_lowercase(),
+        }
+    }
+
+    /// Add the resource and the operation attempted to the error, keeping the innermost context
+    pub fn context(self, context: ErrorContext) -> Self {
+        match self {
+            Error::WithContext { .. } => self,
+            _ => Error::WithContext {
+                context,
+                source: Box::new(self),
+            },
+        }
+    }
+
+    /// Error without its context
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::WithContext { source, .. } => source.root(),
+            _ => self,
+        }
+    }
+}
+
+/// Add the resource and the operation attempted to the error of a result
+pub trait ResultExt<T> {
+    fn with_context(self, context: impl FnOnce() -> ErrorContext) -> Result<T>;
+}
+
+impl<T> ResultExt<T> for Result<T> {
+    fn with_context(self, context: impl FnOnce() -> ErrorContext) -> Result<T> {
+        self.map_err(|e| e.context(context()))
+    }
+}
+
+/// Operation attempted on a resource
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operation {
+    Apply,
+    StatusPatch,
+    Delete,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Operation::Apply => "apply",
+            Operation::StatusPatch => "status patch",
+            Operation::Delete => "delete",
+        })
+    }
+}
+
+/// Resource, and operation on it, of an error
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErrorContext {
+    pub operation: Operation,
+    pub kind: String,
+    pub namespace: Option<String>,
+    pub name: String,
+}
+
+impl ErrorContext {
+    /// Context of an operation on the resource of type K with the given namespace and name
+    pub fn new<K: Resource<DynamicType = ()>>(
+        operation: Operation,
+        namespace: Option<String>,
+        name: &str,
+    ) -> Self {
+        Self {
+            operation,
+            kind: K::kind(&()).into_owned(),
+            namespace,
+            name: name.to_owned(),
+        }
+    }
+
+    /// Context of an operation on the given resource
+    pub fn of<K: Resource<DynamicType = ()>>(operation: Operation, resource: &K) -> Self {
+        Self::new::<K>(operation, resource.namespace(), &resource.name_any())
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.namespace {
+            Some(namespace) => write!(
+                f,
+                "{} {} {namespace}/{}",
+                self.operation, self.kind, self.name
+            ),
+            None => write!(f, "{} {} {}", self.operation, self.kind, self.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Error, ErrorContext, Operation, ResultExt};
+
+    use crate::crd::echo::Echo;
+
+    use k8s_openapi::api::apps::v1::Deployment;
+
+    #[test]
+    fn test_context() {
+        let echo = Echo::test(None);
+        let result: Result<(), Error> = Err(Error::MissingObjectKey("status"));
+        let error = result
+            .with_context(|| ErrorContext::of(Operation::StatusPatch, &echo))
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "status patch Echo default/test: MissingObjectKey: status"
+        );
+        assert_eq!(
+            error.metric_label(),
+            "status patch echo: missingobjectkey(\"status\")"
+        );
+        assert!(matches!(error.root(), Error::MissingObjectKey("status")));
+    }
+
+    #[test]
+    fn test_context_keeps_innermost() {
+        let error = Error::MissingObject("deployment")
+            .context(ErrorContext::new::<Deployment>(
+                Operation::Delete,
+                Some("default".to_string()),
+                "test",
+            ))
+            .context(ErrorContext::new::<Deployment>(
+                Operation::Apply,
+                Some("default".to_string()),
+                "test",
+            ));
+
+        assert_eq!(
+            error.to_string(),
+            "delete Deployment default/test: MissingObjectKey: deployment"
+        );
     }
 }
@@ -0,0 +1,295 @@
+//! Periodic garbage collection of the resources managed by the operator whose owner is gone.
+//!
+//! Owner references are lost when resources are restored from a backup or migrated between
+//! clusters, so the Kubernetes garbage collector never deletes them. Owners are found from the
+//! `app` (owner name) and `app.kubernetes.io/name` (owner kind) labels set by the reconcilers.
+use crate::controller::{ControllerId, State};
+use crate::crd::echo::Echo;
+use crate::crd::echogateway::EchoGateway;
+use crate::crd::echoroute::EchoRoute;
+use crate::error::{Error, Result};
+use crate::metrics::ControllerMetrics;
+use crate::permissions::Permission;
+
+use std::fmt::Debug;
+
+use chrono::Utc;
+use k8s_openapi::api::apps::v1::Deployment;
+use k8s_openapi::api::core::v1::Service;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use k8s_openapi::NamespaceResourceScope;
+use kube::api::{Api, DeleteParams, ListParams, ObjectMeta, Patch, PatchParams};
+use kube::client::Client;
+use kube::{Resource, ResourceExt};
+use prometheus_client::encoding::EncodeLabelValue;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::{self, Duration};
+use tracing::{error, info};
+
+pub const CONTROLLER_ID: ControllerId = "gc";
+
+pub const PERMISSIONS: &[Permission] = &[
+    Permission::new(
+        "example.com",
+        &["echoes", "echogateways", "echoroutes"],
+        &["get"],
+    ),
+    Permission::new("apps", &["deployments"], &["delete", "list", "patch"]),
+    Permission::new("", &["services"], &["delete", "list", "patch"]),
+];
+
+const MANAGED_BY_SELECTOR: &str = "app.kubernetes.io/managed-by=echo-operator";
+/// `app.kubernetes.io/name` label of the owners known by the collector
+const OWNER_KINDS: [&str; 3] = ["echo", "echogateway", "echoroute"];
+
+/// What to do with the resources whose owner reference is missing
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GcPolicy {
+    /// Only report them in the metrics
+    Report,
+    /// Restore the owner reference when the owner exists, delete them otherwise
+    Adopt,
+    /// Delete them, even when the owner exists
+    Delete,
+}
+
+/// Ownership of a managed resource
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum Ownership {
+    /// References its existing owner as the owner requires, as controller by default
+    Owned,
+    /// Its owner exists but it does not reference it as the owner requires
+    Unowned,
+    /// Its owner does not exist
+    Orphaned,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum GcAction {
+    None,
+    Adopted,
+    Deleted,
+}
+
+/// Ownership of a resource given the current owner reference of its owner, if it exists
+pub fn ownership(metadata: &ObjectMeta, owner: Option<&OwnerReference>) -> Ownership {
+    match owner {
+        None => Ownership::Orphaned,
+        Some(owner)
+            if metadata
+                .owner_references
+                .iter()
+                .flatten()
+                .any(|r| r.controller == owner.controller && r.uid == owner.uid) =>
+        {
+            Ownership::Owned
+        }
+        Some(_) => Ownership::Unowned,
+    }
+}
+
+/// Action taken by the policy for a resource
+pub fn action(policy: GcPolicy, ownership: Ownership) -> GcAction {
+    match (policy, ownership) {
+        (_, Ownership::Owned) | (GcPolicy::Report, _) => GcAction::None,
+        (GcPolicy::Adopt, Ownership::Unowned) => GcAction::Adopted,
+        (GcPolicy::Adopt, Ownership::Orphaned) | (GcPolicy::Delete, _) => GcAction::Deleted,
+    }
+}
+
+/// Run the garbage collection every interval
+pub async fn run(state: State, client: Client, policy: GcPolicy, interval: Duration) {
+    let metrics = state.controller_metrics(CONTROLLER_ID);
+    let maintenance = state.maintenance();
+    let mut ticker = time::interval(interval);
+    // safe unwrap: signal handlers can always be registered in the tokio runtime
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    metrics.ready_set(1);
+    info!(msg = "starting garbage collector", ?policy);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {},
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        }
+        if let Some(window) = maintenance.active(Utc::now()) {
+            info!(msg = "skipping garbage collection during maintenance window", %window);
+            continue;
+        }
+        if let Err(e) = collect::<Deployment>(client.clone(), policy, &metrics).await {
+            error!(msg = "failed to collect deployments", %e);
+            metrics.reconcile_failure_set(&e);
+        }
+        if let Err(e) = collect::<Service>(client.clone(), policy, &metrics).await {
+            error!(msg = "failed to collect services", %e);
+            metrics.reconcile_failure_set(&e);
+        }
+    }
+}
+
+async fn collect<K>(client: Client, policy: GcPolicy, metrics: &ControllerMetrics) -> Result<()>
+where
+    K: Resource<DynamicType = (), Scope = NamespaceResourceScope>
+        + Clone
+        + Debug
+        + DeserializeOwned,
+{
+    let kind = K::kind(&());
+    let resources = Api::<K>::all(client.clone())
+        .list(&ListParams::default().labels(MANAGED_BY_SELECTOR))
+        .await
+        .map_err(Error::KubeError)?;
+
+    for resource in resources {
+        // safe unwrap: only namespaced resources are collected
+        let namespace = resource.namespace().unwrap();
+        let labels = resource.labels();
+        let (Some(owner_name), Some(owner_kind)) =
+            (labels.get("app"), labels.get("app.kubernetes.io/name"))
+        else {
+            continue;
+        };
+        // never collect resources of unknown owners, they could not be found anyway
+        if !OWNER_KINDS.contains(&owner_kind.as_str()) {
+            continue;
+        }
+        let owner = owner_reference(client.clone(), owner_kind, &namespace, owner_name).await?;
+        let ownership = ownership(resource.meta(), owner.as_ref());
+        let action = action(policy, ownership);
+
+        let api = Api::<K>::namespaced(client.clone(), &namespace);
+        match (action, owner) {
+            (GcAction::Adopted, Some(owner)) => {
+                info!(msg = "adopting resource", %kind, %namespace, name = resource.name_any());
+                let patch = json!({"metadata": {"ownerReferences": [owner]}});
+                api.patch(
+                    &resource.name_any(),
+                    &PatchParams::default(),
+                    &Patch::Merge(&patch),
+                )
+                .await
+                .map_err(Error::KubeError)?;
+            }
+            (GcAction::Deleted, _) => {
+                info!(msg = "deleting resource", %kind, %namespace, name = resource.name_any());
+                api.delete(&resource.name_any(), &DeleteParams::default())
+                    .await
+                    .map_err(Error::KubeError)?;
+            }
+            _ => {}
+        }
+        if ownership != Ownership::Owned {
+            metrics.gc_resources_inc(&kind, ownership, action);
+        }
+    }
+    Ok(())
+}
+
+/// Controller owner reference of the owner identified by the resource labels, if it exists
+async fn owner_reference(
+    client: Client,
+    owner_kind: &str,
+    namespace: &str,
+    name: &str,
+) -> Result<Option<OwnerReference>> {
+    let owner = match owner_kind {
+        "echo" => Api::<Echo>::namespaced(client, namespace)
+            .get_opt(name)
+            .await
+            .map(|o| o.and_then(|o| o.owner_reference())),
+        "echogateway" => Api::<EchoGateway>::namespaced(client, namespace)
+            .get_opt(name)
+            .await
+            .map(|o| o.and_then(|o| o.controller_owner_ref(&()))),
+        "echoroute" => Api::<EchoRoute>::namespaced(client, namespace)
+            .get_opt(name)
+            .await
+            .map(|o| o.and_then(|o| o.controller_owner_ref(&()))),
+        // safe unreachable: owner kinds are filtered by OWNER_KINDS
+        _ => unreachable!("unknown owner kind {owner_kind}"),
+    };
+    owner.map_err(Error::KubeError)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{action, ownership, GcAction, GcPolicy, Ownership};
+
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+    use kube::api::ObjectMeta;
+
+    fn owner(uid: &str) -> OwnerReference {
+        OwnerReference {
+            api_version: "example.com/v1".to_string(),
+            kind: "Echo".to_string(),
+            name: "test".to_string(),
+            uid: uid.to_string(),
+            controller: Some(true),
+            ..OwnerReference::default()
+        }
+    }
+
+    fn metadata(owner_references: Option<Vec<OwnerReference>>) -> ObjectMeta {
+        ObjectMeta {
+            owner_references,
+            ..ObjectMeta::default()
+        }
+    }
+
+    #[test]
+    fn test_ownership() {
+        assert_eq!(
+            ownership(&metadata(Some(vec![owner("a")])), Some(&owner("a"))),
+            Ownership::Owned
+        );
+        // restored from a backup: the owner was created again with another uid
+        assert_eq!(
+            ownership(&metadata(Some(vec![owner("old")])), Some(&owner("a"))),
+            Ownership::Unowned
+        );
+        assert_eq!(
+            ownership(&metadata(None), Some(&owner("a"))),
+            Ownership::Unowned
+        );
+        // the Echo sets a non-controller reference
+        let non_controller = OwnerReference {
+            controller: Some(false),
+            ..owner("a")
+        };
+        assert_eq!(
+            ownership(&metadata(Some(vec![owner("a")])), Some(&non_controller)),
+            Ownership::Unowned
+        );
+        assert_eq!(
+            ownership(
+                &metadata(Some(vec![non_controller.clone()])),
+                Some(&non_controller)
+            ),
+            Ownership::Owned
+        );
+        assert_eq!(ownership(&metadata(None), None), Ownership::Orphaned);
+    }
+
+    #[test]
+    fn test_action() {
+        assert_eq!(action(GcPolicy::Delete, Ownership::Owned), GcAction::None);
+        assert_eq!(
+            action(GcPolicy::Report, Ownership::Orphaned),
+            GcAction::None
+        );
+        assert_eq!(
+            action(GcPolicy::Adopt, Ownership::Unowned),
+            GcAction::Adopted
+        );
+        assert_eq!(
+            action(GcPolicy::Adopt, Ownership::Orphaned),
+            GcAction::Deleted
+        );
+        assert_eq!(
+            action(GcPolicy::Delete, Ownership::Unowned),
+            GcAction::Deleted
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Heartbeat for external watchdogs, e.g. a dead man's switch.
+//!
+//! `/health` answers while the echo reconcile loop is wedged, so the heartbeat is only emitted
+//! while the echo controller makes progress: it reconciled an Echo recently, which happens at
+//! least every periodic requeue, or it has no Echoes to reconcile.
+use crate::controller::{ControllerId, State};
+use crate::crd::echo::Echo;
+use crate::permissions::Permission;
+
+use std::sync::{Mutex, OnceLock};
+
+use futures::FutureExt;
+use kube::client::Client;
+use kube::runtime::reflector::Store;
+use serde_json::json;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::{self, Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+pub const CONTROLLER_ID: ControllerId = "heartbeat";
+
+pub const PERMISSIONS: &[Permission] = &[];
+
+/// Time without progress after which the echo controller is considered wedged, twice its
+/// periodic requeue
+const MAX_SILENCE: Duration = Duration::from_secs(10 * 60);
+
+/// Progress of the echo controller
+pub struct Heartbeat {
+    /// Last reconciliation finished, or the start of the operator
+    progress: Mutex<Instant>,
+    /// Echoes to reconcile, once the controller started
+    echoes: OnceLock<Store<Echo>>,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            progress: Mutex::new(Instant::now()),
+            echoes: OnceLock::new(),
+        }
+    }
+}
+
+impl Heartbeat {
+    /// Record a finished reconciliation
+    pub(crate) fn progress(&self) {
+        // safe unwrap: the lock is never held while panicking
+        *self.progress.lock().unwrap() = Instant::now();
+    }
+
+    /// Consider the controller idle, and so alive, while the store has no Echoes
+    pub(crate) fn watch(&self, echoes: Store<Echo>) {
+        let _ignore_already_set = self.echoes.set(echoes);
+    }
+
+    /// Time since the last progress
+    fn silence(&self, now: Instant) -> Duration {
+        // safe unwrap: the lock is never held while panicking
+        now.saturating_duration_since(*self.progress.lock().unwrap())
+    }
+
+    /// Whether the echo controller started and its store completed the initial list
+    pub(crate) fn synced(&self) -> bool {
+        self.echoes
+            .get()
+            .and_then(|s| s.wait_until_ready().now_or_never())
+            .is_some_and(|r| r.is_ok())
+    }
+
+    pub(crate) fn alive(&self, now: Instant) -> bool {
+        let idle = self.echoes.get().is_some_and(|s| s.state().is_empty());
+        idle || self.silence(now) <= MAX_SILENCE
+    }
+}
+
+/// How often the heartbeat is emitted and where
+#[derive(Clone, Debug)]
+pub struct HeartbeatConfig {
+    pub interval: Duration,
+    /// URL receiving a POST on every heartbeat
+    pub url: Option<String>,
+}
+
+/// Emit the heartbeat periodically until shutdown, while the echo controller is alive
+pub async fn run(state: State, _client: Client, config: HeartbeatConfig) {
+    let metrics = state.controller_metrics(CONTROLLER_ID);
+    let heartbeat = state.heartbeat();
+    let http = reqwest::Client::new();
+    let mut ticker = time::interval(config.interval);
+    // safe unwrap: signal handlers can always be registered in the tokio runtime
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    metrics.ready_set(1);
+    info!(msg = "starting heartbeat", interval = ?config.interval, url = config.url);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {},
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        }
+        let now = Instant::now();
+        if !heartbeat.alive(now) {
+            warn!(
+                msg = "skipping heartbeat, the echo controller made no progress",
+                silence = ?heartbeat.silence(now)
+            );
+            continue;
+        }
+        debug!(msg = "heartbeat");
+        metrics.heartbeats_inc();
+        if let Some(url) = config.url.as_deref() {
+            let sent = http
+                .post(url)
+                .json(&json!({"controller": crate::echo::controller::CONTROLLER_ID}))
+                .send()
+                .await
+                .and_then(|r| r.error_for_status());
+            if let Err(e) = sent {
+                error!(msg = "failed to send heartbeat", %e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Heartbeat, MAX_SILENCE};
+
+    use kube::runtime::reflector::store;
+    use kube::runtime::watcher;
+    use tokio::time::{Duration, Instant};
+
+    #[test]
+    fn test_alive() {
+        let heartbeat = Heartbeat::default();
+        let now = Instant::now();
+        assert!(heartbeat.alive(now));
+        assert!(!heartbeat.alive(now + MAX_SILENCE + Duration::from_secs(1)));
+
+        let now = Instant::now();
+        heartbeat.progress();
+        assert!(heartbeat.alive(now + MAX_SILENCE));
+    }
+
+    #[test]
+    fn test_synced() {
+        let heartbeat = Heartbeat::default();
+        assert!(!heartbeat.synced());
+
+        let (reader, mut writer) = store();
+        heartbeat.watch(reader);
+        assert!(!heartbeat.synced());
+
+        writer.apply_watcher_event(&watcher::Event::InitDone);
+        assert!(heartbeat.synced());
+    }
+
+    #[test]
+    fn test_alive_without_echoes() {
+        let heartbeat = Heartbeat::default();
+        let (reader, _writer) = store();
+        heartbeat.watch(reader);
+
+        assert!(heartbeat.alive(Instant::now() + MAX_SILENCE * 2));
+    }
+}
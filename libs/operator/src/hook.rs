@@ -0,0 +1,119 @@
+//! Extension points to customize the Echo reconciliation from downstream builds.
+//!
+//! Hooks are registered in the [`State`](crate::controller::State) with
+//! [`State::with_hook`](crate::controller::State::with_hook) and run in registration order. An
+//! error returned by a hook fails the reconciliation, which is retried by the controller.
+use crate::crd::echo::Echo;
+use crate::error::Result;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use k8s_openapi::api::apps::v1::Deployment;
+
+#[async_trait]
+pub trait ReconcileHook: Send + Sync {
+    /// Called with the generated Deployment before it is applied, so it can be mutated
+    async fn pre_apply(&self, _echo: &Echo, _deployment: &mut Deployment) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called with the Deployment returned by the API server once it is applied
+    async fn post_apply(&self, _echo: &Echo, _deployment: &Deployment) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called before the reconciler deletes a resource of the Echo
+    async fn pre_delete(&self, _echo: &Echo, _kind: &str, _name: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Registered hooks, in order
+pub type Hooks = Vec<Arc<dyn ReconcileHook>>;
+
+pub(crate) async fn pre_apply(
+    hooks: &Hooks,
+    echo: &Echo,
+    deployment: &mut Deployment,
+) -> Result<()> {
+    for hook in hooks {
+        hook.pre_apply(echo, deployment).await?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn post_apply(hooks: &Hooks, echo: &Echo, deployment: &Deployment) -> Result<()> {
+    for hook in hooks {
+        hook.post_apply(echo, deployment).await?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn pre_delete(hooks: &Hooks, echo: &Echo, kind: &str, name: &str) -> Result<()> {
+    for hook in hooks {
+        hook.pre_delete(echo, kind, name).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pre_apply, pre_delete, Hooks, ReconcileHook};
+
+    use crate::crd::echo::Echo;
+    use crate::error::{Error, Result};
+
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use k8s_openapi::api::apps::v1::Deployment;
+
+    struct LabelHook(&'static str);
+
+    #[async_trait]
+    impl ReconcileHook for LabelHook {
+        async fn pre_apply(&self, _echo: &Echo, deployment: &mut Deployment) -> Result<()> {
+            deployment
+                .metadata
+                .labels
+                .get_or_insert_with(Default::default)
+                .insert("hook".to_string(), self.0.to_string());
+            Ok(())
+        }
+    }
+
+    struct DenyDeleteHook;
+
+    #[async_trait]
+    impl ReconcileHook for DenyDeleteHook {
+        async fn pre_delete(&self, _echo: &Echo, kind: &str, _name: &str) -> Result<()> {
+            Err(Error::HookError(format!("{kind} deletion denied")))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pre_apply_runs_hooks_in_order() {
+        let hooks: Hooks = vec![Arc::new(LabelHook("first")), Arc::new(LabelHook("second"))];
+        let mut deployment = Deployment::default();
+
+        pre_apply(&hooks, &Echo::test(None), &mut deployment)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            deployment.metadata.labels.unwrap().get("hook"),
+            Some(&"second".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pre_delete_error() {
+        let hooks: Hooks = vec![Arc::new(LabelHook("first")), Arc::new(DenyDeleteHook)];
+
+        assert!(matches!(
+            pre_delete(&hooks, &Echo::test(None), "Deployment", "test").await,
+            Err(Error::HookError(_))
+        ));
+    }
+}
@@ -0,0 +1,485 @@
+//! Namespaced watchers of the objects managed for the echoes, e.g. their Deployments, started when
+//! the first echo of a namespace appears and stopped when its last one is deleted, so the stores
+//! only cache the objects of the namespaces using the operator.
+//!
+//! The events of the namespaced watchers are merged into a single stream, fit for one reflector:
+//! their initial lists are applied object by object, and the objects not listed again, or of the
+//! namespaces not watched anymore, are deleted, as the store is shared by every namespace.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::stream::{self, AbortHandle, Abortable, BoxStream, SelectAll};
+use futures::StreamExt;
+use k8s_openapi::NamespaceResourceScope;
+use kube::api::Api;
+use kube::client::Client;
+use kube::runtime::watcher::{self};
+use kube::runtime::WatchStreamExt;
+use kube::{Resource, ResourceExt};
+use serde::de::DeserializeOwned;
+use tokio::sync::Notify;
+use tracing::info;
+
+type Event<K> = watcher::Result<watcher::Event<K>>;
+
+/// Namespace gaining its first echo, or losing its last one
+#[derive(Clone, Debug, PartialEq)]
+enum NamespaceChange {
+    Added(String),
+    Removed(String),
+}
+
+/// Echoes of each namespace, from the events of an echo watcher, starting and stopping the
+/// namespaced watchers
+#[derive(Debug, Default)]
+pub struct NamespaceTracker {
+    echoes: HashMap<String, HashSet<String>>,
+    /// Echoes of the initial list in progress
+    listed: Option<HashMap<String, HashSet<String>>>,
+    /// Unset when the watchers are cluster-wide
+    changes: Option<UnboundedSender<NamespaceChange>>,
+}
+
+impl NamespaceTracker {
+    /// Track the echo of the event
+    pub fn observe<E: Resource>(&mut self, event: &watcher::Event<E>) {
+        match event {
+            watcher::Event::Init => self.listed = Some(HashMap::new()),
+            watcher::Event::InitApply(echo) => {
+                if let Some(listed) = self.listed.as_mut() {
+                    let (namespace, name) = key(echo);
+                    listed.entry(namespace).or_default().insert(name);
+                }
+            }
+            watcher::Event::InitDone => {
+                let listed = self.listed.take().unwrap_or_default();
+                let previous = std::mem::replace(&mut self.echoes, listed);
+                previous
+                    .keys()
+                    .filter(|namespace| !self.echoes.contains_key(*namespace))
+                    .for_each(|namespace| self.send(NamespaceChange::Removed(namespace.clone())));
+                self.echoes
+                    .keys()
+                    .filter(|namespace| !previous.contains_key(*namespace))
+                    .for_each(|namespace| self.send(NamespaceChange::Added(namespace.clone())));
+            }
+            watcher::Event::Apply(echo) => {
+                let (namespace, name) = key(echo);
+                let echoes = self.echoes.entry(namespace.clone()).or_default();
+                let added = echoes.is_empty();
+                echoes.insert(name);
+                if added {
+                    self.send(NamespaceChange::Added(namespace));
+                }
+            }
+            watcher::Event::Delete(echo) => {
+                let (namespace, name) = key(echo);
+                if let Some(echoes) = self.echoes.get_mut(&namespace) {
+                    echoes.remove(&name);
+                    if echoes.is_empty() {
+                        self.echoes.remove(&namespace);
+                        self.send(NamespaceChange::Removed(namespace));
+                    }
+                }
+            }
+        }
+    }
+
+    fn send(&self, change: NamespaceChange) {
+        if let Some(changes) = &self.changes {
+            // the watchers are only dropped on shutdown
+            let _ignore_errors = changes.unbounded_send(change);
+        }
+    }
+}
+
+fn key<K: Resource>(obj: &K) -> (String, String) {
+    (obj.namespace().unwrap_or_default(), obj.name_any())
+}
+
+/// Namespaces whose objects completed their initial list
+#[derive(Debug, Default)]
+pub struct ReadyNamespaces {
+    /// Unset when the watchers are cluster-wide, and every namespace is ready with the store
+    lazy: bool,
+    ready: Mutex<HashSet<String>>,
+    changed: Notify,
+}
+
+impl ReadyNamespaces {
+    fn lazy() -> Self {
+        Self {
+            lazy: true,
+            ..Self::default()
+        }
+    }
+
+    /// Wait until the objects of the namespace are listed, so they are not reported as missing
+    pub async fn wait(&self, namespace: &str) {
+        loop {
+            // registered before the check, so a change in between is not missed
+            let changed = self.changed.notified();
+            if !self.lazy || self.is_ready(namespace) {
+                return;
+            }
+            changed.await;
+        }
+    }
+
+    fn is_ready(&self, namespace: &str) -> bool {
+        // safe unwrap: the lock is never held across a panic
+        self.ready.lock().unwrap().contains(namespace)
+    }
+
+    fn set(&self, namespace: &str, ready: bool) {
+        // safe unwrap: the lock is never held across a panic
+        let mut namespaces = self.ready.lock().unwrap();
+        if ready {
+            namespaces.insert(namespace.to_string());
+        } else {
+            namespaces.remove(namespace);
+        }
+        drop(namespaces);
+        self.changed.notify_waiters();
+    }
+}
+
+/// Namespaces to watch, reported by the tracker
+#[derive(Debug)]
+pub struct WatchedNamespaces {
+    /// Unset when the watchers are cluster-wide
+    changes: Option<UnboundedReceiver<NamespaceChange>>,
+    ready: Arc<ReadyNamespaces>,
+}
+
+impl WatchedNamespaces {
+    /// Namespaces whose objects completed their initial list
+    pub fn ready(&self) -> Arc<ReadyNamespaces> {
+        self.ready.clone()
+    }
+}
+
+/// Tracker of the namespaces with echoes and the namespaces it reports, all of them when not lazy
+pub fn namespaces(lazy: bool) -> (NamespaceTracker, WatchedNamespaces) {
+    if !lazy {
+        let namespaces = WatchedNamespaces {
+            changes: None,
+            ready: Arc::default(),
+        };
+        return (NamespaceTracker::default(), namespaces);
+    }
+    let (changes_tx, changes_rx) = mpsc::unbounded();
+    let tracker = NamespaceTracker {
+        changes: Some(changes_tx),
+        ..NamespaceTracker::default()
+    };
+    let namespaces = WatchedNamespaces {
+        changes: Some(changes_rx),
+        ready: Arc::new(ReadyNamespaces::lazy()),
+    };
+    (tracker, namespaces)
+}
+
+/// Watcher of the objects of type K in the namespaces, cluster-wide when not lazy. The events are
+/// already backed off on errors.
+pub fn watcher<K>(
+    client: Client,
+    config: watcher::Config,
+    namespaces: WatchedNamespaces,
+) -> BoxStream<'static, Event<K>>
+where
+    K: Resource<Scope = NamespaceResourceScope, DynamicType = ()>
+        + Clone
+        + Debug
+        + Default
+        + DeserializeOwned
+        + Send
+        + 'static,
+{
+    let Some(changes) = namespaces.changes else {
+        return watcher::watcher(Api::<K>::all(client), config)
+            .default_backoff()
+            .boxed();
+    };
+    let watchers = NamespacedWatchers::new(client, config, changes, namespaces.ready);
+    stream::unfold(watchers, |mut watchers| async move {
+        watchers.next().await.map(|event| (event, watchers))
+    })
+    .boxed()
+}
+
+/// Watchers of the namespaces with echoes
+struct NamespacedWatchers<K: Resource> {
+    client: Client,
+    config: watcher::Config,
+    changes: UnboundedReceiver<NamespaceChange>,
+    watchers: SelectAll<BoxStream<'static, (String, Event<K>)>>,
+    aborts: HashMap<String, AbortHandle>,
+    /// Names of the objects of each namespace in the store
+    known: HashMap<String, HashSet<String>>,
+    /// Names of the objects of each namespace in the initial list in progress
+    listed: HashMap<String, HashSet<String>>,
+    ready: Arc<ReadyNamespaces>,
+    pending: VecDeque<Event<K>>,
+}
+
+impl<K> NamespacedWatchers<K>
+where
+    K: Resource<Scope = NamespaceResourceScope, DynamicType = ()>
+        + Clone
+        + Debug
+        + Default
+        + DeserializeOwned
+        + Send
+        + 'static,
+{
+    fn new(
+        client: Client,
+        config: watcher::Config,
+        changes: UnboundedReceiver<NamespaceChange>,
+        ready: Arc<ReadyNamespaces>,
+    ) -> Self {
+        Self {
+            client,
+            config,
+            changes,
+            watchers: SelectAll::new(),
+            aborts: HashMap::new(),
+            known: HashMap::new(),
+            listed: HashMap::new(),
+            ready,
+            // the store is ready, empty, until the first namespace is watched
+            pending: VecDeque::from([Ok(watcher::Event::Init), Ok(watcher::Event::InitDone)]),
+        }
+    }
+
+    /// Next event of the watched namespaces, none once the tracker is dropped
+    async fn next(&mut self) -> Option<Event<K>> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+            tokio::select! {
+                change = self.changes.next() => match change? {
+                    NamespaceChange::Added(namespace) => self.start(namespace),
+                    NamespaceChange::Removed(namespace) => self.stop(&namespace),
+                },
+                Some((namespace, event)) = self.watchers.next(), if !self.watchers.is_empty() => {
+                    self.handle(&namespace, event);
+                }
+            }
+        }
+    }
+
+    fn start(&mut self, namespace: String) {
+        if self.aborts.contains_key(&namespace) {
+            return;
+        }
+        info!(msg = "starting namespaced watcher", kind = %K::kind(&()), %namespace);
+        let (abort, registration) = AbortHandle::new_pair();
+        let events = watcher::watcher(
+            Api::<K>::namespaced(self.client.clone(), &namespace),
+            self.config.clone(),
+        )
+        .default_backoff();
+        let tagged = {
+            let namespace = namespace.clone();
+            Abortable::new(events, registration).map(move |event| (namespace.clone(), event))
+        };
+        self.watchers.push(tagged.boxed());
+        self.aborts.insert(namespace, abort);
+    }
+
+    fn stop(&mut self, namespace: &str) {
+        let Some(abort) = self.aborts.remove(namespace) else {
+            return;
+        };
+        info!(msg = "stopping namespaced watcher", kind = %K::kind(&()), namespace);
+        abort.abort();
+        self.ready.set(namespace, false);
+        self.listed.remove(namespace);
+        for name in self.known.remove(namespace).unwrap_or_default() {
+            self.pending
+                .push_back(Ok(watcher::Event::Delete(stub(namespace, name))));
+        }
+    }
+
+    fn handle(&mut self, namespace: &str, event: Event<K>) {
+        match event {
+            Ok(watcher::Event::Init) => {
+                self.listed.insert(namespace.to_string(), HashSet::new());
+            }
+            Ok(watcher::Event::InitApply(obj)) => {
+                self.listed
+                    .entry(namespace.to_string())
+                    .or_default()
+                    .insert(obj.name_any());
+                self.apply(namespace, obj);
+            }
+            Ok(watcher::Event::InitDone) => {
+                let listed = self.listed.remove(namespace).unwrap_or_default();
+                let known = self
+                    .known
+                    .insert(namespace.to_string(), listed.clone())
+                    .unwrap_or_default();
+                for name in known.difference(&listed) {
+                    self.pending
+                        .push_back(Ok(watcher::Event::Delete(stub(namespace, name.clone()))));
+                }
+                self.ready.set(namespace, true);
+            }
+            Ok(watcher::Event::Apply(obj)) => self.apply(namespace, obj),
+            Ok(watcher::Event::Delete(obj)) => {
+                if let Some(known) = self.known.get_mut(namespace) {
+                    known.remove(&obj.name_any());
+                }
+                self.pending.push_back(Ok(watcher::Event::Delete(obj)));
+            }
+            Err(e) => self.pending.push_back(Err(e)),
+        }
+    }
+
+    fn apply(&mut self, namespace: &str, obj: K) {
+        self.known
+            .entry(namespace.to_string())
+            .or_default()
+            .insert(obj.name_any());
+        self.pending.push_back(Ok(watcher::Event::Apply(obj)));
+    }
+}
+
+/// Object with only the name and namespace, enough to delete it from a store
+fn stub<K: Resource + Default>(namespace: &str, name: String) -> K {
+    let mut obj = K::default();
+    obj.meta_mut().name = Some(name);
+    obj.meta_mut().namespace = Some(namespace.to_string());
+    obj
+}
+
+#[cfg(test)]
+mod test {
+    use super::{NamespaceChange, NamespaceTracker, NamespacedWatchers, ReadyNamespaces};
+
+    use std::sync::Arc;
+
+    use futures::channel::mpsc;
+    use futures::stream::AbortHandle;
+    use http::{Request, Response};
+    use k8s_openapi::api::apps::v1::Deployment;
+    use kube::api::ObjectMeta;
+    use kube::client::Body;
+    use kube::runtime::watcher::{self, Event};
+    use kube::{Client, ResourceExt};
+
+    fn object(namespace: &str, name: &str) -> Deployment {
+        Deployment {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                namespace: Some(namespace.to_string()),
+                ..ObjectMeta::default()
+            },
+            ..Deployment::default()
+        }
+    }
+
+    #[test]
+    fn test_tracker_changes() {
+        let (tx, mut rx) = mpsc::unbounded();
+        let mut tracker = NamespaceTracker {
+            changes: Some(tx),
+            ..NamespaceTracker::default()
+        };
+        tracker.observe(&Event::<Deployment>::Init);
+        tracker.observe(&Event::InitApply(object("a", "1")));
+        tracker.observe(&Event::InitApply(object("a", "2")));
+        tracker.observe(&Event::<Deployment>::InitDone);
+        tracker.observe(&Event::Apply(object("b", "1")));
+        tracker.observe(&Event::Apply(object("b", "1")));
+        tracker.observe(&Event::Delete(object("a", "1")));
+        tracker.observe(&Event::Delete(object("a", "2")));
+        // relist without the echoes of b
+        tracker.observe(&Event::<Deployment>::Init);
+        tracker.observe(&Event::InitApply(object("c", "1")));
+        tracker.observe(&Event::<Deployment>::InitDone);
+
+        let mut changes = Vec::new();
+        while let Ok(Some(change)) = rx.try_next() {
+            changes.push(change);
+        }
+        assert_eq!(
+            changes,
+            vec![
+                NamespaceChange::Added("a".to_string()),
+                NamespaceChange::Added("b".to_string()),
+                NamespaceChange::Removed("a".to_string()),
+                NamespaceChange::Removed("b".to_string()),
+                NamespaceChange::Added("c".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ready_namespaces_wait() {
+        ReadyNamespaces::default().wait("default").await;
+
+        let ready = Arc::new(ReadyNamespaces::lazy());
+        let wait = tokio::spawn({
+            let ready = ready.clone();
+            async move { ready.wait("default").await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!wait.is_finished());
+
+        ready.set("other", true);
+        tokio::task::yield_now().await;
+        assert!(!wait.is_finished());
+
+        ready.set("default", true);
+        wait.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_namespaced_watchers_events() {
+        let (mock_service, _handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+        let (_tx, rx) = mpsc::unbounded();
+        let ready = Arc::new(ReadyNamespaces::lazy());
+        let mut watchers = NamespacedWatchers::<Deployment>::new(
+            Client::new(mock_service, "default"),
+            watcher::Config::default(),
+            rx,
+            ready.clone(),
+        );
+        watchers.pending.clear();
+
+        watchers.handle("a", Ok(Event::Init));
+        watchers.handle("a", Ok(Event::InitApply(object("a", "1"))));
+        watchers.handle("a", Ok(Event::InitApply(object("a", "2"))));
+        watchers.handle("a", Ok(Event::InitDone));
+        assert!(ready.is_ready("a"));
+        // relist without the first object
+        watchers.handle("a", Ok(Event::Init));
+        watchers.handle("a", Ok(Event::InitApply(object("a", "2"))));
+        watchers.handle("a", Ok(Event::InitDone));
+        watchers
+            .aborts
+            .insert("a".to_string(), AbortHandle::new_pair().0);
+        watchers.stop("a");
+        assert!(!ready.is_ready("a"));
+
+        let events: Vec<String> = watchers
+            .pending
+            .drain(..)
+            .map(|event| match event {
+                Ok(Event::Apply(d)) => format!("apply {}", d.name_any()),
+                Ok(Event::Delete(d)) => format!("delete {}", d.name_any()),
+                _ => "other".to_string(),
+            })
+            .collect();
+        assert_eq!(
+            events,
+            vec!["apply 1", "apply 2", "apply 2", "delete 1", "delete 2"]
+        );
+    }
+}
@@ -1,6 +1,43 @@
+pub mod audit;
+pub mod clock;
+pub mod clusterdefaults;
+pub mod clusterecho;
 pub mod controller;
 pub mod crd;
+pub mod dashboard;
+pub mod diff;
 pub mod echo;
+pub mod echogateway;
+pub mod echoquota;
+pub mod echoreplication;
+pub mod echoroute;
+pub mod echostatus;
 pub mod error;
+pub mod gc;
+pub mod heartbeat;
+pub mod hook;
+pub mod lazy_reflector;
+pub mod maintenance;
 mod metrics;
+pub mod namespace_filter;
+pub mod notify;
+pub mod owned;
+pub mod permissions;
+pub mod prelude;
+pub mod prober;
+pub mod rbac_allowlist;
+pub mod readiness;
+pub mod remotewrite;
+pub mod requeue;
+pub mod sentry;
+pub mod startup;
+pub mod statsd;
+pub mod status;
+pub mod stores;
 pub mod telemetry;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+pub mod watchlist;
+
+pub use k8s_openapi;
+pub use kube;
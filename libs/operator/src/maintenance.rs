@@ -0,0 +1,256 @@
+//! Maintenance windows during which the operator performs no mutating action, so planned cluster
+//! operations are not undone by the reconcilers.
+use crate::controller::{ControllerId, State};
+use crate::error::{Error, Result};
+use crate::permissions::Permission;
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::api::Api;
+use kube::client::Client;
+use kube::runtime::{watcher, WatchStreamExt};
+use kube::ResourceExt;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info};
+
+pub const CONTROLLER_ID: ControllerId = "maintenance";
+
+pub const PERMISSIONS: &[Permission] = &[Permission::new("", &["configmaps"], &["list", "watch"])];
+
+/// Annotation setting the maintenance window of a single Echo
+pub const MAINTENANCE_WINDOW_ANNOTATION: &str = "echoes.example.com/maintenance-window";
+
+/// Key of the maintenance ConfigMap holding the cluster-wide window
+pub const CONFIG_MAP_KEY: &str = "window";
+
+/// Interval written as `<start>/<end>` RFC 3339 timestamps, e.g.
+/// `2024-01-01T22:00:00Z/2024-01-02T02:00:00Z`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl MaintenanceWindow {
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        self.start <= now && now < self.end
+    }
+
+    /// Requeue when the window ends, or after the interval if it is later, as it can be changed
+    pub fn requeue_after(&self, now: DateTime<Utc>, requeue: Duration) -> Duration {
+        (self.end - now).to_std().unwrap_or_default().min(requeue)
+    }
+}
+
+impl FromStr for MaintenanceWindow {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::InvalidMaintenanceWindow(format!("{s}: {reason}"));
+        let (start, end) = s
+            .split_once('/')
+            .ok_or_else(|| invalid("expected <start>/<end>"))?;
+        let parse = |t: &str| {
+            DateTime::parse_from_rfc3339(t.trim())
+                .map(|t| t.with_timezone(&Utc))
+                .map_err(|e| invalid(&e.to_string()))
+        };
+        let window = Self {
+            start: parse(start)?,
+            end: parse(end)?,
+        };
+        if window.end <= window.start {
+            return Err(invalid("end is not after start"));
+        }
+        Ok(window)
+    }
+}
+
+impl fmt::Display for MaintenanceWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.start.to_rfc3339(), self.end.to_rfc3339())
+    }
+}
+
+/// Cluster-wide maintenance windows
+#[derive(Debug, Default)]
+pub struct Maintenance {
+    /// Window of the `--maintenance-window` flag
+    window: Option<MaintenanceWindow>,
+    /// Window of the maintenance ConfigMap, when it is watched
+    config_map_window: RwLock<Option<MaintenanceWindow>>,
+}
+
+impl Maintenance {
+    pub fn new(window: Option<MaintenanceWindow>) -> Self {
+        Self {
+            window,
+            config_map_window: RwLock::default(),
+        }
+    }
+
+    /// Cluster-wide window in progress, if any
+    pub fn active(&self, now: DateTime<Utc>) -> Option<MaintenanceWindow> {
+        // safe unwrap: the lock is never held across a panic
+        let config_map_window = *self.config_map_window.read().unwrap();
+        self.window
+            .into_iter()
+            .chain(config_map_window)
+            .find(|w| w.contains(now))
+    }
+
+    fn set_config_map_window(&self, window: Option<MaintenanceWindow>) {
+        info!(msg = "maintenance ConfigMap window changed", window = ?window.map(|w| w.to_string()));
+        // safe unwrap: the lock is never held across a panic
+        *self.config_map_window.write().unwrap() = window;
+    }
+}
+
+/// Window of the maintenance ConfigMap. An invalid one is ignored, as it can not be reported in
+/// any status.
+fn config_map_window(config_map: &ConfigMap) -> Option<MaintenanceWindow> {
+    let window = config_map.data.as_ref()?.get(CONFIG_MAP_KEY)?;
+    window
+        .parse()
+        .inspect_err(|e| error!(msg = "ignoring maintenance ConfigMap window", %e))
+        .ok()
+}
+
+/// Keep the window of the maintenance ConfigMap, in the operator namespace, up to date
+pub async fn run(state: State, client: Client, config_map: String) {
+    let metrics = state.controller_metrics(CONTROLLER_ID);
+    let maintenance = state.maintenance();
+    let config = watcher::Config::default().fields(&format!("metadata.name={config_map}"));
+    let mut events = watcher(Api::<ConfigMap>::default_namespaced(client), config)
+        .default_backoff()
+        .boxed();
+    // safe unwrap: signal handlers can always be registered in the tokio runtime
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    info!(msg = "watching maintenance ConfigMap", %config_map);
+    // whether the ConfigMap was found by the list in progress
+    let mut listed = false;
+    loop {
+        let event = tokio::select! {
+            event = events.next() => event,
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        };
+        match event {
+            Some(Ok(watcher::Event::Init)) => listed = false,
+            Some(Ok(watcher::Event::InitApply(cm))) => {
+                listed = true;
+                maintenance.set_config_map_window(config_map_window(&cm));
+            }
+            Some(Ok(watcher::Event::InitDone)) => {
+                if !listed {
+                    maintenance.set_config_map_window(None);
+                }
+                metrics.ready_set(1);
+            }
+            Some(Ok(watcher::Event::Apply(cm))) => {
+                maintenance.set_config_map_window(config_map_window(&cm))
+            }
+            Some(Ok(watcher::Event::Delete(cm))) => {
+                info!(msg = "maintenance ConfigMap deleted", name = cm.name_any());
+                maintenance.set_config_map_window(None);
+            }
+            Some(Err(e)) => {
+                error!(msg = "maintenance ConfigMap watch failed", %e);
+                metrics.watch_operations_failed_inc();
+            }
+            None => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{config_map_window, Maintenance, MaintenanceWindow, CONFIG_MAP_KEY};
+
+    use crate::error::Error;
+    use crate::test_utils::test_time;
+
+    use std::collections::BTreeMap;
+
+    use chrono::Duration;
+    use k8s_openapi::api::core::v1::ConfigMap;
+
+    const WINDOW: &str = "2024-01-01T00:00:00Z/2024-01-01T02:00:00Z";
+
+    #[test]
+    fn test_parse_window() {
+        let window: MaintenanceWindow = WINDOW.parse().unwrap();
+        assert_eq!(window.start, test_time());
+        assert_eq!(window.end, test_time() + Duration::hours(2));
+        assert_eq!(
+            window.to_string(),
+            "2024-01-01T00:00:00+00:00/2024-01-01T02:00:00+00:00"
+        );
+
+        for invalid in [
+            "2024-01-01T00:00:00Z",
+            "2024-01-01T00:00:00Z/tomorrow",
+            "2024-01-01T02:00:00Z/2024-01-01T00:00:00Z",
+        ] {
+            assert!(matches!(
+                invalid.parse::<MaintenanceWindow>(),
+                Err(Error::InvalidMaintenanceWindow(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_active_window() {
+        let window: MaintenanceWindow = WINDOW.parse().unwrap();
+        let maintenance = Maintenance::new(Some(window));
+        assert_eq!(maintenance.active(test_time()), Some(window));
+        assert_eq!(maintenance.active(window.end), None);
+
+        let config_map_window = MaintenanceWindow {
+            start: window.end,
+            end: window.end + Duration::hours(1),
+        };
+        maintenance.set_config_map_window(Some(config_map_window));
+        assert_eq!(maintenance.active(window.end), Some(config_map_window));
+    }
+
+    #[test]
+    fn test_requeue_after() {
+        let window: MaintenanceWindow = WINDOW.parse().unwrap();
+        let hour = std::time::Duration::from_secs(60 * 60);
+
+        // the window ends in two hours
+        assert_eq!(window.requeue_after(test_time(), hour), hour);
+        assert_eq!(
+            window.requeue_after(
+                test_time() + Duration::hours(1) + Duration::minutes(30),
+                hour
+            ),
+            std::time::Duration::from_secs(30 * 60)
+        );
+        assert_eq!(
+            window.requeue_after(window.end + Duration::hours(1), hour),
+            std::time::Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_config_map_window() {
+        let config_map = |window: &str| ConfigMap {
+            data: Some(BTreeMap::from([(
+                CONFIG_MAP_KEY.to_string(),
+                window.to_string(),
+            )])),
+            ..ConfigMap::default()
+        };
+        assert!(config_map_window(&config_map(WINDOW)).is_some());
+        assert!(config_map_window(&config_map("tonight")).is_none());
+        assert!(config_map_window(&ConfigMap::default()).is_none());
+    }
+}
@@ -4,14 +4,171 @@ use opentelemetry::trace::TraceId;
 use prometheus_client::encoding::{EncodeLabelSet, EncodeLabelValue};
 use prometheus_client::metrics::{
     counter::Counter, exemplar::HistogramWithExemplars, family::Family, gauge::Gauge,
+    histogram::Histogram,
 };
 use prometheus_client::registry::{Registry, Unit};
-use std::sync::Arc;
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
 use tokio::time::Instant;
 
 use crate::controller::ControllerId;
+use crate::gc::{GcAction, Ownership};
 use std::collections::HashMap;
 
+/// Prefix of every metric name
+pub const METRICS_PREFIX: &str = "echo-operator";
+
+/// Type of a metric, which determines how it is queried
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    /// Histogram of seconds
+    Histogram,
+}
+
+/// Metric registered by every controller
+#[derive(Clone, Copy, Debug)]
+pub struct MetricDefinition {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: MetricKind,
+    /// Labeled with the namespace and name of the reconciled object
+    pub per_resource: bool,
+}
+
+pub const METRIC_DEFINITIONS: [MetricDefinition; 20] = [
+    MetricDefinition {
+        name: "reconcile_operations",
+        help: "Total number of reconcile operations",
+        kind: MetricKind::Counter,
+        per_resource: false,
+    },
+    MetricDefinition {
+        name: "reconcile_failures",
+        help: "Number of errors that occurred during reconcile operations",
+        kind: MetricKind::Counter,
+        per_resource: false,
+    },
+    MetricDefinition {
+        name: "reconcile_duration",
+        help: "Histogram of reconcile operations",
+        kind: MetricKind::Histogram,
+        per_resource: false,
+    },
+    MetricDefinition {
+        name: "reconcile_success_ratio_5m",
+        help: "Ratio of reconcile operations without errors in the last 5 minutes",
+        kind: MetricKind::Gauge,
+        per_resource: false,
+    },
+    MetricDefinition {
+        name: "reconcile_success_ratio_1h",
+        help: "Ratio of reconcile operations without errors in the last hour",
+        kind: MetricKind::Gauge,
+        per_resource: false,
+    },
+    MetricDefinition {
+        name: "reconcile_slow",
+        help: "Number of reconcile operations slower than the slow reconcile threshold",
+        kind: MetricKind::Counter,
+        per_resource: false,
+    },
+    MetricDefinition {
+        name: "reconcile_deploy_delete_create",
+        help: "Number of times that reconciling a deployment required deleting and re-creating it",
+        kind: MetricKind::Counter,
+        per_resource: false,
+    },
+    MetricDefinition {
+        name: "spec_replicas",
+        help: "Number of expected replicas for the object",
+        kind: MetricKind::Gauge,
+        per_resource: true,
+    },
+    MetricDefinition {
+        name: "status_update_errors",
+        help: "Number of errors that occurred during update operations to status subresources",
+        kind: MetricKind::Counter,
+        per_resource: false,
+    },
+    MetricDefinition {
+        name: "status_updates_coalesced",
+        help: "Number of status patches replaced by a newer one of the same object before being sent",
+        kind: MetricKind::Counter,
+        per_resource: false,
+    },
+    MetricDefinition {
+        name: "status_updates_dropped",
+        help: "Number of status patches dropped because the status writer queue was full",
+        kind: MetricKind::Counter,
+        per_resource: false,
+    },
+    MetricDefinition {
+        name: "status_queue_depth",
+        help: "Number of objects with a status patch waiting for the status writer",
+        kind: MetricKind::Gauge,
+        per_resource: false,
+    },
+    MetricDefinition {
+        name: "triggered",
+        help: "Number of times a Kubernetes object applied or delete event triggered to reconcile an object",
+        kind: MetricKind::Counter,
+        per_resource: false,
+    },
+    MetricDefinition {
+        name: "triggers_deduplicated",
+        help: "Number of events dropped because the fields read by the reconciliation did not change",
+        kind: MetricKind::Counter,
+        per_resource: false,
+    },
+    MetricDefinition {
+        name: "watch_operations_failed",
+        help: "Total number of watch operations that failed",
+        kind: MetricKind::Counter,
+        per_resource: false,
+    },
+    MetricDefinition {
+        name: "ready",
+        help: "1 when the controller is ready to reconcile resources, 0 otherwise",
+        kind: MetricKind::Gauge,
+        per_resource: false,
+    },
+    MetricDefinition {
+        name: "heartbeats",
+        help: "Number of heartbeats emitted while the echo controller makes progress",
+        kind: MetricKind::Counter,
+        per_resource: false,
+    },
+    MetricDefinition {
+        name: "notification_failures",
+        help: "Number of notifications that could not be delivered to a webhook",
+        kind: MetricKind::Counter,
+        per_resource: false,
+    },
+    MetricDefinition {
+        name: "probe_duration",
+        help: "Histogram of the probes of the echo Services",
+        kind: MetricKind::Histogram,
+        per_resource: true,
+    },
+    MetricDefinition {
+        name: "gc_resources",
+        help: "Number of managed resources found without their owner by the garbage collector",
+        kind: MetricKind::Counter,
+        per_resource: false,
+    },
+];
+
+fn help(name: &str) -> &'static str {
+    METRIC_DEFINITIONS
+        .iter()
+        .find(|d| d.name == name)
+        .expect("all registered metrics have a definition")
+        .help
+}
+
 #[derive(Clone)]
 pub struct Metrics {
     pub controllers: HashMap<ControllerId, Arc<ControllerMetrics>>,
@@ -37,15 +194,47 @@ impl Metrics {
     }
 }
 
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub struct ControllerMetrics {
     controller: String,
     pub reconcile: ReconcileMetrics,
     pub spec_replicas: Family<ResourceLabels, Gauge>,
     pub status_update_errors: Family<ControllerLabels, Counter>,
+    pub status_updates_coalesced: Family<ControllerLabels, Counter>,
+    pub status_updates_dropped: Family<ControllerLabels, Counter>,
+    pub status_queue_depth: Family<ControllerLabels, Gauge>,
     pub triggered: Family<TriggeredLabels, Counter>,
+    pub triggers_deduplicated: Family<TriggeredByLabels, Counter>,
     pub watch_operations_failed: Family<ControllerLabels, Counter>,
     pub ready: Family<ControllerLabels, Gauge>,
+    pub heartbeats: Family<ControllerLabels, Counter>,
+    pub notification_failures: Family<ControllerLabels, Counter>,
+    pub probe_duration: Family<ResourceLabels, Histogram>,
+    pub gc_resources: Family<GcLabels, Counter>,
+}
+
+impl Default for ControllerMetrics {
+    fn default() -> Self {
+        Self {
+            controller: Default::default(),
+            reconcile: Default::default(),
+            spec_replicas: Default::default(),
+            status_update_errors: Default::default(),
+            status_updates_coalesced: Default::default(),
+            status_updates_dropped: Default::default(),
+            status_queue_depth: Default::default(),
+            triggered: Default::default(),
+            triggers_deduplicated: Default::default(),
+            watch_operations_failed: Default::default(),
+            ready: Default::default(),
+            heartbeats: Default::default(),
+            notification_failures: Default::default(),
+            probe_duration: Family::<ResourceLabels, Histogram>::new_with_constructor(|| {
+                Histogram::new([0.01, 0.05, 0.1, 0.5, 1., 5.].into_iter())
+            }),
+            gc_resources: Default::default(),
+        }
+    }
 }
 
 impl ControllerMetrics {
@@ -60,54 +249,99 @@ impl ControllerMetrics {
     pub fn register(self, r: &mut Registry) -> Self {
         r.register(
             "reconcile_operations",
-            "Total number of reconcile operations",
+            help("reconcile_operations"),
             self.reconcile.operations.clone(),
         );
         r.register(
             "reconcile_failures",
-            "Number of errors that occurred during reconcile operations",
+            help("reconcile_failures"),
             self.reconcile.failures.clone(),
         );
         r.register_with_unit(
             "reconcile_duration",
-            "Histogram of reconcile operations",
+            help("reconcile_duration"),
             Unit::Seconds,
             self.reconcile.duration.clone(),
         );
+        r.register(
+            "reconcile_success_ratio_5m",
+            help("reconcile_success_ratio_5m"),
+            self.reconcile.success_ratio_5m.clone(),
+        );
+        r.register(
+            "reconcile_success_ratio_1h",
+            help("reconcile_success_ratio_1h"),
+            self.reconcile.success_ratio_1h.clone(),
+        );
+        r.register(
+            "reconcile_slow",
+            help("reconcile_slow"),
+            self.reconcile.slow.clone(),
+        );
         r.register(
             "reconcile_deploy_delete_create",
-            "Number of times that reconciling a deployment required deleting and re-creating it",
+            help("reconcile_deploy_delete_create"),
             self.reconcile.deploy_delete_create.clone(),
         );
         r.register(
             "spec_replicas",
-            "Number of expected replicas for the object",
+            help("spec_replicas"),
             self.spec_replicas.clone(),
         );
         r.register(
             "status_update_errors",
-            "Number of errors that occurred during update operations to status subresources",
+            help("status_update_errors"),
             self.status_update_errors.clone(),
         );
         r.register(
-            "triggered",
-            "Number of times a Kubernetes object applied or delete event triggered to reconcile an object",
-            self.triggered.clone(),
+            "status_updates_coalesced",
+            help("status_updates_coalesced"),
+            self.status_updates_coalesced.clone(),
+        );
+        r.register(
+            "status_updates_dropped",
+            help("status_updates_dropped"),
+            self.status_updates_dropped.clone(),
+        );
+        r.register(
+            "status_queue_depth",
+            help("status_queue_depth"),
+            self.status_queue_depth.clone(),
+        );
+        r.register("triggered", help("triggered"), self.triggered.clone());
+        r.register(
+            "triggers_deduplicated",
+            help("triggers_deduplicated"),
+            self.triggers_deduplicated.clone(),
         );
         r.register(
             "watch_operations_failed",
-            "Total number of watch operations that failed",
+            help("watch_operations_failed"),
             self.watch_operations_failed.clone(),
         );
+        r.register("ready", help("ready"), self.ready.clone());
+        r.register("heartbeats", help("heartbeats"), self.heartbeats.clone());
         r.register(
-            "ready",
-            "1 when the controller is ready to reconcile resources, 0 otherwise",
-            self.ready.clone(),
+            "notification_failures",
+            help("notification_failures"),
+            self.notification_failures.clone(),
+        );
+        r.register_with_unit(
+            "probe_duration",
+            help("probe_duration"),
+            Unit::Seconds,
+            self.probe_duration.clone(),
+        );
+        r.register(
+            "gc_resources",
+            help("gc_resources"),
+            self.gc_resources.clone(),
         );
         self
     }
 
     pub fn reconcile_failure_set(&self, e: &Error) {
+        self.reconcile.windows_record(0, 1);
         self.reconcile
             .failures
             .get_or_create(&ErrorLabels {
@@ -125,6 +359,7 @@ impl ControllerMetrics {
             .operations
             .get_or_create(&controller_labels)
             .inc();
+        self.reconcile.windows_record(1, 0);
         ReconcileMeasurer {
             start: Instant::now(),
             labels: trace_id.try_into().ok(),
@@ -136,6 +371,31 @@ impl ControllerMetrics {
         }
     }
 
+    /// Update the rolling success ratios, which change with time even without reconciliations
+    pub fn success_ratios_refresh(&self) {
+        let controller_labels = ControllerLabels {
+            controller: self.controller.clone(),
+        };
+        // safe unwrap: the lock is never held while panicking
+        let windows = self.reconcile.windows.lock().unwrap();
+        let minute = windows.minute();
+        self.reconcile
+            .success_ratio_5m
+            .get_or_create(&controller_labels)
+            .set(windows.success_ratio(minute, 5));
+        self.reconcile
+            .success_ratio_1h
+            .get_or_create(&controller_labels)
+            .set(windows.success_ratio(minute, 60));
+    }
+
+    pub fn reconcile_slow_inc(&self) {
+        let controller_labels = ControllerLabels {
+            controller: self.controller.clone(),
+        };
+        self.reconcile.slow.get_or_create(&controller_labels).inc();
+    }
+
     pub fn reconcile_deploy_delete_create_inc(&self) {
         let controller_labels = ControllerLabels {
             controller: self.controller.clone(),
@@ -166,6 +426,33 @@ impl ControllerMetrics {
             .inc();
     }
 
+    pub fn status_updates_coalesced_inc(&self) {
+        let controller_labels = ControllerLabels {
+            controller: self.controller.clone(),
+        };
+        self.status_updates_coalesced
+            .get_or_create(&controller_labels)
+            .inc();
+    }
+
+    pub fn status_updates_dropped_inc(&self) {
+        let controller_labels = ControllerLabels {
+            controller: self.controller.clone(),
+        };
+        self.status_updates_dropped
+            .get_or_create(&controller_labels)
+            .inc();
+    }
+
+    pub fn status_queue_depth_set(&self, depth: usize) {
+        let controller_labels = ControllerLabels {
+            controller: self.controller.clone(),
+        };
+        self.status_queue_depth
+            .get_or_create(&controller_labels)
+            .set(depth as i64);
+    }
+
     pub fn triggered_inc(&self, action: Action, triggered_by: &str) {
         let triggered_labels = TriggeredLabels {
             controller: self.controller.clone(),
@@ -175,6 +462,16 @@ impl ControllerMetrics {
         self.triggered.get_or_create(&triggered_labels).inc();
     }
 
+    pub fn triggers_deduplicated_inc(&self, triggered_by: &str) {
+        let triggered_by_labels = TriggeredByLabels {
+            controller: self.controller.clone(),
+            triggered_by: triggered_by.to_string(),
+        };
+        self.triggers_deduplicated
+            .get_or_create(&triggered_by_labels)
+            .inc();
+    }
+
     pub fn watch_operations_failed_inc(&self) {
         let controller_labels = ControllerLabels {
             controller: self.controller.clone(),
@@ -190,6 +487,50 @@ impl ControllerMetrics {
         };
         self.ready.get_or_create(&controller_labels).set(status);
     }
+
+    pub fn ready(&self) -> bool {
+        let controller_labels = ControllerLabels {
+            controller: self.controller.clone(),
+        };
+        self.ready.get_or_create(&controller_labels).get() == 1
+    }
+
+    pub fn gc_resources_inc(&self, kind: &str, ownership: Ownership, action: GcAction) {
+        let gc_labels = GcLabels {
+            controller: self.controller.clone(),
+            kind: kind.to_string(),
+            ownership,
+            action,
+        };
+        self.gc_resources.get_or_create(&gc_labels).inc();
+    }
+
+    pub fn probe_duration_observe(&self, namespace: &str, name: &str, seconds: f64) {
+        let resource_labels = ResourceLabels {
+            controller: self.controller.clone(),
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+        };
+        self.probe_duration
+            .get_or_create(&resource_labels)
+            .observe(seconds);
+    }
+
+    pub fn heartbeats_inc(&self) {
+        let controller_labels = ControllerLabels {
+            controller: self.controller.clone(),
+        };
+        self.heartbeats.get_or_create(&controller_labels).inc();
+    }
+
+    pub fn notification_failures_inc(&self) {
+        let controller_labels = ControllerLabels {
+            controller: self.controller.clone(),
+        };
+        self.notification_failures
+            .get_or_create(&controller_labels)
+            .inc();
+    }
 }
 
 #[derive(Clone)]
@@ -197,6 +538,10 @@ pub struct ReconcileMetrics {
     pub operations: Family<ControllerLabels, Counter>,
     pub failures: Family<ErrorLabels, Counter>,
     pub duration: Family<ControllerLabels, HistogramWithExemplars<TraceLabel>>,
+    pub success_ratio_5m: Family<ControllerLabels, Gauge<f64, AtomicU64>>,
+    pub success_ratio_1h: Family<ControllerLabels, Gauge<f64, AtomicU64>>,
+    windows: Arc<Mutex<ReconcileWindows>>,
+    pub slow: Family<ControllerLabels, Counter>,
     pub deploy_delete_create: Family<ControllerLabels, Counter>,
 }
 
@@ -209,11 +554,83 @@ impl Default for ReconcileMetrics {
                 Family::<ControllerLabels, HistogramWithExemplars<TraceLabel>>::new_with_constructor(
                     || HistogramWithExemplars::new([0.1, 0.5, 1., 5., 10.].into_iter()),
                 ),
+            success_ratio_5m: Default::default(),
+            success_ratio_1h: Default::default(),
+            windows: Arc::default(),
+            slow: Default::default(),
             deploy_delete_create: Default::default(),
         }
     }
 }
 
+impl ReconcileMetrics {
+    fn windows_record(&self, operations: u64, failures: u64) {
+        // safe unwrap: the lock is never held while panicking
+        let mut windows = self.windows.lock().unwrap();
+        let minute = windows.minute();
+        windows.record(minute, operations, failures);
+    }
+}
+
+/// Reconcile operations and failures per minute of the last hour, to compute the rolling success
+/// ratios in process instead of with Prometheus recording rules
+#[derive(Debug)]
+struct ReconcileWindows {
+    start: Instant,
+    /// Minute since start, operations and failures, oldest first
+    buckets: VecDeque<(u64, u64, u64)>,
+}
+
+impl Default for ReconcileWindows {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            buckets: VecDeque::new(),
+        }
+    }
+}
+
+impl ReconcileWindows {
+    /// Minutes of the longest window
+    const MAX_MINUTES: u64 = 60;
+
+    fn minute(&self) -> u64 {
+        self.start.elapsed().as_secs() / 60
+    }
+
+    fn record(&mut self, minute: u64, operations: u64, failures: u64) {
+        match self.buckets.back_mut() {
+            Some((m, ops, fails)) if *m == minute => {
+                *ops += operations;
+                *fails += failures;
+            }
+            _ => self.buckets.push_back((minute, operations, failures)),
+        }
+        while self
+            .buckets
+            .front()
+            .is_some_and(|(m, _, _)| m + Self::MAX_MINUTES <= minute)
+        {
+            self.buckets.pop_front();
+        }
+    }
+
+    /// Ratio of operations without failures in the last minutes, including the current one.
+    /// Without operations nothing failed, so it is 1.
+    fn success_ratio(&self, minute: u64, minutes: u64) -> f64 {
+        let (operations, failures) = self
+            .buckets
+            .iter()
+            .filter(|(m, _, _)| m + minutes > minute)
+            .fold((0, 0), |(ops, fails), (_, o, f)| (ops + o, fails + f));
+        if operations == 0 {
+            return 1.;
+        }
+        // failures reported outside of reconcile operations can exceed them
+        (1. - failures as f64 / operations as f64).max(0.)
+    }
+}
+
 /// Smart function duration measurer
 ///
 /// Relies on Drop to calculate duration and register the observation in the histogram
@@ -273,8 +690,109 @@ pub struct TriggeredLabels {
     pub triggered_by: String,
 }
 
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct TriggeredByLabels {
+    pub controller: String,
+    pub triggered_by: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct GcLabels {
+    pub controller: String,
+    pub kind: String,
+    pub ownership: Ownership,
+    pub action: GcAction,
+}
+
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
 pub enum Action {
     Apply,
     Delete,
 }
+
+/// Name, labels and value of a sample
+pub(crate) type Sample<'a> = (&'a str, Vec<(&'a str, String)>, f64);
+
+/// Name, labels and value of a sample line, e.g. `name{label="value"} 1 # {id="..."} 1`
+pub(crate) fn parse_sample(line: &str) -> Option<Sample<'_>> {
+    let name_end = line.find(['{', ' '])?;
+    let name = &line[..name_end];
+    let mut labels = Vec::new();
+    let mut rest = &line[name_end..];
+    if let Some(mut label_text) = rest.strip_prefix('{') {
+        loop {
+            label_text = label_text.trim_start_matches(',');
+            if let Some(after) = label_text.strip_prefix('}') {
+                rest = after;
+                break;
+            }
+            let (key, after) = label_text.split_once("=\"")?;
+            let mut value = String::new();
+            let mut chars = after.char_indices();
+            let end = loop {
+                match chars.next()? {
+                    (_, '\\') => value.push(chars.next()?.1),
+                    (i, '"') => break i,
+                    (_, c) => value.push(c),
+                }
+            };
+            labels.push((key, value));
+            label_text = &after[end + 1..];
+        }
+    }
+    let value = rest.split_whitespace().next()?.parse().ok()?;
+    Some((name, labels, value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_sample, ReconcileWindows};
+
+    #[test]
+    fn test_success_ratio() {
+        let mut windows = ReconcileWindows::default();
+        assert_eq!(windows.success_ratio(0, 5), 1.);
+
+        windows.record(0, 4, 0);
+        windows.record(0, 0, 1);
+        assert_eq!(windows.success_ratio(0, 5), 0.75);
+
+        windows.record(10, 2, 0);
+        assert_eq!(windows.success_ratio(10, 5), 1.);
+        assert_eq!(windows.success_ratio(10, 60), 5. / 6.);
+    }
+
+    #[test]
+    fn test_success_ratio_forgets_old_minutes() {
+        let mut windows = ReconcileWindows::default();
+        windows.record(0, 1, 1);
+        windows.record(59, 1, 0);
+        assert_eq!(windows.success_ratio(59, 60), 0.5);
+
+        windows.record(60, 1, 0);
+        assert_eq!(windows.buckets.len(), 2);
+        assert_eq!(windows.success_ratio(60, 60), 1.);
+    }
+
+    #[test]
+    fn test_success_ratio_clamped() {
+        let mut windows = ReconcileWindows::default();
+        windows.record(0, 1, 3);
+        assert_eq!(windows.success_ratio(0, 5), 0.);
+    }
+
+    #[test]
+    fn test_parse_sample() {
+        let (name, labels, value) =
+            parse_sample(r#"requests_total{path="/a\"b",code="200"} 2.5 # {id="x"} 1"#).unwrap();
+        assert_eq!(name, "requests_total");
+        assert_eq!(
+            labels,
+            vec![("path", "/a\"b".to_string()), ("code", "200".to_string())]
+        );
+        assert_eq!(value, 2.5);
+
+        let (name, labels, value) = parse_sample("up 1").unwrap();
+        assert_eq!((name, labels.len(), value), ("up", 0, 1.));
+    }
+}
@@ -0,0 +1,63 @@
+//! Namespaces where the operator manages resources, so protected namespaces are left alone even
+//! when the operator RBAC allows them.
+
+/// Allowlist and denylist of namespace names. A trailing `*` matches every name with that prefix,
+/// e.g. `kube-*`.
+#[derive(Clone, Debug, Default)]
+pub struct NamespaceFilter {
+    allowlist: Vec<String>,
+    denylist: Vec<String>,
+}
+
+fn matches(pattern: &str, namespace: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => namespace.starts_with(prefix),
+        None => pattern == namespace,
+    }
+}
+
+impl NamespaceFilter {
+    pub fn new(allowlist: Vec<String>, denylist: Vec<String>) -> Self {
+        Self {
+            allowlist,
+            denylist,
+        }
+    }
+
+    /// Whether the namespace is managed: it is not denied and, with an allowlist, it is allowed.
+    /// The denylist wins when both match.
+    pub fn allows(&self, namespace: &str) -> bool {
+        !self.denylist.iter().any(|p| matches(p, namespace))
+            && (self.allowlist.is_empty() || self.allowlist.iter().any(|p| matches(p, namespace)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::NamespaceFilter;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn test_default_allows_every_namespace() {
+        assert!(NamespaceFilter::default().allows("kube-system"));
+    }
+
+    #[test]
+    fn test_denylist() {
+        let filter = NamespaceFilter::new(vec![], names(&["kube-*", "monitoring"]));
+        assert!(!filter.allows("kube-system"));
+        assert!(!filter.allows("monitoring"));
+        assert!(filter.allows("default"));
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let filter = NamespaceFilter::new(names(&["team-*"]), names(&["team-secret"]));
+        assert!(filter.allows("team-a"));
+        assert!(!filter.allows("default"));
+        assert!(!filter.allows("team-secret"));
+    }
+}
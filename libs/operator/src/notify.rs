@@ -0,0 +1,354 @@
+//! Webhook notifications when an Echo health changes, and CloudEvents of its lifecycle.
+use crate::metrics::ControllerMetrics;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use tracing::{debug, error};
+
+pub const DEFAULT_TEMPLATE: &str = "Echo {namespace}/{name} is {health}: {message}";
+
+/// Source of the CloudEvents
+const CLOUDEVENTS_SOURCE: &str = "echo-operator";
+/// Prefix of the CloudEvents types, followed by the lowercase transition, e.g. `.ready`
+const CLOUDEVENTS_TYPE_PREFIX: &str = "com.example.echo";
+
+/// CloudEvents sent by this process, making their ids unique
+static CLOUDEVENTS: AtomicU64 = AtomicU64::new(0);
+
+/// Health of an Echo, as reported in notifications
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Health {
+    Ready,
+    NotReady,
+    Degraded,
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Health::Ready => write!(f, "Ready"),
+            Health::NotReady => write!(f, "NotReady"),
+            Health::Degraded => write!(f, "Degraded"),
+        }
+    }
+}
+
+/// Payload format expected by the webhook receiving the notifications
+#[derive(Clone, Debug, PartialEq)]
+pub enum SinkFormat {
+    /// JSON object with the Echo, its health and the rendered message
+    Generic,
+    /// Slack incoming webhook message
+    Slack,
+    /// CloudEvent in HTTP binary mode, with the Echo and its health as data. These sinks also
+    /// receive the creation and deletion of the Echoes
+    CloudEvents,
+}
+
+/// Creation or deletion of an Echo, only published to the CloudEvents sinks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lifecycle {
+    /// Its Deployment was created
+    Created,
+    Deleted,
+}
+
+impl fmt::Display for Lifecycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lifecycle::Created => write!(f, "Created"),
+            Lifecycle::Deleted => write!(f, "Deleted"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Sink {
+    pub url: String,
+    pub format: SinkFormat,
+}
+
+/// Health transition of an Echo
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notification {
+    pub namespace: String,
+    pub name: String,
+    pub health: Health,
+    pub message: String,
+}
+
+impl Notification {
+    /// Render the template replacing `{namespace}`, `{name}`, `{health}` and `{message}`
+    fn render(&self, template: &str) -> String {
+        template
+            .replace("{namespace}", &self.namespace)
+            .replace("{name}", &self.name)
+            .replace("{health}", &self.health.to_string())
+            .replace("{message}", &self.message)
+    }
+
+    fn payload(&self, format: &SinkFormat, template: &str) -> Value {
+        let text = self.render(template);
+        match format {
+            SinkFormat::Generic => json!({
+                "namespace": self.namespace,
+                "name": self.name,
+                "health": self.health.to_string(),
+                "message": text,
+            }),
+            SinkFormat::Slack => json!({ "text": text }),
+            SinkFormat::CloudEvents => json!({
+                "namespace": self.namespace,
+                "name": self.name,
+                "health": self.health.to_string(),
+                "message": self.message,
+            }),
+        }
+    }
+}
+
+/// Headers of a CloudEvent in HTTP binary mode, its data being the request body
+fn cloudevent_headers(
+    transition: &str,
+    namespace: &str,
+    name: &str,
+    time: DateTime<Utc>,
+) -> Vec<(&'static str, String)> {
+    let id = format!(
+        "{:x}-{:x}",
+        time.timestamp_nanos_opt().unwrap_or_default(),
+        CLOUDEVENTS.fetch_add(1, Ordering::Relaxed)
+    );
+    vec![
+        ("ce-specversion", "1.0".to_string()),
+        ("ce-id", id),
+        ("ce-source", CLOUDEVENTS_SOURCE.to_string()),
+        (
+            "ce-type",
+            format!("{CLOUDEVENTS_TYPE_PREFIX}.{}", transition.to_lowercase()),
+        ),
+        ("ce-subject", format!("{namespace}/{name}")),
+        ("ce-time", time.to_rfc3339()),
+    ]
+}
+
+/// Sends a notification to every sink when an Echo becomes Ready, NotReady or Degraded
+pub struct Notifier {
+    sinks: Vec<Sink>,
+    template: String,
+    http: reqwest::Client,
+    /// Last health notified for every Echo, by `namespace/name`
+    last_health: Mutex<HashMap<String, Health>>,
+}
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Self::new(Vec::new(), DEFAULT_TEMPLATE.to_string())
+    }
+}
+
+impl Notifier {
+    pub fn new(sinks: Vec<Sink>, template: String) -> Self {
+        Self {
+            sinks,
+            template,
+            http: reqwest::Client::new(),
+            last_health: Mutex::default(),
+        }
+    }
+
+    /// Record the current health of an Echo, returning a notification when it changed.
+    ///
+    /// `previous` seeds the health of Echoes not seen yet, e.g. after an operator restart.
+    pub fn observe(
+        &self,
+        notification: Notification,
+        previous: Option<Health>,
+    ) -> Option<Notification> {
+        let key = format!("{}/{}", notification.namespace, notification.name);
+        // safe unwrap: the lock is never held across a panic
+        let mut last_health = self.last_health.lock().unwrap();
+        let last = last_health.insert(key, notification.health).or(previous);
+        match last {
+            Some(last) if last == notification.health => None,
+            // a new Echo becoming ready is not news
+            None if notification.health == Health::Ready => None,
+            _ => Some(notification),
+        }
+    }
+
+    /// Deliver a notification to every sink in the background
+    pub fn send(self: &Arc<Self>, notification: Notification, metrics: Arc<ControllerMetrics>) {
+        for sink in &self.sinks {
+            let payload = notification.payload(&sink.format, &self.template);
+            let headers = match sink.format {
+                SinkFormat::CloudEvents => cloudevent_headers(
+                    &notification.health.to_string(),
+                    &notification.namespace,
+                    &notification.name,
+                    Utc::now(),
+                ),
+                SinkFormat::Generic | SinkFormat::Slack => Vec::new(),
+            };
+            self.deliver(sink.url.clone(), payload, headers, metrics.clone());
+        }
+    }
+
+    /// Publish the creation or deletion of an Echo to the CloudEvents sinks in the background
+    pub fn send_lifecycle(
+        self: &Arc<Self>,
+        namespace: &str,
+        name: &str,
+        lifecycle: Lifecycle,
+        metrics: Arc<ControllerMetrics>,
+    ) {
+        if lifecycle == Lifecycle::Deleted {
+            // safe unwrap: the lock is never held across a panic
+            let mut last_health = self.last_health.lock().unwrap();
+            last_health.remove(&format!("{namespace}/{name}"));
+        }
+        for sink in self
+            .sinks
+            .iter()
+            .filter(|s| s.format == SinkFormat::CloudEvents)
+        {
+            let payload = json!({"namespace": namespace, "name": name});
+            let headers = cloudevent_headers(&lifecycle.to_string(), namespace, name, Utc::now());
+            self.deliver(sink.url.clone(), payload, headers, metrics.clone());
+        }
+    }
+
+    fn deliver(
+        self: &Arc<Self>,
+        url: String,
+        payload: Value,
+        headers: Vec<(&'static str, String)>,
+        metrics: Arc<ControllerMetrics>,
+    ) {
+        let notifier = self.clone();
+        tokio::spawn(async move {
+            let request = headers
+                .into_iter()
+                .fold(notifier.http.post(&url), |request, (name, value)| {
+                    request.header(name, value)
+                });
+            let result = request
+                .json(&payload)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            match result {
+                Ok(_) => debug!(msg = "notification delivered", url),
+                Err(e) => {
+                    error!(msg = "failed to deliver notification", url, %e);
+                    metrics.notification_failures_inc();
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{cloudevent_headers, Health, Notification, Notifier, SinkFormat, DEFAULT_TEMPLATE};
+
+    use crate::test_utils::test_time;
+
+    use serde_json::json;
+
+    fn notification(health: Health) -> Notification {
+        Notification {
+            namespace: "default".to_string(),
+            name: "test".to_string(),
+            health,
+            message: "1/1 replicas ready".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_observe_transitions() {
+        let notifier = Notifier::default();
+
+        assert_eq!(notifier.observe(notification(Health::Ready), None), None);
+        assert_eq!(notifier.observe(notification(Health::Ready), None), None);
+        assert_eq!(
+            notifier.observe(notification(Health::NotReady), None),
+            Some(notification(Health::NotReady))
+        );
+        assert_eq!(
+            notifier.observe(notification(Health::Degraded), None),
+            Some(notification(Health::Degraded))
+        );
+        assert_eq!(
+            notifier.observe(notification(Health::Ready), None),
+            Some(notification(Health::Ready))
+        );
+    }
+
+    #[test]
+    fn test_observe_seeds_from_previous() {
+        let notifier = Notifier::default();
+
+        assert_eq!(
+            notifier.observe(notification(Health::Ready), Some(Health::NotReady)),
+            Some(notification(Health::Ready))
+        );
+        assert_eq!(
+            notifier.observe(notification(Health::NotReady), Some(Health::Ready)),
+            Some(notification(Health::NotReady))
+        );
+    }
+
+    #[test]
+    fn test_payload() {
+        let notification = notification(Health::NotReady);
+
+        assert_eq!(
+            notification.payload(&SinkFormat::Slack, DEFAULT_TEMPLATE),
+            json!({"text": "Echo default/test is NotReady: 1/1 replicas ready"})
+        );
+        assert_eq!(
+            notification.payload(&SinkFormat::Generic, "{name} {health}"),
+            json!({
+                "namespace": "default",
+                "name": "test",
+                "health": "NotReady",
+                "message": "test NotReady",
+            })
+        );
+    }
+
+    #[test]
+    fn test_cloudevents() {
+        let notification = notification(Health::Degraded);
+        assert_eq!(
+            notification.payload(&SinkFormat::CloudEvents, "{name}"),
+            json!({
+                "namespace": "default",
+                "name": "test",
+                "health": "Degraded",
+                "message": "1/1 replicas ready",
+            })
+        );
+
+        let headers = cloudevent_headers("Degraded", "default", "test", test_time());
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.clone())
+                .unwrap()
+        };
+        assert_eq!(header("ce-specversion"), "1.0");
+        assert_eq!(header("ce-type"), "com.example.echo.degraded");
+        assert_eq!(header("ce-subject"), "default/test");
+        assert_eq!(header("ce-time"), test_time().to_rfc3339());
+        let other = cloudevent_headers("Degraded", "default", "test", test_time());
+        assert_ne!(header("ce-id"), other[1].1);
+    }
+}
@@ -0,0 +1,133 @@
+//! Deletion of the objects created for the custom resources, e.g. the Ingress of an EchoGateway
+//! once its spec does not set one anymore.
+//!
+//! Objects are deleted by the name the reconcilers give them, so an object created with the same
+//! name by a user or another controller is only deleted when it references the resource as its
+//! controller.
+use crate::error::{Error, Result};
+
+use std::fmt::Debug;
+
+use kube::api::{Api, DeleteParams, Preconditions};
+use kube::{Resource, ResourceExt};
+use serde::de::DeserializeOwned;
+use tracing::debug;
+
+/// Ignore not found errors, as the object is already deleted
+pub fn ignore_not_found<T>(result: std::result::Result<T, kube::Error>) -> Result<()> {
+    match result {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(ae)) if ae.code == 404 => Ok(()),
+        Err(e) => Err(Error::KubeError(e)),
+    }
+}
+
+/// Whether the controller owner reference of the object points at the owner
+pub fn is_controlled_by<K: Resource, O: Resource>(object: &K, owner: &O) -> bool {
+    object
+        .meta()
+        .owner_references
+        .iter()
+        .flatten()
+        .any(|r| r.controller == Some(true) && Some(&r.uid) == owner.meta().uid.as_ref())
+}
+
+/// Delete the object when the owner is its controller, returning whether it was deleted. Missing
+/// objects are ignored.
+pub async fn delete_controlled<K, O>(api: &Api<K>, name: &str, owner: &O) -> Result<bool>
+where
+    K: Resource + Clone + DeserializeOwned + Debug,
+    O: Resource,
+{
+    let Some(object) = api.get_opt(name).await.map_err(Error::KubeError)? else {
+        return Ok(false);
+    };
+    if !is_controlled_by(&object, owner) {
+        debug!(msg = "keeping object not controlled by its owner", name);
+        return Ok(false);
+    }
+    let params = DeleteParams {
+        // the object could be replaced since it was read
+        preconditions: Some(Preconditions {
+            uid: object.uid(),
+            ..Preconditions::default()
+        }),
+        ..DeleteParams::default()
+    };
+    ignore_not_found(api.delete(name, &params).await)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{delete_controlled, is_controlled_by};
+
+    use crate::crd::echogateway::{EchoGateway, EchoGatewaySpec};
+    use crate::test_utils::fake_apiserver::FakeApiServer;
+
+    use k8s_openapi::api::networking::v1::Ingress;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+    use kube::api::{Api, ObjectMeta};
+    use kube::Resource;
+
+    fn gateway() -> EchoGateway {
+        let mut gateway = EchoGateway::new("test", EchoGatewaySpec::default());
+        gateway.meta_mut().namespace = Some("default".to_string());
+        gateway.meta_mut().uid = Some("gateway-uid".to_string());
+        gateway
+    }
+
+    fn ingress(owner_references: Option<Vec<OwnerReference>>) -> Ingress {
+        Ingress {
+            metadata: ObjectMeta {
+                name: Some("test".to_string()),
+                namespace: Some("default".to_string()),
+                owner_references,
+                ..ObjectMeta::default()
+            },
+            ..Ingress::default()
+        }
+    }
+
+    #[test]
+    fn test_is_controlled_by() {
+        let gateway = gateway();
+        let controller = gateway.controller_owner_ref(&()).unwrap();
+
+        assert!(is_controlled_by(
+            &ingress(Some(vec![controller.clone()])),
+            &gateway
+        ));
+        assert!(!is_controlled_by(&ingress(None), &gateway));
+        // owned but not controlled
+        let owner = OwnerReference {
+            controller: None,
+            ..controller.clone()
+        };
+        assert!(!is_controlled_by(&ingress(Some(vec![owner])), &gateway));
+        // controlled by another object
+        let other = OwnerReference {
+            uid: "other-uid".to_string(),
+            ..controller
+        };
+        assert!(!is_controlled_by(&ingress(Some(vec![other])), &gateway));
+    }
+
+    #[tokio::test]
+    async fn test_delete_controlled() {
+        let gateway = gateway();
+        let fake = FakeApiServer::default();
+        let api = Api::<Ingress>::namespaced(fake.client(), "default");
+
+        assert!(!delete_controlled(&api, "test", &gateway).await.unwrap());
+
+        fake.create(&ingress(None));
+        assert!(!delete_controlled(&api, "test", &gateway).await.unwrap());
+        assert!(fake.get::<Ingress>(Some("default"), "test").is_some());
+
+        fake.delete::<Ingress>(Some("default"), "test");
+        fake.create(&ingress(gateway.controller_owner_ref(&()).map(|r| vec![r])));
+        assert!(delete_controlled(&api, "test", &gateway).await.unwrap());
+        assert!(fake.get::<Ingress>(Some("default"), "test").is_none());
+    }
+}
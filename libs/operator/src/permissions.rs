@@ -0,0 +1,162 @@
+//! Kubernetes API permissions of the controllers, declared next to each one, so the RBAC of the
+//! operator is generated from the controllers it runs instead of maintained by hand.
+use crate::clusterdefaults;
+use crate::clusterecho;
+use crate::controller::ControllerId;
+use crate::dashboard;
+use crate::echo;
+use crate::echogateway;
+use crate::echoquota;
+use crate::echoreplication;
+use crate::echoroute;
+use crate::echostatus;
+use crate::gc;
+use crate::heartbeat;
+use crate::maintenance;
+use crate::prober;
+use crate::remotewrite;
+use crate::statsd;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use k8s_openapi::api::rbac::v1::PolicyRule;
+
+/// Verbs allowed on resources of an API group, the core one being `""`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Permission {
+    pub api_group: &'static str,
+    pub resources: &'static [&'static str],
+    pub verbs: &'static [&'static str],
+}
+
+impl Permission {
+    pub const fn new(
+        api_group: &'static str,
+        resources: &'static [&'static str],
+        verbs: &'static [&'static str],
+    ) -> Self {
+        Self {
+            api_group,
+            resources,
+            verbs,
+        }
+    }
+}
+
+/// Identifiers of every controller of the crate
+pub const CONTROLLER_IDS: [ControllerId; 15] = [
+    echo::controller::CONTROLLER_ID,
+    echostatus::controller::CONTROLLER_ID,
+    echogateway::controller::CONTROLLER_ID,
+    echoroute::controller::CONTROLLER_ID,
+    echoquota::controller::CONTROLLER_ID,
+    echoreplication::controller::CONTROLLER_ID,
+    clusterecho::controller::CONTROLLER_ID,
+    gc::CONTROLLER_ID,
+    heartbeat::CONTROLLER_ID,
+    dashboard::CONTROLLER_ID,
+    prober::CONTROLLER_ID,
+    maintenance::CONTROLLER_ID,
+    statsd::CONTROLLER_ID,
+    remotewrite::CONTROLLER_ID,
+    clusterdefaults::CONTROLLER_ID,
+];
+
+/// Permissions of the controller, if it is known
+pub fn controller_permissions(id: &str) -> Option<&'static [Permission]> {
+    match id {
+        echo::controller::CONTROLLER_ID => Some(echo::controller::PERMISSIONS),
+        echostatus::controller::CONTROLLER_ID => Some(echostatus::controller::PERMISSIONS),
+        echogateway::controller::CONTROLLER_ID => Some(echogateway::controller::PERMISSIONS),
+        echoroute::controller::CONTROLLER_ID => Some(echoroute::controller::PERMISSIONS),
+        echoquota::controller::CONTROLLER_ID => Some(echoquota::controller::PERMISSIONS),
+        echoreplication::controller::CONTROLLER_ID => {
+            Some(echoreplication::controller::PERMISSIONS)
+        }
+        clusterecho::controller::CONTROLLER_ID => Some(clusterecho::controller::PERMISSIONS),
+        gc::CONTROLLER_ID => Some(gc::PERMISSIONS),
+        heartbeat::CONTROLLER_ID => Some(heartbeat::PERMISSIONS),
+        dashboard::CONTROLLER_ID => Some(dashboard::PERMISSIONS),
+        prober::CONTROLLER_ID => Some(prober::PERMISSIONS),
+        maintenance::CONTROLLER_ID => Some(maintenance::PERMISSIONS),
+        statsd::CONTROLLER_ID => Some(statsd::PERMISSIONS),
+        remotewrite::CONTROLLER_ID => Some(remotewrite::PERMISSIONS),
+        clusterdefaults::CONTROLLER_ID => Some(clusterdefaults::PERMISSIONS),
+        _ => None,
+    }
+}
+
+/// ClusterRole rules granting the permissions, merged so every resource appears in one rule and
+/// resources of a group with the same verbs share it
+pub fn policy_rules<'a>(permissions: impl IntoIterator<Item = &'a Permission>) -> Vec<PolicyRule> {
+    let mut verbs: BTreeMap<(&str, &str), BTreeSet<&str>> = BTreeMap::new();
+    for permission in permissions {
+        for resource in permission.resources {
+            verbs
+                .entry((permission.api_group, *resource))
+                .or_default()
+                .extend(permission.verbs);
+        }
+    }
+    let mut resources: BTreeMap<(&str, BTreeSet<&str>), Vec<String>> = BTreeMap::new();
+    for ((api_group, resource), verbs) in verbs {
+        resources
+            .entry((api_group, verbs))
+            .or_default()
+            .push(resource.to_string());
+    }
+    resources
+        .into_iter()
+        .map(|((api_group, verbs), resources)| PolicyRule {
+            api_groups: Some(vec![api_group.to_string()]),
+            resources: Some(resources),
+            verbs: verbs.into_iter().map(str::to_string).collect(),
+            ..PolicyRule::default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{controller_permissions, policy_rules, Permission, CONTROLLER_IDS};
+
+    #[test]
+    fn test_every_controller_has_permissions() {
+        assert!(CONTROLLER_IDS
+            .iter()
+            .all(|id| controller_permissions(id).is_some()));
+        assert!(controller_permissions("unknown").is_none());
+    }
+
+    #[test]
+    fn test_policy_rules_merge() {
+        let permissions = [
+            Permission::new("apps", &["deployments"], &["list", "watch"]),
+            Permission::new("apps", &["deployments"], &["patch", "list"]),
+            Permission::new("", &["services"], &["list", "patch", "watch"]),
+            Permission::new("", &["pods"], &["list"]),
+        ];
+        let rules: Vec<(Vec<String>, Vec<String>, Vec<String>)> = policy_rules(&permissions)
+            .into_iter()
+            .map(|r| (r.api_groups.unwrap(), r.resources.unwrap(), r.verbs))
+            .collect();
+
+        let strings = |s: &[&str]| s.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            rules,
+            vec![
+                (strings(&[""]), strings(&["pods"]), strings(&["list"])),
+                (
+                    strings(&[""]),
+                    strings(&["services"]),
+                    strings(&["list", "patch", "watch"])
+                ),
+                (
+                    strings(&["apps"]),
+                    strings(&["deployments"]),
+                    strings(&["list", "patch", "watch"])
+                ),
+            ]
+        );
+    }
+}
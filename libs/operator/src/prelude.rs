@@ -0,0 +1,67 @@
+//! Items used by most controllers, so out-of-tree controllers built on this crate do not need to
+//! know its module layout: `use echo_operator::prelude::*;`.
+//!
+//! The `kube` and `k8s_openapi` versions of this crate are re-exported at its root.
+pub use crate::controller::{
+    Context, ControllerId, ControllerRegistry, ControllerRunner, State, METRICS_PREFIX,
+};
+pub use crate::crd::echo::{Echo, EchoSpec, EchoStatus};
+pub use crate::echo::condition::ConditionType;
+pub use crate::echo::spec::EchoSpecBuilder;
+pub use crate::error::{Error, ErrorContext, Operation, Result, ResultExt};
+pub use crate::metrics::{ControllerMetrics, Metrics};
+pub use crate::requeue::ReconcileConfig;
+pub use crate::stores::Stores;
+pub use crate::telemetry;
+
+pub use futures::future::BoxFuture;
+pub use futures::StreamExt;
+pub use kube::api::{Api, ListParams, Patch, PatchParams};
+pub use kube::runtime::controller::{Action, Controller};
+pub use kube::runtime::reflector::{self, ObjectRef, Store};
+pub use kube::runtime::{watcher, WatchStreamExt};
+pub use kube::{Client, Resource, ResourceExt};
+pub use prometheus_client::registry::Registry;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use futures::FutureExt;
+    use http::{Request, Response};
+    use kube::client::Body;
+
+    const CONTROLLER_ID: ControllerId = "downstream";
+
+    /// Controller written only with the prelude
+    struct Downstream;
+
+    impl ControllerRunner for Downstream {
+        fn id(&self) -> ControllerId {
+            CONTROLLER_ID
+        }
+
+        fn run(&self, state: State, client: Client) -> BoxFuture<'static, ()> {
+            async move {
+                let ctx = state.to_context(client, CONTROLLER_ID, Stores::default());
+                ctx.metrics.ready_set(1);
+            }
+            .boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_downstream_controller() {
+        let (mock_service, _handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+        let state = State::new(Registry::default(), &[CONTROLLER_ID]);
+        ControllerRegistry::default()
+            .register_runner(Downstream)
+            .run(state.clone(), Client::new(mock_service, "default"))
+            .await;
+
+        assert!(state
+            .metrics()
+            .unwrap()
+            .contains("ready{controller=\"downstream\"} 1"));
+    }
+}
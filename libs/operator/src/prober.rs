@@ -0,0 +1,192 @@
+//! Active probing of the echo Services, e.g. the DNS one, catching echoes whose pods are Ready
+//! while the Service path is broken.
+//!
+//! Every interval, the operator GETs the path in the first port of the Service of each echo and
+//! reports the result in its EndpointHealthy condition and the `probe_duration` metric.
+use crate::controller::{Context, ControllerId, State};
+use crate::crd::echo::Echo;
+use crate::echo::endpoint::ProbeResult;
+use crate::error::{Error, Result};
+use crate::permissions::Permission;
+use crate::stores::Stores;
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::Service;
+use kube::api::{Api, ListParams};
+use kube::client::Client;
+use kube::ResourceExt;
+use opentelemetry::trace::TraceId;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::{self, Duration};
+use tracing::{debug, error, info};
+
+pub const CONTROLLER_ID: ControllerId = "prober";
+
+pub const PERMISSIONS: &[Permission] = &[
+    Permission::new("example.com", &["echoes"], &["list"]),
+    Permission::new("", &["services"], &["list"]),
+];
+
+/// Services of the echoes, named after them
+const SERVICE_SELECTOR: &str =
+    "app.kubernetes.io/name=echo,app.kubernetes.io/managed-by=echo-operator";
+/// Echoes probed at the same time
+const PROBE_CONCURRENCY: usize = 16;
+
+/// What and how often the echo Services are probed
+#[derive(Clone, Debug)]
+pub struct ProbeConfig {
+    /// HTTP path requested to the Services
+    pub path: String,
+    /// Time between probes of every echo
+    pub interval: Duration,
+    /// Time after which a probe fails
+    pub timeout: Duration,
+}
+
+/// URL probed in the cluster DNS name and first port of the Service
+fn probe_url(service: &Service, path: &str) -> Option<String> {
+    let port = service.spec.as_ref()?.ports.as_ref()?.first()?.port;
+    Some(format!(
+        "http://{}.{}.svc:{port}/{}",
+        service.name_any(),
+        service.namespace()?,
+        path.trim_start_matches('/')
+    ))
+}
+
+async fn probe(http: &reqwest::Client, url: &str) -> ProbeResult {
+    match http.get(url).send().await {
+        Ok(response) if response.status().is_success() => {
+            ProbeResult::Healthy(response.status().as_u16())
+        }
+        Ok(response) => ProbeResult::Unhealthy(format!("responded {}", response.status().as_u16())),
+        Err(e) => ProbeResult::Unhealthy(e.without_url().to_string()),
+    }
+}
+
+/// Probe the echo Services periodically until shutdown
+pub async fn run(state: State, client: Client, config: ProbeConfig) {
+    let ctx = state.to_context(client, CONTROLLER_ID, Stores::default());
+    let http = match reqwest::Client::builder().timeout(config.timeout).build() {
+        Ok(http) => http,
+        Err(e) => {
+            error!(msg = "failed to build the probe HTTP client", %e);
+            return;
+        }
+    };
+    let mut ticker = time::interval(config.interval);
+    // safe unwrap: signal handlers can always be registered in the tokio runtime
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    ctx.metrics.ready_set(1);
+    info!(msg = "starting echo prober", path = config.path, interval = ?config.interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {},
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        }
+        if let Err(e) = probe_all(&ctx, &http, &config.path).await {
+            error!(msg = "failed to probe echoes", %e);
+            ctx.metrics.reconcile_failure_set(&e);
+        }
+    }
+}
+
+async fn probe_all(ctx: &Context, http: &reqwest::Client, path: &str) -> Result<()> {
+    let _timer = ctx.metrics.reconcile_count_and_measure(&TraceId::INVALID);
+    let echoes = Api::<Echo>::all(ctx.client.clone())
+        .list(&ListParams::default())
+        .await
+        .map_err(Error::KubeError)?;
+    let urls: HashMap<(String, String), String> = Api::<Service>::all(ctx.client.clone())
+        .list(&ListParams::default().labels(SERVICE_SELECTOR))
+        .await
+        .map_err(Error::KubeError)?
+        .iter()
+        .filter_map(|s| {
+            let url = probe_url(s, path)?;
+            Some(((s.namespace()?, s.name_any()), url))
+        })
+        .collect();
+
+    futures::stream::iter(echoes.items.iter().filter(|e| e.namespace_allowed(ctx)))
+        .for_each_concurrent(PROBE_CONCURRENCY, |echo| {
+            let url = urls.get(&(echo.get_namespace(), echo.name_any()));
+            async move {
+                let reported = match url {
+                    Some(url) => probe_echo(ctx, http, echo, url).await,
+                    None => echo.clear_endpoint_health(ctx).await,
+                };
+                if let Err(e) = reported {
+                    error!(
+                        msg = "failed to report echo probe",
+                        namespace = echo.get_namespace(),
+                        name = echo.name_any(),
+                        %e
+                    );
+                    ctx.metrics.reconcile_failure_set(&e);
+                }
+            }
+        })
+        .await;
+    Ok(())
+}
+
+async fn probe_echo(ctx: &Context, http: &reqwest::Client, echo: &Echo, url: &str) -> Result<()> {
+    let start = Instant::now();
+    let result = probe(http, url).await;
+    ctx.metrics.probe_duration_observe(
+        &echo.get_namespace(),
+        &echo.name_any(),
+        start.elapsed().as_secs_f64(),
+    );
+    debug!(
+        msg = "probed echo",
+        namespace = echo.get_namespace(),
+        name = echo.name_any(),
+        url,
+        ?result
+    );
+    echo.report_endpoint_health(ctx, &result).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::probe_url;
+
+    use k8s_openapi::api::core::v1::{Service, ServicePort, ServiceSpec};
+    use kube::api::ObjectMeta;
+
+    #[test]
+    fn test_probe_url() {
+        let mut service = Service {
+            metadata: ObjectMeta {
+                name: Some("test".to_string()),
+                namespace: Some("default".to_string()),
+                ..ObjectMeta::default()
+            },
+            ..Service::default()
+        };
+        assert_eq!(probe_url(&service, "/healthz"), None);
+
+        service.spec = Some(ServiceSpec {
+            ports: Some(vec![ServicePort {
+                port: 80,
+                ..ServicePort::default()
+            }]),
+            ..ServiceSpec::default()
+        });
+        assert_eq!(
+            probe_url(&service, "/healthz").as_deref(),
+            Some("http://test.default.svc:80/healthz")
+        );
+        assert_eq!(
+            probe_url(&service, "").as_deref(),
+            Some("http://test.default.svc:80/")
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Permissions the echoes may grant to their pods with `spec.rbac.rules`.
+//!
+//! The API server lets the operator grant any permission it holds, e.g. on Secrets cluster-wide,
+//! so without an allowlist anyone able to create an Echo could give its pods the permissions of the
+//! operator. Rules beyond the allowlist are denied by the admission webhook and never applied.
+use crate::error::{Error, Result};
+
+use std::fmt;
+use std::str::FromStr;
+
+use k8s_openapi::api::rbac::v1::PolicyRule;
+
+/// Verb allowed on a resource of an API group, written `<verb>:<resource>[.<group>]`, e.g.
+/// `get:pods`, `get:pods/log` or `list:deployments.apps`. `*` matches every verb, resource or
+/// group, e.g. `*:configmaps` or `get:*.*`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AllowedPermission {
+    pub verb: String,
+    pub resource: String,
+    pub api_group: String,
+}
+
+fn matches(pattern: &str, value: &str) -> bool {
+    pattern == "*" || pattern == value
+}
+
+impl AllowedPermission {
+    fn allows(&self, api_group: &str, resource: &str, verb: &str) -> bool {
+        matches(&self.api_group, api_group)
+            && matches(&self.resource, resource)
+            && matches(&self.verb, verb)
+    }
+}
+
+impl FromStr for AllowedPermission {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::InvalidRbacAllowlist(format!("{s}: {reason}"));
+        let (verb, resource) = s
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| invalid("expected <verb>:<resource>[.<group>]"))?;
+        // resources never contain dots, unlike the API groups
+        let (resource, api_group) = resource.split_once('.').unwrap_or((resource, ""));
+        if verb.is_empty() || resource.is_empty() {
+            return Err(invalid("empty verb or resource"));
+        }
+        Ok(Self {
+            verb: verb.to_string(),
+            resource: resource.to_string(),
+            api_group: api_group.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for AllowedPermission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.verb, self.resource)?;
+        if !self.api_group.is_empty() {
+            write!(f, ".{}", self.api_group)?;
+        }
+        Ok(())
+    }
+}
+
+/// Permissions the RBAC rules of the echoes may grant, none by default
+#[derive(Clone, Debug, Default)]
+pub struct RbacAllowlist {
+    permissions: Vec<AllowedPermission>,
+}
+
+impl RbacAllowlist {
+    pub fn new(permissions: Vec<AllowedPermission>) -> Self {
+        Self { permissions }
+    }
+
+    fn allows(&self, api_group: &str, resource: &str, verb: &str) -> bool {
+        self.permissions
+            .iter()
+            .any(|p| p.allows(api_group, resource, verb))
+    }
+
+    /// Check that every permission granted by the rules is allowed. Rules without API groups
+    /// grant the core group ones.
+    pub fn check(&self, rules: &[PolicyRule]) -> Result<()> {
+        let core_group = [String::new()];
+        let mut denied = Vec::new();
+        for rule in rules {
+            let api_groups = match rule.api_groups.as_deref() {
+                Some(api_groups) if !api_groups.is_empty() => api_groups,
+                _ => &core_group[..],
+            };
+            for api_group in api_groups {
+                for resource in rule.resources.iter().flatten() {
+                    for verb in &rule.verbs {
+                        if !self.allows(api_group, resource, verb) {
+                            denied.push(
+                                AllowedPermission {
+                                    verb: verb.clone(),
+                                    resource: resource.clone(),
+                                    api_group: api_group.clone(),
+                                }
+                                .to_string(),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        if denied.is_empty() {
+            return Ok(());
+        }
+        Err(Error::ForbiddenRbacRules(format!(
+            "permissions not allowed by the operator: {}",
+            denied.join(", ")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AllowedPermission, RbacAllowlist};
+
+    use crate::error::Error;
+
+    use k8s_openapi::api::rbac::v1::PolicyRule;
+
+    fn allowlist(permissions: &[&str]) -> RbacAllowlist {
+        RbacAllowlist::new(permissions.iter().map(|p| p.parse().unwrap()).collect())
+    }
+
+    fn rule(api_group: &str, resource: &str, verbs: &[&str]) -> PolicyRule {
+        PolicyRule {
+            api_groups: Some(vec![api_group.to_string()]),
+            resources: Some(vec![resource.to_string()]),
+            verbs: verbs.iter().map(|v| v.to_string()).collect(),
+            ..PolicyRule::default()
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "list:deployments.apps"
+                .parse::<AllowedPermission>()
+                .unwrap(),
+            AllowedPermission {
+                verb: "list".to_string(),
+                resource: "deployments".to_string(),
+                api_group: "apps".to_string(),
+            }
+        );
+        let permission: AllowedPermission = "get:pods/log".parse().unwrap();
+        assert_eq!(permission.resource, "pods/log");
+        assert_eq!(permission.api_group, "");
+        assert_eq!(permission.to_string(), "get:pods/log");
+        assert!(matches!(
+            "pods".parse::<AllowedPermission>(),
+            Err(Error::InvalidRbacAllowlist(_))
+        ));
+        assert!(matches!(
+            ":pods".parse::<AllowedPermission>(),
+            Err(Error::InvalidRbacAllowlist(_))
+        ));
+    }
+
+    #[test]
+    fn test_default_denies_every_rule() {
+        assert!(RbacAllowlist::default()
+            .check(&[rule("", "pods", &["get"])])
+            .is_err());
+        assert!(RbacAllowlist::default().check(&[]).is_ok());
+    }
+
+    #[test]
+    fn test_check() {
+        let allowlist = allowlist(&["get:pods", "list:pods", "*:configmaps", "get:*.apps"]);
+
+        assert!(allowlist
+            .check(&[rule("", "pods", &["get", "list"])])
+            .is_ok());
+        assert!(allowlist
+            .check(&[rule("", "configmaps", &["delete"])])
+            .is_ok());
+        assert!(allowlist
+            .check(&[rule("apps", "deployments", &["get"])])
+            .is_ok());
+        let error = allowlist
+            .check(&[
+                rule("", "pods", &["get", "delete"]),
+                rule("", "secrets", &["get"]),
+            ])
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "ForbiddenRbacRules: permissions not allowed by the operator: delete:pods, get:secrets"
+        );
+    }
+
+    #[test]
+    fn test_check_wildcard_rules() {
+        let allowlist = allowlist(&["get:pods"]);
+
+        // a wildcard in the rule grants more than the allowed permission
+        assert!(allowlist.check(&[rule("", "pods", &["*"])]).is_err());
+        assert!(allowlist.check(&[rule("", "*", &["get"])]).is_err());
+        assert!(allowlist.check(&[rule("*", "pods", &["get"])]).is_err());
+    }
+
+    #[test]
+    fn test_check_rule_without_api_groups() {
+        let mut rule = rule("", "pods", &["get"]);
+        rule.api_groups = None;
+
+        assert!(allowlist(&["get:pods"]).check(&[rule]).is_ok());
+    }
+}
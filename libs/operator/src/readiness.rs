@@ -0,0 +1,143 @@
+//! Readiness of the operator, served on `/readyz`.
+//!
+//! `/health` only proves the HTTP server answers, so `/readyz` aggregates the checks telling
+//! whether the operator actually manages the Echoes, each reported on its own so a failing probe
+//! shows which one failed.
+use crate::controller::{ControllerId, State};
+use crate::crd::echo::Echo;
+
+use std::collections::BTreeMap;
+
+use kube::api::GroupVersionKind;
+use kube::client::Client;
+use kube::{discovery, Resource};
+use serde::Serialize;
+use tokio::time::Instant;
+use tracing::debug;
+
+/// Result of every readiness check
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Checks {
+    /// The apiserver serves the Echo CRD
+    pub crd_discoverable: bool,
+    /// The echo controller completed the initial list of the Echoes
+    pub stores_synced: bool,
+    /// The replica reconciles, always true as every replica does without leader election
+    pub leader: bool,
+    /// The echo controller made progress recently, so its watches are not stuck
+    pub watchers_fresh: bool,
+    /// Every enabled controller reports itself ready
+    pub controllers_ready: bool,
+}
+
+/// Readiness of the operator, ready only if every check passes
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: Checks,
+    /// `ready` gauge of each enabled controller
+    pub controllers: BTreeMap<ControllerId, bool>,
+}
+
+impl Readiness {
+    fn new(
+        crd_discoverable: bool,
+        echo_controller: Option<(bool, bool)>,
+        controllers: BTreeMap<ControllerId, bool>,
+    ) -> Self {
+        // the echoes are only watched by the echo controller
+        let (stores_synced, watchers_fresh) = echo_controller.unwrap_or((true, true));
+        let checks = Checks {
+            crd_discoverable,
+            stores_synced,
+            leader: true,
+            watchers_fresh,
+            controllers_ready: controllers.values().all(|&ready| ready),
+        };
+        Self {
+            ready: checks.crd_discoverable
+                && checks.stores_synced
+                && checks.leader
+                && checks.watchers_fresh
+                && checks.controllers_ready,
+            checks,
+            controllers,
+        }
+    }
+}
+
+/// Whether the apiserver serves the Echo CRD in the version of the operator
+async fn crd_discoverable(client: Client) -> bool {
+    let gvk = GroupVersionKind::gvk(&Echo::group(&()), &Echo::version(&()), &Echo::kind(&()));
+    match discovery::pinned_kind(&client, &gvk).await {
+        Ok(_) => true,
+        Err(e) => {
+            debug!(msg = "Echo CRD not discoverable", %e);
+            false
+        }
+    }
+}
+
+impl State {
+    /// Run every readiness check
+    pub async fn readiness(&self, client: Client) -> Readiness {
+        let controllers = self.controllers_ready();
+        let heartbeat = self.heartbeat();
+        let echo_controller = controllers
+            .contains_key(crate::echo::controller::CONTROLLER_ID)
+            .then(|| (heartbeat.synced(), heartbeat.alive(Instant::now())));
+        Readiness::new(crd_discoverable(client).await, echo_controller, controllers)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Readiness;
+
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_readiness() {
+        let controllers = BTreeMap::from([("echo", true), ("gc", true)]);
+        let readiness = Readiness::new(true, Some((true, true)), controllers.clone());
+        assert!(readiness.ready);
+        assert!(readiness.checks.leader);
+
+        let readiness = Readiness::new(false, Some((true, true)), controllers.clone());
+        assert!(!readiness.ready);
+        assert!(!readiness.checks.crd_discoverable);
+
+        let readiness = Readiness::new(true, Some((false, true)), controllers.clone());
+        assert!(!readiness.ready);
+        assert!(!readiness.checks.stores_synced);
+
+        let readiness = Readiness::new(true, Some((true, false)), controllers);
+        assert!(!readiness.ready);
+        assert!(!readiness.checks.watchers_fresh);
+
+        let readiness = Readiness::new(true, None, BTreeMap::from([("gc", false)]));
+        assert!(!readiness.ready);
+        assert!(readiness.checks.stores_synced && readiness.checks.watchers_fresh);
+        assert!(!readiness.checks.controllers_ready);
+    }
+
+    #[test]
+    fn test_readiness_serialization() {
+        let readiness = Readiness::new(true, None, BTreeMap::from([("gc", true)]));
+        assert_eq!(
+            serde_json::to_value(readiness).unwrap(),
+            serde_json::json!({
+                "ready": true,
+                "checks": {
+                    "crdDiscoverable": true,
+                    "storesSynced": true,
+                    "leader": true,
+                    "watchersFresh": true,
+                    "controllersReady": true,
+                },
+                "controllers": {"gc": true},
+            })
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//! Push of the controller metrics with the Prometheus remote-write protocol, for environments
+//! where nothing scrapes `/metrics`, e.g. serverless clusters or air-gapped edge sites.
+//!
+//! Every interval, the registry is encoded as for `/metrics` and all its samples are sent, at the
+//! time of the push, in a snappy compressed protobuf `WriteRequest`. Both encodings are small
+//! enough to be written here: the compression only emits literals, which every snappy decoder
+//! accepts.
+use crate::controller::{ControllerId, State};
+use crate::metrics::parse_sample;
+use crate::permissions::Permission;
+
+use std::path::PathBuf;
+
+use chrono::Utc;
+use kube::client::Client;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::{self, Duration};
+use tracing::{debug, error, info};
+
+pub const CONTROLLER_ID: ControllerId = "remotewrite";
+
+pub const PERMISSIONS: &[Permission] = &[];
+
+const REMOTE_WRITE_VERSION: &str = "0.1.0";
+const USER_AGENT: &str = concat!("echo-operator/", env!("CARGO_PKG_VERSION"));
+
+/// Credentials of the remote-write endpoint
+#[derive(Clone, Debug, Default)]
+pub enum RemoteWriteAuth {
+    #[default]
+    None,
+    Basic {
+        username: String,
+        password: String,
+    },
+    /// File with the bearer token, read on every push so rotated tokens are picked up
+    BearerTokenFile(PathBuf),
+}
+
+/// Where and how often the metrics are pushed
+#[derive(Clone, Debug)]
+pub struct RemoteWriteConfig {
+    pub url: String,
+    pub interval: Duration,
+    pub auth: RemoteWriteAuth,
+    /// Labels added to every series, e.g. `cluster=eu-1`
+    pub labels: Vec<(String, String)>,
+}
+
+/// Label added to every series, as `name=value`
+pub fn parse_label(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("{s}: expected <name>=<value>")),
+    }
+}
+
+/// Push the metrics periodically until shutdown
+pub async fn run(state: State, _client: Client, config: RemoteWriteConfig) {
+    let metrics = state.controller_metrics(CONTROLLER_ID);
+    let http = reqwest::Client::new();
+    let mut ticker = time::interval(config.interval);
+    // safe unwrap: signal handlers can always be registered in the tokio runtime
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    metrics.ready_set(1);
+    info!(msg = "starting metrics remote-write", url = config.url, interval = ?config.interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {},
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        }
+        let text = match state.metrics() {
+            Ok(text) => text,
+            Err(e) => {
+                error!(msg = "failed to encode metrics", %e);
+                metrics.reconcile_failure_set(&e);
+                continue;
+            }
+        };
+        let series = series(&text, &config.labels);
+        debug!(msg = "remote-writing metrics", series = series.len());
+        let body = snappy_compress(&write_request(&series, Utc::now().timestamp_millis()));
+        if let Err(e) = push(&http, &config, body).await {
+            error!(msg = "failed to remote-write metrics", url = config.url, %e);
+        }
+    }
+}
+
+async fn push(
+    http: &reqwest::Client,
+    config: &RemoteWriteConfig,
+    body: Vec<u8>,
+) -> Result<(), String> {
+    let mut request = http
+        .post(&config.url)
+        .header("Content-Encoding", "snappy")
+        .header("Content-Type", "application/x-protobuf")
+        .header("User-Agent", USER_AGENT)
+        .header("X-Prometheus-Remote-Write-Version", REMOTE_WRITE_VERSION)
+        .timeout(config.interval)
+        .body(body);
+    request = match &config.auth {
+        RemoteWriteAuth::None => request,
+        RemoteWriteAuth::Basic { username, password } => {
+            request.basic_auth(username, Some(password))
+        }
+        RemoteWriteAuth::BearerTokenFile(path) => {
+            let token = tokio::fs::read_to_string(path)
+                .await
+                .map_err(|e| format!("reading {}: {e}", path.display()))?;
+            request.bearer_auth(token.trim())
+        }
+    };
+    request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Series of the metrics text with their value, the labels sorted by name and including the
+/// metric name as `__name__`
+fn series(text: &str, extra_labels: &[(String, String)]) -> Vec<(Vec<(String, String)>, f64)> {
+    text.lines()
+        .filter(|line| !line.starts_with('#') && !line.is_empty())
+        .filter_map(parse_sample)
+        .map(|(name, labels, value)| {
+            let mut labels: Vec<(String, String)> = labels
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .chain(extra_labels.iter().cloned())
+                .chain([("__name__".to_string(), name.replace('-', "_"))])
+                .collect();
+            labels.sort();
+            (labels, value)
+        })
+        .collect()
+}
+
+/// Protobuf `WriteRequest` of the series, sampled at `timestamp` milliseconds
+fn write_request(series: &[(Vec<(String, String)>, f64)], timestamp: i64) -> Vec<u8> {
+    let mut request = Vec::new();
+    for (labels, value) in series {
+        let mut time_series = Vec::new();
+        for (name, value) in labels {
+            let mut label = Vec::new();
+            put_bytes(&mut label, 1, name.as_bytes());
+            put_bytes(&mut label, 2, value.as_bytes());
+            put_bytes(&mut time_series, 1, &label);
+        }
+        let mut sample = Vec::new();
+        put_varint(&mut sample, 1 << 3 | 1);
+        sample.extend_from_slice(&value.to_le_bytes());
+        put_varint(&mut sample, 2 << 3);
+        put_varint(&mut sample, timestamp as u64);
+        put_bytes(&mut time_series, 2, &sample);
+        put_bytes(&mut request, 1, &time_series);
+    }
+    request
+}
+
+fn put_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// Length delimited field
+fn put_bytes(buffer: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    put_varint(buffer, field << 3 | 2);
+    put_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+/// Snappy block of the data, made of literals of up to 64KiB
+fn snappy_compress(data: &[u8]) -> Vec<u8> {
+    let mut block = Vec::with_capacity(data.len() + data.len() / 65536 * 3 + 8);
+    put_varint(&mut block, data.len() as u64);
+    for literal in data.chunks(65536) {
+        let n = literal.len() - 1;
+        if n < 60 {
+            block.push((n as u8) << 2);
+        } else if n < 256 {
+            block.push(60 << 2);
+            block.push(n as u8);
+        } else {
+            block.push(61 << 2);
+            block.extend_from_slice(&(n as u16).to_le_bytes());
+        }
+        block.extend_from_slice(literal);
+    }
+    block
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_label, put_varint, series, snappy_compress, write_request};
+
+    const METRICS: &str = r#"# HELP echo_operator_reconcile_operations Total number of reconcile operations.
+# TYPE echo_operator_reconcile_operations counter
+echo_operator_reconcile_operations_total{controller="echo"} 3
+# EOF
+"#;
+
+    fn label(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn test_parse_label() {
+        assert_eq!(parse_label("cluster=eu-1"), Ok(label("cluster", "eu-1")));
+        assert_eq!(parse_label("empty="), Ok(label("empty", "")));
+        assert!(parse_label("cluster").is_err());
+        assert!(parse_label("=eu-1").is_err());
+    }
+
+    #[test]
+    fn test_series() {
+        let series = series(METRICS, &[label("cluster", "eu-1")]);
+        assert_eq!(
+            series,
+            vec![(
+                vec![
+                    label("__name__", "echo_operator_reconcile_operations_total"),
+                    label("cluster", "eu-1"),
+                    label("controller", "echo"),
+                ],
+                3.
+            )]
+        );
+    }
+
+    #[test]
+    fn test_write_request() {
+        let request = write_request(&[(vec![label("a", "b")], 1.)], 300);
+        // labels: name "a", value "b"
+        let label = [0x0a, 1, b'a', 0x12, 1, b'b'];
+        // samples: value 1, timestamp 300
+        let sample = [0x09, 0, 0, 0, 0, 0, 0, 0xf0, 0x3f, 0x10, 0xac, 0x02];
+        let mut expected = vec![0x0a, 22, 0x0a, 6];
+        expected.extend_from_slice(&label);
+        expected.extend_from_slice(&[0x12, 12]);
+        expected.extend_from_slice(&sample);
+        assert_eq!(request, expected);
+    }
+
+    #[test]
+    fn test_varint() {
+        let mut buffer = Vec::new();
+        put_varint(&mut buffer, 1);
+        put_varint(&mut buffer, 300);
+        assert_eq!(buffer, vec![0x01, 0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_snappy_compress() {
+        assert_eq!(snappy_compress(b"abc"), vec![3, 2 << 2, b'a', b'b', b'c']);
+
+        let data = vec![7; 100];
+        let block = snappy_compress(&data);
+        assert_eq!(&block[..3], &[100, 60 << 2, 99]);
+        assert_eq!(&block[3..], &data[..]);
+
+        let data = vec![7; 65537];
+        let block = snappy_compress(&data);
+        // length varint, a 64KiB literal and a 1 byte one
+        assert_eq!(&block[..3], &[0x81, 0x80, 0x04]);
+        assert_eq!(&block[3..6], &[61 << 2, 0xff, 0xff]);
+        assert_eq!(&block[6 + 65536..], &[0, 7]);
+    }
+}
@@ -0,0 +1,185 @@
+//! Requeue policy of the reconciliations, shared by the reconcilers and the error policies of every
+//! controller.
+use crate::echo::provenance::fnv1a;
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Mutex;
+
+use futures::stream::{self, Stream};
+use kube::ResourceExt;
+use tokio::time::{self, Duration, Instant};
+
+/// Namespace and name of the reconciled object
+type ObjectKey = (String, String);
+
+/// When the reconciliations are requeued
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReconcileConfig {
+    /// Requeue of successful reconciliations, so drift is corrected without watch events
+    pub success_interval: Duration,
+    /// Requeue of the first failed reconciliation of an object, doubled on every consecutive one
+    pub error_backoff_base: Duration,
+    /// Longest requeue of failed reconciliations
+    pub error_backoff_max: Duration,
+    /// Fraction of the requeue added, different for every object, so objects failing at the same
+    /// time are not retried at the same time
+    pub jitter: f64,
+    /// Interval of the full resyncs, reconciling every object of the controllers at once, e.g.
+    /// for compliance. Disabled when unset
+    pub resync_interval: Option<Duration>,
+}
+
+impl Default for ReconcileConfig {
+    fn default() -> Self {
+        Self {
+            success_interval: Duration::from_secs(5 * 60),
+            error_backoff_base: Duration::from_secs(5 * 60),
+            error_backoff_max: Duration::from_secs(5 * 60),
+            jitter: 0.0,
+            resync_interval: None,
+        }
+    }
+}
+
+impl ReconcileConfig {
+    /// Requeue of a successful reconciliation of the object
+    pub fn success_requeue<K: ResourceExt>(&self, obj: &K) -> Duration {
+        self.jittered(self.success_interval, obj)
+    }
+
+    /// Requeue of the object after the given consecutive failed reconciliations, at least one
+    pub fn error_requeue<K: ResourceExt>(&self, obj: &K, failures: u32) -> Duration {
+        let backoff = self
+            .error_backoff_base
+            .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+            .min(self.error_backoff_max);
+        self.jittered(backoff, obj)
+    }
+
+    /// Triggers of the full resyncs, for `Controller::reconcile_all_on`. It never fires when they
+    /// are disabled
+    pub fn resyncs(&self) -> Pin<Box<dyn Stream<Item = ()> + Send + Sync>> {
+        match self.resync_interval.filter(|i| !i.is_zero()) {
+            Some(interval) => {
+                let ticker = time::interval_at(Instant::now() + interval, interval);
+                Box::pin(stream::unfold(ticker, |mut ticker| async move {
+                    ticker.tick().await;
+                    Some(((), ticker))
+                }))
+            }
+            None => Box::pin(stream::pending()),
+        }
+    }
+
+    /// Requeue increased by the jitter fraction of the object, stable across reconciliations
+    fn jittered<K: ResourceExt>(&self, requeue: Duration, obj: &K) -> Duration {
+        let key = format!("{}/{}", obj.namespace().unwrap_or_default(), obj.name_any());
+        let fraction = fnv1a(key.as_bytes()) as f64 / u64::MAX as f64;
+        requeue.mul_f64(1.0 + self.jitter.clamp(0.0, 1.0) * fraction)
+    }
+}
+
+/// Consecutive failed reconciliations of the objects of a controller
+#[derive(Default)]
+pub struct Failures(Mutex<HashMap<ObjectKey, u32>>);
+
+impl Failures {
+    /// Record a failed reconciliation of the object, returning its consecutive failures
+    pub fn record<K: ResourceExt>(&self, obj: &K) -> u32 {
+        // safe unwrap: the lock is never held while panicking
+        let mut failures = self.0.lock().unwrap();
+        let count = failures
+            .entry((obj.namespace().unwrap_or_default(), obj.name_any()))
+            .or_default();
+        *count = count.saturating_add(1);
+        *count
+    }
+
+    /// Forget the failures of the object after a successful reconciliation
+    pub fn reset<K: ResourceExt>(&self, obj: &K) {
+        // safe unwrap: the lock is never held while panicking
+        self.0
+            .lock()
+            .unwrap()
+            .remove(&(obj.namespace().unwrap_or_default(), obj.name_any()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Failures, ReconcileConfig};
+
+    use crate::crd::echo::Echo;
+
+    use futures::StreamExt;
+    use kube::Resource;
+    use tokio::time::{timeout, Duration, Instant};
+
+    fn config() -> ReconcileConfig {
+        ReconcileConfig {
+            success_interval: Duration::from_secs(300),
+            error_backoff_base: Duration::from_secs(5),
+            error_backoff_max: Duration::from_secs(60),
+            jitter: 0.0,
+            resync_interval: None,
+        }
+    }
+
+    #[test]
+    fn test_error_requeue_backs_off() {
+        let echo = Echo::test(None);
+        let requeues: Vec<u64> = (1..=6)
+            .map(|failures| config().error_requeue(&echo, failures).as_secs())
+            .collect();
+        assert_eq!(requeues, vec![5, 10, 20, 40, 60, 60]);
+        assert_eq!(
+            config().error_requeue(&echo, u32::MAX),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn test_jitter() {
+        let echo = Echo::test(None);
+        let mut other = Echo::test(None);
+        other.meta_mut().name = Some("other".to_string());
+        let config = ReconcileConfig {
+            jitter: 0.5,
+            ..config()
+        };
+
+        let requeue = config.success_requeue(&echo);
+        assert!(requeue >= Duration::from_secs(300) && requeue <= Duration::from_secs(450));
+        assert_eq!(config.success_requeue(&echo), requeue);
+        assert_ne!(config.success_requeue(&other), requeue);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_resyncs() {
+        let resyncing = ReconcileConfig {
+            resync_interval: Some(Duration::from_secs(60)),
+            ..config()
+        };
+        let start = Instant::now();
+        let mut resyncs = resyncing.resyncs();
+        resyncs.next().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(60));
+        resyncs.next().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(120));
+
+        let disabled = timeout(Duration::from_secs(3600), config().resyncs().next()).await;
+        assert!(disabled.is_err());
+    }
+
+    #[test]
+    fn test_failures() {
+        let echo = Echo::test(None);
+        let failures = Failures::default();
+        assert_eq!(failures.record(&echo), 1);
+        assert_eq!(failures.record(&echo), 2);
+
+        failures.reset(&echo);
+        assert_eq!(failures.record(&echo), 1);
+    }
+}
@@ -0,0 +1,154 @@
+//! Startup ramp smoothing the applies of the Echo Deployments after an operator restart, when every
+//! Echo is reconciled at once and their applies can trip the apiserver priority and fairness.
+//!
+//! During the ramp the applies which would not change the cached Deployment are skipped, and the
+//! rest are limited by a token bucket.
+use std::sync::Mutex;
+
+use tokio::time::{self, Duration, Instant};
+
+/// Applies allowed while the operator starts
+#[derive(Clone, Debug, PartialEq)]
+pub struct StartupRampConfig {
+    /// Time since the start during which the applies are limited, zero to disable the ramp
+    pub duration: Duration,
+    /// Applies per second refilling the bucket
+    pub rate: f64,
+    /// Applies allowed at once, the size of the bucket
+    pub burst: u32,
+}
+
+impl Default for StartupRampConfig {
+    fn default() -> Self {
+        Self {
+            duration: Duration::ZERO,
+            rate: 5.0,
+            burst: 10,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Token bucket limiting the applies until the ramp ends
+#[derive(Debug)]
+pub struct StartupRamp {
+    config: StartupRampConfig,
+    started_at: Instant,
+    bucket: Mutex<Bucket>,
+}
+
+impl Default for StartupRamp {
+    fn default() -> Self {
+        Self::new(StartupRampConfig::default())
+    }
+}
+
+impl StartupRamp {
+    pub fn new(config: StartupRampConfig) -> Self {
+        let now = Instant::now();
+        Self {
+            bucket: Mutex::new(Bucket {
+                tokens: f64::from(config.burst),
+                refilled_at: now,
+            }),
+            config,
+            started_at: now,
+        }
+    }
+
+    /// Whether the applies are still limited
+    pub fn active(&self) -> bool {
+        self.started_at.elapsed() < self.config.duration
+    }
+
+    /// Wait until an apply is allowed, right away once the ramp ended
+    pub async fn acquire(&self) {
+        while self.active() {
+            match self.try_acquire(Instant::now()) {
+                Ok(()) => return,
+                // the ramp can end before the token is available
+                Err(wait) => time::sleep(wait.min(self.remaining())).await,
+            }
+        }
+    }
+
+    fn remaining(&self) -> Duration {
+        self.config
+            .duration
+            .saturating_sub(self.started_at.elapsed())
+    }
+
+    /// Take a token, or return the time until the next one
+    fn try_acquire(&self, now: Instant) -> Result<(), Duration> {
+        // safe unwrap: the lock is never held across a panic
+        let mut bucket = self.bucket.lock().unwrap();
+        let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * self.config.rate;
+        bucket.tokens = (bucket.tokens + refill).min(f64::from(self.config.burst.max(1)));
+        bucket.refilled_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if self.config.rate > 0.0 {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.config.rate,
+            ))
+        } else {
+            Err(self.remaining())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{StartupRamp, StartupRampConfig};
+
+    use tokio::time::{Duration, Instant};
+
+    fn ramp() -> StartupRamp {
+        StartupRamp::new(StartupRampConfig {
+            duration: Duration::from_secs(60),
+            rate: 2.0,
+            burst: 2,
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_try_acquire() {
+        let ramp = ramp();
+        let now = Instant::now();
+        assert_eq!(ramp.try_acquire(now), Ok(()));
+        assert_eq!(ramp.try_acquire(now), Ok(()));
+        assert_eq!(ramp.try_acquire(now), Err(Duration::from_millis(500)));
+        assert_eq!(ramp.try_acquire(now + Duration::from_millis(500)), Ok(()));
+        // the bucket never holds more than the burst
+        let later = now + Duration::from_secs(10);
+        assert_eq!(ramp.try_acquire(later), Ok(()));
+        assert_eq!(ramp.try_acquire(later), Ok(()));
+        assert!(ramp.try_acquire(later).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_acquire_waits_for_tokens() {
+        let ramp = ramp();
+        let start = Instant::now();
+        for _ in 0..4 {
+            ramp.acquire().await;
+        }
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_inactive_after_duration() {
+        let ramp = ramp();
+        assert!(ramp.active());
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(!ramp.active());
+
+        assert!(!StartupRamp::default().active());
+    }
+}
@@ -0,0 +1,219 @@
+//! Export of the controller metrics to a StatsD server, e.g. the Datadog agent, for clusters that
+//! cannot scrape the Prometheus endpoint.
+//!
+//! Every interval, the registry is encoded as for `/metrics` and every sample is sent with the
+//! DogStatsD protocol, its labels becoming tags: gauges as gauges, and counters and the sums and
+//! counts of histograms as counters of their increase since the previous export. Histogram buckets
+//! are not exported.
+use crate::controller::{ControllerId, State};
+use crate::metrics::parse_sample;
+use crate::permissions::Permission;
+
+use std::collections::HashMap;
+
+use kube::client::Client;
+use tokio::net::UdpSocket;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::{self, Duration};
+use tracing::{debug, error, info};
+
+pub const CONTROLLER_ID: ControllerId = "statsd";
+
+pub const PERMISSIONS: &[Permission] = &[];
+
+/// Largest datagram sent, fitting in the usual MTU
+const MAX_DATAGRAM: usize = 1432;
+
+/// Where and how often the metrics are sent
+#[derive(Clone, Debug)]
+pub struct StatsdConfig {
+    /// `host:port` of the StatsD server
+    pub address: String,
+    pub interval: Duration,
+    /// Tags added to every metric, e.g. `env:prod`
+    pub tags: Vec<String>,
+}
+
+/// Send the metrics periodically until shutdown
+pub async fn run(state: State, _client: Client, config: StatsdConfig) {
+    let metrics = state.controller_metrics(CONTROLLER_ID);
+    let socket = match UdpSocket::bind("0.0.0.0:0").await {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!(msg = "failed to bind the StatsD socket", %e);
+            return;
+        }
+    };
+    let mut previous = HashMap::new();
+    let mut ticker = time::interval(config.interval);
+    // safe unwrap: signal handlers can always be registered in the tokio runtime
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    metrics.ready_set(1);
+    info!(msg = "starting StatsD exporter", address = config.address, interval = ?config.interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {},
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        }
+        let text = match state.metrics() {
+            Ok(text) => text,
+            Err(e) => {
+                error!(msg = "failed to encode metrics", %e);
+                metrics.reconcile_failure_set(&e);
+                continue;
+            }
+        };
+        let lines = statsd_lines(&text, &mut previous, &config.tags);
+        debug!(msg = "sending metrics to StatsD", metrics = lines.len());
+        for datagram in datagrams(&lines) {
+            if let Err(e) = socket.send_to(datagram.as_bytes(), &config.address).await {
+                error!(msg = "failed to send metrics to StatsD", address = config.address, %e);
+                break;
+            }
+        }
+    }
+}
+
+/// Type of the metric family of the samples, from its `# TYPE` line
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FamilyType {
+    Gauge,
+    Counter,
+    Histogram,
+    Other,
+}
+
+/// DogStatsD lines of the samples of the metrics text, `previous` keeping the last value of the
+/// counters to send their increase
+fn statsd_lines(text: &str, previous: &mut HashMap<String, f64>, tags: &[String]) -> Vec<String> {
+    let mut family_type = FamilyType::Other;
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        if let Some(type_line) = line.strip_prefix("# TYPE ") {
+            family_type = match type_line.rsplit(' ').next() {
+                Some("gauge") => FamilyType::Gauge,
+                Some("counter") => FamilyType::Counter,
+                Some("histogram") => FamilyType::Histogram,
+                _ => FamilyType::Other,
+            };
+            continue;
+        }
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        let Some((name, labels, value)) = parse_sample(line) else {
+            continue;
+        };
+        let (name, counter) = match family_type {
+            FamilyType::Gauge => (name, false),
+            FamilyType::Counter => (name.strip_suffix("_total").unwrap_or(name), true),
+            FamilyType::Histogram if name.ends_with("_sum") || name.ends_with("_count") => {
+                (name, true)
+            }
+            FamilyType::Histogram | FamilyType::Other => continue,
+        };
+        let value = if counter {
+            let series = format!("{name}{labels:?}");
+            let last = previous.insert(series, value).unwrap_or_default();
+            // counters restart from zero when they are recreated
+            let increase = if value >= last { value - last } else { value };
+            if increase == 0. {
+                continue;
+            }
+            format!("{increase}|c")
+        } else {
+            format!("{value}|g")
+        };
+        let tags: Vec<String> = labels
+            .iter()
+            .map(|(k, v)| format!("{k}:{}", sanitize(v)))
+            .chain(tags.iter().cloned())
+            .collect();
+        let mut statsd_line = format!("{}:{value}", name.replace('-', "_"));
+        if !tags.is_empty() {
+            statsd_line.push_str("|#");
+            statsd_line.push_str(&tags.join(","));
+        }
+        lines.push(statsd_line);
+    }
+    lines
+}
+
+/// Tag value without the characters of the DogStatsD syntax
+fn sanitize(value: &str) -> String {
+    value.replace([',', '|', '#', '\n'], "_")
+}
+
+/// Lines joined into datagrams of up to `MAX_DATAGRAM` bytes
+fn datagrams(lines: &[String]) -> Vec<String> {
+    let mut datagrams: Vec<String> = Vec::new();
+    for line in lines {
+        match datagrams.last_mut() {
+            Some(datagram) if datagram.len() + 1 + line.len() <= MAX_DATAGRAM => {
+                datagram.push('\n');
+                datagram.push_str(line);
+            }
+            _ => datagrams.push(line.clone()),
+        }
+    }
+    datagrams
+}
+
+#[cfg(test)]
+mod test {
+    use super::{datagrams, statsd_lines, MAX_DATAGRAM};
+
+    use std::collections::HashMap;
+
+    const METRICS: &str = r#"# HELP echo_operator_ready 1 when the controller is ready to reconcile resources, 0 otherwise.
+# TYPE echo_operator_ready gauge
+echo_operator_ready{controller="echo"} 1
+# HELP echo_operator_reconcile_operations Total number of reconcile operations.
+# TYPE echo_operator_reconcile_operations counter
+echo_operator_reconcile_operations_total{controller="echo"} 3
+# HELP echo_operator_reconcile_duration_seconds Histogram of reconcile operations.
+# TYPE echo_operator_reconcile_duration_seconds histogram
+# UNIT echo_operator_reconcile_duration_seconds seconds
+echo_operator_reconcile_duration_seconds_sum{controller="echo"} 0.5
+echo_operator_reconcile_duration_seconds_count{controller="echo"} 3
+echo_operator_reconcile_duration_seconds_bucket{le="0.1",controller="echo"} 2 # {id="abc"} 0.05
+# EOF
+"#;
+
+    #[test]
+    fn test_statsd_lines() {
+        let mut previous = HashMap::new();
+        let tags = vec!["env:test".to_string()];
+        assert_eq!(
+            statsd_lines(METRICS, &mut previous, &tags),
+            vec![
+                "echo_operator_ready:1|g|#controller:echo,env:test",
+                "echo_operator_reconcile_operations:3|c|#controller:echo,env:test",
+                "echo_operator_reconcile_duration_seconds_sum:0.5|c|#controller:echo,env:test",
+                "echo_operator_reconcile_duration_seconds_count:3|c|#controller:echo,env:test",
+            ]
+        );
+
+        // only the increase of the counters is sent
+        let metrics = METRICS.replace(
+            "_total{controller=\"echo\"} 3",
+            "_total{controller=\"echo\"} 5",
+        );
+        assert_eq!(
+            statsd_lines(&metrics, &mut previous, &[]),
+            vec![
+                "echo_operator_ready:1|g|#controller:echo",
+                "echo_operator_reconcile_operations:2|c|#controller:echo",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_datagrams() {
+        let line = "a".repeat(MAX_DATAGRAM / 2);
+        let lines = vec![line.clone(), "b".to_string(), line.clone()];
+        let datagrams = datagrams(&lines);
+        assert_eq!(datagrams, vec![format!("{line}\nb"), line]);
+    }
+}
@@ -0,0 +1,314 @@
+//! Coalescing of the Echo status patches, so bursts of Deployment events do not patch the status
+//! once per event.
+//!
+//! With a queue, the patches are sent by a writer task instead of the reconciliations, so slow
+//! status writes do not delay them. The queue holds the latest patch of each object, up to its
+//! capacity, and the patches of other objects are dropped while it is full.
+use crate::crd::echo::Echo;
+use crate::error::{Error, Result};
+use crate::metrics::ControllerMetrics;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use kube::api::{Api, Patch, PatchParams};
+use kube::Client;
+use serde_json::Value;
+use tokio::sync::Notify;
+use tokio::time::Duration;
+use tracing::{debug, error, warn};
+
+const FIELD_MANAGER: &str = "echoes.example.com";
+
+/// Namespace and name of the patched Echo
+type ObjectKey = (String, String);
+
+/// Status patches waiting for the end of their window, by object
+#[derive(Default)]
+pub struct StatusBatcher {
+    /// Time a patch waits for newer ones of the same object. Zero patches right away.
+    window: Duration,
+    /// Objects the writer task queue holds. Unset sends the patches from the reconciliations.
+    queue_capacity: Option<usize>,
+    pending: Mutex<HashMap<ObjectKey, Value>>,
+    /// Wakes the writer task up when a patch is queued
+    queued: Notify,
+    writer_started: AtomicBool,
+}
+
+impl StatusBatcher {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            queue_capacity: None,
+            pending: Mutex::default(),
+            queued: Notify::new(),
+            writer_started: AtomicBool::new(false),
+        }
+    }
+
+    /// Send the patches from a writer task, queueing up to `capacity` objects
+    pub fn with_queue(mut self, capacity: Option<usize>) -> Self {
+        self.queue_capacity = capacity;
+        self
+    }
+
+    /// Apply the status patch after the window, replacing the pending one of the same object
+    pub(crate) async fn patch(
+        self: &Arc<Self>,
+        client: &Client,
+        metrics: &Arc<ControllerMetrics>,
+        namespace: &str,
+        name: &str,
+        status_patch: Value,
+    ) -> Result<()> {
+        let key = (namespace.to_owned(), name.to_owned());
+        if let Some(capacity) = self.queue_capacity {
+            self.enqueue(client, metrics, key, status_patch, capacity);
+            return Ok(());
+        }
+        if self.window.is_zero() {
+            return patch_status(client, namespace, name, status_patch).await;
+        }
+        let replaced = {
+            // safe unwrap: the lock is never held across a panic
+            let mut pending = self.pending.lock().unwrap();
+            let replaced = pending.insert(key.clone(), status_patch).is_some();
+            metrics.status_queue_depth_set(pending.len());
+            replaced
+        };
+        if replaced {
+            debug!(msg = "coalesced Echo status patch");
+            metrics.status_updates_coalesced_inc();
+            return Ok(());
+        }
+        let batcher = self.clone();
+        let client = client.clone();
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(batcher.window).await;
+            batcher.write(&client, &metrics, &key).await;
+        });
+        Ok(())
+    }
+
+    /// Queue the status patch for the writer task, replacing the pending one of the same object
+    fn enqueue(
+        self: &Arc<Self>,
+        client: &Client,
+        metrics: &Arc<ControllerMetrics>,
+        key: ObjectKey,
+        status_patch: Value,
+        capacity: usize,
+    ) {
+        {
+            // safe unwrap: the lock is never held across a panic
+            let mut pending = self.pending.lock().unwrap();
+            if !pending.contains_key(&key) && pending.len() >= capacity {
+                let (namespace, name) = key;
+                warn!(msg = "dropped Echo status patch, status queue is full", %namespace, %name);
+                metrics.status_updates_dropped_inc();
+                return;
+            }
+            if pending.insert(key, status_patch).is_some() {
+                debug!(msg = "coalesced Echo status patch");
+                metrics.status_updates_coalesced_inc();
+            }
+            metrics.status_queue_depth_set(pending.len());
+        }
+        if !self.writer_started.swap(true, Ordering::SeqCst) {
+            let batcher = self.clone();
+            let client = client.clone();
+            let metrics = metrics.clone();
+            tokio::spawn(async move { batcher.run_writer(&client, &metrics).await });
+        }
+        self.queued.notify_one();
+    }
+
+    /// Send the queued patches, waiting the window for newer ones after being woken up
+    async fn run_writer(&self, client: &Client, metrics: &ControllerMetrics) {
+        loop {
+            self.queued.notified().await;
+            if !self.window.is_zero() {
+                tokio::time::sleep(self.window).await;
+            }
+            loop {
+                // safe unwrap: the lock is never held across a panic
+                let Some(key) = self.pending.lock().unwrap().keys().next().cloned() else {
+                    break;
+                };
+                self.write(client, metrics, &key).await;
+            }
+        }
+    }
+
+    /// Apply every pending status patch right away, e.g. before shutting down
+    pub(crate) async fn flush(&self, client: &Client, metrics: &ControllerMetrics) {
+        // safe unwrap: the lock is never held across a panic
+        let keys: Vec<ObjectKey> = self.pending.lock().unwrap().keys().cloned().collect();
+        debug!(msg = "flushing Echo status patches", pending = keys.len());
+        for key in keys {
+            self.write(client, metrics, &key).await;
+        }
+    }
+
+    async fn write(&self, client: &Client, metrics: &ControllerMetrics, key: &ObjectKey) {
+        let status_patch = {
+            // safe unwrap: the lock is never held across a panic
+            let mut pending = self.pending.lock().unwrap();
+            let Some(status_patch) = pending.remove(key) else {
+                // already flushed
+                return;
+            };
+            metrics.status_queue_depth_set(pending.len());
+            status_patch
+        };
+        let (namespace, name) = key;
+        if let Err(e) = patch_status(client, namespace, name, status_patch).await {
+            error!(msg = "failed to patch Echo status", %namespace, %name, %e);
+            metrics.status_update_errors_inc();
+        }
+    }
+}
+
+async fn patch_status(client: &Client, namespace: &str, name: &str, status: Value) -> Result<()> {
+    let patch = PatchParams::apply(FIELD_MANAGER).force();
+    Api::<Echo>::namespaced(client.clone(), namespace)
+        .patch_status(name, &patch, &Patch::Apply(status))
+        .await
+        .map_err(Error::KubeError)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::StatusBatcher;
+
+    use crate::crd::echo::Echo;
+    use crate::metrics::{ControllerLabels, ControllerMetrics};
+    use crate::test_utils::ApiServerHandle;
+
+    use std::sync::Arc;
+
+    use http::{Request, Response};
+    use http_body_util::BodyExt;
+    use kube::client::Body;
+    use kube::Client;
+    use serde_json::{json, Value};
+    use tokio::time::Duration;
+
+    fn client() -> (Client, ApiServerHandle) {
+        let (mock_service, handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+        (Client::new(mock_service, "default"), handle)
+    }
+
+    fn status_patch(replicas: i32) -> Value {
+        json!({"apiVersion": "example.com/v1", "kind": "Echo", "status": {"replicas": replicas}})
+    }
+
+    /// Answer the next status patch, returning its body
+    async fn next_status_patch(handle: &mut ApiServerHandle) -> Value {
+        let (request, send) = handle.next_request().await.expect("service not called");
+        assert_eq!(request.method(), http::Method::PATCH);
+        assert_eq!(
+            request.uri().path(),
+            "/apis/example.com/v1/namespaces/default/echoes/test/status"
+        );
+        let body = request.into_body().collect().await.unwrap().to_bytes();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        let response = serde_json::to_vec(&Echo::test(None)).unwrap();
+        send.send_response(Response::builder().body(Body::from(response)).unwrap());
+        body
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_patch_coalesces_within_window() {
+        let (client, mut handle) = client();
+        let metrics = Arc::new(ControllerMetrics::new("echo"));
+        let batcher = Arc::new(StatusBatcher::new(Duration::from_secs(1)));
+
+        for replicas in 1..=3 {
+            batcher
+                .patch(&client, &metrics, "default", "test", status_patch(replicas))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(next_status_patch(&mut handle).await, status_patch(3));
+        let coalesced = metrics
+            .status_updates_coalesced
+            .get_or_create(&ControllerLabels {
+                controller: "echo".to_string(),
+            })
+            .get();
+        assert_eq!(coalesced, 2);
+    }
+
+    #[tokio::test]
+    async fn test_queue_sends_from_writer() {
+        let (client, mut handle) = client();
+        let metrics = Arc::new(ControllerMetrics::new("echo"));
+        let batcher = Arc::new(StatusBatcher::new(Duration::ZERO).with_queue(Some(10)));
+
+        // the patch returns without waiting for the API server
+        batcher
+            .patch(&client, &metrics, "default", "test", status_patch(1))
+            .await
+            .unwrap();
+
+        assert_eq!(next_status_patch(&mut handle).await, status_patch(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queue_drops_when_full() {
+        let (client, mut handle) = client();
+        let metrics = Arc::new(ControllerMetrics::new("echo"));
+        let batcher = Arc::new(StatusBatcher::new(Duration::from_secs(1)).with_queue(Some(1)));
+        let labels = ControllerLabels {
+            controller: "echo".to_string(),
+        };
+
+        for (name, replicas) in [("test", 1), ("other", 1), ("test", 2)] {
+            batcher
+                .patch(&client, &metrics, "default", name, status_patch(replicas))
+                .await
+                .unwrap();
+        }
+        assert_eq!(metrics.status_queue_depth.get_or_create(&labels).get(), 1);
+        assert_eq!(
+            metrics.status_updates_dropped.get_or_create(&labels).get(),
+            1
+        );
+        assert_eq!(
+            metrics
+                .status_updates_coalesced
+                .get_or_create(&labels)
+                .get(),
+            1
+        );
+
+        assert_eq!(next_status_patch(&mut handle).await, status_patch(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_flush_patches_pending() {
+        let (client, mut handle) = client();
+        let metrics = Arc::new(ControllerMetrics::new("echo"));
+        let batcher = Arc::new(StatusBatcher::new(Duration::from_secs(60)));
+        batcher
+            .patch(&client, &metrics, "default", "test", status_patch(1))
+            .await
+            .unwrap();
+
+        let flush = {
+            let batcher = batcher.clone();
+            let client = client.clone();
+            tokio::spawn(async move { batcher.flush(&client, &metrics).await })
+        };
+
+        assert_eq!(next_status_patch(&mut handle).await, status_patch(1));
+        flush.await.unwrap();
+        assert!(batcher.pending.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,161 @@
+//! Reflector stores of a controller, indexed by the type of their objects, so a controller can
+//! cache resources of different kinds, e.g. `stores.get::<Deployment>()`.
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use kube::runtime::reflector::store::WriterDropped;
+use kube::runtime::reflector::{Lookup, Store};
+use kube::Resource;
+
+/// Annotations holding a copy of the object, e.g. the one of `kubectl apply`
+const BULKY_ANNOTATIONS: [&str; 1] = ["kubectl.kubernetes.io/last-applied-configuration"];
+
+/// Remove the fields the controllers never read before the object enters a store, as they can be
+/// larger than the rest of it: the managed fields and the annotations copying the object.
+///
+/// Not for the Echoes of the echo controller, whose managed fields set their reconcile priority.
+pub fn prune<K: Resource>(obj: &mut K) {
+    let metadata = obj.meta_mut();
+    metadata.managed_fields = None;
+    if let Some(annotations) = metadata.annotations.as_mut() {
+        BULKY_ANNOTATIONS.iter().for_each(|a| {
+            annotations.remove(*a);
+        });
+    }
+}
+
+/// Store of any object type
+trait AnyStore: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+
+    fn wait_until_ready(&self) -> BoxFuture<'_, Result<(), WriterDropped>>;
+}
+
+impl<K> AnyStore for Store<K>
+where
+    K: 'static + Lookup + Clone + Send + Sync,
+    K::DynamicType: 'static + Hash + Eq + Clone + Send + Sync,
+{
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn wait_until_ready(&self) -> BoxFuture<'_, Result<(), WriterDropped>> {
+        Store::wait_until_ready(self).boxed()
+    }
+}
+
+/// Reflector stores, at most one per object type
+#[derive(Default)]
+pub struct Stores(HashMap<TypeId, Box<dyn AnyStore>>);
+
+impl Stores {
+    /// Add the store of the objects of type K, replacing the previous one
+    pub fn with<K>(mut self, store: Store<K>) -> Self
+    where
+        K: 'static + Lookup + Clone + Send + Sync,
+        K::DynamicType: 'static + Hash + Eq + Clone + Send + Sync,
+    {
+        self.0.insert(TypeId::of::<K>(), Box::new(store));
+        self
+    }
+
+    /// Store of the objects of type K, if the controller caches them
+    pub fn get<K>(&self) -> Option<&Store<K>>
+    where
+        K: 'static + Lookup,
+        K::DynamicType: 'static + Hash + Eq,
+    {
+        self.0
+            .get(&TypeId::of::<K>())
+            .and_then(|s| s.as_any().downcast_ref())
+    }
+
+    /// Wait until every store completed its initial list
+    pub async fn wait_until_ready(&self) -> Result<(), WriterDropped> {
+        for store in self.0.values() {
+            store.wait_until_ready().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{prune, Stores};
+
+    use crate::crd::echo::Echo;
+
+    use k8s_openapi::api::apps::v1::Deployment;
+    use k8s_openapi::api::core::v1::Pod;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ManagedFieldsEntry;
+    use kube::runtime::reflector::store::Writer;
+    use kube::runtime::reflector::ObjectRef;
+    use kube::runtime::watcher;
+    use kube::ResourceExt;
+
+    #[test]
+    fn test_prune() {
+        let mut deployment = Deployment::default();
+        deployment.metadata.managed_fields = Some(vec![ManagedFieldsEntry {
+            manager: Some("echo-operator".to_string()),
+            ..ManagedFieldsEntry::default()
+        }]);
+        deployment.annotations_mut().extend([
+            (
+                "kubectl.kubernetes.io/last-applied-configuration".to_string(),
+                "{}".to_string(),
+            ),
+            ("team".to_string(), "platform".to_string()),
+        ]);
+
+        prune(&mut deployment);
+        assert_eq!(deployment.metadata.managed_fields, None);
+        assert_eq!(
+            deployment.annotations().keys().collect::<Vec<_>>(),
+            vec!["team"]
+        );
+    }
+
+    #[test]
+    fn test_get_by_type() {
+        let mut writer = Writer::<Echo>::default();
+        let echo = Echo::test(None);
+        writer.apply_watcher_event(&watcher::Event::Apply(echo.clone()));
+        let stores = Stores::default()
+            .with(writer.as_reader())
+            .with(Writer::<Deployment>::default().as_reader());
+
+        let echo_ref = ObjectRef::from_obj(&echo);
+        assert_eq!(
+            stores
+                .get::<Echo>()
+                .unwrap()
+                .get(&echo_ref)
+                .map(|e| e.name_any()),
+            Some(echo.name_any())
+        );
+        assert!(stores.get::<Deployment>().unwrap().state().is_empty());
+        assert!(stores.get::<Pod>().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_ready() {
+        let mut echo_writer = Writer::<Echo>::default();
+        let mut deployment_writer = Writer::<Deployment>::default();
+        let stores = Stores::default()
+            .with(echo_writer.as_reader())
+            .with(deployment_writer.as_reader());
+
+        let wait = tokio::spawn(async move { stores.wait_until_ready().await });
+        echo_writer.apply_watcher_event(&watcher::Event::InitDone);
+        tokio::task::yield_now().await;
+        assert!(!wait.is_finished());
+
+        deployment_writer.apply_watcher_event(&watcher::Event::InitDone);
+        assert!(wait.await.unwrap().is_ok());
+    }
+}
@@ -1,13 +1,21 @@
+//! Logging and, with the `otel` feature, OpenTelemetry tracing. Without the feature the tracing
+//! URL is ignored and no span has a trace id.
+#[cfg(feature = "otel")]
 use std::time::Duration;
 
-use opentelemetry::trace::{TraceError, TraceId, TracerProvider};
-use opentelemetry::KeyValue;
+use opentelemetry::trace::{TraceError, TraceId};
+#[cfg(feature = "otel")]
+use opentelemetry::{trace::TracerProvider, KeyValue};
+#[cfg(feature = "otel")]
 use opentelemetry_otlp::WithExportConfig;
-use opentelemetry_sdk::trace::{self, RandomIdGenerator, Sampler};
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::trace::{self, RandomIdGenerator, Sampler, Tracer};
+#[cfg(feature = "otel")]
 use opentelemetry_sdk::Resource;
 use serde::Serialize;
 use thiserror::Error;
 use tracing::dispatcher::SetGlobalDefaultError;
+#[cfg(feature = "otel")]
 use tracing_opentelemetry::OpenTelemetryLayer;
 use tracing_subscriber::{prelude::*, EnvFilter, Registry};
 
@@ -36,6 +44,7 @@ pub enum Error {
 /// let trace_id = get_trace_id();
 /// println!("Current trace ID: {:?}", trace_id);
 /// ```
+#[cfg(feature = "otel")]
 pub fn get_trace_id() -> TraceId {
     use opentelemetry::trace::TraceContextExt as _; // opentelemetry::Context -> opentelemetry::trace::Span
     use tracing_opentelemetry::OpenTelemetrySpanExt as _; // tracing::Span to opentelemetry::Context
@@ -47,6 +56,12 @@ pub fn get_trace_id() -> TraceId {
         .trace_id()
 }
 
+/// Invalid trace id, as spans are never traced without the `otel` feature
+#[cfg(not(feature = "otel"))]
+pub fn get_trace_id() -> TraceId {
+    TraceId::INVALID
+}
+
 /// Specifies the format of log output, either JSON or plain-text.
 ///
 /// This enum derives `clap::ValueEnum` for use in command-line argument parsing,
@@ -102,6 +117,9 @@ pub enum LogFormat {
 ///
 /// If the tracing subsystem is successfully configured, the function returns
 /// `Ok(())`, otherwise an appropriate error is returned.
+///
+/// Without the `otel` feature the tracing URL is ignored, with a warning.
+#[cfg_attr(not(feature = "otel"), allow(unused_variables))]
 pub async fn init(
     log_filter: &str,
     log_format: LogFormat,
@@ -117,42 +135,60 @@ pub async fn init(
 
     let collector = Registry::default().with(logger).with(filter);
 
-    if let Some(url) = tracing_url {
-        let provider = opentelemetry_otlp::new_pipeline()
-            .tracing()
-            .with_exporter(
-                opentelemetry_otlp::new_exporter()
-                    .tonic()
-                    .with_endpoint(url)
-                    .with_timeout(Duration::from_secs(3)),
-            )
-            .with_trace_config(
-                trace::Config::default()
-                    .with_sampler(Sampler::TraceIdRatioBased(trace_ratio))
-                    .with_id_generator(RandomIdGenerator::default())
-                    .with_max_events_per_span(64)
-                    .with_max_attributes_per_span(16)
-                    .with_max_events_per_span(16)
-                    .with_resource(Resource::new(vec![KeyValue::new(
-                        "service.name",
-                        "echo-operator",
-                    )])),
-            )
-            .install_batch(opentelemetry_sdk::runtime::Tokio)
-            .map_err(Error::TraceError)?;
-        let tracer = provider
-            .tracer_builder("opentelemetry-otlp")
-            .with_version(env!("CARGO_PKG_VERSION"))
-            .build();
-
-        let telemetry = OpenTelemetryLayer::new(tracer);
-        tracing::subscriber::set_global_default(collector.with(telemetry))
-            .map_err(Error::SetGlobalDefaultError)
-    } else {
-        tracing::subscriber::set_global_default(collector).map_err(Error::SetGlobalDefaultError)
+    match tracing_url {
+        #[cfg(feature = "otel")]
+        Some(url) => {
+            let telemetry = OpenTelemetryLayer::new(tracer(url, trace_ratio)?);
+            tracing::subscriber::set_global_default(collector.with(telemetry))
+                .map_err(Error::SetGlobalDefaultError)
+        }
+        #[cfg(not(feature = "otel"))]
+        Some(url) => {
+            tracing::subscriber::set_global_default(collector)
+                .map_err(Error::SetGlobalDefaultError)?;
+            tracing::warn!(
+                msg = "ignoring tracing URL, built without the otel feature",
+                url
+            );
+            Ok(())
+        }
+        None => {
+            tracing::subscriber::set_global_default(collector).map_err(Error::SetGlobalDefaultError)
+        }
     }
 }
 
+/// Tracer exporting the spans with OTLP over gRPC
+#[cfg(feature = "otel")]
+fn tracer(url: &str, trace_ratio: f64) -> Result<Tracer, Error> {
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(url)
+                .with_timeout(Duration::from_secs(3)),
+        )
+        .with_trace_config(
+            trace::Config::default()
+                .with_sampler(Sampler::TraceIdRatioBased(trace_ratio))
+                .with_id_generator(RandomIdGenerator::default())
+                .with_max_events_per_span(64)
+                .with_max_attributes_per_span(16)
+                .with_max_events_per_span(16)
+                .with_resource(Resource::new(vec![KeyValue::new(
+                    "service.name",
+                    "echo-operator",
+                )])),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .map_err(Error::TraceError)?;
+    Ok(provider
+        .tracer_builder("opentelemetry-otlp")
+        .with_version(env!("CARGO_PKG_VERSION"))
+        .build())
+}
+
 #[cfg(all(test, feature = "integration-test"))]
 mod test {
     // This test only works when telemetry is initialized fully
@@ -0,0 +1,300 @@
+//! Mock API server machinery to unit test controllers without a Kubernetes cluster.
+//!
+//! Enabled in this crate tests and exported behind the `test-utils` feature so controllers built
+//! on top of this crate can reuse the scenario/verifier pattern.
+pub mod fake_apiserver;
+
+use crate::clock::MockClock;
+use crate::controller::Context;
+use crate::crd::echo::{Echo, EchoSpec, EchoStatus};
+use crate::error::Result;
+use crate::requeue::ReconcileConfig;
+use crate::stores::Stores;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use http::{Request, Response};
+use k8s_openapi::api::apps::v1::Deployment;
+use kube::runtime::reflector::store::Writer;
+use kube::{client::Body, Client, Resource, ResourceExt};
+use serde_json::json;
+use tower_test::mock::SendResponse;
+
+impl Echo {
+    /// A normal test echo with a given status
+    pub fn test(status: Option<EchoStatus>) -> Self {
+        let mut e = Echo::new(
+            "test",
+            EchoSpec {
+                replicas: 1,
+                ..EchoSpec::default()
+            },
+        );
+        e.meta_mut().namespace = Some("default".into());
+        e.status = status;
+        e
+    }
+
+    /// Modify echo replicas
+    pub fn change_replicas(mut self, replicas: i32) -> Self {
+        self.spec.replicas = replicas;
+        self
+    }
+
+    /// Modify echo to set a deletion timestamp
+    pub fn needs_delete(mut self) -> Self {
+        use chrono::prelude::{DateTime, TimeZone, Utc};
+        let now: DateTime<Utc> = Utc.with_ymd_and_hms(2017, 4, 2, 12, 50, 32).unwrap();
+        use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+        self.meta_mut().deletion_timestamp = Some(Time(now));
+        self
+    }
+
+    /// Modify a echo to have an expected status
+    pub fn with_status(mut self, status: EchoStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+}
+
+// We wrap tower_test::mock::Handle
+pub type ApiServerHandle = tower_test::mock::Handle<Request<Body>, Response<Body>>;
+pub struct ApiServerVerifier(pub ApiServerHandle);
+
+/// Scenarios we test for in ApiServerVerifier
+pub enum Scenario {
+    /// objects changes will cause a patch
+    EchoPatch(Echo),
+    /// deployment patch is answered with an injected failure
+    EchoPatchFault(Echo, Fault),
+    /// deployment patch is answered after a delay
+    EchoPatchDelayed(Echo, Duration),
+    /// deployment patch is rejected as unprocessable, so it is deleted and patched again
+    EchoRecreate(Echo),
+}
+
+/// Failures injected by the mock apiserver
+pub enum Fault {
+    /// Kubernetes `Status` error with the given code and reason, e.g. `(409, "Conflict")`
+    Status(u16, &'static str),
+    /// Successful response with a body which is not valid json
+    MalformedBody,
+    /// Request is never answered, as it happens on timeouts or broken connections
+    NoResponse,
+}
+
+/// Kubernetes `Status` failure response
+pub fn status_response(code: u16, reason: &str) -> Response<Body> {
+    let status = json!({
+        "apiVersion": "v1",
+        "kind": "Status",
+        "metadata": {},
+        "status": "Failure",
+        "message": format!("injected {reason} error"),
+        "reason": reason,
+        "code": code,
+    });
+    Response::builder()
+        .status(code)
+        .body(Body::from(serde_json::to_vec(&status).unwrap()))
+        .unwrap()
+}
+
+pub async fn timeout_after_1s(handle: tokio::task::JoinHandle<()>) {
+    tokio::time::timeout(std::time::Duration::from_secs(1), handle)
+        .await
+        .expect("timeout on mock apiserver")
+        .expect("scenario succeeded")
+}
+
+impl ApiServerVerifier {
+    /// Tests only get to run specific scenarios that has matching handlers
+    ///
+    /// This setup makes it easy to handle multiple requests by chaining handlers together.
+    ///
+    /// NB: If the controller is making more calls than we are handling in the scenario,
+    /// you then typically see a `KubeError(Service(Closed(())))` from the reconciler.
+    ///
+    /// You should await the `JoinHandle` (with a timeout) from this function to ensure that the
+    /// scenario runs to completion (i.e. all expected calls were responded to),
+    /// using the timeout to catch missing api calls to Kubernetes.
+    pub fn run(self, scenario: Scenario) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            // every reconciliation of a new deployment checks the quotas first
+            let this = self.handle_echo_quota_list().await;
+            // moving self => one scenario per test
+            match scenario {
+                Scenario::EchoPatch(echo) => this.handle_echo_patch(echo.clone()).await,
+                Scenario::EchoPatchFault(echo, fault) => {
+                    this.handle_echo_patch_fault(echo, fault).await
+                }
+                Scenario::EchoPatchDelayed(echo, delay) => {
+                    this.handle_echo_patch_delayed(echo, delay).await
+                }
+                Scenario::EchoRecreate(echo) => this.handle_echo_recreate(echo).await,
+            }
+            .expect("scenario completed without errors");
+        })
+    }
+
+    async fn handle_echo_quota_list(mut self) -> Self {
+        let (request, send) = self.0.next_request().await.expect("service not called");
+        assert_eq!(request.method(), http::Method::GET);
+        assert_eq!(request.uri().path(), "/apis/example.com/v1/echoquotas");
+        let response = json!({
+            "apiVersion": "example.com/v1",
+            "kind": "EchoQuotaList",
+            "metadata": {"resourceVersion": "1"},
+            "items": [],
+        });
+        send.send_response(
+            Response::builder()
+                .body(Body::from(serde_json::to_vec(&response).unwrap()))
+                .unwrap(),
+        );
+        self
+    }
+
+    async fn next_deployment_patch(
+        &mut self,
+        echo: &Echo,
+    ) -> (Request<Body>, SendResponse<Response<Body>>) {
+        let (request, send) = self.0.next_request().await.expect("service not called");
+        assert_eq!(request.method(), http::Method::PATCH);
+        assert_eq!(
+            request.uri().to_string(),
+            format!(
+                "/apis/apps/v1/namespaces/default/deployments/{}?&force=true&fieldManager=echoes.example.com",
+                echo.name_any()
+            )
+        );
+        (request, send)
+    }
+
+    async fn handle_echo_patch(self, echo: Echo) -> Result<Self> {
+        self.handle_echo_patch_delayed(echo, Duration::ZERO).await
+    }
+
+    async fn handle_echo_patch_delayed(mut self, echo: Echo, delay: Duration) -> Result<Self> {
+        let (request, send) = self.next_deployment_patch(&echo).await;
+
+        let req_body = request.into_body().collect_bytes().await.unwrap();
+        let json: serde_json::Value =
+            serde_json::from_slice(&req_body).expect("patch object is json");
+        let deployment: Deployment = serde_json::from_value(json).expect("valid deployment");
+        assert_eq!(
+            deployment.clone().spec.unwrap().replicas.unwrap(),
+            echo.spec.replicas,
+            "deployment replicas equal to echo spec replicas"
+        );
+        let response = serde_json::to_vec(&deployment).unwrap();
+        tokio::time::sleep(delay).await;
+        // pass through echo "patch accepted"
+        send.send_response(Response::builder().body(Body::from(response)).unwrap());
+        Ok(self)
+    }
+
+    async fn handle_echo_patch_fault(mut self, echo: Echo, fault: Fault) -> Result<Self> {
+        let (_request, send) = self.next_deployment_patch(&echo).await;
+        match fault {
+            Fault::Status(code, reason) => send.send_response(status_response(code, reason)),
+            Fault::MalformedBody => send.send_response(
+                Response::builder()
+                    .body(Body::from(b"{\"kind\": \"Deploy".to_vec()))
+                    .unwrap(),
+            ),
+            // dropping the sender closes the request without response
+            Fault::NoResponse => drop(send),
+        }
+        self.handle_last_error_patch(&echo).await
+    }
+
+    /// Failed reconciliations are recorded in the status, with the trace id when traced
+    async fn handle_last_error_patch(mut self, echo: &Echo) -> Result<Self> {
+        let (request, send) = self.0.next_request().await.expect("service not called");
+        assert_eq!(request.method(), http::Method::PATCH);
+        assert_eq!(
+            request.uri().path(),
+            format!(
+                "/apis/example.com/v1/namespaces/default/echoes/{}/status",
+                echo.name_any()
+            )
+        );
+        let req_body = request.into_body().collect_bytes().await.unwrap();
+        let json: serde_json::Value =
+            serde_json::from_slice(&req_body).expect("patch object is json");
+        assert!(json["status"]["lastError"]["message"].is_string());
+        send.send_response(
+            Response::builder()
+                .body(Body::from(serde_json::to_vec(echo).unwrap()))
+                .unwrap(),
+        );
+        Ok(self)
+    }
+
+    async fn handle_echo_recreate(mut self, echo: Echo) -> Result<Self> {
+        let (_request, send) = self.next_deployment_patch(&echo).await;
+        send.send_response(status_response(422, "Invalid"));
+
+        let (request, send) = self.0.next_request().await.expect("service not called");
+        assert_eq!(request.method(), http::Method::DELETE);
+        assert_eq!(
+            request.uri().path(),
+            format!(
+                "/apis/apps/v1/namespaces/default/deployments/{}",
+                echo.name_any()
+            )
+        );
+        let response = json!({
+            "apiVersion": "v1",
+            "kind": "Status",
+            "metadata": {},
+            "status": "Success",
+        });
+        send.send_response(
+            Response::builder()
+                .body(Body::from(serde_json::to_vec(&response).unwrap()))
+                .unwrap(),
+        );
+
+        self.handle_echo_patch(echo).await
+    }
+}
+
+/// Time returned by the mock clock of the test context
+pub fn test_time() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()
+}
+
+pub fn get_test_context() -> (Arc<Context>, ApiServerVerifier) {
+    get_test_context_with_clock(MockClock::new(test_time()))
+}
+
+pub fn get_test_context_with_clock(clock: MockClock) -> (Arc<Context>, ApiServerVerifier) {
+    let (mock_service, handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+    let mock_client = Client::new(mock_service, "default");
+    let stores = Stores::default().with(Writer::<Deployment>::default().as_reader());
+    let ctx = Context {
+        client: mock_client,
+        metrics: Arc::default(),
+        stores: Arc::new(stores),
+        clock: Arc::new(clock),
+        notifier: Arc::default(),
+        auditor: Arc::default(),
+        error_reporter: Arc::default(),
+        hooks: Arc::default(),
+        status_batcher: Arc::default(),
+        maintenance: Arc::default(),
+        cluster_defaults: Arc::default(),
+        registry_credentials_secret: None,
+        namespace_filter: Arc::default(),
+        rbac_allowlist: Arc::default(),
+        slow_reconcile_threshold: None,
+        reconcile_config: ReconcileConfig::default(),
+        startup_ramp: Arc::default(),
+        failures: Arc::default(),
+    };
+    (Arc::new(ctx), ApiServerVerifier(handle))
+}
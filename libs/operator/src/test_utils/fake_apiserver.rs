@@ -0,0 +1,602 @@
+//! In-process fake apiserver serving list and watch streams.
+//!
+//! Unlike [`super::ApiServerVerifier`], which answers individual requests, it keeps objects in
+//! memory and streams watch events, so full controllers (reflectors, watch resumption, bookmarks
+//! and `reconcile_all` storms) can run against it. Objects are stored by plural, namespace and
+//! name; patches are merged as JSON merge patches and `status` is only updated through the
+//! status subresource.
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::stream::{self, BoxStream};
+use futures::{FutureExt, StreamExt};
+use http::{Method, Request, Response, StatusCode, Uri};
+use http_body::Frame;
+use http_body_util::StreamBody;
+use kube::client::Body;
+use kube::{Client, Resource};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tower::Service;
+
+pub type FakeBody = StreamBody<BoxStream<'static, Result<Frame<Bytes>, Infallible>>>;
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct ObjectKey {
+    plural: String,
+    namespace: Option<String>,
+    name: String,
+}
+
+struct WatchEvent {
+    resource_version: u64,
+    plural: String,
+    type_: &'static str,
+    object: Value,
+}
+
+impl WatchEvent {
+    fn to_line(&self) -> Bytes {
+        let mut line = serde_json::to_vec(&json!({"type": self.type_, "object": self.object}))
+            .expect("watch event is serializable");
+        line.push(b'\n');
+        Bytes::from(line)
+    }
+}
+
+struct Watcher {
+    plural: String,
+    namespace: Option<String>,
+    label_selector: Vec<(String, String)>,
+    sender: UnboundedSender<Bytes>,
+}
+
+impl Watcher {
+    fn matches(&self, plural: &str, object: &Value) -> bool {
+        self.plural == plural
+            && self
+                .namespace
+                .as_ref()
+                .map_or(true, |ns| object["metadata"]["namespace"] == *ns)
+            && self
+                .label_selector
+                .iter()
+                .all(|(k, v)| object["metadata"]["labels"][k] == *v)
+    }
+}
+
+#[derive(Default)]
+struct Storage {
+    resource_version: u64,
+    objects: BTreeMap<ObjectKey, Value>,
+    /// history of events replayed to watches starting from an old resource version
+    events: Vec<WatchEvent>,
+    watchers: Vec<Watcher>,
+}
+
+impl Storage {
+    fn emit(&mut self, plural: &str, type_: &'static str, object: Value) {
+        let event = WatchEvent {
+            resource_version: self.resource_version,
+            plural: plural.to_string(),
+            type_,
+            object,
+        };
+        self.watchers.retain(|w| !w.sender.is_closed());
+        self.watchers
+            .iter()
+            .filter(|w| w.matches(plural, &event.object))
+            .for_each(|w| {
+                let _ignore_errors = w.sender.unbounded_send(event.to_line());
+            });
+        self.events.push(event);
+    }
+
+    /// Create or merge the object, emitting a watch event only if it changed
+    fn apply(&mut self, key: ObjectKey, mut patch: Value, status_subresource: bool) -> Value {
+        let existing = self.objects.get(&key).cloned();
+        let mut object = existing.clone().unwrap_or_else(|| json!({}));
+        if status_subresource {
+            merge(&mut object["status"], &patch["status"]);
+        } else {
+            if let Some(p) = patch.as_object_mut() {
+                p.remove("status");
+            }
+            merge(&mut object, &patch);
+        }
+        if existing.as_ref() == Some(&object) {
+            return object;
+        }
+
+        self.resource_version += 1;
+        let generation = match &existing {
+            None => 1,
+            Some(previous) => {
+                let previous_generation = previous["metadata"]["generation"].as_i64().unwrap_or(1);
+                if previous["spec"] != object["spec"] {
+                    previous_generation + 1
+                } else {
+                    previous_generation
+                }
+            }
+        };
+        let metadata = &mut object["metadata"];
+        metadata["name"] = json!(key.name);
+        if let Some(namespace) = &key.namespace {
+            metadata["namespace"] = json!(namespace);
+        }
+        metadata["resourceVersion"] = json!(self.resource_version.to_string());
+        metadata["generation"] = json!(generation);
+        if existing.is_none() {
+            metadata["uid"] = json!(format!("uid-{}", self.resource_version));
+        }
+
+        let event_type = if existing.is_some() {
+            "MODIFIED"
+        } else {
+            "ADDED"
+        };
+        self.objects.insert(key.clone(), object.clone());
+        self.emit(&key.plural, event_type, object.clone());
+        object
+    }
+
+    fn delete(&mut self, key: &ObjectKey) -> Option<Value> {
+        let mut object = self.objects.remove(key)?;
+        self.resource_version += 1;
+        object["metadata"]["resourceVersion"] = json!(self.resource_version.to_string());
+        self.emit(&key.plural, "DELETED", object.clone());
+        Some(object)
+    }
+}
+
+/// JSON merge patch: objects are merged recursively, `null` removes keys and anything else
+/// replaces the previous value.
+fn merge(target: &mut Value, patch: &Value) {
+    match patch {
+        Value::Object(patch_map) => {
+            if !target.is_object() {
+                *target = json!({});
+            }
+            // safe unwrap: target has just been converted to an object
+            let target_map = target.as_object_mut().unwrap();
+            for (k, v) in patch_map {
+                if v.is_null() {
+                    target_map.remove(k);
+                } else {
+                    merge(target_map.entry(k.clone()).or_insert(Value::Null), v);
+                }
+            }
+        }
+        Value::Null => {}
+        _ => *target = patch.clone(),
+    }
+}
+
+/// Resource addressed by a request path
+struct ResourcePath {
+    api_version: String,
+    plural: String,
+    namespace: Option<String>,
+    name: Option<String>,
+    subresource: Option<String>,
+}
+
+impl ResourcePath {
+    fn parse(path: &str) -> Option<Self> {
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let (api_version, rest) = match segments.as_slice() {
+            ["api", version, rest @ ..] => (version.to_string(), rest),
+            ["apis", group, version, rest @ ..] => (format!("{group}/{version}"), rest),
+            _ => return None,
+        };
+        let (namespace, rest) = match rest {
+            ["namespaces", namespace, rest @ ..] if !rest.is_empty() => {
+                (Some(namespace.to_string()), rest)
+            }
+            _ => (None, rest),
+        };
+        let (plural, rest) = rest.split_first()?;
+        Some(Self {
+            api_version,
+            plural: plural.to_string(),
+            namespace,
+            name: rest.first().map(|s| s.to_string()),
+            subresource: rest.get(1).map(|s| s.to_string()),
+        })
+    }
+
+    fn key(&self, name: &str) -> ObjectKey {
+        ObjectKey {
+            plural: self.plural.clone(),
+            namespace: self.namespace.clone(),
+            name: name.to_string(),
+        }
+    }
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (escaped, bytes[i]) {
+            (Some(b), _) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (None, b'+') => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (None, b) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn query_params(uri: &Uri) -> BTreeMap<String, String> {
+    uri.query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|p| p.split_once('='))
+        .map(|(k, v)| (percent_decode(k), percent_decode(v)))
+        .collect()
+}
+
+/// Only equality based selectors are supported
+fn parse_label_selector(selector: Option<&String>) -> Vec<(String, String)> {
+    selector
+        .map(|s| {
+            s.split(',')
+                .filter_map(|r| r.split_once('='))
+                .map(|(k, v)| (k.trim().to_string(), v.trim_start_matches('=').to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn json_response(status: StatusCode, value: &Value) -> Response<FakeBody> {
+    let bytes = Bytes::from(serde_json::to_vec(value).expect("response is serializable"));
+    Response::builder()
+        .status(status)
+        .body(StreamBody::new(
+            stream::iter([Ok(Frame::data(bytes))]).boxed(),
+        ))
+        .expect("valid response")
+}
+
+fn status_response(status: StatusCode, reason: &str) -> Response<FakeBody> {
+    json_response(
+        status,
+        &json!({
+            "apiVersion": "v1",
+            "kind": "Status",
+            "metadata": {},
+            "status": "Failure",
+            "message": reason,
+            "reason": reason,
+            "code": status.as_u16(),
+        }),
+    )
+}
+
+#[derive(Clone, Default)]
+pub struct FakeApiServer {
+    storage: Arc<Mutex<Storage>>,
+}
+
+impl FakeApiServer {
+    /// Kubernetes client sending every request to this server
+    pub fn client(&self) -> Client {
+        Client::new(self.clone(), "default")
+    }
+
+    fn storage(&self) -> MutexGuard<'_, Storage> {
+        self.storage.lock().expect("fake apiserver lock poisoned")
+    }
+
+    fn object_key<K: Resource<DynamicType = ()>>(namespace: Option<&str>, name: &str) -> ObjectKey {
+        ObjectKey {
+            plural: K::plural(&()).to_string(),
+            namespace: namespace.map(String::from),
+            name: name.to_string(),
+        }
+    }
+
+    /// Store an object as if it was created by a user
+    pub fn create<K: Resource<DynamicType = ()> + Serialize>(&self, obj: &K) -> Value {
+        let key = Self::object_key::<K>(
+            obj.meta().namespace.as_deref(),
+            obj.meta().name.as_deref().expect("object has a name"),
+        );
+        let value = serde_json::to_value(obj).expect("object is serializable");
+        let status = value.get("status").cloned();
+        let mut storage = self.storage();
+        let object = storage.apply(key.clone(), value, false);
+        match status {
+            Some(status) => storage.apply(key, json!({ "status": status }), true),
+            None => object,
+        }
+    }
+
+    /// Get an object from the server storage
+    pub fn get<K: Resource<DynamicType = ()> + DeserializeOwned>(
+        &self,
+        namespace: Option<&str>,
+        name: &str,
+    ) -> Option<K> {
+        self.storage()
+            .objects
+            .get(&Self::object_key::<K>(namespace, name))
+            .map(|v| serde_json::from_value(v.clone()).expect("stored object is valid"))
+    }
+
+    /// Merge the given status, as other controllers (e.g. kube-controller-manager) would do
+    pub fn set_status<K: Resource<DynamicType = ()>>(
+        &self,
+        namespace: Option<&str>,
+        name: &str,
+        status: Value,
+    ) {
+        let key = Self::object_key::<K>(namespace, name);
+        let mut storage = self.storage();
+        if storage.objects.contains_key(&key) {
+            storage.apply(key, json!({ "status": status }), true);
+        }
+    }
+
+    /// Delete an object, as if it was deleted by a user
+    pub fn delete<K: Resource<DynamicType = ()>>(&self, namespace: Option<&str>, name: &str) {
+        self.storage()
+            .delete(&Self::object_key::<K>(namespace, name));
+    }
+
+    /// Send a bookmark event with the current resource version to the resource watchers
+    pub fn bookmark<K: Resource<DynamicType = ()>>(&self) {
+        let storage = self.storage();
+        let event = WatchEvent {
+            resource_version: storage.resource_version,
+            plural: K::plural(&()).to_string(),
+            type_: "BOOKMARK",
+            object: json!({
+                "apiVersion": K::api_version(&()),
+                "kind": K::kind(&()),
+                "metadata": {"resourceVersion": storage.resource_version.to_string()},
+            }),
+        };
+        storage
+            .watchers
+            .iter()
+            .filter(|w| w.plural == event.plural)
+            .for_each(|w| {
+                let _ignore_errors = w.sender.unbounded_send(event.to_line());
+            });
+    }
+
+    /// Number of watch requests currently streaming events
+    pub fn watchers(&self) -> usize {
+        let mut storage = self.storage();
+        storage.watchers.retain(|w| !w.sender.is_closed());
+        storage.watchers.len()
+    }
+
+    /// Poll the storage until the object fulfills the condition
+    ///
+    /// Panics if the condition is not fulfilled before the timeout.
+    pub async fn wait_for<K, F>(
+        &self,
+        namespace: Option<&str>,
+        name: &str,
+        condition: F,
+        timeout: Duration,
+    ) -> K
+    where
+        K: Resource<DynamicType = ()> + DeserializeOwned,
+        F: Fn(&K) -> bool,
+    {
+        tokio::time::timeout(timeout, async {
+            loop {
+                if let Some(obj) = self.get::<K>(namespace, name).filter(|o| condition(o)) {
+                    return obj;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("timeout waiting for {} {name}", K::kind(&())))
+    }
+
+    async fn handle(&self, request: Request<Body>) -> Response<FakeBody> {
+        let (parts, body) = request.into_parts();
+        let Some(path) = ResourcePath::parse(parts.uri.path()) else {
+            return status_response(StatusCode::NOT_FOUND, "NotFound");
+        };
+        let query = query_params(&parts.uri);
+        let body: Value = match body.collect_bytes().await {
+            Ok(bytes) if !bytes.is_empty() => match serde_json::from_slice(&bytes) {
+                Ok(value) => value,
+                Err(_) => return status_response(StatusCode::BAD_REQUEST, "BadRequest"),
+            },
+            _ => Value::Null,
+        };
+
+        match (parts.method, path.name.clone()) {
+            (Method::GET, None) if query.get("watch").is_some_and(|w| w == "true" || w == "1") => {
+                self.watch(&path, &query)
+            }
+            (Method::GET, None) => self.list(&path, &query),
+            (Method::GET, Some(name)) => match self.storage().objects.get(&path.key(&name)) {
+                Some(object) => json_response(StatusCode::OK, object),
+                None => status_response(StatusCode::NOT_FOUND, "NotFound"),
+            },
+            (Method::POST, None) => {
+                let mut storage = self.storage();
+                let metadata = &body["metadata"];
+                // e.g. Events are created with a generated name
+                let name = match (metadata["name"].as_str(), metadata["generateName"].as_str()) {
+                    (Some(name), _) => name.to_string(),
+                    (None, Some(prefix)) => format!("{prefix}{}", storage.resource_version + 1),
+                    (None, None) => {
+                        return status_response(StatusCode::UNPROCESSABLE_ENTITY, "Invalid");
+                    }
+                };
+                if storage.objects.contains_key(&path.key(&name)) {
+                    return status_response(StatusCode::CONFLICT, "AlreadyExists");
+                }
+                let object = storage.apply(path.key(&name), body, false);
+                json_response(StatusCode::CREATED, &object)
+            }
+            (Method::PATCH, Some(name)) => {
+                let status_subresource = path.subresource.as_deref() == Some("status");
+                let mut storage = self.storage();
+                if status_subresource && !storage.objects.contains_key(&path.key(&name)) {
+                    return status_response(StatusCode::NOT_FOUND, "NotFound");
+                }
+                let object = storage.apply(path.key(&name), body, status_subresource);
+                json_response(StatusCode::OK, &object)
+            }
+            (Method::DELETE, Some(name)) => match self.storage().delete(&path.key(&name)) {
+                Some(object) => json_response(StatusCode::OK, &object),
+                None => status_response(StatusCode::NOT_FOUND, "NotFound"),
+            },
+            _ => status_response(StatusCode::METHOD_NOT_ALLOWED, "MethodNotAllowed"),
+        }
+    }
+
+    fn list(&self, path: &ResourcePath, query: &BTreeMap<String, String>) -> Response<FakeBody> {
+        let label_selector = parse_label_selector(query.get("labelSelector"));
+        let storage = self.storage();
+        let items: Vec<&Value> = storage
+            .objects
+            .iter()
+            .filter(|(k, _)| {
+                k.plural == path.plural
+                    && path
+                        .namespace
+                        .as_ref()
+                        .map_or(true, |ns| k.namespace.as_ref() == Some(ns))
+            })
+            .map(|(_, v)| v)
+            .filter(|v| {
+                label_selector
+                    .iter()
+                    .all(|(k, value)| v["metadata"]["labels"][k] == *value)
+            })
+            .collect();
+        json_response(
+            StatusCode::OK,
+            &json!({
+                "apiVersion": path.api_version,
+                "kind": "List",
+                "metadata": {"resourceVersion": storage.resource_version.to_string()},
+                "items": items,
+            }),
+        )
+    }
+
+    fn watch(&self, path: &ResourcePath, query: &BTreeMap<String, String>) -> Response<FakeBody> {
+        let (sender, receiver) = unbounded();
+        let since = query
+            .get("resourceVersion")
+            .and_then(|rv| rv.parse::<u64>().ok())
+            .unwrap_or(0);
+        let watcher = Watcher {
+            plural: path.plural.clone(),
+            namespace: path.namespace.clone(),
+            label_selector: parse_label_selector(query.get("labelSelector")),
+            sender,
+        };
+
+        let mut storage = self.storage();
+        storage
+            .events
+            .iter()
+            .filter(|e| e.resource_version > since && watcher.matches(&e.plural, &e.object))
+            .for_each(|e| {
+                let _ignore_errors = watcher.sender.unbounded_send(e.to_line());
+            });
+        storage.watchers.push(watcher);
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .body(StreamBody::new(
+                receiver.map(|line| Ok(Frame::data(line))).boxed(),
+            ))
+            .expect("valid response")
+    }
+}
+
+impl Service<Request<Body>> for FakeApiServer {
+    type Response = Response<FakeBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let server = self.clone();
+        async move { Ok::<_, Infallible>(server.handle(request).await) }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{merge, percent_decode, ResourcePath};
+
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_namespaced_status_path() {
+        let path =
+            ResourcePath::parse("/apis/example.com/v1/namespaces/default/echoes/test/status")
+                .unwrap();
+        assert_eq!(path.api_version, "example.com/v1");
+        assert_eq!(path.plural, "echoes");
+        assert_eq!(path.namespace.as_deref(), Some("default"));
+        assert_eq!(path.name.as_deref(), Some("test"));
+        assert_eq!(path.subresource.as_deref(), Some("status"));
+    }
+
+    #[test]
+    fn test_parse_cluster_wide_core_path() {
+        let path = ResourcePath::parse("/api/v1/pods").unwrap();
+        assert_eq!(path.api_version, "v1");
+        assert_eq!(path.plural, "pods");
+        assert_eq!(path.namespace, None);
+        assert_eq!(path.name, None);
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(
+            percent_decode("app.kubernetes.io%2Fmanaged-by%3Decho-operator"),
+            "app.kubernetes.io/managed-by=echo-operator"
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut target = json!({"a": {"b": 1, "c": 2}, "d": [1, 2]});
+        merge(&mut target, &json!({"a": {"b": 3, "c": null}, "d": [3]}));
+        assert_eq!(target, json!({"a": {"b": 3}, "d": [3]}));
+    }
+}
@@ -0,0 +1,114 @@
+//! Streaming lists of the Echo and Deployment watchers: the initial list, and the relists after a
+//! reconnection, are received as watch events of the objects instead of list pages, which bounds
+//! the memory spikes and the apiserver cost of relisting large clusters.
+//!
+//! The watchers always request bookmarks, so reconnections resume from a recent resource version
+//! instead of relisting. Streaming lists require the `WatchList` feature of the apiserver, enabled
+//! by default since Kubernetes 1.32, and are only used when it is available.
+//!
+//! Otherwise the initial list is requested in pages of a bounded size, each one deserialized and
+//! stored before the next one is requested, so listing the Deployments of large clusters does not
+//! buffer them all in a single response.
+use crate::controller::State;
+
+use k8s_openapi::apimachinery::pkg::version::Info;
+use kube::client::Client;
+use kube::runtime::watcher;
+use tracing::{info, warn};
+
+/// First Kubernetes version serving streaming lists by default
+pub const MIN_VERSION: (u32, u32) = (1, 32);
+
+/// Objects per page of the paginated initial lists
+pub const DEFAULT_PAGE_SIZE: u32 = 500;
+
+/// Whether the watchers use streaming lists: enabled in the state and supported by the apiserver
+pub async fn enabled(state: &State, client: &Client) -> bool {
+    if !state.streaming_lists() {
+        return false;
+    }
+    match client.apiserver_version().await {
+        Ok(info) if supported(&info) => true,
+        Ok(info) => {
+            info!(
+                msg = "streaming lists not supported, using paginated lists",
+                version = info.git_version
+            );
+            false
+        }
+        Err(e) => {
+            warn!(msg = "failed to get apiserver version, using paginated lists", %e);
+            false
+        }
+    }
+}
+
+/// Watcher config with the initial list strategy, and the page size of the paginated lists
+pub fn config(config: watcher::Config, streaming_lists: bool, page_size: u32) -> watcher::Config {
+    let config = watcher::Config {
+        bookmarks: true,
+        ..config
+    }
+    .page_size(page_size);
+    if streaming_lists {
+        config.streaming_lists()
+    } else {
+        config
+    }
+}
+
+/// Whether the apiserver version serves streaming lists. Minor versions of managed clusters can
+/// have a suffix, e.g. `32+`.
+fn supported(info: &Info) -> bool {
+    let number = |s: &str| {
+        s.trim_end_matches(|c: char| !c.is_ascii_digit())
+            .parse::<u32>()
+            .ok()
+    };
+    match (number(&info.major), number(&info.minor)) {
+        (Some(major), Some(minor)) => (major, minor) >= MIN_VERSION,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{config, supported};
+
+    use k8s_openapi::apimachinery::pkg::version::Info;
+    use kube::runtime::watcher::{self, InitialListStrategy};
+
+    fn info(major: &str, minor: &str) -> Info {
+        Info {
+            major: major.to_string(),
+            minor: minor.to_string(),
+            ..Info::default()
+        }
+    }
+
+    #[test]
+    fn test_supported() {
+        assert!(supported(&info("1", "32")));
+        assert!(supported(&info("1", "33+")));
+        assert!(supported(&info("2", "0")));
+        assert!(!supported(&info("1", "31")));
+        assert!(!supported(&info("1", "29+")));
+        assert!(!supported(&info("", "")));
+    }
+
+    #[test]
+    fn test_config() {
+        let streaming = config(watcher::Config::default().disable_bookmarks(), true, 100);
+        assert!(streaming.bookmarks);
+        assert_eq!(
+            streaming.initial_list_strategy,
+            InitialListStrategy::StreamingList
+        );
+        let paginated = config(watcher::Config::default().any_semantic(), false, 100);
+        assert_eq!(
+            paginated.initial_list_strategy,
+            InitialListStrategy::ListWatch
+        );
+        assert_eq!(paginated.page_size, Some(100));
+    }
+}
@@ -0,0 +1,96 @@
+//! Chaos scenarios: the operator is killed or stopped between reconcile steps and every Echo
+//! must still converge once it is running again.
+//!
+//! They disrupt the shared operator, so they must run sequentially:
+//! `cargo test -p tests --features e2e-chaos-test chaos -- --test-threads=1`
+use crate::test::{is_deployment_ready, is_echo_ready, setup, setup_namespace, wait_for};
+
+use echo_operator::crd::echo::{Echo, EchoSpec};
+use echo_operator_test_support::operator::{kill_operator, scale_operator, wait_operator_ready};
+use k8s_openapi::api::apps::v1::Deployment;
+use kube::api::{Api, Patch, PatchParams, PostParams};
+use kube::runtime::wait::conditions;
+use kube::ResourceExt;
+
+#[tokio::test]
+async fn chaos_operator_killed_during_create() {
+    let name = "chaos-killed-during-create";
+    let namespace = setup_namespace(name).await;
+    let echo_api: Api<Echo> = namespace.api();
+    let deployment_api: Api<Deployment> = namespace.api();
+
+    echo_api
+        .create(
+            &PostParams::default(),
+            &Echo::new(
+                name,
+                EchoSpec {
+                    replicas: 1,
+                    ..EchoSpec::default()
+                },
+            ),
+        )
+        .await
+        .unwrap();
+    kill_operator(namespace.client()).await.unwrap();
+    wait_operator_ready(namespace.client()).await.unwrap();
+
+    wait_for(deployment_api, name, is_deployment_ready()).await;
+    wait_for(echo_api, name, is_echo_ready()).await;
+}
+
+#[tokio::test]
+async fn chaos_operator_stopped_during_change() {
+    let name = "chaos-stopped-during-change";
+    let (namespace, echo_api, deployment_api) = setup(name).await;
+
+    scale_operator(namespace.client(), 0).await.unwrap();
+    wait_operator_ready(namespace.client()).await.unwrap();
+
+    let mut echo = echo_api.get(name).await.unwrap();
+    echo.spec.replicas = 2;
+    echo.metadata.managed_fields = None;
+    echo_api
+        .patch(
+            name,
+            &PatchParams::apply("e2e-test").force(),
+            &Patch::Apply(&echo),
+        )
+        .await
+        .unwrap();
+
+    scale_operator(namespace.client(), 1).await.unwrap();
+    wait_operator_ready(namespace.client()).await.unwrap();
+
+    wait_for(deployment_api.clone(), name, is_deployment_ready()).await;
+    wait_for(echo_api, name, is_echo_ready()).await;
+    let deployment = deployment_api.get(name).await.unwrap();
+    assert_eq!(deployment.spec.unwrap().replicas.unwrap(), 2);
+}
+
+#[tokio::test]
+async fn chaos_deployment_deleted_while_operator_stopped() {
+    let name = "chaos-deleted-while-stopped";
+    let (namespace, echo_api, deployment_api) = setup(name).await;
+
+    scale_operator(namespace.client(), 0).await.unwrap();
+    wait_operator_ready(namespace.client()).await.unwrap();
+
+    let deployment = deployment_api.get(name).await.unwrap();
+    deployment_api
+        .delete(name, &Default::default())
+        .await
+        .unwrap();
+    wait_for(
+        deployment_api.clone(),
+        name,
+        conditions::is_deleted(&deployment.uid().unwrap()),
+    )
+    .await;
+
+    scale_operator(namespace.client(), 1).await.unwrap();
+    wait_operator_ready(namespace.client()).await.unwrap();
+
+    wait_for(deployment_api, name, is_deployment_ready()).await;
+    wait_for(echo_api, name, is_echo_ready()).await;
+}
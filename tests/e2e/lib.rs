@@ -1,43 +1,45 @@
+#[cfg(all(test, feature = "e2e-chaos-test"))]
+mod chaos;
+
+#[cfg(all(test, feature = "e2e-load-test"))]
+mod load;
+
 #[cfg(all(test, feature = "e2e-test"))]
 mod test {
     use std::time::Duration;
 
     use echo_operator::crd::echo::{Echo, EchoSpec};
+    use echo_operator_test_support::TestNamespace;
     use k8s_openapi::api::apps::v1::Deployment;
     use kube::api::{Api, Patch, PatchParams, PostParams};
-    use kube::client::Client;
     use kube::runtime::wait::{await_condition, conditions, Condition};
     use kube::ResourceExt;
     use serde_json::json;
     use tokio::time::timeout;
 
-    fn is_echo_ready() -> impl Condition<Echo> {
+    pub(crate) fn is_echo_ready() -> impl Condition<Echo> {
         |obj: Option<&Echo>| {
             if let Some(echo) = &obj {
                 if let Some(status) = &echo.status {
-                    if let Some(conditions) = &status.conditions {
-                        return conditions.iter().any(|c| c.type_ == "Ready");
-                    }
+                    return status.is_ready();
                 }
             }
             false
         }
     }
 
-    fn is_echo_not_ready() -> impl Condition<Echo> {
+    pub(crate) fn is_echo_not_ready() -> impl Condition<Echo> {
         |obj: Option<&Echo>| {
             if let Some(echo) = &obj {
                 if let Some(status) = &echo.status {
-                    if let Some(conditions) = &status.conditions {
-                        return conditions.iter().all(|c| c.type_ != "Ready");
-                    }
+                    return !status.is_ready();
                 }
             }
             true
         }
     }
 
-    fn is_deployment_ready() -> impl Condition<Deployment> {
+    pub(crate) fn is_deployment_ready() -> impl Condition<Deployment> {
         |obj: Option<&Deployment>| {
             if let Some(deployment) = &obj {
                 if let Some(status) = &deployment.status {
@@ -49,7 +51,7 @@ mod test {
         }
     }
 
-    async fn wait_for<R, C>(api: Api<R>, name: &str, condition: C)
+    pub(crate) async fn wait_for<R, C>(api: Api<R>, name: &str, condition: C)
     where
         R: kube::Resource
             + Clone
@@ -68,23 +70,41 @@ mod test {
         .unwrap();
     }
 
-    async fn setup(name: &str) -> (Api<Echo>, Api<Deployment>) {
-        let echo = Echo::new(name, EchoSpec { replicas: 1 });
+    pub(crate) async fn setup_namespace(name: &str) -> TestNamespace {
+        echo_operator_test_support::setup(name).await.unwrap()
+    }
 
-        let client = Client::try_default().await.unwrap();
-        let echo_api = Api::<Echo>::namespaced(client.clone(), "default");
+    pub(crate) async fn setup_echo(
+        namespace: &TestNamespace,
+        name: &str,
+    ) -> (Api<Echo>, Api<Deployment>) {
+        let echo = Echo::new(
+            name,
+            EchoSpec {
+                replicas: 1,
+                ..EchoSpec::default()
+            },
+        );
+
+        let echo_api: Api<Echo> = namespace.api();
 
         echo_api
             .create(&PostParams::default(), &echo)
             .await
             .unwrap();
 
-        let deployment_api = Api::<Deployment>::namespaced(client.clone(), "default");
+        let deployment_api: Api<Deployment> = namespace.api();
         wait_for(deployment_api.clone(), name, is_deployment_ready()).await;
         wait_for(echo_api.clone(), name, is_echo_ready()).await;
         (echo_api, deployment_api)
     }
 
+    pub(crate) async fn setup(name: &str) -> (TestNamespace, Api<Echo>, Api<Deployment>) {
+        let namespace = setup_namespace(name).await;
+        let (echo_api, deployment_api) = setup_echo(&namespace, name).await;
+        (namespace, echo_api, deployment_api)
+    }
+
     #[tokio::test]
     async fn echo_create() {
         let name = "test-create";
@@ -94,7 +114,7 @@ mod test {
     #[tokio::test]
     async fn echo_delete_deployment() {
         let name = "test-delete-deployment";
-        let (echo_api, deployment_api) = setup(name).await;
+        let (_namespace, echo_api, deployment_api) = setup(name).await;
 
         let deploy = deployment_api.get(name).await.unwrap();
         deployment_api
@@ -126,7 +146,7 @@ mod test {
     #[tokio::test]
     async fn echo_delete_echo() {
         let name = "test-delete-echo";
-        let (echo_api, deployment_api) = setup(name).await;
+        let (_namespace, echo_api, deployment_api) = setup(name).await;
 
         let deploy = deployment_api.get(name).await.unwrap();
         let echo = echo_api.get(name).await.unwrap();
@@ -150,7 +170,7 @@ mod test {
     #[tokio::test]
     async fn echo_change_deployment() {
         let name = "test-change-deployment";
-        let (echo_api, deployment_api) = setup(name).await;
+        let (_namespace, echo_api, deployment_api) = setup(name).await;
 
         let mut deploy = deployment_api.get(name).await.unwrap();
         deploy.spec.as_mut().unwrap().replicas = Some(2);
@@ -175,7 +195,7 @@ mod test {
     #[tokio::test]
     async fn echo_change_echo() {
         let name = "test-change-echo";
-        let (echo_api, deployment_api) = setup(name).await;
+        let (_namespace, echo_api, deployment_api) = setup(name).await;
 
         let mut echo = echo_api.get(name).await.unwrap();
         echo.spec.replicas = 2;
@@ -230,8 +250,8 @@ mod test {
                 }
             }
         });
-        let deployment_api =
-            Api::<Deployment>::namespaced(Client::try_default().await.unwrap(), "default");
+        let namespace = setup_namespace(name).await;
+        let deployment_api: Api<Deployment> = namespace.api();
         deployment_api
             .create(
                 &PostParams::default(),
@@ -240,6 +260,6 @@ mod test {
             .await
             .unwrap();
 
-        setup(name).await;
+        setup_echo(&namespace, name).await;
     }
 }
@@ -0,0 +1,105 @@
+//! Load scenarios: the operator relists many Deployments after a restart and its memory must stay
+//! bounded by the page size of the initial lists, not grow with the whole list.
+//!
+//! They restart the shared operator, so they must run sequentially:
+//! `cargo test -p tests --features e2e-load-test load -- --test-threads=1`
+//!
+//! `ECHO_E2E_LOAD_DEPLOYMENTS` sets the number of Deployments, 2000 by default, and
+//! `ECHO_E2E_LOAD_MEMORY_LIMIT_MB` the memory the operator can reach while relisting them, 128
+//! by default.
+use crate::test::setup_namespace;
+
+use std::env;
+use std::time::Duration;
+
+use echo_operator_test_support::operator::{
+    kill_operator, operator_memory_bytes, wait_operator_ready,
+};
+use k8s_openapi::api::apps::v1::Deployment;
+use kube::api::{Api, PostParams};
+use serde_json::json;
+use tokio::time::sleep;
+
+/// Time the memory of the operator is sampled after its restart, longer than the kubelet stats
+/// period
+const SAMPLE_DURATION: Duration = Duration::from_secs(60);
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+fn env_or(name: &str, default: u64) -> u64 {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Deployment watched by the operator, without pods, padded to the size of a real one
+fn deployment(name: &str) -> Deployment {
+    serde_json::from_value(json!({
+        "apiVersion": "apps/v1",
+        "kind": "Deployment",
+        "metadata": {
+            "name": name,
+            "labels": {
+                "app.kubernetes.io/managed-by": "echo-operator"
+            },
+            "annotations": {
+                "echo-operator.example.com/e2e-padding": "x".repeat(2048)
+            }
+        },
+        "spec": {
+            "replicas": 0,
+            "selector": {
+                "matchLabels": {
+                    "app": name
+                }
+            },
+            "template": {
+                "metadata": {
+                    "labels": {
+                        "app": name
+                    }
+                },
+                "spec": {
+                    "containers": [
+                        {
+                            "name": name,
+                            "image": "inanimate/echo-server:latest"
+                        }
+                    ]
+                }
+            }
+        }
+    }))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn load_relist_deployments_memory() {
+    let name = "load-relist-deployments";
+    let deployments = env_or("ECHO_E2E_LOAD_DEPLOYMENTS", 2000);
+    let limit = env_or("ECHO_E2E_LOAD_MEMORY_LIMIT_MB", 128) * 1024 * 1024;
+    let namespace = setup_namespace(name).await;
+    let deployment_api: Api<Deployment> = namespace.api();
+    for i in 0..deployments {
+        deployment_api
+            .create(&PostParams::default(), &deployment(&format!("{name}-{i}")))
+            .await
+            .unwrap();
+    }
+
+    kill_operator(namespace.client()).await.unwrap();
+    wait_operator_ready(namespace.client()).await.unwrap();
+
+    let mut peak = 0;
+    let mut elapsed = Duration::ZERO;
+    while elapsed < SAMPLE_DURATION {
+        peak = peak.max(operator_memory_bytes(namespace.client()).await.unwrap());
+        sleep(SAMPLE_INTERVAL).await;
+        elapsed += SAMPLE_INTERVAL;
+    }
+    assert!(peak > 0, "no memory reported for the operator");
+    assert!(
+        peak <= limit,
+        "operator memory {peak} bytes above {limit} bytes relisting {deployments} Deployments"
+    );
+}
@@ -0,0 +1,251 @@
+//! Self-provisioning of the e2e environment.
+//!
+//! The environment is configured with the following variables:
+//!
+//! - `ECHO_E2E_CLUSTER`: `kind` (default), `k3d` or `existing` to use the current kube context.
+//! - `ECHO_E2E_CLUSTER_NAME`: name of the kind/k3d cluster, `chart-testing` by default.
+//! - `ECHO_E2E_OPERATOR`: `in-process` (default) runs the controller inside the test process,
+//!   `helm` installs the chart and `external` expects an operator already running.
+//! - `ECHO_E2E_IMAGE_TAG`: image tag installed in `helm` mode. For kind clusters the image is
+//!   loaded from the local docker daemon.
+use crate::{Error, Result};
+
+use std::process::Command;
+use std::sync::OnceLock;
+
+use echo_operator::clusterecho;
+use echo_operator::controller::{ControllerRegistry, State};
+use echo_operator::echo;
+use echo_operator::echogateway;
+use echo_operator::echoquota;
+use echo_operator::echoreplication;
+use echo_operator::echoroute;
+use echo_operator::echostatus;
+use kube::Client;
+use prometheus_client::registry::Registry;
+
+const DEFAULT_CLUSTER_NAME: &str = "chart-testing";
+const KIND_CONFIG: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../../.github/kind-cluster-1.30.yaml"
+);
+const CHART_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../charts/echo-operator");
+const IMAGE_REPOSITORY: &str = "ghcr.io/pando85/echo-operator";
+
+static CLUSTER: OnceLock<Result<(), String>> = OnceLock::new();
+static OPERATOR: OnceLock<Result<(), String>> = OnceLock::new();
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClusterProvider {
+    Kind,
+    K3d,
+    Existing,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OperatorMode {
+    InProcess,
+    Helm,
+    External,
+}
+
+#[derive(Clone, Debug)]
+pub struct BootstrapConfig {
+    pub cluster: ClusterProvider,
+    pub cluster_name: String,
+    pub operator: OperatorMode,
+    pub image_tag: Option<String>,
+}
+
+impl BootstrapConfig {
+    pub fn from_env() -> Result<Self> {
+        let cluster = match std::env::var("ECHO_E2E_CLUSTER").as_deref() {
+            Err(_) | Ok("kind") => ClusterProvider::Kind,
+            Ok("k3d") => ClusterProvider::K3d,
+            Ok("existing") => ClusterProvider::Existing,
+            Ok(other) => {
+                return Err(Error::BootstrapError(format!(
+                    "unknown ECHO_E2E_CLUSTER: {other}"
+                )))
+            }
+        };
+        let operator = match std::env::var("ECHO_E2E_OPERATOR").as_deref() {
+            Err(_) | Ok("in-process") => OperatorMode::InProcess,
+            Ok("helm") => OperatorMode::Helm,
+            Ok("external") => OperatorMode::External,
+            Ok(other) => {
+                return Err(Error::BootstrapError(format!(
+                    "unknown ECHO_E2E_OPERATOR: {other}"
+                )))
+            }
+        };
+        Ok(Self {
+            cluster,
+            cluster_name: std::env::var("ECHO_E2E_CLUSTER_NAME")
+                .unwrap_or_else(|_| DEFAULT_CLUSTER_NAME.to_string()),
+            operator,
+            image_tag: std::env::var("ECHO_E2E_IMAGE_TAG").ok(),
+        })
+    }
+}
+
+/// Create the cluster if needed. It only runs once per test process.
+pub fn ensure_cluster(config: &BootstrapConfig) -> Result<()> {
+    CLUSTER
+        .get_or_init(|| create_cluster(config).map_err(|e| e.to_string()))
+        .clone()
+        .map_err(Error::BootstrapError)
+}
+
+/// Start the operator as configured. It only runs once per test process and it requires the
+/// CRDs to be installed.
+pub fn ensure_operator(config: &BootstrapConfig) -> Result<()> {
+    OPERATOR
+        .get_or_init(|| start_operator(config).map_err(|e| e.to_string()))
+        .clone()
+        .map_err(Error::BootstrapError)
+}
+
+fn create_cluster(config: &BootstrapConfig) -> Result<()> {
+    let name = config.cluster_name.as_str();
+    match config.cluster {
+        ClusterProvider::Existing => Ok(()),
+        ClusterProvider::Kind => {
+            let clusters = run("kind", &["get", "clusters"])?;
+            if clusters.lines().any(|c| c == name) {
+                run("kind", &["export", "kubeconfig", "--name", name]).map(|_| ())
+            } else {
+                run(
+                    "kind",
+                    &[
+                        "create",
+                        "cluster",
+                        "--name",
+                        name,
+                        "--config",
+                        KIND_CONFIG,
+                        "--wait",
+                        "120s",
+                    ],
+                )
+                .map(|_| ())
+            }
+        }
+        ClusterProvider::K3d => {
+            let clusters = run("k3d", &["cluster", "list", "--no-headers"])?;
+            if clusters
+                .lines()
+                .any(|c| c.split_whitespace().next() == Some(name))
+            {
+                run(
+                    "k3d",
+                    &["kubeconfig", "merge", name, "--kubeconfig-switch-context"],
+                )
+                .map(|_| ())
+            } else {
+                run("k3d", &["cluster", "create", name, "--wait"]).map(|_| ())
+            }
+        }
+    }
+}
+
+fn start_operator(config: &BootstrapConfig) -> Result<()> {
+    match config.operator {
+        OperatorMode::External => Ok(()),
+        OperatorMode::InProcess => {
+            // Every tokio test has its own runtime, so the controller gets a dedicated one which
+            // lives until the test process exits.
+            std::thread::spawn(|| {
+                tokio::runtime::Builder::new_multi_thread()
+                    .enable_all()
+                    .build()
+                    .expect("tokio runtime for the operator")
+                    .block_on(async {
+                        let client = Client::try_default()
+                            .await
+                            .expect("kubernetes client for the operator");
+                        let controllers = ControllerRegistry::default()
+                            .register(echo::controller::CONTROLLER_ID, echo::controller::run)
+                            .register(
+                                echostatus::controller::CONTROLLER_ID,
+                                echostatus::controller::run,
+                            )
+                            .register(
+                                echogateway::controller::CONTROLLER_ID,
+                                echogateway::controller::run,
+                            )
+                            .register(
+                                echoroute::controller::CONTROLLER_ID,
+                                echoroute::controller::run,
+                            )
+                            .register(
+                                echoquota::controller::CONTROLLER_ID,
+                                echoquota::controller::run,
+                            )
+                            .register(
+                                echoreplication::controller::CONTROLLER_ID,
+                                echoreplication::controller::run,
+                            )
+                            .register(
+                                clusterecho::controller::CONTROLLER_ID,
+                                clusterecho::controller::run,
+                            );
+                        let state =
+                            State::new(Registry::with_prefix("echo-operator"), &controllers.ids());
+                        controllers.run(state, client).await;
+                    })
+            });
+            Ok(())
+        }
+        OperatorMode::Helm => {
+            let tag = config.image_tag.as_deref().ok_or_else(|| {
+                Error::BootstrapError("ECHO_E2E_IMAGE_TAG is required in helm mode".to_string())
+            })?;
+            if config.cluster == ClusterProvider::Kind {
+                run(
+                    "kind",
+                    &[
+                        "load",
+                        "docker-image",
+                        "--name",
+                        &config.cluster_name,
+                        &format!("{IMAGE_REPOSITORY}:{tag}"),
+                    ],
+                )?;
+            }
+            run(
+                "helm",
+                &[
+                    "upgrade",
+                    "--install",
+                    crate::operator::OPERATOR_NAME,
+                    CHART_PATH,
+                    "--namespace",
+                    crate::operator::OPERATOR_NAMESPACE,
+                    "--create-namespace",
+                    "--set",
+                    &format!("image.tag={tag}"),
+                    "--set",
+                    "logging.level=info\\,echo-operator=trace",
+                    "--wait",
+                ],
+            )
+            .map(|_| ())
+        }
+    }
+}
+
+fn run(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| Error::BootstrapError(format!("{program}: {e}")))?;
+    if !output.status.success() {
+        return Err(Error::BootstrapError(format!(
+            "{program} {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
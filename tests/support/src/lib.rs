@@ -0,0 +1,216 @@
+//! Helpers to run e2e tests isolated from each other against a shared cluster.
+//!
+//! Each test gets its own namespace, which is deleted when the [`TestNamespace`] guard is dropped,
+//! even if the test panics.
+pub mod bootstrap;
+pub mod operator;
+
+use crate::bootstrap::{ensure_cluster, ensure_operator, BootstrapConfig};
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use k8s_openapi::api::core::v1::Namespace;
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use kube::api::{Api, DeleteParams, ObjectMeta, Patch, PatchParams, PostParams};
+use kube::runtime::wait::{await_condition, conditions};
+use kube::Client;
+use thiserror::Error;
+use tokio::time::timeout;
+
+const CRDS: [&str; 6] = [
+    include_str!("../../../charts/echo-operator/crds/crd-echo.yaml"),
+    include_str!("../../../charts/echo-operator/crds/crd-echogateway.yaml"),
+    include_str!("../../../charts/echo-operator/crds/crd-echoroute.yaml"),
+    include_str!("../../../charts/echo-operator/crds/crd-echoquota.yaml"),
+    include_str!("../../../charts/echo-operator/crds/crd-echoreplication.yaml"),
+    include_str!("../../../charts/echo-operator/crds/crd-clusterecho.yaml"),
+];
+const FIELD_MANAGER: &str = "echo-operator-e2e";
+const MAX_NAMESPACE_LENGTH: usize = 63;
+
+/// Label added to every namespace created by the e2e tests
+pub const NAMESPACE_LABEL: &str = "echo-operator.example.com/e2e";
+
+static NAMESPACE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Kube Error: {0}")]
+    KubeError(#[source] kube::Error),
+
+    #[error("Yaml Error: {0}")]
+    YamlError(#[source] serde_yaml::Error),
+
+    #[error("Wait Error: {0}")]
+    WaitError(#[source] kube::runtime::wait::Error),
+
+    #[error("Timeout waiting for {0}")]
+    Timeout(String),
+
+    #[error("Bootstrap Error: {0}")]
+    BootstrapError(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Bootstrap the environment, install the CRDs and a unique namespace for the given test
+pub async fn setup(test_name: &str) -> Result<TestNamespace> {
+    let config = BootstrapConfig::from_env()?;
+    ensure_cluster(&config)?;
+    let client = Client::try_default().await.map_err(Error::KubeError)?;
+    install_crds(client.clone()).await?;
+    ensure_operator(&config)?;
+    TestNamespace::new(client, test_name).await
+}
+
+/// Apply the chart CRDs and wait until they are established
+pub async fn install_crds(client: Client) -> Result<()> {
+    let crd_api = Api::<CustomResourceDefinition>::all(client);
+    for crd_yaml in CRDS {
+        let crd: CustomResourceDefinition =
+            serde_yaml::from_str(crd_yaml).map_err(Error::YamlError)?;
+        // safe unwrap: CRDs in the chart always have a name
+        let name = crd.metadata.name.clone().unwrap();
+        crd_api
+            .patch(
+                &name,
+                &PatchParams::apply(FIELD_MANAGER).force(),
+                &Patch::Apply(&crd),
+            )
+            .await
+            .map_err(Error::KubeError)?;
+        timeout(
+            Duration::from_secs(30),
+            await_condition(crd_api.clone(), &name, conditions::is_crd_established()),
+        )
+        .await
+        .map_err(|_| Error::Timeout(format!("CRD {name} established")))?
+        .map_err(Error::WaitError)?;
+    }
+    Ok(())
+}
+
+/// Namespace dedicated to a single test, deleted on drop
+pub struct TestNamespace {
+    name: String,
+    client: Client,
+}
+
+impl TestNamespace {
+    pub async fn new(client: Client, test_name: &str) -> Result<Self> {
+        let name = unique_name(test_name);
+        let namespace = Namespace {
+            metadata: ObjectMeta {
+                name: Some(name.clone()),
+                labels: Some(BTreeMap::from([(
+                    NAMESPACE_LABEL.to_string(),
+                    "true".to_string(),
+                )])),
+                ..ObjectMeta::default()
+            },
+            ..Namespace::default()
+        };
+        Api::<Namespace>::all(client.clone())
+            .create(&PostParams::default(), &namespace)
+            .await
+            .map_err(Error::KubeError)?;
+        Ok(Self { name, client })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn client(&self) -> Client {
+        self.client.clone()
+    }
+
+    /// Namespaced API for the test namespace
+    pub fn api<K>(&self) -> Api<K>
+    where
+        K: kube::Resource<Scope = k8s_openapi::NamespaceResourceScope>,
+        <K as kube::Resource>::DynamicType: Default,
+    {
+        Api::namespaced(self.client(), &self.name)
+    }
+}
+
+impl Drop for TestNamespace {
+    fn drop(&mut self) {
+        let name = self.name.clone();
+        // The test runtime can be single threaded and it is blocked by this drop, so the
+        // namespace is deleted from a new thread with its own runtime and client.
+        let result = std::thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("tokio runtime for namespace cleanup")
+                .block_on(async move {
+                    let client = Client::try_default().await?;
+                    Api::<Namespace>::all(client)
+                        .delete(&name, &DeleteParams::background())
+                        .await
+                        .map(|_| ())
+                })
+        })
+        .join();
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => eprintln!("failed to delete namespace {}: {e}", self.name),
+            Err(_) => eprintln!("failed to delete namespace {}: cleanup panicked", self.name),
+        }
+    }
+}
+
+/// Generate a valid namespace name, unique across tests and test processes
+fn unique_name(test_name: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let suffix = format!(
+        "{:x}{:x}{:x}",
+        std::process::id(),
+        nanos,
+        NAMESPACE_COUNTER.fetch_add(1, Ordering::SeqCst)
+    );
+    let sanitized: String = test_name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .take(MAX_NAMESPACE_LENGTH - suffix.len() - 1)
+        .collect();
+    let prefix = sanitized.trim_matches('-');
+    if prefix.is_empty() {
+        format!("e2e-{suffix}")
+    } else {
+        format!("{prefix}-{suffix}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{unique_name, MAX_NAMESPACE_LENGTH};
+
+    #[test]
+    fn test_unique_name_is_unique() {
+        assert_ne!(unique_name("test"), unique_name("test"));
+    }
+
+    #[test]
+    fn test_unique_name_is_a_valid_namespace() {
+        let name = unique_name("Test_Echo::Create--");
+        assert!(name.starts_with("test-echo--create-"));
+        assert!(name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-'));
+    }
+
+    #[test]
+    fn test_unique_name_is_truncated() {
+        let name = unique_name(&"a".repeat(100));
+        assert!(name.len() <= MAX_NAMESPACE_LENGTH);
+    }
+}
@@ -0,0 +1,108 @@
+//! Operator disruption helpers for chaos and load tests.
+//!
+//! They act on the operator installed by the Helm chart (see `make e2e`).
+use crate::{Error, Result};
+
+use std::time::Duration;
+
+use k8s_openapi::api::apps::v1::Deployment;
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::{Api, DeleteParams, ListParams, Patch, PatchParams};
+use kube::runtime::wait::{await_condition, Condition};
+use kube::Client;
+use serde_json::{json, Value};
+use tokio::time::timeout;
+
+pub const OPERATOR_NAMESPACE: &str = "echo-operator";
+pub const OPERATOR_NAME: &str = "echo-operator";
+
+/// Kill the operator pods without grace period, as a crash would do
+pub async fn kill_operator(client: Client) -> Result<()> {
+    let pod_api = Api::<Pod>::namespaced(client, OPERATOR_NAMESPACE);
+    pod_api
+        .delete_collection(
+            &DeleteParams {
+                grace_period_seconds: Some(0),
+                ..DeleteParams::default()
+            },
+            &ListParams::default().labels(&format!("app.kubernetes.io/name={OPERATOR_NAME}")),
+        )
+        .await
+        .map_err(Error::KubeError)?;
+    Ok(())
+}
+
+/// Set the number of operator replicas
+pub async fn scale_operator(client: Client, replicas: i32) -> Result<()> {
+    let deployment_api = Api::<Deployment>::namespaced(client, OPERATOR_NAMESPACE);
+    deployment_api
+        .patch_scale(
+            OPERATOR_NAME,
+            &PatchParams::default(),
+            &Patch::Merge(json!({"spec": {"replicas": replicas}})),
+        )
+        .await
+        .map_err(Error::KubeError)?;
+    Ok(())
+}
+
+/// Wait until every operator replica runs the latest revision and is ready
+pub async fn wait_operator_ready(client: Client) -> Result<()> {
+    let deployment_api = Api::<Deployment>::namespaced(client, OPERATOR_NAMESPACE);
+    timeout(
+        Duration::from_secs(60),
+        await_condition(deployment_api, OPERATOR_NAME, is_deployment_rolled_out()),
+    )
+    .await
+    .map_err(|_| Error::Timeout("operator ready".to_string()))?
+    .map_err(Error::WaitError)?;
+    Ok(())
+}
+
+/// Memory working set of the operator pods, summed, as reported by the kubelet of their nodes
+pub async fn operator_memory_bytes(client: Client) -> Result<u64> {
+    let pod_api = Api::<Pod>::namespaced(client.clone(), OPERATOR_NAMESPACE);
+    let pods = pod_api
+        .list(&ListParams::default().labels(&format!("app.kubernetes.io/name={OPERATOR_NAME}")))
+        .await
+        .map_err(Error::KubeError)?;
+    let mut bytes = 0;
+    for pod in pods {
+        let name = pod.metadata.name.unwrap_or_default();
+        let Some(node) = pod.spec.and_then(|spec| spec.node_name) else {
+            continue;
+        };
+        // safe unwrap: the node names are valid URL path segments
+        let request = http::Request::get(format!("/api/v1/nodes/{node}/proxy/stats/summary"))
+            .body(Vec::new())
+            .unwrap();
+        let summary: Value = client.request(request).await.map_err(Error::KubeError)?;
+        bytes += summary["pods"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|p| {
+                p["podRef"]["namespace"] == OPERATOR_NAMESPACE && p["podRef"]["name"] == name
+            })
+            .flat_map(|p| p["containers"].as_array().into_iter().flatten())
+            .filter_map(|c| c["memory"]["workingSetBytes"].as_u64())
+            .sum::<u64>();
+    }
+    Ok(bytes)
+}
+
+fn is_deployment_rolled_out() -> impl Condition<Deployment> {
+    |obj: Option<&Deployment>| {
+        let Some(deployment) = obj else {
+            return false;
+        };
+        let (Some(spec), Some(status)) = (&deployment.spec, &deployment.status) else {
+            return false;
+        };
+        let desired = spec.replicas.unwrap_or(1);
+        status.observed_generation >= deployment.metadata.generation
+            && status.replicas.unwrap_or(0) == desired
+            && status.updated_replicas.unwrap_or(0) == desired
+            && status.ready_replicas.unwrap_or(0) == desired
+    }
+}